@@ -3,7 +3,6 @@ use std::fs;
 use std::path::Path;
 use std::process::Command;
 use serde::Deserialize;
-use toml;
 
 #[derive(Debug, Deserialize)]
 pub struct Dependency {
@@ -81,8 +80,7 @@ impl<'a> Cargo<'a> {
                     output.status.code(),
                     stdout,
                     stderr
-                )
-                .into(),
+                ),
             ))
         }
     }
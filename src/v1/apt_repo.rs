@@ -0,0 +1,292 @@
+// Flat APT repository with a single "main" component:
+//   pool/<suite>/<package>/<artifacts>
+//   dists/<suite>/main/binary-<arch>/Packages{,.gz}
+//   dists/<suite>/main/source/Sources{,.gz}
+//   dists/<suite>/{Release,InRelease,Release.gpg}
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::signing::{clearsign, detach_sign};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// runs a dpkg-scan* tool in the repo root, Filename: and Directory: fields are relative to it
+fn scan_pool(root: &Path, tool: &str, args: &[&str], suite: &str) -> Result<Vec<u8>> {
+    let output = Command::new(tool)
+        .current_dir(root)
+        .args(args)
+        .arg(Path::new("pool").join(suite))
+        .output()
+        .map_err(|err| eyre!("Failed to run {}: {}", tool, err))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+// writes the index and its .gz next to it
+fn write_index(index: &Path, content: &[u8]) -> Result<Vec<PathBuf>> {
+    fs::write(index, content)?;
+    let status = Command::new("gzip")
+        .args(["-9", "-n", "-k", "-f"])
+        .arg(index)
+        .status()?;
+    if !status.success() {
+        return Err(eyre!("Failed to compress {}", index.display()));
+    }
+    let mut compressed = index.as_os_str().to_owned();
+    compressed.push(".gz");
+    Ok(vec![index.to_path_buf(), PathBuf::from(compressed)])
+}
+
+fn get_field(stanza: &str, name: &str) -> Option<String> {
+    stanza.lines().find_map(|line| {
+        line.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix(": "))
+            .map(|value| value.trim().to_string())
+    })
+}
+
+/// (package, version) of every stanza of a Packages or Sources index.
+pub fn parse_index(content: &str) -> Vec<(String, String)> {
+    content
+        .split("\n\n")
+        .filter_map(|stanza| Some((get_field(stanza, "Package")?, get_field(stanza, "Version")?)))
+        .collect()
+}
+
+/// Source package of a binary stanza, "Source: name (version)" when it differs from the binary.
+fn get_binary_source(stanza: &str) -> Option<(String, String)> {
+    let package = get_field(stanza, "Package")?;
+    let version = get_field(stanza, "Version")?;
+    match get_field(stanza, "Source") {
+        Some(source) => match source.split_once(' ') {
+            Some((name, source_version)) => Some((
+                name.to_string(),
+                source_version.trim_matches(|c| c == '(' || c == ')').to_string(),
+            )),
+            None => Some((source, version)),
+        },
+        None => Some((package, version)),
+    }
+}
+
+pub struct AptRepo {
+    root: PathBuf,
+    origin: String,
+}
+
+impl AptRepo {
+    pub fn new(root: PathBuf, origin: String) -> Self {
+        AptRepo { root, origin }
+    }
+
+    fn get_dist_dir(&self, suite: &str) -> PathBuf {
+        self.root.join("dists").join(suite)
+    }
+
+    /// Copies the artifacts of one package into the pool, returns their paths in the repo.
+    pub fn add_package(
+        &self,
+        suite: &str,
+        package_name: &str,
+        artifacts: &[PathBuf],
+    ) -> Result<Vec<PathBuf>> {
+        let pool_dir = self.root.join("pool").join(suite).join(package_name);
+        fs::create_dir_all(&pool_dir)?;
+        let mut added = vec![];
+        for artifact in artifacts {
+            let file_name = artifact
+                .file_name()
+                .ok_or_else(|| eyre!("Invalid artifact path {}", artifact.display()))?;
+            let destination = pool_dir.join(file_name);
+            fs::copy(artifact, &destination).map_err(|err| {
+                eyre!("Failed to copy {} into repo: {}", artifact.display(), err)
+            })?;
+            added.push(destination);
+        }
+        Ok(added)
+    }
+
+    fn write_packages_index(&self, suite: &str, arch: &str) -> Result<Vec<PathBuf>> {
+        let binary_dir = self
+            .get_dist_dir(suite)
+            .join("main")
+            .join(format!("binary-{}", arch));
+        fs::create_dir_all(&binary_dir)?;
+        let packages = scan_pool(&self.root, "dpkg-scanpackages", &["--multiversion", "--arch", arch], suite)?;
+        write_index(&binary_dir.join("Packages"), &packages)
+    }
+
+    fn write_sources_index(&self, suite: &str) -> Result<Vec<PathBuf>> {
+        let source_dir = self.get_dist_dir(suite).join("main").join("source");
+        fs::create_dir_all(&source_dir)?;
+        let sources = scan_pool(&self.root, "dpkg-scansources", &[], suite)?;
+        write_index(&source_dir.join("Sources"), &sources)
+    }
+
+    /// Fails when a binary package of the suite has no matching source package,
+    /// apt-get source has to work for everything in the repo.
+    pub fn check_sources(&self, suite: &str, archs: &[String]) -> Result<()> {
+        let dist_dir = self.get_dist_dir(suite);
+        let sources = fs::read_to_string(dist_dir.join("main/source/Sources"))?;
+        let sources = parse_index(&sources);
+        let mut missing = vec![];
+        for arch in archs {
+            let packages = fs::read_to_string(
+                dist_dir.join("main").join(format!("binary-{}", arch)).join("Packages"),
+            )?;
+            for stanza in packages.split("\n\n") {
+                let Some(source) = get_binary_source(stanza) else {
+                    continue;
+                };
+                if !sources.contains(&source) && !missing.contains(&source) {
+                    missing.push(source);
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let missing: Vec<String> = missing
+            .iter()
+            .map(|(name, version)| format!("{} {}", name, version))
+            .collect();
+        Err(eyre!(
+            "Source packages missing in suite {}: {}",
+            suite,
+            missing.join(", ")
+        ))
+    }
+
+    /// Regenerates the indices and the Release file of a suite, returns the Release file.
+    pub fn update_suite(&self, suite: &str, archs: &[String]) -> Result<PathBuf> {
+        let dist_dir = self.get_dist_dir(suite);
+        let mut indices = vec![];
+        for arch in archs {
+            indices.append(&mut self.write_packages_index(suite, arch)?);
+        }
+        indices.append(&mut self.write_sources_index(suite)?);
+        let mut release = format!(
+            "Origin: {origin}\nLabel: {origin}\nSuite: {suite}\nCodename: {suite}\nArchitectures: {archs}\nComponents: main\nSHA256:\n",
+            origin = self.origin,
+            suite = suite,
+            archs = archs.join(" ")
+        );
+        for index in indices.iter() {
+            let size = fs::metadata(index)?.len();
+            let hash = calculate_sha256(fs::File::open(index)?)?;
+            let name = index.strip_prefix(&dist_dir)?.to_str().unwrap();
+            release.push_str(&format!(" {} {} {}\n", hash, size, name));
+        }
+        let release_file = dist_dir.join("Release");
+        fs::write(&release_file, release)?;
+        info!("APT repository suite {} updated in {}", suite, dist_dir.display());
+        Ok(release_file)
+    }
+
+    pub fn sign_suite(&self, suite: &str, key: &str) -> Result<()> {
+        let dist_dir = self.get_dist_dir(suite);
+        let release_file = dist_dir.join("Release");
+        clearsign(&release_file, &dist_dir.join("InRelease"), key)?;
+        detach_sign(&release_file, &dist_dir.join("Release.gpg"), key)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Builds a minimal binary package with dpkg-deb.
+    pub fn create_deb(dir: &Path, name: &str, version: &str, arch: &str) -> PathBuf {
+        let package_dir = dir.join(format!("{}-deb", name));
+        fs::create_dir_all(package_dir.join("DEBIAN")).unwrap();
+        fs::write(
+            package_dir.join("DEBIAN/control"),
+            format!(
+                "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: John Doe <johndoe@example.com>\nDescription: fixture\n",
+                name, version, arch
+            ),
+        )
+        .unwrap();
+        let file_version = version.split_once(':').map_or(version, |(_, version)| version);
+        let deb = dir.join(format!("{}_{}_{}.deb", name, file_version, arch));
+        // fixed timestamps, so rebuilding the fixture gives the same deb
+        let status = Command::new("dpkg-deb")
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .args(["--root-owner-group", "--build"])
+            .arg(&package_dir)
+            .arg(&deb)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+        fs::remove_dir_all(package_dir).unwrap();
+        deb
+    }
+
+    /// Writes a .dsc whose only file is an empty orig tarball.
+    pub fn create_dsc(dir: &Path, name: &str, version: &str) -> Vec<PathBuf> {
+        let upstream_version = version.rsplit_once('-').map_or(version, |(upstream, _)| upstream);
+        let orig = dir.join(format!("{}_{}.orig.tar.gz", name, upstream_version));
+        fs::write(&orig, "").unwrap();
+        let dsc = dir.join(format!("{}_{}.dsc", name, version));
+        fs::write(
+            &dsc,
+            format!(
+                "Format: 3.0 (quilt)\nSource: {name}\nBinary: {name}\nArchitecture: any\nVersion: {version}\nMaintainer: John Doe <johndoe@example.com>\nFiles:\n d41d8cd98f00b204e9800998ecf8427e 0 {orig}\n",
+                name = name,
+                version = version,
+                orig = orig.file_name().unwrap().to_str().unwrap()
+            ),
+        )
+        .unwrap();
+        vec![dsc, orig]
+    }
+
+    #[test]
+    fn test_apt_repo_indices() {
+        let dir = tempdir().unwrap();
+        let deb = create_deb(dir.path(), "hello-world", "1.0.0-1", "amd64");
+        let repo = AptRepo::new(dir.path().join("repo"), "pkg-builder".to_string());
+
+        repo.add_package("bookworm", "hello-world", &[deb]).unwrap();
+        let release_file = repo
+            .update_suite("bookworm", &["amd64".to_string()])
+            .unwrap();
+        let error = repo
+            .check_sources("bookworm", &["amd64".to_string()])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "Source packages missing in suite bookworm: hello-world 1.0.0-1");
+
+        repo.add_package("bookworm", "hello-world", &create_dsc(dir.path(), "hello-world", "1.0.0-1"))
+            .unwrap();
+        let release_file_with_sources = repo
+            .update_suite("bookworm", &["amd64".to_string()])
+            .unwrap();
+        assert_eq!(release_file, release_file_with_sources);
+        repo.check_sources("bookworm", &["amd64".to_string()]).unwrap();
+        let sources =
+            fs::read_to_string(dir.path().join("repo/dists/bookworm/main/source/Sources")).unwrap();
+        assert!(sources.contains("Directory: pool/bookworm/hello-world"));
+
+        let packages =
+            fs::read_to_string(dir.path().join("repo/dists/bookworm/main/binary-amd64/Packages"))
+                .unwrap();
+        assert!(packages.contains("Package: hello-world"));
+        assert!(packages.contains("Filename: pool/bookworm/hello-world/hello-world_1.0.0-1_amd64.deb"));
+        let release = fs::read_to_string(release_file).unwrap();
+        assert!(release.contains("Codename: bookworm"));
+        assert!(release.contains(" main/binary-amd64/Packages\n"));
+        assert!(release.contains(" main/binary-amd64/Packages.gz\n"));
+        assert!(release.contains(" main/source/Sources.gz\n"));
+    }
+}
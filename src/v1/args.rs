@@ -1,10 +1,18 @@
-use clap::{Args, Parser, Subcommand};
+use crate::v1::ci::CiProvider;
+use clap::{ArgAction, Args, Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
 pub struct PkgBuilderArgs {
     #[clap(subcommand)]
     pub action: ActionType,
+    /// number of parallel build jobs, overrides config value
+    /// 0 uses all available cores
+    #[clap(long, short, global = true)]
+    pub jobs: Option<usize>,
+    /// -v logs debug output, -vv also the file listings of tar
+    #[clap(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
 }
 
 #[derive(Debug, Subcommand)]
@@ -19,9 +27,55 @@ pub enum ActionType {
     Autopkgtest(DefaultCommand),
     /// run linting against package
     Lintian(DefaultCommand),
+    /// install the built deb in a fresh container and run the configured smoke tests
+    SmokeTest(DefaultCommand),
+    /// check the systemd units shipped by the built debs
+    UnitCheck(DefaultCommand),
+    /// check that the maintainer scripts of the built debs install without prompting
+    DebconfCheck(DefaultCommand),
+    /// check the built debs against the [content_policy] of the package
+    ContentCheck(DefaultCommand),
 
     /// Verify package against hashes, it also rebuilds the package
     Verify(VerifyConfig),
+    /// build the packages of a workspace, e.g. only the ones changed since a git ref
+    BuildAll(BuildAllCommand),
+    /// build all packages of a workspace into a signed, versioned release
+    Release(ReleaseCommand),
+    /// pin the versions, sources and configs of all packages of a workspace
+    Freeze(FreezeCommand),
+    /// copy a verified package into the next channel of a workspace, e.g. from dev to staging
+    Promote(PromoteCommand),
+    /// report archive changes of the build dependencies of a workspace, which need rebuilds
+    TransitionCheck(TransitionCheckCommand),
+    /// bump the package version for packaging only changes
+    Bump(BumpCommand),
+    /// draft the changelog entry of a new upstream release from the upstream git history
+    Changelog(ChangelogCommand),
+    /// binary-only rebuild of the last built source package, e.g. against newer libraries
+    Binnmu(BinnmuCommand),
+    /// rebuild and compare against the released hashes, meant for cron
+    RebuildCheck(RebuildCheckCommand),
+    /// prepare the orig tarball and print its hash for pkg-builder-verify.toml
+    OrigHash(DefaultCommand),
+    /// generate a CI pipeline building the package on push and tags
+    CiInit(CiInitCommand),
+    /// show generated packaging files for review
+    Show(ShowCommand),
+    /// parse and lint every pkg-builder.toml under a directory
+    LintAll(LintAllCommand),
+    /// save or restore the build state of the package, e.g. to move it to another machine
+    Snapshot(SnapshotCommand),
+    /// print the stages, commands, paths and inputs of package without running anything
+    Plan(PlanCommand),
+    /// describe a field of pkg-builder.toml, e.g. build_env.codename
+    Explain(ExplainCommand),
+    /// check that the host can build the package, e.g. user namespaces inside of a container
+    Doctor(DefaultCommand),
+    /// download the source and toolchains of the package into the store for `package --offline`
+    Fetch(DefaultCommand),
+    /// prepare signed source-only uploads for dput, e.g. to a PPA or Debian mentors
+    Upload(UploadCommand),
     // pkg-builder version
     Version
 }
@@ -43,6 +97,277 @@ pub struct VerifyConfig {
     /// if given it won't repackage it
     #[clap(long)]
     pub no_package: Option<bool>,
+
+    /// additionally verifies that the files on disk match
+    /// the Checksums-Sha256 declared in the .changes file
+    #[clap(long)]
+    pub check_changes: Option<bool>,
+
+    /// shows old and new hash of every changed artifact and
+    /// writes the new hashes into pkg-builder-verify.toml after confirmation
+    #[clap(long)]
+    pub update: bool,
+
+    /// with --update, accept the new hashes without asking
+    #[clap(long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReleaseCommand {
+    /// location of the workspace manifest, either full path
+    /// or directory to workspace.toml is located
+    /// if not given current directory is searched for workspace.toml
+    #[clap(long)]
+    pub manifest: Option<String>,
+
+    /// output directory of the release, has to be empty or missing
+    #[clap(long)]
+    pub out: String,
+
+    /// if given the already built packages are collected, nothing is rebuilt
+    #[clap(long)]
+    pub no_package: Option<bool>,
+
+    /// output directory of the previous release, fails if a published
+    /// version-revision was rebuilt with different contents
+    #[clap(long)]
+    pub previous: Option<String>,
+
+    /// refuses to build when a package deviates from workspace.freeze.toml
+    #[clap(long)]
+    pub frozen: bool,
+
+    /// fails instead of warning when a package targets a suite past its end of life
+    #[clap(long)]
+    pub fail_on_eol: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BuildAllCommand {
+    /// location of the workspace manifest, either full path
+    /// or directory to workspace.toml is located
+    /// if not given current directory is searched for workspace.toml
+    #[clap(long)]
+    pub manifest: Option<String>,
+
+    /// fails instead of warning when a package targets a suite past its end of life
+    #[clap(long)]
+    pub fail_on_eol: bool,
+
+    /// only builds the packages with changes under their config directory since the
+    /// git ref, and the packages build depending on them
+    #[clap(long)]
+    pub changed_since: Option<String>,
+
+    /// refuses to build when a package deviates from workspace.freeze.toml
+    #[clap(long)]
+    pub frozen: bool,
+
+    /// only rebuilds the packages failed in the last run recorded in workspace.run.toml,
+    /// and the packages build depending on them
+    #[clap(long, conflicts_with = "changed_since")]
+    pub retry_failed: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TransitionCheckCommand {
+    /// location of the workspace manifest, either full path
+    /// or directory to workspace.toml is located
+    /// if not given current directory is searched for workspace.toml
+    #[clap(long)]
+    pub manifest: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct FreezeCommand {
+    /// location of the workspace manifest, either full path
+    /// or directory to workspace.toml is located
+    /// if not given current directory is searched for workspace.toml
+    #[clap(long)]
+    pub manifest: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PromoteCommand {
+    /// location of the workspace manifest, either full path
+    /// or directory to workspace.toml is located
+    /// if not given current directory is searched for workspace.toml
+    #[clap(long)]
+    pub manifest: Option<String>,
+
+    /// channel the package is promoted from,
+    /// if not given the last build is published into the first channel
+    #[clap(long)]
+    pub from: Option<String>,
+
+    /// channel the package is promoted to
+    #[clap(long)]
+    pub to: String,
+
+    /// package_name of the promoted package
+    pub package: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RebuildCheckCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// location of pkg-builder-verify.toml holding the released hashes, either full path
+    /// or directory to pkg-builder-verify.toml is located
+    /// if not given current directory is searched for pkg-builder-verify.toml
+    #[clap(long)]
+    pub verify_config: Option<String>,
+
+    /// shell command run when the output drifted, gets the report on stdin
+    /// and the subject in PKG_BUILDER_SUBJECT, e.g. "mail -s \"$PKG_BUILDER_SUBJECT\" ops@example.com"
+    #[clap(long)]
+    pub notify_command: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CiInitCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// CI system to generate the pipeline for
+    #[clap(long, value_enum)]
+    pub provider: CiProvider,
+}
+
+#[derive(Debug, Args)]
+pub struct ShowCommand {
+    #[clap(subcommand)]
+    pub show_sub_command: ShowSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ShowSubCommand {
+    /// generates the debian dir without building and prints control, rules, changelog and install files
+    Debian(ShowDebianCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct ShowDebianCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// writes the debian dir to <out>/debian instead of printing it, e.g. to diff it against the previous version
+    #[clap(long)]
+    pub out: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct LintAllCommand {
+    /// directory searched for pkg-builder.toml files, e.g. examples
+    pub dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SnapshotCommand {
+    #[clap(subcommand)]
+    pub snapshot_sub_command: SnapshotSubCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotSubCommand {
+    /// archives the extracted source, generated debian dir and dev stamp of the package
+    /// to <workdir>/.snapshots, built debs are left out
+    Save(SaveSnapshotCommand),
+    /// replaces the build state of the package with a saved snapshot
+    Restore(RestoreSnapshotCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct SaveSnapshotCommand {
+    /// name of the snapshot
+    pub name: String,
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreSnapshotCommand {
+    /// name of the snapshot
+    pub name: String,
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    /// replaces the current build state of the package
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PlanCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// prints the plan as JSON document with a schema_version, for other tools
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExplainCommand {
+    /// dotted path of the field, the top level sections are listed when not given
+    pub field: Option<String>,
+
+    /// prints the JSON Schema of pkg-builder.toml instead, for editors
+    #[clap(long, conflicts_with = "field")]
+    pub json_schema: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BumpCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// increments revision_number and adds a changelog entry
+    #[clap(long)]
+    pub revision: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ChangelogCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// previously packaged tag, found from the version of the latest changelog entry if not given
+    #[clap(long)]
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct BinnmuCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+
+    /// appended to the version of the binaries, +b1, +b2, ...
+    #[clap(long)]
+    pub suffix: String,
+
+    /// reason of the rebuild, used as the changelog entry
+    #[clap(long)]
+    pub changelog: String,
 }
 
 #[derive(Debug, Args)]
@@ -70,6 +395,60 @@ pub struct PackageCommand {
     #[clap(long)]
     /// runs lintian or not, based on value, overrides config value
     pub run_lintian: Option<bool>,
+    /// overrides config value
+    /// checks the shipped systemd units or not based on supplied value
+    #[clap(long)]
+    pub run_unit_checks: Option<bool>,
+    /// overrides config value
+    /// checks the maintainer scripts for prompts blocking noninteractive installs or not based on supplied value
+    #[clap(long)]
+    pub run_debconf_checks: Option<bool>,
+    /// incremental build for packaging development, keeps the extracted source and the
+    /// generated debian dir while the tarball and spec are unchanged and builds in a snapshot
+    /// of the chroot, package tests are off unless enabled with the flags above
+    #[clap(long)]
+    pub dev: bool,
+    /// shows a progress line per chroot setup command instead of its output, overrides config value
+    #[clap(long)]
+    pub quiet_chroot_setup: bool,
+    /// fails before the build when anything would be downloaded, run `fetch` first
+    #[clap(long)]
+    pub offline: bool,
+    /// rebuilds a version built before with other tool pins or toolchains, instead of failing
+    #[clap(long)]
+    pub allow_changed_inputs: bool,
+    /// builds for every distribution of build_env.codenames
+    #[clap(long)]
+    pub all_distros: bool,
+    /// with --all-distros, number of distributions built at the same time, they share the jobs
+    #[clap(long, default_value_t = 2)]
+    pub parallel_builds: usize,
+    /// overrides package_fields.spec_file
+    /// path to the debcrafter spec file (.sss), relative to current directory
+    #[clap(long)]
+    pub spec: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct UploadCommand {
+    #[clap(subcommand)]
+    pub upload_sub_command: UploadSubCommand,
+}
+#[derive(Debug, Subcommand)]
+pub enum UploadSubCommand {
+    /// copies the _source.changes of the last build with its files into the upload dir and signs it with debsign
+    Prepare(UploadPrepareCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct UploadPrepareCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    /// gpg key fingerprint or user id to sign with, overrides upload.key
+    #[clap(long)]
+    pub key: Option<String>,
 }
 
 #[derive(Debug, Args)]
@@ -81,6 +460,8 @@ pub struct EnvCommand {
 pub enum BuildEnvSubCommand {
     /// creates build env used for packaging
     Create(CreateBuildEnvCommand),
+    /// upgrades the packages of the build env, recreates it after build_env.chroot_max_age_days
+    Update(UpdateBuildEnvCommand),
     /// removes build env
     Clean(CleanBuildEnvCommand),
 }
@@ -93,6 +474,13 @@ pub struct CreateBuildEnvCommand {
     pub config: Option<String>,
 }
 #[derive(Debug, Args)]
+pub struct UpdateBuildEnvCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+}
+#[derive(Debug, Args)]
 pub struct CleanBuildEnvCommand {
     /// location of pkg-builder config_file, either full path
     /// or directory to pkg-builder.toml is located
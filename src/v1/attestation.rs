@@ -0,0 +1,277 @@
+// SBOM (CycloneDX) and provenance (in-toto statement with a SLSA predicate)
+// describing how a package was built from its pinned inputs.
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use crate::v1::distribution::{get_distribution, DistributionFamily};
+use crate::v1::pkg_config::{LanguageEnv, PackageType, PkgConfig};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+fn get_toolchain(language_env: &LanguageEnv) -> Option<(&str, &str)> {
+    match language_env {
+        LanguageEnv::Rust(config) => Some(("rust", &config.rust_version)),
+        LanguageEnv::Go(config) => Some(("go", &config.go_version)),
+        LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
+            Some(("node", &config.node_version))
+        }
+        LanguageEnv::Java(config) => Some(("jdk", &config.jdk_version)),
+        LanguageEnv::Nim(config) => Some(("nim", &config.nim_version)),
+        LanguageEnv::Zig(config) => Some(("zig", &config.zig_version)),
+        LanguageEnv::Custom(config) => Some((&config.toolchain_name, &config.toolchain_version)),
+        LanguageEnv::Python(config) => config.python_version.as_deref().map(|version| ("python", version)),
+        LanguageEnv::Dotnet(_) | LanguageEnv::C => None,
+    }
+}
+
+// upstream source as (uri, digest), the digest is empty for unpinned tarballs
+fn get_source(config: &PkgConfig) -> Option<(String, Value)> {
+    match &config.package_type {
+        PackageType::Default(package_type) => {
+            let digest = match &package_type.tarball_hash {
+                Some(hash) => json!({ "sha256": hash }),
+                None => json!({}),
+            };
+            Some((package_type.get_source_url(&config.package_fields.version_number), digest))
+        }
+        PackageType::Git(package_type) => Some((
+            package_type.git_url.clone(),
+            json!({ "gitTag": package_type.git_tag }),
+        )),
+        PackageType::Virtual => None,
+    }
+}
+
+fn get_language_env(config: &PkgConfig) -> Option<&LanguageEnv> {
+    match &config.package_type {
+        PackageType::Default(package_type) => Some(&package_type.language_env),
+        PackageType::Git(package_type) => Some(&package_type.language_env),
+        PackageType::Virtual => None,
+    }
+}
+
+fn get_subjects(artifacts: &[PathBuf]) -> Result<Vec<(String, String)>> {
+    let mut subjects = vec![];
+    for artifact in artifacts {
+        let name = artifact
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| eyre!("Invalid artifact path {}", artifact.display()))?;
+        let hash = calculate_sha256(open_for_hashing(artifact)?)?;
+        subjects.push((name.to_string(), hash));
+    }
+    Ok(subjects)
+}
+
+/// licenses are the ones the license scan found in the upstream source.
+pub fn create_sbom(config: &PkgConfig, artifacts: &[PathBuf], licenses: &[String]) -> Result<Value> {
+    let package_fields = &config.package_fields;
+    let distribution = get_distribution(&config.build_env.codename)?.info();
+    let namespace = match distribution.family {
+        DistributionFamily::Debian => "debian",
+        DistributionFamily::Ubuntu => "ubuntu",
+    };
+    let purl = format!(
+        "pkg:deb/{}/{}@{}?arch={}&distro={}",
+        namespace,
+        package_fields.package_name,
+        package_fields.get_debian_version(),
+        config.build_env.arch.get_deb_arch(),
+        distribution.suite
+    );
+    let hashes: Vec<Value> = get_subjects(artifacts)?
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(".deb"))
+        .map(|(_, hash)| json!({ "alg": "SHA-256", "content": hash }))
+        .collect();
+
+    let mut components = vec![];
+    if let Some((uri, digest)) = get_source(config) {
+        let mut source = json!({
+            "type": "file",
+            "name": format!("{}-source", package_fields.package_name),
+            "version": package_fields.version_number,
+            "externalReferences": [{ "type": "distribution", "url": uri }],
+        });
+        if let Some(hash) = digest.get("sha256") {
+            source["hashes"] = json!([{ "alg": "SHA-256", "content": hash }]);
+        }
+        if !licenses.is_empty() {
+            // SPDX tags like MIT OR Apache-2.0 are expressions rather than ids
+            let licenses: Vec<Value> = licenses
+                .iter()
+                .map(|license| match license.contains(' ') {
+                    true => json!({ "expression": license }),
+                    false => json!({ "license": { "id": license } }),
+                })
+                .collect();
+            source["licenses"] = json!(licenses);
+        }
+        components.push(source);
+    }
+    if let Some((name, version)) = get_language_env(config).and_then(get_toolchain) {
+        components.push(json!({
+            "type": "application",
+            "name": name,
+            "version": version,
+            // only used at build time
+            "scope": "excluded",
+        }));
+    }
+
+    Ok(json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "tools": [{ "name": "pkg-builder", "version": env!("CARGO_PKG_VERSION") }],
+            "component": {
+                "type": "application",
+                "name": package_fields.package_name,
+                "version": package_fields.get_debian_version(),
+                "purl": purl,
+                "hashes": hashes,
+            },
+        },
+        "components": components,
+    }))
+}
+
+/// recipe_revision is the git commit of the config the package was built from.
+pub fn create_provenance(config: &PkgConfig, artifacts: &[PathBuf], recipe_revision: Option<&str>) -> Result<Value> {
+    let subjects: Vec<Value> = get_subjects(artifacts)?
+        .into_iter()
+        .map(|(name, hash)| json!({ "name": name, "digest": { "sha256": hash } }))
+        .collect();
+    let mut resolved_dependencies: Vec<Value> = get_source(config)
+        .map(|(uri, digest)| json!({ "uri": uri, "digest": digest }))
+        .into_iter()
+        .collect();
+    // the build fails unless the tag was signed by one of them
+    if let PackageType::Git(package_type) = &config.package_type {
+        if let (Some(signers), Some(source)) = (
+            package_type.get_required_signers(),
+            resolved_dependencies.first_mut(),
+        ) {
+            source["annotations"] = json!({
+                "allowedSigners": signers.get_fingerprints(),
+            });
+        }
+    }
+    let toolchain = get_language_env(config)
+        .and_then(get_toolchain)
+        .map(|(name, version)| json!({ "name": name, "version": version }));
+
+    Ok(json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": subjects,
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": "https://github.com/eth-pkg/pkg-builder/sbuild/v1",
+                "externalParameters": {
+                    "package_name": config.package_fields.package_name,
+                    "version": config.package_fields.get_debian_version(),
+                    "codename": config.build_env.codename,
+                    "arch": config.build_env.arch.as_str(),
+                    "toolchain": toolchain,
+                    "recipe_revision": recipe_revision,
+                },
+                "resolvedDependencies": resolved_dependencies,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": format!("https://github.com/eth-pkg/pkg-builder@{}", env!("CARGO_PKG_VERSION")),
+                },
+            },
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::{
+        AllowedSigners, Arch, DefaultPackageTypeConfig, GitPackageTypeConfig, RustConfig,
+    };
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn rust_config() -> PkgConfig {
+        let mut config = PkgConfig::default();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.package_fields.version_number = "1.0.0".to_string();
+        config.package_fields.revision_number = "1".to_string();
+        config.build_env.codename = "bookworm".to_string();
+        config.build_env.arch = Arch::Amd64;
+        config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            tarball_url: "https://example.com/hello-world-1.0.0.tar.gz".to_string(),
+            tarball_hash: Some("abcd".to_string()),
+            tarball_auth: None,
+            github_release: None,
+            language_env: LanguageEnv::Rust(RustConfig {
+                rust_version: "1.77.0".to_string(),
+                ..RustConfig::default()
+            }),
+        });
+        config
+    }
+
+    #[test]
+    fn test_create_sbom_and_provenance() {
+        let dir = tempdir().unwrap();
+        let deb = dir.path().join("hello-world_1.0.0-1_amd64.deb");
+        fs::write(&deb, "hello").unwrap();
+        let config = rust_config();
+
+        let licenses = ["MIT".to_string(), "MIT OR Apache-2.0".to_string()];
+        let sbom = create_sbom(&config, std::slice::from_ref(&deb), &licenses).unwrap();
+        assert_eq!(
+            sbom["metadata"]["component"]["purl"],
+            "pkg:deb/debian/hello-world@1.0.0-1?arch=amd64&distro=bookworm"
+        );
+        assert_eq!(sbom["components"][0]["hashes"][0]["content"], "abcd");
+        assert_eq!(sbom["components"][0]["licenses"][0]["license"]["id"], "MIT");
+        assert_eq!(sbom["components"][0]["licenses"][1]["expression"], "MIT OR Apache-2.0");
+        assert_eq!(sbom["components"][1]["name"], "rust");
+
+        let provenance = create_provenance(&config, &[deb], Some("0123abcd")).unwrap();
+        assert_eq!(provenance["subject"][0]["name"], "hello-world_1.0.0-1_amd64.deb");
+        assert_eq!(provenance["predicate"]["buildDefinition"]["externalParameters"]["recipe_revision"], "0123abcd");
+        assert_eq!(
+            provenance["subject"][0]["digest"]["sha256"],
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(
+            provenance["predicate"]["buildDefinition"]["resolvedDependencies"][0]["uri"],
+            "https://example.com/hello-world-1.0.0.tar.gz"
+        );
+        assert!(
+            provenance["predicate"]["buildDefinition"]["resolvedDependencies"][0]
+                .get("annotations")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_provenance_records_verified_tag_signers() {
+        let mut config = rust_config();
+        config.package_type = PackageType::Git(GitPackageTypeConfig {
+            git_url: "https://github.com/example/hello-world.git".to_string(),
+            git_tag: "v1.0.0".to_string(),
+            require_signed_tag: Some(true),
+            allowed_signers: Some(AllowedSigners {
+                key_file: "signers.asc".to_string(),
+                fingerprints: vec!["0123 4567 89ab cdef 0123 4567 89ab cdef 0123 4567".to_string()],
+            }),
+            ..GitPackageTypeConfig::default()
+        });
+        let provenance = create_provenance(&config, &[], None).unwrap();
+        let source = &provenance["predicate"]["buildDefinition"]["resolvedDependencies"][0];
+        assert_eq!(source["digest"]["gitTag"], "v1.0.0");
+        assert!(source["annotations"].get("signatureVerified").is_none());
+        assert_eq!(
+            source["annotations"]["allowedSigners"][0],
+            "0123456789ABCDEF0123456789ABCDEF01234567"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+// Checks the GLIBC and GLIBCXX symbol versions the ELF binaries of the built debs require
+// against the newest versions of the targeted release, a binary requiring newer symbols
+// does not run on it, e.g. after linking against a toolchain from outside the archive.
+use crate::v1::build::content_policy::{dpkg_deb, parse_contents};
+use crate::v1::build::quality::{ContentFinding, FindingLevel};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Newest symbol versions the binaries may require, None is not checked.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct AbiBaseline {
+    pub glibc: Option<String>,
+    pub glibcxx: Option<String>,
+}
+
+/// Compares dotted numeric versions, e.g. 2.9 < 2.34
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<u64> { version.split('.').map(|part| part.parse().unwrap_or(0)).collect() };
+    parts(a).cmp(&parts(b))
+}
+
+/// Newest version of each symbol family required by the undefined symbols of objdump -T,
+/// e.g. "0000000000000000      DF *UND*  0000000000000000 (GLIBC_2.34) __libc_start_main"
+pub fn parse_required_versions(objdump: &str) -> BTreeMap<String, String> {
+    let re = Regex::new(r"\b(GLIBC|GLIBCXX)_([0-9]+(?:\.[0-9]+)*)\b").unwrap();
+    let mut required: BTreeMap<String, String> = BTreeMap::new();
+    for line in objdump.lines().filter(|line| line.contains("*UND*")) {
+        for captures in re.captures_iter(line) {
+            let family = captures[1].to_string();
+            let version = captures[2].to_string();
+            match required.get(&family) {
+                Some(newest) if compare_versions(newest, &version) != Ordering::Less => {}
+                _ => {
+                    required.insert(family, version);
+                }
+            }
+        }
+    }
+    required
+}
+
+/// Findings of a binary requiring newer versions than the baseline, level is error or warning.
+pub fn check_required_versions(
+    package: &str,
+    path: &str,
+    required: &BTreeMap<String, String>,
+    baseline: &AbiBaseline,
+    level: FindingLevel,
+) -> Vec<ContentFinding> {
+    let limits = [("GLIBC", &baseline.glibc), ("GLIBCXX", &baseline.glibcxx)];
+    limits
+        .iter()
+        .filter_map(|(family, limit)| {
+            let limit = limit.as_ref()?;
+            let version = required.get(*family)?;
+            (compare_versions(version, limit) == Ordering::Greater).then(|| ContentFinding {
+                package: package.to_string(),
+                level,
+                message: format!("{} requires {}_{}, newer than {}_{} of the target", path, family, version, family, limit),
+            })
+        })
+        .collect()
+}
+
+fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+fn objdump_dynamic_symbols(path: &Path) -> Result<String> {
+    let output = Command::new("objdump").arg("-T").arg(path).output()?;
+    if !output.status.success() {
+        // static binaries have no dynamic symbol table
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Findings of the ELF files of every deb, extracted with dpkg-deb and read with objdump.
+pub fn check_debs(debs: &[PathBuf], baseline: &AbiBaseline, level: FindingLevel) -> Result<Vec<ContentFinding>> {
+    let mut findings = vec![];
+    for deb in debs {
+        let package = dpkg_deb(&["--field"], deb)?
+            .lines()
+            .find_map(|line| line.strip_prefix("Package:"))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default();
+        let extract_dir = tempdir()?;
+        let output = Command::new("dpkg-deb").arg("-x").arg(deb).arg(extract_dir.path()).output()?;
+        if !output.status.success() {
+            return Err(eyre!("dpkg-deb -x {} failed: {}", deb.display(), String::from_utf8_lossy(&output.stderr)));
+        }
+        for entry in parse_contents(&dpkg_deb(&["-c"], deb)?) {
+            if !entry.mode.starts_with('-') {
+                continue;
+            }
+            let file = extract_dir.path().join(entry.path.trim_start_matches('/'));
+            if !is_elf(&file) {
+                continue;
+            }
+            let required = parse_required_versions(&objdump_dynamic_symbols(&file)?);
+            findings.append(&mut check_required_versions(&package, &entry.path, &required, baseline, level));
+        }
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_required_versions() {
+        let objdump = "\n/usr/bin/geth:     file format elf64-x86-64\n\n\
+                       DYNAMIC SYMBOL TABLE:\n\
+                       0000000000000000      DF *UND*\t0000000000000000 (GLIBC_2.34) __libc_start_main\n\
+                       0000000000000000      DF *UND*\t0000000000000000  GLIBC_2.9   pipe2\n\
+                       0000000000000000      DF *UND*\t0000000000000000 (GLIBCXX_3.4.32) _ZSt28__throw_bad_array_new_lengthv\n\
+                       0000000000401000 g    DF .text\t0000000000000010  GLIBC_2.99  exported\n";
+        let required = parse_required_versions(objdump);
+        assert_eq!(required.get("GLIBC").map(String::as_str), Some("2.34"));
+        assert_eq!(required.get("GLIBCXX").map(String::as_str), Some("3.4.32"));
+
+        let bookworm = AbiBaseline {
+            glibc: Some("2.36".to_string()),
+            glibcxx: Some("3.4.30".to_string()),
+        };
+        assert_eq!(
+            check_required_versions("geth", "/usr/bin/geth", &required, &bookworm, FindingLevel::Error),
+            vec![ContentFinding {
+                package: "geth".to_string(),
+                level: FindingLevel::Error,
+                message: "/usr/bin/geth requires GLIBCXX_3.4.32, newer than GLIBCXX_3.4.30 of the target".to_string(),
+            }]
+        );
+        let glibc_only = AbiBaseline {
+            glibc: Some("2.34".to_string()),
+            glibcxx: None,
+        };
+        assert!(check_required_versions("geth", "/usr/bin/geth", &required, &glibc_only, FindingLevel::Error).is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+// Ships the AppArmor profiles of the package: installs them into /etc/apparmor.d, loads them
+// from the maintainer scripts like dh_apparmor does, and checks their syntax with autopkgtest.
+use crate::v1::build::extras::append_lines;
+use crate::v1::build::quality::AutopkgtestResult;
+use crate::v1::pkg_config::AppArmorConfig;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// autopkgtest tests checking a profile are named apparmor-<profile>.
+pub const APPARMOR_TEST_PREFIX: &str = "apparmor-";
+const SNIPPET_START: &str = "# Added by pkg-builder: apparmor";
+const SNIPPET_END: &str = "# End pkg-builder: apparmor";
+
+/// The profile name is the file name, e.g. apparmor/usr.bin.geth -> usr.bin.geth
+pub fn get_profile_name(profile: &str) -> &str {
+    profile.rsplit('/').next().unwrap_or(profile)
+}
+
+pub fn get_postinst_snippet(names: &[&str]) -> String {
+    format!(
+        r#"if [ "$1" = "configure" ]; then
+    for profile in {}; do
+        mkdir -p /etc/apparmor.d/local
+        [ -e "/etc/apparmor.d/local/$profile" ] || touch "/etc/apparmor.d/local/$profile"
+        if command -v apparmor_parser >/dev/null 2>&1 && aa-enabled --quiet 2>/dev/null; then
+            apparmor_parser -r -T -W "/etc/apparmor.d/$profile" || true
+        fi
+    done
+fi"#,
+        names.join(" ")
+    )
+}
+
+pub fn get_postrm_snippet(names: &[&str]) -> String {
+    format!(
+        r#"if [ "$1" = "purge" ]; then
+    for profile in {}; do
+        rm -f "/etc/apparmor.d/disable/$profile" "/etc/apparmor.d/force-complain/$profile" "/etc/apparmor.d/local/$profile"
+    done
+fi"#,
+        names.join(" ")
+    )
+}
+
+/// Inserts the snippet before #DEBHELPER#, the snippet of a previous run is replaced.
+pub fn insert_snippet(script: &str, snippet: &str) -> Result<String> {
+    let script = match (script.find(SNIPPET_START), script.find(SNIPPET_END)) {
+        (Some(start), Some(end)) => format!("{}{}", &script[..start], script[end + SNIPPET_END.len()..].trim_start_matches('\n')),
+        _ => script.to_string(),
+    };
+    let position = script
+        .find("#DEBHELPER#")
+        .ok_or_else(|| eyre!("the script has no #DEBHELPER# token to add the apparmor snippet before"))?;
+    Ok(format!(
+        "{}{}\n{}\n{}\n{}",
+        &script[..position],
+        SNIPPET_START,
+        snippet,
+        SNIPPET_END,
+        &script[position..]
+    ))
+}
+
+fn add_to_script(path: &Path, snippet: &str) -> Result<()> {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(_) => "#!/bin/sh\nset -e\n\n#DEBHELPER#\n\nexit 0\n".to_string(),
+    };
+    let script = insert_snippet(&script, snippet).map_err(|err| eyre!("{}: {}", path.display(), err))?;
+    fs::write(path, script)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+// one test per profile, so the quality report tells which of them does not parse
+fn add_tests(debian_dir: &Path, names: &[&str]) -> Result<()> {
+    let tests_dir = debian_dir.join("tests");
+    fs::create_dir_all(&tests_dir)?;
+    let control_path = tests_dir.join("control");
+    let mut control = fs::read_to_string(&control_path).unwrap_or_default();
+    for name in names {
+        let test = format!("{}{}", APPARMOR_TEST_PREFIX, name);
+        let test_path = tests_dir.join(&test);
+        fs::write(
+            &test_path,
+            format!("#!/bin/sh\nset -e\napparmor_parser --skip-kernel-load --skip-cache /etc/apparmor.d/{}\n", name),
+        )?;
+        fs::set_permissions(&test_path, fs::Permissions::from_mode(0o755))?;
+        if control.lines().any(|line| line.trim() == format!("Tests: {}", test)) {
+            continue;
+        }
+        if !control.is_empty() && !control.ends_with("\n\n") {
+            control.push_str(if control.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+        control.push_str(&format!("Tests: {}\nDepends: @, apparmor\nRestrictions: allow-stderr\n", test));
+    }
+    fs::write(&control_path, control)?;
+    Ok(())
+}
+
+/// Copies the profiles into debian/apparmor, installs them and adds the maintainer script
+/// snippets and autopkgtest tests, repeated runs leave the debian dir unchanged.
+pub fn add_apparmor_profiles(build_files_dir: &Path, package_name: &str, config: &AppArmorConfig) -> Result<()> {
+    let package = config.package.as_deref().unwrap_or(package_name);
+    let is_main = package == package_name;
+    let debian_dir = build_files_dir.join("debian");
+    let profiles_dir = debian_dir.join("apparmor");
+    fs::create_dir_all(&profiles_dir)?;
+
+    let names: Vec<&str> = config.profiles.iter().map(|profile| get_profile_name(profile)).collect();
+    let mut install = vec![];
+    for (profile, name) in config.profiles.iter().zip(names.iter()) {
+        fs::copy(profile, profiles_dir.join(name))
+            .map_err(|err| eyre!("apparmor: could not copy profile {}: {}", profile, err))?;
+        info!("Adding apparmor profile {} to {}", name, package);
+        install.push(format!("debian/apparmor/{} etc/apparmor.d", name));
+    }
+    append_lines(&debian_dir, package, is_main, "install", &install)?;
+    add_to_script(&debian_dir.join(format!("{}.postinst", package)), &get_postinst_snippet(&names))?;
+    add_to_script(&debian_dir.join(format!("{}.postrm", package)), &get_postrm_snippet(&names))?;
+    add_tests(&debian_dir, &names)
+}
+
+/// The autopkgtest results of the profile checks.
+pub fn get_apparmor_results(results: &[AutopkgtestResult]) -> Vec<AutopkgtestResult> {
+    results
+        .iter()
+        .filter(|result| result.test.starts_with(APPARMOR_TEST_PREFIX))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_insert_snippet() {
+        let script = "#!/bin/sh\nset -e\n\n#DEBHELPER#\n\nexit 0\n";
+        let inserted = insert_snippet(script, "echo geth").unwrap();
+        assert_eq!(
+            inserted,
+            "#!/bin/sh\nset -e\n\n# Added by pkg-builder: apparmor\necho geth\n# End pkg-builder: apparmor\n#DEBHELPER#\n\nexit 0\n"
+        );
+        assert_eq!(insert_snippet(&inserted, "echo geth").unwrap(), inserted);
+        assert!(insert_snippet("#!/bin/sh\nexit 0\n", "echo geth").is_err());
+    }
+
+    #[test]
+    fn test_add_apparmor_profiles() {
+        let config_root = tempdir().unwrap();
+        let source = tempdir().unwrap();
+        let profile = config_root.path().join("usr.bin.geth");
+        fs::write(&profile, "profile geth /usr/bin/geth {}\n").unwrap();
+        fs::create_dir_all(source.path().join("debian/tests")).unwrap();
+        fs::write(source.path().join("debian/tests/control"), "Tests: smoke\nDepends: @\n").unwrap();
+        let config = AppArmorConfig {
+            package: None,
+            profiles: vec![profile.to_string_lossy().to_string()],
+        };
+        add_apparmor_profiles(source.path(), "geth", &config).unwrap();
+        add_apparmor_profiles(source.path(), "geth", &config).unwrap();
+
+        let debian_dir = source.path().join("debian");
+        assert!(debian_dir.join("apparmor/usr.bin.geth").exists());
+        assert_eq!(
+            fs::read_to_string(debian_dir.join("geth.install")).unwrap(),
+            "debian/apparmor/usr.bin.geth etc/apparmor.d\n"
+        );
+        let postinst = fs::read_to_string(debian_dir.join("geth.postinst")).unwrap();
+        assert_eq!(postinst.matches(SNIPPET_START).count(), 1);
+        assert!(postinst.contains("for profile in usr.bin.geth; do"));
+        assert!(fs::read_to_string(debian_dir.join("geth.postrm")).unwrap().contains("\"$1\" = \"purge\""));
+        assert_eq!(
+            fs::read_to_string(debian_dir.join("tests/control")).unwrap(),
+            "Tests: smoke\nDepends: @\n\nTests: apparmor-usr.bin.geth\nDepends: @, apparmor\nRestrictions: allow-stderr\n"
+        );
+
+        let result = |test: &str| AutopkgtestResult {
+            test: test.to_string(),
+            result: "PASS".to_string(),
+            details: String::new(),
+        };
+        assert_eq!(
+            get_apparmor_results(&[result("smoke"), result("apparmor-usr.bin.geth")]),
+            vec![result("apparmor-usr.bin.geth")]
+        );
+    }
+}
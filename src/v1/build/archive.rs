@@ -0,0 +1,224 @@
+// Warnings about the targeted suite, its end of life and the Release file of the mirror the
+// chroot is updated from, an expired Valid-Until or signature breaks apt update in the chroot.
+use crate::v1::build::quality::ArchiveFinding;
+use crate::v1::distribution::DistributionInfo;
+use log::{info, warn};
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const SECONDS_PER_DAY: i64 = 86400;
+
+fn finding(check: &str, message: String) -> ArchiveFinding {
+    ArchiveFinding {
+        check: check.to_string(),
+        message,
+    }
+}
+
+// days since 1970-01-01 of the proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// YYYY-MM-DD of the unix time, in UTC.
+pub fn format_date(time: i64) -> String {
+    let (year, month, day) = civil_from_days(time.div_euclid(SECONDS_PER_DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Date of a changelog trailer of the unix time in UTC, e.g. "Sat, 19 Oct 2024 08:00:00 +0000".
+pub fn format_changelog_date(time: i64) -> String {
+    let days = time.div_euclid(SECONDS_PER_DAY);
+    let seconds = time.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} +0000",
+        // 1970-01-01 was a thursday
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Unix time of a Release file date, e.g. "Sat, 19 Oct 2024 08:00:00 UTC".
+pub fn parse_release_date(value: &str) -> Option<i64> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, ..] = parts.as_slice() else {
+        return None;
+    };
+    let month = MONTHS.iter().position(|name| name == month)? as i64 + 1;
+    let time: Vec<i64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let [hours, minutes, seconds] = time.as_slice() else {
+        return None;
+    };
+    let days = days_from_civil(year.parse().ok()?, month, day.parse().ok()?);
+    Some(days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Set once the suite is past its end of life on today, a YYYY-MM-DD date.
+pub fn check_eol(info: &DistributionInfo, today: &str) -> Option<ArchiveFinding> {
+    let eol = info.eol?;
+    if today < eol {
+        return None;
+    }
+    Some(finding(
+        "eol",
+        format!(
+            "{} reached its end of life on {}, it no longer gets security updates",
+            info.codename, eol
+        ),
+    ))
+}
+
+/// Checks the Valid-Until field of the Release file, most suites do not set it.
+pub fn check_valid_until(release: &str, now: i64) -> Option<ArchiveFinding> {
+    let valid_until = release
+        .lines()
+        .find_map(|line| line.strip_prefix("Valid-Until:"))?
+        .trim();
+    let time = parse_release_date(valid_until)?;
+    if time > now {
+        return None;
+    }
+    Some(finding(
+        "valid-until",
+        format!("Release file expired on {}, apt refuses to update from it", valid_until),
+    ))
+}
+
+/// Problems reported on the gpgv status fd, e.g. "[GNUPG:] EXPKEYSIG 648ACFD622F3D138 Debian Archive ...".
+pub fn parse_gpgv_status(status: &str) -> Vec<ArchiveFinding> {
+    status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] "))
+        .filter_map(|line| {
+            let (keyword, details) = line.split_once(' ').unwrap_or((line, ""));
+            let message = match keyword {
+                "EXPSIG" => "the Release signature expired",
+                "EXPKEYSIG" => "the Release is signed by an expired key",
+                "REVKEYSIG" => "the Release is signed by a revoked key",
+                "BADSIG" => "the Release signature is bad",
+                "NO_PUBKEY" => "the Release is signed by a key missing from the keyring",
+                _ => return None,
+            };
+            Some(finding("signature", format!("{}: {}", message, details.trim())))
+        })
+        .collect()
+}
+
+/// Fetches the InRelease of the suite and checks it against the keyring of the distribution,
+/// the check is skipped with a warning when the mirror or gpgv cannot be reached.
+pub fn check_archive(repo_url: &str, suite: &str, keyring: &str) -> Vec<ArchiveFinding> {
+    let url = format!("{}/dists/{}/InRelease", repo_url.trim_end_matches('/'), suite);
+    let Ok(dir) = tempfile::tempdir() else {
+        return vec![];
+    };
+    let release_file = dir.path().join("InRelease");
+    let fetched = Command::new("wget")
+        .arg("-q")
+        .arg("-O")
+        .arg(&release_file)
+        .arg(&url)
+        .status()
+        .is_ok_and(|status| status.success());
+    let release = match fs::read_to_string(&release_file) {
+        Ok(release) if fetched => release,
+        _ => {
+            warn!("Skipping the archive checks, {} could not be fetched", url);
+            return vec![];
+        }
+    };
+    let mut findings: Vec<ArchiveFinding> = check_valid_until(&release, now()).into_iter().collect();
+    match Command::new("gpgv")
+        .args(["--status-fd", "1", "--keyring", keyring])
+        .arg(&release_file)
+        .output()
+    {
+        Ok(output) => findings.append(&mut parse_gpgv_status(&String::from_utf8_lossy(&output.stdout))),
+        Err(err) => warn!("Skipping the Release signature check, gpgv could not be run: {}", err),
+    }
+    findings
+}
+
+/// The end of life and archive findings of the suite, offline builds only check the end of life.
+pub fn check_distribution(info: &DistributionInfo, repo_url: &str, offline: bool) -> Vec<ArchiveFinding> {
+    let mut findings: Vec<ArchiveFinding> = check_eol(info, &format_date(now())).into_iter().collect();
+    match offline {
+        true => info!("Skipping the archive checks of {}, the build is offline", info.suite),
+        false => findings.append(&mut check_archive(repo_url, info.suite, info.keyring)),
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::distribution::get_distribution;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(1729296000), "2024-10-19");
+        assert_eq!(parse_release_date("Sat, 19 Oct 2024 08:00:00 UTC"), Some(1729296000 + 8 * 3600));
+        assert_eq!(parse_release_date("Thu, 29 Feb 2024 00:00:00 UTC").map(format_date), Some("2024-02-29".to_string()));
+        assert_eq!(parse_release_date("tomorrow"), None);
+        assert_eq!(format_changelog_date(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+        assert_eq!(format_changelog_date(1729296000 + 8 * 3600 + 61), "Sat, 19 Oct 2024 08:01:01 +0000");
+        assert_eq!(format_changelog_date(1709251199), "Thu, 29 Feb 2024 23:59:59 +0000");
+    }
+
+    #[test]
+    fn test_eol_and_release_checks() {
+        let jammy = get_distribution("jammy jellyfish").unwrap().info();
+        assert_eq!(check_eol(jammy, "2026-10-15"), None);
+        assert_eq!(
+            check_eol(jammy, "2028-01-01").unwrap().message,
+            "jammy jellyfish reached its end of life on 2027-06-01, it no longer gets security updates"
+        );
+
+        let release = "Origin: Debian\nSuite: stable-backports\nValid-Until: Sat, 19 Oct 2024 08:00:00 UTC\n";
+        assert_eq!(check_valid_until(release, 1729300000), None);
+        assert_eq!(check_valid_until(release, 1729411200).unwrap().check, "valid-until");
+        assert_eq!(check_valid_until("Origin: Debian\n", 1729411200), None);
+
+        let status = "[GNUPG:] NEWSIG\n[GNUPG:] EXPKEYSIG 648ACFD622F3D138 Debian Archive Automatic Signing Key\n";
+        assert_eq!(
+            parse_gpgv_status(status),
+            vec![ArchiveFinding {
+                check: "signature".to_string(),
+                message: "the Release is signed by an expired key: 648ACFD622F3D138 Debian Archive Automatic Signing Key"
+                    .to_string(),
+            }]
+        );
+        assert!(parse_gpgv_status("[GNUPG:] GOODSIG 648ACFD622F3D138 Debian\n[GNUPG:] VALIDSIG ABC\n").is_empty());
+    }
+}
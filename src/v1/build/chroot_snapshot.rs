@@ -0,0 +1,285 @@
+// Builds in a throwaway snapshot of the unpacked chroot instead of unpacking the cached
+// tarball on every build: a btrfs snapshot when the cache dir is on btrfs, an overlay with the
+// unpacked chroot as lower dir otherwise (fuse-overlayfs). The tarball stays the source of
+// truth, the unpacked chroot is refreshed when the tarball changed, and the tarball is built in
+// when no snapshot can be made.
+//   <cache dir>/bookworm-amd64.tar.gz          cached chroot
+//   <cache dir>/bookworm-amd64/                subvolume or lower dir of the unpacked tarball
+//   <cache dir>/snapshots/bookworm-amd64-<id>  snapshot of one build
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+// inside the unpacked chroot, tar restores the mtime of the tarball root on it, so the
+// modification time of the dir itself tells nothing about the tarball it came from
+const TARBALL_STAMP_FILE_NAME: &str = ".pkg-builder-tarball";
+
+pub fn is_btrfs(dir: &Path) -> bool {
+    Command::new("stat")
+        .args(["-f", "-c", "%T"])
+        .arg(dir)
+        .output()
+        .is_ok_and(|output| output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "btrfs")
+}
+
+/// Subvolume of the unpacked tarball, next to it.
+pub fn get_base_dir(tarball: &Path) -> PathBuf {
+    let file_name = tarball.file_name().unwrap().to_string_lossy();
+    tarball.with_file_name(file_name.trim_end_matches(".tar.gz"))
+}
+
+// in a user namespace with the subordinate ids mapped, like sbuild --chroot-mode=unshare,
+// so the unpacked files keep the owners they have in the tarball
+fn run_mapped(args: &[&str]) -> Result<()> {
+    let status = Command::new("unshare")
+        .args(["--map-root-user", "--map-auto"])
+        .args(args)
+        .status()
+        .map_err(|err| eyre!("Failed to run unshare: {}", err))?;
+    if !status.success() {
+        return Err(eyre!("{} exited with {}", args.join(" "), status));
+    }
+    Ok(())
+}
+
+fn run_btrfs(args: &[&str]) -> Result<()> {
+    let output = Command::new("btrfs")
+        .args(args)
+        .output()
+        .map_err(|err| eyre!("Failed to run btrfs: {}", err))?;
+    if !output.status.success() {
+        return Err(eyre!("btrfs {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(())
+}
+
+// the files are owned by the subordinate ids, the emptied subvolume goes with rm
+fn remove_dir(dir: &Path) -> Result<()> {
+    run_mapped(&["rm", "-rf", dir.to_str().unwrap()])
+}
+
+pub fn has_fuse_overlayfs() -> bool {
+    Command::new("fuse-overlayfs")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+// mtime and size, the tarball is replaced, never modified in place
+fn get_tarball_stamp(tarball: &Path) -> Result<String> {
+    let metadata = fs::metadata(tarball)?;
+    Ok(format!("{}.{:09} {}", metadata.mtime(), metadata.mtime_nsec(), metadata.size()))
+}
+
+fn is_outdated(base_dir: &Path, tarball: &Path) -> Result<bool> {
+    let Ok(stamp) = fs::read_to_string(base_dir.join(TARBALL_STAMP_FILE_NAME)) else {
+        return Ok(true);
+    };
+    Ok(stamp.trim() != get_tarball_stamp(tarball)?)
+}
+
+fn get_unique_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    format!("{}-{}", std::process::id(), nanos)
+}
+
+fn get_sibling_dir(base_dir: &Path, kind: &str) -> PathBuf {
+    base_dir.with_file_name(format!(
+        "{}.{}-{}",
+        base_dir.file_name().unwrap().to_string_lossy(),
+        kind,
+        get_unique_id()
+    ))
+}
+
+/// Unpacks the tarball into a subvolume on btrfs, a plain dir otherwise, again when the tarball
+/// changed since it was unpacked.
+pub fn update_base(tarball: &Path, btrfs: bool) -> Result<PathBuf> {
+    let base_dir = get_base_dir(tarball);
+    if !is_outdated(&base_dir, tarball)? {
+        return Ok(base_dir);
+    }
+    info!("Unpacking {} into {}", tarball.display(), base_dir.display());
+    // unpacked aside and renamed, parallel builds never see a partial chroot
+    let tmp_dir = get_sibling_dir(&base_dir, "tmp");
+    if btrfs {
+        run_btrfs(&["subvolume", "create", tmp_dir.to_str().unwrap()])?;
+    } else {
+        fs::create_dir(&tmp_dir)?;
+    }
+    let stamp_file = tmp_dir.join(TARBALL_STAMP_FILE_NAME);
+    let unpacked = run_mapped(&["tar", "-xzf", tarball.to_str().unwrap(), "-C", tmp_dir.to_str().unwrap()])
+        .and_then(|_| get_tarball_stamp(tarball))
+        .and_then(|stamp| {
+            run_mapped(&["sh", "-c", &format!("echo '{}' > '{}'", stamp, stamp_file.display())])
+        });
+    if let Err(err) = unpacked {
+        remove_dir(&tmp_dir)?;
+        return Err(err);
+    }
+    // the outdated chroot is moved aside, never removed in place while a build may snapshot it
+    let old_dir = get_sibling_dir(&base_dir, "old");
+    let moved_aside = fs::rename(&base_dir, &old_dir).is_ok();
+    if fs::rename(&tmp_dir, &base_dir).is_err() {
+        // another build unpacked it first
+        remove_dir(&tmp_dir)?;
+    }
+    if moved_aside {
+        remove_dir(&old_dir)?;
+    }
+    Ok(base_dir)
+}
+
+/// Removes the subvolume of the tarball, if any.
+pub fn remove_base(tarball: &Path) -> Result<()> {
+    let base_dir = get_base_dir(tarball);
+    if base_dir.exists() {
+        info!("Cleaning unpacked chroot: {}", base_dir.display());
+        remove_dir(&base_dir)?;
+    }
+    Ok(())
+}
+
+/// Writable snapshot of the unpacked chroot, deleted when dropped.
+pub struct ChrootSnapshot {
+    dir: PathBuf,
+    /// merged dir of an overlay snapshot, unmounted before dir is removed
+    mount_dir: Option<PathBuf>,
+}
+
+impl ChrootSnapshot {
+    fn get_snapshot_dir(base_dir: &Path) -> Result<PathBuf> {
+        let snapshots_dir = base_dir.parent().unwrap().join(SNAPSHOTS_DIR_NAME);
+        fs::create_dir_all(&snapshots_dir)?;
+        Ok(snapshots_dir.join(format!(
+            "{}-{}",
+            base_dir.file_name().unwrap().to_string_lossy(),
+            get_unique_id()
+        )))
+    }
+
+    pub fn create_btrfs(base_dir: &Path) -> Result<Self> {
+        let dir = Self::get_snapshot_dir(base_dir)?;
+        run_btrfs(&["subvolume", "snapshot", base_dir.to_str().unwrap(), dir.to_str().unwrap()])?;
+        Ok(ChrootSnapshot { dir, mount_dir: None })
+    }
+
+    // mounted from the user namespace of the unpacked files, fusermount puts the mount into the
+    // mount namespace of the host where sbuild finds it
+    pub fn create_overlay(base_dir: &Path) -> Result<Self> {
+        let dir = Self::get_snapshot_dir(base_dir)?;
+        for name in ["upper", "work", "merged"] {
+            fs::create_dir_all(dir.join(name))?;
+        }
+        let mount_dir = dir.join("merged");
+        // without a mount when fuse-overlayfs fails, dropping it removes dir again
+        let mut snapshot = ChrootSnapshot {
+            dir: dir.clone(),
+            mount_dir: None,
+        };
+        run_mapped(&[
+            "fuse-overlayfs",
+            "-o",
+            &format!(
+                "lowerdir={},upperdir={},workdir={}",
+                base_dir.display(),
+                dir.join("upper").display(),
+                dir.join("work").display()
+            ),
+            mount_dir.to_str().unwrap(),
+        ])?;
+        snapshot.mount_dir = Some(mount_dir);
+        Ok(snapshot)
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        self.mount_dir.as_deref().unwrap_or(&self.dir)
+    }
+}
+
+impl Drop for ChrootSnapshot {
+    fn drop(&mut self) {
+        if let Some(mount_dir) = &self.mount_dir {
+            let unmounted = Command::new("fusermount").arg("-u").arg(mount_dir).status();
+            if !unmounted.is_ok_and(|status| status.success()) {
+                warn!("Failed to unmount chroot overlay {}, it is left in place", mount_dir.display());
+                return;
+            }
+        }
+        if let Err(err) = remove_dir(&self.dir) {
+            warn!("Failed to remove chroot snapshot {}: {}", self.dir.display(), err);
+        }
+    }
+}
+
+/// The program that snapshots chroots in the cache dir, None when the tarball is unpacked.
+pub fn get_snapshot_program(cache_dir: &Path) -> Option<&'static str> {
+    if is_btrfs(cache_dir) {
+        Some("btrfs")
+    } else if has_fuse_overlayfs() {
+        Some("fuse-overlayfs")
+    } else {
+        None
+    }
+}
+
+/// Snapshot to build in, None when neither btrfs nor fuse-overlayfs are available or the
+/// snapshot failed, the build then unpacks the tarball as usual.
+pub fn get_chroot_snapshot(tarball: &Path) -> Option<ChrootSnapshot> {
+    let cache_dir = tarball.parent()?;
+    let Some(program) = get_snapshot_program(cache_dir) else {
+        warn!(
+            "{} is not on btrfs and fuse-overlayfs is not installed, building in the unpacked tarball instead of a snapshot",
+            cache_dir.display()
+        );
+        return None;
+    };
+    let btrfs = program == "btrfs";
+    let snapshot = update_base(tarball, btrfs).and_then(|base_dir| match btrfs {
+        true => ChrootSnapshot::create_btrfs(&base_dir),
+        false => ChrootSnapshot::create_overlay(&base_dir),
+    });
+    match snapshot {
+        Ok(snapshot) => Some(snapshot),
+        Err(err) => {
+            warn!("Building in the unpacked tarball, the chroot snapshot failed: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_falls_back_to_the_tarball() {
+        assert_eq!(
+            get_base_dir(Path::new("/cache/bookworm-amd64-rust-0123456789ab.tar.gz")),
+            Path::new("/cache/bookworm-amd64-rust-0123456789ab")
+        );
+        let dir = tempdir().unwrap();
+        let tarball = dir.path().join("bookworm-amd64.tar.gz");
+        fs::write(&tarball, "").unwrap();
+        let base_dir = get_base_dir(&tarball);
+        assert!(is_outdated(&base_dir, &tarball).unwrap());
+        // unpacked from this tarball, whatever the mtime of the dir
+        fs::create_dir(&base_dir).unwrap();
+        fs::write(base_dir.join(TARBALL_STAMP_FILE_NAME), get_tarball_stamp(&tarball).unwrap() + "\n").unwrap();
+        filetime::set_file_mtime(&base_dir, filetime::FileTime::from_unix_time(0, 0)).unwrap();
+        assert!(!is_outdated(&base_dir, &tarball).unwrap());
+        filetime::set_file_mtime(&tarball, filetime::FileTime::from_unix_time(1, 0)).unwrap();
+        assert!(is_outdated(&base_dir, &tarball).unwrap());
+        fs::remove_dir_all(&base_dir).unwrap();
+        if !is_btrfs(dir.path()) && !has_fuse_overlayfs() {
+            assert!(get_chroot_snapshot(&tarball).is_none());
+        }
+        remove_base(&tarball).unwrap();
+    }
+}
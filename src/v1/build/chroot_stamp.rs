@@ -0,0 +1,107 @@
+// Records when the cached chroot was created and last updated, next to the tarball:
+//   <cache dir>/bookworm-amd64.tar.gz
+//   <cache dir>/bookworm-amd64.stamp.toml
+// `env update` upgrades the packages of the tarball in place until it is older than the max age,
+// then it is recreated from scratch so removed or renamed packages of the suite do not pile up.
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_CHROOT_MAX_AGE_DAYS: u64 = 30;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ChrootStamp {
+    /// unix time of the sbuild-createchroot run
+    pub created: u64,
+    /// unix time of the last `env update`, the creation when never updated
+    pub updated: u64,
+    /// sha256 of the tarball as of the last create or update
+    pub sha256: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+pub fn get_stamp_file(tarball: &Path) -> PathBuf {
+    let file_name = tarball.file_name().unwrap().to_string_lossy();
+    tarball.with_file_name(format!("{}.stamp.toml", file_name.trim_end_matches(".tar.gz")))
+}
+
+impl ChrootStamp {
+    /// Stamp of a tarball created or updated just now.
+    pub fn new(tarball: &Path, created: Option<u64>) -> Result<Self> {
+        let updated = now();
+        Ok(ChrootStamp {
+            created: created.unwrap_or(updated),
+            updated,
+            sha256: calculate_sha256(open_for_hashing(tarball)?)?,
+        })
+    }
+
+    /// The recorded stamp, for tarballs of earlier versions without one the creation is the
+    /// modification time of the tarball.
+    pub fn read(tarball: &Path) -> Result<Self> {
+        if let Some(stamp) = fs::read_to_string(get_stamp_file(tarball))
+            .ok()
+            .and_then(|content| toml::from_str::<ChrootStamp>(&content).ok())
+        {
+            return Ok(stamp);
+        }
+        let modified = fs::metadata(tarball)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        Ok(ChrootStamp {
+            created: modified,
+            updated: modified,
+            sha256: String::new(),
+        })
+    }
+
+    pub fn write(&self, tarball: &Path) -> Result<()> {
+        fs::write(get_stamp_file(tarball), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get_age_days(&self) -> u64 {
+        now().saturating_sub(self.created) / SECS_PER_DAY
+    }
+
+    pub fn is_expired(&self, max_age_days: u64) -> bool {
+        self.get_age_days() >= max_age_days
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stamp_expires_after_max_age() {
+        let dir = tempdir().unwrap();
+        let tarball = dir.path().join("bookworm-amd64.tar.gz");
+        fs::write(&tarball, "chroot").unwrap();
+        assert_eq!(get_stamp_file(&tarball), dir.path().join("bookworm-amd64.stamp.toml"));
+
+        // no stamp yet, the tarball was just written
+        let stamp = ChrootStamp::read(&tarball).unwrap();
+        assert!(stamp.sha256.is_empty());
+        assert!(!stamp.is_expired(DEFAULT_CHROOT_MAX_AGE_DAYS));
+
+        let created = now() - 40 * SECS_PER_DAY;
+        let stamp = ChrootStamp::new(&tarball, Some(created)).unwrap();
+        stamp.write(&tarball).unwrap();
+        let stamp = ChrootStamp::read(&tarball).unwrap();
+        assert_eq!(stamp.created, created);
+        assert_eq!(stamp.sha256, calculate_sha256("chroot".as_bytes()).unwrap());
+        assert_eq!(stamp.get_age_days(), 40);
+        assert!(stamp.is_expired(DEFAULT_CHROOT_MAX_AGE_DAYS));
+        assert!(!stamp.is_expired(60));
+    }
+}
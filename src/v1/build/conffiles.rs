@@ -0,0 +1,145 @@
+// Renders the templated conffiles of the config, e.g. /etc/default/<package>, into the debian
+// dir and installs them with debhelper, which registers every file under /etc as conffile.
+// piuparts checks that they are still pristine after the install, so upgrades do not prompt.
+use crate::v1::build::extras::append_lines;
+use crate::v1::pkg_config::{ConffilesConfig, PackageFields};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Variables every template can use besides its own.
+pub const CONFFILE_BUILTINS: &[&str] = &["package_name", "version"];
+// rendered files are kept apart from the debcrafter output
+const RENDERED_DIR: &str = "pkg-builder-conffiles";
+
+fn placeholder() -> Regex {
+    Regex::new(r"\{\{\s*([A-Za-z0-9_]+)\s*\}\}").unwrap()
+}
+
+/// Names of the {{ name }} placeholders of the template.
+pub fn get_placeholders(template: &str) -> Vec<String> {
+    placeholder()
+        .captures_iter(template)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+pub fn render_template(template: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    if let Some(name) = get_placeholders(template).into_iter().find(|name| !variables.contains_key(name)) {
+        return Err(eyre!("conffiles: no value for {{{{ {} }}}}", name));
+    }
+    let rendered = placeholder().replace_all(template, |captures: &regex::Captures| variables[&captures[1]].clone());
+    let mut rendered = rendered.to_string();
+    if !rendered.ends_with('\n') {
+        rendered.push('\n');
+    }
+    Ok(rendered)
+}
+
+/// Renders the conffiles and adds their install entries, repeated runs leave the debian dir unchanged.
+pub fn add_conffiles(build_files_dir: &Path, package_fields: &PackageFields, config: &ConffilesConfig) -> Result<()> {
+    let package = config.package.as_deref().unwrap_or(&package_fields.package_name);
+    let is_main = package == package_fields.package_name;
+    let debian_dir = build_files_dir.join("debian");
+
+    let mut install = vec![];
+    for conffile in config.files.iter() {
+        let mut variables = BTreeMap::from([
+            ("package_name".to_string(), package_fields.package_name.clone()),
+            ("version".to_string(), package_fields.version_number.clone()),
+        ]);
+        variables.extend(conffile.variables.clone());
+        let content = render_template(&conffile.template, &variables)
+            .map_err(|err| eyre!("{}: {}", conffile.path, err))?;
+
+        let relative = conffile.path.trim_start_matches('/');
+        let rendered = Path::new("debian").join(RENDERED_DIR).join(package).join(relative);
+        fs::create_dir_all(build_files_dir.join(&rendered).parent().unwrap())?;
+        fs::write(build_files_dir.join(&rendered), content)?;
+        let target_dir = Path::new(relative).parent().unwrap();
+        install.push(format!("{} {}", rendered.display(), target_dir.display()));
+    }
+    append_lines(&debian_dir, package, is_main, "install", &install)
+}
+
+/// piuparts custom script failing when a conffile is not registered or was changed by the
+/// maintainer scripts, dpkg would prompt on the next upgrade of a changed one.
+pub fn write_piuparts_conffile_check(scripts_dir: &Path, config: &ConffilesConfig, package_name: &str) -> Result<()> {
+    let package = config.package.as_deref().unwrap_or(package_name);
+    let paths: Vec<&str> = config.files.iter().map(|conffile| conffile.path.as_str()).collect();
+    let script = scripts_dir.join("post_install_conffiles");
+    fs::write(
+        &script,
+        format!(
+            r#"#!/bin/sh
+set -e
+dpkg-query --show --showformat='${{Conffiles}}\n' {package} > /tmp/conffiles
+for conffile in {paths}; do
+    sum=$(awk -v path="$conffile" '$1 == path {{ print $2 }}' /tmp/conffiles)
+    if [ -z "$sum" ]; then
+        echo "ERROR: $conffile is not a conffile of {package}"
+        exit 1
+    fi
+    if [ "$(md5sum < "$conffile" | cut -d' ' -f1)" != "$sum" ]; then
+        echo "ERROR: $conffile was modified during the install, upgrades would prompt"
+        exit 1
+    fi
+done
+"#,
+            package = package,
+            paths = paths.join(" ")
+        ),
+    )?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::ConffileTemplate;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_add_conffiles() {
+        let source = tempdir().unwrap();
+        let root = source.path();
+        fs::create_dir_all(root.join("debian")).unwrap();
+        let package_fields = PackageFields {
+            package_name: "lodestar".to_string(),
+            version_number: "1.20.0".to_string(),
+            ..Default::default()
+        };
+        let config = ConffilesConfig {
+            package: None,
+            files: vec![ConffileTemplate {
+                path: "/etc/default/lodestar".to_string(),
+                template: "# {{package_name}} {{ version }}\nPORT={{port}}".to_string(),
+                variables: BTreeMap::from([("port".to_string(), "9000".to_string())]),
+            }],
+        };
+        add_conffiles(root, &package_fields, &config).unwrap();
+        add_conffiles(root, &package_fields, &config).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("debian/pkg-builder-conffiles/lodestar/etc/default/lodestar")).unwrap(),
+            "# lodestar 1.20.0\nPORT=9000\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("debian/lodestar.install")).unwrap(),
+            "debian/pkg-builder-conffiles/lodestar/etc/default/lodestar etc/default\n"
+        );
+
+        let error = render_template("PORT={{port}}", &BTreeMap::new()).unwrap_err();
+        assert_eq!(error.to_string(), "conffiles: no value for {{ port }}");
+
+        let scripts_dir = tempdir().unwrap();
+        write_piuparts_conffile_check(scripts_dir.path(), &config, "lodestar").unwrap();
+        let script = fs::read_to_string(scripts_dir.path().join("post_install_conffiles")).unwrap();
+        assert!(script.contains("--showformat='${Conffiles}\\n' lodestar"));
+        assert!(script.contains("for conffile in /etc/default/lodestar; do"));
+    }
+}
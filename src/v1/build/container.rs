@@ -0,0 +1,301 @@
+// Builds with dpkg-buildpackage in a docker or podman container instead of an sbuild chroot,
+// for CI systems that cannot run unshare chroots. The image of the suite gets the toolchain
+// installed once and is tagged by the hash of its Containerfile, so a changed pin builds a new
+// image. The base image is pinned by digest in the Containerfile, so a moved tag of the suite
+// builds a new image as well. The artifacts and the build log land next to the source like
+// sbuild puts them, with the setup output and the chroot-packages hooks sbuild logs.
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::build::manifest::{chroot_packages_before_command, chroot_packages_command};
+use crate::v1::build::provision::ChrootProvisioner;
+use crate::v1::build::store::ContentStore;
+use crate::v1::packager::BuildBackend;
+use eyre::{eyre, Result};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const BUILD_DIR: &str = "/build";
+const STAGED_DIR_NAME: &str = "staged";
+// output of the setup commands, run when the image is built, printed into every build log
+const SETUP_LOG: &str = "/var/log/pkg-builder-setup.log";
+
+pub struct ContainerBackend {
+    /// "docker" or "podman"
+    pub engine: &'static str,
+    /// image of the suite, e.g. docker.io/library/debian:bookworm, or pinned by digest
+    pub base_image: String,
+    /// e.g. bookworm-amd64
+    pub name: String,
+    pub provisioner: ChrootProvisioner,
+    pub download_cache_dir: PathBuf,
+    /// run after the toolchain, proxies first
+    pub setup_commands: Vec<String>,
+    pub build_files_dir: PathBuf,
+    pub build_log_file: PathBuf,
+    pub environment: BTreeMap<String, String>,
+    pub jobs: usize,
+    /// only the arch all debs and the source are built
+    pub arch_all: bool,
+}
+
+/// Containerfile of the build image, staged files are copied from the staged dir of the context.
+pub fn get_containerfile(base_image: &str, has_staged_files: bool, setup_commands: &[String]) -> String {
+    let mut containerfile = format!("FROM {}\nENV DEBIAN_FRONTEND=noninteractive\n", base_image);
+    if has_staged_files {
+        containerfile.push_str(&format!("COPY {}/ /\n", STAGED_DIR_NAME));
+    }
+    containerfile.push_str(&format!(
+        "RUN apt-get update && apt-get install -y --no-install-recommends build-essential devscripts equivs fakeroot && touch {}\n",
+        SETUP_LOG
+    ));
+    for command in setup_commands {
+        containerfile.push_str(&format!(
+            "RUN ({}) >> {1} 2>&1 || {{ cat {1}; exit 1; }}\n",
+            command, SETUP_LOG
+        ));
+    }
+    containerfile
+}
+
+/// Installs the build dependencies and builds the source dir, owner gets the files back when
+/// root in the container is not the invoking user, as with docker.
+pub fn get_build_script(source_dir_name: &str, arch_all: bool, jobs: usize, owner: Option<(u32, u32)>) -> String {
+    let source_dir = format!("{}/{}", BUILD_DIR, source_dir_name);
+    let mut script = "set -e\nexec 2>&1\n".to_string();
+    if let Some((uid, gid)) = owner {
+        script.push_str(&format!("trap 'chown -R {}:{} {}' EXIT\n", uid, gid, BUILD_DIR));
+    }
+    script.push_str(&format!("cat {}\n", SETUP_LOG));
+    script.push_str("apt-get update\n");
+    // mk-build-deps leaves its own changes and buildinfo in the working dir
+    script.push_str(&format!(
+        "cd /tmp && mk-build-deps --install --remove --tool 'apt-get -y --no-install-recommends' {}/debian/control\n",
+        source_dir
+    ));
+    script.push_str(&format!("{}\n", chroot_packages_before_command()));
+    script.push_str(&format!(
+        "cd {} && dpkg-buildpackage -us -uc -sa --build={} -j{}\n",
+        source_dir,
+        if arch_all { "source,all" } else { "full" },
+        jobs
+    ));
+    script.push_str(&format!("{}\n", chroot_packages_command()));
+    script
+}
+
+impl ContainerBackend {
+    fn get_containerfile(&self) -> Result<String> {
+        let has_staged_files = !self.provisioner.get_staged_files(&self.download_cache_dir).is_empty();
+        Ok(get_containerfile(&self.get_pinned_base_image()?, has_staged_files, &self.setup_commands))
+    }
+
+    // the base image at the digest it was pulled with, unless it is pinned already
+    fn get_pinned_base_image(&self) -> Result<String> {
+        if self.base_image.contains("@sha256:") {
+            return Ok(self.base_image.clone());
+        }
+        if self.run_engine(&["image", "inspect", &self.base_image]).is_err() {
+            info!("Pulling {}", self.base_image);
+            self.run_engine(&["pull", &self.base_image])?;
+        }
+        let digest = self.run_engine(&["image", "inspect", "--format", "{{index .RepoDigests 0}}", &self.base_image])?;
+        Ok(digest.trim().to_string())
+    }
+
+    fn get_repository(&self) -> String {
+        format!("pkg-builder/{}", self.name)
+    }
+
+    /// Tag of the image, the staged files are named by their sha256 in the download cache.
+    pub fn get_tag(&self) -> Result<String> {
+        let mut pins = self.get_containerfile()?;
+        for (host_path, path) in self.provisioner.get_staged_files(&self.download_cache_dir) {
+            pins.push_str(&format!("{} {}\n", host_path.file_name().unwrap().to_string_lossy(), path));
+        }
+        let hash = calculate_sha256(pins.as_bytes())?;
+        Ok(format!("{}:{}", self.get_repository(), &hash[..12]))
+    }
+
+    fn run_engine(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new(self.engine)
+            .args(args)
+            .output()
+            .map_err(|err| eyre!("Failed to run {}: {}", self.engine, err))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "{} {} failed: {}",
+                self.engine,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn get_owner(&self) -> Result<Option<(u32, u32)>> {
+        // rootless podman maps root of the container to the invoking user
+        if self.engine == "podman" {
+            return Ok(None);
+        }
+        let metadata = fs::metadata(self.build_files_dir.parent().unwrap())?;
+        Ok(Some((metadata.uid(), metadata.gid())))
+    }
+}
+
+impl BuildBackend for ContainerBackend {
+    fn create(&self) -> Result<()> {
+        let tag = self.get_tag()?;
+        if self.run_engine(&["image", "inspect", &tag]).is_ok() {
+            return Ok(());
+        }
+        info!("Building the build image {}", tag);
+        self.provisioner.fetch(&self.download_cache_dir, &ContentStore::new(ContentStore::default_root()))?;
+        let context_dir = tempfile::tempdir()?;
+        self.provisioner
+            .stage_into(&self.download_cache_dir, &context_dir.path().join(STAGED_DIR_NAME))?;
+        let containerfile = context_dir.path().join("Containerfile");
+        fs::write(&containerfile, self.get_containerfile()?)?;
+        let status = Command::new(self.engine)
+            .args(["build", "-t", &tag, "-f"])
+            .arg(&containerfile)
+            .arg(context_dir.path())
+            .status()?;
+        if !status.success() {
+            return Err(eyre!("Failed to build the build image {}: {} exited with {}", tag, self.engine, status));
+        }
+        Ok(())
+    }
+
+    // images of every pin of the suite and arch
+    fn clean(&self) -> Result<()> {
+        let images = self.run_engine(&["images", "--format", "{{.Repository}}:{{.Tag}}"])?;
+        let prefix = format!("{}:", self.get_repository());
+        for image in images.lines() {
+            if image.trim_start_matches("localhost/").starts_with(&prefix) {
+                info!("Cleaning build image: {}", image);
+                self.run_engine(&["image", "rm", image])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn build(&self) -> Result<()> {
+        self.create()?;
+        let deb_dir = self.build_files_dir.parent().unwrap();
+        let source_dir_name = self.build_files_dir.file_name().unwrap().to_string_lossy().to_string();
+        let script = get_build_script(&source_dir_name, self.arch_all, self.jobs, self.get_owner()?);
+        let mut command = Command::new(self.engine);
+        command
+            .args(["run", "--rm", "-v"])
+            .arg(format!("{}:{}", deb_dir.display(), BUILD_DIR));
+        for (name, value) in self.environment.iter() {
+            command.arg("-e").arg(format!("{}={}", name, value));
+        }
+        let tag = self.get_tag()?;
+        command.arg(&tag).args(["sh", "-c", &script]);
+        info!("Building package in {} image {}", self.engine, tag);
+
+        let mut child = command.stdout(Stdio::piped()).spawn()?;
+        let mut build_log = fs::File::create(&self.build_log_file)?;
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines() {
+                let line = line?;
+                writeln!(build_log, "{}", line)?;
+                info!("{}", line);
+            }
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(eyre!(
+                "Build in the {} container failed, see {}",
+                self.engine,
+                self.build_log_file.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::{Arch, Backend, BuildEnv, Validation};
+
+    #[test]
+    fn test_container_build() {
+        let mut provisioner = ChrootProvisioner::new();
+        provisioner.stage("https://go.dev/dl/go1.22.2.linux-amd64.tar.gz", "abcd", "/tmp/go.tar.gz");
+        let base_image = format!("docker.io/library/debian@sha256:{}", "1".repeat(64));
+        let mut backend = ContainerBackend {
+            engine: "podman",
+            base_image: base_image.clone(),
+            name: "bookworm-amd64".to_string(),
+            provisioner,
+            download_cache_dir: PathBuf::from("/cache/downloads"),
+            setup_commands: vec!["tar -C /usr/local -xzf /tmp/go.tar.gz".to_string()],
+            build_files_dir: PathBuf::from("/workdir/hello-world-1.0.0-1/hello-world-1.0.0"),
+            build_log_file: PathBuf::from("/workdir/hello-world-1.0.0-1/hello-world_1.0.0-1_amd64.build"),
+            environment: BTreeMap::new(),
+            jobs: 4,
+            arch_all: false,
+        };
+        let containerfile = backend.get_containerfile().unwrap();
+        assert!(containerfile.starts_with(&format!("FROM {}\n", base_image)));
+        assert!(containerfile.contains("COPY staged/ /\n"));
+        assert!(containerfile.ends_with(
+            "RUN (tar -C /usr/local -xzf /tmp/go.tar.gz) >> /var/log/pkg-builder-setup.log 2>&1 || { cat /var/log/pkg-builder-setup.log; exit 1; }\n"
+        ));
+        let tag = backend.get_tag().unwrap();
+        assert!(tag.starts_with("pkg-builder/bookworm-amd64:"));
+        assert_eq!(tag, backend.get_tag().unwrap());
+        // another digest of the base image is another image
+        backend.base_image = format!("docker.io/library/debian@sha256:{}", "2".repeat(64));
+        assert_ne!(tag, backend.get_tag().unwrap());
+
+        let script = get_build_script("hello-world-1.0.0", true, 4, Some((1000, 1000)));
+        assert!(script.contains("trap 'chown -R 1000:1000 /build' EXIT\n"));
+        assert!(script.contains("cat /var/log/pkg-builder-setup.log\n"));
+        assert!(script.contains(&format!(
+            "{}\ncd /build/hello-world-1.0.0 && dpkg-buildpackage -us -uc -sa --build=source,all -j4\n{}\n",
+            chroot_packages_before_command(),
+            chroot_packages_command()
+        )));
+
+        let foreign = match Arch::host() {
+            Arch::Arm64 => Arch::Amd64,
+            _ => Arch::Arm64,
+        };
+        let build_env = BuildEnv {
+            arch: foreign,
+            backend: Some(Backend::Podman),
+            ..Default::default()
+        };
+        let errors = build_env.validate().unwrap_err();
+        assert!(errors.iter().any(|error| error.to_string().starts_with("field: backend podman builds for")));
+
+        // the options of the sbuild chroot are refused instead of ignored
+        let build_env = BuildEnv {
+            backend: Some(Backend::Podman),
+            fixed_build_path: Some(true),
+            build_user: Some("builder".to_string()),
+            ..Default::default()
+        };
+        let errors: Vec<String> = build_env.validate().unwrap_err().iter().map(|error| error.to_string()).collect();
+        assert!(errors.contains(&"field: fixed_build_path needs backend sbuild, not podman".to_string()));
+        assert!(errors.contains(&"field: build_user needs backend sbuild, not podman".to_string()));
+
+        // the legacy docker = true builds with the docker backend
+        let build_env = BuildEnv {
+            docker: Some(true),
+            container_image: Some("docker.io/library/debian:bookworm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(build_env.get_backend(), Some(Backend::Docker));
+        let errors = build_env.validate().unwrap_err();
+        assert!(errors.iter().any(|error| error.to_string().starts_with("field: container_image has to be pinned by digest")));
+    }
+}
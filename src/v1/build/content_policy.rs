@@ -0,0 +1,215 @@
+// Checks the contents of the built debs against the [content_policy] of the package, the
+// installed size, files outside of the debian file hierarchy, setuid files and embedded libraries.
+use crate::v1::build::quality::{ContentFinding, FindingLevel};
+use crate::v1::pkg_config::ContentPolicyConfig;
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// left to the local administrator, and to add-on packages outside of debian
+const FORBIDDEN_DIRS: &[&str] = &["/usr/local/", "/opt/"];
+// libraries debian ships as packages of their own, the debs should depend on those
+const KNOWN_LIBRARIES: &[&str] = &[
+    "libbz2", "libcrypto", "libcurl", "libffi", "libgmp", "libjpeg", "liblz4", "liblzma", "libpng",
+    "librocksdb", "libsecp256k1", "libsnappy", "libsqlite3", "libssl", "libuv", "libxml2", "libyaml",
+    "libz", "libzstd",
+];
+
+/// File of the dpkg-deb -c listing.
+#[derive(Debug, PartialEq)]
+pub struct DebEntry {
+    /// e.g. -rwsr-xr-x
+    pub mode: String,
+    /// absolute path as installed, e.g. /usr/bin/geth
+    pub path: String,
+}
+
+fn finding(package: &str, level: FindingLevel, message: String) -> ContentFinding {
+    ContentFinding {
+        package: package.to_string(),
+        level,
+        message,
+    }
+}
+
+pub fn dpkg_deb(args: &[&str], deb: &Path) -> Result<String> {
+    let output = Command::new("dpkg-deb").args(args).arg(deb).output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "dpkg-deb {} {} failed: {}",
+            args.join(" "),
+            deb.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Parses dpkg-deb -c, e.g. "-rwxr-xr-x root/root 1234 2024-06-05 10:00 ./usr/bin/geth",
+/// symlink targets are dropped.
+pub fn parse_contents(listing: &str) -> Vec<DebEntry> {
+    let re = Regex::new(r"^(\S{10})\s+\S+\s+\d+\s+\S+\s+\S+\s+\.(/.*)$").unwrap();
+    listing
+        .lines()
+        .filter_map(|line| {
+            let captures = re.captures(line)?;
+            let path = &captures[2];
+            let path = path.split_once(" -> ").map_or(path, |(path, _)| path);
+            Some(DebEntry {
+                mode: captures[1].to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn is_allowed(path: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .filter_map(|pattern| glob::Pattern::new(pattern).ok())
+        .any(|pattern| pattern.matches(path))
+}
+
+// e.g. /usr/lib/geth/libsecp256k1.so.0 -> libsecp256k1, static archives count as well
+fn get_library_name(path: &str) -> Option<&str> {
+    let file_name = path.rsplit('/').next()?;
+    let (name, suffix) = file_name.split_once('.')?;
+    if !(suffix == "a" || suffix == "so" || suffix.starts_with("so.")) {
+        return None;
+    }
+    KNOWN_LIBRARIES.iter().find(|library| **library == name).copied()
+}
+
+/// Findings of one deb, installed_size is in KiB as in the Installed-Size field.
+pub fn check_contents(
+    package: &str,
+    installed_size: Option<u64>,
+    entries: &[DebEntry],
+    config: &ContentPolicyConfig,
+) -> Vec<ContentFinding> {
+    let mut findings = vec![];
+    if let (Some(max), Some(size)) = (config.max_installed_size_kb, installed_size) {
+        if size > max {
+            findings.push(finding(
+                package,
+                FindingLevel::Error,
+                format!("Installed-Size {} KiB exceeds max_installed_size_kb {} KiB", size, max),
+            ));
+        }
+    }
+    for entry in entries.iter().filter(|entry| !entry.mode.starts_with('d')) {
+        let path = entry.path.as_str();
+        let forbidden = FORBIDDEN_DIRS.iter().find(|dir| path.starts_with(*dir));
+        if let Some(dir) = forbidden {
+            if !is_allowed(path, &config.allowed_paths) {
+                findings.push(finding(
+                    package,
+                    FindingLevel::Error,
+                    format!("{} is under {}, add it to allowed_paths if intended", path, dir),
+                ));
+            }
+        }
+        let mode: Vec<char> = entry.mode.chars().collect();
+        let setuid = matches!(mode[3], 's' | 'S');
+        let setgid = matches!(mode[6], 's' | 'S');
+        if (setuid || setgid) && !is_allowed(path, &config.allowed_setuid) {
+            let bits = match (setuid, setgid) {
+                (true, true) => "setuid and setgid",
+                (true, false) => "setuid",
+                _ => "setgid",
+            };
+            findings.push(finding(
+                package,
+                FindingLevel::Error,
+                format!("{} is {}, add it to allowed_setuid if intended", path, bits),
+            ));
+        }
+        if let Some(library) = get_library_name(path) {
+            if !config.allowed_libraries.iter().any(|allowed| allowed == library) {
+                findings.push(finding(
+                    package,
+                    FindingLevel::Warning,
+                    format!("{} is an embedded copy of {}, depend on the packaged library instead", path, library),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+/// Findings of every deb, read with dpkg-deb.
+pub fn check_debs(debs: &[PathBuf], config: &ContentPolicyConfig) -> Result<Vec<ContentFinding>> {
+    let mut findings = vec![];
+    for deb in debs {
+        // the whole control file
+        let control = dpkg_deb(&["--field"], deb)?;
+        let mut name = String::new();
+        let mut installed_size = None;
+        for line in control.lines() {
+            if let Some(value) = line.strip_prefix("Package:") {
+                name = value.trim().to_string();
+            } else if let Some(value) = line.strip_prefix("Installed-Size:") {
+                installed_size = value.trim().parse::<u64>().ok();
+            }
+        }
+        let entries = parse_contents(&dpkg_deb(&["-c"], deb)?);
+        findings.append(&mut check_contents(&name, installed_size, &entries, config));
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_contents() {
+        let listing = "drwxr-xr-x root/root         0 2024-06-05 10:00 ./\n\
+                       drwxr-xr-x root/root         0 2024-06-05 10:00 ./usr/local/\n\
+                       -rwxr-xr-x root/root   1048576 2024-06-05 10:00 ./usr/bin/geth\n\
+                       -rwsr-xr-x root/root     16384 2024-06-05 10:00 ./usr/bin/geth-helper\n\
+                       -rw-r--r-- root/root      4096 2024-06-05 10:00 ./usr/local/share/geth/genesis.json\n\
+                       -rw-r--r-- root/root      2048 2024-06-05 10:00 ./opt/geth/README\n\
+                       -rw-r--r-- root/root    262144 2024-06-05 10:00 ./usr/lib/geth/libsecp256k1.so.0.0.0\n\
+                       -rw-r--r-- root/root    131072 2024-06-05 10:00 ./usr/lib/geth/libgeth.so\n\
+                       lrwxrwxrwx root/root         0 2024-06-05 10:00 ./usr/lib/geth/libz.so -> libz.so.1\n";
+        let entries = parse_contents(listing);
+        assert_eq!(entries.len(), 9);
+        assert_eq!(
+            entries[8],
+            DebEntry {
+                mode: "lrwxrwxrwx".to_string(),
+                path: "/usr/lib/geth/libz.so".to_string(),
+            }
+        );
+
+        let config = ContentPolicyConfig {
+            max_installed_size_kb: Some(1024),
+            allowed_paths: vec!["/opt/geth/*".to_string()],
+            allowed_setuid: vec![],
+            allowed_libraries: vec!["libz".to_string()],
+        };
+        let messages: Vec<String> = check_contents("geth", Some(1500), &entries, &config)
+            .into_iter()
+            .map(|finding| format!("{}: {}", finding.level, finding.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: Installed-Size 1500 KiB exceeds max_installed_size_kb 1024 KiB",
+                "error: /usr/bin/geth-helper is setuid, add it to allowed_setuid if intended",
+                "error: /usr/local/share/geth/genesis.json is under /usr/local/, add it to allowed_paths if intended",
+                "warning: /usr/lib/geth/libsecp256k1.so.0.0.0 is an embedded copy of libsecp256k1, depend on the packaged library instead",
+            ]
+        );
+
+        let config = ContentPolicyConfig {
+            allowed_paths: vec!["/opt/geth/*".to_string(), "/usr/local/share/geth/*".to_string()],
+            allowed_setuid: vec!["/usr/bin/geth-helper".to_string()],
+            allowed_libraries: vec!["libz".to_string(), "libsecp256k1".to_string()],
+            ..ContentPolicyConfig::default()
+        };
+        assert_eq!(check_contents("geth", Some(1500), &entries, &config), vec![]);
+    }
+}
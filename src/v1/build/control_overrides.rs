@@ -0,0 +1,414 @@
+use crate::v1::pkg_config::{ControlOverrides, MultiArchConfig};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+
+// the package a relation is about, "libssl3 (>= 3.0) | libssl1.1" -> "libssl3"
+fn relation_key(relation: &str) -> String {
+    let relation = relation.trim();
+    if relation.starts_with("${") {
+        return relation.to_string();
+    }
+    relation
+        .split(|c: char| c.is_whitespace() || c == '(' || c == '[' || c == ':' || c == '|')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn merge_relations(generated: &str, overrides: &[String]) -> String {
+    let mut relations: Vec<String> = generated
+        .split(',')
+        .map(|relation| relation.trim().to_string())
+        .filter(|relation| !relation.is_empty())
+        .collect();
+    for relation in overrides {
+        let key = relation_key(relation);
+        match relations
+            .iter_mut()
+            .find(|existing| relation_key(existing) == key)
+        {
+            Some(existing) => *existing = relation.trim().to_string(),
+            None => relations.push(relation.trim().to_string()),
+        }
+    }
+    relations.join(", ")
+}
+
+// a field value with its continuation lines joined
+fn get_field(stanza: &[String], field: &str) -> Option<(usize, usize, String)> {
+    let prefix = format!("{}:", field);
+    let start = stanza.iter().position(|line| line.starts_with(&prefix))?;
+    let mut end = start + 1;
+    while end < stanza.len() && stanza[end].starts_with([' ', '\t']) {
+        end += 1;
+    }
+    let value = std::iter::once(&stanza[start][prefix.len()..])
+        .chain(stanza[start + 1..end].iter().map(|line| line.as_str()))
+        .map(|line| line.trim())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    Some((start, end, value))
+}
+
+/// Binary packages of debian/control with their Architecture field.
+pub fn get_binary_architectures(control: &str) -> Vec<(String, String)> {
+    control
+        .split("\n\n")
+        .map(|stanza| stanza.lines().map(|line| line.to_string()).collect::<Vec<String>>())
+        .filter_map(|stanza| {
+            let (_, _, package) = get_field(&stanza, "Package")?;
+            let architecture = get_field(&stanza, "Architecture").map_or("any".to_string(), |field| field.2);
+            Some((package, architecture))
+        })
+        .collect()
+}
+
+/// No binary package is built per architecture, e.g. scripts or data only packages.
+pub fn is_arch_all_only(control: &str) -> bool {
+    let architectures = get_binary_architectures(control);
+    !architectures.is_empty() && architectures.iter().all(|(_, architecture)| architecture == "all")
+}
+
+pub fn merge_control_overrides(
+    control: &str,
+    package: &str,
+    control_overrides: &ControlOverrides,
+) -> Result<String> {
+    let mut stanzas: Vec<Vec<String>> = control
+        .split("\n\n")
+        .map(|stanza| stanza.lines().map(|line| line.to_string()).collect())
+        .filter(|stanza: &Vec<String>| !stanza.is_empty())
+        .collect();
+    let package_line = format!("Package: {}", package);
+    let stanza = stanzas
+        .iter_mut()
+        .find(|stanza| stanza.iter().any(|line| line.trim_end() == package_line))
+        .ok_or_else(|| {
+            eyre!(
+                "control_overrides: package {} not found in debian/control",
+                package
+            )
+        })?;
+    for (field, relations) in control_overrides.get_relations() {
+        if relations.is_empty() {
+            continue;
+        }
+        match get_field(stanza, field) {
+            Some((start, end, value)) => {
+                let merged = merge_relations(&value, relations);
+                stanza.splice(start..end, [format!("{}: {}", field, merged)]);
+            }
+            None => stanza.push(format!("{}: {}", field, merge_relations("", relations))),
+        }
+    }
+    let mut merged = stanzas
+        .iter()
+        .map(|stanza| stanza.join("\n"))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    merged.push('\n');
+    Ok(merged)
+}
+
+fn set_field(stanza: &mut Vec<String>, field: &str, value: &str) {
+    let line = format!("{}: {}", field, value);
+    match get_field(stanza, field) {
+        Some((start, end, _)) => {
+            stanza.splice(start..end, [line]);
+        }
+        None => stanza.push(line),
+    }
+}
+
+/// Sets the Multi-Arch field of the listed binary packages, Multi-Arch: same is refused for
+/// Architecture: all packages, there is only one of them.
+pub fn merge_multi_arch(control: &str, multi_arch: &MultiArchConfig) -> Result<String> {
+    let mut stanzas: Vec<Vec<String>> = control
+        .split("\n\n")
+        .map(|stanza| stanza.lines().map(|line| line.to_string()).collect())
+        .filter(|stanza: &Vec<String>| !stanza.is_empty())
+        .collect();
+    for (value, packages) in multi_arch.get_fields() {
+        for package in packages {
+            let stanza = stanzas
+                .iter_mut()
+                .find(|stanza| get_field(stanza, "Package").is_some_and(|field| &field.2 == package))
+                .ok_or_else(|| eyre!("multi_arch: package {} not found in debian/control", package))?;
+            let architecture = get_field(stanza, "Architecture").map_or("any".to_string(), |field| field.2);
+            if value == "same" && architecture == "all" {
+                return Err(eyre!("multi_arch: {} is Architecture: all, it cannot be Multi-Arch: same", package));
+            }
+            set_field(stanza, "Multi-Arch", value);
+        }
+    }
+    let mut merged = stanzas
+        .iter()
+        .map(|stanza| stanza.join("\n"))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    merged.push('\n');
+    Ok(merged)
+}
+
+/// Added to every binary stanza, dpkg-gencontrol copies it as Pkg-Builder-Recipe into the debs.
+pub const RECIPE_REVISION_FIELD: &str = "XB-Pkg-Builder-Recipe";
+
+pub fn merge_recipe_revision(control: &str, revision: &str) -> String {
+    let mut stanzas: Vec<Vec<String>> = control
+        .split("\n\n")
+        .map(|stanza| stanza.lines().map(|line| line.to_string()).collect())
+        .filter(|stanza: &Vec<String>| !stanza.is_empty())
+        .collect();
+    for stanza in stanzas.iter_mut().filter(|stanza| get_field(stanza, "Package").is_some()) {
+        set_field(stanza, RECIPE_REVISION_FIELD, revision);
+    }
+    let mut merged = stanzas
+        .iter()
+        .map(|stanza| stanza.join("\n"))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    merged.push('\n');
+    merged
+}
+
+pub fn apply_recipe_revision(build_files_dir: &Path, revision: &str) -> Result<()> {
+    let control_path = build_files_dir.join("debian").join("control");
+    let control = fs::read_to_string(&control_path)?;
+    fs::write(&control_path, merge_recipe_revision(&control, revision))?;
+    info!("Embedding recipe revision {}", revision);
+    Ok(())
+}
+
+/// Sets Standards-Version and the debhelper compat level in the source stanza. The compat level
+/// stays where the package declares it, in debian/compat with a matching debhelper build
+/// dependency, or as debhelper-compat (= N) build dependency, debhelper rejects both at once.
+/// Returns the control and whether debian/compat holds the compat level.
+pub fn merge_debian_policy(
+    control: &str,
+    standards_version: Option<&str>,
+    debhelper_compat: Option<u32>,
+    has_compat_file: bool,
+) -> Result<(String, bool)> {
+    let mut stanzas: Vec<Vec<String>> = control
+        .split("\n\n")
+        .map(|stanza| stanza.lines().map(|line| line.to_string()).collect())
+        .filter(|stanza: &Vec<String>| !stanza.is_empty())
+        .collect();
+    let source = stanzas
+        .first_mut()
+        .filter(|stanza| get_field(stanza, "Source").is_some())
+        .ok_or_else(|| eyre!("debian/control has no Source stanza"))?;
+    if let Some(standards_version) = standards_version {
+        set_field(source, "Standards-Version", standards_version);
+    }
+    let build_depends = get_field(source, "Build-Depends").map(|field| field.2).unwrap_or_default();
+    let uses_compat_file = has_compat_file
+        && !build_depends
+            .split(',')
+            .any(|relation| relation_key(relation) == "debhelper-compat");
+    if let Some(compat) = debhelper_compat {
+        let relation = match uses_compat_file {
+            true => format!("debhelper (>= {})", compat),
+            false => format!("debhelper-compat (= {})", compat),
+        };
+        set_field(source, "Build-Depends", &merge_relations(&build_depends, &[relation]));
+    }
+    let mut merged = stanzas
+        .iter()
+        .map(|stanza| stanza.join("\n"))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    merged.push('\n');
+    Ok((merged, uses_compat_file))
+}
+
+pub fn apply_debian_policy(
+    build_files_dir: &Path,
+    standards_version: Option<&str>,
+    debhelper_compat: Option<u32>,
+) -> Result<()> {
+    if standards_version.is_none() && debhelper_compat.is_none() {
+        return Ok(());
+    }
+    let debian_dir = build_files_dir.join("debian");
+    let control = fs::read_to_string(debian_dir.join("control"))?;
+    let compat_file = debian_dir.join("compat");
+    let (merged, uses_compat_file) =
+        merge_debian_policy(&control, standards_version, debhelper_compat, compat_file.exists())?;
+    if let Some(compat) = debhelper_compat {
+        if uses_compat_file {
+            fs::write(&compat_file, format!("{}\n", compat))?;
+        } else if compat_file.exists() {
+            fs::remove_file(&compat_file)?;
+        }
+        info!("Set debhelper compat level {}", compat);
+    }
+    fs::write(debian_dir.join("control"), merged)?;
+    Ok(())
+}
+
+pub fn apply_control_overrides(
+    build_files_dir: &Path,
+    package_name: &str,
+    control_overrides: &ControlOverrides,
+) -> Result<()> {
+    let debian_control_path = build_files_dir.join("debian").join("control");
+    let package = control_overrides.package.as_deref().unwrap_or(package_name);
+    info!(
+        "Applying control overrides to package {} in {}",
+        package,
+        debian_control_path.display()
+    );
+    let control = fs::read_to_string(&debian_control_path)?;
+    let merged = merge_control_overrides(&control, package, control_overrides)?;
+    fs::write(&debian_control_path, merged)?;
+    Ok(())
+}
+
+pub fn apply_multi_arch(build_files_dir: &Path, multi_arch: &MultiArchConfig) -> Result<()> {
+    let debian_control_path = build_files_dir.join("debian").join("control");
+    info!("Setting Multi-Arch fields in {}", debian_control_path.display());
+    let control = fs::read_to_string(&debian_control_path)?;
+    fs::write(&debian_control_path, merge_multi_arch(&control, multi_arch)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROL: &str = "Source: hello-world
+Priority: optional
+
+Package: hello-world
+Architecture: any
+Depends: ${shlibs:Depends}, ${misc:Depends},
+ libssl3 (>= 3.0.0)
+Description: Example Package
+";
+
+    #[test]
+    fn test_merge_control_overrides() {
+        let control_overrides = ControlOverrides {
+            package: None,
+            depends: vec!["libssl3 (>= 3.0.11)".to_string(), "adduser".to_string()],
+            conflicts: vec![],
+            provides: vec!["hello-world-api".to_string()],
+        };
+        let merged = merge_control_overrides(CONTROL, "hello-world", &control_overrides).unwrap();
+        assert_eq!(
+            merged,
+            "Source: hello-world
+Priority: optional
+
+Package: hello-world
+Architecture: any
+Depends: ${shlibs:Depends}, ${misc:Depends}, libssl3 (>= 3.0.11), adduser
+Description: Example Package
+Provides: hello-world-api
+"
+        );
+    }
+
+    #[test]
+    fn test_get_binary_architectures() {
+        assert_eq!(
+            get_binary_architectures(CONTROL),
+            vec![("hello-world".to_string(), "any".to_string())]
+        );
+        assert!(!is_arch_all_only(CONTROL));
+
+        let control = "Source: hello-world\n\nPackage: hello-world\nArchitecture: all\n\n\
+                       Package: hello-world-doc\nArchitecture: all\n";
+        assert!(is_arch_all_only(control));
+        assert!(!is_arch_all_only("Source: hello-world\n"));
+    }
+
+    #[test]
+    fn test_merge_debian_policy() {
+        let control = "Source: hello-world\nPriority: optional\nStandards-Version: 4.5.1\n\
+                       Build-Depends: debhelper (>= 12), cargo\n\nPackage: hello-world\nArchitecture: any\n";
+        assert_eq!(
+            merge_debian_policy(control, Some("4.6.2"), Some(13), true).unwrap(),
+            (
+                "Source: hello-world\nPriority: optional\nStandards-Version: 4.6.2\n\
+                 Build-Depends: debhelper (>= 13), cargo\n\nPackage: hello-world\nArchitecture: any\n"
+                    .to_string(),
+                true
+            )
+        );
+        assert_eq!(
+            merge_debian_policy(control, None, Some(13), false).unwrap(),
+            (
+                "Source: hello-world\nPriority: optional\nStandards-Version: 4.5.1\n\
+                 Build-Depends: debhelper (>= 12), cargo, debhelper-compat (= 13)\n\nPackage: hello-world\nArchitecture: any\n"
+                    .to_string(),
+                false
+            )
+        );
+        // an existing debhelper-compat relation wins over debian/compat
+        let control = "Source: hello-world\nBuild-Depends: debhelper-compat (= 12)\n";
+        assert_eq!(
+            merge_debian_policy(control, Some("4.6.2"), Some(13), true).unwrap(),
+            (
+                "Source: hello-world\nBuild-Depends: debhelper-compat (= 13)\nStandards-Version: 4.6.2\n".to_string(),
+                false
+            )
+        );
+        assert!(merge_debian_policy(CONTROL.split("\n\n").nth(1).unwrap(), None, Some(13), false).is_err());
+    }
+
+    #[test]
+    fn test_merge_multi_arch() {
+        let control = "Source: hello-world\n\nPackage: libhello1\nArchitecture: any\nMulti-Arch: foreign\n\n\
+                       Package: hello-world\nArchitecture: any\n\nPackage: hello-world-doc\nArchitecture: all\n";
+        let multi_arch = MultiArchConfig {
+            same: vec!["libhello1".to_string()],
+            foreign: vec!["hello-world".to_string()],
+            allowed: vec![],
+        };
+        assert_eq!(
+            merge_multi_arch(control, &multi_arch).unwrap(),
+            "Source: hello-world\n\nPackage: libhello1\nArchitecture: any\nMulti-Arch: same\n\n\
+             Package: hello-world\nArchitecture: any\nMulti-Arch: foreign\n\nPackage: hello-world-doc\nArchitecture: all\n"
+        );
+
+        let multi_arch = MultiArchConfig {
+            same: vec!["hello-world-doc".to_string()],
+            ..MultiArchConfig::default()
+        };
+        assert_eq!(
+            merge_multi_arch(control, &multi_arch).unwrap_err().to_string(),
+            "multi_arch: hello-world-doc is Architecture: all, it cannot be Multi-Arch: same"
+        );
+        let multi_arch = MultiArchConfig {
+            allowed: vec!["hello".to_string()],
+            ..MultiArchConfig::default()
+        };
+        assert!(merge_multi_arch(control, &multi_arch).is_err());
+    }
+
+    #[test]
+    fn test_merge_recipe_revision() {
+        let control = "Source: hello-world\nMaintainer: John Doe <johndoe@example.com>\n\nPackage: hello-world\nArchitecture: any\n\nPackage: hello-world-doc\nArchitecture: all\nXB-Pkg-Builder-Recipe: 0000\n";
+        assert_eq!(
+            merge_recipe_revision(control, "0123abcd-dirty"),
+            "Source: hello-world\nMaintainer: John Doe <johndoe@example.com>\n\nPackage: hello-world\nArchitecture: any\nXB-Pkg-Builder-Recipe: 0123abcd-dirty\n\nPackage: hello-world-doc\nArchitecture: all\nXB-Pkg-Builder-Recipe: 0123abcd-dirty\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_control_overrides_unknown_package() {
+        let control_overrides = ControlOverrides {
+            provides: vec!["hello-world-api".to_string()],
+            ..ControlOverrides::default()
+        };
+        let result = merge_control_overrides(CONTROL, "hello", &control_overrides);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "control_overrides: package hello not found in debian/control"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// Builds for an arch other than the host one. By default the build runs natively in a chroot
+// of that arch, qemu-user-static runs its binaries through binfmt_misc. With cross_build the
+// chroot has the host arch and sbuild installs the cross toolchain, much faster but only for
+// packages whose build system supports cross compiling.
+use crate::v1::build::testbed::get_qemu_arch;
+use crate::v1::pkg_config::{Arch, BuildEnv};
+use eyre::{eyre, Result};
+use std::path::Path;
+
+const BINFMT_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// sbuild arguments selecting the architectures of the build.
+pub fn get_arch_args(build_env: &BuildEnv) -> Vec<String> {
+    let arch = build_env.arch.get_build_arch();
+    let host = Arch::host().as_str();
+    if arch == host {
+        return vec![];
+    }
+    match build_env.cross_build {
+        Some(true) => vec![format!("--host={}", arch), format!("--build={}", host)],
+        _ => vec![format!("--arch={}", arch)],
+    }
+}
+
+/// The chroot of arch runs emulated, which needs a registered qemu-user-static interpreter.
+pub fn check_emulation(arch: &str) -> Result<()> {
+    if arch == Arch::host().as_str() {
+        return Ok(());
+    }
+    let interpreter = format!("qemu-{}", get_qemu_arch(arch)?);
+    if !Path::new(BINFMT_DIR).join(&interpreter).exists() {
+        return Err(eyre!(
+            "{} is not registered in {}, install qemu-user-static and binfmt-support to build for {}, or set cross_build = true",
+            interpreter,
+            BINFMT_DIR,
+            arch
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::{get_url_arch, ArchBinary, GoConfig, JavaConfig, LanguageEnv};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_arch_args_follow_the_build_mode() {
+        let foreign = match Arch::host() {
+            Arch::Arm64 => Arch::Amd64,
+            _ => Arch::Arm64,
+        };
+        let mut build_env = BuildEnv {
+            arch: foreign,
+            ..Default::default()
+        };
+        assert_eq!(get_arch_args(&build_env), [format!("--arch={}", foreign)]);
+        assert_eq!(build_env.get_chroot_arch(), foreign.as_str());
+
+        build_env.cross_build = Some(true);
+        assert_eq!(
+            get_arch_args(&build_env),
+            [format!("--host={}", foreign), format!("--build={}", Arch::host())]
+        );
+        assert_eq!(build_env.get_chroot_arch(), Arch::host().as_str());
+
+        build_env.arch = Arch::host();
+        assert!(get_arch_args(&build_env).is_empty());
+        assert!(check_emulation(Arch::host().as_str()).is_ok());
+
+        let mut language_env = LanguageEnv::Go(GoConfig {
+            go_version: "1.22.2".to_string(),
+            go_binary_url: "https://go.dev/dl/go1.22.2.linux-amd64.tar.gz".to_string(),
+            go_binary_checksum: "amd64".to_string(),
+            arch_binaries: BTreeMap::from([(
+                "arm64".to_string(),
+                ArchBinary {
+                    url: "https://go.dev/dl/go1.22.2.linux-arm64.tar.gz".to_string(),
+                    checksum: "arm64".to_string(),
+                    gpg_asc: None,
+                },
+            )]),
+            ..Default::default()
+        });
+        assert!(!language_env.is_missing_arch_binary("arm64"));
+        assert!(language_env.is_missing_arch_binary("riscv64"));
+        assert!(!language_env.is_missing_arch_binary("amd64"));
+        assert_eq!(get_url_arch("https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz"), Some("amd64"));
+        assert_eq!(get_url_arch("https://nodejs.org/dist/v20.12.2/node-v20.12.2-linux-arm64.tar.gz"), Some("arm64"));
+        assert_eq!(get_url_arch("https://example.com/toolchain.tar.gz"), None);
+        // an arm64 download configured outside of arch_binaries is not taken for amd64
+        let arm64_only = LanguageEnv::Go(GoConfig {
+            go_binary_url: "https://go.dev/dl/go1.22.2.linux-arm64.tar.gz".to_string(),
+            ..Default::default()
+        });
+        assert!(!arm64_only.is_missing_arch_binary("arm64"));
+        assert!(arm64_only.is_missing_arch_binary("amd64"));
+        // openjdk comes from the archive, only the oracle jdk is downloaded
+        let openjdk = LanguageEnv::Java(JavaConfig::default());
+        assert!(!openjdk.is_missing_arch_binary("riscv64"));
+        language_env.select_arch("arm64");
+        let LanguageEnv::Go(config) = language_env else { unreachable!() };
+        assert_eq!(config.go_binary_url, "https://go.dev/dl/go1.22.2.linux-arm64.tar.gz");
+        assert_eq!(config.go_binary_checksum, "arm64");
+    }
+}
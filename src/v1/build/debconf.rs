@@ -0,0 +1,245 @@
+// Checks that the maintainer scripts of the built debs install with DEBIAN_FRONTEND=noninteractive,
+// and loads the debconf preseed into the piuparts, autopkgtest and smoke test environments.
+use crate::v1::build::quality::{FindingLevel, ScriptFinding};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const MAINTAINER_SCRIPTS: &[&str] = &["config", "preinst", "postinst", "prerm", "postrm"];
+// only shown, never asked, so they need no answer
+const DISPLAY_TYPES: &[&str] = &["note", "text", "error", "title"];
+/// Where the preseed file is copied to in the autopkgtest testbed and the smoke test container.
+pub const PRESEED_PATH: &str = "/tmp/pkg-builder.preseed";
+
+fn finding(script: &str, level: FindingLevel, message: String) -> ScriptFinding {
+    ScriptFinding {
+        script: script.to_string(),
+        level,
+        message,
+    }
+}
+
+/// Question names of a debconf-set-selections file, e.g. "geth geth/network select mainnet".
+pub fn parse_preseed(content: &str) -> BTreeSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Questions of a templates file that are asked and have no default, as (name, type).
+pub fn get_questions_without_default(templates: &str) -> Vec<(String, String)> {
+    let mut questions = vec![];
+    for stanza in templates.split("\n\n") {
+        let mut name = None;
+        let mut question_type = None;
+        let mut has_default = false;
+        for line in stanza.lines() {
+            if let Some(value) = line.strip_prefix("Template:") {
+                name = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("Type:") {
+                question_type = Some(value.trim().to_string());
+            } else if line.starts_with("Default:") || line.starts_with("DefaultChoice:") {
+                has_default = true;
+            }
+        }
+        if let (Some(name), Some(question_type)) = (name, question_type) {
+            if !has_default && !DISPLAY_TYPES.contains(&question_type.as_str()) {
+                questions.push((name, question_type));
+            }
+        }
+    }
+    questions
+}
+
+/// Interaction that blocks or fails an install without a debconf frontend.
+pub fn check_maintainer_script(script: &str, content: &str) -> Vec<ScriptFinding> {
+    let tty = Regex::new(r"/dev/tty\b").unwrap();
+    let dialog = Regex::new(r"(^|[\s;&|(`])(whiptail|dialog)\s").unwrap();
+    let read = Regex::new(r"(^|[;&(]|\bthen|\bdo)\s*read\s").unwrap();
+    let db_input = Regex::new(r"\bdb_input\s").unwrap();
+    let set_e = content.lines().next().is_some_and(|line| line.contains(" -e"))
+        || content.lines().any(|line| line.trim() == "set -e");
+
+    let mut findings = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let number = number + 1;
+        if tty.is_match(line) {
+            findings.push(finding(
+                script,
+                FindingLevel::Error,
+                format!("line {}: reads from /dev/tty, blocks automated installs", number),
+            ));
+        } else if dialog.is_match(line) {
+            findings.push(finding(
+                script,
+                FindingLevel::Error,
+                format!("line {}: prompts with whiptail or dialog instead of debconf", number),
+            ));
+        } else if read.is_match(line) && !line.contains('<') {
+            findings.push(finding(
+                script,
+                FindingLevel::Error,
+                format!("line {}: reads from stdin, blocks automated installs", number),
+            ));
+        } else if set_e && db_input.is_match(line) && !line.contains("||") {
+            findings.push(finding(
+                script,
+                FindingLevel::Error,
+                format!(
+                    "line {}: db_input without `|| true` fails with set -e, it returns 30 when the question is not shown",
+                    number
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+/// Findings of the maintainer scripts and templates of one package, questions without a
+/// default are answered empty unless preseeded.
+pub fn check_control_dir(package: &str, control_dir: &Path, preseeded: &BTreeSet<String>) -> Result<Vec<ScriptFinding>> {
+    let mut findings = vec![];
+    for script in MAINTAINER_SCRIPTS {
+        let path = control_dir.join(script);
+        if path.is_file() {
+            let content = String::from_utf8_lossy(&fs::read(&path)?).to_string();
+            findings.append(&mut check_maintainer_script(&format!("{}.{}", package, script), &content));
+        }
+    }
+    let templates = control_dir.join("templates");
+    if templates.is_file() {
+        for (name, question_type) in get_questions_without_default(&fs::read_to_string(&templates)?) {
+            if !preseeded.contains(&name) {
+                findings.push(finding(
+                    &format!("{}.templates", package),
+                    FindingLevel::Warning,
+                    format!(
+                        "{} ({}) has no default and is not preseeded, noninteractive installs answer it empty",
+                        name, question_type
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(findings)
+}
+
+/// Extracts the control files of every deb and checks them.
+pub fn check_debs(debs: &[PathBuf], preseed_file: Option<&Path>) -> Result<Vec<ScriptFinding>> {
+    let preseeded = match preseed_file {
+        Some(preseed_file) => parse_preseed(&fs::read_to_string(preseed_file)?),
+        None => BTreeSet::new(),
+    };
+    let mut findings = vec![];
+    for deb in debs {
+        let control_dir = tempfile::tempdir()?;
+        let output = Command::new("dpkg-deb").arg("-e").arg(deb).arg(control_dir.path()).output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "Failed to extract the control files of {}: {}",
+                deb.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        // e.g. hello-world_1.0.0-1_amd64.deb
+        let file_name = deb.file_name().unwrap().to_string_lossy();
+        let package = file_name.split('_').next().unwrap_or_default();
+        findings.append(&mut check_control_dir(package, control_dir.path(), &preseeded)?);
+    }
+    Ok(findings)
+}
+
+/// piuparts custom script loading the preseed before the package is installed.
+pub fn create_piuparts_scripts_dir(preseed_file: &Path) -> Result<tempfile::TempDir> {
+    let preseed = fs::read_to_string(preseed_file)
+        .map_err(|err| eyre!("Could not read {}: {}", preseed_file.display(), err))?;
+    let scripts_dir = tempfile::tempdir()?;
+    let script = scripts_dir.path().join("pre_install_debconf_preseed");
+    fs::write(
+        &script,
+        format!("#!/bin/sh\nset -e\ndebconf-set-selections <<'PRESEED'\n{}\nPRESEED\n", preseed.trim_end()),
+    )?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    Ok(scripts_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_maintainer_script() {
+        let script = "#!/bin/sh\nset -e\n. /usr/share/debconf/confmodule\n\
+                      db_input high geth/network || true\n\
+                      db_input medium geth/datadir\n\
+                      # read the answer\n\
+                      echo -n 'Network? '; read network\n\
+                      while read line; do echo $line; done < /etc/geth/peers\n\
+                      whiptail --yesno 'Start geth?' 8 40\n\
+                      exec 3</dev/tty\n";
+        let messages: Vec<String> = check_maintainer_script("geth.config", script)
+            .into_iter()
+            .map(|finding| format!("{}: {}", finding.level, finding.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: line 5: db_input without `|| true` fails with set -e, it returns 30 when the question is not shown",
+                "error: line 7: reads from stdin, blocks automated installs",
+                "error: line 9: prompts with whiptail or dialog instead of debconf",
+                "error: line 10: reads from /dev/tty, blocks automated installs",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_control_dir_reports_questions_not_preseeded() {
+        let control_dir = tempdir().unwrap();
+        fs::write(
+            control_dir.path().join("templates"),
+            "Template: geth/network\nType: select\nChoices: mainnet, holesky\nDescription: Network\n\n\
+             Template: geth/datadir\nType: string\nDefault: /var/lib/geth\nDescription: Data dir\n\n\
+             Template: geth/jwt-secret\nType: password\nDescription: JWT secret\n\n\
+             Template: geth/restart\nType: note\nDescription: Restart geth\n",
+        )
+        .unwrap();
+        fs::write(control_dir.path().join("postinst"), "#!/bin/sh\nset -e\n").unwrap();
+
+        let preseeded = parse_preseed("# answers\ngeth geth/network select mainnet\n");
+        let findings = check_control_dir("geth", control_dir.path(), &preseeded).unwrap();
+        assert_eq!(
+            findings,
+            vec![finding(
+                "geth.templates",
+                FindingLevel::Warning,
+                "geth/jwt-secret (password) has no default and is not preseeded, noninteractive installs answer it empty"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_create_piuparts_scripts_dir() {
+        let dir = tempdir().unwrap();
+        let preseed_file = dir.path().join("geth.preseed");
+        fs::write(&preseed_file, "geth geth/network select mainnet\n").unwrap();
+        let scripts_dir = create_piuparts_scripts_dir(&preseed_file).unwrap();
+        assert_eq!(
+            fs::read_to_string(scripts_dir.path().join("pre_install_debconf_preseed")).unwrap(),
+            "#!/bin/sh\nset -e\ndebconf-set-selections <<'PRESEED'\ngeth geth/network select mainnet\nPRESEED\n"
+        );
+    }
+}
@@ -89,15 +89,15 @@ pub fn check_if_installed(debcrafter_version: &String) -> eyre::Result<()> {
 //     Ok(())
 // }
 
-pub fn create_debian_dir(specification_file: &str, target_dir: &str, debcrafter_version: &String) -> Result<(), Error> {
+pub fn create_debian_dir(specification_file: &Path, target_dir: &Path, debcrafter_version: &String) -> Result<(), Error> {
     let debcrafter_dir = tempdir().expect("Failed to create temporary directory");
 
-    let spec_file_path = fs::canonicalize(PathBuf::from(specification_file)).map_err(|_| {
-        Error::CommandFailed(format!("{} spec_file doesn't exist", specification_file).into())
+    let spec_file_path = fs::canonicalize(specification_file).map_err(|_| {
+        Error::CommandFailed(format!("{} spec_file doesn't exist", specification_file.display()).into())
     })?;
     if !spec_file_path.exists() {
         return Err(Error::CommandFailed(
-            format!("{} spec_file doesn't exist", specification_file).into(),
+            format!("{} spec_file doesn't exist", specification_file.display()).into(),
         ));
     }
     let spec_dir = spec_file_path.parent().unwrap();
@@ -114,7 +114,7 @@ pub fn create_debian_dir(specification_file: &str, target_dir: &str, debcrafter_
 
     if let Some(first_directory) = get_first_directory(debcrafter_dir.path()) {
         let tmp_debian_dir = first_directory.join("debian");
-        let dest_dir = Path::new(target_dir).join("debian");
+        let dest_dir = target_dir.join("debian");
         copy_dir_contents_recursive(&tmp_debian_dir, &dest_dir)
             .map_err(|err| Error::CommandFailed(err.into()))?;
     } else {
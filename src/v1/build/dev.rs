@@ -0,0 +1,115 @@
+// Incremental builds of `package --dev`: the artifacts dir is kept between builds and a
+// stamp records what the extracted source and the generated debian dir were made from.
+use crate::v1::build::dir_setup::{calculate_sha256, git_tarball_cache_key, open_for_hashing};
+use crate::v1::pkg_config::{PackageType, PkgConfig};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const DEV_STAMP_FILE_NAME: &str = ".dev-stamp.toml";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct DevStamp {
+    /// tarball_url and tarball_hash, or the key of the git tarball
+    pub source: Option<String>,
+    pub tarball_sha256: Option<String>,
+    /// sha256 of the spec file and the debcrafter version
+    pub spec: Option<String>,
+}
+
+impl DevStamp {
+    /// A missing or unreadable stamp starts empty, everything is prepared again.
+    pub fn read(artifacts_dir: &Path) -> Self {
+        fs::read_to_string(artifacts_dir.join(DEV_STAMP_FILE_NAME))
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, artifacts_dir: &Path) -> Result<()> {
+        let content =
+            toml::to_string(self).map_err(|err| eyre!("Failed to serialize dev stamp: {}", err))?;
+        fs::write(artifacts_dir.join(DEV_STAMP_FILE_NAME), content)?;
+        Ok(())
+    }
+
+    /// The tarball is unchanged since the source was extracted.
+    pub fn is_source_current(&self, config: &PkgConfig, tarball_path: &Path) -> bool {
+        if self.source != Some(get_source_key(config)) || !tarball_path.exists() {
+            return false;
+        }
+        let tarball_sha256 = open_for_hashing(tarball_path).and_then(calculate_sha256).ok();
+        tarball_sha256.is_some() && self.tarball_sha256 == tarball_sha256
+    }
+
+    pub fn set_source(&mut self, config: &PkgConfig, tarball_path: &Path) -> Result<()> {
+        self.source = Some(get_source_key(config));
+        self.tarball_sha256 = Some(calculate_sha256(open_for_hashing(tarball_path)?)?);
+        // debcrafter writes into the newly extracted source
+        self.spec = None;
+        Ok(())
+    }
+}
+
+fn get_source_key(config: &PkgConfig) -> String {
+    match &config.package_type {
+        PackageType::Default(package_type) => format!(
+            "{} {}",
+            package_type.get_source_url(&config.package_fields.version_number),
+            package_type.tarball_hash.clone().unwrap_or_default()
+        ),
+        PackageType::Git(package_type) => git_tarball_cache_key(package_type),
+        PackageType::Virtual => "virtual".to_string(),
+    }
+}
+
+/// Changes when the spec file or the debcrafter generating the debian dir from it changes.
+pub fn get_spec_key(config: &PkgConfig) -> Result<String> {
+    let spec_file = Path::new(&config.package_fields.spec_file);
+    let spec = fs::read(spec_file)
+        .map_err(|err| eyre!("Could not read {}: {}", spec_file.display(), err))?;
+    Ok(format!(
+        "{} {} {}",
+        calculate_sha256(spec.as_slice())?,
+        config.build_env.debcrafter_version,
+        config.package_fields.get_debian_version()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::DefaultPackageTypeConfig;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dev_stamp_tracks_tarball_and_spec() {
+        let dir = tempdir().unwrap();
+        let tarball_path = dir.path().join("hello-world_1.0.0.orig.tar.gz");
+        fs::write(&tarball_path, "tarball").unwrap();
+        let spec_file = dir.path().join("hello-world.sss");
+        fs::write(&spec_file, "name = \"hello-world\"\n").unwrap();
+        let mut config = PkgConfig::default();
+        config.package_fields.spec_file = spec_file.to_str().unwrap().to_string();
+        config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            tarball_url: "https://example.com/hello-world-1.0.0.tar.gz".to_string(),
+            ..DefaultPackageTypeConfig::default()
+        });
+
+        let mut stamp = DevStamp::read(dir.path());
+        assert!(!stamp.is_source_current(&config, &tarball_path));
+        stamp.set_source(&config, &tarball_path).unwrap();
+        stamp.spec = Some(get_spec_key(&config).unwrap());
+        stamp.write(dir.path()).unwrap();
+
+        let stamp = DevStamp::read(dir.path());
+        assert!(stamp.is_source_current(&config, &tarball_path));
+        assert_eq!(stamp.spec, Some(get_spec_key(&config).unwrap()));
+
+        fs::write(&spec_file, "name = \"hello-world\"\nsection = \"net\"\n").unwrap();
+        assert_ne!(stamp.spec, Some(get_spec_key(&config).unwrap()));
+        fs::write(&tarball_path, "changed").unwrap();
+        assert!(!stamp.is_source_current(&config, &tarball_path));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::{env, fs, io};
@@ -5,35 +6,243 @@ use std::{env, fs, io};
 use eyre::{eyre, Result};
 
 use crate::v1::build::debcrafter_helper;
-use crate::v1::pkg_config::SubModule;
+use crate::v1::build::git_clone::clone_source;
+use crate::v1::build::trust::verify_git_signature;
+use crate::v1::bump::prepend_changelog_entry;
+use crate::v1::pkg_config::{
+    GitPackageTypeConfig, PackageFields, SubModule, TarballAuth,
+    DEFAULT_STANDARDS_VERSION,
+};
+use crate::v1::secrets::get_secret;
 use dirs::home_dir;
 use filetime::FileTime;
-use log::info;
+use log::{info, trace, warn};
 use sha2::{Digest, Sha256, Sha512};
 use std::io::{Read, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 
-pub fn create_package_dir(build_artifacts_dir: &String) -> Result<()> {
+pub fn create_package_dir(build_artifacts_dir: &Path) -> Result<()> {
     if fs::metadata(build_artifacts_dir).is_ok() {
-        info!("Remove previous package folder {}", &build_artifacts_dir);
-        fs::remove_dir_all(build_artifacts_dir)?;
+        info!("Remove previous package folder {}", build_artifacts_dir.display());
+        remove_workdir(build_artifacts_dir)?;
     }
-    info!("Creating package folder {}", &build_artifacts_dir);
+    info!("Creating package folder {}", build_artifacts_dir.display());
     fs::create_dir_all(build_artifacts_dir)?;
     Ok(())
 }
 
-pub fn download_source(tarball_path: &str, tarball_url: &str, config_root: &str) -> Result<()> {
-    info!("Downloading source {}", tarball_path);
+/// Makes every directory under `path` rwx and every file rw for the owner, so the
+/// tree can be removed and rebuilt without sudo. Symlinks are left alone.
+/// Returns the entries whose permissions could not be changed, these are owned
+/// by another user, e.g. created inside the unshare chroot.
+pub fn normalize_permissions(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut foreign = vec![];
+    normalize_permissions_recursive(path, &mut foreign)?;
+    Ok(foreign)
+}
+
+fn normalize_permissions_recursive(path: &Path, foreign: &mut Vec<PathBuf>) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+    let required = if metadata.is_dir() { 0o700 } else { 0o600 };
+    let mode = metadata.permissions().mode();
+    if mode & required != required {
+        let permissions = fs::Permissions::from_mode(mode | required);
+        match fs::set_permissions(path, permissions) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                foreign.push(path.to_path_buf());
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            normalize_permissions_recursive(&entry?.path(), foreign)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes a work directory, which may contain files left behind by the chroot
+/// with odd permissions or subordinate uids. Permissions are normalized first,
+/// if that is not enough the removal is retried inside a user namespace where
+/// the subordinate uids are mapped, so no sudo is needed.
+pub fn remove_workdir(path: &Path) -> Result<()> {
+    let foreign = normalize_permissions(path)?;
+    if !foreign.is_empty() {
+        info!(
+            "{} entries under {} are owned by another user.",
+            foreign.len(),
+            path.display()
+        );
+    }
+    match fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            info!(
+                "Could not remove {} directly, retrying with unshare.",
+                path.display()
+            );
+            let status = Command::new("unshare")
+                .arg("--map-root-user")
+                .arg("--map-auto")
+                .arg("rm")
+                .arg("-rf")
+                .arg(path)
+                .status()?;
+            if !status.success() || path.exists() {
+                return Err(eyre!(
+                    "Could not remove {}, some files are owned by a user not mapped to you. Remove it manually with sudo rm -rf {}",
+                    path.display(),
+                    path.display()
+                ));
+            }
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn get_current_id(flag: &str) -> Result<u32> {
+    let output = Command::new("id").arg(flag).output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| eyre!("Could not get the current id with id {}", flag))
+}
+
+/// Subordinate id ranges of a user in /etc/subuid or /etc/subgid, entries name the user or its id.
+pub fn parse_subid_ranges(content: &str, user: &str, id: u32) -> Vec<(u32, u32)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let owner = fields.next()?;
+            let first = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            (owner == user || owner == id.to_string()).then_some((first, count))
+        })
+        .collect()
+}
+
+/// The id range the unshare chroot is mapped to, the first one delegated to the invoking user,
+/// as sbuild and unshare --map-auto pick it.
+pub fn get_unshare_id_map() -> Result<(u32, u32)> {
+    let user = whoami::username();
+    let mut delegated = vec![];
+    for (file, flag) in [("/etc/subuid", "-u"), ("/etc/subgid", "-g")] {
+        let content = fs::read_to_string(file).unwrap_or_default();
+        let ranges = parse_subid_ranges(&content, &user, get_current_id(flag)?);
+        if ranges.is_empty() {
+            return Err(eyre!(
+                "No subordinate ids of {} in {}, the unshare chroot needs them. Add them with sudo usermod --add-subuids 100000-165535 --add-subgids 100000-165535 {}",
+                user,
+                file,
+                user
+            ));
+        }
+        delegated.push(ranges[0]);
+    }
+    Ok(delegated[0])
+}
+
+/// Fails if the workdir has entries that are neither owned by uid nor by the chroot mapped
+/// to id_map, e.g. root owned files of a run with sudo, they could not be removed or rebuilt.
+pub fn check_workdir_ownership(path: &Path, uid: u32, id_map: (u32, u32)) -> Result<()> {
+    let (first, count) = id_map;
+    let mut foreign = vec![];
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(entry) = pending.pop() {
+        let Ok(metadata) = fs::symlink_metadata(&entry) else {
+            continue;
+        };
+        let owner = metadata.uid();
+        if owner != uid && !(owner >= first && (owner as u64) < first as u64 + count as u64) {
+            foreign.push(format!("{} (uid {})", entry.display(), owner));
+            continue;
+        }
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&entry) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+    if foreign.is_empty() {
+        return Ok(());
+    }
+    foreign.sort();
+    Err(eyre!(
+        "{} entries under {} are owned by another user, e.g. a run with sudo, remove them with sudo rm -rf {}: {}",
+        foreign.len(),
+        path.display(),
+        path.display(),
+        foreign.iter().take(5).cloned().collect::<Vec<String>>().join(", ")
+    ))
+}
+
+/// Checks the workdir of the package before the build, not needed when running as root.
+pub fn validate_workdir_ownership(path: &Path) -> Result<()> {
+    let uid = get_current_id("-u")?;
+    if uid == 0 || !path.exists() {
+        return Ok(());
+    }
+    check_workdir_ownership(path, uid, get_unshare_id_map()?)
+}
+
+/// Creates path at most once across concurrent runs. The creator holds a lock file next to it
+/// and writes to a temporary path that is renamed into place, so nobody sees a half written
+/// file, the others wait for the lock and reuse the result.
+pub fn create_exclusively<F>(path: &Path, create: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let parent = path.parent().ok_or_else(|| eyre!("{} has no parent dir", path.display()))?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("{} has no file name", path.display()))?
+        .to_string_lossy()
+        .to_string();
+    fs::create_dir_all(parent)?;
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(parent.join(format!(".{}.lock", file_name)))?;
+    if lock.try_lock().is_err() {
+        info!("Waiting for another run to finish creating {}", path.display());
+        lock.lock()?;
+    }
+    if path.exists() {
+        info!("{} exists, reusing it", path.display());
+        return Ok(());
+    }
+    // same file system as path, so the rename is atomic
+    let tmp_dir = tempfile::Builder::new().prefix(".tmp-").tempdir_in(parent)?;
+    let tmp_path = tmp_dir.path().join(&file_name);
+    create(&tmp_path)?;
+    if !tmp_path.exists() {
+        return Err(eyre!("Failed to create {}", path.display()));
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn download_source(tarball_path: &Path, tarball_url: &str, config_root: &Path) -> Result<()> {
+    info!("Downloading source {}", tarball_path.display());
     let is_web = tarball_url.starts_with("http");
     let tarball_url = get_tarball_url(tarball_url, config_root);
     if is_web {
         info!(
             "Downloading tar: {} to location: {}",
-            tarball_url, tarball_path
+            tarball_url,
+            tarball_path.display()
         );
         let status = Command::new("wget")
             .arg("-q")
@@ -45,20 +254,84 @@ pub fn download_source(tarball_path: &str, tarball_url: &str, config_root: &str)
             return Err(eyre!("Download failed".to_string()));
         }
     } else {
-        info!("Copying tar: {} to location: {}", tarball_url, tarball_path);
+        info!(
+            "Copying tar: {} to location: {}",
+            tarball_url,
+            tarball_path.display()
+        );
         fs::copy(tarball_url, tarball_path)?;
     }
     Ok(())
 }
 
-pub fn update_submodules(git_submodules: &Vec<SubModule>, current_dir: &str) -> Result<()> {
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// wgetrc and url of the download, the credential goes into one of them.
+pub fn get_wget_request(tarball_url: &str, auth: &TarballAuth, secret: Option<&str>) -> (String, String) {
+    let mut wgetrc = String::new();
+    for (name, value) in auth.headers.iter() {
+        wgetrc.push_str(&format!("header = {}: {}\n", name, value));
+    }
+    let mut url = tarball_url.to_string();
+    match (secret, &auth.query_param) {
+        (Some(secret), Some(param)) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            url.push_str(&format!("{}{}={}", separator, param, percent_encode(secret)));
+        }
+        (Some(secret), None) => {
+            let header = auth.header.as_deref().unwrap_or("Authorization");
+            wgetrc.push_str(&format!("header = {}: {}\n", header, secret));
+        }
+        (None, _) => {}
+    }
+    (wgetrc, url)
+}
+
+/// Downloads the tarball with the headers and credential of auth, both are passed to wget in
+/// files of a private tempdir, so they show up neither in the process list nor in the logs.
+pub fn download_source_with_auth(tarball_path: &Path, tarball_url: &str, auth: &TarballAuth) -> Result<()> {
+    let secret = auth.credential.as_deref().map(get_secret).transpose()?;
+    let (wgetrc, url) = get_wget_request(tarball_url, auth, secret.as_deref());
+    let request_dir = tempfile::tempdir()?;
+    let wgetrc_path = request_dir.path().join("wgetrc");
+    let url_path = request_dir.path().join("url");
+    fs::write(&wgetrc_path, wgetrc)?;
+    fs::write(&url_path, url)?;
+    info!(
+        "Downloading tar: {} to location: {}",
+        tarball_url,
+        tarball_path.display()
+    );
+    let status = Command::new("wget")
+        .arg(format!("--config={}", wgetrc_path.display()))
+        .arg("-q")
+        .arg("-O")
+        .arg(tarball_path)
+        .arg("--input-file")
+        .arg(&url_path)
+        .status()?;
+    if !status.success() {
+        return Err(eyre!("Download of {} failed", tarball_url));
+    }
+    Ok(())
+}
+
+pub fn update_submodules(git_submodules: &[SubModule], current_dir: &Path) -> Result<()> {
     // DO not use git2, it has very little git supported functionality
     // Initialize all submodules if they are not already initialized
     // Update submodules to specific commits
-    for submodule in git_submodules.clone() {
+    for submodule in git_submodules {
         let output = Command::new("git")
-            .current_dir(Path::new(current_dir).join(submodule.path.clone()))
-            .args(&["checkout", &submodule.commit.clone()])
+            .current_dir(current_dir.join(&submodule.path))
+            .args(["checkout", &submodule.commit.clone()])
             .output()
             .map_err(|err| eyre!(format!("Failed to checkout submodule {}", err)))?;
         if !output.status.success() {
@@ -77,8 +350,8 @@ pub fn update_submodules(git_submodules: &Vec<SubModule>, current_dir: &str) ->
 pub fn clone_and_checkout_tag(
     git_url: &str,
     tag_version: &str,
-    path: &str,
-    git_submodules: &Vec<SubModule>,
+    path: &Path,
+    git_submodules: &[SubModule],
 ) -> Result<()> {
     match Command::new("which").arg("git-lfs").output() {
         Ok(_) => Ok(()),
@@ -86,15 +359,15 @@ pub fn clone_and_checkout_tag(
     }?;
 
     let output = Command::new("git")
-        .args(&[
+        .args([
             "clone",
             "--depth",
             "1",
             "--branch",
             tag_version,
             git_url,
-            path,
         ])
+        .arg(path)
         .output()
         .expect("Failed to execute git clone command");
     if !output.status.success() {
@@ -108,7 +381,7 @@ pub fn clone_and_checkout_tag(
     // Initialize submodules
     let output = Command::new("git")
         .current_dir(path)
-        .args(&["submodule", "update", "--init", "--recursive"])
+        .args(["submodule", "update", "--init", "--recursive"])
         .output()?;
 
     if !output.status.success() {
@@ -151,66 +424,106 @@ fn set_creation_time<P: AsRef<Path>>(dir_path: P, timestamp: FileTime) -> io::Re
     Ok(())
 }
 
+// Back in the path for reproducibility: January 1, 2022
+const GIT_TARBALL_MTIME: i64 = 1640995200;
+const GIT_TARBALL_ARGS: [&str; 6] = [
+    "--sort=name",
+    "--owner=0",
+    "--group=0",
+    "--numeric-owner",
+    // --mtime does not work, the mtime is set on the files instead
+    "--pax-option=exthdr.name=%d/PaxHeaders/%f,delete=atime,delete=ctime",
+    "-czf",
+];
+
+/// Key of a generated git tarball, changes when the tag, a submodule pin, the allowed
+/// signers or the tarball normalization changes.
+pub fn git_tarball_cache_key(config: &GitPackageTypeConfig) -> String {
+    let mut key = format!("url={}\ntag={}\n", config.git_url, config.git_tag);
+    for submodule in config.submodules.iter() {
+        key.push_str(&format!("submodule={}@{}\n", submodule.path, submodule.commit));
+    }
+    // a tarball cached without verification is not reused once signatures are required
+    if let Some(signers) = config.get_required_signers() {
+        key.push_str(&format!("signers={}\n", signers.get_fingerprints().join(",")));
+    }
+    key.push_str(&format!("mtime={}\ntar={}\n", GIT_TARBALL_MTIME, GIT_TARBALL_ARGS.join(" ")));
+    calculate_sha256(key.as_bytes()).unwrap()
+}
+
+/// Copies the cached tarball to tarball_path, or generates it from git and caches it.
+pub fn download_git_cached(
+    build_artifacts_dir: &Path,
+    tarball_path: &Path,
+    orig_cache_dir: &Path,
+    package_name: &str,
+    config: &GitPackageTypeConfig,
+) -> Result<()> {
+    let key = git_tarball_cache_key(config);
+    let cached_tarball = orig_cache_dir.join(format!("{}-{}.tar.gz", package_name, key));
+    if cached_tarball.exists() {
+        info!("Reusing orig tarball {}", cached_tarball.display());
+        fs::copy(&cached_tarball, tarball_path)?;
+        return Ok(());
+    }
+    download_git(build_artifacts_dir, tarball_path, package_name, config)?;
+    fs::create_dir_all(orig_cache_dir)?;
+    fs::copy(tarball_path, &cached_tarball)?;
+    Ok(())
+}
+
 pub fn download_git(
-    build_artifacts_dir: &str,
-    tarball_path: &str,
+    build_artifacts_dir: &Path,
+    tarball_path: &Path,
     package_name: &str,
-    git_url: &str,
-    tag_version: &str,
-    git_submodules: &Vec<SubModule>,
+    config: &GitPackageTypeConfig,
 ) -> Result<()> {
-    let path = Path::new(build_artifacts_dir).join(package_name);
+    let git_url = &config.git_url;
+    let tag_version = &config.git_tag;
+    let git_submodules = &config.submodules;
+    let path = build_artifacts_dir.join(package_name);
     if path.exists() {
-        fs::remove_dir_all(path.clone())?;
-    }
-    fs::create_dir_all(&path.clone())?;
-    //let path = Path::new("/tmp/nimbus");
-    clone_and_checkout_tag(
-        git_url,
-        tag_version,
-        path.clone().to_str().unwrap(),
-        &git_submodules,
-    )?;
+        fs::remove_dir_all(&path)?;
+    }
+    fs::create_dir_all(&path)?;
+    clone_source(config.git_client.unwrap_or_default(), git_url, tag_version, &path, git_submodules, || {
+        clone_and_checkout_tag(git_url, tag_version, &path, git_submodules)
+    })?;
+    if let Some(signers) = config.get_required_signers() {
+        verify_git_signature(&path, tag_version, signers)?;
+    }
     // remove .git directory, no need to package it
     fs::remove_dir_all(path.join(".git"))?;
 
-    // // Back in the path for reproducibility: January 1, 2022
-    let timestamp = FileTime::from_unix_time(1640995200, 0);
+    let timestamp = FileTime::from_unix_time(GIT_TARBALL_MTIME, 0);
     set_creation_time(path.clone(), timestamp)?;
 
     info!("Creating tar from git repo from {}", path.display());
     let output = Command::new("tar")
-        .args(&[
-            "--sort=name",
-            "--owner=0",
-            "--group=0",
-            "--numeric-owner",
-            // does not work
-            // "--mtime='2019-01-01 00:00'",
-            "--pax-option=exthdr.name=%d/PaxHeaders/%f,delete=atime,delete=ctime",
-            "-czf",
-            tarball_path,
-            package_name,
-        ])
+        .args(GIT_TARBALL_ARGS)
+        .arg(tarball_path)
+        .arg(package_name)
         .current_dir(build_artifacts_dir)
         .output()?;
     if !output.status.success() {
         return Err(eyre!(format!(
             "Failed to create tarball: {}",
             String::from_utf8_lossy(&output.stderr)
-        ))
-        .into());
+        )));
     }
 
     Ok(())
 }
 
-pub fn create_empty_tar(build_artifacts_dir: &str, tarball_path: &str) -> Result<()> {
+pub fn create_empty_tar(build_artifacts_dir: &Path, tarball_path: &Path) -> Result<()> {
     info!("Creating empty .tar.gz for virtual package");
     let output = Command::new("tar")
-        .args(["czvf", tarball_path, "--files-from", "/dev/null"])
+        .arg("czvf")
+        .arg(tarball_path)
+        .args(["--files-from", "/dev/null"])
         .current_dir(build_artifacts_dir)
         .output()?;
+    trace!("{}", String::from_utf8_lossy(&output.stdout));
     if !output.status.success() {
         return Err(eyre!("Virtual package .tar.gz creation failed".to_string(),));
     }
@@ -242,20 +555,85 @@ pub fn calculate_sha256<R: Read>(mut reader: R) -> Result<String> {
     Ok(hex_digest)
 }
 
-pub fn verify_tarball_checksum(tarball_path: &str, expected_checksum: &str) -> Result<bool> {
-    let mut file = fs::File::open(tarball_path).map_err(|_| eyre!("Could not open tarball."))?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)
-        .map_err(|_| eyre!("Could not read tarball."))?;
+// files above this size log hashing progress
+const LARGE_FILE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Reader that logs progress in 10% steps while large files are streamed through a hasher.
+pub struct ProgressReader<R: Read> {
+    inner: R,
+    name: String,
+    total_size: u64,
+    bytes_read: u64,
+    report_step: u64,
+    next_report: u64,
+    reports: usize,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, name: &str, total_size: u64) -> Self {
+        let report_step = if total_size >= LARGE_FILE_SIZE {
+            total_size / 10
+        } else {
+            0
+        };
+        Self::with_report_step(inner, name, total_size, report_step)
+    }
+
+    fn with_report_step(inner: R, name: &str, total_size: u64, report_step: u64) -> Self {
+        ProgressReader {
+            inner,
+            name: name.to_string(),
+            total_size,
+            bytes_read: 0,
+            report_step,
+            next_report: report_step,
+            reports: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read += read as u64;
+        if self.report_step > 0 && self.bytes_read >= self.next_report {
+            info!(
+                "Hashing {}: {}% ({} of {} bytes)",
+                self.name,
+                self.bytes_read * 100 / self.total_size.max(1),
+                self.bytes_read,
+                self.total_size
+            );
+            self.reports += 1;
+            self.next_report = self.bytes_read + self.report_step;
+        }
+        Ok(read)
+    }
+}
+
+/// Opens a file for hashing without reading it into memory.
+pub fn open_for_hashing(path: &Path) -> Result<ProgressReader<BufReader<fs::File>>> {
+    let file = fs::File::open(path)
+        .map_err(|_| eyre!("Could not open file {}.", path.display()))?;
+    let total_size = file.metadata()?.len();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(ProgressReader::new(BufReader::new(file), &name, total_size))
+}
 
-    let actual_sha512 = calculate_sha512(&*buffer.clone()).unwrap_or_default();
+pub fn verify_tarball_checksum(tarball_path: &Path, expected_checksum: &str) -> Result<bool> {
+    let reader = open_for_hashing(tarball_path).map_err(|_| eyre!("Could not open tarball."))?;
+    let actual_sha512 = calculate_sha512(reader).unwrap_or_default();
     info!("sha512 hash {}", &actual_sha512);
 
     if actual_sha512 == expected_checksum {
         return Ok(true);
     }
 
-    let actual_sha256 = calculate_sha256(&*buffer).unwrap_or_default();
+    let reader = open_for_hashing(tarball_path).map_err(|_| eyre!("Could not open tarball."))?;
+    let actual_sha256 = calculate_sha256(reader).unwrap_or_default();
     info!("sha256 hash {}", &actual_sha256);
 
     if actual_sha256 == expected_checksum {
@@ -264,7 +642,7 @@ pub fn verify_tarball_checksum(tarball_path: &str, expected_checksum: &str) -> R
     Err(eyre!("Hashes do not match."))
 }
 
-pub fn verify_hash(tarball_path: &str, expected_checksum: Option<String>) -> Result<()> {
+pub fn verify_hash(tarball_path: &Path, expected_checksum: Option<String>) -> Result<()> {
     match expected_checksum {
         Some(tarball_hash) => match verify_tarball_checksum(tarball_path, &tarball_hash) {
             Ok(true) => Ok(()),
@@ -275,19 +653,22 @@ pub fn verify_hash(tarball_path: &str, expected_checksum: Option<String>) -> Res
     }
 }
 
-pub fn extract_source(tarball_path: &str, build_files_dir: &str) -> Result<()> {
-    info!("Extracting source {}", &build_files_dir);
+pub fn extract_source(tarball_path: &Path, build_files_dir: &Path) -> Result<()> {
+    info!("Extracting source {}", build_files_dir.display());
     fs::create_dir_all(build_files_dir)?;
 
-    let mut args = vec!["zxvf", &tarball_path, "-C", &build_files_dir];
-    let numbers_to_strip = components_to_strip(tarball_path.to_string().clone());
-    let numbers_to_strip = numbers_to_strip.unwrap_or_default();
-    let strip = format!("--strip-components={}", numbers_to_strip);
+    let numbers_to_strip = components_to_strip(tarball_path).unwrap_or_default();
+    let mut cmd = Command::new("tar");
+    cmd.arg("zxvf")
+        .arg(tarball_path)
+        .arg("-C")
+        .arg(build_files_dir);
     if numbers_to_strip > 0 {
-        args.push(&strip);
+        cmd.arg(format!("--strip-components={}", numbers_to_strip));
     }
-    info!("Stripping components: {} {:?}", numbers_to_strip, args);
-    let output = Command::new("tar").args(args).output()?;
+    info!("Stripping components: {} {:?}", numbers_to_strip, cmd);
+    let output = cmd.output()?;
+    trace!("{}", String::from_utf8_lossy(&output.stdout));
     if !output.status.success() {
         let error_message = String::from_utf8(output.stderr)
             .unwrap_or_else(|_| "Unknown error occurred during extraction".to_string());
@@ -298,10 +679,45 @@ pub fn extract_source(tarball_path: &str, build_files_dir: &str) -> Result<()> {
     Ok(())
 }
 
+pub fn validate_spec_file(spec_file: &Path, config_root: &Path) -> Result<()> {
+    if spec_file.is_file() {
+        return Ok(());
+    }
+    let mut candidates: Vec<String> = match fs::read_dir(config_root) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "sss"))
+            .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect(),
+        Err(_) => vec![],
+    };
+    candidates.sort();
+    let suggestion = match candidates.as_slice() {
+        [] => format!("No .sss spec files found in {}.", config_root.display()),
+        [single] => format!(
+            "Did you mean spec_file = \"{}\"? It is the only .sss file in {}.",
+            single,
+            config_root.display()
+        ),
+        _ => format!(
+            "Available spec files in {}: {}.",
+            config_root.display(),
+            candidates.join(", ")
+        ),
+    };
+    Err(eyre!(
+        "Spec file {} does not exist. {}\nSet package_fields.spec_file in pkg-builder.toml relative to the config directory, or pass --spec <path>.",
+        spec_file.display(),
+        suggestion
+    ))
+}
+
 pub fn create_debian_dir(
-    build_files_dir: &String,
+    build_files_dir: &Path,
     debcrafter_version: &String,
-    spec_file: &str,
+    spec_file: &Path,
+    debian_version: &str,
 ) -> Result<()> {
     debcrafter_helper::check_if_dpkg_parsechangelog_installed()?;
     debcrafter_helper::check_if_installed(debcrafter_version)?;
@@ -311,16 +727,103 @@ pub fn create_debian_dir(
         "Created /debian dir under build_files_dir folder: {:?}",
         build_files_dir
     );
+    verify_changelog_version(build_files_dir, debian_version)?;
+    Ok(())
+}
+
+// hello-world (1:1.0.0-1) bookworm; urgency=medium
+pub fn parse_changelog_version(changelog: &str) -> Option<String> {
+    let first_line = changelog.lines().find(|line| !line.trim().is_empty())?;
+    let start = first_line.find('(')?;
+    let end = first_line[start..].find(')')? + start;
+    Some(first_line[start + 1..end].trim().to_string())
+}
+
+/// The changelog is written by hand next to the spec file, make sure its latest
+/// entry matches the configured version including the epoch, otherwise sbuild
+/// produces artifacts under a different name than expected.
+pub fn verify_changelog_version(build_files_dir: &Path, debian_version: &str) -> Result<()> {
+    let changelog_path = build_files_dir.join("debian").join("changelog");
+    let changelog = fs::read_to_string(&changelog_path).map_err(|err| {
+        eyre!(
+            "Could not read changelog {}: {}",
+            changelog_path.display(),
+            err
+        )
+    })?;
+    let changelog_version = parse_changelog_version(&changelog).ok_or_else(|| {
+        eyre!(
+            "Could not parse version from changelog {}",
+            changelog_path.display()
+        )
+    })?;
+    if changelog_version != debian_version {
+        return Err(eyre!(
+            "Changelog version {} does not match the configured version {}. Update the changelog next to the spec file, or package_fields.epoch, version_number and revision_number in pkg-builder.toml.",
+            changelog_version,
+            debian_version
+        ));
+    }
     Ok(())
 }
 
-pub fn patch_quilt(build_files_dir: &String) -> Result<()> {
-    let debian_source_format_path = format!("{}/debian/source/format", build_files_dir);
+/// Checks the debian dir shipped in the source and adds a changelog entry for the configured
+/// version when it has none. The entry reuses the date of the latest entry, the date is the
+/// SOURCE_DATE_EPOCH of the build, so rebuilding the same tag gives the same debs.
+pub fn use_upstream_debian_dir(build_files_dir: &Path, package_name: &str, debian_version: &str, codename: &str) -> Result<()> {
+    let debian_dir = build_files_dir.join("debian");
+    if !debian_dir.is_dir() {
+        return Err(eyre!(
+            "use_upstream_debian_dir is set, but the source has no debian dir in {}",
+            build_files_dir.display()
+        ));
+    }
+    let missing: Vec<&str> = ["control", "rules", "changelog"]
+        .into_iter()
+        .filter(|file| !debian_dir.join(file).is_file())
+        .collect();
+    if !missing.is_empty() {
+        return Err(eyre!("The upstream debian dir has no {}", missing.join(", ")));
+    }
+    let control = fs::read_to_string(debian_dir.join("control"))?;
+    let source = control.lines().find_map(|line| line.strip_prefix("Source:")).map(str::trim);
+    if source != Some(package_name) {
+        return Err(eyre!(
+            "The upstream debian/control is for source {}, not {}",
+            source.unwrap_or("<none>"),
+            package_name
+        ));
+    }
+    let changelog_path = debian_dir.join("changelog");
+    let changelog = fs::read_to_string(&changelog_path)?;
+    if parse_changelog_version(&changelog).as_deref() == Some(debian_version) {
+        return Ok(());
+    }
+    let date = changelog
+        .lines()
+        .find_map(|line| line.strip_prefix(" -- "))
+        .and_then(|trailer| trailer.split_once("  "))
+        .map(|(_, date)| date.trim().to_string())
+        .ok_or_else(|| eyre!("Could not find the date of the latest entry in {}", changelog_path.display()))?;
+    prepend_changelog_entry(
+        &changelog_path,
+        package_name,
+        debian_version,
+        codename,
+        &[format!("  * Package {} with pkg-builder.", debian_version)],
+        &date,
+    )?;
+    info!("Added changelog entry {} to the upstream debian dir", debian_version);
+    Ok(())
+}
+
+pub fn patch_quilt(build_files_dir: &Path) -> Result<()> {
+    let debian_source_format_path = build_files_dir.join("debian/source/format");
     info!(
         "Setting up quilt format for patching. Debian source format path: {}",
-        debian_source_format_path
+        debian_source_format_path.display()
     );
-    let debian_source_dir = PathBuf::from(&build_files_dir).join("debian/source");
+    let debian_source_dir = build_files_dir.join("debian/source");
     if !debian_source_dir.exists() {
         fs::create_dir_all(&debian_source_dir)?;
         info!(
@@ -329,35 +832,35 @@ pub fn patch_quilt(build_files_dir: &String) -> Result<()> {
         );
     }
 
-    if !Path::new(&debian_source_format_path).exists() {
+    if !debian_source_format_path.exists() {
         fs::write(&debian_source_format_path, "3.0 (quilt)\n")?;
         info!(
             "Quilt format file created at: {}",
-            debian_source_format_path
+            debian_source_format_path.display()
         );
     } else {
         info!(
             "Quilt format file already exists at: {}",
-            debian_source_format_path
+            debian_source_format_path.display()
         );
     }
     Ok(())
 }
 
-pub fn patch_pc_dir(build_files_dir: &String) -> Result<()> {
-    let pc_version_path = format!("{}/.pc/.version", &build_files_dir);
+pub fn patch_pc_dir(build_files_dir: &Path) -> Result<()> {
+    let pc_version_path = build_files_dir.join(".pc/.version");
     info!("Creating necessary directories for patching");
-    fs::create_dir_all(format!("{}/.pc", &build_files_dir))?;
+    fs::create_dir_all(build_files_dir.join(".pc"))?;
     let mut pc_version_file = fs::File::create(pc_version_path)?;
     writeln!(pc_version_file, "2")?;
     Ok(())
 }
 
-pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> Result<()> {
-    let debian_control_path = format!("{}/debian/control", build_files_dir);
+pub fn patch_standards_version(build_files_dir: &Path, homepage: &str) -> Result<()> {
+    let debian_control_path = build_files_dir.join("debian/control");
     info!(
         "Adding Standards-Version to the control file. Debian control path: {}",
-        debian_control_path
+        debian_control_path.display()
     );
     let input_file = fs::File::open(&debian_control_path)?;
     let reader = BufReader::new(input_file);
@@ -366,7 +869,7 @@ pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> R
     let has_standards_version = original_content
         .iter()
         .any(|line| line.starts_with("Standards-Version"));
-    let standards_version_line = "Standards-Version: 4.5.1";
+    let standards_version_line = format!("Standards-Version: {}", DEFAULT_STANDARDS_VERSION);
     let homepage_line = format!("Homepage: {}", homepage);
     if !has_standards_version {
         let mut insert_index = 0;
@@ -378,7 +881,7 @@ pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> R
         }
 
         let mut updated_content = original_content.clone();
-        updated_content.insert(insert_index, standards_version_line.to_string());
+        updated_content.insert(insert_index, standards_version_line);
         updated_content.insert(insert_index + 1, homepage_line.to_string());
 
         let mut output_file = fs::File::create(&debian_control_path)?;
@@ -393,23 +896,41 @@ pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> R
     Ok(())
 }
 
-pub fn copy_src_dir(build_files_dir: &String, src_dir: &String) -> Result<()> {
-    let src_dir_path = Path::new(src_dir);
-    if src_dir_path.exists() {
-        copy_directory_recursive(Path::new(src_dir), Path::new(&build_files_dir))
+/// Files of a debian dir worth reviewing: control, rules, changelog and the *.install files.
+pub fn get_review_files(debian_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = ["control", "rules", "changelog"]
+        .iter()
+        .map(|name| debian_dir.join(name))
+        .filter(|path| path.exists())
+        .collect();
+    let mut install_files = vec![];
+    for entry in fs::read_dir(debian_dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "install") {
+            install_files.push(path);
+        }
+    }
+    install_files.sort();
+    files.append(&mut install_files);
+    Ok(files)
+}
+
+pub fn copy_src_dir(build_files_dir: &Path, src_dir: &Path) -> Result<()> {
+    if src_dir.exists() {
+        copy_directory_recursive(src_dir, build_files_dir)
             .map_err(|err| eyre!(format!("Failed to copy src directory: {}", err)))?;
     }
     Ok(())
 }
 
-pub fn patch_rules_permission(build_files_dir: &str) -> Result<()> {
+pub fn patch_rules_permission(build_files_dir: &Path) -> Result<()> {
+    let debian_rules = build_files_dir.join("debian/rules");
     info!(
-        "Adding executable permission for {}/debian/rules",
-        build_files_dir
+        "Adding executable permission for {}",
+        debian_rules.display()
     );
 
-    let debian_rules = format!("{}/debian/rules", build_files_dir);
-    let mut permissions = fs::metadata(debian_rules.clone())
+    let mut permissions = fs::metadata(&debian_rules)
         .map_err(|_| eyre!("Failed to get debian/rules permission."))?
         .permissions();
     permissions.set_mode(permissions.mode() | 0o111);
@@ -418,7 +939,39 @@ pub fn patch_rules_permission(build_files_dir: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn patch_source(build_files_dir: &String, homepage: &String, src_dir: &String) -> Result<()> {
+fn collect_relative_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_relative_files(root, &path, files)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap();
+            let relative = relative
+                .to_str()
+                .ok_or_else(|| eyre!("Invalid file name {}", relative.display()))?;
+            files.push(relative.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Lists every file under dir in debian/source/include-binaries, otherwise dpkg-source -b
+/// refuses the vendored dependencies as unwanted binary files.
+pub fn add_include_binaries(build_files_dir: &Path, dir: &str) -> Result<()> {
+    let include_binaries = build_files_dir.join("debian/source/include-binaries");
+    let mut files: Vec<String> = match fs::read_to_string(&include_binaries) {
+        Ok(content) => content.lines().map(str::to_string).collect(),
+        Err(_) => vec![],
+    };
+    collect_relative_files(build_files_dir, &build_files_dir.join(dir), &mut files)?;
+    files.sort();
+    files.dedup();
+    fs::create_dir_all(include_binaries.parent().unwrap())?;
+    fs::write(&include_binaries, files.join("\n") + "\n")?;
+    Ok(())
+}
+
+pub fn patch_source(build_files_dir: &Path, homepage: &str, src_dir: &Path) -> Result<()> {
     // Patch quilt
     patch_quilt(build_files_dir)?;
 
@@ -437,16 +990,39 @@ pub fn patch_source(build_files_dir: &String, homepage: &String, src_dir: &Strin
     Ok(())
 }
 
-pub fn setup_sbuild() -> Result<()> {
+pub fn render_sbuildrc(
+    content: &str,
+    home_dir: &str,
+    build_user: &str,
+    build_environment: &BTreeMap<String, String>,
+) -> String {
+    // perl single quoted strings only need ' and \ escaped
+    let quote = |value: &str| value.replace('\\', "\\\\").replace('\'', "\\'");
+    let extra_environment: String = build_environment
+        .iter()
+        .map(|(key, value)| format!(",\n'{}' => '{}'", quote(key), quote(value)))
+        .collect();
+    content
+        .replace("<HOME>", home_dir)
+        .replace("<BUILD_USER>", &quote(build_user))
+        .replace("<BUILD_ENVIRONMENT>", &extra_environment)
+}
+
+pub fn setup_sbuild(build_user: &str, build_environment: &BTreeMap<String, String>) -> Result<()> {
     let home_dir = home_dir().expect("Home dir is empty");
     let dest_path = home_dir.join(".sbuildrc");
     let content = include_str!(".sbuildrc");
     let home_dir = home_dir.to_str().unwrap_or("/home/runner").to_string();
-    let replaced_contents = content.replace("<HOME>", &home_dir);
-    let mut file =
-        fs::File::create(dest_path).map_err(|_| eyre!("Failed to create ~/.sbuildrc."))?;
+    let replaced_contents = render_sbuildrc(content, &home_dir, build_user, build_environment);
+    // parallel builds of a matrix rewrite it, sbuild never reads a truncated file
+    let mut file = tempfile::Builder::new()
+        .prefix(".sbuildrc.tmp-")
+        .tempfile_in(dest_path.parent().unwrap_or(Path::new(".")))
+        .map_err(|_| eyre!("Failed to create ~/.sbuildrc."))?;
     file.write_all(replaced_contents.as_bytes())
         .map_err(|_| eyre!("Failed to write ~/.sbuildrc."))?;
+    file.persist(&dest_path)
+        .map_err(|_| eyre!("Failed to write ~/.sbuildrc."))?;
 
     Ok(())
 }
@@ -486,7 +1062,7 @@ pub fn copy_directory_recursive(src_dir: &Path, dest_dir: &Path) -> Result<(), i
     Ok(())
 }
 
-pub fn components_to_strip(tar_gz_file: String) -> Result<usize, io::Error> {
+pub fn components_to_strip(tar_gz_file: &Path) -> Result<usize, io::Error> {
     let output = Command::new("tar")
         .arg("--list")
         .arg("-z")
@@ -534,69 +1110,74 @@ pub fn longest_common_prefix(strings: &[&str]) -> String {
     prefix
 }
 
-pub fn get_build_artifacts_dir(
-    package_name: &str,
-    work_dir: &str,
-    version_number: &str,
-    revision_number: &str,
-) -> String {
-    let build_artifacts_dir = format!(
-        "{}/{}-{}-{}",
-        work_dir, &package_name, version_number, revision_number
-    );
-    build_artifacts_dir
-}
-
-pub fn get_tarball_path(
-    package_name: &str,
-    version_number: &str,
-    build_artifacts_dir: &str,
-) -> String {
-    let tarball_path = format!(
-        "{}/{}_{}.orig.tar.gz",
-        &build_artifacts_dir, &package_name, &version_number
-    );
-    tarball_path
+/// Layout of a package build under the workdir:
+///   <workdir>/<name>-<version>-<revision>/                  artifacts_dir, sbuild output
+///   <workdir>/<name>-<version>-<revision>/<name>_<version>.orig.tar.gz
+///   <workdir>/<name>-<version>-<revision>/<name>-<version>/ build_files_dir, extracted source
+///   <workdir>/.orig-cache/                                    generated git tarballs, kept across builds
+#[derive(Debug, PartialEq, Clone)]
+pub struct BuildPaths {
+    pub artifacts_dir: PathBuf,
+    pub tarball_path: PathBuf,
+    pub build_files_dir: PathBuf,
+    pub orig_cache_dir: PathBuf,
 }
 
-pub fn get_build_files_dir(
-    package_name: &str,
-    version_number: &str,
-    build_artifacts_dir: &str,
-) -> String {
-    let build_files_dir = format!(
-        "{}/{}-{}",
-        build_artifacts_dir, &package_name, &version_number
-    );
-    build_files_dir
+impl BuildPaths {
+    pub fn new(workdir: &Path, package_fields: &PackageFields) -> Self {
+        let package_name = &package_fields.package_name;
+        let version_number = &package_fields.version_number;
+        let artifacts_dir = workdir.join(format!(
+            "{}-{}-{}",
+            package_name, version_number, package_fields.revision_number
+        ));
+        BuildPaths {
+            tarball_path: artifacts_dir.join(format!("{}_{}.orig.tar.gz", package_name, version_number)),
+            build_files_dir: artifacts_dir.join(format!("{}-{}", package_name, version_number)),
+            artifacts_dir,
+            orig_cache_dir: workdir.join(".orig-cache"),
+        }
+    }
 }
 
-pub fn get_tarball_url(tarball_url: &str, config_root: &str) -> String {
+pub fn get_tarball_url(tarball_url: &str, config_root: &Path) -> String {
     if tarball_url.starts_with("http") {
         tarball_url.to_string()
     } else {
-        expand_path(tarball_url, Some(config_root))
+        expand_path(tarball_url, config_root)
     }
 }
 
-pub fn expand_path(dir: &str, dir_to_expand: Option<&str>) -> String {
+/// Expands ~, relative paths are joined to base.
+pub fn expand_path(dir: &str, base: &Path) -> String {
     if dir.starts_with('~') {
         let expanded_path = shellexpand::tilde(dir).to_string();
         expanded_path
     } else if dir.starts_with('/') {
         dir.to_string()
     } else {
-        let parent_dir = match dir_to_expand {
-            None => env::current_dir().unwrap(),
-            Some(path) => PathBuf::from(path),
-        };
-        let dir = parent_dir.join(dir);
+        let dir = base.join(dir);
         let path = fs::canonicalize(dir.clone()).unwrap_or(dir);
         let path = path.to_str().unwrap().to_string();
         path
     }
 }
 
+/// Expands a path of the config, relative paths are relative to the config root.
+pub fn expand_config_path(field: &str, dir: &str, config_root: &Path) -> String {
+    let path = expand_path(dir, config_root);
+    if let Ok(current_dir) = env::current_dir() {
+        let cwd_path = expand_path(dir, &current_dir);
+        if cwd_path != path {
+            warn!(
+                "{} {} is resolved relative to the config root to {}, resolving it relative to the current directory ({}) is deprecated",
+                field, dir, path, cwd_path
+            );
+        }
+    }
+    path
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -605,7 +1186,8 @@ mod tests {
     use std::path::PathBuf;
     // use std::sync::Once;
     // use env_logger::Env;
-    use crate::v1::pkg_config::{PackageType, PkgConfig};
+    use crate::v1::build::fixtures;
+    use crate::v1::pkg_config::AllowedSigners;
     use tempfile::tempdir;
 
     // static INIT: Once = Once::new();
@@ -631,10 +1213,112 @@ mod tests {
         server
     }
 
+    #[test]
+    fn test_get_review_files() {
+        let dir = tempdir().unwrap();
+        let debian_dir = dir.path().join("debian");
+        fs::create_dir_all(debian_dir.join("source")).unwrap();
+        for name in ["rules", "control", "compat", "hello-world.install", "hello-world-dev.install"] {
+            fs::write(debian_dir.join(name), "").unwrap();
+        }
+        let files = get_review_files(&debian_dir).unwrap();
+        let names: Vec<&str> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["control", "rules", "hello-world-dev.install", "hello-world.install"]
+        );
+    }
+
+    #[test]
+    fn test_add_include_binaries() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("debian/source")).unwrap();
+        fs::write(dir.path().join("debian/source/include-binaries"), "debian/logo.png\n").unwrap();
+        fs::create_dir_all(dir.path().join("debian/python-wheels/sub")).unwrap();
+        fs::write(dir.path().join("debian/python-wheels/click-8.1.7-py3-none-any.whl"), "").unwrap();
+        fs::write(dir.path().join("debian/python-wheels/sub/a.whl"), "").unwrap();
+        add_include_binaries(dir.path(), "debian/python-wheels").unwrap();
+        add_include_binaries(dir.path(), "debian/python-wheels").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("debian/source/include-binaries")).unwrap(),
+            "debian/logo.png\ndebian/python-wheels/click-8.1.7-py3-none-any.whl\ndebian/python-wheels/sub/a.whl\n"
+        );
+    }
+
+    #[test]
+    fn test_git_tarball_cache_key_changes_with_pins() {
+        let config = GitPackageTypeConfig {
+            git_url: "https://example.com/repo.git".to_string(),
+            git_tag: "v1.0.0".to_string(),
+            submodules: vec![SubModule {
+                commit: "a1b2c3".to_string(),
+                path: "vendor/lib".to_string(),
+            }],
+            ..GitPackageTypeConfig::default()
+        };
+        let key = git_tarball_cache_key(&config);
+        assert_eq!(key, git_tarball_cache_key(&config.clone()));
+        let retagged = GitPackageTypeConfig {
+            git_tag: "v1.0.1".to_string(),
+            ..config.clone()
+        };
+        assert_ne!(key, git_tarball_cache_key(&retagged));
+
+        let repinned = GitPackageTypeConfig {
+            submodules: vec![SubModule {
+                commit: "d4e5f6".to_string(),
+                path: "vendor/lib".to_string(),
+            }],
+            ..config.clone()
+        };
+        assert_ne!(key, git_tarball_cache_key(&repinned));
+
+        let signed = GitPackageTypeConfig {
+            require_signed_tag: Some(true),
+            allowed_signers: Some(AllowedSigners {
+                key_file: "signers.asc".to_string(),
+                fingerprints: vec!["0123456789ABCDEF0123456789ABCDEF01234567".to_string()],
+            }),
+            ..config.clone()
+        };
+        assert_ne!(key, git_tarball_cache_key(&signed));
+    }
+
+    #[test]
+    fn test_download_git_cached_reuses_tarball() {
+        let workdir = tempdir().unwrap();
+        let artifacts_dir = workdir.path().join("hello-world-1.0.0-1");
+        let orig_cache_dir = workdir.path().join(".orig-cache");
+        fs::create_dir_all(&artifacts_dir).unwrap();
+        fs::create_dir_all(&orig_cache_dir).unwrap();
+        let config = GitPackageTypeConfig {
+            git_url: "https://example.com/repo.git".to_string(),
+            git_tag: "v1.0.0".to_string(),
+            ..GitPackageTypeConfig::default()
+        };
+        let key = git_tarball_cache_key(&config);
+        fs::write(orig_cache_dir.join(format!("hello-world-{}.tar.gz", key)), "cached").unwrap();
+
+        let tarball_path = artifacts_dir.join("hello-world_1.0.0.orig.tar.gz");
+        // the url is never contacted, the tarball comes from the cache
+        download_git_cached(
+            &artifacts_dir,
+            &tarball_path,
+            &orig_cache_dir,
+            "hello-world",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&tarball_path).unwrap(), "cached");
+    }
+
     #[test]
     fn expand_path_expands_tilde_correctly() {
         setup();
-        let result = expand_path("~", None);
+        let result = expand_path("~", Path::new("/tmp"));
         assert_ne!(result, "~");
         assert!(!result.contains('~'));
     }
@@ -642,22 +1326,48 @@ mod tests {
     #[test]
     fn expand_path_handles_absolute_paths() {
         setup();
-        let result = expand_path("/absolute/path", None);
+        let result = expand_path("/absolute/path", Path::new("/tmp"));
         assert_eq!(result, "/absolute/path");
     }
 
     #[test]
     fn expand_path_expands_relative_paths_with_parent() {
         setup();
-        let result = expand_path("somefile", Some("/tmp"));
+        let result = expand_path("somefile", Path::new("/tmp"));
         assert_eq!(result, "/tmp/somefile");
     }
 
     #[test]
-    fn expand_path_expands_relative_paths_without_parent() {
+    fn expand_config_path_ignores_current_dir() {
         setup();
-        let result = expand_path("somefile", None);
-        assert!(result.starts_with('/'));
+        let config_root = tempdir().unwrap();
+        let config_root = fs::canonicalize(config_root.path()).unwrap();
+        let result = expand_config_path("workdir", "packages", &config_root);
+        assert_eq!(result, config_root.join("packages").to_str().unwrap());
+        assert_eq!(expand_config_path("workdir", "~/packages", &config_root), expand_path("~/packages", &config_root));
+    }
+
+    #[test]
+    fn test_build_paths_layout() {
+        let paths = BuildPaths::new(
+            Path::new("/workdir"),
+            &PackageFields {
+                package_name: "hello-world".to_string(),
+                version_number: "1.0.0".to_string(),
+                revision_number: "1".to_string(),
+                ..PackageFields::default()
+            },
+        );
+        assert_eq!(paths.artifacts_dir, Path::new("/workdir/hello-world-1.0.0-1"));
+        assert_eq!(
+            paths.tarball_path,
+            Path::new("/workdir/hello-world-1.0.0-1/hello-world_1.0.0.orig.tar.gz")
+        );
+        assert_eq!(
+            paths.build_files_dir,
+            Path::new("/workdir/hello-world-1.0.0-1/hello-world-1.0.0")
+        );
+        assert_eq!(paths.orig_cache_dir, Path::new("/workdir/.orig-cache"));
     }
 
     #[test]
@@ -668,7 +1378,7 @@ mod tests {
 
         let build_artifacts_dir = temp_dir.path().join("test_package");
 
-        let result = create_package_dir(&String::from(build_artifacts_dir.to_str().unwrap()));
+        let result = create_package_dir(&build_artifacts_dir);
 
         assert!(result.is_ok());
         assert!(build_artifacts_dir.exists());
@@ -686,7 +1396,7 @@ mod tests {
         let test_file = build_artifacts_dir.clone().join("test_file");
         File::create(test_file.clone()).expect("Failed to create test_file");
         assert!(test_file.clone().exists());
-        let result = create_package_dir(&String::from(build_artifacts_dir.to_str().unwrap()));
+        let result = create_package_dir(&build_artifacts_dir);
 
         assert!(result.is_ok());
         assert!(!test_file.clone().exists());
@@ -699,12 +1409,9 @@ mod tests {
 
         let temp_dir = tempdir().expect("Failed to create temporary directory");
 
-        let build_artifacts_dir = String::from(temp_dir.path().to_str().unwrap());
-        let tarball_name = "test_package.tar.gz";
-        let tarball_path = temp_dir.path().join(tarball_name);
-        let tarball_path_str = String::from(temp_dir.path().join(tarball_name).to_str().unwrap());
+        let tarball_path = temp_dir.path().join("test_package.tar.gz");
 
-        let result = create_empty_tar(&build_artifacts_dir, &tarball_path_str);
+        let result = create_empty_tar(temp_dir.path(), &tarball_path);
 
         assert!(result.is_ok());
         assert!(tarball_path.exists());
@@ -722,7 +1429,7 @@ mod tests {
         let tarball_path = temp_dir.path().join(tarball_name);
         let tarball_url = format!("{}/{}", server.base_url(), tarball_name);
 
-        let result = download_source(tarball_path.to_str().unwrap(), &tarball_url, "/examples");
+        let result = download_source(&tarball_path, &tarball_url, Path::new("/examples"));
 
         assert!(result.is_ok());
         assert!(tarball_path.exists());
@@ -732,6 +1439,31 @@ mod tests {
     #[ignore]
     fn test_download_source_with_git_package() {}
 
+    #[test]
+    fn test_get_wget_request() {
+        let url = "https://artifacts.example.com/hello-world-1.0.0.tar.gz";
+        let mut auth = TarballAuth {
+            credential: Some("artifact-store".to_string()),
+            headers: BTreeMap::from([("Accept".to_string(), "application/octet-stream".to_string())]),
+            ..TarballAuth::default()
+        };
+        assert_eq!(
+            get_wget_request(url, &auth, Some("Bearer abc123")),
+            (
+                "header = Accept: application/octet-stream\nheader = Authorization: Bearer abc123\n".to_string(),
+                url.to_string()
+            )
+        );
+
+        auth.query_param = Some("private_token".to_string());
+        let (wgetrc, url) = get_wget_request(&format!("{}?ref=main", url), &auth, Some("a+b/c"));
+        assert_eq!(wgetrc, "header = Accept: application/octet-stream\n");
+        assert_eq!(
+            url,
+            "https://artifacts.example.com/hello-world-1.0.0.tar.gz?ref=main&private_token=a%2Bb%2Fc"
+        );
+    }
+
     #[test]
     fn test_extract_source() {
         setup();
@@ -740,15 +1472,15 @@ mod tests {
         let temp_dir = temp_dir.path();
         let tarball_path: PathBuf = PathBuf::from("tests/misc/test_package.tar.gz");
 
-        let build_files_dir = temp_dir.join(package_name).to_string_lossy().to_string();
+        let build_files_dir = temp_dir.join(package_name);
 
         assert!(tarball_path.exists());
-        let result = extract_source(tarball_path.to_str().unwrap(), &build_files_dir);
+        let result = extract_source(&tarball_path, &build_files_dir);
 
         assert!(result.is_ok(), "{:?}", result);
-        assert!(Path::new(&build_files_dir).exists());
+        assert!(build_files_dir.exists());
 
-        let test_file_path = PathBuf::from(build_files_dir.clone()).join("empty_file.txt");
+        let test_file_path = build_files_dir.join("empty_file.txt");
 
         assert!(
             test_file_path.exists(),
@@ -765,7 +1497,7 @@ mod tests {
         fs::create_dir_all(temp_dir.path().join("debian")).expect("Could not create dir");
         File::create(&rules_path)?;
 
-        patch_rules_permission(temp_dir.path().to_str().unwrap())?;
+        patch_rules_permission(temp_dir.path())?;
 
         let permissions = fs::metadata(&rules_path)?.permissions();
         assert_ne!(permissions.mode() & 0o111, 0);
@@ -777,7 +1509,7 @@ mod tests {
     fn patch_rules_permission_handles_nonexistent_directory() {
         setup();
 
-        let result = patch_rules_permission("/nonexistent/dir");
+        let result = patch_rules_permission(Path::new("/nonexistent/dir"));
 
         assert!(result.is_err());
     }
@@ -787,9 +1519,7 @@ mod tests {
         setup();
 
         let temp_dir = tempdir()?;
-        let build_files_dir = temp_dir.path().to_str().unwrap().to_string();
-
-        patch_quilt(&build_files_dir)?;
+        patch_quilt(temp_dir.path())?;
 
         let debian_source_dir = temp_dir.path().join("debian/source");
         assert!(debian_source_dir.exists());
@@ -807,12 +1537,10 @@ mod tests {
 
         let temp_dir = tempdir()?;
         let temp_dir = temp_dir.path();
-        let build_files_dir = temp_dir.to_str().unwrap().to_string();
-
         fs::create_dir_all(temp_dir.join("debian/source")).expect("Failed to create dir for test.");
         File::create(temp_dir.join("debian/source/format")).expect("Failed to create file.");
 
-        let result = patch_quilt(&build_files_dir);
+        let result = patch_quilt(temp_dir);
         assert!(result.is_ok());
 
         let entries: Vec<_> = fs::read_dir(temp_dir)?.collect();
@@ -824,7 +1552,7 @@ mod tests {
     #[test]
     fn test_verify_hash_valid_checksum_512() {
         setup();
-        let tarball_path = "tests/misc/test_package.tar.gz";
+        let tarball_path = Path::new("tests/misc/test_package.tar.gz");
         let expected_checksum = "abd0b8e99f983926dbf60bdcbaef13f83ec7b31d56e68f6252ed05981b237c837044ce768038fc34b71f925e2fb19b7dee451897db512bb4a99e0e1bc96d8ab3";
 
         let result = verify_hash(tarball_path, Some(expected_checksum.to_string()));
@@ -835,7 +1563,7 @@ mod tests {
     #[test]
     fn test_verify_hash_invalid_checksum_512() {
         setup();
-        let tarball_path = "tests/misc/test_package.tar.gz";
+        let tarball_path = Path::new("tests/misc/test_package.tar.gz");
         let expected_checksum = "abd0b8e99f983926dbf60bdcbaef13f83ec7b31d56e68f6252ed05981b237c837044ce768038fc34b71f925e2fb19b7dee451897db512bb4a99e0e1bc96d8ab2";
 
         let result = verify_hash(tarball_path, Some(expected_checksum.to_string()));
@@ -850,7 +1578,7 @@ mod tests {
     #[test]
     fn test_verify_hash_valid_checksum_256() {
         setup();
-        let tarball_path = "tests/misc/test_package.tar.gz";
+        let tarball_path = Path::new("tests/misc/test_package.tar.gz");
         let expected_checksum = "b610e83c026d4c465636779240b6ed40a076593a61df5f6b9f9f59f1a929478d";
 
         let result = verify_hash(tarball_path, Some(expected_checksum.to_string()));
@@ -861,7 +1589,7 @@ mod tests {
     #[test]
     fn test_verify_hash_invalid_checksum_256() {
         setup();
-        let tarball_path = "tests/misc/test_package.tar.gz";
+        let tarball_path = Path::new("tests/misc/test_package.tar.gz");
         let expected_checksum = "b610e83c026d4c465636779240b6ed40a076593a61df5f6b9f9f59f1a929478_";
 
         let result = verify_hash(tarball_path, Some(expected_checksum.to_string()));
@@ -874,28 +1602,264 @@ mod tests {
     }
 
     #[test]
-    fn test_clone_and_checkout_tag() {
-        let url = "https://github.com/status-im/nimbus-eth2.git";
-        let temp_dir = tempdir().expect("Failed to create temporary directory");
-        let repo_path = temp_dir.path();
-        let repo_path_str = repo_path.to_str().unwrap();
-        let tag_version = "v24.3.0";
-        let str = fs::read_to_string("examples/bookworm/git-package/nimbus/pkg-builder.toml")
-            .expect("File does not exist");
-        let config: PkgConfig = toml::from_str(&str).expect("Cannot parse file.");
-        match config.package_type {
-            PackageType::Git(gitconfig) => {
-                let result =
-                    clone_and_checkout_tag(url, tag_version, repo_path_str, &gitconfig.submodules);
-                assert!(
-                    result.is_ok(),
-                    "Failed to clone and checkout tag: {:?}",
-                    result
-                );
+    fn validate_spec_file_accepts_existing_file() {
+        let temp_dir = tempdir().unwrap();
+        let spec_file = temp_dir.path().join("hello-world.sss");
+        File::create(&spec_file).unwrap();
+
+        let result = validate_spec_file(&spec_file, temp_dir.path());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn validate_spec_file_suggests_single_candidate() {
+        let temp_dir = tempdir().unwrap();
+        File::create(temp_dir.path().join("hello-world.sss")).unwrap();
+        File::create(temp_dir.path().join("hello-world.sps")).unwrap();
+        let config_root = temp_dir.path();
+        let missing = temp_dir.path().join("hello-wrld.sss");
+
+        let error = validate_spec_file(&missing, config_root)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Did you mean spec_file = \"hello-world.sss\"?"), "{}", error);
+        assert!(error.contains("--spec <path>"));
+    }
+
+    #[test]
+    fn validate_spec_file_lists_all_candidates() {
+        let temp_dir = tempdir().unwrap();
+        File::create(temp_dir.path().join("b.sss")).unwrap();
+        File::create(temp_dir.path().join("a.sss")).unwrap();
+        let config_root = temp_dir.path();
+        let missing = temp_dir.path().join("c.sss");
+
+        let error = validate_spec_file(&missing, config_root)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Available spec files in"), "{}", error);
+        assert!(error.contains("a.sss, b.sss"));
+    }
+
+    #[test]
+    fn validate_spec_file_without_candidates() {
+        let temp_dir = tempdir().unwrap();
+        let config_root = temp_dir.path();
+        let missing = temp_dir.path().join("hello-world.sss");
+
+        let error = validate_spec_file(&missing, config_root)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("No .sss spec files found"), "{}", error);
+    }
+
+    #[test]
+    fn test_streamed_hashing_of_sparse_large_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("sparse.img");
+        let size: u64 = 16 * 1024 * 1024;
+        let file = File::create(&path).unwrap();
+        file.set_len(size).unwrap();
+
+        let expected = calculate_sha256(io::repeat(0).take(size)).unwrap();
+        let reader = open_for_hashing(&path).unwrap();
+        assert_eq!(calculate_sha256(reader).unwrap(), expected);
+        assert!(verify_tarball_checksum(&path, &expected).unwrap());
+    }
+
+    #[test]
+    fn test_progress_reader_reports_large_files() {
+        let size: u64 = 16 * 1024 * 1024;
+        let mut reader =
+            ProgressReader::with_report_step(io::repeat(0).take(size), "sparse.img", size, size / 10);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(reader.bytes_read, size);
+        assert!(reader.reports >= 9, "{}", reader.reports);
+
+        let mut reader = ProgressReader::new(io::repeat(0).take(size), "small.img", size);
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        assert_eq!(reader.reports, 0);
+    }
+
+    #[test]
+    fn render_sbuildrc_adds_build_environment() {
+        let content = include_str!(".sbuildrc");
+        let mut build_environment = BTreeMap::new();
+        build_environment.insert("CARGO_BUILD_JOBS".to_string(), "8".to_string());
+        build_environment.insert("GRADLE_OPTS".to_string(), "-Dx='y'".to_string());
+
+        let rendered = render_sbuildrc(content, "/home/builder", "builder", &build_environment);
+        assert!(rendered.contains(
+            "'HOME' => '/home/builder',\n'CARGO_BUILD_JOBS' => '8',\n'GRADLE_OPTS' => '-Dx=\\'y\\''\n};"
+        ), "{}", rendered);
+
+        let rendered = render_sbuildrc(content, "/home/builder", "builder", &BTreeMap::new());
+        assert!(rendered.contains("'HOME' => '/home/builder'\n};"));
+        assert!(rendered.contains("$build_user = 'builder';"));
+    }
+
+    #[test]
+    fn test_remove_workdir_with_read_only_dirs() {
+        let workdir = tempdir().unwrap();
+        let package_dir = workdir.path().join("hello-world");
+        let debian_dir = package_dir.join("build/debian");
+        fs::create_dir_all(&debian_dir).unwrap();
+        fs::write(debian_dir.join("control"), "Source: hello-world\n").unwrap();
+        fs::set_permissions(debian_dir.join("control"), fs::Permissions::from_mode(0o400)).unwrap();
+        fs::set_permissions(&debian_dir, fs::Permissions::from_mode(0o500)).unwrap();
+
+        let foreign = normalize_permissions(&package_dir).unwrap();
+        assert!(foreign.is_empty());
+        let mode = fs::metadata(&debian_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o700, 0o700);
+
+        fs::set_permissions(&debian_dir, fs::Permissions::from_mode(0o500)).unwrap();
+        remove_workdir(&package_dir).unwrap();
+        assert!(!package_dir.exists());
+    }
+
+    #[test]
+    fn test_create_exclusively_from_concurrent_runs() {
+        let cache_dir = tempdir().unwrap();
+        let image_path = cache_dir.path().join("autopkgtest-noble-amd64.img");
+        let created = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    create_exclusively(&image_path, |tmp_path| {
+                        created.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        assert!(!image_path.exists());
+                        fs::write(tmp_path, "partial")?;
+                        std::thread::sleep(std::time::Duration::from_millis(200));
+                        fs::write(tmp_path, "image")?;
+                        Ok(())
+                    })
+                    .unwrap();
+                    assert_eq!(fs::read_to_string(&image_path).unwrap(), "image");
+                });
             }
-            _ => panic!("Wrong type of file."),
-        }
+        });
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // a failed creation leaves nothing behind, the next run creates it again
+        let tarball_path = cache_dir.path().join("noble-amd64.tar.gz");
+        let result = create_exclusively(&tarball_path, |tmp_path| {
+            fs::write(tmp_path, "partial")?;
+            Err(eyre!("sbuild-createchroot failed"))
+        });
+        assert!(result.is_err());
+        assert!(!tarball_path.exists());
+        create_exclusively(&tarball_path, |tmp_path| Ok(fs::write(tmp_path, "chroot")?)).unwrap();
+        assert_eq!(fs::read_to_string(&tarball_path).unwrap(), "chroot");
+
+        let mut entries: Vec<String> = fs::read_dir(cache_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ".autopkgtest-noble-amd64.img.lock",
+                ".noble-amd64.tar.gz.lock",
+                "autopkgtest-noble-amd64.img",
+                "noble-amd64.tar.gz"
+            ]
+        );
+    }
 
-        fs::remove_dir_all(temp_dir).unwrap();
+    #[test]
+    fn test_check_workdir_ownership() {
+        let workdir = tempdir().unwrap();
+        let package_dir = workdir.path().join("hello-world");
+        fs::create_dir_all(package_dir.join("build/debian")).unwrap();
+        fs::write(package_dir.join("build/debian/control"), "Source: hello-world\n").unwrap();
+        let uid = fs::metadata(&package_dir).unwrap().uid();
+
+        check_workdir_ownership(&package_dir, uid, (100000, 65536)).unwrap();
+        // entries of the chroot are mapped to the subordinate ids
+        check_workdir_ownership(&package_dir, uid + 1, (uid, 65536)).unwrap();
+        let err = check_workdir_ownership(&package_dir, uid + 1, (uid + 1, 65536)).unwrap_err();
+        assert!(err.to_string().starts_with("1 entries under"), "{}", err);
+
+        let subuid = "root:100000:65536\nbuilder:165536:65536\n1000:231072:65536\n";
+        assert_eq!(parse_subid_ranges(subuid, "builder", 1000), vec![(165536, 65536), (231072, 65536)]);
+        assert!(parse_subid_ranges(subuid, "nobody", 65534).is_empty());
+    }
+
+    #[test]
+    fn verify_changelog_version_requires_epoch() {
+        let build_files_dir = tempdir().unwrap();
+        let debian_dir = build_files_dir.path().join("debian");
+        fs::create_dir_all(&debian_dir).unwrap();
+        fs::write(
+            debian_dir.join("changelog"),
+            "hello-world (1:1.0.0-1) bookworm; urgency=medium\n\n  * Initial packaging\n",
+        )
+        .unwrap();
+        let build_files_dir = build_files_dir.path();
+
+        assert!(verify_changelog_version(build_files_dir, "1:1.0.0-1").is_ok());
+        let result = verify_changelog_version(build_files_dir, "1.0.0-1");
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Changelog version 1:1.0.0-1 does not match the configured version 1.0.0-1"));
+    }
+
+    #[test]
+    fn test_use_upstream_debian_dir() {
+        let build_files_dir = tempdir().unwrap();
+        let build_files_dir = build_files_dir.path();
+        assert!(use_upstream_debian_dir(build_files_dir, "hello-world", "1.1.0-1", "bookworm").is_err());
+        let debian_dir = build_files_dir.join("debian");
+        fs::create_dir_all(&debian_dir).unwrap();
+        fs::write(debian_dir.join("control"), "Source: hello-world\n\nPackage: hello-world\n").unwrap();
+        fs::write(debian_dir.join("rules"), "#!/usr/bin/make -f\n%:\n\tdh $@\n").unwrap();
+        let result = use_upstream_debian_dir(build_files_dir, "hello-world", "1.1.0-1", "bookworm");
+        assert_eq!(result.unwrap_err().to_string(), "The upstream debian dir has no changelog");
+        fs::write(
+            debian_dir.join("changelog"),
+            "hello-world (1.0.0-1) unstable; urgency=medium\n\n  * Initial packaging\n\n -- John Doe <johndoe@example.com>  Tue, 17 Oct 2023 13:19:27 +0700\n",
+        )
+        .unwrap();
+        assert!(use_upstream_debian_dir(build_files_dir, "other", "1.1.0-1", "bookworm").is_err());
+
+        use_upstream_debian_dir(build_files_dir, "hello-world", "1.1.0-1", "bookworm").unwrap();
+        use_upstream_debian_dir(build_files_dir, "hello-world", "1.1.0-1", "bookworm").unwrap();
+        let changelog = fs::read_to_string(debian_dir.join("changelog")).unwrap();
+        assert!(changelog.starts_with(
+            "hello-world (1.1.0-1) bookworm; urgency=medium\n\n  * Package 1.1.0-1 with pkg-builder.\n\n -- John Doe <johndoe@example.com>  Tue, 17 Oct 2023 13:19:27 +0700\n\nhello-world (1.0.0-1)"
+        ));
+    }
+
+    #[test]
+    fn test_clone_and_checkout_tag() {
+        fixtures::allow_file_submodules();
+        let upstream = tempdir().unwrap();
+        let (url, first_commit) = fixtures::create_upstream_git_repo_with_submodule(
+            upstream.path(),
+            "hello-world",
+            "v1.0.0",
+            "vendor/hello-lib",
+        )
+        .unwrap();
+        let temp_dir = tempdir().expect("Failed to create temporary directory");
+        let repo_path = temp_dir.path().join("hello-world");
+        let submodules = vec![SubModule {
+            commit: first_commit.clone(),
+            path: "vendor/hello-lib".to_string(),
+        }];
+
+        let result = clone_and_checkout_tag(&url, "v1.0.0", &repo_path, &submodules);
+        assert!(result.is_ok(), "Failed to clone and checkout tag: {:?}", result);
+        assert!(repo_path.join("README").exists());
+        let output = Command::new("git")
+            .current_dir(repo_path.join("vendor/hello-lib"))
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), first_commit);
+        assert!(!repo_path.join("vendor/hello-lib/CHANGELOG").exists());
     }
 }
@@ -0,0 +1,185 @@
+// Copies build artifacts to a destination with normalized names, the last stage of a build,
+// e.g. into the staging dir of a release.
+use crate::v1::pkg_config::{ExportsConfig, PackageFields};
+use eyre::{eyre, Result};
+use log::info;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const EXPORT_PLACEHOLDERS: &[&str] = &[
+    "name", "package", "arch", "ext", "source", "version", "revision", "file_version", "codename",
+];
+
+// e.g. hello-world_1.0.0-1_amd64.deb -> (hello-world, Some(amd64), deb),
+// hello-world_1.0.0.orig.tar.gz -> (hello-world, None, orig.tar.gz)
+fn split_file_name<'a>(file_name: &'a str, package_fields: &PackageFields) -> (&'a str, Option<&'a str>, &'a str) {
+    let parts: Vec<&str> = file_name.splitn(3, '_').collect();
+    match parts.as_slice() {
+        [package, _, rest] => {
+            let (arch, ext) = rest.split_once('.').unwrap_or((rest, ""));
+            (package, Some(arch), ext)
+        }
+        [package, rest] => {
+            let ext = [package_fields.get_file_version(), package_fields.version_number.clone()]
+                .iter()
+                .find_map(|version| rest.strip_prefix(version.as_str())?.strip_prefix('.'))
+                .or_else(|| rest.split_once('.').map(|(_, ext)| ext))
+                .unwrap_or("");
+            (package, None, ext)
+        }
+        _ => (file_name, None, ""),
+    }
+}
+
+/// Destination of the artifact, relative to the export destination, e.g. the template
+/// "{package}_{version}_{arch}.{ext}" drops the revision of the deb names.
+pub fn render_export_name(template: &str, file_name: &str, package_fields: &PackageFields, codename: &str) -> Result<String> {
+    let (package, arch, ext) = split_file_name(file_name, package_fields);
+    let values = BTreeMap::from([
+        ("name", Some(file_name.to_string())),
+        ("package", Some(package.to_string())),
+        ("arch", arch.map(str::to_string)),
+        ("ext", Some(ext.to_string())),
+        ("source", Some(package_fields.package_name.clone())),
+        ("version", Some(package_fields.version_number.clone())),
+        ("revision", Some(package_fields.revision_number.clone())),
+        ("file_version", Some(package_fields.get_file_version())),
+        ("codename", Some(codename.to_string())),
+    ]);
+    let mut rendered = template.to_string();
+    for (placeholder, value) in values {
+        let pattern = format!("{{{}}}", placeholder);
+        if !rendered.contains(&pattern) {
+            continue;
+        }
+        let value = value.ok_or_else(|| eyre!("exports: {} has no {{{}}} for {}", file_name, placeholder, template))?;
+        rendered = rendered.replace(&pattern, &value);
+    }
+    Ok(rendered)
+}
+
+/// Source and destination of every artifact matched by the rules, an artifact is exported by
+/// every rule matching it, two artifacts exported to the same destination are an error.
+pub fn plan_exports(
+    artifacts: &[PathBuf],
+    config: &ExportsConfig,
+    package_fields: &PackageFields,
+    codename: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let destination = Path::new(&config.destination);
+    let mut exports: Vec<(PathBuf, PathBuf)> = vec![];
+    for rule in config.files.iter() {
+        let pattern = glob::Pattern::new(&rule.glob)
+            .map_err(|err| eyre!("exports: invalid glob {}: {}", rule.glob, err))?;
+        let template = rule.name.as_deref().unwrap_or("{name}");
+        let mut matched = false;
+        for artifact in artifacts {
+            let file_name = artifact.file_name().unwrap().to_string_lossy();
+            if !pattern.matches(&file_name) {
+                continue;
+            }
+            matched = true;
+            let target = destination.join(render_export_name(template, &file_name, package_fields, codename)?);
+            if let Some((other, _)) = exports.iter().find(|(source, existing)| existing == &target && source != artifact) {
+                return Err(eyre!(
+                    "exports: {} and {} are both exported to {}",
+                    other.display(),
+                    artifact.display(),
+                    target.display()
+                ));
+            }
+            exports.push((artifact.clone(), target));
+        }
+        if !matched {
+            return Err(eyre!("exports: {} matches none of the build artifacts", rule.glob));
+        }
+    }
+    Ok(exports)
+}
+
+pub fn export_artifacts(
+    artifacts: &[PathBuf],
+    config: &ExportsConfig,
+    package_fields: &PackageFields,
+    codename: &str,
+) -> Result<Vec<PathBuf>> {
+    let exports = plan_exports(artifacts, config, package_fields, codename)?;
+    for (source, target) in exports.iter() {
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::copy(source, target)
+            .map_err(|err| eyre!("Failed to export {} to {}: {}", source.display(), target.display(), err))?;
+        info!("Exported {} to {}", source.display(), target.display());
+    }
+    Ok(exports.into_iter().map(|(_, target)| target).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::ExportRule;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_artifacts() {
+        let build_dir = tempdir().unwrap();
+        let staging = tempdir().unwrap();
+        let artifacts: Vec<PathBuf> = [
+            "hello-world_1.0.0-1_amd64.deb",
+            "hello-world-dbgsym_1.0.0-1_amd64.ddeb",
+            "hello-world_1.0.0-1.dsc",
+            "hello-world_1.0.0.orig.tar.gz",
+            "hello-world_1.0.0-1_amd64.changes",
+        ]
+        .iter()
+        .map(|name| {
+            let path = build_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            path
+        })
+        .collect();
+        let package_fields = PackageFields {
+            package_name: "hello-world".to_string(),
+            version_number: "1.0.0".to_string(),
+            revision_number: "1".to_string(),
+            ..PackageFields::default()
+        };
+        let rule = |glob: &str, name: Option<&str>| ExportRule {
+            glob: glob.to_string(),
+            name: name.map(str::to_string),
+        };
+        let config = ExportsConfig {
+            destination: staging.path().to_string_lossy().to_string(),
+            files: vec![
+                rule("*.deb", Some("{codename}/{package}_{version}_{arch}.{ext}")),
+                rule("*.orig.tar.gz", Some("{source}-{version}.{ext}")),
+                rule("*.changes", None),
+            ],
+        };
+        let exported = export_artifacts(&artifacts, &config, &package_fields, "bookworm").unwrap();
+        assert_eq!(
+            exported,
+            vec![
+                staging.path().join("bookworm/hello-world_1.0.0_amd64.deb"),
+                staging.path().join("hello-world-1.0.0.orig.tar.gz"),
+                staging.path().join("hello-world_1.0.0-1_amd64.changes"),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(&exported[0]).unwrap(),
+            "hello-world_1.0.0-1_amd64.deb"
+        );
+
+        // the dsc has no arch, and both debs would end up in the same file
+        let config = ExportsConfig {
+            files: vec![rule("*.dsc", Some("{package}_{arch}.dsc"))],
+            ..config
+        };
+        assert!(plan_exports(&artifacts, &config, &package_fields, "bookworm").is_err());
+        let config = ExportsConfig {
+            files: vec![rule("*.*deb", Some("{source}.deb"))],
+            ..config
+        };
+        assert!(plan_exports(&artifacts, &config, &package_fields, "bookworm").is_err());
+    }
+}
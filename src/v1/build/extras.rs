@@ -0,0 +1,166 @@
+// Finds shell completions and man pages in the extracted source and adds them to the
+// debhelper install files, so they end up in the package without touching the spec.
+use crate::v1::pkg_config::ExtrasConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_COMPLETIONS: &[&str] = &[
+    "completions/*",
+    "completion/*",
+    "contrib/completion/*",
+    "contrib/completions/*",
+    "shell-completions/*",
+];
+const DEFAULT_MAN_PAGES: &[&str] = &[
+    "man/*.[1-9]",
+    "man/man[1-9]/*.[1-9]",
+    "doc/*.[1-9]",
+    "docs/*.[1-9]",
+    "docs/man/*.[1-9]",
+];
+
+fn get_patterns<'a>(configured: &'a [String], defaults: &'a [&'a str]) -> Vec<&'a str> {
+    match configured.is_empty() {
+        true => defaults.to_vec(),
+        false => configured.iter().map(String::as_str).collect(),
+    }
+}
+
+/// Files matching the globs, relative to the source dir, the debian dir is left out.
+pub fn find_files(source_dir: &Path, patterns: &[&str]) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        let full_pattern = format!("{}/{}", glob::Pattern::escape(&source_dir.to_string_lossy()), pattern);
+        let paths = glob::glob(&full_pattern)
+            .map_err(|err| eyre!("extras: invalid glob {}: {}", pattern, err))?;
+        for path in paths.flatten() {
+            let Ok(relative) = path.strip_prefix(source_dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().to_string();
+            if path.is_file() && !relative.starts_with("debian/") && !files.contains(&relative) {
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the shell looks for the completion, bash loads <command> and <command>.bash.
+pub fn get_completion_dir(file: &str) -> Option<&'static str> {
+    let name = Path::new(file).file_name()?.to_str()?;
+    if name.ends_with(".fish") {
+        Some("usr/share/fish/vendor_completions.d")
+    } else if name.ends_with(".zsh") || name.starts_with('_') {
+        Some("usr/share/zsh/vendor-completions")
+    } else if name.ends_with(".bash") || !name.contains('.') {
+        Some("usr/share/bash-completion/completions")
+    } else {
+        None
+    }
+}
+
+// debian/<package>.<name>, or debian/<name> if the spec only generates that one for the
+// main package, debhelper ignores debian/<name> once debian/<package>.<name> exists
+pub fn append_lines(debian_dir: &Path, package: &str, is_main: bool, name: &str, lines: &[String]) -> Result<()> {
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let package_file = debian_dir.join(format!("{}.{}", package, name));
+    let file = match is_main && !package_file.exists() && debian_dir.join(name).exists() {
+        true => debian_dir.join(name),
+        false => package_file,
+    };
+    let mut content = fs::read_to_string(&file).unwrap_or_default();
+    let existing: Vec<String> = content.lines().map(|line| line.trim().to_string()).collect();
+    let missing: Vec<&String> = lines.iter().filter(|line| !existing.contains(line)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for line in missing {
+        info!("Adding {} to {}", line, file.display());
+        content.push_str(line);
+        content.push('\n');
+    }
+    fs::write(&file, content)?;
+    Ok(())
+}
+
+/// Adds install entries of the completions and man pages found in the source, repeated
+/// runs leave the install files unchanged.
+pub fn add_extras(build_files_dir: &Path, package_name: &str, config: &ExtrasConfig) -> Result<()> {
+    let package = config.package.as_deref().unwrap_or(package_name);
+    let is_main = package == package_name;
+    let debian_dir = build_files_dir.join("debian");
+
+    let mut install = vec![];
+    for file in find_files(build_files_dir, &get_patterns(&config.completions, DEFAULT_COMPLETIONS))? {
+        match get_completion_dir(&file) {
+            Some(dir) => install.push(format!("{} {}", file, dir)),
+            None => warn!("Skipping {}, not a bash, zsh or fish completion", file),
+        }
+    }
+    append_lines(&debian_dir, package, is_main, "install", &install)?;
+
+    let man_pages = find_files(build_files_dir, &get_patterns(&config.man_pages, DEFAULT_MAN_PAGES))?;
+    append_lines(&debian_dir, package, is_main, "manpages", &man_pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_add_extras() {
+        let source = tempdir().unwrap();
+        let root = source.path();
+        for file in [
+            "completions/geth.bash",
+            "completions/_geth",
+            "completions/geth.fish",
+            "completions/geth.ps1",
+            "docs/geth.1",
+            "docs/README.md",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::create_dir_all(root.join("debian")).unwrap();
+        fs::write(root.join("debian/install"), "build/bin/geth usr/bin").unwrap();
+
+        let config = ExtrasConfig::default();
+        add_extras(root, "geth", &config).unwrap();
+        add_extras(root, "geth", &config).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.join("debian/install")).unwrap(),
+            "build/bin/geth usr/bin\n\
+             completions/_geth usr/share/zsh/vendor-completions\n\
+             completions/geth.bash usr/share/bash-completion/completions\n\
+             completions/geth.fish usr/share/fish/vendor_completions.d\n"
+        );
+        assert_eq!(fs::read_to_string(root.join("debian/geth.manpages")).unwrap(), "docs/geth.1\n");
+
+        let config = ExtrasConfig {
+            package: Some("geth-tools".to_string()),
+            completions: vec!["completions/*.bash".to_string()],
+            man_pages: vec!["docs/*.md".to_string()],
+        };
+        add_extras(root, "geth", &config).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("debian/geth-tools.install")).unwrap(),
+            "completions/geth.bash usr/share/bash-completion/completions\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("debian/geth-tools.manpages")).unwrap(),
+            "docs/README.md\n"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// Fixture generators for hermetic tests, nothing in here touches the network.
+use crate::v1::apt_repo::AptRepo;
+use eyre::{eyre, Result};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Fixture command {:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+fn write_upstream_sources(source_dir: &Path, package_name: &str) -> Result<()> {
+    fs::create_dir_all(source_dir)?;
+    let mut makefile = fs::File::create(source_dir.join("Makefile"))?;
+    writeln!(makefile, "all:\n\techo {}", package_name)?;
+    fs::write(source_dir.join("README"), format!("{} fixture\n", package_name))?;
+    Ok(())
+}
+
+/// Creates `<package_name>-<version>.tar.gz` with a single top level directory,
+/// the same layout upstream release tarballs have.
+pub fn create_upstream_tarball(dir: &Path, package_name: &str, version: &str) -> Result<PathBuf> {
+    let top_level = format!("{}-{}", package_name, version);
+    write_upstream_sources(&dir.join(&top_level), package_name)?;
+    let tarball_path = dir.join(format!("{}.tar.gz", top_level));
+    run(Command::new("tar")
+        .current_dir(dir)
+        .arg("czf")
+        .arg(&tarball_path)
+        .arg(&top_level))?;
+    fs::remove_dir_all(dir.join(&top_level))?;
+    Ok(tarball_path)
+}
+
+/// Creates a git repository with one commit tagged with `tag`,
+/// returns a file:// url usable as git_url.
+pub fn create_upstream_git_repo(dir: &Path, package_name: &str, tag: &str) -> Result<String> {
+    let repo_dir = dir.join(format!("{}.git-fixture", package_name));
+    write_upstream_sources(&repo_dir, package_name)?;
+    git(&repo_dir, &["init", "-q"])?;
+    git(&repo_dir, &["add", "."])?;
+    git(&repo_dir, &["commit", "-q", "-m", "fixture"])?;
+    git(&repo_dir, &["tag", tag])?;
+    Ok(format!("file://{}", repo_dir.display()))
+}
+
+fn git(repo_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .current_dir(repo_dir)
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_CONFIG_SYSTEM", "/dev/null")
+        .args(["-c", "user.name=pkg-builder", "-c", "user.email=pkg-builder@localhost"])
+        .args(["-c", "protocol.file.allow=always"])
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Fixture command git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+static ALLOW_FILE_SUBMODULES: Once = Once::new();
+
+/// git refuses file:// submodules since 2.38.1, the clones of the tests inherit the
+/// override from the environment of the test process.
+pub fn allow_file_submodules() {
+    ALLOW_FILE_SUBMODULES.call_once(|| {
+        env::set_var("GIT_CONFIG_COUNT", "1");
+        env::set_var("GIT_CONFIG_KEY_0", "protocol.file.allow");
+        env::set_var("GIT_CONFIG_VALUE_0", "always");
+    });
+}
+
+/// Creates a git repository tagged with `tag` with a submodule at `submodule_path` that
+/// has two commits, the superproject records the second one. Returns the file:// url and
+/// the first commit of the submodule, to pin it through git submodules.
+pub fn create_upstream_git_repo_with_submodule(
+    dir: &Path,
+    package_name: &str,
+    tag: &str,
+    submodule_path: &str,
+) -> Result<(String, String)> {
+    let submodule_dir = dir.join(format!("{}-submodule.git-fixture", package_name));
+    write_upstream_sources(&submodule_dir, package_name)?;
+    git(&submodule_dir, &["init", "-q"])?;
+    git(&submodule_dir, &["add", "."])?;
+    git(&submodule_dir, &["commit", "-q", "-m", "first"])?;
+    let first_commit = git(&submodule_dir, &["rev-parse", "HEAD"])?;
+    fs::write(submodule_dir.join("CHANGELOG"), "second\n")?;
+    git(&submodule_dir, &["add", "."])?;
+    git(&submodule_dir, &["commit", "-q", "-m", "second"])?;
+
+    let repo_dir = dir.join(format!("{}.git-fixture", package_name));
+    write_upstream_sources(&repo_dir, package_name)?;
+    git(&repo_dir, &["init", "-q"])?;
+    let submodule_url = format!("file://{}", submodule_dir.display());
+    git(&repo_dir, &["submodule", "add", "-q", &submodule_url, submodule_path])?;
+    git(&repo_dir, &["add", "."])?;
+    git(&repo_dir, &["commit", "-q", "-m", "fixture"])?;
+    git(&repo_dir, &["tag", tag])?;
+    Ok((format!("file://{}", repo_dir.display()), first_commit))
+}
+
+/// Creates an APT mirror of the debs with the local repo generator, the indices of
+/// dists/<codename>/main are listed with their sha256 in the Release file, returns a
+/// file:// url usable as build_env.repo_url. Unsigned, apt needs [trusted=yes].
+pub fn create_local_apt_mirror(dir: &Path, codename: &str, arch: &str, debs: &[PathBuf]) -> Result<String> {
+    let mirror_dir = dir.join("mirror");
+    let repo = AptRepo::new(mirror_dir.clone(), "pkg-builder".to_string());
+    // the pool of the suite is scanned even without debs
+    fs::create_dir_all(mirror_dir.join("pool").join(codename))?;
+    for deb in debs {
+        let package_name = deb
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.split('_').next())
+            .ok_or_else(|| eyre!("Invalid deb path {}", deb.display()))?;
+        repo.add_package(codename, package_name, std::slice::from_ref(deb))?;
+    }
+    repo.update_suite(codename, &[arch.to_string()])?;
+    Ok(format!("file://{}", mirror_dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::apt_repo::tests::create_deb;
+    use crate::v1::build::dir_setup::{
+        calculate_sha256, create_package_dir, download_git, download_source, extract_source,
+        patch_source, verify_hash, BuildPaths,
+    };
+    use crate::v1::build::sbuild::Sbuild;
+    use crate::v1::pkg_config::{Arch, GitClient, GitPackageTypeConfig, PackageFields, PkgConfig};
+    use tempfile::tempdir;
+
+    fn package_fields() -> PackageFields {
+        PackageFields {
+            package_name: "hello-world".to_string(),
+            version_number: "1.0.0".to_string(),
+            revision_number: "1".to_string(),
+            ..PackageFields::default()
+        }
+    }
+
+    #[test]
+    fn test_default_package_pipeline_without_network() {
+        let upstream = tempdir().unwrap();
+        let workdir = tempdir().unwrap();
+        let tarball = create_upstream_tarball(upstream.path(), "hello-world", "1.0.0").unwrap();
+        let tarball_hash = calculate_sha256(fs::File::open(&tarball).unwrap()).unwrap();
+
+        let paths = BuildPaths::new(workdir.path(), &package_fields());
+        create_package_dir(&paths.artifacts_dir).unwrap();
+        download_source(
+            &paths.tarball_path,
+            tarball.file_name().unwrap().to_str().unwrap(),
+            upstream.path(),
+        )
+        .unwrap();
+        verify_hash(&paths.tarball_path, Some(tarball_hash)).unwrap();
+        extract_source(&paths.tarball_path, &paths.build_files_dir).unwrap();
+        assert!(paths.build_files_dir.join("Makefile").exists());
+
+        // debcrafter is not available in tests, provide the debian dir by hand
+        let debian_dir = paths.build_files_dir.join("debian");
+        fs::create_dir_all(&debian_dir).unwrap();
+        fs::write(debian_dir.join("control"), "Source: hello-world\nPriority: optional\n").unwrap();
+        fs::write(debian_dir.join("rules"), "#!/usr/bin/make -f\n").unwrap();
+        patch_source(
+            &paths.build_files_dir,
+            "https://example.com",
+            &upstream.path().join("src"),
+        )
+        .unwrap();
+        assert!(debian_dir.join("source/format").exists());
+    }
+
+    #[test]
+    fn test_git_package_pipeline_without_network() {
+        let upstream = tempdir().unwrap();
+        let workdir = tempdir().unwrap();
+        let git_url = create_upstream_git_repo(upstream.path(), "hello-world", "v1.0.0").unwrap();
+
+        let paths = BuildPaths::new(workdir.path(), &package_fields());
+        create_package_dir(&paths.artifacts_dir).unwrap();
+        download_git(
+            &paths.artifacts_dir,
+            &paths.tarball_path,
+            "hello-world",
+            &GitPackageTypeConfig {
+                git_url,
+                git_tag: "v1.0.0".to_string(),
+                git_client: Some(GitClient::Gitoxide),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        extract_source(&paths.tarball_path, &paths.build_files_dir).unwrap();
+        assert!(paths.build_files_dir.join("README").exists());
+        assert!(!paths.build_files_dir.join(".git").exists());
+    }
+
+    #[test]
+    fn test_local_apt_mirror_is_injected_as_repo_url() {
+        let dir = tempdir().unwrap();
+        let deb = create_deb(dir.path(), "hello-world", "1.0.0-1", "amd64");
+        let repo_url = create_local_apt_mirror(dir.path(), "bookworm", "amd64", &[deb]).unwrap();
+
+        let mut config = PkgConfig::default();
+        config.build_env.codename = "bookworm".to_string();
+        config.build_env.arch = Arch::Amd64;
+        config.build_env.repo_url = Some(repo_url);
+        let sbuild = Sbuild::new(config, dir.path().to_path_buf());
+
+        // apt of the host with its own state, pointed at the mirror the build would use
+        let apt_dir = dir.path().join("apt");
+        fs::create_dir_all(apt_dir.join("state/lists/partial")).unwrap();
+        fs::create_dir_all(apt_dir.join("cache/archives/partial")).unwrap();
+        let source_list = apt_dir.join("sources.list");
+        fs::write(
+            &source_list,
+            format!("deb [trusted=yes] {} bookworm main\n", sbuild.get_repo_url().unwrap()),
+        )
+        .unwrap();
+        let apt = |program: &str, args: &[&str]| {
+            Command::new(program)
+                .env("APT_CONFIG", "/dev/null")
+                .arg(format!("-oDir::State={}", apt_dir.join("state").display()))
+                .arg(format!("-oDir::Cache={}", apt_dir.join("cache").display()))
+                .arg(format!("-oDir::Etc::SourceList={}", source_list.display()))
+                .args(["-oDir::Etc::SourceParts=/dev/null", "-oAPT::Architecture=amd64", "-oDebug::NoLocking=1"])
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        let update = apt("apt-get", &["update"]);
+        assert!(update.status.success(), "{}", String::from_utf8_lossy(&update.stderr));
+        let show = apt("apt-cache", &["show", "hello-world"]);
+        assert!(show.status.success(), "{}", String::from_utf8_lossy(&show.stderr));
+        let stanza = String::from_utf8_lossy(&show.stdout);
+        assert!(stanza.contains("Version: 1.0.0-1"));
+        assert!(stanza.contains("Filename: pool/bookworm/hello-world/hello-world_1.0.0-1_amd64.deb"));
+    }
+}
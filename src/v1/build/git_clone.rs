@@ -0,0 +1,280 @@
+// Shallow clone of the git source with gitoxide in process, so a minimal container needs neither
+// git nor a git of the right version. Only what the source needs is done: the tag or commit is
+// fetched with depth 1 and checked out detached, every submodule is fetched the same way at its
+// pinned or recorded commit. Repositories using git-lfs are left to the git command line.
+use crate::v1::pkg_config::{GitClient, SubModule};
+use eyre::{eyre, Result};
+use gix::progress::Discard;
+use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit};
+use gix::refs::Target;
+use gix::remote::fetch::{Shallow, Tags};
+use gix::remote::Direction;
+use gix::{ObjectId, Repository};
+use log::{info, warn};
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+fn is_commit_id(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// any .gitattributes of the work tree, lfs attributes are often set per directory
+fn has_lfs_files(path: &Path) -> Result<bool> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && entry.file_name() != ".git" {
+            if has_lfs_files(&entry.path())? {
+                return Ok(true);
+            }
+        } else if file_type.is_file()
+            && entry.file_name() == ".gitattributes"
+            && fs::read_to_string(entry.path())?.contains("filter=lfs")
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// relative submodule urls, e.g. ../lib.git, are relative to the url of the parent
+fn resolve_submodule_url(parent_url: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+    let mut base = parent_url.trim_end_matches('/').to_string();
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            rest = stripped;
+            base.truncate(base.rfind('/').unwrap_or(0));
+        } else {
+            return format!("{}/{}", base, rest);
+        }
+    }
+}
+
+fn fetch(repo: &Repository, git_url: &str, reference: &str) -> Result<()> {
+    let refspecs = match is_commit_id(reference) {
+        true => vec![reference.to_string()],
+        false => vec![
+            format!("+refs/tags/{0}:refs/tags/{0}", reference),
+            format!("+refs/heads/{0}:refs/remotes/origin/{0}", reference),
+        ],
+    };
+    let fetch_error = |err: gix::Error| eyre!("Failed to fetch {} from {}: {}", reference, git_url, err);
+    let remote = repo
+        .remote_at(git_url)
+        .map_err(fetch_error)?
+        .with_refspecs(refspecs.iter().map(String::as_str), Direction::Fetch)
+        .map_err(fetch_error)?
+        .with_fetch_tags(Tags::None);
+    remote
+        .connect(Direction::Fetch)
+        .map_err(fetch_error)?
+        .prepare_fetch(Discard, Default::default())
+        .map_err(fetch_error)?
+        .with_shallow(Shallow::DepthAtRemote(NonZeroU32::MIN))
+        .receive(Discard, &AtomicBool::new(false))
+        .map_err(fetch_error)?;
+    Ok(())
+}
+
+fn find_commit(repo: &Repository, git_url: &str, reference: &str) -> Result<ObjectId> {
+    let id = [format!("refs/tags/{}", reference), format!("refs/remotes/origin/{}", reference)]
+        .iter()
+        .find_map(|name| repo.find_reference(name.as_str()).ok()?.peel_to_id().ok())
+        .or_else(|| repo.rev_parse_single(reference).ok())
+        .ok_or_else(|| eyre!("{} has no tag, branch or commit {}", git_url, reference))?;
+    Ok(id.object()?.peel_to_commit()?.id)
+}
+
+fn checkout(repo: &Repository, commit_id: ObjectId) -> Result<()> {
+    let workdir = repo.workdir().ok_or_else(|| eyre!("the clone has no work tree"))?;
+    let tree_id = repo.find_commit(commit_id)?.tree_id()?;
+    let mut index = repo.index_from_tree(&tree_id)?;
+    let mut options = repo.checkout_options(gix::worktree::stack::state::attributes::Source::IdMapping)?;
+    options.destination_is_initially_empty = true;
+    gix::worktree::state::checkout(
+        &mut index,
+        workdir,
+        repo.objects.clone().into_arc()?,
+        &Discard,
+        &Discard,
+        &AtomicBool::new(false),
+        options,
+    )?;
+    index.write(Default::default())?;
+    repo.edit_reference(RefEdit {
+        change: Change::Update {
+            log: LogChange::default(),
+            expected: PreviousValue::Any,
+            new: Target::Object(commit_id),
+        },
+        name: "HEAD".try_into()?,
+        deref: false,
+    })?;
+    Ok(())
+}
+
+/// Fetches reference, a tag, branch or commit id, with depth 1 and checks it out detached.
+pub fn shallow_clone(git_url: &str, reference: &str, path: &Path) -> Result<Repository> {
+    let mut repo = gix::init(path)?;
+    // the reflog of HEAD needs a committer, a minimal container has none configured
+    repo.committer_or_set_generic_fallback()?;
+    fetch(&repo, git_url, reference)?;
+    let commit_id = find_commit(&repo, git_url, reference)?;
+    checkout(&repo, commit_id)?;
+    Ok(repo)
+}
+
+// the submodules of repo at their pinned commits, the commit recorded by the parent otherwise,
+// pins apply to the top level only like with the git command line
+fn clone_submodules(repo: &Repository, git_url: &str, pinned: &[SubModule]) -> Result<()> {
+    let workdir = repo.workdir().ok_or_else(|| eyre!("the clone has no work tree"))?.to_path_buf();
+    let Some(submodules) = repo.submodules()? else {
+        return Ok(());
+    };
+    for submodule in submodules {
+        let sub_path = submodule.path()?.to_string();
+        let url = resolve_submodule_url(git_url, &submodule.url()?.to_bstring().to_string());
+        let commit = match pinned.iter().find(|submodule| submodule.path == sub_path) {
+            Some(submodule) => submodule.commit.clone(),
+            None => submodule
+                .index_id()?
+                .ok_or_else(|| eyre!("submodule {} records no commit", sub_path))?
+                .to_string(),
+        };
+        if !is_commit_id(&commit) {
+            return Err(eyre!("submodule {} is pinned to {}, the full commit id is needed", sub_path, commit));
+        }
+        info!("Cloning submodule {} at {}", sub_path, commit);
+        let sub_dir = workdir.join(&sub_path);
+        fs::create_dir_all(&sub_dir)?;
+        let sub_repo = shallow_clone(&url, &commit, &sub_dir)?;
+        if has_lfs_files(&sub_dir)? {
+            return Err(eyre!("submodule {} uses git-lfs", sub_path));
+        }
+        clone_submodules(&sub_repo, &url, &[])?;
+    }
+    Ok(())
+}
+
+/// Clone of the tag with its submodules by gitoxide.
+pub fn clone_with_library(git_url: &str, tag: &str, path: &Path, submodules: &[SubModule]) -> Result<()> {
+    info!("Cloning {} at {} with gitoxide", git_url, tag);
+    let repo = shallow_clone(git_url, tag, path)?;
+    if has_lfs_files(path)? {
+        return Err(eyre!("{} uses git-lfs", git_url));
+    }
+    clone_submodules(&repo, git_url, submodules)
+}
+
+/// Clones with the configured client, auto falls back to the git command line when gitoxide
+/// cannot handle the repository.
+pub fn clone_source<F>(client: GitClient, git_url: &str, tag: &str, path: &Path, submodules: &[SubModule], clone_with_cli: F) -> Result<()>
+where
+    F: Fn() -> Result<()>,
+{
+    match client {
+        GitClient::Cli => clone_with_cli(),
+        GitClient::Gitoxide => clone_with_library(git_url, tag, path, submodules),
+        GitClient::Auto => {
+            let Err(err) = clone_with_library(git_url, tag, path, submodules) else {
+                return Ok(());
+            };
+            warn!("gitoxide could not clone {}, using the git command line: {}", git_url, err);
+            // git clone needs an empty dir
+            fs::remove_dir_all(path)?;
+            fs::create_dir_all(path)?;
+            clone_with_cli()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=John Doe", "-c", "user.email=johndoe@example.com", "-c", "protocol.file.allow=always"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[test]
+    fn test_library_clone_checks_out_pinned_submodules() {
+        let dir = tempdir().unwrap();
+        let lib_dir = dir.path().join("lib");
+        fs::create_dir_all(&lib_dir).unwrap();
+        git(&lib_dir, &["init", "-q"]);
+        fs::write(lib_dir.join("lib.txt"), "1").unwrap();
+        git(&lib_dir, &["add", "."]);
+        git(&lib_dir, &["commit", "-q", "-m", "first"]);
+        let first = git(&lib_dir, &["rev-parse", "HEAD"]);
+        fs::write(lib_dir.join("lib.txt"), "2").unwrap();
+        git(&lib_dir, &["commit", "-q", "-am", "second"]);
+
+        let app_dir = dir.path().join("app");
+        fs::create_dir_all(&app_dir).unwrap();
+        git(&app_dir, &["init", "-q"]);
+        fs::write(app_dir.join("app.txt"), "app").unwrap();
+        git(&app_dir, &["submodule", "add", "-q", lib_dir.to_str().unwrap(), "vendor/lib"]);
+        git(&app_dir, &["add", "."]);
+        git(&app_dir, &["commit", "-q", "-m", "app"]);
+        git(&app_dir, &["tag", "v1.0.0"]);
+
+        let app_url = format!("file://{}", app_dir.display());
+        let clone_dir = dir.path().join("clone");
+        let pinned = [SubModule {
+            path: "vendor/lib".to_string(),
+            commit: first.clone(),
+        }];
+        let cli_used = || Err(eyre!("the git command line was used"));
+        clone_source(GitClient::Auto, &app_url, "v1.0.0", &clone_dir, &pinned, cli_used).unwrap();
+        assert_eq!(fs::read_to_string(clone_dir.join("app.txt")).unwrap(), "app");
+        assert_eq!(fs::read_to_string(clone_dir.join("vendor/lib/lib.txt")).unwrap(), "1");
+
+        // without a pin the submodule is at the commit recorded by the tag
+        let clone_dir = dir.path().join("unpinned");
+        clone_source(GitClient::Gitoxide, &app_url, "v1.0.0", &clone_dir, &[], cli_used).unwrap();
+        assert_eq!(fs::read_to_string(clone_dir.join("vendor/lib/lib.txt")).unwrap(), "2");
+
+        let clone_dir = dir.path().join("missing");
+        let err = clone_source(GitClient::Gitoxide, &app_url, "v2.0.0", &clone_dir, &[], cli_used).unwrap_err();
+        assert!(err.to_string().contains("Failed to fetch v2.0.0 from"), "{}", err);
+        let err = clone_source(GitClient::Auto, &app_url, "v2.0.0", &clone_dir, &[], cli_used).unwrap_err();
+        assert_eq!(err.to_string(), "the git command line was used");
+    }
+
+    #[test]
+    fn test_has_lfs_files_reads_every_gitattributes() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/.gitattributes"), "*.bin filter=lfs").unwrap();
+        fs::write(dir.path().join(".gitattributes"), "*.sh text eol=lf").unwrap();
+        assert!(!has_lfs_files(dir.path()).unwrap());
+        fs::create_dir_all(dir.path().join("assets/images")).unwrap();
+        fs::write(dir.path().join("assets/images/.gitattributes"), "*.png filter=lfs diff=lfs").unwrap();
+        assert!(has_lfs_files(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn test_resolve_submodule_url() {
+        let parent = "https://github.com/sigp/lighthouse.git";
+        assert_eq!(resolve_submodule_url(parent, "../blst.git"), "https://github.com/sigp/blst.git");
+        assert_eq!(resolve_submodule_url(parent, "./vendor"), "https://github.com/sigp/lighthouse.git/vendor");
+        assert_eq!(resolve_submodule_url(parent, "https://github.com/supranational/blst"), "https://github.com/supranational/blst");
+    }
+}
@@ -0,0 +1,238 @@
+// Fetches the tarball of a github release: the release is resolved by the tag derived from
+// version_number, the asset is picked by a glob, so recipes do not depend on the exact naming.
+// A checksum asset is verified when the release has one, a detached signature when a trust
+// key of source github_release is declared.
+use crate::v1::build::dir_setup::{calculate_sha256, download_source, open_for_hashing};
+use crate::v1::build::trust::verify_detached_signature;
+use crate::v1::pkg_config::{GithubReleaseConfig, TrustedKey};
+use crate::v1::secrets::get_secret;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+// raises the rate limit of the api, the assets themselves are public
+const GITHUB_TOKEN_SECRET: &str = "github-token";
+const DEFAULT_CHECKSUM_ASSETS: &[&str] = &["{asset}.sha256", "{asset}.sha256sum", "SHA256SUMS", "sha256sums.txt", "checksums.txt"];
+const DEFAULT_SIGNATURE_ASSETS: &[&str] = &["{asset}.asc", "{asset}.sig"];
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+pub fn fetch_release(api_url: &str, repo: &str, tag: &str) -> Result<Release> {
+    let url = format!("{}/repos/{}/releases/tags/{}", api_url, repo, tag);
+    info!("Resolving release {} of {}", tag, repo);
+    // the token is passed in a wgetrc, so it does not show up in the process list
+    let request_dir = tempfile::tempdir()?;
+    let wgetrc_path = request_dir.path().join("wgetrc");
+    let mut wgetrc = "header = Accept: application/vnd.github+json\n".to_string();
+    if let Ok(token) = get_secret(GITHUB_TOKEN_SECRET) {
+        wgetrc.push_str(&format!("header = Authorization: Bearer {}\n", token));
+    }
+    fs::write(&wgetrc_path, wgetrc)?;
+    let output = Command::new("wget")
+        .arg(format!("--config={}", wgetrc_path.display()))
+        .args(["-q", "-O", "-", &url])
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!("Could not resolve release {} of {}, does the tag exist?", tag, repo));
+    }
+    serde_json::from_slice(&output.stdout).map_err(|err| eyre!("Invalid release {} of {}: {}", tag, repo, err))
+}
+
+/// The assets matching the globs, the first glob with a match wins.
+fn find_assets<'a>(release: &'a Release, patterns: &[String]) -> Result<Vec<&'a ReleaseAsset>> {
+    for pattern in patterns {
+        let pattern = glob::Pattern::new(pattern).map_err(|err| eyre!("github_release: invalid glob {}: {}", pattern, err))?;
+        let assets: Vec<&ReleaseAsset> = release.assets.iter().filter(|asset| pattern.matches(&asset.name)).collect();
+        if !assets.is_empty() {
+            return Ok(assets);
+        }
+    }
+    Ok(vec![])
+}
+
+pub fn select_asset<'a>(release: &'a Release, pattern: &str) -> Result<&'a ReleaseAsset> {
+    let names = || release.assets.iter().map(|asset| asset.name.as_str()).collect::<Vec<_>>().join(", ");
+    match find_assets(release, &[pattern.to_string()])?.as_slice() {
+        [asset] => Ok(asset),
+        [] => Err(eyre!("No asset of release {} matches {}, the assets are: {}", release.tag_name, pattern, names())),
+        assets => Err(eyre!(
+            "{} assets of release {} match {}: {}",
+            assets.len(),
+            release.tag_name,
+            pattern,
+            assets.iter().map(|asset| asset.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+fn get_patterns(configured: &Option<String>, defaults: &[&str], asset_name: &str) -> Vec<String> {
+    match configured {
+        Some(pattern) => vec![pattern.clone()],
+        None => defaults.iter().map(|pattern| pattern.replace("{asset}", &glob::Pattern::escape(asset_name))).collect(),
+    }
+}
+
+/// sha256 of the asset in a checksum file, either "<hash>  <name>" lines or a lone hash.
+pub fn parse_checksum(content: &str, asset_name: &str) -> Option<String> {
+    let lines: Vec<&str> = content.lines().filter(|line| !line.trim().is_empty()).collect();
+    for line in lines.iter() {
+        let mut fields = line.split_whitespace();
+        let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        // sha256sum marks binary mode with *
+        if name.trim_start_matches('*') == asset_name {
+            return Some(hash.to_lowercase());
+        }
+    }
+    match lines.as_slice() {
+        [line] if line.trim().len() == 64 && !line.trim().contains(' ') => Some(line.trim().to_lowercase()),
+        _ => None,
+    }
+}
+
+fn verify_checksum(release: &Release, config: &GithubReleaseConfig, asset: &ReleaseAsset, tarball_path: &Path) -> Result<()> {
+    let patterns = get_patterns(&config.checksum_asset, DEFAULT_CHECKSUM_ASSETS, &asset.name);
+    let Some(checksum_asset) = find_assets(release, &patterns)?.into_iter().next() else {
+        if config.checksum_asset.is_some() {
+            return Err(eyre!("No checksum asset of release {} matches {}", release.tag_name, patterns[0]));
+        }
+        warn!("Release {} has no checksum asset, set tarball_hash to pin {}", release.tag_name, asset.name);
+        return Ok(());
+    };
+    let checksum_dir = tempfile::tempdir()?;
+    let checksum_path = checksum_dir.path().join(&checksum_asset.name);
+    download_source(&checksum_path, &checksum_asset.browser_download_url, Path::new("."))?;
+    let expected = parse_checksum(&fs::read_to_string(&checksum_path)?, &asset.name)
+        .ok_or_else(|| eyre!("{} has no sha256 checksum of {}", checksum_asset.name, asset.name))?;
+    let actual = calculate_sha256(open_for_hashing(tarball_path)?)?;
+    if actual != expected {
+        return Err(eyre!(
+            "sha256 of {} is {}, but {} lists {}",
+            asset.name,
+            actual,
+            checksum_asset.name,
+            expected
+        ));
+    }
+    info!("Verified {} against {}", asset.name, checksum_asset.name);
+    Ok(())
+}
+
+fn verify_signature(
+    release: &Release,
+    config: &GithubReleaseConfig,
+    asset: &ReleaseAsset,
+    tarball_path: &Path,
+    key: Option<&TrustedKey>,
+) -> Result<()> {
+    let patterns = get_patterns(&config.signature_asset, DEFAULT_SIGNATURE_ASSETS, &asset.name);
+    let signature_asset = find_assets(release, &patterns)?.into_iter().next();
+    let (signature_asset, key) = match (signature_asset, key) {
+        (Some(signature_asset), Some(key)) => (signature_asset, key),
+        (None, Some(_)) => {
+            return Err(eyre!("A github_release trust key is declared, but release {} has no signature of {}", release.tag_name, asset.name))
+        }
+        (Some(signature_asset), None) => {
+            warn!("{} is not verified, declare a trust key of source github_release", signature_asset.name);
+            return Ok(());
+        }
+        (None, None) => return Ok(()),
+    };
+    let signature_dir = tempfile::tempdir()?;
+    let signature_path = signature_dir.path().join(&signature_asset.name);
+    download_source(&signature_path, &signature_asset.browser_download_url, Path::new("."))?;
+    let fingerprint = verify_detached_signature(key, &signature_path, tarball_path)?;
+    info!("{} is signed by {}", asset.name, fingerprint);
+    Ok(())
+}
+
+/// Downloads the asset of the release to tarball_path and verifies it.
+pub fn download_release_asset(
+    tarball_path: &Path,
+    config: &GithubReleaseConfig,
+    version_number: &str,
+    key: Option<&TrustedKey>,
+) -> Result<()> {
+    download_release_asset_from(GITHUB_API_URL, tarball_path, config, version_number, key)
+}
+
+pub fn download_release_asset_from(
+    api_url: &str,
+    tarball_path: &Path,
+    config: &GithubReleaseConfig,
+    version_number: &str,
+    key: Option<&TrustedKey>,
+) -> Result<()> {
+    let release = fetch_release(api_url, &config.repo, &config.get_tag(version_number))?;
+    let asset = select_asset(&release, &config.get_asset_pattern(version_number))?;
+    download_source(tarball_path, &asset.browser_download_url, Path::new("."))?;
+    verify_checksum(&release, config, asset, tarball_path)?;
+    verify_signature(&release, config, asset, tarball_path, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::prelude::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_download_release_asset() {
+        let server = MockServer::start();
+        let tarball = b"lodestar tarball";
+        let hash = calculate_sha256(&tarball[..]).unwrap();
+        let release = serde_json::json!({
+            "tag_name": "v1.20.0",
+            "assets": [
+                { "name": "lodestar-v1.20.0-linux-amd64.tar.gz", "browser_download_url": server.url("/amd64.tar.gz") },
+                { "name": "lodestar-v1.20.0-linux-arm64.tar.gz", "browser_download_url": server.url("/arm64.tar.gz") },
+                { "name": "SHA256SUMS", "browser_download_url": server.url("/SHA256SUMS") },
+            ],
+        });
+        server.mock(|when, then| {
+            when.path("/repos/ChainSafe/lodestar/releases/tags/v1.20.0");
+            then.status(200).json_body(release);
+        });
+        server.mock(|when, then| {
+            when.path("/amd64.tar.gz");
+            then.status(200).body(tarball);
+        });
+        server.mock(|when, then| {
+            when.path("/SHA256SUMS");
+            then.status(200)
+                .body(format!("{}  lodestar-v1.20.0-linux-amd64.tar.gz\n{}  lodestar-v1.20.0-linux-arm64.tar.gz\n", hash, "0".repeat(64)));
+        });
+
+        let dir = tempdir().unwrap();
+        let tarball_path = dir.path().join("lodestar_1.20.0.orig.tar.gz");
+        let mut config = GithubReleaseConfig {
+            repo: "ChainSafe/lodestar".to_string(),
+            asset: "lodestar-{tag}-linux-amd64*".to_string(),
+            ..Default::default()
+        };
+        download_release_asset_from(&server.base_url(), &tarball_path, &config, "1.20.0", None).unwrap();
+        assert_eq!(fs::read(&tarball_path).unwrap(), tarball);
+
+        config.asset = "lodestar-{tag}-linux-*".to_string();
+        let error = download_release_asset_from(&server.base_url(), &tarball_path, &config, "1.20.0", None).unwrap_err();
+        assert!(error.to_string().starts_with("2 assets of release v1.20.0 match lodestar-v1.20.0-linux-*"));
+
+        assert_eq!(parse_checksum(&format!("{}\n", hash), "any.tar.gz"), Some(hash.clone()));
+        assert_eq!(parse_checksum(&format!("{} *geth.tar.gz\n", hash), "geth.tar.gz"), Some(hash));
+    }
+}
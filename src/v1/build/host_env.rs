@@ -0,0 +1,245 @@
+// What the host can run, detected once per process. Inside docker or kubernetes the default
+// seccomp and AppArmor profiles block unprivileged user namespaces, so the unshare chroots of
+// sbuild fail with a bare "Operation not permitted". When the backend is left to pkg-builder
+// the docker backend is taken instead, otherwise the build fails up front saying what to enable.
+use crate::v1::build::testbed::get_testbed;
+use crate::v1::pkg_config::{AutopkgtestTestbed, Backend, BuildEnv};
+use crate::v1::tools::find_program;
+use eyre::{eyre, Result};
+use log::warn;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+static HOST_ENV: OnceLock<HostEnv> = OnceLock::new();
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct HostEnv {
+    /// container pkg-builder runs in, e.g. docker or kubernetes, None outside of one
+    pub container: Option<String>,
+    /// why unshare chroots cannot be created and what to enable, None when they can
+    pub user_namespaces_blocked: Option<String>,
+    /// /dev/kvm can be opened, the qemu testbed runs emulated without it
+    pub kvm: bool,
+    /// usable container engines, docker only with a reachable daemon
+    pub engines: Vec<&'static str>,
+}
+
+/// The container runtime, from the marker files the runtimes leave, the container variable of
+/// systemd-nspawn, lxc and podman, and the cgroup of pid 1.
+pub fn get_container(dockerenv: bool, containerenv: bool, container_var: Option<&str>, cgroup: &str) -> Option<String> {
+    if cgroup.contains("kubepods") {
+        return Some("kubernetes".to_string());
+    }
+    if let Some(container) = container_var.filter(|container| !container.is_empty()) {
+        return Some(container.to_string());
+    }
+    if dockerenv || cgroup.contains("/docker") {
+        return Some("docker".to_string());
+    }
+    if containerenv {
+        return Some("podman".to_string());
+    }
+    cgroup.contains("/lxc").then(|| "lxc".to_string())
+}
+
+/// What to enable for unprivileged user namespaces, read_sysctl reads /proc/sys/<name>.
+pub fn get_user_namespace_hint(container: Option<&str>, read_sysctl: impl Fn(&str) -> Option<u64>) -> String {
+    if read_sysctl("user/max_user_namespaces") == Some(0) {
+        return "user.max_user_namespaces is 0, raise it with sudo sysctl -w user.max_user_namespaces=15000".to_string();
+    }
+    if read_sysctl("kernel/unprivileged_userns_clone") == Some(0) {
+        return "they are disabled, enable them with sudo sysctl -w kernel.unprivileged_userns_clone=1".to_string();
+    }
+    if read_sysctl("kernel/apparmor_restrict_unprivileged_userns") == Some(1) {
+        return "AppArmor restricts them, allow them with sudo sysctl -w kernel.apparmor_restrict_unprivileged_userns=0".to_string();
+    }
+    match container {
+        Some("kubernetes") => {
+            "the pod blocks them, run it with securityContext.privileged: true or a seccomp profile allowing unshare".to_string()
+        }
+        Some(container) => format!(
+            "{} blocks them, start the container with --security-opt seccomp=unconfined --security-opt apparmor=unconfined, or --privileged",
+            container
+        ),
+        None => "unshare --user --map-root-user failed".to_string(),
+    }
+}
+
+fn read_sysctl(name: &str) -> Option<u64> {
+    fs::read_to_string(Path::new("/proc/sys").join(name)).ok()?.trim().parse().ok()
+}
+
+fn can_create_user_namespace() -> bool {
+    Command::new("unshare")
+        .args(["--user", "--map-root-user", "true"])
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+impl HostEnv {
+    pub fn detect() -> Self {
+        let container = get_container(
+            Path::new("/.dockerenv").exists(),
+            Path::new("/run/.containerenv").exists(),
+            env::var("container").ok().as_deref(),
+            &fs::read_to_string("/proc/1/cgroup").unwrap_or_default(),
+        );
+        let user_namespaces_blocked = match (find_program("unshare"), can_create_user_namespace()) {
+            (None, _) => Some("unshare is not installed, install util-linux".to_string()),
+            (Some(_), true) => None,
+            (Some(_), false) => Some(get_user_namespace_hint(container.as_deref(), read_sysctl)),
+        };
+        let mut engines = vec![];
+        let docker_daemon = env::var_os("DOCKER_HOST").is_some() || Path::new("/var/run/docker.sock").exists();
+        if find_program("docker").is_some() && docker_daemon {
+            engines.push("docker");
+        }
+        if find_program("podman").is_some() {
+            engines.push("podman");
+        }
+        HostEnv {
+            container,
+            user_namespaces_blocked,
+            kvm: fs::OpenOptions::new().read(true).write(true).open("/dev/kvm").is_ok(),
+            engines,
+        }
+    }
+
+    /// Detected on first use.
+    pub fn get() -> &'static HostEnv {
+        HOST_ENV.get_or_init(HostEnv::detect)
+    }
+
+    fn get_name(&self) -> String {
+        match &self.container {
+            Some(container) => format!("this {} container", container),
+            None => "this host".to_string(),
+        }
+    }
+
+    fn get_location(&self) -> String {
+        match &self.container {
+            Some(_) => format!("in {}", self.get_name()),
+            None => format!("on {}", self.get_name()),
+        }
+    }
+
+    /// Picks the backend and testbed the host can run where the config leaves them open.
+    pub fn adapt_build_env(&self, build_env: &mut BuildEnv) {
+        if let (None, Some(blocked)) = (build_env.backend, &self.user_namespaces_blocked) {
+            if self.engines.contains(&"docker") && build_env.builds_for_host() {
+                warn!(
+                    "unshare chroots cannot be created {}, {}. Building with the docker backend instead.",
+                    self.get_location(),
+                    blocked
+                );
+                build_env.backend = Some(Backend::Docker);
+            }
+        }
+        let uses_qemu = get_testbed(build_env.autopkgtest_testbed, build_env.arch.get_build_arch()) == AutopkgtestTestbed::Qemu;
+        if build_env.run_autopkgtest == Some(true) && build_env.autopkgtest_testbed.is_none() && uses_qemu && !self.kvm {
+            // rootless podman needs user namespaces as well
+            if self.user_namespaces_blocked.is_none() && self.engines.contains(&"podman") {
+                warn!("/dev/kvm is not available {}, running autopkgtest in a podman container instead of a qemu VM.", self.get_location());
+                build_env.autopkgtest_testbed = Some(AutopkgtestTestbed::Podman);
+            } else {
+                warn!("/dev/kvm is not available {}, the qemu autopkgtest testbed runs emulated and slow.", self.get_location());
+            }
+        }
+    }
+
+    /// Fails when the build env needs unshare chroots the host cannot create.
+    pub fn check_build_env(&self, build_env: &BuildEnv) -> Result<()> {
+        let backend = build_env.backend.unwrap_or(Backend::Sbuild);
+        match (&self.user_namespaces_blocked, backend) {
+            (Some(blocked), Backend::Sbuild) if !build_env.builds_for_host() => Err(eyre!(
+                "sbuild needs unprivileged user namespaces for its unshare chroot to build for {}, but {}: {}. The docker backend builds for the host architecture only, enable user namespaces {}.",
+                build_env.arch.get_build_arch(),
+                self.get_name(),
+                blocked,
+                self.get_location()
+            )),
+            (Some(blocked), Backend::Sbuild) => Err(eyre!(
+                "sbuild needs unprivileged user namespaces for its unshare chroot, but {}: {}. Or set build_env.backend = \"docker\" with access to a docker daemon.",
+                self.get_name(),
+                blocked
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Findings of doctor, build_env is the adapted one.
+    pub fn format_report(&self, build_env: &BuildEnv) -> String {
+        let mut report = format!("container: {}\n", self.container.as_deref().unwrap_or("none"));
+        report.push_str(&format!(
+            "user namespaces: {}\n",
+            self.user_namespaces_blocked.as_deref().map_or("ok".to_string(), |blocked| format!("blocked, {}", blocked))
+        ));
+        report.push_str(&format!("kvm: {}\n", if self.kvm { "ok" } else { "not available" }));
+        report.push_str(&format!(
+            "container engines: {}\n",
+            if self.engines.is_empty() { "none".to_string() } else { self.engines.join(", ") }
+        ));
+        report.push_str(&format!("backend: {}\n", build_env.backend.unwrap_or(Backend::Sbuild).as_str()));
+        let testbed = get_testbed(build_env.autopkgtest_testbed, build_env.arch.get_build_arch());
+        report.push_str(&format!(
+            "autopkgtest testbed: {}\n",
+            if testbed == AutopkgtestTestbed::Qemu { "qemu" } else { "podman" }
+        ));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_user_namespaces_select_docker() {
+        assert_eq!(get_container(true, false, None, "0::/\n"), Some("docker".to_string()));
+        assert_eq!(get_container(false, true, Some("podman"), ""), Some("podman".to_string()));
+        assert_eq!(get_container(true, false, None, "0::/kubepods/besteffort/pod1\n"), Some("kubernetes".to_string()));
+        assert_eq!(get_container(false, false, None, "0::/init.scope\n"), None);
+
+        let hint = get_user_namespace_hint(Some("docker"), |_| None);
+        assert!(hint.starts_with("docker blocks them, start the container with --security-opt seccomp=unconfined"));
+        let hint = get_user_namespace_hint(None, |name| (name == "kernel/apparmor_restrict_unprivileged_userns").then_some(1));
+        assert!(hint.starts_with("AppArmor restricts them"));
+
+        let host = HostEnv {
+            container: Some("docker".to_string()),
+            user_namespaces_blocked: Some(hint),
+            kvm: false,
+            engines: vec!["docker"],
+        };
+        let mut build_env = BuildEnv::default();
+        let err = host.check_build_env(&build_env).unwrap_err().to_string();
+        assert!(err.starts_with("sbuild needs unprivileged user namespaces for its unshare chroot, but this docker container: AppArmor"));
+        host.adapt_build_env(&mut build_env);
+        assert_eq!(build_env.backend, Some(Backend::Docker));
+        assert!(host.check_build_env(&build_env).is_ok());
+        assert!(host.format_report(&build_env).contains("backend: docker\n"));
+
+        // a configured backend is kept
+        let mut build_env = BuildEnv {
+            backend: Some(Backend::Sbuild),
+            ..Default::default()
+        };
+        host.adapt_build_env(&mut build_env);
+        assert_eq!(build_env.backend, Some(Backend::Sbuild));
+        assert!(host.check_build_env(&build_env).is_err());
+
+        // docker builds for the host arch only
+        let mut build_env = BuildEnv {
+            cross_build: Some(true),
+            ..Default::default()
+        };
+        host.adapt_build_env(&mut build_env);
+        assert_eq!(build_env.backend, None);
+        let err = host.check_build_env(&build_env).unwrap_err().to_string();
+        assert!(err.contains("The docker backend builds for the host architecture only, enable user namespaces in this docker container."));
+    }
+}
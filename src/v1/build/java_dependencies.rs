@@ -0,0 +1,150 @@
+// Resolves java dependencies on the host and vendors them into the source tree,
+// so the build inside sbuild never has to reach Maven Central.
+use crate::v1::build::dir_setup::add_include_binaries;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// relative to the source root, where debian/rules runs gradle and mvn
+pub const GRADLE_USER_HOME: &str = "debian/gradle-home";
+pub const MAVEN_REPO: &str = "debian/maven-repo";
+
+const GRADLE_OFFLINE_INIT_SCRIPT: &str = "\
+// written by pkg-builder, dependencies were resolved and verified on the host
+gradle.startParameter.offline = true
+gradle.startParameter.dependencyVerificationMode =
+    org.gradle.api.artifacts.verification.DependencyVerificationMode.STRICT
+";
+
+fn run_host_command(cmd: &mut Command) -> Result<()> {
+    info!("Running {:?}", cmd);
+    let status = cmd
+        .status()
+        .map_err(|err| eyre!("Failed to run {:?}: {}", cmd, err))?;
+    if !status.success() {
+        return Err(eyre!("{:?} failed with {}", cmd, status));
+    }
+    Ok(())
+}
+
+pub fn write_gradle_offline_init_script(build_files_dir: &Path) -> Result<()> {
+    let init_dir = build_files_dir.join(GRADLE_USER_HOME).join("init.d");
+    fs::create_dir_all(&init_dir)?;
+    fs::write(init_dir.join("offline.gradle"), GRADLE_OFFLINE_INIT_SCRIPT)?;
+    Ok(())
+}
+
+// .mvn/maven.config is picked up by every mvn invocation in the project
+pub fn write_maven_offline_config(build_files_dir: &Path) -> Result<()> {
+    let mvn_dir = build_files_dir.join(".mvn");
+    fs::create_dir_all(&mvn_dir)?;
+    fs::write(
+        mvn_dir.join("maven.config"),
+        format!("--offline\n--strict-checksums\n-Dmaven.repo.local={}\n", MAVEN_REPO),
+    )?;
+    Ok(())
+}
+
+/// Resolves the gradle dependency graph into debian/gradle-home, records the
+/// checksums in gradle/verification-metadata.xml and verifies them offline.
+pub fn provision_gradle_dependencies(build_files_dir: &Path) -> Result<()> {
+    let gradle = if build_files_dir.join("gradlew").exists() {
+        "./gradlew"
+    } else {
+        "gradle"
+    };
+    let gradle_user_home = build_files_dir.join(GRADLE_USER_HOME);
+    run_host_command(
+        Command::new(gradle)
+            .current_dir(build_files_dir)
+            .arg("--gradle-user-home")
+            .arg(&gradle_user_home)
+            .args(["--write-verification-metadata", "sha256", "dependencies"]),
+    )?;
+    run_host_command(
+        Command::new(gradle)
+            .current_dir(build_files_dir)
+            .arg("--gradle-user-home")
+            .arg(&gradle_user_home)
+            .args(["--offline", "--dependency-verification", "strict", "dependencies"]),
+    )?;
+    write_gradle_offline_init_script(build_files_dir)?;
+    add_include_binaries(build_files_dir, GRADLE_USER_HOME)?;
+    info!("Gradle dependencies provisioned into {}", gradle_user_home.display());
+    Ok(())
+}
+
+/// Resolves the maven dependency graph into debian/maven-repo, failing on checksum mismatches.
+pub fn provision_maven_dependencies(build_files_dir: &Path) -> Result<()> {
+    let maven_repo = build_files_dir.join(MAVEN_REPO);
+    run_host_command(
+        Command::new("mvn")
+            .current_dir(build_files_dir)
+            .arg("--batch-mode")
+            .arg("--strict-checksums")
+            .arg(format!("-Dmaven.repo.local={}", maven_repo.display()))
+            .arg("dependency:go-offline"),
+    )?;
+    write_maven_offline_config(build_files_dir)?;
+    add_include_binaries(build_files_dir, MAVEN_REPO)?;
+    info!("Maven dependencies provisioned into {}", maven_repo.display());
+    Ok(())
+}
+
+/// Paths provision_java_dependencies writes into the source tree, the vendored dir first.
+pub fn get_vendored_java_paths(uses_gradle: bool) -> Vec<&'static str> {
+    match uses_gradle {
+        true => vec![GRADLE_USER_HOME, "gradle/verification-metadata.xml"],
+        false => vec![MAVEN_REPO, ".mvn/maven.config"],
+    }
+}
+
+pub fn provision_java_dependencies(build_files_dir: &Path, uses_gradle: bool) -> Result<()> {
+    if uses_gradle {
+        provision_gradle_dependencies(build_files_dir)
+    } else if build_files_dir.join("pom.xml").exists() {
+        provision_maven_dependencies(build_files_dir)
+    } else {
+        Err(eyre!(
+            "offline_dependencies is set, but neither gradle is configured nor {} exists",
+            build_files_dir.join("pom.xml").display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_offline_configuration_is_written_into_source_tree() {
+        let build_files_dir = tempdir().unwrap();
+        write_gradle_offline_init_script(build_files_dir.path()).unwrap();
+        write_maven_offline_config(build_files_dir.path()).unwrap();
+
+        let init_script = fs::read_to_string(
+            build_files_dir
+                .path()
+                .join("debian/gradle-home/init.d/offline.gradle"),
+        )
+        .unwrap();
+        assert!(init_script.contains("gradle.startParameter.offline = true"));
+        let maven_config =
+            fs::read_to_string(build_files_dir.path().join(".mvn/maven.config")).unwrap();
+        assert!(maven_config.contains("--offline"));
+        assert!(maven_config.contains("-Dmaven.repo.local=debian/maven-repo"));
+    }
+
+    #[test]
+    fn test_provision_java_dependencies_without_build_system() {
+        let build_files_dir = tempdir().unwrap();
+        let result = provision_java_dependencies(build_files_dir.path(), false);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("offline_dependencies is set, but neither gradle is configured"));
+    }
+}
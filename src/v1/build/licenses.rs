@@ -0,0 +1,312 @@
+// Scans the extracted source for licenses before the build: the texts of the license files
+// and the SPDX-License-Identifier tags of the other files. The detected licenses are compared
+// with the Files stanzas of debian/copyright, an undeclared GPL or AGPL fails the build.
+use crate::v1::build::quality::{FindingLevel, LicenseFinding, LicenseScan, LicenseSummary};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+// bytes read of a license file, of other files only the head is searched for the tag
+const LICENSE_FILE_BYTES: u64 = 64 * 1024;
+const HEADER_BYTES: u64 = 4 * 1024;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DetectedLicense {
+    /// relative to the source root
+    pub path: String,
+    /// SPDX identifier
+    pub license: String,
+}
+
+fn is_license_file(name: &str) -> bool {
+    let name = name.to_uppercase();
+    ["LICENSE", "LICENCE", "COPYING", "UNLICENSE"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// SPDX identifier of a license text, from phrases the texts do not share.
+pub fn detect_license_text(text: &str) -> Option<&'static str> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let has = |phrase: &str| text.contains(phrase);
+    let license = if has("GNU AFFERO GENERAL PUBLIC LICENSE") {
+        "AGPL-3.0"
+    } else if has("GNU LESSER GENERAL PUBLIC LICENSE") {
+        if has("Version 3") { "LGPL-3.0" } else { "LGPL-2.1" }
+    } else if has("GNU GENERAL PUBLIC LICENSE") {
+        if has("Version 3") { "GPL-3.0" } else { "GPL-2.0" }
+    } else if has("Apache License") && has("Version 2.0") {
+        "Apache-2.0"
+    } else if has("Mozilla Public License Version 2.0") {
+        "MPL-2.0"
+    } else if has("Permission is hereby granted, free of charge") {
+        "MIT"
+    } else if has("Redistribution and use in source and binary forms") {
+        if has("Neither the name") { "BSD-3-Clause" } else { "BSD-2-Clause" }
+    } else if has("Permission to use, copy, modify, and/or distribute this software") {
+        "ISC"
+    } else if has("This is free and unencumbered software released into the public domain") {
+        "Unlicense"
+    } else if has("CC0 1.0 Universal") {
+        "CC0-1.0"
+    } else {
+        return None;
+    };
+    Some(license)
+}
+
+pub fn find_spdx_identifiers(text: &str) -> Vec<String> {
+    let tag = Regex::new(r"SPDX-License-Identifier:\s*([A-Za-z0-9.+\- ()]+?)\s*(?:\*/|-->|$)").unwrap();
+    text.lines()
+        .filter_map(|line| tag.captures(line))
+        .map(|captures| captures[1].trim().to_string())
+        .collect()
+}
+
+fn read_head(path: &Path, bytes: u64) -> Option<String> {
+    let mut content = vec![];
+    fs::File::open(path).ok()?.take(bytes).read_to_end(&mut content).ok()?;
+    // binaries are skipped
+    if content.contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&content).to_string())
+}
+
+fn scan_dir(source_dir: &Path, dir: &Path, exclude: &[glob::Pattern], detected: &mut Vec<DetectedLicense>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        let relative = path.strip_prefix(source_dir).unwrap().to_string_lossy().to_string();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        // the packaging is declared in debian/copyright itself
+        if name.starts_with('.') || relative == "debian" || exclude.iter().any(|pattern| pattern.matches(&relative)) {
+            continue;
+        }
+        if path.is_symlink() {
+            continue;
+        }
+        if path.is_dir() {
+            scan_dir(source_dir, &path, exclude, detected)?;
+            continue;
+        }
+        let licenses: Vec<String> = match is_license_file(&name) {
+            true => read_head(&path, LICENSE_FILE_BYTES)
+                .and_then(|text| detect_license_text(&text))
+                .map(|license| vec![license.to_string()])
+                .unwrap_or_default(),
+            false => read_head(&path, HEADER_BYTES)
+                .map(|text| find_spdx_identifiers(&text))
+                .unwrap_or_default(),
+        };
+        for license in licenses {
+            detected.push(DetectedLicense {
+                path: relative.clone(),
+                license,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Licenses found under source_dir, paths matching an exclude glob are skipped.
+pub fn scan_source(source_dir: &Path, exclude: &[String]) -> Result<Vec<DetectedLicense>> {
+    let exclude = exclude
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(|err| eyre!("license_scan: invalid glob {}: {}", pattern, err)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut detected = vec![];
+    scan_dir(source_dir, source_dir, &exclude, &mut detected)?;
+    Ok(detected)
+}
+
+pub fn summarize_by_directory(detected: &[DetectedLicense]) -> Vec<LicenseSummary> {
+    let mut directories: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for license in detected {
+        let directory = match Path::new(&license.path).parent() {
+            Some(parent) if parent != Path::new("") => parent.to_string_lossy().to_string(),
+            _ => ".".to_string(),
+        };
+        directories.entry(directory).or_default().insert(license.license.clone());
+    }
+    directories
+        .into_iter()
+        .map(|(directory, licenses)| LicenseSummary {
+            directory,
+            licenses: licenses.into_iter().collect(),
+        })
+        .collect()
+}
+
+/// Files stanzas of a DEP-5 debian/copyright as (globs, license names).
+pub fn parse_copyright(content: &str) -> Vec<(Vec<String>, Vec<String>)> {
+    let mut stanzas = vec![];
+    for paragraph in content.split("\n\n") {
+        let mut files = vec![];
+        let mut license = None;
+        let mut field = "";
+        for line in paragraph.lines() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                if field == "Files" {
+                    files.extend(line.split_whitespace().map(str::to_string));
+                }
+                continue;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            field = name.trim();
+            match field {
+                "Files" => files.extend(value.split_whitespace().map(str::to_string)),
+                "License" => license = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+        if let (false, Some(license)) = (files.is_empty(), license) {
+            stanzas.push((files, split_license_expression(&license)));
+        }
+    }
+    stanzas
+}
+
+fn split_license_expression(expression: &str) -> Vec<String> {
+    let separator = Regex::new(r"(?i)\s+(?:or|and|with)\s+|,|\(|\)").unwrap();
+    separator
+        .split(expression)
+        .map(str::trim)
+        .filter(|license| !license.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Debian short names and SPDX identifiers compare equal, e.g. GPL-3+ and GPL-3.0-or-later.
+pub fn normalize_license(license: &str) -> String {
+    let mut license = license.trim().to_lowercase();
+    for suffix in ["+", "-or-later", "-only"] {
+        if let Some(stripped) = license.strip_suffix(suffix) {
+            license = stripped.to_string();
+        }
+    }
+    while let Some(stripped) = license.strip_suffix(".0") {
+        license = stripped.to_string();
+    }
+    match license.as_str() {
+        "expat" => "mit".to_string(),
+        _ => license,
+    }
+}
+
+// a tag like MIT OR GPL-2.0 is only copyleft when every alternative is
+fn is_copyleft(expression: &str) -> bool {
+    split_license_expression(expression).iter().all(|license| {
+        let license = normalize_license(license);
+        license.starts_with("gpl") || license.starts_with("agpl")
+    })
+}
+
+fn is_declared(expression: &str, declared: &[String]) -> bool {
+    let declared: Vec<String> = declared.iter().map(|name| normalize_license(name)).collect();
+    split_license_expression(expression)
+        .iter()
+        .any(|license| declared.contains(&normalize_license(license)))
+}
+
+/// Compares the detected licenses with debian/copyright, the last matching Files stanza applies.
+pub fn check_licenses(detected: &[DetectedLicense], copyright: Option<&str>) -> Vec<LicenseFinding> {
+    let Some(copyright) = copyright else {
+        return detected
+            .iter()
+            .filter(|license| is_copyleft(&license.license))
+            .map(|license| LicenseFinding {
+                path: license.path.clone(),
+                license: license.license.clone(),
+                level: FindingLevel::Warning,
+                message: "there is no debian/copyright declaring it".to_string(),
+            })
+            .collect();
+    };
+    let stanzas = parse_copyright(copyright);
+    let mut findings = vec![];
+    for license in detected {
+        let stanza = stanzas.iter().rev().find(|(files, _)| {
+            files
+                .iter()
+                .any(|glob| glob::Pattern::new(glob).is_ok_and(|pattern| pattern.matches(&license.path)))
+        });
+        let (level, message) = match stanza {
+            None => (FindingLevel::Warning, "not covered by any Files stanza of debian/copyright".to_string()),
+            Some((_, declared)) if is_declared(&license.license, declared) => continue,
+            Some((_, declared)) => (
+                if is_copyleft(&license.license) { FindingLevel::Error } else { FindingLevel::Warning },
+                format!("debian/copyright declares {}", declared.join(", ")),
+            ),
+        };
+        findings.push(LicenseFinding {
+            path: license.path.clone(),
+            license: license.license.clone(),
+            level,
+            message,
+        });
+    }
+    findings
+}
+
+pub fn scan_licenses(source_dir: &Path, exclude: &[String]) -> Result<LicenseScan> {
+    let detected = scan_source(source_dir, exclude)?;
+    let copyright = fs::read_to_string(source_dir.join("debian/copyright")).ok();
+    Ok(LicenseScan {
+        directories: summarize_by_directory(&detected),
+        findings: check_licenses(&detected, copyright.as_deref()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_licenses() {
+        let source = tempdir().unwrap();
+        let root = source.path();
+        for (file, content) in [
+            ("LICENSE", "Apache License\n  Version 2.0, January 2004\n"),
+            ("src/main.rs", "// SPDX-License-Identifier: Apache-2.0\nfn main() {}\n"),
+            ("vendor/lib/COPYING", "GNU GENERAL PUBLIC LICENSE\nVersion 3, 29 June 2007\n"),
+            ("vendor/mit/LICENSE-MIT", "Permission is hereby granted, free of charge, to any person\n"),
+            ("testdata/COPYING", "GNU AFFERO GENERAL PUBLIC LICENSE\n"),
+            ("debian/copyright", "Files: *\nCopyright: 2024 Acme\nLicense: Apache-2.0\n\nFiles: vendor/mit/*\nCopyright: 2020 Jane\nLicense: Expat\n"),
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap()).unwrap();
+            fs::write(root.join(file), content).unwrap();
+        }
+        let scan = scan_licenses(root, &["testdata".to_string()]).unwrap();
+        assert_eq!(
+            scan.directories,
+            vec![
+                LicenseSummary { directory: ".".to_string(), licenses: vec!["Apache-2.0".to_string()] },
+                LicenseSummary { directory: "src".to_string(), licenses: vec!["Apache-2.0".to_string()] },
+                LicenseSummary { directory: "vendor/lib".to_string(), licenses: vec!["GPL-3.0".to_string()] },
+                LicenseSummary { directory: "vendor/mit".to_string(), licenses: vec!["MIT".to_string()] },
+            ]
+        );
+        assert_eq!(
+            scan.findings,
+            vec![LicenseFinding {
+                path: "vendor/lib/COPYING".to_string(),
+                license: "GPL-3.0".to_string(),
+                level: FindingLevel::Error,
+                message: "debian/copyright declares Apache-2.0".to_string(),
+            }]
+        );
+
+        assert_eq!(normalize_license("GPL-3+"), normalize_license("GPL-3.0-or-later"));
+        assert_eq!(split_license_expression("GPL-2+ or (Apache-2.0 and MIT)"), ["GPL-2+", "Apache-2.0", "MIT"]);
+        assert_eq!(find_spdx_identifiers("/* SPDX-License-Identifier: MIT OR Apache-2.0 */"), ["MIT OR Apache-2.0"]);
+        assert!(is_declared("MIT OR GPL-2.0", &["Expat".to_string()]));
+        assert!(!is_copyleft("MIT OR GPL-2.0") && is_copyleft("GPL-2.0-only"));
+    }
+}
@@ -0,0 +1,104 @@
+// Build logs kept across builds, the artifacts dir only holds the log of the last build.
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_LOG_KEEP_COUNT: usize = 10;
+pub const DEFAULT_LOG_KEEP_SIZE_MB: u64 = 200;
+
+/// Writes log_file gzipped to archive_dir/<file name>-<unix time>.gz.
+pub fn archive_log(log_file: &Path, archive_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(archive_dir)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let archive = archive_dir.join(format!(
+        "{}-{}.gz",
+        log_file.file_name().unwrap().to_string_lossy(),
+        timestamp
+    ));
+    let output = Command::new("gzip")
+        .arg("-c")
+        .arg(log_file)
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to compress {}: {}",
+            log_file.display(),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    fs::write(&archive, output.stdout)?;
+    Ok(archive)
+}
+
+/// Removes the oldest logs of archive_dir until at most keep_count remain,
+/// taking together at most keep_size bytes, returns the removed logs.
+pub fn rotate_logs(archive_dir: &Path, keep_count: usize, keep_size: u64) -> Result<Vec<PathBuf>> {
+    let mut logs = vec![];
+    for entry in fs::read_dir(archive_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            logs.push((metadata.modified()?, entry.path(), metadata.len()));
+        }
+    }
+    // newest first, the name breaks ties of logs written in the same second
+    logs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+
+    let mut removed = vec![];
+    let mut total_size = 0;
+    for (index, (_, path, size)) in logs.into_iter().enumerate() {
+        total_size += size;
+        if index >= keep_count || total_size > keep_size {
+            fs::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+    if !removed.is_empty() {
+        info!("Removed {} old build logs from {}", removed.len(), archive_dir.display());
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::{set_file_mtime, FileTime};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_archive_log_is_compressed() {
+        let dir = tempdir().unwrap();
+        let log_file = dir.path().join("hello-world_1.0.0-1_amd64.build");
+        fs::write(&log_file, "x".repeat(10_000)).unwrap();
+
+        let archive = archive_log(&log_file, &dir.path().join("logs")).unwrap();
+        assert!(archive
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("hello-world_1.0.0-1_amd64.build-"));
+        assert!(fs::metadata(&archive).unwrap().len() < 1_000);
+    }
+
+    #[test]
+    fn test_rotate_logs_by_count_and_size() {
+        let dir = tempdir().unwrap();
+        for (index, size) in [100, 100, 100, 100].iter().enumerate() {
+            let log = dir.path().join(format!("build-{}.gz", index));
+            fs::write(&log, "x".repeat(*size)).unwrap();
+            set_file_mtime(&log, FileTime::from_unix_time(1_700_000_000 + index as i64, 0)).unwrap();
+        }
+
+        let removed = rotate_logs(dir.path(), 3, 1_000).unwrap();
+        assert_eq!(removed, vec![dir.path().join("build-0.gz")]);
+
+        let removed = rotate_logs(dir.path(), 3, 250).unwrap();
+        assert_eq!(removed, vec![dir.path().join("build-1.gz")]);
+        assert!(dir.path().join("build-3.gz").exists());
+        assert!(dir.path().join("build-2.gz").exists());
+    }
+}
@@ -0,0 +1,479 @@
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::pkg_config::{PackageType, PkgConfig};
+use crate::v1::tools::compare_debian_versions;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Lines in the build log starting with this prefix are facts reported from
+// inside the chroot, e.g. "pkg-builder: dotnet-source=backup"
+pub const BUILD_LOG_MARKER: &str = "pkg-builder: ";
+
+/// Shell command that reports a key=value fact from inside the chroot.
+/// printf is used, so the command line itself never matches the marker.
+pub fn build_log_marker_command(key: &str, value: &str) -> String {
+    format!("printf '{}%s=%s\\n' {} {}", BUILD_LOG_MARKER, key, value)
+}
+
+// Output lines of build hooks are prefixed, e.g. "pkg-builder-hook: finished-build: ii  libc6 ..."
+pub const HOOK_LOG_MARKER: &str = "pkg-builder-hook: ";
+
+/// Wraps a hook command, so its output can be told apart in the build log. The status of the
+/// command is passed around sed on fd 3, the sh of the chroots has no pipefail.
+pub fn hook_command(stage: &str, command: &str) -> String {
+    format!(
+        "{{ {{ {{ {{ {}; }} 2>&1; echo $? >&3; }} | sed 's/^/{}{}: /' >&4; }} 3>&1 | (read status; exit $status); }} 4>&1",
+        command, HOOK_LOG_MARKER, stage
+    )
+}
+
+// installed packages of the chroot after the build, as "<package> <version>" lines
+pub const CHROOT_PACKAGES_STAGE: &str = "chroot-packages";
+// the same once the build dependencies are installed, before the build starts
+pub const CHROOT_PACKAGES_BEFORE_STAGE: &str = "chroot-packages-before";
+const LIST_PACKAGES: &str = "dpkg-query -W -f='${binary:Package} ${Version}\\n'";
+
+/// Reports the packages installed in the chroot, run as finished build command.
+pub fn chroot_packages_command() -> String {
+    hook_command(CHROOT_PACKAGES_STAGE, LIST_PACKAGES)
+}
+
+/// Reports the packages installed in the chroot, run as starting build command.
+pub fn chroot_packages_before_command() -> String {
+    hook_command(CHROOT_PACKAGES_BEFORE_STAGE, LIST_PACKAGES)
+}
+
+fn parse_package_list(packages: &str) -> BTreeMap<String, String> {
+    packages
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(package, version)| (package.to_string(), version.trim().to_string()))
+        .collect()
+}
+
+/// stage -> output of its hooks
+pub fn parse_hook_output(build_log: &str) -> BTreeMap<String, String> {
+    let mut output: BTreeMap<String, String> = BTreeMap::new();
+    for line in build_log.lines() {
+        if let Some((stage, line)) = line
+            .strip_prefix(HOOK_LOG_MARKER)
+            .and_then(|line| line.split_once(": "))
+        {
+            let stage_output = output.entry(stage.to_string()).or_default();
+            stage_output.push_str(line);
+            stage_output.push('\n');
+        }
+    }
+    output
+}
+
+pub fn parse_build_log_markers(build_log: &str) -> BTreeMap<String, String> {
+    let mut markers = BTreeMap::new();
+    for line in build_log.lines() {
+        if let Some(marker) = line.strip_prefix(BUILD_LOG_MARKER) {
+            if let Some((key, value)) = marker.split_once('=') {
+                markers.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+    }
+    markers
+}
+
+/// Output of a post_process step, derived from the built debs.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct SecondaryArtifact {
+    pub step: String,
+    /// relative to the artifacts dir, e.g. post-process/image/hello-world_1.0.0-1_amd64.image.tar
+    pub path: String,
+    pub sha256: String,
+}
+
+/// External program the build ran, on the host.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct ToolRecord {
+    pub name: String,
+    /// None when the program is not in PATH
+    pub path: Option<String>,
+    pub version: Option<String>,
+    /// the version in build_env, None for tools without a pin
+    pub pinned_version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct BuildManifest {
+    pub package_name: String,
+    pub epoch: Option<String>,
+    pub version_number: String,
+    pub revision_number: String,
+    pub codename: String,
+    pub arch: String,
+    pub pkg_builder_version: String,
+    /// "primary" or "backup", only set for dotnet packages
+    pub dotnet_source: Option<String>,
+    /// chroot packages added, removed or changed since the previous build of the same version
+    #[serde(default)]
+    pub chroot_changes: Vec<String>,
+    /// full version of the previous version of the package built for the same codename and arch
+    pub previous_version: Option<String>,
+    /// chroot packages added, removed or changed since the build of previous_version
+    #[serde(default)]
+    pub chroot_version_changes: Vec<String>,
+    /// tool pins and toolchain hashes the package was built with
+    #[serde(default)]
+    pub fingerprint: BTreeMap<String, String>,
+    /// build environment variables of the language build options, e.g. CARGO_BUILD_ARGS
+    #[serde(default)]
+    pub build_options: BTreeMap<String, String>,
+    /// package -> version of everything installed in the chroot
+    #[serde(default)]
+    pub chroot_packages: BTreeMap<String, String>,
+    /// git commit of the repository of the config, see get_recipe_revision
+    pub recipe_revision: Option<String>,
+    /// chroot packages the build itself added, removed or changed, the build dependencies
+    /// were installed before
+    #[serde(default)]
+    pub build_installs: Vec<String>,
+    /// external programs of the pipeline with their path and version
+    #[serde(default)]
+    pub tools: Vec<ToolRecord>,
+    /// outputs of the post_process steps
+    #[serde(default)]
+    pub secondary_artifacts: Vec<SecondaryArtifact>,
+}
+
+/// Commit of the git repository the config is in, with a -dirty suffix when the config dir has
+/// uncommitted changes, None outside of a git repository.
+pub fn get_recipe_revision(config_root: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .current_dir(config_root)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let revision = git(&["rev-parse", "HEAD"])?;
+    match git(&["status", "--porcelain", "--", "."]) {
+        Some(status) if status.is_empty() => Some(revision),
+        _ => Some(format!("{}-dirty", revision)),
+    }
+}
+
+/// Inputs which change the binaries without changing the package version.
+pub fn config_fingerprint(config: &PkgConfig) -> Result<BTreeMap<String, String>> {
+    let build_env = &config.build_env;
+    let mut fingerprint = BTreeMap::new();
+    let arch = build_env.arch.to_string();
+    for (key, value) in [
+        ("build_env.codename", &build_env.codename),
+        ("build_env.arch", &arch),
+        ("build_env.pkg_builder_version", &build_env.pkg_builder_version),
+        ("build_env.debcrafter_version", &build_env.debcrafter_version),
+        ("build_env.sbuild_version", &build_env.sbuild_version),
+        ("build_env.lintian_version", &build_env.lintian_version),
+        ("build_env.piuparts_version", &build_env.piuparts_version),
+        ("build_env.autopkgtest_version", &build_env.autopkgtest_version),
+    ] {
+        fingerprint.insert(key.to_string(), value.clone());
+    }
+    let language_env = match &config.package_type {
+        PackageType::Default(package_type) => {
+            fingerprint.insert(
+                "package_type.tarball_url".to_string(),
+                package_type.get_source_url(&config.package_fields.version_number),
+            );
+            if let Some(release) = &package_type.github_release {
+                fingerprint.insert(
+                    "package_type.github_release.asset".to_string(),
+                    release.get_asset_pattern(&config.package_fields.version_number),
+                );
+            }
+            if let Some(tarball_hash) = &package_type.tarball_hash {
+                fingerprint.insert("package_type.tarball_hash".to_string(), tarball_hash.clone());
+            }
+            Some(&package_type.language_env)
+        }
+        PackageType::Git(package_type) => {
+            fingerprint.insert("package_type.git_url".to_string(), package_type.git_url.clone());
+            fingerprint.insert("package_type.git_tag".to_string(), package_type.git_tag.clone());
+            Some(&package_type.language_env)
+        }
+        PackageType::Virtual => None,
+    };
+    // the patches are local files, their content changes the binaries, each one is hashed
+    // with its name in the series
+    if let Some(patches) = &config.patches {
+        let mut series = vec![];
+        for file in patches.files.iter() {
+            let content = fs::read(file).map_err(|err| eyre!("Failed to read patch {}: {}", file, err))?;
+            let name = Path::new(file).file_name().unwrap_or_default().to_string_lossy();
+            series.push(format!("{}  {}", calculate_sha256(content.as_slice())?, name));
+        }
+        fingerprint.insert("patches".to_string(), calculate_sha256(series.join("\n").as_bytes())?);
+    }
+    // the relations merged into the generated debian/control, per binary package and field
+    if let Some(control_overrides) = &config.control_overrides {
+        let package = control_overrides
+            .package
+            .as_deref()
+            .unwrap_or(&config.package_fields.package_name);
+        for (field, relations) in control_overrides.get_relations() {
+            if !relations.is_empty() {
+                fingerprint.insert(
+                    format!("control_overrides.{}.{}", package, field.to_lowercase()),
+                    relations.join(", "),
+                );
+            }
+        }
+    }
+    // toolchain versions, urls and checksums, hashed as they include whole signatures
+    if let Some(language_env) = language_env {
+        let language_env = serde_json::to_string(language_env).unwrap_or_default();
+        fingerprint.insert(
+            "package_type.language_env".to_string(),
+            calculate_sha256(language_env.as_bytes())?,
+        );
+    }
+    Ok(fingerprint)
+}
+
+/// Human readable differences between two fingerprints
+pub fn diff_fingerprints(
+    previous: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut changes = vec![];
+    for (key, value) in current.iter() {
+        match previous.get(key) {
+            Some(previous_value) if previous_value == value => {}
+            Some(previous_value) => changes.push(format!("{}: {} -> {}", key, previous_value, value)),
+            None => changes.push(format!("{}: added {}", key, value)),
+        }
+    }
+    for (key, value) in previous.iter() {
+        if !current.contains_key(key) {
+            changes.push(format!("{}: removed {}", key, value));
+        }
+    }
+    changes
+}
+
+impl BuildManifest {
+    pub fn new(config: &PkgConfig) -> Result<Self> {
+        let language_env = match &config.package_type {
+            PackageType::Default(package_type) => Some(&package_type.language_env),
+            PackageType::Git(package_type) => Some(&package_type.language_env),
+            PackageType::Virtual => None,
+        };
+        Ok(BuildManifest {
+            package_name: config.package_fields.package_name.clone(),
+            epoch: config.package_fields.epoch.clone(),
+            version_number: config.package_fields.version_number.clone(),
+            revision_number: config.package_fields.revision_number.clone(),
+            codename: config.build_env.codename.clone(),
+            arch: config.build_env.arch.to_string(),
+            pkg_builder_version: env!("CARGO_PKG_VERSION").to_string(),
+            dotnet_source: None,
+            chroot_changes: vec![],
+            previous_version: None,
+            chroot_version_changes: vec![],
+            fingerprint: config_fingerprint(config)?,
+            build_options: language_env
+                .map(|language_env| language_env.get_build_options())
+                .unwrap_or_default(),
+            chroot_packages: BTreeMap::new(),
+            recipe_revision: None,
+            build_installs: vec![],
+            tools: vec![],
+            secondary_artifacts: vec![],
+        })
+    }
+
+    pub fn apply_build_log(&mut self, build_log: &str) {
+        let markers = parse_build_log_markers(build_log);
+        if let Some(dotnet_source) = markers.get("dotnet-source") {
+            self.dotnet_source = Some(dotnet_source.clone());
+        }
+        let hook_output = parse_hook_output(build_log);
+        if let Some(packages) = hook_output.get(CHROOT_PACKAGES_STAGE) {
+            self.chroot_packages = parse_package_list(packages);
+            if let Some(before) = hook_output.get(CHROOT_PACKAGES_BEFORE_STAGE) {
+                self.build_installs = diff_fingerprints(&parse_package_list(before), &self.chroot_packages);
+            }
+        }
+    }
+
+    pub fn get_full_version(&self) -> String {
+        match &self.epoch {
+            Some(epoch) => format!("{}:{}-{}", epoch, self.version_number, self.revision_number),
+            None => format!("{}-{}", self.version_number, self.revision_number),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|err| eyre!("Failed to serialize build manifest: {}", err))?;
+        fs::write(path, content)
+            .map_err(|err| eyre!("Failed to write build manifest {}: {}", path.display(), err))?;
+        Ok(())
+    }
+}
+
+/// Manifest of the newest version of the package below the version of manifest in history_dir,
+/// built for the same codename and arch.
+pub fn find_previous_version(history_dir: &Path, manifest: &BuildManifest) -> Option<BuildManifest> {
+    let version = manifest.get_full_version();
+    fs::read_dir(history_dir)
+        .ok()?
+        .filter_map(|entry| fs::read_to_string(entry.ok()?.path()).ok())
+        .filter_map(|content| toml::from_str::<BuildManifest>(&content).ok())
+        .filter(|previous| {
+            previous.package_name == manifest.package_name
+                && previous.codename == manifest.codename
+                && previous.arch == manifest.arch
+                && compare_debian_versions(&previous.get_full_version(), &version) == Ordering::Less
+        })
+        .max_by(|a, b| compare_debian_versions(&a.get_full_version(), &b.get_full_version()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::v1::pkg_config::PatchesConfig;
+
+    #[test]
+    fn test_parse_build_log_markers() {
+        let build_log = "\
+I: running printf 'pkg-builder: %s=%s\\n' dotnet-source primary
+E: Unable to locate package dotnet-sdk-8.0
+pkg-builder: dotnet-source=backup
+";
+        let markers = parse_build_log_markers(build_log);
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers.get("dotnet-source").unwrap(), "backup");
+    }
+
+    #[test]
+    fn test_parse_hook_output() {
+        let build_log = format!(
+            "I: running {}\npkg-builder-hook: finished-build: ii  libc6\npkg-builder-hook: finished-build: ii  zlib1g\nunrelated\n",
+            hook_command("finished-build", "dpkg -l")
+        );
+        let output = parse_hook_output(&build_log);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output.get("finished-build").unwrap(), "ii  libc6\nii  zlib1g\n");
+
+        // the status of the command is kept through the sed prefixing its output
+        for (command, success) in [("true", true), ("echo failed && false", false)] {
+            let status = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(hook_command("finished-build", command))
+                .status()
+                .unwrap();
+            assert_eq!(status.success(), success, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_diff_fingerprints() {
+        let mut config = PkgConfig::default();
+        config.build_env.sbuild_version = "0.85.6".to_string();
+        let previous = config_fingerprint(&config).unwrap();
+        assert!(diff_fingerprints(&previous, &previous).is_empty());
+
+        config.build_env.sbuild_version = "0.85.7".to_string();
+        config.package_type = PackageType::Git(Default::default());
+        let changes = diff_fingerprints(&previous, &config_fingerprint(&config).unwrap());
+        assert_eq!(changes.len(), 4);
+        assert!(changes.contains(&"build_env.sbuild_version: 0.85.6 -> 0.85.7".to_string()));
+        assert!(changes.contains(&"package_type.git_tag: added ".to_string()));
+
+        // the content of each patch counts with its name, an unreadable patch is an error
+        let dir = tempdir().unwrap();
+        let (first, second) = (dir.path().join("first.patch"), dir.path().join("second.patch"));
+        fs::write(&first, "ab").unwrap();
+        fs::write(&second, "c").unwrap();
+        let patches = |files: &[&Path]| PatchesConfig {
+            files: files.iter().map(|file| file.to_string_lossy().to_string()).collect(),
+        };
+        config.patches = Some(patches(&[&first, &second]));
+        let previous = config_fingerprint(&config).unwrap();
+        fs::write(&first, "a").unwrap();
+        fs::write(&second, "bc").unwrap();
+        let changes = diff_fingerprints(&previous, &config_fingerprint(&config).unwrap());
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].starts_with("patches: "));
+        config.patches = Some(patches(&[&dir.path().join("missing.patch")]));
+        let error = config_fingerprint(&config).unwrap_err().to_string();
+        assert!(error.starts_with("Failed to read patch"), "{}", error);
+    }
+
+    #[test]
+    fn test_get_recipe_revision() {
+        let dir = tempdir().unwrap();
+        let config_root = dir.path().join("hello-world");
+        fs::create_dir_all(&config_root).unwrap();
+        assert_eq!(get_recipe_revision(&config_root), None);
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(dir.path())
+                .env("GIT_CONFIG_GLOBAL", "/dev/null")
+                .args(["-c", "user.name=Alice", "-c", "user.email=alice@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        fs::write(config_root.join("pkg-builder.toml"), "").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "Add hello-world"]);
+        let revision = get_recipe_revision(&config_root).unwrap();
+        assert_eq!(revision.len(), 40);
+        // only changes of the config dir count
+        fs::write(dir.path().join("README"), "").unwrap();
+        assert_eq!(get_recipe_revision(&config_root).unwrap(), revision);
+        fs::write(config_root.join("pkg-builder.toml"), "[build_env]\n").unwrap();
+        assert_eq!(get_recipe_revision(&config_root).unwrap(), format!("{}-dirty", revision));
+    }
+
+    #[test]
+    fn test_manifest_write_and_read() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("manifest.toml");
+        let mut config = PkgConfig::default();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.build_env.codename = "bookworm".to_string();
+        let mut manifest = BuildManifest::new(&config).unwrap();
+        manifest.apply_build_log(
+            "pkg-builder: dotnet-source=primary\npkg-builder-hook: chroot-packages: libc6:amd64 2.36-9+deb12u7\n",
+        );
+
+        manifest.write(&path).unwrap();
+        let read_manifest: BuildManifest =
+            toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read_manifest, manifest);
+        assert_eq!(read_manifest.dotnet_source, Some("primary".to_string()));
+        assert_eq!(read_manifest.chroot_packages["libc6:amd64"], "2.36-9+deb12u7");
+        assert!(read_manifest.build_installs.is_empty());
+    }
+
+    #[test]
+    fn test_build_installs() {
+        let mut manifest = BuildManifest::default();
+        manifest.apply_build_log(
+            "pkg-builder-hook: chroot-packages-before: libc6:amd64 2.36-9+deb12u7\n\
+             pkg-builder-hook: chroot-packages-before: make:amd64 4.3-4.1\n\
+             pkg-builder-hook: chroot-packages: libc6:amd64 2.36-9+deb12u7\n\
+             pkg-builder-hook: chroot-packages: make:amd64 4.3-4.1\n\
+             pkg-builder-hook: chroot-packages: curl:amd64 7.88.1-10+deb12u5\n",
+        );
+        assert_eq!(manifest.build_installs, vec!["curl:amd64: added 7.88.1-10+deb12u5"]);
+    }
+}
@@ -0,0 +1,141 @@
+// Builds of one package for every distribution of build_env.codenames. Each build gets the
+// config with its codename and its own workdir, the default workdir is per codename already,
+// a configured one gets a subdirectory per codename. The builds run on a bounded number of
+// threads sharing the jobs, a failed build does not stop the others. The log lines of a build
+// are prefixed with its codename.
+use crate::v1::build::sbuild::resolve_jobs;
+use crate::v1::distribution::get_distribution;
+use crate::v1::pkg_config::PkgConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+thread_local! {
+    // codename of the matrix build running on the thread
+    static BUILD_CODENAME: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Prefix of the log lines of the thread, e.g. "[noble numbat] " in a matrix build.
+pub fn get_log_prefix() -> String {
+    BUILD_CODENAME.with(|codename| match codename.borrow().as_ref() {
+        Some(codename) => format!("[{}] ", codename),
+        None => String::new(),
+    })
+}
+
+/// The config of every codename of build_env.codenames, the jobs are split between the
+/// parallel builds.
+pub fn get_matrix_configs(config: &PkgConfig, parallel: usize) -> Result<Vec<PkgConfig>> {
+    let codenames = config
+        .build_env
+        .codenames
+        .as_ref()
+        .ok_or_else(|| eyre!("--all-distros needs build_env.codenames, e.g. codenames = [\"bookworm\", \"noble numbat\"]"))?;
+    let threads = parallel.max(1).min(codenames.len());
+    let jobs = (resolve_jobs(config.build_env.jobs) / threads.max(1)).max(1);
+    let mut configs = vec![];
+    let mut workdirs: BTreeMap<String, &str> = BTreeMap::new();
+    for codename in codenames {
+        get_distribution(codename)?;
+        let mut matrix_config = config.clone();
+        matrix_config.build_env.codename = codename.clone();
+        matrix_config.build_env.jobs = Some(jobs);
+        let workdir = match &config.build_env.workdir {
+            Some(workdir) => Path::new(workdir).join(codename).to_string_lossy().to_string(),
+            None => format!("~/.pkg-builder/packages/{}", codename),
+        };
+        if let Some(other) = workdirs.insert(shellexpand::tilde(&workdir).to_string(), codename) {
+            return Err(eyre!("the builds for {} and {} would share the workdir {}", other, codename, workdir));
+        }
+        if config.build_env.workdir.is_some() {
+            matrix_config.build_env.workdir = Some(workdir);
+        }
+        configs.push(matrix_config);
+    }
+    Ok(configs)
+}
+
+/// Runs build for every config on at most parallel threads, fails listing the failed codenames.
+pub fn build_matrix<F>(configs: Vec<PkgConfig>, parallel: usize, build: F) -> Result<()>
+where
+    F: Fn(PkgConfig) -> Result<()> + Sync,
+{
+    let codenames: Vec<String> = configs.iter().map(|config| config.build_env.codename.clone()).collect();
+    info!("Building for {} on {} threads", codenames.join(", "), parallel.max(1).min(configs.len()));
+    let next = AtomicUsize::new(0);
+    let configs = Mutex::new(configs.into_iter().map(Some).collect::<Vec<Option<PkgConfig>>>());
+    let failed = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..parallel.max(1).min(codenames.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(codename) = codenames.get(index) else {
+                    break;
+                };
+                BUILD_CODENAME.with(|build_codename| *build_codename.borrow_mut() = Some(codename.clone()));
+                let config = configs.lock().unwrap()[index].take().unwrap();
+                match build(config) {
+                    Ok(()) => info!("Build for {} succeeded", codename),
+                    Err(err) => {
+                        warn!("Build for {} failed: {:?}", codename, err);
+                        failed.lock().unwrap().push(index);
+                    }
+                }
+            });
+        }
+    });
+    let mut failed = failed.into_inner().unwrap();
+    if failed.is_empty() {
+        return Ok(());
+    }
+    failed.sort();
+    let failed: Vec<&str> = failed.iter().map(|index| codenames[*index].as_str()).collect();
+    Err(eyre!("{} of {} builds failed: {}", failed.len(), codenames.len(), failed.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matrix_builds_every_codename() {
+        let mut config = PkgConfig::default();
+        config.build_env.codename = "bookworm".to_string();
+        assert!(get_matrix_configs(&config, 2).is_err());
+
+        config.build_env.codenames = Some(vec!["bookworm".to_string(), "noble numbat".to_string()]);
+        config.build_env.workdir = Some("/tmp/hello-world".to_string());
+        config.build_env.jobs = Some(8);
+        let configs = get_matrix_configs(&config, 2).unwrap();
+        assert_eq!(configs[1].build_env.codename, "noble numbat");
+        assert_eq!(configs[1].build_env.workdir.as_deref(), Some("/tmp/hello-world/noble numbat"));
+        assert_eq!(configs[1].build_env.jobs, Some(4));
+        assert_eq!(get_matrix_configs(&config, 16).unwrap()[0].build_env.jobs, Some(4));
+        config.build_env.workdir = None;
+        assert_eq!(get_matrix_configs(&config, 2).unwrap()[0].build_env.workdir, None);
+        config.build_env.codenames = Some(vec!["bookworm".to_string(), "bookworm".to_string()]);
+        assert_eq!(
+            get_matrix_configs(&config, 2).unwrap_err().to_string(),
+            "the builds for bookworm and bookworm would share the workdir ~/.pkg-builder/packages/bookworm"
+        );
+
+        let built = Mutex::new(vec![]);
+        let err = build_matrix(configs, 2, |config| {
+            assert_eq!(get_log_prefix(), format!("[{}] ", config.build_env.codename));
+            built.lock().unwrap().push(config.build_env.codename.clone());
+            match config.build_env.codename.as_str() {
+                "bookworm" => Ok(()),
+                _ => Err(eyre!("sbuild failed")),
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 builds failed: noble numbat");
+        let mut built = built.into_inner().unwrap();
+        built.sort();
+        assert_eq!(built, ["bookworm", "noble numbat"]);
+    }
+}
@@ -2,4 +2,46 @@ pub mod sbuild;
 pub mod sbuild_packager;
 pub mod dir_setup;
 pub mod debcrafter_helper;
+pub mod manifest;
+pub mod control_overrides;
+pub mod store;
+pub mod java_dependencies;
+pub mod trust;
+pub mod quality;
+pub mod logs;
+pub mod smoke;
+pub mod redact;
+pub mod units;
+pub mod dev;
+pub mod debconf;
+pub mod extras;
+pub mod content_policy;
+pub mod exports;
+pub mod archive;
+pub mod multi_arch;
+pub mod patches;
+pub mod provision;
+pub mod apparmor;
+pub mod snapshot;
+pub mod abi;
+pub mod plan;
+pub mod testbed;
+pub mod conffiles;
+pub mod watchdog;
+pub mod licenses;
+pub mod github_release;
+pub mod test_deps;
+pub mod cross;
+pub mod chroot_snapshot;
+pub mod chroot_stamp;
+pub mod container;
+pub mod post_process;
+pub mod privilege;
+pub mod git_clone;
+pub mod python_dependencies;
+pub mod host_env;
+pub mod upload;
+pub mod matrix;
 
+#[cfg(test)]
+pub mod fixtures;
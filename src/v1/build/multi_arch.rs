@@ -0,0 +1,119 @@
+// Checks that the Multi-Arch: same debs can be installed for several architectures at once,
+// dpkg refuses files shared between them unless they are identical on every architecture.
+use crate::v1::build::content_policy::{dpkg_deb, parse_contents, DebEntry};
+use crate::v1::build::quality::{ContentFinding, FindingLevel};
+use crate::v1::pkg_config::MultiArchConfig;
+use eyre::Result;
+use std::path::PathBuf;
+
+// the binaries differ per architecture, only one of them can be installed
+const BINARY_DIRS: &[&str] = &["/bin/", "/sbin/", "/usr/bin/", "/usr/sbin/", "/usr/games/"];
+// architecture dependent, unless under the directory of the triplet
+const LIBRARY_DIRS: &[&str] = &["/lib/", "/usr/lib/", "/usr/libexec/"];
+
+/// e.g. amd64 -> x86_64-linux-gnu
+pub fn get_multiarch_triplet(arch: &str) -> Option<&'static str> {
+    match arch {
+        "amd64" => Some("x86_64-linux-gnu"),
+        "arm64" => Some("aarch64-linux-gnu"),
+        "armhf" => Some("arm-linux-gnueabihf"),
+        "riscv64" => Some("riscv64-linux-gnu"),
+        "i386" => Some("i386-linux-gnu"),
+        _ => None,
+    }
+}
+
+fn finding(package: &str, level: FindingLevel, message: String) -> ContentFinding {
+    ContentFinding {
+        package: package.to_string(),
+        level,
+        message,
+    }
+}
+
+/// Files of a Multi-Arch: same deb which conflict with the deb of another architecture.
+pub fn check_coinstallable(package: &str, arch: &str, entries: &[DebEntry]) -> Vec<ContentFinding> {
+    let triplet = get_multiarch_triplet(arch).unwrap_or(arch);
+    let mut findings = vec![];
+    for entry in entries.iter().filter(|entry| !entry.mode.starts_with('d')) {
+        let path = entry.path.as_str();
+        if let Some(dir) = BINARY_DIRS.iter().find(|dir| path.starts_with(*dir)) {
+            findings.push(finding(
+                package,
+                FindingLevel::Error,
+                format!("{} is under {}, it conflicts with the {} of other architectures", path, dir, package),
+            ));
+        } else if let Some(dir) = LIBRARY_DIRS.iter().find(|dir| path.starts_with(*dir)) {
+            if !path.starts_with(&format!("{}{}/", dir, triplet)) {
+                findings.push(finding(
+                    package,
+                    FindingLevel::Error,
+                    format!("{} is not under {}{}/, it conflicts with the {} of other architectures", path, dir, triplet, package),
+                ));
+            }
+        } else if path.starts_with("/usr/include/") && !path.starts_with(&format!("/usr/include/{}/", triplet)) {
+            findings.push(finding(
+                package,
+                FindingLevel::Warning,
+                format!("{} must be identical on every architecture, move it to /usr/include/{}/ otherwise", path, triplet),
+            ));
+        }
+    }
+    findings
+}
+
+/// Findings of the debs listed as same in the config, which also have to carry the Multi-Arch field.
+pub fn check_debs(debs: &[PathBuf], config: &MultiArchConfig) -> Result<Vec<ContentFinding>> {
+    let mut findings = vec![];
+    for deb in debs {
+        let control = dpkg_deb(&["--field"], deb)?;
+        let field = |name: &str| {
+            control
+                .lines()
+                .find_map(|line| line.strip_prefix(&format!("{}:", name)))
+                .map(|value| value.trim().to_string())
+        };
+        let package = field("Package").unwrap_or_default();
+        if !config.same.contains(&package) {
+            continue;
+        }
+        if field("Multi-Arch").as_deref() != Some("same") {
+            findings.push(finding(&package, FindingLevel::Error, "the built deb is not Multi-Arch: same".to_string()));
+        }
+        let arch = field("Architecture").unwrap_or_default();
+        let entries = parse_contents(&dpkg_deb(&["-c"], deb)?);
+        findings.append(&mut check_coinstallable(&package, &arch, &entries));
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_coinstallable() {
+        let listing = "drwxr-xr-x root/root         0 2024-06-05 10:00 ./usr/lib/x86_64-linux-gnu/\n\
+                       -rw-r--r-- root/root    262144 2024-06-05 10:00 ./usr/lib/x86_64-linux-gnu/libhello.so.1.0.0\n\
+                       lrwxrwxrwx root/root         0 2024-06-05 10:00 ./usr/lib/x86_64-linux-gnu/libhello.so.1 -> libhello.so.1.0.0\n\
+                       -rw-r--r-- root/root      1024 2024-06-05 10:00 ./usr/lib/libhello-plugin.so\n\
+                       -rwxr-xr-x root/root     16384 2024-06-05 10:00 ./usr/bin/hello-config\n\
+                       -rw-r--r-- root/root      2048 2024-06-05 10:00 ./usr/include/hello.h\n\
+                       -rw-r--r-- root/root       512 2024-06-05 10:00 ./usr/include/x86_64-linux-gnu/hello-config.h\n\
+                       -rw-r--r-- root/root       512 2024-06-05 10:00 ./usr/share/doc/libhello1/copyright\n";
+        let entries = parse_contents(listing);
+        let messages: Vec<String> = check_coinstallable("libhello1", "amd64", &entries)
+            .into_iter()
+            .map(|finding| format!("{}: {}", finding.level, finding.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "error: /usr/lib/libhello-plugin.so is not under /usr/lib/x86_64-linux-gnu/, it conflicts with the libhello1 of other architectures",
+                "error: /usr/bin/hello-config is under /usr/bin/, it conflicts with the libhello1 of other architectures",
+                "warning: /usr/include/hello.h must be identical on every architecture, move it to /usr/include/x86_64-linux-gnu/ otherwise",
+            ]
+        );
+        assert_eq!(check_coinstallable("libhello1", "arm64", &entries[..3]).len(), 2);
+    }
+}
@@ -0,0 +1,128 @@
+// Patches of the upstream source declared in [patches], so a trivial build failure can be fixed
+// without forking the upstream tarball. They are copied into debian/patches, appended to the
+// series after the patches an upstream debian dir already has, and applied with quilt, which
+// leaves them recorded in .pc for dpkg-source.
+use crate::v1::pkg_config::PatchesConfig;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const QUILT_FORMAT: &str = "3.0 (quilt)";
+
+pub fn get_patch_name(file: &str) -> &str {
+    file.rsplit('/').next().unwrap_or(file)
+}
+
+/// The series with the names appended, names already in it keep their place.
+pub fn get_series(series: &str, names: &[&str]) -> String {
+    let mut lines: Vec<&str> = series.lines().filter(|line| !line.trim().is_empty()).collect();
+    for name in names {
+        let listed = lines
+            .iter()
+            .any(|line| line.split_whitespace().next() == Some(*name));
+        if !listed {
+            lines.push(name);
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+// patches are only applied by dpkg-source with the quilt format
+fn check_source_format(build_files_dir: &Path) -> Result<()> {
+    let format_file = build_files_dir.join("debian/source/format");
+    let format = fs::read_to_string(&format_file).unwrap_or_default();
+    if format.trim() != QUILT_FORMAT {
+        return Err(eyre!(
+            "patches need the {} source format, {} is {}",
+            QUILT_FORMAT,
+            format_file.display(),
+            format.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Copies the patches into debian/patches and adds them to the series.
+pub fn copy_patches(build_files_dir: &Path, patches: &PatchesConfig) -> Result<()> {
+    check_source_format(build_files_dir)?;
+    let patches_dir = build_files_dir.join("debian/patches");
+    fs::create_dir_all(&patches_dir)?;
+    for file in patches.files.iter() {
+        fs::copy(file, patches_dir.join(get_patch_name(file)))
+            .map_err(|err| eyre!("Could not copy patch {}: {}", file, err))?;
+    }
+    let series_file = patches_dir.join("series");
+    let series = fs::read_to_string(&series_file).unwrap_or_default();
+    let names: Vec<&str> = patches.files.iter().map(|file| get_patch_name(file)).collect();
+    fs::write(&series_file, get_series(&series, &names))?;
+    Ok(())
+}
+
+fn run_quilt(build_files_dir: &Path, args: &[&str]) -> Result<i32> {
+    let output = Command::new("quilt")
+        .arg("--quiltrc=-")
+        .args(args)
+        .current_dir(build_files_dir)
+        .env("QUILT_PATCHES", "debian/patches")
+        .output()
+        .map_err(|err| eyre!("Failed to run quilt, install it to apply patches: {}", err))?;
+    let code = output.status.code().unwrap_or(-1);
+    // 2 is nothing to push or pop
+    if code != 0 && code != 2 {
+        return Err(eyre!(
+            "quilt {} failed: {}{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(code)
+}
+
+/// Applies the series from scratch, patches applied by a previous run are popped first.
+pub fn add_patches(build_files_dir: &Path, patches: &PatchesConfig) -> Result<()> {
+    let applied = build_files_dir.join(".pc/applied-patches");
+    if fs::read_to_string(&applied).is_ok_and(|content| !content.trim().is_empty()) {
+        run_quilt(build_files_dir, &["pop", "-a"])?;
+    }
+    copy_patches(build_files_dir, patches)?;
+    run_quilt(build_files_dir, &["push", "-a"])?;
+    info!("Applied {} patches", patches.files.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_patches_are_appended_to_the_series() {
+        assert_eq!(
+            get_series("upstream-fix.patch -p1\n", &["upstream-fix.patch", "arm64.patch"]),
+            "upstream-fix.patch -p1\narm64.patch\n"
+        );
+        assert_eq!(get_series("", &["arm64.patch"]), "arm64.patch\n");
+
+        let dir = tempdir().unwrap();
+        let build_files_dir = dir.path().join("hello-world-1.0.0");
+        fs::create_dir_all(build_files_dir.join("debian/source")).unwrap();
+        let patch_file = dir.path().join("arm64.patch");
+        fs::write(&patch_file, "--- a/Makefile\n+++ b/Makefile\n").unwrap();
+        let patches = PatchesConfig {
+            files: vec![patch_file.to_str().unwrap().to_string()],
+        };
+        fs::write(build_files_dir.join("debian/source/format"), "3.0 (native)\n").unwrap();
+        let err = copy_patches(&build_files_dir, &patches).unwrap_err();
+        assert!(err.to_string().starts_with("patches need the 3.0 (quilt) source format"));
+
+        fs::write(build_files_dir.join("debian/source/format"), "3.0 (quilt)\n").unwrap();
+        copy_patches(&build_files_dir, &patches).unwrap();
+        copy_patches(&build_files_dir, &patches).unwrap();
+        let series = fs::read_to_string(build_files_dir.join("debian/patches/series")).unwrap();
+        assert_eq!(series, "arm64.patch\n");
+        assert!(build_files_dir.join("debian/patches/arm64.patch").exists());
+    }
+}
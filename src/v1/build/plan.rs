@@ -0,0 +1,112 @@
+// The build plan of a package: the resolved paths, the inputs the binaries depend on and the
+// stages of `package` with the commands they run, printed by `pkg-builder plan --json` for
+// external orchestrators. The JSON document is a stable interface, fields are only added,
+// renaming or removing one bumps PLAN_SCHEMA_VERSION.
+use eyre::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct PlanStage {
+    pub name: String,
+    /// argv of each command, empty for the steps pkg-builder runs itself
+    pub commands: Vec<Vec<String>>,
+    /// working directory of the commands
+    pub cwd: Option<String>,
+}
+
+impl PlanStage {
+    pub fn new(name: &str) -> Self {
+        PlanStage {
+            name: name.to_string(),
+            commands: vec![],
+            cwd: None,
+        }
+    }
+}
+
+/// A stage of package run as root through the privilege tool of the build env.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct PrivilegedOperation {
+    pub name: String,
+    /// the privilege tool and the program it runs
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct BuildPlan {
+    pub schema_version: u32,
+    pub package_name: String,
+    pub version: String,
+    pub codename: String,
+    pub arch: String,
+    pub paths: BTreeMap<String, String>,
+    /// the fingerprint of the build manifest, extended with the hashes of the local inputs
+    pub inputs: BTreeMap<String, String>,
+    pub stages: Vec<PlanStage>,
+    pub privileged: Vec<PrivilegedOperation>,
+}
+
+impl BuildPlan {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn format_summary(&self) -> String {
+        let mut summary = format!(
+            "{} {} for {} {}\n",
+            self.package_name, self.version, self.codename, self.arch
+        );
+        for stage in self.stages.iter() {
+            summary.push_str(&format!("{}\n", stage.name));
+            for command in stage.commands.iter() {
+                summary.push_str(&format!("    {}\n", command.join(" ")));
+            }
+        }
+        for operation in self.privileged.iter() {
+            summary.push_str(&format!("needs root: {} ({})\n", operation.name, operation.command.join(" ")));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_plan_json_is_stable() {
+        let mut stage = PlanStage::new("sbuild");
+        stage.commands.push(vec!["sbuild".to_string(), "-d".to_string(), "bookworm".to_string()]);
+        let plan = BuildPlan {
+            schema_version: PLAN_SCHEMA_VERSION,
+            package_name: "hello-world".to_string(),
+            version: "1.0.0-1".to_string(),
+            codename: "bookworm".to_string(),
+            arch: "amd64".to_string(),
+            paths: BTreeMap::from([("workdir".to_string(), "/tmp/workdir".to_string())]),
+            inputs: BTreeMap::from([("build_env.codename".to_string(), "bookworm".to_string())]),
+            stages: vec![PlanStage::new("source"), stage],
+            privileged: vec![PrivilegedOperation {
+                name: "piuparts".to_string(),
+                command: vec!["sudo".to_string(), "-n".to_string(), "piuparts".to_string()],
+            }],
+        };
+        let json: Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            ["arch", "codename", "inputs", "package_name", "paths", "privileged", "schema_version", "stages", "version"]
+        );
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["stages"][1]["commands"][0][2], "bookworm");
+        assert_eq!(json["stages"][0]["cwd"], Value::Null);
+        assert_eq!(
+            plan.format_summary(),
+            "hello-world 1.0.0-1 for bookworm amd64\nsource\nsbuild\n    sbuild -d bookworm\nneeds root: piuparts (sudo -n piuparts)\n"
+        );
+    }
+}
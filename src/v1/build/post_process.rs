@@ -0,0 +1,225 @@
+// Derived outputs of the checked debs, e.g. a container image with them installed. Each step
+// writes into post-process/<name>/ next to the debs, every file it leaves there is listed in the
+// build manifest as a secondary artifact with its sha256.
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use crate::v1::build::manifest::SecondaryArtifact;
+use crate::v1::pkg_config::{PostProcessConfig, PostProcessStep, PostProcessor};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const POST_PROCESS_DIR: &str = "post-process";
+
+/// Inputs of the steps.
+pub struct PostProcessInputs<'a> {
+    pub artifacts_dir: &'a Path,
+    pub debs: &'a [PathBuf],
+    pub manifest_file: &'a Path,
+    /// e.g. hello-world_1.0.0-1_amd64, the outputs are named after it
+    pub base_name: String,
+    /// base image of the oci processor unless the step sets one
+    pub image: Option<String>,
+    /// "docker" or "podman", builds and saves the oci images
+    pub engine: &'static str,
+}
+
+fn run(command: &mut Command, what: &str) -> Result<()> {
+    let status = command.status().map_err(|err| eyre!("Failed to run {}: {}", what, err))?;
+    if !status.success() {
+        return Err(eyre!("{} exited with {}", what, status));
+    }
+    Ok(())
+}
+
+fn get_deb_names(debs: &[PathBuf]) -> Vec<String> {
+    debs.iter()
+        .map(|deb| deb.file_name().unwrap().to_string_lossy().to_string())
+        .collect()
+}
+
+// fixed order, owners and times, so the same debs give the same bundle
+fn create_tar(inputs: &PostProcessInputs, out_file: &Path) -> Result<()> {
+    run(
+        Command::new("tar")
+            .args(["--sort=name", "--mtime=@0", "--owner=0", "--group=0", "--numeric-owner", "-cf"])
+            .arg(out_file)
+            .arg("-C")
+            .arg(inputs.artifacts_dir)
+            .args(get_deb_names(inputs.debs)),
+        "tar",
+    )
+}
+
+fn create_squashfs(inputs: &PostProcessInputs, out_file: &Path) -> Result<()> {
+    let staging_dir = tempfile::tempdir()?;
+    for deb in inputs.debs {
+        fs::copy(deb, staging_dir.path().join(deb.file_name().unwrap()))?;
+    }
+    run(
+        Command::new("mksquashfs")
+            .arg(staging_dir.path())
+            .arg(out_file)
+            .args(["-noappend", "-all-root", "-mkfs-time", "0", "-all-time", "0"]),
+        "mksquashfs",
+    )
+}
+
+fn get_oci_containerfile(image: &str) -> String {
+    format!(
+        "FROM {}\nCOPY debs/ /tmp/debs/\nRUN apt-get update && apt-get install -y --no-install-recommends /tmp/debs/*.deb && rm -rf /tmp/debs /var/lib/apt/lists/*\n",
+        image
+    )
+}
+
+fn create_oci_image(inputs: &PostProcessInputs, step: &PostProcessStep, out_file: &Path) -> Result<()> {
+    let image = step
+        .image
+        .clone()
+        .or_else(|| inputs.image.clone())
+        .ok_or_else(|| eyre!("post_process {}: no container image known for the suite, set image", step.name))?;
+    let context_dir = tempfile::tempdir()?;
+    let debs_dir = context_dir.path().join("debs");
+    fs::create_dir_all(&debs_dir)?;
+    for deb in inputs.debs {
+        fs::copy(deb, debs_dir.join(deb.file_name().unwrap()))?;
+    }
+    let containerfile = context_dir.path().join("Containerfile");
+    fs::write(&containerfile, get_oci_containerfile(&image))?;
+    let tag = format!("pkg-builder/{}:{}", step.name, inputs.base_name.to_lowercase().replace(['_', '~', '+'], "-"));
+    run(
+        Command::new(inputs.engine)
+            .args(["build", "-t", &tag, "-f"])
+            .arg(&containerfile)
+            .arg(context_dir.path()),
+        &format!("{} build", inputs.engine),
+    )?;
+    let mut save = Command::new(inputs.engine);
+    save.arg("save");
+    // docker only writes its own archive format
+    if inputs.engine == "podman" {
+        save.args(["--format", "oci-archive"]);
+    }
+    run(save.arg("-o").arg(out_file).arg(&tag), &format!("{} save", inputs.engine))
+}
+
+fn run_command(inputs: &PostProcessInputs, command: &str, out_dir: &Path) -> Result<()> {
+    run(
+        Command::new("sh")
+            .args(["-c", command])
+            .current_dir(out_dir)
+            .env("PKG_BUILDER_MANIFEST", inputs.manifest_file)
+            .env("PKG_BUILDER_ARTIFACTS_DIR", inputs.artifacts_dir)
+            .env("PKG_BUILDER_OUTPUT_DIR", out_dir),
+        command,
+    )
+}
+
+fn collect_outputs(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_outputs(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn run_step(inputs: &PostProcessInputs, step: &PostProcessStep) -> Result<Vec<SecondaryArtifact>> {
+    let out_dir = inputs.artifacts_dir.join(POST_PROCESS_DIR).join(&step.name);
+    // outputs of an earlier run of the step are not outputs of this one
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir)?;
+    }
+    fs::create_dir_all(&out_dir)?;
+    info!("Running post_process step {}", step.name);
+    let out_file = |extension: &str| out_dir.join(format!("{}.{}.{}", inputs.base_name, step.name, extension));
+    match (&step.processor, &step.command) {
+        (Some(PostProcessor::Tar), _) => create_tar(inputs, &out_file("tar"))?,
+        (Some(PostProcessor::Squashfs), _) => create_squashfs(inputs, &out_file("squashfs"))?,
+        (Some(PostProcessor::Oci), _) => create_oci_image(inputs, step, &out_file("image.tar"))?,
+        (None, Some(command)) => run_command(inputs, command, &out_dir)?,
+        (None, None) => return Err(eyre!("post_process {} needs either processor or command", step.name)),
+    }
+    let mut files = vec![];
+    collect_outputs(&out_dir, &mut files)?;
+    files.sort();
+    if files.is_empty() {
+        return Err(eyre!("post_process {} produced nothing in {}", step.name, out_dir.display()));
+    }
+    let mut outputs = vec![];
+    for file in files {
+        outputs.push(SecondaryArtifact {
+            step: step.name.clone(),
+            path: file.strip_prefix(inputs.artifacts_dir)?.to_string_lossy().to_string(),
+            sha256: calculate_sha256(open_for_hashing(&file)?)?,
+        });
+    }
+    Ok(outputs)
+}
+
+/// Runs the steps in order, the outputs of every step.
+pub fn run_post_process(config: &PostProcessConfig, inputs: &PostProcessInputs) -> Result<Vec<SecondaryArtifact>> {
+    let mut outputs = vec![];
+    for step in config.steps.iter() {
+        outputs.extend(run_step(inputs, step)?);
+    }
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::apt_repo::tests::create_deb;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_post_process_outputs_are_secondary_artifacts() {
+        let dir = tempdir().unwrap();
+        let deb = create_deb(dir.path(), "hello-world", "1.0.0-1", "amd64");
+        let manifest_file = dir.path().join("hello-world_1.0.0-1_amd64.manifest.toml");
+        fs::write(&manifest_file, "package_name = \"hello-world\"\n").unwrap();
+        let inputs = PostProcessInputs {
+            artifacts_dir: dir.path(),
+            debs: std::slice::from_ref(&deb),
+            manifest_file: &manifest_file,
+            base_name: "hello-world_1.0.0-1_amd64".to_string(),
+            image: None,
+            engine: "podman",
+        };
+        let config = PostProcessConfig {
+            steps: vec![
+                PostProcessStep {
+                    name: "bundle".to_string(),
+                    processor: Some(PostProcessor::Tar),
+                    ..Default::default()
+                },
+                PostProcessStep {
+                    name: "release-notes".to_string(),
+                    command: Some("cp \"$PKG_BUILDER_MANIFEST\" notes.toml".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+        let outputs = run_post_process(&config, &inputs).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].path, "post-process/bundle/hello-world_1.0.0-1_amd64.bundle.tar");
+        assert_eq!(outputs[1].step, "release-notes");
+        assert_eq!(outputs[1].path, "post-process/release-notes/notes.toml");
+        // the bundle is the same for the same debs
+        assert_eq!(run_post_process(&config, &inputs).unwrap(), outputs);
+
+        assert!(get_oci_containerfile("docker.io/library/debian:bookworm").starts_with("FROM docker.io/library/debian:bookworm\n"));
+        let empty = PostProcessConfig {
+            steps: vec![PostProcessStep {
+                name: "nothing".to_string(),
+                command: Some("true".to_string()),
+                ..Default::default()
+            }],
+        };
+        assert!(run_post_process(&empty, &inputs).unwrap_err().to_string().contains("produced nothing"));
+    }
+}
@@ -0,0 +1,123 @@
+// Commands needing root, piuparts and the creation of the autopkgtest qemu image, all go through
+// the configured privilege tool. Unless build_env.privilege_prompt is set the tool must not ask
+// for a password, check runs it once before the build so an unattended build fails right away
+// instead of waiting for input in the middle of it.
+use crate::v1::pkg_config::{BuildEnv, PrivilegeTool};
+use eyre::{eyre, Result};
+use log::info;
+use std::process::{Command, Stdio};
+
+pub struct PrivilegeBroker {
+    tool: PrivilegeTool,
+    prompt: bool,
+}
+
+impl PrivilegeBroker {
+    pub fn new(build_env: &BuildEnv) -> Self {
+        PrivilegeBroker {
+            tool: build_env.privilege_tool.unwrap_or(PrivilegeTool::Sudo),
+            prompt: build_env.privilege_prompt.unwrap_or(false),
+        }
+    }
+
+    /// argv the command of operation is prefixed with.
+    pub fn get_prefix(&self, operation: &str) -> Result<Vec<String>> {
+        let prefix: &[&str] = match (self.tool, self.prompt) {
+            (PrivilegeTool::Never, _) => {
+                return Err(eyre!(
+                    "{} needs root, but build_env.privilege_tool is never, disable it or set a privilege tool",
+                    operation
+                ))
+            }
+            // the password can be piped in, e.g. by CI
+            (PrivilegeTool::Sudo, true) => &["sudo", "-S"],
+            (PrivilegeTool::Sudo, false) => &["sudo", "-n"],
+            (PrivilegeTool::Doas, true) => &["doas"],
+            (PrivilegeTool::Doas, false) => &["doas", "-n"],
+            (PrivilegeTool::Pkexec, true) => &["pkexec"],
+            (PrivilegeTool::Pkexec, false) => &["pkexec", "--disable-internal-agent"],
+        };
+        Ok(prefix.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    /// cmd run through the privilege tool.
+    pub fn wrap(&self, operation: &str, cmd: &Command) -> Result<Command> {
+        let prefix = self.get_prefix(operation)?;
+        let mut wrapped = Command::new(&prefix[0]);
+        wrapped.args(&prefix[1..]);
+        if let Some(dir) = cmd.get_current_dir() {
+            // pkexec starts the command in the home dir of root
+            if self.tool == PrivilegeTool::Pkexec {
+                wrapped.arg("env").arg(format!("--chdir={}", dir.display()));
+            }
+            wrapped.current_dir(dir);
+        }
+        wrapped.arg(cmd.get_program()).args(cmd.get_args());
+        Ok(wrapped)
+    }
+
+    /// Fails before the build when operations need root and the tool cannot get it without
+    /// asking, with privilege_prompt sudo asks for the password now.
+    pub fn check(&self, operations: &[&str]) -> Result<()> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+        let operations = operations.join(", ");
+        let prefix = self.get_prefix(&operations)?;
+        let mut cmd = Command::new(&prefix[0]);
+        match (self.tool, self.prompt) {
+            (PrivilegeTool::Sudo, true) => {
+                info!("{} need root, please provide your password to sudo, if prompted.", operations);
+                cmd.args(&prefix[1..]).arg("-v");
+            }
+            // doas and pkexec cannot ask ahead of time
+            (_, true) => return Ok(()),
+            (_, false) => {
+                cmd.args(&prefix[1..]).arg("true").stdin(Stdio::null());
+            }
+        }
+        let status = cmd
+            .status()
+            .map_err(|err| eyre!("{} need root, but {} could not be run: {}", operations, prefix[0], err))?;
+        if !status.success() {
+            return Err(eyre!(
+                "{} need root, but {} {} failed, allow {} without a password or set build_env.privilege_prompt = true",
+                operations,
+                prefix.join(" "),
+                if self.prompt { "-v" } else { "true" },
+                self.tool.as_str()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_privileged_commands_follow_the_tool() {
+        let mut build_env = BuildEnv::default();
+        let broker = PrivilegeBroker::new(&build_env);
+        let mut cmd = Command::new("piuparts");
+        cmd.arg("-d").arg("bookworm").current_dir("/tmp");
+        let wrapped = broker.wrap("piuparts", &cmd).unwrap();
+        assert_eq!(wrapped.get_program(), "sudo");
+        let args: Vec<&str> = wrapped.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args, ["-n", "piuparts", "-d", "bookworm"]);
+        assert!(broker.check(&[]).is_ok());
+
+        build_env.privilege_tool = Some(PrivilegeTool::Pkexec);
+        let wrapped = PrivilegeBroker::new(&build_env).wrap("piuparts", &cmd).unwrap();
+        let args: Vec<&str> = wrapped.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args[..3], ["--disable-internal-agent", "env", "--chdir=/tmp"]);
+
+        build_env.privilege_tool = Some(PrivilegeTool::Never);
+        let broker = PrivilegeBroker::new(&build_env);
+        assert!(broker.wrap("piuparts", &cmd).is_err());
+        assert!(broker.check(&[]).is_ok());
+        let err = broker.check(&["piuparts"]).unwrap_err().to_string();
+        assert!(err.starts_with("piuparts needs root"));
+    }
+}
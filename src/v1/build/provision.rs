@@ -0,0 +1,441 @@
+// Provisions the build chroot with toolchains not in the archive. Pinned downloads are fetched
+// and checked on the host, cached by their hash and staged into the chroot, the commands left
+// to run in the chroot are checked one by one and report their result in the build log.
+use crate::v1::build::dir_setup::{calculate_sha256, calculate_sha512, create_exclusively};
+use crate::v1::build::manifest::{build_log_marker_command, BUILD_LOG_MARKER};
+use crate::v1::build::quality::ProvisionResult;
+use crate::v1::build::sbuild::calculate_sha1;
+use crate::v1::build::store::ContentStore;
+use eyre::{eyre, Result};
+use log::info;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const PROVISION_MARKER_PREFIX: &str = "provision-";
+// output lines of quiet steps are prefixed, e.g. "pkg-builder-provision: go-unpack: <line>"
+pub const PROVISION_LOG_MARKER: &str = "pkg-builder-provision: ";
+// lines of a failed quiet step shown on the console
+const PROVISION_LOG_TAIL: usize = 30;
+const QUIET_STEP_LOG: &str = "/tmp/pkg-builder-provision.log";
+
+/// Algorithm of a pinned hash by its length, sha1 for the dotnet debs, sha256 otherwise.
+pub fn get_hash_algorithm(hash: &str) -> &'static str {
+    match hash.len() {
+        40 => "sha1",
+        128 => "sha512",
+        _ => "sha256",
+    }
+}
+
+fn calculate_hash(algorithm: &str, file: &Path) -> Result<String> {
+    let reader = fs::File::open(file)?;
+    match algorithm {
+        "sha1" => Ok(calculate_sha1(reader)?),
+        "sha512" => calculate_sha512(reader),
+        _ => calculate_sha256(reader),
+    }
+}
+
+// the file is removed unless its hash matched
+fn download_verified(url: &str, hash: &str, file: &Path) -> Result<()> {
+    info!("Downloading {} to stage it into the chroot", url);
+    let status = Command::new("wget").arg("-q").arg("-O").arg(file).arg(url).status()?;
+    if !status.success() {
+        return Err(eyre!("Failed to download {}: wget exited with {}", url, status));
+    }
+    let algorithm = get_hash_algorithm(hash);
+    let actual_hash = calculate_hash(algorithm, file)?;
+    if actual_hash != hash.to_lowercase() {
+        fs::remove_file(file)?;
+        return Err(eyre!("{} actual {} is {}, expected {}", url, algorithm, actual_hash, hash));
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProvisionStep {
+    /// file fetched on the host, checked against its hash and copied to path in the chroot,
+    /// see get_hash_algorithm
+    Stage { url: String, hash: String, path: String },
+    /// file prepared on the host before the build, e.g. a keyring, copied to path in the chroot
+    Copy { file: PathBuf, path: String },
+    /// command run in the chroot, name is unique within the provisioner
+    Run { name: String, command: String },
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ChrootProvisioner {
+    pub steps: Vec<ProvisionStep>,
+    /// the output of each step is printed prefixed after it finished, see ProvisionLog
+    pub quiet: bool,
+}
+
+/// Runs the command in the chroot, reports provision-<name>=ok or =failed and stops the setup on failure.
+pub fn checked_command(name: &str, command: &str) -> String {
+    let key = format!("{}{}", PROVISION_MARKER_PREFIX, name);
+    format!(
+        "if {{ {}; }}; then {}; else {} && exit 1; fi",
+        command,
+        build_log_marker_command(&key, "ok"),
+        build_log_marker_command(&key, "failed")
+    )
+}
+
+/// Like checked_command, the output is gathered and printed with the PROVISION_LOG_MARKER
+/// prefix once the command finished, followed by its exit code.
+pub fn quiet_checked_command(name: &str, command: &str) -> String {
+    let key = format!("{}{}", PROVISION_MARKER_PREFIX, name);
+    format!(
+        "{{ if {{ {}; }} > {log} 2>&1; then status=0; else status=$?; fi; echo \"exit code $status\" >> {log}; \
+         sed 's/^/{}{}: /' {log}; if [ $status -eq 0 ]; then {}; else {} && exit 1; fi; }}",
+        command,
+        PROVISION_LOG_MARKER,
+        name,
+        build_log_marker_command(&key, "ok"),
+        build_log_marker_command(&key, "failed"),
+        log = QUIET_STEP_LOG
+    )
+}
+
+impl ChrootProvisioner {
+    pub fn new() -> Self {
+        ChrootProvisioner::default()
+    }
+
+    pub fn stage(&mut self, url: &str, hash: &str, path: &str) -> &mut Self {
+        self.steps.push(ProvisionStep::Stage {
+            url: url.to_string(),
+            hash: hash.to_lowercase(),
+            path: path.to_string(),
+        });
+        self
+    }
+
+    pub fn copy(&mut self, file: &Path, path: &str) -> &mut Self {
+        self.steps.push(ProvisionStep::Copy {
+            file: file.to_path_buf(),
+            path: path.to_string(),
+        });
+        self
+    }
+
+    pub fn run(&mut self, name: &str, command: impl Into<String>) -> &mut Self {
+        self.steps.push(ProvisionStep::Run {
+            name: name.to_string(),
+            command: command.into(),
+        });
+        self
+    }
+
+    /// Chroot setup commands, staged files are checked again once they are in the chroot.
+    pub fn get_setup_commands(&self) -> Vec<String> {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                ProvisionStep::Stage { hash, path, .. } => {
+                    let file_name = path.rsplit('/').next().unwrap_or(path);
+                    (
+                        format!("stage-{}", file_name),
+                        format!("echo \"{}  {}\" | {}sum -c", hash, path, get_hash_algorithm(hash)),
+                    )
+                }
+                ProvisionStep::Copy { path, .. } => {
+                    let file_name = path.rsplit('/').next().unwrap_or(path);
+                    (format!("copy-{}", file_name), format!("test -s {}", path))
+                }
+                ProvisionStep::Run { name, command } => (name.clone(), command.clone()),
+            })
+            .map(|(name, command)| match self.quiet {
+                true => quiet_checked_command(&name, &command),
+                false => checked_command(&name, &command),
+            })
+            .collect()
+    }
+
+    /// Host file in cache_dir and chroot path of every staged file, copied files are staged as is.
+    pub fn get_staged_files(&self, cache_dir: &Path) -> Vec<(PathBuf, String)> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                ProvisionStep::Stage { hash, path, .. } => Some((cache_dir.join(hash), path.clone())),
+                ProvisionStep::Copy { file, path } => Some((file.clone(), path.clone())),
+                ProvisionStep::Run { .. } => None,
+            })
+            .collect()
+    }
+
+    /// sbuild pre-build commands copying the staged files into the chroot.
+    pub fn get_stage_commands(&self, cache_dir: &Path) -> Vec<String> {
+        self.get_staged_files(cache_dir)
+            .into_iter()
+            .map(|(host_path, path)| {
+                let dir = match path.rsplit_once('/') {
+                    Some((dir, _)) if !dir.is_empty() => dir,
+                    _ => "/",
+                };
+                format!(
+                    "%SBUILD_CHROOT_EXEC sh -c 'mkdir -p {} && cat > {}' < '{}'",
+                    dir,
+                    path,
+                    host_path.display()
+                )
+            })
+            .collect()
+    }
+
+    fn get_downloads(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.steps.iter().filter_map(|step| match step {
+            ProvisionStep::Stage { url, hash, .. } => Some((url, hash)),
+            ProvisionStep::Copy { .. } | ProvisionStep::Run { .. } => None,
+        })
+    }
+
+    /// Puts the staged files missing from cache_dir there, from the store or downloaded, a file
+    /// is only cached once its hash matched.
+    pub fn fetch(&self, cache_dir: &Path, store: &ContentStore) -> Result<()> {
+        for (url, hash) in self.get_downloads() {
+            let object_path = store.object_path(get_hash_algorithm(hash), hash)?;
+            create_exclusively(&cache_dir.join(hash), |tmp_file| {
+                if !object_path.exists() {
+                    return download_verified(url, hash, tmp_file);
+                }
+                info!("Staging {} from the store", url);
+                fs::copy(&object_path, tmp_file)?;
+                Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Downloads the staged files missing from the store into it.
+    pub fn fetch_into_store(&self, store: &ContentStore) -> Result<()> {
+        for (url, hash) in self.get_downloads() {
+            let algorithm = get_hash_algorithm(hash);
+            if store.object_path(algorithm, hash)?.exists() {
+                continue;
+            }
+            let tmp_file = store.get_tmp_dir()?.join(hash);
+            download_verified(url, hash, &tmp_file)?;
+            store.insert(algorithm, hash, &tmp_file)?;
+        }
+        Ok(())
+    }
+
+    /// Urls of the staged files missing from both cache_dir and the store.
+    pub fn get_missing_downloads(&self, cache_dir: &Path, store: &ContentStore) -> Vec<String> {
+        self.get_downloads()
+            .filter(|(_, hash)| !cache_dir.join(hash).exists() && !store.contains(get_hash_algorithm(hash), hash))
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// Copies the staged files to their chroot paths under root, e.g. a chroot extracted on the host.
+    pub fn stage_into(&self, cache_dir: &Path, root: &Path) -> Result<()> {
+        for (host_path, path) in self.get_staged_files(cache_dir) {
+            let target = root.join(path.trim_start_matches('/'));
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::copy(&host_path, &target)
+                .map_err(|err| eyre!("Failed to stage {} to {}: {}", host_path.display(), target.display(), err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Filters the build output of quiet steps: a progress line per step, and the tail of the
+/// output of a failed step.
+#[derive(Debug, Default)]
+pub struct ProvisionLog {
+    tails: BTreeMap<String, VecDeque<String>>,
+}
+
+impl ProvisionLog {
+    pub fn new() -> Self {
+        ProvisionLog::default()
+    }
+
+    /// Lines to show for a line of the build output.
+    pub fn filter_line(&mut self, line: &str) -> Vec<String> {
+        if let Some((name, output)) = line.strip_prefix(PROVISION_LOG_MARKER).and_then(|line| line.split_once(": ")) {
+            let tail = self.tails.entry(name.to_string()).or_default();
+            tail.push_back(output.to_string());
+            if tail.len() > PROVISION_LOG_TAIL {
+                tail.pop_front();
+            }
+            return vec![];
+        }
+        let Some(result) = parse_provision_results(line).pop() else {
+            return vec![line.to_string()];
+        };
+        let tail = self.tails.remove(&result.step).unwrap_or_default();
+        let mut lines = vec![format!("Provisioning {}: {}", result.step, result.status)];
+        if result.status != "ok" {
+            lines.extend(tail.into_iter().map(|line| format!("  {}", line)));
+        }
+        lines
+    }
+}
+
+/// Full output of every quiet step, by step name.
+pub fn parse_provision_logs(build_log: &str) -> BTreeMap<String, String> {
+    let mut logs: BTreeMap<String, String> = BTreeMap::new();
+    for line in build_log.lines() {
+        if let Some((name, output)) = line.strip_prefix(PROVISION_LOG_MARKER).and_then(|line| line.split_once(": ")) {
+            let log = logs.entry(name.to_string()).or_default();
+            log.push_str(output);
+            log.push('\n');
+        }
+    }
+    logs
+}
+
+/// Result of every provisioning step which ran, in the order of the build log.
+pub fn parse_provision_results(build_log: &str) -> Vec<ProvisionResult> {
+    build_log
+        .lines()
+        .filter_map(|line| line.strip_prefix(BUILD_LOG_MARKER))
+        .filter_map(|marker| {
+            let (key, status) = marker.split_once('=')?;
+            Some(ProvisionResult {
+                step: key.trim().strip_prefix(PROVISION_MARKER_PREFIX)?.to_string(),
+                status: status.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_provisioner_commands() {
+        let mut provisioner = ChrootProvisioner::new();
+        provisioner
+            .stage("https://go.dev/dl/go1.22.4.linux-amd64.tar.gz", &"a".repeat(64), "/tmp/go.tar.gz")
+            .run("go-unpack", "tar -C /usr/local -xzf /tmp/go.tar.gz");
+        assert_eq!(
+            provisioner.get_setup_commands(),
+            vec![
+                format!(
+                    "if {{ echo \"{}  /tmp/go.tar.gz\" | sha256sum -c; }}; then {}; else {} && exit 1; fi",
+                    "a".repeat(64),
+                    build_log_marker_command("provision-stage-go.tar.gz", "ok"),
+                    build_log_marker_command("provision-stage-go.tar.gz", "failed")
+                ),
+                format!(
+                    "if {{ tar -C /usr/local -xzf /tmp/go.tar.gz; }}; then {}; else {} && exit 1; fi",
+                    build_log_marker_command("provision-go-unpack", "ok"),
+                    build_log_marker_command("provision-go-unpack", "failed")
+                ),
+            ]
+        );
+        let cache_dir = Path::new("/var/cache/pkg-builder/downloads");
+        assert_eq!(
+            provisioner.get_stage_commands(cache_dir),
+            vec![format!(
+                "%SBUILD_CHROOT_EXEC sh -c 'mkdir -p /tmp && cat > /tmp/go.tar.gz' < '/var/cache/pkg-builder/downloads/{}'",
+                "a".repeat(64)
+            )]
+        );
+
+        // the pinned dotnet debs are checked by their sha1
+        let mut dotnet = ChrootProvisioner::new();
+        dotnet.stage("https://example.com/dotnet-host.deb", &"B".repeat(40), "/tmp/dotnet/dotnet-host.deb");
+        assert!(dotnet.get_setup_commands()[0].contains(&format!("\"{}  /tmp/dotnet/dotnet-host.deb\" | sha1sum -c", "b".repeat(40))));
+    }
+
+    #[test]
+    fn test_stage_into_and_results() {
+        let cache_dir = tempdir().unwrap();
+        let root = tempdir().unwrap();
+        let sha256 = calculate_sha256("go".as_bytes()).unwrap();
+        fs::write(cache_dir.path().join(&sha256), "go").unwrap();
+        let mut provisioner = ChrootProvisioner::new();
+        provisioner.stage("https://go.dev/dl/go.tar.gz", &sha256, "/tmp/go.tar.gz");
+        let store_dir = tempdir().unwrap();
+        let store = ContentStore::new(store_dir.path().to_path_buf());
+        // already cached, nothing is downloaded
+        assert!(provisioner.get_missing_downloads(cache_dir.path(), &store).is_empty());
+        provisioner.fetch(cache_dir.path(), &store).unwrap();
+        provisioner.stage_into(cache_dir.path(), root.path()).unwrap();
+        assert_eq!(fs::read_to_string(root.path().join("tmp/go.tar.gz")).unwrap(), "go");
+
+        // a host file is copied as is, it is neither downloaded nor in the store
+        let keyring = cache_dir.path().join("microsoft.asc");
+        fs::write(&keyring, "key").unwrap();
+        let mut copied = ChrootProvisioner::new();
+        copied.copy(&keyring, "/etc/apt/keyrings/microsoft.asc");
+        assert!(copied.get_missing_downloads(cache_dir.path(), &store).is_empty());
+        assert!(copied.get_setup_commands()[0].starts_with("if { test -s /etc/apt/keyrings/microsoft.asc; }"));
+        copied.stage_into(cache_dir.path(), root.path()).unwrap();
+        assert_eq!(fs::read_to_string(root.path().join("etc/apt/keyrings/microsoft.asc")).unwrap(), "key");
+
+        // an offline build takes the file from the store of pkg-builder fetch
+        let offline_cache_dir = tempdir().unwrap();
+        assert_eq!(
+            provisioner.get_missing_downloads(offline_cache_dir.path(), &store),
+            ["https://go.dev/dl/go.tar.gz"]
+        );
+        store.insert_copy("sha256", &sha256, &cache_dir.path().join(&sha256)).unwrap();
+        provisioner.fetch_into_store(&store).unwrap();
+        assert!(provisioner.get_missing_downloads(offline_cache_dir.path(), &store).is_empty());
+        provisioner.fetch(offline_cache_dir.path(), &store).unwrap();
+        assert_eq!(fs::read_to_string(offline_cache_dir.path().join(&sha256)).unwrap(), "go");
+
+        let build_log = "pkg-builder: provision-stage-go.tar.gz=ok\n\
+                         + tar -C /usr/local -xzf /tmp/go.tar.gz\n\
+                         pkg-builder: provision-go-unpack=failed\n\
+                         pkg-builder: dotnet-source=primary\n";
+        assert_eq!(
+            parse_provision_results(build_log),
+            vec![
+                ProvisionResult {
+                    step: "stage-go.tar.gz".to_string(),
+                    status: "ok".to_string(),
+                },
+                ProvisionResult {
+                    step: "go-unpack".to_string(),
+                    status: "failed".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quiet_provision_log() {
+        let mut provisioner = ChrootProvisioner::new();
+        provisioner.run("go-unpack", "tar -C /usr/local -xzf /tmp/go.tar.gz");
+        provisioner.quiet = true;
+        assert_eq!(
+            provisioner.get_setup_commands(),
+            vec![quiet_checked_command("go-unpack", "tar -C /usr/local -xzf /tmp/go.tar.gz")]
+        );
+        assert!(provisioner.get_setup_commands()[0].contains("sed 's/^/pkg-builder-provision: go-unpack: /'"));
+
+        let build_log = "I: Running chroot setup commands\n\
+                         pkg-builder-provision: apt-update: Get:1 http://deb.debian.org/debian bookworm InRelease\n\
+                         pkg-builder-provision: apt-update: exit code 0\n\
+                         pkg-builder: provision-apt-update=ok\n\
+                         pkg-builder-provision: go-unpack: tar: /tmp/go.tar.gz: Cannot open\n\
+                         pkg-builder-provision: go-unpack: exit code 2\n\
+                         pkg-builder: provision-go-unpack=failed\n";
+        let mut provision_log = ProvisionLog::new();
+        let shown: Vec<String> = build_log.lines().flat_map(|line| provision_log.filter_line(line)).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "I: Running chroot setup commands",
+                "Provisioning apt-update: ok",
+                "Provisioning go-unpack: failed",
+                "  tar: /tmp/go.tar.gz: Cannot open",
+                "  exit code 2",
+            ]
+        );
+        let logs = parse_provision_logs(build_log);
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs["go-unpack"], "tar: /tmp/go.tar.gz: Cannot open\nexit code 2\n");
+    }
+}
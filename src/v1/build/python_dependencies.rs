@@ -0,0 +1,131 @@
+// Downloads the pinned pip dependencies on the host and vendors them into the source tree as
+// wheels, so pip in the build never has to reach PyPI. Every requirement needs a --hash, pip
+// checks them on the download and once more when the wheels are resolved offline.
+use crate::v1::build::dir_setup::add_include_binaries;
+use crate::v1::pkg_config::{Arch, PythonConfig};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// relative to the source root, where debian/rules runs pip
+pub const PYTHON_WHEELS_DIR: &str = "debian/python-wheels";
+
+fn run_host_command(cmd: &mut Command) -> Result<()> {
+    info!("Running {:?}", cmd);
+    let status = cmd
+        .status()
+        .map_err(|err| eyre!("Failed to run {:?}: {}", cmd, err))?;
+    if !status.success() {
+        return Err(eyre!("{:?} failed with {}", cmd, status));
+    }
+    Ok(())
+}
+
+/// Requirements of the file without a --hash, continuation lines are joined first.
+pub fn get_unhashed_requirements(requirements: &str) -> Vec<String> {
+    requirements
+        .replace("\\\n", " ")
+        .lines()
+        .map(|line| line.split(" #").next().unwrap_or_default().trim())
+        // options like --index-url or -r other.txt are no requirements
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('-'))
+        .filter(|line| !line.contains("--hash="))
+        .map(|line| line.split_whitespace().next().unwrap_or_default().to_string())
+        .collect()
+}
+
+// wheels only, building an sdist would run code of the dependency on the host
+fn pip_download(config: &PythonConfig, requirements: &Path, dest: &Path) -> Command {
+    let mut cmd = Command::new("python3");
+    cmd.args(["-m", "pip", "download", "--require-hashes", "--only-binary=:all:"]);
+    if let Some(python_version) = &config.python_version {
+        cmd.arg("--python-version").arg(python_version);
+    }
+    cmd.arg("-r").arg(requirements).arg("-d").arg(dest);
+    cmd
+}
+
+/// Downloads the wheels of requirements_file into debian/python-wheels and resolves them again
+/// without an index, failing on a missing wheel or a hash mismatch.
+pub fn provision_python_dependencies(build_files_dir: &Path, config: &PythonConfig, build_arch: &str) -> Result<()> {
+    // pip picks the wheels of the host, compiled ones would not run in a chroot of another arch
+    if build_arch != Arch::host().as_str() {
+        return Err(eyre!(
+            "offline_dependencies downloads the wheels for {}, the build for {} has to run on a {} host",
+            Arch::host().as_str(),
+            build_arch,
+            build_arch
+        ));
+    }
+    let requirements_file = config
+        .requirements_file
+        .as_deref()
+        .ok_or_else(|| eyre!("offline_dependencies is set, but requirements_file is not"))?;
+    let requirements = build_files_dir.join(requirements_file);
+    let content = fs::read_to_string(&requirements).map_err(|err| {
+        eyre!(
+            "offline_dependencies is set, but {} cannot be read: {}",
+            requirements.display(),
+            err
+        )
+    })?;
+    let unhashed = get_unhashed_requirements(&content);
+    if !unhashed.is_empty() {
+        return Err(eyre!(
+            "{} has requirements without --hash: {}, generate it with pip-compile --generate-hashes",
+            requirements_file,
+            unhashed.join(", ")
+        ));
+    }
+    let wheels_dir = build_files_dir.join(PYTHON_WHEELS_DIR);
+    fs::create_dir_all(&wheels_dir)?;
+    run_host_command(&mut pip_download(config, &requirements, &wheels_dir))?;
+    let verify_dir = tempfile::tempdir()?;
+    run_host_command(
+        pip_download(config, &requirements, verify_dir.path())
+            .arg("--no-index")
+            .arg("--find-links")
+            .arg(&wheels_dir),
+    )?;
+    add_include_binaries(build_files_dir, PYTHON_WHEELS_DIR)?;
+    info!("Python dependencies provisioned into {}", wheels_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_requirements_need_hashes() {
+        let requirements = "\
+# pip-compile --generate-hashes pyproject.toml
+--index-url https://pypi.org/simple
+click==8.1.7 \\
+    --hash=sha256:ae74fb96c20a0277a1d615f1e4d73c8414f5a98db8b799a7931d1582f3390c28
+requests==2.31.0  # via hello-world
+";
+        assert_eq!(get_unhashed_requirements(requirements), ["requests==2.31.0"]);
+
+        let build_files_dir = tempdir().unwrap();
+        fs::write(build_files_dir.path().join("requirements.txt"), requirements).unwrap();
+        let config = PythonConfig {
+            requirements_file: Some("requirements.txt".to_string()),
+            offline_dependencies: Some(true),
+            ..Default::default()
+        };
+        let err = provision_python_dependencies(build_files_dir.path(), &config, Arch::host().as_str()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "requirements.txt has requirements without --hash: requests==2.31.0, generate it with pip-compile --generate-hashes"
+        );
+        assert!(!build_files_dir.path().join(PYTHON_WHEELS_DIR).exists());
+
+        let build_arch = if Arch::host() == Arch::Riscv64 { "arm64" } else { "riscv64" };
+        let err = provision_python_dependencies(build_files_dir.path(), &config, build_arch).unwrap_err();
+        assert!(err.to_string().starts_with("offline_dependencies downloads the wheels for"));
+    }
+}
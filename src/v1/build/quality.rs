@@ -0,0 +1,411 @@
+// Structured findings of lintian, piuparts, autopkgtest, the systemd unit, maintainer script, content policy and license checks, parsed from their output
+// and kept as <name>_<version>_<arch>.quality.json next to the built artifacts.
+use eyre::{eyre, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct LintianTag {
+    pub severity: String,
+    pub package: String,
+    pub tag: String,
+    pub details: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PiupartsSection {
+    pub level: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AutopkgtestResult {
+    pub test: String,
+    pub result: String,
+    pub details: String,
+}
+
+/// Level of a finding of the checks, error level findings fail the report.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingLevel {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for FindingLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FindingLevel::Error => "error",
+            FindingLevel::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct UnitFinding {
+    pub unit: String,
+    pub level: FindingLevel,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ScriptFinding {
+    /// e.g. "geth.postinst" or "geth.templates"
+    pub script: String,
+    pub level: FindingLevel,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ContentFinding {
+    /// binary package, e.g. "geth"
+    pub package: String,
+    pub level: FindingLevel,
+    pub message: String,
+}
+
+/// A finding about one unit, script or package of the built debs.
+pub trait Finding {
+    /// The unit, script or package the finding is about.
+    fn subject(&self) -> &str;
+    fn level(&self) -> FindingLevel;
+    fn message(&self) -> &str;
+}
+
+impl Finding for UnitFinding {
+    fn subject(&self) -> &str {
+        &self.unit
+    }
+    fn level(&self) -> FindingLevel {
+        self.level
+    }
+    fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Finding for ScriptFinding {
+    fn subject(&self) -> &str {
+        &self.script
+    }
+    fn level(&self) -> FindingLevel {
+        self.level
+    }
+    fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Finding for ContentFinding {
+    fn subject(&self) -> &str {
+        &self.package
+    }
+    fn level(&self) -> FindingLevel {
+        self.level
+    }
+    fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ArchiveFinding {
+    /// "eol", "valid-until" or "signature"
+    pub check: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct LicenseSummary {
+    /// relative to the source root, "." for the root
+    pub directory: String,
+    pub licenses: Vec<String>,
+}
+
+/// A detected license debian/copyright does not declare.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct LicenseFinding {
+    pub path: String,
+    pub license: String,
+    /// error for an undeclared GPL or AGPL, otherwise warning
+    pub level: FindingLevel,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct LicenseScan {
+    pub directories: Vec<LicenseSummary>,
+    pub findings: Vec<LicenseFinding>,
+}
+
+impl LicenseScan {
+    /// Every detected license once, in order.
+    pub fn get_licenses(&self) -> Vec<String> {
+        let mut licenses: Vec<String> = self
+            .directories
+            .iter()
+            .flat_map(|summary| summary.licenses.iter().cloned())
+            .collect();
+        licenses.sort();
+        licenses.dedup();
+        licenses
+    }
+}
+
+/// Outcome of a step provisioning the build chroot, e.g. unpacking the go toolchain.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ProvisionResult {
+    /// e.g. "go-unpack" or "stage-go.tar.gz"
+    pub step: String,
+    /// "ok" or "failed"
+    pub status: String,
+}
+
+/// Attempt of a test stage that failed on the testbed and was run again.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct RetryAttempt {
+    /// e.g. "autopkgtest"
+    pub stage: String,
+    pub attempt: u32,
+    pub reason: String,
+}
+
+/// One entry per tool, None when the tool did not run.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct QualityReport {
+    pub lintian: Option<Vec<LintianTag>>,
+    pub piuparts: Option<Vec<PiupartsSection>>,
+    pub autopkgtest: Option<Vec<AutopkgtestResult>>,
+    /// the autopkgtest checks of the shipped apparmor profiles
+    pub apparmor: Option<Vec<AutopkgtestResult>>,
+    pub systemd_units: Option<Vec<UnitFinding>>,
+    pub maintainer_scripts: Option<Vec<ScriptFinding>>,
+    pub content_policy: Option<Vec<ContentFinding>>,
+    /// files of the Multi-Arch: same debs conflicting between architectures
+    pub multi_arch: Option<Vec<ContentFinding>>,
+    /// binaries requiring newer GLIBC or GLIBCXX symbols than the target release has
+    pub abi: Option<Vec<ContentFinding>>,
+    /// retried attempts of the test stages, the entries of the tools are of the last attempt
+    pub retries: Option<Vec<RetryAttempt>>,
+    /// end of life and Release file findings of the targeted suite, checked before the build
+    pub archive: Option<Vec<ArchiveFinding>>,
+    /// steps installing the toolchain into the build chroot, up to the failed one
+    pub provision: Option<Vec<ProvisionResult>>,
+    /// chroot packages the build changed, see BuildManifest::build_installs
+    pub build_installs: Option<Vec<ContentFinding>>,
+    /// licenses of the upstream source, scanned before the build
+    pub licenses: Option<LicenseScan>,
+}
+
+impl QualityReport {
+    /// Missing report files start empty, so each tool only replaces its own entry.
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(QualityReport::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|err| eyre!("Failed to parse {}: {}", path.display(), err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Error level findings and failed tests, the report is green when there are none.
+    pub fn get_failures(&self) -> Vec<String> {
+        let mut failures = vec![];
+        for tag in self.lintian.iter().flatten().filter(|tag| tag.severity == "error") {
+            failures.push(format!("lintian: {} {}", tag.package, tag.tag));
+        }
+        for section in self.piuparts.iter().flatten().filter(|section| section.level == "ERROR") {
+            failures.push(format!("piuparts: {}", section.message));
+        }
+        for (tool, results) in [("autopkgtest", &self.autopkgtest), ("apparmor", &self.apparmor)] {
+            for result in results.iter().flatten().filter(|result| result.result == "FAIL") {
+                failures.push(format!("{}: {} failed", tool, result.test));
+            }
+        }
+        for unit in self.systemd_units.iter().flatten().filter(|unit| unit.level == FindingLevel::Error) {
+            failures.push(format!("systemd_units: {} {}", unit.unit, unit.message));
+        }
+        for script in self.maintainer_scripts.iter().flatten().filter(|script| script.level == FindingLevel::Error) {
+            failures.push(format!("maintainer_scripts: {} {}", script.script, script.message));
+        }
+        for (tool, findings) in [
+            ("content_policy", &self.content_policy),
+            ("multi_arch", &self.multi_arch),
+            ("abi", &self.abi),
+            ("build_installs", &self.build_installs),
+        ] {
+            for finding in findings.iter().flatten().filter(|finding| finding.level == FindingLevel::Error) {
+                failures.push(format!("{}: {} {}", tool, finding.package, finding.message));
+            }
+        }
+        for step in self.provision.iter().flatten().filter(|step| step.status == "failed") {
+            failures.push(format!("provision: {} failed", step.step));
+        }
+        for finding in self.licenses.iter().flat_map(|scan| scan.findings.iter()) {
+            if finding.level == FindingLevel::Error {
+                failures.push(format!("licenses: {} {}", finding.path, finding.message));
+            }
+        }
+        failures
+    }
+}
+
+fn lintian_severity(code: &str) -> Option<&'static str> {
+    match code {
+        "E" => Some("error"),
+        "W" => Some("warning"),
+        "I" => Some("info"),
+        "P" => Some("pedantic"),
+        "X" => Some("experimental"),
+        "O" => Some("overridden"),
+        "C" => Some("classification"),
+        "M" => Some("masked"),
+        _ => None,
+    }
+}
+
+/// Parses tag lines, e.g. "W: hello-world: no-manual-page usr/bin/hello-world",
+/// the explanations printed with -i (N: lines) are skipped.
+pub fn parse_lintian(output: &str) -> Vec<LintianTag> {
+    let re = Regex::new(r"^([A-Z]): ([^:\s]+(?: source)?): (\S+)\s*(.*)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = re.captures(line)?;
+            let severity = lintian_severity(&captures[1])?;
+            Some(LintianTag {
+                severity: severity.to_string(),
+                package: captures[2].to_string(),
+                tag: captures[3].to_string(),
+                details: captures[4].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Keeps the errors, warnings and the PASS/FAIL verdicts of the piuparts log,
+/// e.g. "0m45.2s ERROR: FAIL: Package purging left files on system".
+pub fn parse_piuparts(output: &str) -> Vec<PiupartsSection> {
+    let re = Regex::new(r"^\s*\d+m\d+\.\d+s (\w+): (.*)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = re.captures(line)?;
+            let level = &captures[1];
+            let message = captures[2].trim();
+            let verdict = message.starts_with("PASS:") || message.starts_with("FAIL:");
+            if level != "ERROR" && level != "WARNING" && !verdict {
+                return None;
+            }
+            Some(PiupartsSection {
+                level: level.to_string(),
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parses the summary autopkgtest prints at the end, e.g. "smoke  FAIL non-zero exit status 1".
+pub fn parse_autopkgtest(output: &str) -> Vec<AutopkgtestResult> {
+    let re = Regex::new(r"^(\S+)\s+(PASS|FAIL|SKIP|FLAKY|NEUTRAL)\b\s*(.*)$").unwrap();
+    let summary = match output.rfind("@@@@@@@@@@@@@@@@@@@@ summary") {
+        Some(start) => &output[start..],
+        None => output,
+    };
+    summary
+        .lines()
+        .filter_map(|line| {
+            let captures = re.captures(line)?;
+            Some(AutopkgtestResult {
+                test: captures[1].to_string(),
+                result: captures[2].to_string(),
+                details: captures[3].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_tool_output() {
+        let lintian = "E: hello-world: binary-without-manpage usr/bin/hello-world\n\
+                       N:\n\
+                       N:   Each binary in /usr/bin should have a manual page\n\
+                       W: hello-world source: newer-standards-version 4.7.0 (current is 4.6.2)\n";
+        assert_eq!(
+            parse_lintian(lintian),
+            vec![
+                LintianTag {
+                    severity: "error".to_string(),
+                    package: "hello-world".to_string(),
+                    tag: "binary-without-manpage".to_string(),
+                    details: "usr/bin/hello-world".to_string(),
+                },
+                LintianTag {
+                    severity: "warning".to_string(),
+                    package: "hello-world source".to_string(),
+                    tag: "newer-standards-version".to_string(),
+                    details: "4.7.0 (current is 4.6.2)".to_string(),
+                },
+            ]
+        );
+
+        let piuparts = "  0m0.0s INFO: ------------------------------------------------------------------------------\n\
+                        0m12.1s DEBUG: Starting command: ['dpkg', '-i', 'hello-world_1.0.0-1_amd64.deb']\n\
+                        0m30.4s ERROR: FAIL: Package purging left files on system:\n\
+                        0m30.5s INFO: PASS: All tests.\n";
+        assert_eq!(
+            parse_piuparts(piuparts),
+            vec![
+                PiupartsSection {
+                    level: "ERROR".to_string(),
+                    message: "FAIL: Package purging left files on system:".to_string(),
+                },
+                PiupartsSection {
+                    level: "INFO".to_string(),
+                    message: "PASS: All tests.".to_string(),
+                },
+            ]
+        );
+
+        let autopkgtest = "smoke PASS\n\
+                           @@@@@@@@@@@@@@@@@@@@ summary\n\
+                           smoke                PASS\n\
+                           upgrade              FAIL non-zero exit status 1\n";
+        let results = parse_autopkgtest(autopkgtest);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].test, "upgrade");
+        assert_eq!(results[1].result, "FAIL");
+        assert_eq!(results[1].details, "non-zero exit status 1");
+    }
+
+    #[test]
+    fn test_report_keeps_other_tools() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("hello-world_1.0.0-1_amd64.quality.json");
+        let mut report = QualityReport::read(&path).unwrap();
+        report.lintian = Some(vec![]);
+        report.write(&path).unwrap();
+
+        let mut report = QualityReport::read(&path).unwrap();
+        report.autopkgtest = Some(parse_autopkgtest("smoke PASS\n"));
+        report.write(&path).unwrap();
+
+        let report = QualityReport::read(&path).unwrap();
+        assert_eq!(report.lintian, Some(vec![]));
+        assert_eq!(report.piuparts, None);
+        assert_eq!(report.autopkgtest.unwrap()[0].result, "PASS");
+    }
+}
@@ -1,25 +1,110 @@
-use crate::v1::packager::BackendBuildEnv;
-use crate::v1::pkg_config::{LanguageEnv, PackageType, PkgConfig};
-use crate::v1::pkg_config_verify::PkgVerifyConfig;
-use cargo_metadata::semver::Version;
+use crate::v1::build::dir_setup::{calculate_sha256, create_exclusively, open_for_hashing};
+use crate::v1::packager::{BackendBuildEnv, BuildBackend};
+use crate::v1::build::host_env::HostEnv;
+use crate::v1::build::java_dependencies::GRADLE_USER_HOME;
+use crate::v1::build::python_dependencies::PYTHON_WHEELS_DIR;
+use crate::v1::build::manifest::{
+    build_log_marker_command, chroot_packages_before_command, chroot_packages_command,
+    diff_fingerprints, find_previous_version, hook_command, parse_hook_output, BuildManifest,
+};
+use crate::v1::build::logs::{
+    archive_log, rotate_logs, DEFAULT_LOG_KEEP_COUNT, DEFAULT_LOG_KEEP_SIZE_MB,
+};
+use crate::v1::build::redact::Redactor;
+use crate::v1::build::apparmor::get_apparmor_results;
+use crate::v1::build::archive::check_distribution;
+use crate::v1::build::content_policy;
+use crate::v1::build::licenses::scan_licenses;
+use crate::v1::build::abi::{self, AbiBaseline};
+use crate::v1::build::multi_arch;
+use crate::v1::build::plan::{BuildPlan, PlanStage, PrivilegedOperation, PLAN_SCHEMA_VERSION};
+use crate::v1::build::provision::{
+    parse_provision_logs, parse_provision_results, quiet_checked_command, ChrootProvisioner, ProvisionLog,
+};
+use crate::v1::build::conffiles::write_piuparts_conffile_check;
+use crate::v1::build::debconf::{check_debs, create_piuparts_scripts_dir, PRESEED_PATH};
+use crate::v1::build::chroot_snapshot::{get_chroot_snapshot, get_snapshot_program, remove_base};
+use crate::v1::build::chroot_stamp::{get_stamp_file, ChrootStamp, DEFAULT_CHROOT_MAX_AGE_DAYS};
+use crate::v1::build::container::ContainerBackend;
+use crate::v1::build::cross::{check_emulation, get_arch_args};
+use crate::v1::build::post_process::{self, PostProcessInputs};
+use crate::v1::build::privilege::PrivilegeBroker;
+use crate::v1::build::smoke::run_smoke_tests;
+use crate::v1::build::store::ContentStore;
+use crate::v1::build::test_deps::{get_apt_recommends_flag, get_autopkgtest_args, select_test_debs};
+use crate::v1::build::testbed::{
+    check_qemu_installed, get_podman_virt_args, get_qemu_virt_args, get_testbed, pull_container_image,
+};
+use crate::v1::build::watchdog::{Heartbeat, Watch, Watchdog};
+use crate::v1::build::units::{check_units, extract_debs};
+use crate::v1::build::quality::{
+    parse_autopkgtest, parse_lintian, parse_piuparts, AutopkgtestResult, ContentFinding, Finding, FindingLevel,
+    QualityReport, RetryAttempt,
+};
+use crate::v1::build::trust::{
+    apt_repository_commands, build_keyring, download_signature, find_default_key, get_default_key,
+    get_default_key_file, get_signature_url, verify_detached_signature, write_keyring_command, MICROSOFT_KEYRING,
+};
+use crate::v1::distribution::{self, Distribution};
+use crate::v1::pkg_config::{
+    Arch, AutopkgtestTestbed, Backend, CustomConfig, DotnetPackage, LanguageEnv, PackageType, PkgConfig, PostProcessConfig,
+    PostProcessor, TrustedKey,
+};
+use crate::v1::pkg_config_verify::{HashUpdate, PkgVerifyConfig};
+use crate::v1::tools::{
+    self, check_pinned_versions, check_tool_version, get_tool_records, Autopkgtest, Debcrafter, HostTool, Lintian,
+    Piuparts, Tool,
+};
 use eyre::{eyre, Report, Result};
-use log::{info, warn};
-use rand::random;
+use log::{info, log_enabled, warn, Level};
 use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
 use std::fs::create_dir_all;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::{env, fs, io}; // Import from the sha1 crate
+use std::time::Duration;
+use std::{fs, io}; // Import from the sha1 crate
+
+
+// autopkgtest exits with 16 when the testbed failed, e.g. qemu did not boot in time
+const AUTOPKGTEST_TESTBED_FAILURE: i32 = 16;
+const DEFAULT_TEST_RETRIES: u32 = 2;
+const DEFAULT_TEST_RETRY_BACKOFF_SECS: u64 = 30;
+// run inside of a user namespace with the subordinate ids mapped, like sbuild --chroot-mode=unshare,
+// $1 base tarball, $2 empty chroot dir, $3 layer tarball, $4 install commands, $5 staged files.
+// The host resolv.conf is only there while the commands run, the one of the chroot is packed.
+const TOOLCHAIN_LAYER_SCRIPT: &str = r#"set -e
+tar -xzf "$1" -C "$2"
+cp -R "$5/." "$2/"
+if [ -e "$2/etc/resolv.conf" ] || [ -L "$2/etc/resolv.conf" ]; then
+    mv "$2/etc/resolv.conf" "$2/etc/resolv.conf.pkg-builder"
+fi
+cp -L /etc/resolv.conf "$2/etc/resolv.conf"
+mount --rbind /dev "$2/dev"
+mount -t proc proc "$2/proc"
+chroot "$2" sh -c "$4"
+umount -l "$2/dev" "$2/proc"
+rm "$2/etc/resolv.conf"
+if [ -e "$2/etc/resolv.conf.pkg-builder" ] || [ -L "$2/etc/resolv.conf.pkg-builder" ]; then
+    mv "$2/etc/resolv.conf.pkg-builder" "$2/etc/resolv.conf"
+fi
+tar -czf "$3" -C "$2" .
+find "$2" -mindepth 1 -delete
+"#;
+// run by the script above with an empty layer, old conffiles are kept like on an upgraded host
+const CHROOT_UPGRADE_COMMAND: &str = "DEBIAN_FRONTEND=noninteractive apt-get -y -o Dpkg::Options::=--force-confold dist-upgrade && apt-get -y autoremove && apt-get clean";
+const APT_PROXY_FILE: &str = "/etc/apt/apt.conf.d/01pkg-builder-proxy";
 
 pub struct Sbuild {
     config: PkgConfig,
-    build_files_dir: String,
+    build_files_dir: PathBuf,
     cache_dir: String,
+    recipe_revision: Option<String>,
 }
 
 impl Sbuild {
-    pub fn new(config: PkgConfig, build_files_dir: String) -> Sbuild {
+    pub fn new(config: PkgConfig, build_files_dir: PathBuf) -> Sbuild {
         Sbuild {
             cache_dir: config
                 .build_env
@@ -28,232 +113,377 @@ impl Sbuild {
                 .unwrap_or("~/.cache/sbuild".to_string()),
             config,
             build_files_dir,
+            recipe_revision: None,
         }
     }
 
-    fn get_build_deps_based_on_langenv(&self, lang_env: &LanguageEnv) -> Vec<String> {
+    /// Recorded in the build manifest, see get_recipe_revision
+    pub fn with_recipe_revision(mut self, recipe_revision: Option<String>) -> Sbuild {
+        self.recipe_revision = recipe_revision;
+        self
+    }
+
+    // installs the toolchain from the archive, the version was checked on config validation
+    fn get_distro_toolchain_install(&self, lang_env: &LanguageEnv) -> Option<ChrootProvisioner> {
+        lang_env.get_distro_toolchain_version()?;
+        let distribution = self.get_distribution().ok()?;
+        let toolchain = lang_env.get_distro_toolchain(distribution.info())?;
+        let mut install = ChrootProvisioner::new();
+        install.run("toolchain", format!("apt install -y {}", toolchain.packages.join(" ")));
         match lang_env {
-            LanguageEnv::C => {
-                let lang_deps = vec![];
-                lang_deps
+            LanguageEnv::Rust(_) => {
+                install.run("rustc-version", "rustc --version");
+                install.run("cargo-version", "cargo --version");
             }
-            LanguageEnv::Python => {
-                let lang_deps = vec![];
-                lang_deps
+            LanguageEnv::Go(_) => {
+                install.run("go-version", "go version");
+            }
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
+                install.run("node-version", "node --version");
+                install.run("npm-version", "npm --version");
+                if let Some(yarn_version) = &config.yarn_version {
+                    install.run("yarn", format!("npm install --global yarn@{}", yarn_version));
+                    install.run("yarn-version", "yarn --version");
+                }
+            }
+            _ => {}
+        }
+        Some(install)
+    }
+
+    fn get_build_deps_based_on_langenv(&self, lang_env: &LanguageEnv) -> Result<ChrootProvisioner> {
+        if let Some(install) = self.get_distro_toolchain_install(lang_env) {
+            return Ok(install);
+        }
+        let mut install = ChrootProvisioner::new();
+        match lang_env {
+            LanguageEnv::C => {}
+            LanguageEnv::Python(config) => {
+                // python3 of the suite, debs of python applications depend on it
+                install
+                    .run("python", "apt install -y python3 python3-pip python3-venv")
+                    .run("python-version", "python3 --version");
+                if let Some(python_version) = &config.python_version {
+                    install.run(
+                        "python-version-check",
+                        format!(
+                            "python3 -c 'import sys; sys.exit(sys.version_info[:2] != ({}))'",
+                            python_version.replace('.', ", ")
+                        ),
+                    );
+                }
             }
             LanguageEnv::Rust(config) => {
                 // TODO
                 // let rust_version = &config.rust_version;
-                let rust_binary_url = &config.rust_binary_url;
-                let rust_binary_gpg_asc = &config.rust_binary_gpg_asc;
-                let lang_deps = vec![
-                    "apt install -y wget gpg gpg-agent".to_string(),
-                    format!("cd /tmp && wget -O  rust.tar.xz {}", rust_binary_url),
-                    format!(
-                        "cd /tmp && echo \"{}\" >> rust.tar.xz.asc && cat rust.tar.xz.asc ",
-                        rust_binary_gpg_asc
-                    ),
-                    "wget -qO- https://keybase.io/rust/pgp_keys.asc | gpg --import".to_string(),
-                    "cd /tmp && gpg --verify rust.tar.xz.asc rust.tar.xz".to_string(),
-                    "cd /tmp && tar xvJf rust.tar.xz -C . --strip-components=1 --exclude=rust-docs"
-                        .to_string(),
-                    "cd /tmp && /bin/bash install.sh --without=rust-docs".to_string(),
-                    "apt remove -y wget gpg gpg-agent".to_string(),
-                ];
-                lang_deps
+                // the signature is verified on the host, see verify_toolchain_signature
+                install.stage(&config.rust_binary_url, &config.rust_binary_checksum, "/tmp/rust.tar.xz");
+                // the file listing is only wanted with -vv
+                let tar_flags = if log_enabled!(Level::Trace) { "xvJf" } else { "xJf" };
+                install.run(
+                    "rust-unpack",
+                    format!("cd /tmp && tar {} rust.tar.xz -C . --strip-components=1 --exclude=rust-docs", tar_flags),
+                );
+                install.run("rust-install", "cd /tmp && /bin/bash install.sh --without=rust-docs");
             }
             LanguageEnv::Go(config) => {
                 // TODO
                 //let go_version = &config.go_version;
-                let go_binary_url = &config.go_binary_url;
-                let go_binary_checksum = &config.go_binary_checksum;
-                let install = vec![
-                    "apt install -y wget".to_string(),
-                    format!("cd /tmp && wget -O  go.tar.gz {}", go_binary_url),
-                    format!("cd /tmp && echo \"{} go.tar.gz\" >> hash_file.txt && cat hash_file.txt", go_binary_checksum),
-                    "cd /tmp && sha256sum -c hash_file.txt".to_string(),
-                    "cd /tmp && rm -rf /usr/local/go && mkdir /usr/local/go && tar -C /usr/local -xzf go.tar.gz".to_string(),
-                    "ln -s /usr/local/go/bin/go /usr/bin/go".to_string(),
-                    "go version".to_string(),
-                    // add write permission, this is a chroot env, with one user, should be fine
-                    "chmod -R a+rwx /usr/local/go/pkg".to_string(),
-                    "apt remove -y wget".to_string(),
-                ];
                 install
+                    .stage(&config.go_binary_url, &config.go_binary_checksum, "/tmp/go.tar.gz")
+                    .run("go-unpack", "rm -rf /usr/local/go && tar -C /usr/local -xzf /tmp/go.tar.gz")
+                    .run("go-link", "ln -s /usr/local/go/bin/go /usr/bin/go")
+                    .run("go-version", "go version")
+                    // add write permission, this is a chroot env, with one user, should be fine
+                    .run("go-permissions", "chmod -R a+rwx /usr/local/go/pkg");
             }
             LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
-                // let node_version = &config.go_version;
-                let node_binary_url = &config.node_binary_url;
-                let node_binary_checksum = &config.node_binary_checksum;
-                let mut install = vec![
-                    "apt install -y wget".to_string(),
-                    format!("cd /tmp && wget -O  node.tar.gz {}", node_binary_url),
-                    format!("cd /tmp && echo \"{} node.tar.gz\" >> hash_file.txt && cat hash_file.txt", node_binary_checksum),
-                    "cd /tmp && sha256sum -c hash_file.txt".to_string(),
-                    "cd /tmp && rm -rf /usr/share/node && mkdir /usr/share/node && tar -C /usr/share/node -xzf node.tar.gz --strip-components=1".to_string(),
-                    "ls -l /usr/share/node/bin".to_string(),
-                    "ln -s /usr/share/node/bin/node /usr/bin/node".to_string(),
-                    "ln -s /usr/share/node/bin/npm /usr/bin/npm".to_string(),
-                    "ln -s /usr/share/node/bin/npx /usr/bin/npx".to_string(),
-                    "ln -s /usr/share/node/bin/corepack /usr/bin/corepack".to_string(),
-                    "apt remove -y wget".to_string(),
-                    "node --version".to_string(),
-                    "npm --version".to_string(),
-                ];
+                install
+                    .stage(&config.node_binary_url, &config.node_binary_checksum, "/tmp/node.tar.gz")
+                    .run(
+                        "node-unpack",
+                        "rm -rf /usr/share/node && mkdir /usr/share/node && tar -C /usr/share/node -xzf /tmp/node.tar.gz --strip-components=1",
+                    )
+                    .run(
+                        "node-link",
+                        ["node", "npm", "npx", "corepack"]
+                            .map(|tool| format!("ln -s /usr/share/node/bin/{tool} /usr/bin/{tool}", tool = tool))
+                            .join(" && "),
+                    )
+                    .run("node-version", "node --version")
+                    .run("npm-version", "npm --version");
                 if let Some(yarn_version) = &config.yarn_version {
-                    install.push(format!("npm install --global yarn@{}", yarn_version));
-                    install.push("ln -s /usr/share/node/bin/yarn /usr/bin/yarn".to_string());
-                    install.push("yarn --version".to_string());
+                    install.run(
+                        "yarn",
+                        format!("npm install --global yarn@{} && ln -s /usr/share/node/bin/yarn /usr/bin/yarn", yarn_version),
+                    );
+                    install.run("yarn-version", "yarn --version");
                 }
-                install
             }
             LanguageEnv::Java(config) => {
-                let is_oracle = config.is_oracle;
-                if is_oracle {
-                    let jdk_version = &config.jdk_version;
-                    let jdk_binary_url = &config.jdk_binary_url;
-                    let jdk_binary_checksum = &config.jdk_binary_checksum;
-                    let mut install = vec![
-                        "apt install -y wget".to_string(),
-                        format!("mkdir -p /opt/lib/jvm/jdk-{version}-oracle && mkdir -p /usr/lib/jvm", version = jdk_version),
-                        format!("cd /tmp && wget -q --output-document jdk.tar.gz {}", jdk_binary_url),
-                        format!("cd /tmp && echo \"{} jdk.tar.gz\" >> hash_file.txt && cat hash_file.txt", jdk_binary_checksum),
-                        "cd /tmp && sha256sum -c hash_file.txt".to_string(),
-                        format!("cd /tmp && tar -zxf jdk.tar.gz -C /opt/lib/jvm/jdk-{version}-oracle --strip-components=1", version = jdk_version),
-                        format!("ln -s /opt/lib/jvm/jdk-{version}-oracle/bin/java  /usr/bin/java", version = jdk_version),
-                        format!("ln -s /opt/lib/jvm/jdk-{version}-oracle/bin/javac  /usr/bin/javac", version = jdk_version),
-                        "java -version".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
+                if config.is_oracle {
+                    let jdk_dir = format!("/opt/lib/jvm/jdk-{}-oracle", config.jdk_version);
+                    install
+                        .stage(&config.jdk_binary_url, &config.jdk_binary_checksum, "/tmp/jdk.tar.gz")
+                        .run(
+                            "jdk-unpack",
+                            format!(
+                                "mkdir -p {dir} /usr/lib/jvm && tar -zxf /tmp/jdk.tar.gz -C {dir} --strip-components=1",
+                                dir = jdk_dir
+                            ),
+                        )
+                        .run(
+                            "jdk-link",
+                            format!("ln -s {dir}/bin/java /usr/bin/java && ln -s {dir}/bin/javac /usr/bin/javac", dir = jdk_dir),
+                        )
+                        .run("java-version", "java -version");
                     if let Some(gradle_config) = &config.gradle {
                         let gradle_version = &gradle_config.gradle_version;
-                        let gradle_binary_url = &gradle_config.gradle_binary_url;
-                        let gradle_binary_checksum = &gradle_config.gradle_binary_checksum;
-
-                        install.push("apt install -y wget unzip".to_string());
-                        install.push(format!(
-                            "mkdir -p /opt/lib/gradle-{version}",
-                            version = gradle_version
-                        ));
-                        install.push(format!(
-                            "cd /tmp && wget -q --output-document gradle.tar.gz {}",
-                            gradle_binary_url
-                        ));
-                        install.push(format!("cd /tmp && echo \"{} gradle.tar.gz\" > hash_file.txt && cat hash_file.txt", gradle_binary_checksum));
-                        install.push("cd /tmp && sha256sum -c hash_file.txt".to_string());
-                        install.push(format!(
-                            "cd /tmp && unzip gradle.tar.gz && mv gradle-{version} /opt/lib",
-                            version = gradle_version
-                        ));
-                        install.push(format!(
-                            "ln -s /opt/lib/gradle-{version}/bin/gradle  /usr/bin/gradle",
-                            version = gradle_version
-                        ));
-                        install.push("gradle -version".to_string());
-                        install.push("apt remove -y wget".to_string());
+                        install
+                            .stage(&gradle_config.gradle_binary_url, &gradle_config.gradle_binary_checksum, "/tmp/gradle.zip")
+                            .run("gradle-unzip", "apt install -y unzip")
+                            .run(
+                                "gradle-unpack",
+                                format!("mkdir -p /opt/lib && cd /tmp && unzip -q gradle.zip && mv gradle-{} /opt/lib", gradle_version),
+                            )
+                            .run(
+                                "gradle-link",
+                                format!("ln -s /opt/lib/gradle-{}/bin/gradle /usr/bin/gradle", gradle_version),
+                            )
+                            .run("gradle-version", "gradle -version");
                     }
-                    return install;
                 }
-                vec![]
             }
             LanguageEnv::Dotnet(config) => {
                 let dotnet_packages = &config.dotnet_packages;
-                let mut install: Vec<String> = vec![];
-                if config.use_backup_version {
-                    install.push("apt install -y wget".to_string());
-                    install.push("apt install -y libicu-dev".to_string());
-                    for package in dotnet_packages {
-                        install.push(format!("cd /tmp && wget -q {}", package.url));
-                        install.push(format!("cd /tmp && ls && dpkg -i {}.deb", package.name));
-                        // check package version
-                        install.push(format!("cd /tmp && ls && sha1sum {}.deb", package.name));
-                        install.push(format!("cd /tmp &&  echo {} {}.deb > hash_file.txt && cat hash_file.txt", package.hash, package.name));
-                        install.push(format!("cd /tmp && sha1sum -c hash_file.txt"));
-                    }
-                    install.push("dotnet --version".to_string());
-                    install.push("apt remove -y wget".to_string());
-                } else if self.config.build_env.codename == "bookworm"
-                    || self.config.build_env.codename == "jammy jellyfish"
-                {
-                    install.push("apt install -y wget".to_string());
-                    install.push("cd /tmp && wget https://packages.microsoft.com/config/debian/12/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string());
-                    install.push("cd /tmp && dpkg -i packages-microsoft-prod.deb".to_string());
-                    install.push("apt update -y".to_string());
+                let fallback = !config.use_backup_version && config.fallback_to_backup == Some(true);
+                let mut backup = vec![];
+                if config.use_backup_version || fallback {
+                    // the debs are in the store since provision_dependencies, staged from there
+                    let mut debs = vec![];
                     for package in dotnet_packages {
-                        let pkg = transform_name(&package.name, &self.config.build_env.arch);
-                        install.push(format!("cd /tmp && wget -q {}", package.url));
-                        install.push(format!("cd /tmp && apt install -y {}", pkg));
-                        install.push(format!("cd /tmp && apt download -y {}", pkg));
-                        // check package version
-                        install.push(format!("cd /tmp && ls && sha1sum {}.deb", package.name));
-                        install.push(format!("cd /tmp &&  echo {} {}.deb >> hash_file.txt && cat hash_file.txt", package.hash, package.name));
-                        install.push(format!("cd /tmp && sha1sum -c hash_file.txt"));
+                        let deb = format!("/tmp/dotnet/{}.deb", package.name);
+                        install.stage(&package.url, &package.hash, &deb);
+                        debs.push(deb);
                     }
-                    install.push("dotnet --version".to_string());
-                    install.push("apt remove -y wget".to_string());
-          
-                } else if self.config.build_env.codename == "noble numbat" {
-                    install.push("apt install -y wget".to_string());
-                    for package in dotnet_packages {
-                        let pkg = transform_name(&package.name, &self.config.build_env.arch);
-                        install.push(format!("cd /tmp && wget -q {}", package.url));
-                        install.push(format!("cd /tmp && apt install -y {}", pkg));
-                        install.push(format!("cd /tmp && apt download -y {}", pkg));
-                        // check package version
-                        install.push(format!("cd /tmp && ls && sha1sum {}.deb", package.name));
-                        install.push(format!("cd /tmp &&  echo {} {}.deb >> hash_file.txt && cat hash_file.txt", package.hash, package.name));
-                        install.push(format!("cd /tmp && sha1sum -c hash_file.txt"));
-                    }
-                    install.push("dotnet --version".to_string());
-                    install.push("apt remove -y wget".to_string());
+                    backup.push(format!("apt install -y {}", debs.join(" ")));
+                    backup.push(build_log_marker_command("dotnet-source", "backup"));
                 }
-                // validate dotnet packages
-                return install;
+                let mut command = if config.use_backup_version {
+                    backup
+                } else {
+                    let mut primary = self.get_dotnet_primary_install(&mut install, dotnet_packages)?;
+                    primary.push(build_log_marker_command("dotnet-source", "primary"));
+                    if fallback {
+                        // one command, so a failing primary falls through to the backup
+                        vec![format!(
+                            "( {} ) || ( echo \"primary dotnet source failed, falling back to backup\" && {} )",
+                            primary.join(" && "),
+                            backup.join(" && ")
+                        )]
+                    } else {
+                        primary
+                    }
+                };
+                command.push("dotnet --version".to_string());
+                install.run("dotnet", command.join(" && "));
             }
+            LanguageEnv::Custom(config) => install = get_custom_install(config),
             LanguageEnv::Nim(config) => {
                 let nim_version = &config.nim_version;
-                let nim_binary_url = &config.nim_binary_url;
-                let nim_version_checksum = &config.nim_version_checksum;
-                let install = vec![
-                    "apt install -y wget".to_string(),
-                    format!("rm -rf /tmp/nim-{version} && rm -rf /usr/lib/nim/nim-{version}&& rm -rf /opt/lib/nim/nim-{version} && mkdir /tmp/nim-{version}", version = nim_version),
-                    "mkdir -p /opt/lib/nim && mkdir -p /usr/lib/nim".to_string(),
-                    format!("cd /tmp && wget -q {}", nim_binary_url),
-                    format!("cd /tmp && echo {} >> hash_file.txt && cat hash_file.txt", nim_version_checksum),
-                    "cd /tmp && sha256sum -c hash_file.txt".to_string(),
-                    format!("cd /tmp && tar xJf nim-{version}-linux_x64.tar.xz -C nim-{version} --strip-components=1", version = nim_version),
-                    format!("cd /tmp  && mv nim-{version} /opt/lib/nim", version = nim_version),
-                    format!("ln -s /opt/lib/nim/nim-{version}/bin/nim  /usr/bin/nim", version = nim_version),
-                    // equality check not working
-                    //  format!("installed_version=`nim --version | head -n 1 | awk '{{print $4}}'` && echo \"installed version: $installed_version\" && [ \"$installed_version\" != \"{}\" ] && exit 1", nim_version),
-                    "nim --version".to_string(),
-                    "apt remove -y wget".to_string(),
-                ];
+                // the checksum is in the sha256sum format, followed by the file name
+                let nim_checksum = config.nim_version_checksum.split_whitespace().next().unwrap_or_default();
+                let nim_dir = format!("/opt/lib/nim/nim-{}", nim_version);
                 install
+                    .stage(&config.nim_binary_url, nim_checksum, "/tmp/nim.tar.xz")
+                    .run(
+                        "nim-unpack",
+                        format!(
+                            "rm -rf {dir} && mkdir -p {dir} /usr/lib/nim && tar xJf /tmp/nim.tar.xz -C {dir} --strip-components=1",
+                            dir = nim_dir
+                        ),
+                    )
+                    .run("nim-link", format!("ln -s {}/bin/nim /usr/bin/nim", nim_dir))
+                    .run("nim-version", "nim --version");
+            }
+            LanguageEnv::Zig(config) => {
+                let zig_dir = format!("/opt/lib/zig/zig-{}", config.zig_version);
+                install
+                    .stage(&config.zig_binary_url, &config.zig_binary_checksum, "/tmp/zig.tar.xz")
+                    .run(
+                        "zig-unpack",
+                        format!(
+                            "rm -rf {dir} && mkdir -p {dir} && tar xJf /tmp/zig.tar.xz -C {dir} --strip-components=1",
+                            dir = zig_dir
+                        ),
+                    )
+                    // zig finds its lib dir next to the binary, so only the binary is linked
+                    .run("zig-link", format!("ln -s {}/zig /usr/bin/zig", zig_dir))
+                    .run("zig-version", "zig version");
             }
         }
+        Ok(install)
     }
-    fn get_build_deps_not_in_debian(&self) -> Vec<String> {
-        let package_type = &self.config.package_type;
-        let lang_env = match package_type {
-            PackageType::Default(config) => Some(&config.language_env),
-            PackageType::Git(config) => Some(&config.language_env),
-            PackageType::Virtual => None,
+    // installs the pinned dotnet packages from the distribution or microsoft repository,
+    // fails if the pinned version is missing (madison lookup) or the hash changed, the
+    // keyring of the repository is copied into the chroot by the provisioner
+    fn get_dotnet_primary_install(
+        &self,
+        provisioner: &mut ChrootProvisioner,
+        dotnet_packages: &[DotnetPackage],
+    ) -> Result<Vec<String>> {
+        let mut install: Vec<String> = vec![];
+        let distribution = self.get_distribution()?.info();
+        if let Some(dotnet_repository) = &distribution.dotnet_repository {
+            provisioner.copy(&self.get_keyring_file("microsoft"), MICROSOFT_KEYRING);
+            install.append(&mut apt_repository_commands(
+                dotnet_repository.apt_source,
+                &self.get_trust_fingerprints("microsoft"),
+            ));
+        }
+        for package in dotnet_packages {
+            let pkg = transform_name(&package.name, self.config.build_env.arch.get_build_arch());
+            if let Some((name, version)) = pkg.split_once('=') {
+                install.push(format!(
+                    "apt-cache madison {} | grep -q -F \"{}\"",
+                    name, version
+                ));
+            }
+            install.push(format!("cd /tmp && apt install -y {}", pkg));
+            install.push(format!("cd /tmp && apt download -y {}", pkg));
+            // check package version
+            install.push(format!("cd /tmp && ls && sha1sum {}.deb", package.name));
+            install.push(format!("cd /tmp &&  echo {} {}.deb >> hash_file.txt && cat hash_file.txt", package.hash, package.name));
+            install.push("cd /tmp && sha1sum -c hash_file.txt".to_string());
+        }
+        Ok(install)
+    }
+
+    // sources whose keys end up in the chroot, the toolchain downloads are verified on the host
+    fn get_keyring_sources(&self) -> Vec<&'static str> {
+        match self.get_language_env() {
+            Some(LanguageEnv::Dotnet(_)) => match self.get_distribution() {
+                Ok(distribution) if distribution.info().dotnet_repository.is_some() => vec!["microsoft"],
+                _ => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
+    // written by prepare_keyrings, the chroot only refers to it
+    fn get_keyring_file(&self, source: &str) -> PathBuf {
+        let deb_dir = self.get_deb_dir();
+        let workdir = deb_dir.parent().unwrap_or(deb_dir);
+        workdir.join(".keyrings").join(format!("{}.asc", source))
+    }
+
+    // the fingerprints of the declared key of the source, otherwise of its pinned default key
+    fn get_trust_fingerprints(&self, source: &str) -> Vec<String> {
+        match self.config.trust.as_ref().and_then(|trust| trust.get_key(source)) {
+            Some(key) => key.get_fingerprints(),
+            None => find_default_key(source).map(|key| vec![key.fingerprint.to_string()]).unwrap_or_default(),
+        }
+    }
+
+    // the declared key of the source, otherwise its pinned default key if it has one
+    fn get_trust_key(&self, source: &str) -> Result<Option<TrustedKey>> {
+        if let Some(key) = self.config.trust.as_ref().and_then(|trust| trust.get_key(source)) {
+            return Ok(Some(key.clone()));
+        }
+        get_default_key(source, &self.get_keys_dir())
+    }
+
+    // pinned default keys are fetched once into the download cache
+    fn get_keys_dir(&self) -> PathBuf {
+        self.get_download_cache_dir().join("keys")
+    }
+
+    /// Writes the keyrings the chroot trusts to <workdir>/.keyrings, from the declared or the
+    /// pinned default keys. Runs before the build, get_provisioner only refers to the files.
+    pub fn prepare_keyrings(&self) -> Result<()> {
+        for source in self.get_keyring_sources() {
+            let key = self
+                .get_trust_key(source)?
+                .ok_or_else(|| eyre!("trust: no key is declared for {}, refusing to install it", source))?;
+            build_keyring(&key, Path::new(&key.key_file), &self.get_keyring_file(source))?;
+        }
+        Ok(())
+    }
+
+    /// Urls of the pinned default keys that are used but not fetched yet.
+    pub fn get_missing_default_keys(&self) -> Vec<String> {
+        let mut sources = self.get_keyring_sources();
+        if let Some(lang_env) = self.get_language_env() {
+            if self.get_distro_toolchain_install(lang_env).is_none() {
+                sources.extend(lang_env.get_trust_source());
+            }
+        }
+        sources
+            .into_iter()
+            .filter(|source| self.config.trust.as_ref().and_then(|trust| trust.get_key(source)).is_none())
+            .filter_map(find_default_key)
+            .filter(|key| !get_default_key_file(key.source, &self.get_keys_dir()).exists())
+            .map(|key| key.url.to_string())
+            .collect()
+    }
+
+    /// Verifies the signature of the downloaded toolchain on the host against the trust key of
+    /// its source, the download is fetched into the download cache first. The rust signature
+    /// is rust_binary_gpg_asc and is always verified, against the pinned rust key without a
+    /// declared one. The other toolchains are verified against <url>.asc when their key is
+    /// declared, otherwise only the sha256 pin is checked.
+    pub fn verify_toolchain_signature(&self) -> Result<()> {
+        let Some(lang_env) = self.get_language_env() else {
+            return Ok(());
         };
-        match lang_env {
+        let (Some(source), Some(url), Some(checksum)) =
+            (lang_env.get_trust_source(), lang_env.get_binary_url(), lang_env.get_binary_checksum())
+        else {
+            return Ok(());
+        };
+        if self.get_distro_toolchain_install(lang_env).is_some() {
+            return Ok(());
+        }
+        let key = match self.get_trust_key(source)? {
+            Some(key) => key,
             None => {
-                vec![]
+                info!("No {} trust key declared, {} is only checked against its sha256", source, url);
+                return Ok(());
             }
+        };
+        let download_cache_dir = self.get_download_cache_dir();
+        self.get_provisioner()?
+            .fetch(&download_cache_dir, &ContentStore::new(ContentStore::default_root()))?;
+        let signature_dir = tempfile::tempdir()?;
+        let signature = signature_dir.path().join(format!("{}.asc", source));
+        match lang_env {
+            LanguageEnv::Rust(config) => fs::write(&signature, &config.rust_binary_gpg_asc)?,
+            _ => download_signature(&get_signature_url(url), &signature)?,
+        }
+        let download = download_cache_dir.join(checksum);
+        let fingerprint = verify_detached_signature(&key, &signature, &download)?;
+        info!("{} is signed by {}", url, fingerprint);
+        Ok(())
+    }
+
+    pub fn get_provisioner(&self) -> Result<ChrootProvisioner> {
+        match self.get_language_env() {
+            None => Ok(ChrootProvisioner::new()),
             Some(lang_env) => self.get_build_deps_based_on_langenv(lang_env),
         }
     }
-    fn get_test_deps_based_on_langenv(&self, lang_env: &LanguageEnv) -> Vec<String> {
-        match lang_env {
+
+    // staged files are kept next to the cached chroots, named by their sha256
+    pub fn get_download_cache_dir(&self) -> PathBuf {
+        Path::new(&self.get_cache_file()).parent().unwrap().join("downloads")
+    }
+    fn get_test_deps_based_on_langenv(&self, lang_env: &LanguageEnv) -> Result<Vec<String>> {
+        let test_deps = match lang_env {
             LanguageEnv::C => {
                 let lang_deps = vec![];
                 lang_deps
             }
-            LanguageEnv::Python => {
+            LanguageEnv::Python(_) => {
                 let lang_deps = vec![];
                 lang_deps
             }
@@ -281,21 +511,24 @@ impl Sbuild {
             }
             LanguageEnv::Dotnet(_) => {
                 // add ms repo, but do not install dotnet, let test_bed add it as intall dependency
-                if self.config.build_env.codename == "bookworm"
-                    || self.config.build_env.codename == "jammy jellyfish"
-                {
-                    let install = vec![
-                        "apt install -y wget".to_string(),
-                        "cd /tmp && wget https://packages.microsoft.com/config/debian/12/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string(),
-                        "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
-                        "apt-get update -y".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
-                    install
-                } else if self.config.build_env.codename == "noble numbat" {
-                    return vec![];
-                } else {
-                    return vec![];
+                let dotnet_repository = self
+                    .get_distribution()
+                    .ok()
+                    .and_then(|distribution| distribution.info().dotnet_repository.as_ref());
+                match dotnet_repository {
+                    Some(dotnet_repository) => {
+                        let keyring_file = self.get_keyring_file("microsoft");
+                        let armored = fs::read_to_string(&keyring_file).map_err(|err| {
+                            eyre!("trust: could not read the keyring {}: {}", keyring_file.display(), err)
+                        })?;
+                        let mut commands = vec![write_keyring_command(&armored)];
+                        commands.extend(apt_repository_commands(
+                            dotnet_repository.apt_source,
+                            &self.get_trust_fingerprints("microsoft"),
+                        ));
+                        commands
+                    }
+                    None => vec![],
                 }
             }
             LanguageEnv::Nim(_) => {
@@ -303,9 +536,18 @@ impl Sbuild {
                 let lang_deps = vec![];
                 lang_deps
             }
-        }
+            LanguageEnv::Zig(_) => {
+                // zig compiles to binary, no need to install under test_bed
+                vec![]
+            }
+            LanguageEnv::Custom(_) => {
+                // only needed to build, runtime dependencies belong into the spec file
+                vec![]
+            }
+        };
+        Ok(test_deps)
     }
-    fn get_test_deps_not_in_debian(&self) -> Vec<String> {
+    fn get_test_deps_not_in_debian(&self) -> Result<Vec<String>> {
         let package_type = &self.config.package_type;
         let lang_env = match package_type {
             PackageType::Default(config) => Some(&config.language_env),
@@ -313,138 +555,445 @@ impl Sbuild {
             PackageType::Virtual => None,
         };
         match lang_env {
-            None => {
-                vec![]
-            }
+            None => Ok(vec![]),
             Some(lang_env) => self.get_test_deps_based_on_langenv(lang_env),
         }
     }
 
     pub fn get_cache_file(&self) -> String {
-        let dir = &self.cache_dir;
-        let expanded_path = if dir.starts_with('~') {
-            let expanded_path = shellexpand::tilde(dir).to_string();
-            expanded_path
-        } else if dir.starts_with('/') {
-            self.cache_dir.clone()
-        } else {
-            let parent_dir = env::current_dir().unwrap();
-            let dir = parent_dir.join(dir);
-            let path = fs::canonicalize(dir.clone()).unwrap();
-            let path = path.to_str().unwrap().to_string();
-            path
-        };
+        // a relative cache dir is already resolved against the config root by the packager
+        let expanded_path = shellexpand::tilde(&self.cache_dir).to_string();
 
-        let codename = normalize_codename(&self.config.build_env.codename).unwrap();
-        let cache_file_name =
-            format!("{}-{}.tar.gz", codename, self.config.build_env.arch).to_string();
+        let key = self.get_distribution().unwrap().info().key;
+        // per chroot arch, cross builds of every arch share the chroot of the host
+        let cache_file_name = format!("{}-{}.tar.gz", key, self.config.build_env.get_chroot_arch());
         let path = Path::new(&expanded_path);
         let cache_file = path.join(cache_file_name);
         cache_file.to_str().unwrap().to_string()
     }
 
+    fn get_language_env(&self) -> Option<&LanguageEnv> {
+        match &self.config.package_type {
+            PackageType::Default(config) => Some(&config.language_env),
+            PackageType::Git(config) => Some(&config.language_env),
+            PackageType::Virtual => None,
+        }
+    }
+
+    /// Short hash of the toolchain install commands, every pin of the language env (versions,
+    /// urls, checksums, keys) ends up in them. None when nothing is installed.
+    pub fn get_toolchain_hash(&self) -> Result<Option<String>> {
+        let install = self.get_provisioner()?.get_setup_commands();
+        if install.is_empty() {
+            return Ok(None);
+        }
+        let hash = calculate_sha256(install.join("\n").as_bytes())?;
+        Ok(Some(hash[..12].to_string()))
+    }
+
+    /// Cache file with the toolchain installed on top, e.g. bookworm-amd64-javascript-<hash>.tar.gz,
+    /// None unless toolchain_cache is set and the language env installs a toolchain.
+    pub fn get_toolchain_layer_file(&self) -> Result<Option<String>> {
+        if self.config.build_env.toolchain_cache != Some(true) {
+            return Ok(None);
+        }
+        let (Some(lang_env), Some(hash)) = (self.get_language_env(), self.get_toolchain_hash()?) else {
+            return Ok(None);
+        };
+        let cache_file = self.get_cache_file();
+        let base = cache_file.trim_end_matches(".tar.gz");
+        Ok(Some(format!("{}-{}-{}.tar.gz", base, lang_env.get_name(), hash)))
+    }
+
+    /// None for sbuild, the container backends replace the chroot and the sbuild run.
+    pub fn get_container_backend(&self) -> Result<Option<ContainerBackend>> {
+        let engine = match self.config.build_env.backend {
+            Some(Backend::Docker) => "docker",
+            Some(Backend::Podman) => "podman",
+            Some(Backend::Sbuild) | None => return Ok(None),
+        };
+        let distribution = self.get_distribution()?.info();
+        let mut provisioner = self.get_provisioner()?;
+        provisioner.quiet = self.is_quiet_chroot_setup();
+        let mut setup_commands = self.get_proxy_setup_commands();
+        setup_commands.extend(provisioner.get_setup_commands());
+        setup_commands.extend(distribution.chroot_setup_commands.iter().map(|command| command.to_string()));
+        Ok(Some(ContainerBackend {
+            engine,
+            base_image: self.get_build_image()?,
+            name: format!("{}-{}", distribution.key, self.config.build_env.get_chroot_arch()),
+            provisioner,
+            download_cache_dir: self.get_download_cache_dir(),
+            setup_commands,
+            build_files_dir: self.build_files_dir.clone(),
+            build_log_file: self.get_build_log_file(),
+            environment: self.get_build_environment(),
+            jobs: self.get_jobs(),
+            arch_all: self.config.build_env.arch == Arch::All,
+        }))
+    }
+
+    // the toolchain layer when there is one, otherwise the cache file
+    fn get_chroot_tarball(&self) -> Result<String> {
+        Ok(self.get_toolchain_layer_file()?.unwrap_or_else(|| self.get_cache_file()))
+    }
+
+    // layers of other toolchain pins are left alone, they are removed by clean
+    fn create_toolchain_layer(&self, layer_file: &str) -> Result<()> {
+        let cache_file = self.get_cache_file();
+        if !Path::new(&cache_file).exists() {
+            return Err(eyre!("{} not found, create the build env first", cache_file));
+        }
+        let layer_path = Path::new(layer_file);
+        if layer_path.exists() && !is_toolchain_layer_intact(layer_path)? {
+            warn!("{} does not match the sha256 recorded at its creation, recreating it", layer_file);
+            fs::remove_file(layer_path)?;
+        }
+        let provisioner = self.get_provisioner()?;
+        let commands = self.get_layer_commands(provisioner.get_setup_commands());
+        create_exclusively(layer_path, |tmp_file| {
+            info!("Installing the toolchain into {}", layer_file);
+            let download_cache_dir = self.get_download_cache_dir();
+            provisioner.fetch(&download_cache_dir, &ContentStore::new(ContentStore::default_root()))?;
+            let staging_dir = tempfile::tempdir()?;
+            provisioner.stage_into(&download_cache_dir, staging_dir.path())?;
+            self.run_layer_script(&cache_file, tmp_file, &commands, staging_dir.path())
+                .map_err(|err| eyre!("Failed to create toolchain layer {}: {}", layer_file, err))?;
+            // written before the layer is moved into place, so a layer always has its stamp
+            ChrootStamp::new(tmp_file, None)?.write(layer_path)
+        })
+    }
+
+    // apt goes through the proxy while the commands run, the proxy is not kept in the tarball,
+    // which is shared by every config
+    fn get_layer_commands(&self, commands: Vec<String>) -> Vec<String> {
+        let proxy_command = self.get_apt_proxy_command();
+        let mut layer_commands: Vec<String> = proxy_command.iter().cloned().collect();
+        layer_commands.push("apt-get update".to_string());
+        layer_commands.extend(commands);
+        if proxy_command.is_some() {
+            layer_commands.push(format!("rm -f {}", APT_PROXY_FILE));
+        }
+        layer_commands
+    }
+
+    // unpacks tarball, runs the commands in it and packs the result into out_file
+    fn run_layer_script(&self, tarball: &str, out_file: &Path, commands: &[String], staging_dir: &Path) -> Result<()> {
+        let chroot_dir = tempfile::tempdir()?;
+        let status = Command::new("unshare")
+            .args(["--map-root-user", "--map-auto", "--mount", "--pid", "--fork"])
+            .args(["sh", "-c", TOOLCHAIN_LAYER_SCRIPT, "sh"])
+            .arg(tarball)
+            .arg(chroot_dir.path())
+            .arg(out_file)
+            .arg(commands.join(" && "))
+            .arg(staging_dir)
+            .status()?;
+        if !status.success() {
+            return Err(eyre!("exited with {}", status));
+        }
+        Ok(())
+    }
+
+    // the packages of the cached chroot upgraded in place, the tarball is replaced atomically
+    fn upgrade_chroot(&self, cache_file: &str) -> Result<()> {
+        check_emulation(self.config.build_env.get_chroot_arch())?;
+        let path = Path::new(cache_file);
+        let commands = self.get_layer_commands(vec![CHROOT_UPGRADE_COMMAND.to_string()]);
+        info!("Upgrading the packages of {}", cache_file);
+        let tmp_dir = tempfile::Builder::new().prefix(".tmp-").tempdir_in(path.parent().unwrap())?;
+        let tmp_file = tmp_dir.path().join(path.file_name().unwrap());
+        let staging_dir = tempfile::tempdir()?;
+        self.run_layer_script(cache_file, &tmp_file, &commands, staging_dir.path())
+            .map_err(|err| eyre!("Failed to upgrade {}: {}", cache_file, err))?;
+        fs::rename(&tmp_file, path)?;
+        Ok(())
+    }
+
+    // toolchain layers are built on top of the cache file, so they go with it
+    fn remove_toolchain_layers(&self, cache_file: &str) -> Result<()> {
+        let layers = format!("{}-*.tar.gz", glob::Pattern::escape(cache_file.trim_end_matches(".tar.gz")));
+        for layer in glob::glob(&layers)?.flatten() {
+            info!("Cleaning toolchain layer: {}", layer.display());
+            fs::remove_file(&layer).map_err(|_| eyre!("Could not remove toolchain layer {}!", layer.display()))?;
+            let stamp_file = get_stamp_file(&layer);
+            if stamp_file.exists() {
+                fs::remove_file(&stamp_file)?;
+            }
+            remove_base(&layer)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_jobs(&self) -> usize {
+        resolve_jobs(self.config.build_env.jobs)
+    }
+
+    /// User the package is built as inside of the chroot, never root.
+    pub fn get_build_user(&self) -> String {
+        if let Some(build_user) = &self.config.build_env.build_user {
+            return build_user.clone();
+        }
+        match whoami::username() {
+            user if user == "root" => "sbuild".to_string(),
+            user => user,
+        }
+    }
+
+    // exposes the parallelism to the language build systems inside of the chroot
+    pub fn get_build_environment(&self) -> BTreeMap<String, String> {
+        let jobs = self.get_jobs().to_string();
+        let mut build_environment = BTreeMap::new();
+        let lang_env = match &self.config.package_type {
+            PackageType::Default(config) => Some(&config.language_env),
+            PackageType::Git(config) => Some(&config.language_env),
+            PackageType::Virtual => None,
+        };
+        match lang_env {
+            Some(LanguageEnv::Rust(_)) => {
+                build_environment.insert("CARGO_BUILD_JOBS".to_string(), jobs);
+            }
+            Some(LanguageEnv::Go(_)) => {
+                build_environment.insert("GOMAXPROCS".to_string(), jobs);
+            }
+            Some(LanguageEnv::Zig(_)) => {
+                // the home dir of the build user does not exist in the chroot
+                build_environment.insert("ZIG_GLOBAL_CACHE_DIR".to_string(), "/tmp/zig-cache".to_string());
+            }
+            Some(LanguageEnv::Java(config)) if config.gradle.is_some() => {
+                build_environment.insert(
+                    "GRADLE_OPTS".to_string(),
+                    format!("-Dorg.gradle.parallel=true -Dorg.gradle.workers.max={}", jobs),
+                );
+                if config.offline_dependencies == Some(true) {
+                    // picks up the vendored caches and the offline init script
+                    build_environment.insert(
+                        "GRADLE_USER_HOME".to_string(),
+                        GRADLE_USER_HOME.to_string(),
+                    );
+                }
+            }
+            Some(LanguageEnv::Python(config)) if config.offline_dependencies == Some(true) => {
+                // pip installs only the vendored wheels, their hashes were verified on the host
+                build_environment.insert("PIP_NO_INDEX".to_string(), "1".to_string());
+                build_environment.insert("PIP_FIND_LINKS".to_string(), PYTHON_WHEELS_DIR.to_string());
+            }
+            _ => {}
+        }
+        if let Some(lang_env) = lang_env {
+            build_environment.extend(lang_env.get_build_options());
+        }
+        if let Some(proxies) = &self.config.build_env.proxies {
+            if let Some(go) = &proxies.go {
+                build_environment.insert("GOPROXY".to_string(), go.clone());
+            }
+            if let Some(npm) = &proxies.npm {
+                build_environment.insert("NPM_CONFIG_REGISTRY".to_string(), npm.clone());
+            }
+        }
+        if let Some(build_path) = self.get_build_path() {
+            let prefix_map = format!("-fdebug-prefix-map={}=.", build_path);
+            build_environment.insert("DEB_CFLAGS_APPEND".to_string(), prefix_map.clone());
+            build_environment.insert("DEB_CXXFLAGS_APPEND".to_string(), prefix_map);
+            match lang_env {
+                Some(LanguageEnv::Rust(_)) => {
+                    build_environment.insert(
+                        "RUSTFLAGS".to_string(),
+                        format!("--remap-path-prefix={}=.", build_path),
+                    );
+                }
+                Some(LanguageEnv::Go(_)) => {
+                    let goflags = match build_environment.get("GOFLAGS") {
+                        Some(goflags) => format!("-trimpath {}", goflags),
+                        None => "-trimpath".to_string(),
+                    };
+                    build_environment.insert("GOFLAGS".to_string(), goflags);
+                }
+                _ => {}
+            }
+        }
+        build_environment
+    }
+
+    /// Build directory inside of the chroot, None leaves the choice to sbuild.
+    pub fn get_build_path(&self) -> Option<String> {
+        if self.config.build_env.fixed_build_path != Some(true) {
+            return None;
+        }
+        Some(format!("/build/pkg-{}", self.config.package_fields.package_name))
+    }
+
+    pub fn get_distribution(&self) -> Result<&'static dyn Distribution> {
+        distribution::get_distribution(&self.config.build_env.codename)
+    }
+
+    pub fn get_repo_url(&self) -> Result<String> {
+        match &self.config.build_env.repo_url {
+            Some(repo_url) => Ok(repo_url.clone()),
+            None => Ok(self.get_distribution()?.info().repo_url.to_string()),
+        }
+    }
+
+    // image the container backends build in, kept apart from the smoke test image
+    fn get_build_image(&self) -> Result<String> {
+        if let Some(image) = &self.config.build_env.container_image {
+            return Ok(image.clone());
+        }
+        Ok(self
+            .get_distribution()?
+            .info()
+            .container_image
+            .ok_or_else(|| {
+                eyre!(
+                    "No container image known for {}, set build_env.container_image.",
+                    self.config.build_env.codename
+                )
+            })?
+            .to_string())
+    }
+
+    // container image of the suite the smoke tests and the podman testbed run in
+    fn get_container_image(&self) -> Result<String> {
+        if let Some(image) = self.config.smoke_test.as_ref().and_then(|smoke_test| smoke_test.image.clone()) {
+            return Ok(image);
+        }
+        Ok(self
+            .get_distribution()?
+            .info()
+            .container_image
+            .ok_or_else(|| {
+                eyre!(
+                    "No container image known for {}, set smoke_test.image.",
+                    self.config.build_env.codename
+                )
+            })?
+            .to_string())
+    }
+
     pub fn get_deb_dir(&self) -> &Path {
-        let deb_dir = Path::new(&self.build_files_dir).parent().unwrap();
+        let deb_dir = self.build_files_dir.parent().unwrap();
         deb_dir
     }
     pub fn get_deb_name(&self) -> PathBuf {
         let deb_dir = self.get_deb_dir();
         let deb_file_name = format!(
-            "{}_{}-{}_{}.deb",
+            "{}_{}_{}.deb",
             self.config.package_fields.package_name,
-            self.config.package_fields.version_number,
-            self.config.package_fields.revision_number,
-            self.config.build_env.arch
+            self.config.package_fields.get_file_version(),
+            self.config.build_env.arch.get_deb_arch()
         );
-        let deb_name = deb_dir.join(deb_file_name);
-        deb_name
+        deb_dir.join(deb_file_name)
     }
 
-    //hello-world_1.0.0-1_amd64.changes
-    pub fn get_changes_file(&self) -> PathBuf {
+    //hello-world_1.0.0-1_amd64.build
+    pub fn get_build_log_file(&self) -> PathBuf {
         let deb_dir = self.get_deb_dir();
-        let deb_file_name = format!(
-            "{}_{}-{}_{}.changes",
+        let build_log_name = format!(
+            "{}_{}_{}.build",
             self.config.package_fields.package_name,
-            self.config.package_fields.version_number,
-            self.config.package_fields.revision_number,
-            self.config.build_env.arch
+            self.config.package_fields.get_file_version(),
+            self.config.build_env.arch.get_build_arch()
         );
-        let deb_name = deb_dir.join(deb_file_name);
-        deb_name
+        deb_dir.join(build_log_name)
     }
-}
 
-impl BackendBuildEnv for Sbuild {
-    fn clean(&self) -> Result<()> {
-        let cache_file = self.get_cache_file();
-        info!("Cleaning cached build: {}", cache_file);
-        let path = Path::new(&cache_file);
-        if path.exists() {
-            remove_file_or_directory(&cache_file, false)
-                .map_err(|_| eyre!("Could not remove previous cache file!"))?;
+    /// Artifacts listed in the verify config whose sha1 differs from the recorded one
+    pub fn get_hash_updates(&self, verify_config: &PkgVerifyConfig) -> Result<Vec<HashUpdate>> {
+        let output_dir = self.get_deb_dir();
+        let mut updates = vec![];
+        for output in verify_config.verify.package_hash.iter() {
+            let file = output_dir.join(&output.name);
+            if !file.exists() {
+                return Err(eyre!("File to be verified does not exist {}", output.name));
+            }
+            let actual_sha1 = calculate_sha1(open_for_hashing(&file)?)?;
+            if actual_sha1 != output.hash {
+                updates.push(HashUpdate {
+                    name: output.name.clone(),
+                    old_hash: output.hash.clone(),
+                    new_hash: actual_sha1,
+                });
+            }
         }
-        Ok(())
+        Ok(updates)
     }
 
-    fn create(&self) -> Result<()> {
-        let mut temp_dir = env::temp_dir();
-        let dir_name = format!("temp_{}", random::<u32>());
-        temp_dir.push(dir_name);
-        fs::create_dir(&temp_dir)?;
-
-        let cache_file = self.get_cache_file();
-        let cache_dir = Path::new(&cache_file).parent().unwrap();
-        create_dir_all(cache_dir).map_err(|_| eyre!("Failed to create cache_dir"))?;
-        let codename = normalize_codename(&self.config.build_env.codename)?;
-
-        let repo_url = get_repo_url(&self.config.build_env.codename.as_str())?;
-        let create_result = Command::new("sbuild-createchroot")
-            .arg("--chroot-mode=unshare")
-            .arg("--make-sbuild-tarball")
-            .arg(cache_file)
-            .arg(codename)
-            .arg(temp_dir)
-            .arg(repo_url)
-            .status();
-
-        if let Err(err) = create_result {
-            return Err(eyre!(format!("Failed to create new chroot: {}", err)));
-        }
-        Ok(())
+    //hello-world_1.0.0-1_amd64.manifest.toml
+    pub fn get_manifest_file(&self) -> PathBuf {
+        let deb_dir = self.get_deb_dir();
+        let manifest_name = format!(
+            "{}_{}_{}.manifest.toml",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_file_version(),
+            self.config.build_env.arch.get_build_arch()
+        );
+        deb_dir.join(manifest_name)
     }
-    fn package(&self) -> Result<()> {
-        let codename = normalize_codename(&self.config.build_env.codename)?;
 
+    // arguments shared by the source build and binary-only rebuilds
+    fn get_sbuild_args(&self) -> Result<Vec<String>> {
+        let distribution = self.get_distribution()?.info();
+        let codename = distribution.suite;
+
+        let layer_file = self.get_toolchain_layer_file()?;
         let mut cmd_args = vec![
             "-d".to_string(),
             codename.to_string(),
-            "-A".to_string(),                    // build_arch_all
-            "-s".to_string(),                    // build source
-            "--source-only-changes".to_string(), // source_only_changes
             "-c".to_string(), // override cache file location, default is ~/.cache/sbuild both by sbuild and pkg-builder
-            self.get_cache_file(),
+            self.get_chroot_tarball()?,
             "-v".to_string(), // verbose
             "--chroot-mode=unshare".to_string(),
+            format!("--jobs={}", self.get_jobs()), // passed to dpkg-buildpackage as parallel=N
         ];
+        cmd_args.extend(get_arch_args(&self.config.build_env));
+        if let Some(build_path) = self.get_build_path() {
+            cmd_args.push(format!("--build-path={}", build_path));
+        }
 
-        let mut lang_deps = self.get_build_deps_not_in_debian();
+        let quiet = self.is_quiet_chroot_setup();
+        // the proxies are set up first, so the toolchain is installed through them
+        let mut lang_deps = self.get_proxy_setup_commands();
+        // the layer already has the toolchain installed
+        if layer_file.is_none() {
+            let mut provisioner = self.get_provisioner()?;
+            provisioner.quiet = quiet;
+            for command in provisioner.get_stage_commands(&self.get_download_cache_dir()) {
+                cmd_args.push(format!("--pre-build-commands={}", command));
+            }
+            lang_deps.extend(provisioner.get_setup_commands());
+        }
 
-        if &self.config.build_env.codename == "noble numbat" {
-            lang_deps.push("apt install -y software-properties-common".to_string());
-            lang_deps.push("add-apt-repository universe".to_string());
-            lang_deps.push("add-apt-repository restricted".to_string());
-            lang_deps.push("add-apt-repository multiverse".to_string());
-            lang_deps.push("apt update".to_string());
+        for (index, command) in distribution.chroot_setup_commands.iter().enumerate() {
+            match quiet {
+                true => lang_deps.push(quiet_checked_command(&format!("setup-{}", index), command)),
+                false => lang_deps.push(command.to_string()),
+            }
         }
 
         for action in lang_deps.iter() {
             cmd_args.push(format!("--chroot-setup-commands={}", action))
         }
 
+        // snapshots of the build environment for the manifest, the build should not change it
+        cmd_args.push(format!("--starting-build-commands={}", chroot_packages_before_command()));
+        cmd_args.push(format!("--finished-build-commands={}", chroot_packages_command()));
         cmd_args.push("--no-run-piuparts".to_string());
         cmd_args.push("--no-apt-upgrade".to_string());
         cmd_args.push("--no-apt-distupgrade".to_string());
+        Ok(cmd_args)
+    }
+
+    // arguments of the sbuild run of package
+    fn get_package_args(&self) -> Result<Vec<String>> {
+        let mut cmd_args = vec![
+            "-A".to_string(),                    // build_arch_all
+            "-s".to_string(),                    // build source
+            "--source-only-changes".to_string(), // source_only_changes
+        ];
+        if self.config.build_env.arch == Arch::All {
+            cmd_args.push("--no-arch-any".to_string());
+        }
+        cmd_args.append(&mut self.get_sbuild_args()?);
 
         if let Some(true) = self.config.build_env.run_lintian {
             cmd_args.push("--run-lintian".to_string());
@@ -462,19 +1011,872 @@ impl BackendBuildEnv for Sbuild {
         }
 
         cmd_args.push("--no-run-autopkgtest".to_string());
+        cmd_args.append(&mut self.get_hook_args());
+        Ok(cmd_args)
+    }
 
-        info!(
-            "Building package by invoking: sbuild {}",
-            cmd_args.join(" ")
+    fn has_multi_arch_checks(&self) -> bool {
+        self.config.multi_arch.as_ref().is_some_and(|multi_arch| !multi_arch.same.is_empty())
+    }
+
+    /// The sbuild run and the checks of package in the order they run, nothing is executed.
+    pub fn get_plan(&self) -> Result<BuildPlan> {
+        let mut command = vec!["sbuild".to_string()];
+        command.extend(self.get_package_args()?);
+        if let Some(redactor) = self.get_redactor()? {
+            command = command.iter().map(|arg| redactor.redact(arg)).collect();
+        }
+        let mut sbuild = PlanStage::new("sbuild");
+        sbuild.commands.push(command);
+        sbuild.cwd = Some(self.build_files_dir.to_string_lossy().to_string());
+        let mut stages = vec![];
+        if self.config.license_scan.is_some() {
+            stages.push(PlanStage::new("license_scan"));
+        }
+        stages.push(sbuild);
+        let build_env = &self.config.build_env;
+        for (name, enabled) in [
+            ("unit_checks", build_env.run_unit_checks == Some(true)),
+            ("debconf_checks", build_env.run_debconf_checks == Some(true)),
+            ("content_checks", self.config.content_policy.is_some()),
+            ("multi_arch_checks", self.has_multi_arch_checks()),
+            ("abi_checks", self.config.abi.is_some()),
+            ("piuparts", build_env.run_piuparts == Some(true)),
+            ("autopkgtest", build_env.run_autopkgtest == Some(true)),
+            ("post_process", self.config.post_process.is_some()),
+        ] {
+            if enabled {
+                stages.push(PlanStage::new(name));
+            }
+        }
+
+        let manifest = self.get_manifest()?;
+        let mut inputs = manifest.fingerprint;
+        if let Some(recipe_revision) = manifest.recipe_revision {
+            inputs.insert("recipe_revision".to_string(), recipe_revision);
+        }
+        let path = |path: &Path| path.to_string_lossy().to_string();
+        let paths = BTreeMap::from([
+            ("build_files_dir".to_string(), path(&self.build_files_dir)),
+            ("deb_dir".to_string(), path(self.get_deb_dir())),
+            ("cache_file".to_string(), self.get_toolchain_layer_file()?.unwrap_or_else(|| self.get_cache_file())),
+            ("manifest_file".to_string(), path(&self.get_manifest_file())),
+            ("quality_report_file".to_string(), path(&self.get_quality_report_file())),
+        ]);
+        let broker = PrivilegeBroker::new(&self.config.build_env);
+        let mut privileged = vec![];
+        for (name, program) in self.get_privileged_operations()? {
+            let mut command = broker.get_prefix(name)?;
+            command.push(program);
+            privileged.push(PrivilegedOperation {
+                name: name.to_string(),
+                command,
+            });
+        }
+        Ok(BuildPlan {
+            schema_version: PLAN_SCHEMA_VERSION,
+            package_name: self.config.package_fields.package_name.clone(),
+            version: self.config.package_fields.get_debian_version(),
+            codename: build_env.codename.clone(),
+            arch: build_env.arch.to_string(),
+            paths,
+            inputs,
+            stages,
+            privileged,
+        })
+    }
+
+    // autopkgtest-bookworm-amd64.img in the cache dir
+    fn get_autopkgtest_image_file(&self, key: &str) -> PathBuf {
+        let image_name = format!(
+            "autopkgtest-{}-{}.img",
+            key,
+            self.config.build_env.arch.get_build_arch()
         );
+        Path::new(&shellexpand::tilde(&self.cache_dir).to_string()).join(image_name)
+    }
+
+    /// Operations of package needing root and the program each runs.
+    pub fn get_privileged_operations(&self) -> Result<Vec<(&'static str, String)>> {
+        let build_env = &self.config.build_env;
+        let mut operations = vec![];
+        if build_env.run_piuparts == Some(true) {
+            operations.push(("piuparts", "piuparts".to_string()));
+        }
+        if build_env.run_autopkgtest == Some(true) {
+            let distribution = self.get_distribution()?;
+            let arch = build_env.arch.get_build_arch();
+            let is_qemu = get_testbed(build_env.autopkgtest_testbed, arch) == AutopkgtestTestbed::Qemu;
+            if is_qemu && !self.get_autopkgtest_image_file(distribution.info().key).exists() {
+                let cmd = distribution.autopkgtest_image_command(&self.get_autopkgtest_image_file(distribution.info().key), arch, "");
+                operations.push(("autopkgtest image creation", cmd.get_program().to_string_lossy().to_string()));
+            }
+        }
+        Ok(operations)
+    }
+
+    fn get_redactor(&self) -> Result<Option<Redactor>> {
+        self.config.redact.as_ref().map(Redactor::new).transpose()
+    }
+
+    // expanded against the config root by the packager
+    fn get_preseed_file(&self) -> Option<PathBuf> {
+        self.config
+            .debconf
+            .as_ref()
+            .map(|debconf| PathBuf::from(&debconf.preseed_file))
+    }
+
+    fn watch(&self, child: &Child, stage: &str) -> Option<Watch> {
+        self.config
+            .watchdog
+            .as_ref()
+            .map(|config| Watchdog::new(config).watch(child, stage))
+    }
+
+    fn redact_build_log(&self) -> Result<()> {
+        if let Some(redactor) = self.get_redactor()? {
+            redactor.redact_file(&self.get_build_log_file())?;
+        }
+        Ok(())
+    }
+
+    fn run_sbuild(&self, cmd_args: &[String]) -> Result<()> {
+        match self.get_toolchain_layer_file()? {
+            Some(layer_file) => self.create_toolchain_layer(&layer_file)?,
+            None => self
+                .get_provisioner()?
+                .fetch(&self.get_download_cache_dir(), &ContentStore::new(ContentStore::default_root()))?,
+        }
+        let chroot_tarball = self.get_chroot_tarball()?;
+        // kept until sbuild is done, dropping it deletes the snapshot
+        let snapshot = match self.config.build_env.snapshot_chroot {
+            Some(true) => get_chroot_snapshot(Path::new(&chroot_tarball)),
+            _ => None,
+        };
+        let mut cmd_args = cmd_args.to_vec();
+        if let Some(snapshot) = &snapshot {
+            for arg in cmd_args.iter_mut().filter(|arg| **arg == chroot_tarball) {
+                *arg = snapshot.get_dir().to_str().unwrap().to_string();
+            }
+        }
+        let mut command_line = cmd_args.join(" ");
+        if let Some(redactor) = self.get_redactor()? {
+            command_line = redactor.redact(&command_line);
+        }
+        info!("Building package by invoking: sbuild {}", command_line);
 
+        let quiet = self.is_quiet_chroot_setup();
+        // the watchdog reads the output to tell a stalled build from a busy one
+        let piped = quiet || self.config.watchdog.is_some();
         let mut cmd = Command::new("sbuild")
-            .current_dir(self.build_files_dir.clone())
+            .current_dir(&self.build_files_dir)
             .args(&cmd_args)
-            .stdout(Stdio::inherit())
+            .stdout(if piped { Stdio::piped() } else { Stdio::inherit() })
             .stderr(Stdio::inherit())
             .spawn()?;
-        run_process(&mut cmd)?;
+        let watch = self.watch(&cmd, "sbuild");
+        let heartbeat = watch.as_ref().map(Watch::heartbeat);
+        let build_result = match quiet {
+            true => run_process_quiet_provision(&mut cmd, heartbeat),
+            false => run_process(&mut cmd, heartbeat),
+        };
+        let build_result = stop_watch(watch).and(build_result);
+        self.finish_build_log(build_result)
+    }
+
+    // the build log of every backend is redacted, gathered and archived, also for failed builds
+    fn finish_build_log(&self, build_result: Result<()>) -> Result<()> {
+        let quiet = self.is_quiet_chroot_setup();
+        // secrets are masked before the log is copied anywhere else
+        if let Err(err) = self.redact_build_log() {
+            warn!("Failed to redact build log, it is not archived: {}", err);
+            return build_result;
+        }
+        // cleanup hooks also run for failed builds
+        if let Err(err) = self.write_hook_logs() {
+            warn!("Failed to gather build hook output: {}", err);
+        }
+        if let Err(err) = self.report_provision() {
+            warn!("Failed to gather the provisioning results: {}", err);
+        }
+        if quiet {
+            if let Err(err) = self.write_provision_logs() {
+                warn!("Failed to gather the provisioning output: {}", err);
+            }
+        }
+        if let Err(err) = self.archive_build_log() {
+            warn!("Failed to archive build log: {}", err);
+        }
+        build_result
+    }
+
+    // each step installing the toolchain reported its result in the build log
+    fn report_provision(&self) -> Result<()> {
+        let results = parse_provision_results(&fs::read_to_string(self.get_build_log_file())?);
+        if results.is_empty() {
+            return Ok(());
+        }
+        for result in results.iter().filter(|result| result.status != "ok") {
+            warn!("Provisioning step {} of the build chroot {}", result.step, result.status);
+        }
+        self.update_quality_report(|report| report.provision = Some(results))
+    }
+
+    /// Points apt and cargo in the chroot to the configured proxies, run before anything is installed.
+    pub fn get_proxy_setup_commands(&self) -> Vec<String> {
+        let Some(proxies) = &self.config.build_env.proxies else {
+            return vec![];
+        };
+        let mut commands: Vec<String> = self.get_apt_proxy_command().into_iter().collect();
+        // cargo reads the config of every parent dir of the build dir, / included
+        if let Some(cargo) = &proxies.cargo {
+            commands.push(format!(
+                "mkdir -p /.cargo && printf '[source.crates-io]\\nreplace-with = \"pkg-builder-mirror\"\\n\\n[source.pkg-builder-mirror]\\nregistry = \"{}\"\\n' > /.cargo/config.toml",
+                cargo
+            ));
+        }
+        commands
+    }
+
+    fn get_apt_proxy_command(&self) -> Option<String> {
+        let apt = self.config.build_env.proxies.as_ref()?.apt.as_ref()?;
+        Some(format!("echo 'Acquire::http::Proxy \"{}\";' > {}", apt, APT_PROXY_FILE))
+    }
+
+    fn is_quiet_chroot_setup(&self) -> bool {
+        self.config.build_env.quiet_chroot_setup.unwrap_or(false)
+    }
+
+    // the full output of every quiet provisioning step, next to the build log
+    fn write_provision_logs(&self) -> Result<()> {
+        let logs = parse_provision_logs(&fs::read_to_string(self.get_build_log_file())?);
+        let provision_dir = self.get_deb_dir().join("provision");
+        create_dir_all(&provision_dir)?;
+        for (step, output) in logs {
+            fs::write(provision_dir.join(format!("{}.log", step)), output)?;
+        }
+        info!("Provisioning output written to {}", provision_dir.display());
+        Ok(())
+    }
+
+    pub fn get_dsc_file(&self) -> PathBuf {
+        self.get_deb_dir().join(format!(
+            "{}_{}.dsc",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_file_version()
+        ))
+    }
+
+    fn get_binnmu_args(&self, suffix: &str, changelog: &str) -> Result<Vec<String>> {
+        let binnmu = parse_binnmu_suffix(suffix)?;
+        if self.config.build_env.arch == Arch::All {
+            return Err(eyre!("arch all packages have nothing to rebuild in a binNMU, bump the version instead."));
+        }
+        let dsc_file = self.get_dsc_file();
+        if !dsc_file.exists() {
+            return Err(eyre!(
+                "{} not found, a binNMU rebuilds an already built source package, run `pkg-builder package` first.",
+                dsc_file.display()
+            ));
+        }
+        let mut cmd_args = self.get_sbuild_args()?;
+        cmd_args.push(format!("--binNMU={}", binnmu));
+        cmd_args.push(format!("--make-binNMU={}", changelog));
+        // arch all packages are not rebuilt by binNMUs
+        cmd_args.push("--no-arch-all".to_string());
+        cmd_args.push("--no-run-lintian".to_string());
+        cmd_args.push("--no-run-autopkgtest".to_string());
+        cmd_args.append(&mut self.get_hook_args());
+        cmd_args.push(dsc_file.to_str().unwrap().to_string());
+        Ok(cmd_args)
+    }
+
+    /// Binary-only rebuild of the built source package, appends e.g. +b1 to the version.
+    pub fn binnmu(&self, suffix: &str, changelog: &str) -> Result<()> {
+        if self.config.build_env.backend.is_some_and(|backend| backend != Backend::Sbuild) {
+            return Err(eyre!("binNMUs are built with sbuild, set build_env.backend = \"sbuild\"."));
+        }
+        let cmd_args = self.get_binnmu_args(suffix, changelog)?;
+        self.run_sbuild(&cmd_args)
+    }
+
+    fn get_hook_args(&self) -> Vec<String> {
+        let Some(build_hooks) = &self.config.build_hooks else {
+            return vec![];
+        };
+        let mut args = vec![];
+        for (option, stage, commands) in build_hooks.get_stages() {
+            for command in commands {
+                // sbuild expands % escapes in the commands, %% is a literal %
+                args.push(format!("{}={}", option, hook_command(stage, command).replace('%', "%%")));
+            }
+        }
+        args
+    }
+
+    // hooks/<stage>.log next to the artifacts
+    fn write_hook_logs(&self) -> Result<()> {
+        if self.config.build_hooks.is_none() {
+            return Ok(());
+        }
+        let build_log = fs::read_to_string(self.get_build_log_file())?;
+        let hooks_dir = self.get_deb_dir().join("hooks");
+        create_dir_all(&hooks_dir)?;
+        for (stage, output) in parse_hook_output(&build_log) {
+            fs::write(hooks_dir.join(format!("{}.log", stage)), output)?;
+        }
+        info!("Build hook output written to {}", hooks_dir.display());
+        Ok(())
+    }
+
+    // <workdir>/.logs/<package name>, survives the artifacts dir
+    fn get_log_archive_dir(&self) -> PathBuf {
+        let workdir = self.get_deb_dir().parent().unwrap();
+        workdir
+            .join(".logs")
+            .join(&self.config.package_fields.package_name)
+    }
+
+    fn archive_build_log(&self) -> Result<()> {
+        let archive_dir = self.get_log_archive_dir();
+        let archive = archive_log(&self.get_build_log_file(), &archive_dir)?;
+        info!("Build log archived to {}", archive.display());
+        let build_env = &self.config.build_env;
+        rotate_logs(
+            &archive_dir,
+            build_env.log_keep_count.unwrap_or(DEFAULT_LOG_KEEP_COUNT),
+            build_env.log_keep_size_mb.unwrap_or(DEFAULT_LOG_KEEP_SIZE_MB) * 1024 * 1024,
+        )?;
+        Ok(())
+    }
+
+    // survives the artifacts dir, which is recreated on every build
+    fn get_history_manifest_file(&self) -> PathBuf {
+        let workdir = self.get_deb_dir().parent().unwrap();
+        workdir
+            .join(".history")
+            .join(self.get_manifest_file().file_name().unwrap())
+    }
+
+    fn get_manifest(&self) -> Result<BuildManifest> {
+        let mut manifest = BuildManifest::new(&self.config)?;
+        manifest.recipe_revision = self.recipe_revision.clone();
+        // e.g. a pre-signed tarball_url
+        if let Some(redactor) = self.get_redactor()? {
+            redactor.redact_values(&mut manifest.fingerprint);
+        }
+        Ok(manifest)
+    }
+
+    /// Fails when the same version was built successfully before with different tool pins or
+    /// toolchains, the result would be different binaries under the same version.
+    pub fn check_last_build(&self) -> Result<()> {
+        let history_file = self.get_history_manifest_file();
+        let Ok(content) = fs::read_to_string(&history_file) else {
+            return Ok(());
+        };
+        let previous: BuildManifest = toml::from_str(&content)
+            .map_err(|err| eyre!("Failed to parse {}: {}", history_file.display(), err))?;
+        let current = self.get_manifest()?;
+        let changes = diff_fingerprints(&previous.fingerprint, &current.fingerprint);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let message = format!(
+            "{} {} was built before with different inputs, bump the revision with `pkg-builder bump --revision`:\n  {}",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_debian_version(),
+            changes.join("\n  ")
+        );
+        // dev builds change the patches between iterations of the same version
+        let dev = self.config.build_env.dev == Some(true);
+        if self.config.build_env.allow_changed_inputs != Some(true) && !dev {
+            return Err(eyre!("{}\nor rebuild it anyway with `package --allow-changed-inputs`", message));
+        }
+        warn!("{}", message);
+        Ok(())
+    }
+
+    // after every check passed, the outputs are recorded in the manifest
+    fn run_post_process(&self, config: &PostProcessConfig, manifest: &mut BuildManifest) -> Result<()> {
+        let debs = self.get_debs()?;
+        let manifest_file = self.get_manifest_file();
+        let inputs = PostProcessInputs {
+            artifacts_dir: self.get_deb_dir(),
+            debs: &debs,
+            manifest_file: &manifest_file,
+            base_name: format!(
+                "{}_{}_{}",
+                self.config.package_fields.package_name,
+                self.config.package_fields.get_file_version(),
+                self.config.build_env.arch.get_deb_arch()
+            ),
+            image: self.get_container_image().ok(),
+            engine: match self.config.build_env.backend {
+                Some(Backend::Docker) => "docker",
+                _ => "podman",
+            },
+        };
+        manifest.secondary_artifacts = post_process::run_post_process(config, &inputs)?;
+        for artifact in manifest.secondary_artifacts.iter() {
+            info!("Post-processed {}: {}", artifact.step, artifact.path);
+        }
+        manifest.write(&manifest_file)
+    }
+
+    // the external programs package runs with this config
+    fn get_tools(&self) -> Vec<Box<dyn Tool>> {
+        let config = &self.config;
+        let build_env = &config.build_env;
+        let mut tools: Vec<Box<dyn Tool>> = vec![
+            Box::new(HostTool::new("tar", &["--version"])),
+            Box::new(HostTool::new("wget", &["--version"])),
+            Box::new(HostTool::new("git", &["--version"])),
+            Box::new(HostTool::new("dpkg-deb", &["--version"])),
+        ];
+        if !config.uses_upstream_debian_dir() && !matches!(config.package_type, PackageType::Virtual) {
+            tools.push(Box::new(Debcrafter::new(&build_env.debcrafter_version)));
+        }
+        if config.patches.is_some() {
+            tools.push(Box::new(HostTool::new("quilt", &["--version"])));
+        }
+        match build_env.backend {
+            Some(Backend::Docker) => tools.push(Box::new(HostTool::new("docker", &["--version"]))),
+            Some(Backend::Podman) => tools.push(Box::new(HostTool::new("podman", &["--version"]))),
+            Some(Backend::Sbuild) | None => {
+                tools.push(Box::new(tools::Sbuild(build_env.sbuild_version.clone())));
+                tools.push(Box::new(HostTool::new("unshare", &["--version"])));
+            }
+        }
+        // snapshot_chroot needs btrfs or fuse-overlayfs, without them the tarball is unpacked
+        if build_env.snapshot_chroot == Some(true) {
+            let cache_file = self.get_cache_file();
+            if let Some(program) = Path::new(&cache_file).parent().and_then(get_snapshot_program) {
+                tools.push(Box::new(HostTool::new(program, &["--version"])));
+            }
+        }
+        if config.trust.is_some() {
+            tools.push(Box::new(HostTool::new("gpg", &["--version"])));
+        }
+        if build_env.run_unit_checks == Some(true) {
+            tools.push(Box::new(HostTool::new("systemd-analyze", &["--version"])));
+        }
+        if config.abi.is_some() {
+            tools.push(Box::new(HostTool::new("objdump", &["--version"])));
+        }
+        if build_env.run_lintian == Some(true) {
+            tools.push(Box::new(Lintian(build_env.lintian_version.clone())));
+        }
+        if build_env.run_piuparts == Some(true) {
+            tools.push(Box::new(Piuparts(build_env.piuparts_version.clone())));
+        }
+        if build_env.run_autopkgtest == Some(true) {
+            tools.push(Box::new(Autopkgtest(build_env.autopkgtest_version.clone())));
+            if get_testbed(build_env.autopkgtest_testbed, build_env.arch.get_build_arch()) == AutopkgtestTestbed::Podman {
+                tools.push(Box::new(HostTool::new("podman", &["--version"])));
+            }
+        }
+        if config.smoke_test.is_some() {
+            tools.push(Box::new(HostTool::new("podman", &["--version"])));
+        }
+        if let Some(post_process) = &config.post_process {
+            if post_process.steps.iter().any(|step| step.processor == Some(PostProcessor::Squashfs)) {
+                tools.push(Box::new(HostTool::new("mksquashfs", &["-version"])));
+            }
+        }
+        // podman is needed by several stages, recorded once
+        let mut names = vec![];
+        tools.retain(|tool| {
+            let is_new = !names.contains(&tool.program());
+            names.push(tool.program());
+            is_new
+        });
+        tools
+    }
+
+    pub fn write_manifest(&self) -> Result<BuildManifest> {
+        let mut manifest = self.get_manifest()?;
+        manifest.tools = get_tool_records(&self.get_tools());
+        let build_log_file = self.get_build_log_file();
+        match fs::read_to_string(&build_log_file) {
+            Ok(build_log) => manifest.apply_build_log(&build_log),
+            Err(_) => warn!(
+                "Build log {} not found, manifest will not contain facts reported from the chroot.",
+                build_log_file.display()
+            ),
+        }
+        let history_file = self.get_history_manifest_file();
+        if let Some(previous) = fs::read_to_string(&history_file)
+            .ok()
+            .and_then(|content| toml::from_str::<BuildManifest>(&content).ok())
+        {
+            if !previous.chroot_packages.is_empty() && !manifest.chroot_packages.is_empty() {
+                manifest.chroot_changes =
+                    diff_fingerprints(&previous.chroot_packages, &manifest.chroot_packages);
+            }
+        }
+        if !manifest.chroot_changes.is_empty() {
+            info!("Build environment changed since the last build:");
+            for change in manifest.chroot_changes.iter() {
+                info!("  {}", change);
+            }
+        }
+        if let Some(previous) = find_previous_version(history_file.parent().unwrap(), &manifest) {
+            if !previous.chroot_packages.is_empty() && !manifest.chroot_packages.is_empty() {
+                manifest.chroot_version_changes =
+                    diff_fingerprints(&previous.chroot_packages, &manifest.chroot_packages);
+                info!("Build environment changed since version {}:", previous.get_full_version());
+                for change in manifest.chroot_version_changes.iter() {
+                    info!("  {}", change);
+                }
+                manifest.previous_version = Some(previous.get_full_version());
+            }
+        }
+        let manifest_file = self.get_manifest_file();
+        manifest.write(&manifest_file)?;
+        info!("Build manifest written to {}", manifest_file.display());
+        Ok(manifest)
+    }
+
+    // only written once package passed every check, check_last_build compares against it
+    fn write_history_manifest(&self, manifest: &BuildManifest) -> Result<()> {
+        let history_file = self.get_history_manifest_file();
+        create_dir_all(history_file.parent().unwrap())?;
+        manifest.write(&history_file)
+    }
+
+    // network access or maintainer scripts of the build dependencies can install packages
+    // the build then depends on without declaring them
+    fn check_build_installs(&self, manifest: &BuildManifest) -> Result<()> {
+        let fail = self.config.build_env.fail_on_build_installs == Some(true);
+        let findings: Vec<ContentFinding> = manifest
+            .build_installs
+            .iter()
+            .map(|change| {
+                let (package, message) = change.split_once(": ").unwrap_or(("", change));
+                ContentFinding {
+                    package: package.to_string(),
+                    level: if fail { FindingLevel::Error } else { FindingLevel::Warning },
+                    message: format!("{} during the build", message),
+                }
+            })
+            .collect();
+        for finding in findings.iter() {
+            warn!("Chroot package {} {}", finding.package, finding.message);
+        }
+        let changed = findings.len();
+        self.update_quality_report(|report| report.build_installs = Some(findings))?;
+        if fail && changed > 0 {
+            return Err(eyre!("The build changed {} chroot packages, see build_installs in the quality report", changed));
+        }
+        Ok(())
+    }
+
+    //hello-world_1.0.0-1_amd64.quality.json
+    pub fn get_quality_report_file(&self) -> PathBuf {
+        let deb_dir = self.get_deb_dir();
+        let report_name = format!(
+            "{}_{}_{}.quality.json",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_file_version(),
+            self.config.build_env.arch.get_build_arch()
+        );
+        deb_dir.join(report_name)
+    }
+
+    fn update_quality_report(&self, update: impl FnOnce(&mut QualityReport)) -> Result<()> {
+        let report_file = self.get_quality_report_file();
+        let mut report = QualityReport::read(&report_file)?;
+        update(&mut report);
+        report.write(&report_file)?;
+        info!("Quality report written to {}", report_file.display());
+        Ok(())
+    }
+
+    // warns about every finding, records them in the quality report and fails on error level ones
+    fn report_findings<F: Finding>(
+        &self,
+        findings: Vec<F>,
+        set: impl FnOnce(&mut QualityReport, Vec<F>),
+        error_message: impl FnOnce(usize) -> String,
+    ) -> Result<()> {
+        for finding in findings.iter() {
+            warn!("{}: {}: {}", finding.subject(), finding.level(), finding.message());
+        }
+        let errors = findings.iter().filter(|finding| finding.level() == FindingLevel::Error).count();
+        self.update_quality_report(|report| set(report, findings))?;
+        if errors > 0 {
+            return Err(eyre!(error_message(errors)));
+        }
+        Ok(())
+    }
+
+    //hello-world_1.0.0-1_amd64.changes
+    // named after the built architectures, _all.changes when only arch all debs are built
+    pub fn get_changes_file(&self) -> PathBuf {
+        let deb_dir = self.get_deb_dir();
+        let deb_file_name = format!(
+            "{}_{}_{}.changes",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_file_version(),
+            self.config.build_env.arch.get_deb_arch()
+        );
+        deb_dir.join(deb_file_name)
+    }
+
+    /// The source-only changes file of --source-only-changes, for uploads.
+    pub fn get_source_changes_file(&self) -> PathBuf {
+        self.get_deb_dir().join(format!(
+            "{}_{}_source.changes",
+            self.config.package_fields.package_name,
+            self.config.package_fields.get_file_version()
+        ))
+    }
+
+    // the Multi-Arch: same debs must be co-installable with the ones of other architectures
+    fn run_multi_arch_checks(&self) -> Result<()> {
+        let Some(config) = &self.config.multi_arch else {
+            return Ok(());
+        };
+        let debs = self.get_debs()?;
+        let findings = multi_arch::check_debs(&debs, config)?;
+        self.report_findings(
+            findings,
+            |report, findings| report.multi_arch = Some(findings),
+            |errors| format!("{} Multi-Arch co-installability errors in the built debs", errors),
+        )
+    }
+
+    fn run_abi_checks(&self) -> Result<()> {
+        let Some(config) = &self.config.abi else {
+            return Ok(());
+        };
+        let info = self.get_distribution()?.info();
+        let baseline = AbiBaseline {
+            glibc: config.max_glibc_version.clone().or(info.glibc_version.map(str::to_string)),
+            glibcxx: config.max_glibcxx_version.clone().or(info.glibcxx_version.map(str::to_string)),
+        };
+        if baseline == AbiBaseline::default() {
+            warn!("No glibc baseline known for {}, set abi.max_glibc_version to check the binaries", info.codename);
+            return Ok(());
+        }
+        let level = match config.fail_on_newer.unwrap_or(true) {
+            true => FindingLevel::Error,
+            false => FindingLevel::Warning,
+        };
+        let debs = self.get_debs()?;
+        let findings = abi::check_debs(&debs, &baseline, level)?;
+        self.report_findings(
+            findings,
+            |report, findings| report.abi = Some(findings),
+            |errors| format!("{} ABI errors in the built debs, they require newer symbols than {} provides", errors, info.codename),
+        )
+    }
+
+    fn run_license_scan(&self) -> Result<()> {
+        let config = self
+            .config
+            .license_scan
+            .as_ref()
+            .ok_or_else(|| eyre!("No [license_scan] configured for the package."))?;
+        let scan = scan_licenses(&self.build_files_dir, &config.exclude)?;
+        for summary in scan.directories.iter() {
+            info!("{}: {}", summary.directory, summary.licenses.join(", "));
+        }
+        for finding in scan.findings.iter() {
+            warn!("{}: {}: {} {}", finding.path, finding.level, finding.license, finding.message);
+        }
+        let errors = scan.findings.iter().filter(|finding| finding.level == FindingLevel::Error).count();
+        self.update_quality_report(|report| report.licenses = Some(scan))?;
+        if errors > 0 {
+            return Err(eyre!("{} copyleft licenses in the source are not declared in debian/copyright", errors));
+        }
+        Ok(())
+    }
+
+    /// The built debs declared in the changes file.
+    pub fn get_debs(&self) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .get_artifacts()?
+            .into_iter()
+            .filter(|artifact| artifact.extension() == Some("deb".as_ref()))
+            .collect())
+    }
+
+    /// The .changes file and every file it declares, e.g. .dsc, .orig.tar.gz, .deb
+    pub fn get_artifacts(&self) -> Result<Vec<PathBuf>> {
+        let changes_file = self.get_changes_file();
+        let changes_content = fs::read_to_string(&changes_file).map_err(|_| {
+            eyre!(
+                "Could not read changes file {}, was the package built?",
+                changes_file.display()
+            )
+        })?;
+        let deb_dir = self.get_deb_dir();
+        let mut artifacts = vec![];
+        for checksum in parse_changes_checksums(&changes_content)? {
+            let artifact = deb_dir.join(&checksum.name);
+            if !artifact.exists() {
+                return Err(eyre!(
+                    "file {} declared in changes file does not exist",
+                    checksum.name
+                ));
+            }
+            artifacts.push(artifact);
+        }
+        artifacts.push(changes_file);
+        Ok(artifacts)
+    }
+
+    // end of life and Release file findings of the targeted suite, see archive.rs
+    fn check_distribution(&self) -> Result<()> {
+        let info = self.get_distribution()?.info();
+        let offline = self.config.build_env.offline == Some(true);
+        let findings = check_distribution(info, &self.get_repo_url()?, offline);
+        for finding in findings.iter() {
+            warn!("{}: {}", finding.check, finding.message);
+        }
+        let eol = findings.iter().any(|finding| finding.check == "eol");
+        self.update_quality_report(|report| report.archive = Some(findings))?;
+        if eol && self.config.build_env.fail_on_eol == Some(true) {
+            return Err(eyre!(
+                "{} is past its end of life, released packages would not get security updates",
+                info.codename
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl BackendBuildEnv for Sbuild {
+    fn clean(&self) -> Result<()> {
+        if let Some(backend) = self.get_container_backend()? {
+            return backend.clean();
+        }
+        let cache_file = self.get_cache_file();
+        info!("Cleaning cached build: {}", cache_file);
+        let path = Path::new(&cache_file);
+        if path.exists() {
+            remove_file_or_directory(&cache_file, false)
+                .map_err(|_| eyre!("Could not remove previous cache file!"))?;
+        }
+        let stamp_file = get_stamp_file(path);
+        if stamp_file.exists() {
+            fs::remove_file(&stamp_file)?;
+        }
+        remove_base(path)?;
+        self.remove_toolchain_layers(&cache_file)
+    }
+
+    fn create(&self) -> Result<()> {
+        if let Some(backend) = self.get_container_backend()? {
+            return backend.create();
+        }
+        let cache_file = self.get_cache_file();
+        let codename = self.get_distribution()?.info().suite;
+        let repo_url = self.get_repo_url()?;
+        let chroot_arch = self.config.build_env.get_chroot_arch();
+        HostEnv::get().check_build_env(&self.config.build_env)?;
+        check_emulation(chroot_arch)?;
+        // parallel builds of the same suite and arch share the tarball
+        create_exclusively(Path::new(&cache_file), |tmp_file| {
+            let chroot_dir = tempfile::tempdir()?;
+            let create_result = Command::new("sbuild-createchroot")
+                .arg("--chroot-mode=unshare")
+                .arg(format!("--arch={}", chroot_arch))
+                .arg("--make-sbuild-tarball")
+                .arg(tmp_file)
+                .arg(codename)
+                .arg(chroot_dir.path())
+                .arg(&repo_url)
+                .status();
+            match create_result {
+                Ok(status) if status.success() => Ok(()),
+                Ok(status) => Err(eyre!("Failed to create new chroot: sbuild-createchroot exited with {}", status)),
+                Err(err) => Err(eyre!(format!("Failed to create new chroot: {}", err))),
+            }
+        })?;
+        let path = Path::new(&cache_file);
+        if !get_stamp_file(path).exists() {
+            // tarballs of earlier versions count from their modification time
+            ChrootStamp::new(path, Some(ChrootStamp::read(path)?.created))?.write(path)?;
+        }
+        Ok(())
+    }
+
+    fn update(&self) -> Result<()> {
+        if let Some(backend) = self.get_container_backend()? {
+            return Err(eyre!(
+                "env update refreshes sbuild chroots only, run env clean and env create to rebuild the {} image",
+                backend.engine
+            ));
+        }
+        let cache_file = self.get_cache_file();
+        let path = Path::new(&cache_file);
+        if !path.exists() {
+            info!("{} not found, creating it", cache_file);
+            return self.create();
+        }
+        let stamp = ChrootStamp::read(path)?;
+        let max_age_days = self.config.build_env.chroot_max_age_days.unwrap_or(DEFAULT_CHROOT_MAX_AGE_DAYS);
+        if stamp.is_expired(max_age_days) {
+            info!("{} was created {} days ago, recreating it", cache_file, stamp.get_age_days());
+            self.clean()?;
+            return self.create();
+        }
+        self.upgrade_chroot(&cache_file)?;
+        ChrootStamp::new(path, Some(stamp.created))?.write(path)?;
+        // rebuilt on top of the upgraded chroot by the next build
+        self.remove_toolchain_layers(&cache_file)
+    }
+    fn package(&self) -> Result<()> {
+        self.check_distribution()?;
+        if self.config.build_env.strict_tool_versions == Some(true) {
+            check_pinned_versions(&get_tool_records(&self.get_tools()))?;
+        }
+        let privileged: Vec<&str> = self.get_privileged_operations()?.iter().map(|(name, _)| *name).collect();
+        PrivilegeBroker::new(&self.config.build_env).check(&privileged)?;
+        if self.config.license_scan.is_some() {
+            self.run_license_scan()?;
+        }
+        match self.get_container_backend()? {
+            Some(backend) => {
+                self.finish_build_log(backend.build())?;
+                // sbuild runs lintian itself
+                if let Some(true) = self.config.build_env.run_lintian {
+                    self.run_lintian()?;
+                }
+            }
+            None => {
+                HostEnv::get().check_build_env(&self.config.build_env)?;
+                check_emulation(self.config.build_env.get_chroot_arch())?;
+                let cmd_args = self.get_package_args()?;
+                self.run_sbuild(&cmd_args)?;
+            }
+        }
+        let mut manifest = self.write_manifest()?;
+        self.check_build_installs(&manifest)?;
+
+        if let Some(true) = self.config.build_env.run_unit_checks {
+            self.run_unit_checks()?;
+        }
+
+        if let Some(true) = self.config.build_env.run_debconf_checks {
+            self.run_debconf_checks()?;
+        }
+
+        if self.config.content_policy.is_some() {
+            self.run_content_checks()?;
+        }
+
+        if self.has_multi_arch_checks() {
+            self.run_multi_arch_checks()?;
+        }
+
+        if self.config.abi.is_some() {
+            self.run_abi_checks()?;
+        }
 
         if let Some(true) = self.config.build_env.run_piuparts {
             self.run_piuparts()?;
@@ -484,11 +1886,15 @@ impl BackendBuildEnv for Sbuild {
             self.run_autopkgtests()?;
         }
 
-        Ok(())
+        if let Some(post_process_config) = &self.config.post_process {
+            self.run_post_process(post_process_config, &mut manifest)?;
+        }
+
+        self.write_history_manifest(&manifest)
     }
 
-    fn verify(&self, verify_config: PkgVerifyConfig) -> Result<()> {
-        let output_dir = Path::new(&self.build_files_dir).parent().unwrap();
+    fn verify(&self, verify_config: PkgVerifyConfig, check_changes: bool) -> Result<()> {
+        let output_dir = self.build_files_dir.parent().unwrap();
         let package_hash = verify_config.verify.package_hash;
         let mut errors: Vec<Report> = vec![];
         for output in package_hash.iter() {
@@ -499,11 +1905,8 @@ impl BackendBuildEnv for Sbuild {
                     output.name
                 )));
             }
-            let mut file = fs::File::open(file).map_err(|_| eyre!("Could not open file."))?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)
-                .map_err(|_| eyre!("Could not read file."))?;
-            let actual_sha1 = calculate_sha1(&*buffer.clone()).unwrap_or_default();
+            let reader = open_for_hashing(&file)?;
+            let actual_sha1 = calculate_sha1(reader).unwrap_or_default();
             if actual_sha1 != output.hash {
                 errors.push(eyre!(format!(
                     "file {} actual sha1 is {}",
@@ -511,6 +1914,19 @@ impl BackendBuildEnv for Sbuild {
                 )));
             }
         }
+        if check_changes {
+            let changes_file = self.get_changes_file();
+            info!(
+                "Verifying artifacts against checksums declared in {}",
+                changes_file.display()
+            );
+            let mut changes_errors = verify_changes_checksums(
+                &changes_file,
+                output_dir,
+                &self.config.package_fields.get_debian_version(),
+            )?;
+            errors.append(&mut changes_errors);
+        }
         let result = if errors.is_empty() {
             println!("Verify is successful!");
             Ok(())
@@ -529,7 +1945,7 @@ impl BackendBuildEnv for Sbuild {
 
     fn run_lintian(&self) -> Result<()> {
         info!("Running lintian outside, not as same as on CI..",);
-        check_lintian_version(self.config.build_env.lintian_version.clone())?;
+        check_tool_version(&Lintian(self.config.build_env.lintian_version.clone()))?;
         // let deb_dir = self.get_deb_dir();
         let changes_file = self.get_changes_file();
         let changes_file = changes_file.to_str().unwrap();
@@ -545,13 +1961,9 @@ impl BackendBuildEnv for Sbuild {
             "--suppress-tags".to_string(),   // overrides fails for this message
             "debug-file-with-no-debug-symbols".to_string(),
         ];
-        let codename = normalize_codename(&self.config.build_env.codename)?;
-
-        if codename == "jammy".to_string() || codename == "noble".to_string() {
-            // changed a format of .deb packages on ubuntu, it's not a bug
-            // but some lintian will report as such
+        for tag in self.get_distribution()?.info().lintian_suppress_tags {
             cmd_args.push("--suppress-tags".to_string());
-            cmd_args.push("malformed-deb-archive".to_string());
+            cmd_args.push(tag.to_string());
         }
 
         info!(
@@ -562,22 +1974,24 @@ impl BackendBuildEnv for Sbuild {
         let mut cmd = Command::new("lintian")
             // for CI
             .args(&cmd_args)
-            .stdout(Stdio::inherit())
+            .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .spawn()?;
-        run_process(&mut cmd)
+        let watch = self.watch(&cmd, "lintian");
+        let (output, result) = run_process_capturing(&mut cmd, "lintian", watch.as_ref().map(Watch::heartbeat));
+        let result = stop_watch(watch).and(result);
+        self.update_quality_report(|report| report.lintian = Some(parse_lintian(&output)))?;
+        result
     }
 
     fn run_piuparts(&self) -> Result<()> {
         info!("Running piuparts command with elevated privileges..",);
-        info!(
-            "Piuparts must run as root user through sudo, please provide your password, if prompted."
-        );
-        check_piuparts_version(self.config.build_env.piuparts_version.clone())?;
+        check_tool_version(&Piuparts(self.config.build_env.piuparts_version.clone()))?;
 
-        let repo_url = get_repo_url(&self.config.build_env.codename.as_str())?;
-        let keyring = get_keyring(&self.config.build_env.codename)?;
-        let codename = normalize_codename(&self.config.build_env.codename)?;
+        let repo_url = self.get_repo_url()?;
+        let distribution = self.get_distribution()?.info();
+        let keyring = distribution.keyring;
+        let codename = distribution.suite;
 
         let mut cmd_args = vec![
             "-d".to_string(),
@@ -595,31 +2009,37 @@ impl BackendBuildEnv for Sbuild {
             PackageType::Git(config) => Some(&config.language_env),
             PackageType::Virtual => None,
         };
-        if let Some(env) = lang_env {
-            match env {
-                LanguageEnv::Dotnet(_) => {
-                    if self.config.build_env.codename == "bookworm"
-                        || self.config.build_env.codename == "jammy jellyfish"
-                    {
-                        let ms_repo = format!(
-                            "deb https://packages.microsoft.com/debian/12/prod {} main",
-                            self.config.build_env.codename
-                        );
-                        cmd_args.push(format!("--extra-repo={}", ms_repo));
-                        cmd_args.push("--do-not-verify-signatures".to_string());
-                    } else if self.config.build_env.codename == "noble numbat" {
-                    }
-                }
-                _ => {
-                    // no other package repositories supported
-                    // might supply my own, but not for now
-                }
+        // no other package repositories supported
+        // might supply my own, but not for now
+        if let Some(LanguageEnv::Dotnet(_)) = lang_env {
+            if let Some(dotnet_repository) = &distribution.dotnet_repository {
+                cmd_args.push(format!("--extra-repo={}", dotnet_repository.apt_source));
+                cmd_args.push("--do-not-verify-signatures".to_string());
             }
         }
+        // kept until piuparts finished
+        let mut scripts_dir = match self.get_preseed_file() {
+            Some(preseed_file) => Some(create_piuparts_scripts_dir(&preseed_file)?),
+            None => None,
+        };
+        if let Some(conffiles) = &self.config.conffiles {
+            let scripts_dir = match scripts_dir.as_mut() {
+                Some(scripts_dir) => scripts_dir,
+                None => scripts_dir.insert(tempfile::tempdir()?),
+            };
+            write_piuparts_conffile_check(scripts_dir.path(), conffiles, &self.config.package_fields.package_name)?;
+        }
+        if let Some(scripts_dir) = &scripts_dir {
+            cmd_args.push(format!("--scriptsdir={}", scripts_dir.path().display()));
+        }
         let deb_dir = self.get_deb_dir();
         let deb_name = self.get_deb_name();
+        let mut piuparts = Command::new("piuparts");
+        piuparts.current_dir(deb_dir).args(&cmd_args).arg(&deb_name);
+        let broker = PrivilegeBroker::new(&self.config.build_env);
         info!(
-            "Testing package by invoking: sudo -S piuparts {} {}",
+            "Testing package by invoking: {} piuparts {} {}",
+            broker.get_prefix("piuparts")?.join(" "),
             cmd_args.join(" "),
             deb_name.to_str().unwrap()
         );
@@ -628,59 +2048,66 @@ impl BackendBuildEnv for Sbuild {
             deb_dir.display()
         );
 
-        let mut cmd = Command::new("sudo")
-            .current_dir(deb_dir)
-            // for CI
-            .arg("-S")
-            .arg("piuparts")
-            .args(&cmd_args)
-            .arg(deb_name)
-            .stdout(Stdio::inherit())
+        // stderr stays on the terminal for a password prompt of the privilege tool
+        let mut cmd = broker
+            .wrap("piuparts", &piuparts)?
+            .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .spawn()?;
-        run_process(&mut cmd)
+        let watch = self.watch(&cmd, "piuparts");
+        let (output, result) = run_process_capturing(&mut cmd, "piuparts", watch.as_ref().map(Watch::heartbeat));
+        let result = stop_watch(watch).and(result);
+        self.update_quality_report(|report| report.piuparts = Some(parse_piuparts(&output)))?;
+        result
     }
 
     fn run_autopkgtests(&self) -> Result<()> {
         info!("Running autopkgtests command outside of build env.",);
-        check_autopkgtest_version(self.config.build_env.autopkgtest_version.clone())?;
-        let codename = normalize_codename(&self.config.build_env.codename)?;
-
-        let image_name = format!(
-            "autopkgtest-{}-{}.img",
-            codename, self.config.build_env.arch
-        );
-        let mut cache_dir = self.cache_dir.clone();
-        if cache_dir.starts_with('~') {
-            cache_dir = shellexpand::tilde(&cache_dir).to_string()
-        }
-        let image_path = Path::new(&cache_dir).join(image_name.clone());
-        create_autopkgtest_image(
-            image_path.clone(),
-            self.config.build_env.codename.to_string(),
-            self.config.build_env.arch.to_string(),
-        )?;
+        check_tool_version(&Autopkgtest(self.config.build_env.autopkgtest_version.clone()))?;
+        let distribution = self.get_distribution()?;
+        let image_path = self.get_autopkgtest_image_file(distribution.info().key);
+        let arch = self.config.build_env.arch.get_build_arch();
+        let virt_args = match get_testbed(self.config.build_env.autopkgtest_testbed, arch) {
+            AutopkgtestTestbed::Qemu => {
+                check_qemu_installed(arch)?;
+                let broker = PrivilegeBroker::new(&self.config.build_env);
+                create_autopkgtest_image(image_path.clone(), distribution, arch, &self.get_repo_url()?, &broker)?;
+                get_qemu_virt_args(arch, &image_path)?
+            }
+            AutopkgtestTestbed::Podman => {
+                let image = self.get_container_image()?;
+                pull_container_image(&image, arch)?;
+                get_podman_virt_args(&image)
+            }
+        };
 
         let deb_dir = self.get_deb_dir();
-        //  let deb_name = self.get_deb_name();
-        let changes_file = self.get_changes_file();
-        let mut cmd_args = vec![
-            changes_file.to_str().unwrap().to_string(),
+        // the tests come from the source, binary only builds have none besides the changes file
+        let mut test_source = self.get_dsc_file();
+        if !test_source.exists() {
+            test_source = self.get_changes_file();
+        }
+        let debs = select_test_debs(&self.get_debs()?, self.config.test_deps.as_ref())?;
+        let mut cmd_args = get_autopkgtest_args(&test_source, &debs, self.config.test_deps.as_ref());
+        cmd_args.extend([
             // this will not going rebuild the package, which we want to avoid
             // as some packages can take an hour to build,
             // we don't want to build for 2 hours
             "--no-built-binaries".to_string(),
             // needed dist-upgrade as testbed is outdated, when new version of distribution released
             "--apt-upgrade".to_string(),
-        ];
-        let lang_deps = self.get_test_deps_not_in_debian();
+        ]);
+        let lang_deps = self.get_test_deps_not_in_debian()?;
 
         for action in lang_deps.iter() {
             cmd_args.push(format!("--setup-commands={}", action))
         }
+        if let Some(preseed_file) = self.get_preseed_file() {
+            cmd_args.push(format!("--copy={}:{}", preseed_file.display(), PRESEED_PATH));
+            cmd_args.push(format!("--setup-commands=debconf-set-selections {}", PRESEED_PATH));
+        }
         cmd_args.push("--".to_string());
-        cmd_args.push("qemu".to_string());
-        cmd_args.push(image_path.to_str().unwrap().to_string());
+        cmd_args.extend(virt_args);
         info!(
             "Testing package by invoking: autopkgtest {}",
             cmd_args.join(" ")
@@ -689,135 +2116,141 @@ impl BackendBuildEnv for Sbuild {
             "Note this command run inside of directory: {}",
             deb_dir.display()
         );
-        let mut cmd = Command::new("autopkgtest")
-            .current_dir(deb_dir)
-            .args(&cmd_args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?;
-        run_process(&mut cmd)
-    }
-}
-
-fn check_lintian_version(expected_version: String) -> Result<()> {
-    let output = Command::new("lintian").arg("--version").output()?;
-
-    if output.status.success() {
-        let mut output_str = String::from_utf8_lossy(&output.stdout)
-            .to_string()
-            .replace("Lintian v", "")
-            .replace("\n", "")
-            .trim()
-            .to_string();
-        if let Some(pos) = output_str.find("ubuntu") {
-            output_str.truncate(pos);
-            output_str = output_str.trim().to_string();
-        }
-        warn_compare_versions(expected_version, &output_str, "lintian")?;
-        Ok(())
-    } else {
-        Err(eyre!("Failed to execute lintian --version"))
+        let test_retry = self.config.test_retry.clone().unwrap_or_default();
+        let retries = match self.config.test_retry {
+            Some(_) => test_retry.retries.unwrap_or(DEFAULT_TEST_RETRIES),
+            None => 0,
+        };
+        self.update_quality_report(|report| {
+            if let Some(attempts) = report.retries.as_mut() {
+                attempts.retain(|attempt| attempt.stage != "autopkgtest");
+            }
+        })?;
+        let mut attempt = 1;
+        loop {
+            let mut cmd = Command::new("autopkgtest")
+                .current_dir(deb_dir)
+                .args(&cmd_args)
+                .stdout(Stdio::piped())
+                // the progress of the testbed and the tests is written to stderr
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let watch = self.watch(&cmd, "autopkgtest");
+            let output = run_process_with_exit_code(&mut cmd, "autopkgtest", watch.as_ref().map(Watch::heartbeat));
+            stop_watch(watch)?;
+            let (output, exit_code) = output?;
+            let results = parse_autopkgtest(&output);
+            if attempt <= retries && is_testbed_failure(exit_code, &results) {
+                let delay = get_retry_delay(
+                    test_retry.backoff_secs.unwrap_or(DEFAULT_TEST_RETRY_BACKOFF_SECS),
+                    attempt,
+                );
+                warn!(
+                    "autopkgtest attempt {} failed on the testbed, retrying in {}s",
+                    attempt,
+                    delay.as_secs()
+                );
+                self.update_quality_report(|report| {
+                    report.retries.get_or_insert_with(Vec::new).push(RetryAttempt {
+                        stage: "autopkgtest".to_string(),
+                        attempt,
+                        reason: format!("testbed failure, exit code {}", AUTOPKGTEST_TESTBED_FAILURE),
+                    })
+                })?;
+                std::thread::sleep(delay);
+                attempt += 1;
+                continue;
+            }
+            let has_apparmor = self.config.apparmor.is_some();
+            self.update_quality_report(|report| {
+                if has_apparmor {
+                    report.apparmor = Some(get_apparmor_results(&results));
+                }
+                report.autopkgtest = Some(results);
+            })?;
+            return match exit_code {
+                Some(0) => Ok(()),
+                Some(code) => Err(eyre!("autopkgtest exited with {}, see the test output above", code)),
+                None => Err(eyre!("autopkgtest was killed by a signal")),
+            };
+        }
     }
-}
 
-fn check_piuparts_version(expected_version: String) -> Result<()> {
-    let output = Command::new("piuparts").arg("--version").output()?;
-
-    if output.status.success() {
-        let output_str = String::from_utf8_lossy(&output.stdout)
-            .to_string()
-            .replace("piuparts ", "")
-            .replace("\n", "")
-            .trim()
-            .to_string();
-        warn_compare_versions(expected_version, &output_str, "piuparts")?;
-        Ok(())
-    } else {
-        Err(eyre!("Failed to execute piuparts --version"))
+    fn run_smoke_tests(&self) -> Result<()> {
+        let smoke_test = self
+            .config
+            .smoke_test
+            .as_ref()
+            .ok_or_else(|| eyre!("No [smoke_test] configured for the package."))?;
+        let image = self.get_container_image()?;
+        let debs = select_test_debs(&self.get_debs()?, self.config.test_deps.as_ref())?;
+        run_smoke_tests(
+            &image,
+            &debs,
+            &smoke_test.tests,
+            self.get_preseed_file().as_deref(),
+            get_apt_recommends_flag(self.config.test_deps.as_ref()),
+        )
     }
-}
-
-fn check_autopkgtest_version(expected_version: String) -> Result<()> {
-    let output = Command::new("apt")
-        .arg("list")
-        .arg("--installed")
-        .arg("autopkgtest")
-        .output()?;
 
-    //autopkgtest/jammy-updates,now 5.32ubuntu3~22.04.1 all [installed]
-    if output.status.success() {
-        let mut output_str = String::from_utf8_lossy(&output.stdout)
-            .to_string()
-            .replace("Listing...", "")
-            .replace("\n", "")
-            .replace("autopkgtest/stable,now ", "")
-            .replace("autopkgtest/jammy-updates,now ", "")
-            .replace("autopkgtest/jammy,now ", "")
-            .replace("autopkgtest/noble,now ", "")
-            .replace("ubuntu3~22.04.1", "")
-            .replace("ubuntu2", "")
-            .trim()
-            .to_string();
-        if let Some(pos) = output_str.find("all ") {
-            output_str.truncate(pos);
-            output_str = output_str.trim().to_string();
-        }
-        info!("autopkgtest version {}", output_str);
-        // append versions, to it looks like semver
-        let expected_version = format!("{}.0", expected_version);
-        let actual_version = format!("{}.0", output_str);
-        warn_compare_versions(expected_version, &actual_version, "autopkgtest")?;
-        Ok(())
-    } else {
-        Err(eyre!("Failed to execute apt list --installed autopkgtest"))
+    fn run_unit_checks(&self) -> Result<()> {
+        let debs = self.get_debs()?;
+        let root = tempfile::tempdir()?;
+        extract_debs(&debs, root.path())?;
+        let findings = check_units(root.path())?;
+        self.report_findings(
+            findings,
+            |report, findings| report.systemd_units = Some(findings),
+            |errors| format!("{} errors in the shipped systemd units", errors),
+        )
     }
-}
 
-pub fn warn_compare_versions(
-    expected_version: String,
-    actual_version: &str,
-    program_name: &str,
-) -> Result<()> {
-    let expected_version = Version::parse(&expected_version).unwrap();
-    let actual_version = Version::parse(actual_version).unwrap();
-    match expected_version.cmp(&actual_version) {
-        std::cmp::Ordering::Less => {
-            warn!("Warning: using newer versions than expected version.");
-            Ok(())
-        }
-        std::cmp::Ordering::Greater => {
-            warn!("Using older version of {}", program_name);
-            Ok(())
-        }
-        std::cmp::Ordering::Equal => {
-            info!("Versions match. Proceeding.");
-            Ok(())
-        }
+    fn run_debconf_checks(&self) -> Result<()> {
+        let debs = self.get_debs()?;
+        let findings = check_debs(&debs, self.get_preseed_file().as_deref())?;
+        self.report_findings(
+            findings,
+            |report, findings| report.maintainer_scripts = Some(findings),
+            |errors| {
+                format!(
+                    "{} maintainer script errors, the package does not install with DEBIAN_FRONTEND=noninteractive",
+                    errors
+                )
+            },
+        )
     }
-}
 
-pub fn normalize_codename(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => Ok("bookworm"),
-        "noble numbat" => Ok("noble"),
-        "jammy jellyfish" => Ok("jammy"),
-        _ => Err(eyre!("Not supported distribution")),
+    fn run_content_checks(&self) -> Result<()> {
+        let config = self
+            .config
+            .content_policy
+            .as_ref()
+            .ok_or_else(|| eyre!("No [content_policy] configured for the package."))?;
+        let debs = self.get_debs()?;
+        let findings = content_policy::check_debs(&debs, config)?;
+        self.report_findings(
+            findings,
+            |report, findings| report.content_policy = Some(findings),
+            |errors| format!("{} content policy errors in the built debs", errors),
+        )
     }
 }
 
-pub fn get_keyring(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => Ok("/usr/share/keyrings/debian-archive-keyring.gpg"),
-        "noble numbat" | "jammy jellyfish" => Ok("/usr/share/keyrings/ubuntu-archive-keyring.gpg"),
-        _ => Err(eyre!("Not supported distribution")),
-    }
+/// Number of the binNMU, suffix has the form +bN.
+pub fn parse_binnmu_suffix(suffix: &str) -> Result<u32> {
+    suffix
+        .strip_prefix("+b")
+        .and_then(|number| number.parse::<u32>().ok())
+        .filter(|number| *number > 0)
+        .ok_or_else(|| eyre!("Invalid binNMU suffix {}, expected +b1, +b2, ...", suffix))
 }
 
-pub fn get_repo_url(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => Ok("http://deb.debian.org/debian"),
-        "noble numbat" | "jammy jellyfish" => Ok("http://archive.ubuntu.com/ubuntu"),
-        _ => Err(eyre!("Not supported distribution")),
+pub fn resolve_jobs(jobs: Option<usize>) -> usize {
+    match jobs {
+        Some(jobs) if jobs > 0 => jobs,
+        _ => std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1),
     }
 }
 
@@ -833,64 +2266,158 @@ pub fn calculate_sha1<R: Read>(mut reader: R) -> Result<String, io::Error> {
     Ok(hex_digest)
 }
 
-fn create_autopkgtest_image(image_path: PathBuf, codename: String, arch: String) -> Result<()> {
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChangesChecksum {
+    pub hash: String,
+    pub size: u64,
+    pub name: String,
+}
+
+// Checksums-Sha256:
+//  <sha256> <size> <file name>
+pub fn parse_changes_checksums(changes_content: &str) -> Result<Vec<ChangesChecksum>> {
+    let mut checksums = vec![];
+    let mut in_section = false;
+    for line in changes_content.lines() {
+        if line.starts_with("Checksums-Sha256:") {
+            in_section = true;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        // continuation lines of a field start with whitespace
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            break;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(eyre!("Malformed Checksums-Sha256 line: {}", line.trim()));
+        }
+        let size = parts[1]
+            .parse::<u64>()
+            .map_err(|_| eyre!("Invalid file size in Checksums-Sha256 line: {}", line.trim()))?;
+        checksums.push(ChangesChecksum {
+            hash: parts[0].to_string(),
+            size,
+            name: parts[2].to_string(),
+        });
+    }
+    if checksums.is_empty() {
+        return Err(eyre!("No Checksums-Sha256 entries found in changes file"));
+    }
+    Ok(checksums)
+}
+
+pub fn parse_changes_version(changes_content: &str) -> Option<String> {
+    changes_content
+        .lines()
+        .find_map(|line| line.strip_prefix("Version:"))
+        .map(|version| version.trim().to_string())
+}
+
+// file names in the changes file never contain the epoch, only the Version field does
+pub fn verify_changes_checksums(
+    changes_file: &Path,
+    output_dir: &Path,
+    debian_version: &str,
+) -> Result<Vec<Report>> {
+    let changes_content = fs::read_to_string(changes_file).map_err(|_| {
+        eyre!(
+            "Could not read changes file {}, was the package built?",
+            changes_file.display()
+        )
+    })?;
+    let checksums = parse_changes_checksums(&changes_content)?;
+    let mut errors: Vec<Report> = vec![];
+    match parse_changes_version(&changes_content) {
+        Some(version) if version == debian_version => {}
+        Some(version) => errors.push(eyre!(
+            "changes file declares version {}, expected {}",
+            version,
+            debian_version
+        )),
+        None => errors.push(eyre!("changes file does not declare a Version")),
+    }
+    for checksum in checksums.iter() {
+        let file_path = output_dir.join(&checksum.name);
+        if !file_path.exists() {
+            errors.push(eyre!(
+                "file {} declared in changes file does not exist",
+                checksum.name
+            ));
+            continue;
+        }
+        let actual_size = fs::metadata(&file_path)?.len();
+        if actual_size != checksum.size {
+            errors.push(eyre!(
+                "file {} size is {}, changes file declares {}",
+                checksum.name,
+                actual_size,
+                checksum.size
+            ));
+            continue;
+        }
+        let reader = open_for_hashing(&file_path)?;
+        let actual_sha256 = calculate_sha256(reader)?;
+        if actual_sha256 != checksum.hash {
+            errors.push(eyre!(
+                "file {} actual sha256 is {}, changes file declares {}",
+                checksum.name,
+                actual_sha256,
+                checksum.hash
+            ));
+        }
+    }
+    Ok(errors)
+}
+
+fn create_autopkgtest_image(
+    image_path: PathBuf,
+    distribution: &dyn Distribution,
+    arch: &str,
+    repo_url: &str,
+    broker: &PrivilegeBroker,
+) -> Result<()> {
     // do not recreate image if exists
     if image_path.exists() {
         return Ok(());
     }
-    info!("autopkgtests environment does not exist. Creating it.");
-    info!("please provide your password through sudo to as autopkgtest env creation requires it.");
-    create_dir_all(image_path.parent().unwrap())?;
-    let repo_url = get_repo_url(&codename)?;
-
-    match codename.as_str() {
-        "bookworm" => {
-            let codename = normalize_codename(&codename)?;
-            let cmd_args = vec![
-                codename.to_string(),
-                image_path.to_str().unwrap().to_string(),
-                format!("--mirror={}", repo_url),
-                format!("--arch={}", arch),
-            ];
-            let mut cmd = Command::new("sudo")
-                // for CI
-                .arg("-S")
-                .arg("autopkgtest-build-qemu")
-                .args(&cmd_args)
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()?;
-            run_process(&mut cmd)
-        }
-        "noble numbat" | "jammy jellyfish" => {
-            let codename = normalize_codename(&codename)?;
-            let cmd_args = vec![
-                format!("--release={}", codename.to_string()),
-                format!("--mirror={}", repo_url),
-                format!("--arch={}", arch),
-                "-v".to_string(),
-            ];
-            let mut cmd = Command::new("sudo")
-                // for CI
-                .arg("-S")
-                .arg("autopkgtest-buildvm-ubuntu-cloud")
-                .args(&cmd_args)
-                .current_dir(image_path.parent().unwrap().to_str().unwrap())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn()?;
-            run_process(&mut cmd)
-        }
-        _ => Err(eyre!("Not supported distribution")),
+    if !distribution.info().autopkgtest_image {
+        return Err(eyre!(
+            "No autopkgtest image can be built for {}, there are no cloud images of it. Disable run_autopkgtest.",
+            distribution.info().codename
+        ));
+    }
+    // parallel builds of the same suite and arch share the image
+    create_exclusively(&image_path, |tmp_image_path| {
+        info!("autopkgtests environment does not exist. Creating it.");
+        let cmd = distribution.autopkgtest_image_command(tmp_image_path, arch, repo_url);
+        let mut cmd = broker
+            .wrap("autopkgtest image creation", &cmd)?
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        run_process(&mut cmd, None)
+    })
+}
+
+fn stop_watch(watch: Option<Watch>) -> Result<()> {
+    match watch {
+        Some(watch) => watch.stop(),
+        None => Ok(()),
     }
 }
 
-fn run_process(child: &mut Child) -> Result<()> {
+fn run_process(child: &mut Child, heartbeat: Option<&Heartbeat>) -> Result<()> {
     if let Some(stdout) = child.stdout.take() {
         let reader = BufReader::new(stdout);
 
         for line in reader.lines() {
             let line = line?;
+            if let Some(heartbeat) = heartbeat {
+                heartbeat.beat(&line);
+            }
             info!("{}", line);
         }
     }
@@ -904,6 +2431,89 @@ fn run_process(child: &mut Child) -> Result<()> {
     }
 }
 
+// like run_process, the output of quiet provisioning steps is folded into a line per step
+fn run_process_quiet_provision(child: &mut Child, heartbeat: Option<&Heartbeat>) -> Result<()> {
+    if let Some(stdout) = child.stdout.take() {
+        let mut provision_log = ProvisionLog::new();
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if let Some(heartbeat) = heartbeat {
+                heartbeat.beat(&line);
+            }
+            for line in provision_log.filter_line(&line) {
+                info!("{}", line);
+            }
+        }
+    }
+    run_process(child, None)
+}
+
+// logs every line as "<stage>: <line>" as soon as it is written, returns all of them
+fn log_lines(reader: impl Read, stage: &str, heartbeat: Option<Heartbeat>) -> String {
+    let mut output = String::new();
+    for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+        if let Some(heartbeat) = &heartbeat {
+            heartbeat.beat(&line);
+        }
+        info!("{}: {}", stage, line);
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// Streams stdout and, when piped, stderr of the test stage through the log while it runs,
+/// returns stdout followed by stderr for parsing.
+pub fn stream_output(child: &mut Child, stage: &str, heartbeat: Option<&Heartbeat>) -> String {
+    let stderr = child.stderr.take().map(|stderr| {
+        let stage = stage.to_string();
+        let heartbeat = heartbeat.cloned();
+        std::thread::spawn(move || log_lines(stderr, &stage, heartbeat))
+    });
+    let mut output = child
+        .stdout
+        .take()
+        .map(|stdout| log_lines(stdout, stage, heartbeat.cloned()))
+        .unwrap_or_default();
+    if let Some(stderr) = stderr {
+        output.push_str(&stderr.join().unwrap_or_default());
+    }
+    output
+}
+
+// like run_process_capturing, the exit code tells apart testbed and test failures
+fn run_process_with_exit_code(
+    child: &mut Child,
+    stage: &str,
+    heartbeat: Option<&Heartbeat>,
+) -> Result<(String, Option<i32>)> {
+    let output = stream_output(child, stage, heartbeat);
+    let status = child.wait().map_err(|err| eyre!(err.to_string()))?;
+    Ok((output, status.code()))
+}
+
+/// Failures of the testbed rather than the package, a run with a failed test is never retried.
+pub fn is_testbed_failure(exit_code: Option<i32>, results: &[AutopkgtestResult]) -> bool {
+    exit_code == Some(AUTOPKGTEST_TESTBED_FAILURE) && !results.iter().any(|result| result.result == "FAIL")
+}
+
+/// Wait before the retry, the backoff doubles with every further retry.
+pub fn get_retry_delay(backoff_secs: u64, retry: u32) -> Duration {
+    Duration::from_secs(backoff_secs.saturating_mul(2u64.saturating_pow(retry - 1)))
+}
+
+// like run_process, but also returns the output, so it can be parsed after a failure
+fn run_process_capturing(child: &mut Child, stage: &str, heartbeat: Option<&Heartbeat>) -> (String, Result<()>) {
+    let output = stream_output(child, stage, heartbeat);
+    (output, run_process(child, None))
+}
+
+// layers of earlier versions have no recorded sha256, they are recreated as well
+fn is_toolchain_layer_intact(layer: &Path) -> Result<bool> {
+    let stamp = ChrootStamp::read(layer)?;
+    Ok(!stamp.sha256.is_empty() && stamp.sha256 == calculate_sha256(open_for_hashing(layer)?)?)
+}
+
 fn remove_file_or_directory(path: &str, is_directory: bool) -> io::Result<()> {
     if is_directory {
         fs::remove_dir_all(path)?;
@@ -913,6 +2523,43 @@ fn remove_file_or_directory(path: &str, is_directory: bool) -> io::Result<()> {
     Ok(())
 }
 
+// renders the declarative steps of the custom language env
+fn get_custom_install(config: &CustomConfig) -> ChrootProvisioner {
+    let mut install = ChrootProvisioner::new();
+    for (index, step) in config.steps.iter().enumerate() {
+        let file_name = format!(
+            "custom-{}-{}",
+            index,
+            step.url.rsplit('/').next().unwrap_or_default()
+        );
+        install.stage(&step.url, &step.sha256, &format!("/tmp/{}", file_name));
+        let Some(unpack_dir) = &step.unpack_dir else {
+            continue;
+        };
+        install.run(
+            &format!("custom-{}-unpack", index),
+            format!(
+                "mkdir -p {dir} && tar xf /tmp/{file} -C {dir} --strip-components={strip}",
+                dir = unpack_dir,
+                file = file_name,
+                strip = step.strip_components.unwrap_or(0)
+            ),
+        );
+        for (link_index, (link, target)) in step.symlinks.iter().enumerate() {
+            install.run(
+                &format!("custom-{}-link-{}", index, link_index),
+                format!(
+                    "mkdir -p $(dirname {link}) && ln -sf {}/{} {link}",
+                    unpack_dir.trim_end_matches('/'),
+                    target,
+                    link = link
+                ),
+            );
+        }
+    }
+    install
+}
+
 fn transform_name(input: &str, arch: &str) -> String {
     if let Some(pos) = input.find(format!("_{}", arch).as_str()) {
         let trimmed = &input[..pos];
@@ -925,6 +2572,12 @@ fn transform_name(input: &str, arch: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::v1::build::provision::ProvisionStep;
+    use crate::v1::build::trust::tests::{export_key, generate_key};
+    use crate::v1::pkg_config::{
+        BuildHooks, ControlOverrides, CustomInstallStep, DefaultPackageTypeConfig, DotnetConfig, RedactConfig,
+        GoConfig, JavascriptConfig, ProxyConfig, RustConfig,
+    };
     use env_logger::Env;
     use std::fs::File;
     use std::sync::Once;
@@ -943,9 +2596,9 @@ mod tests {
     fn test_clean_sbuild_env_when_file_does_not_exist() {
         setup();
         let mut pkg_config = PkgConfig::default();
-        let build_files_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
+        let build_files_dir = tempdir().unwrap().path().to_path_buf();
         pkg_config.build_env.codename = "bookworm".to_string();
-        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.arch = Arch::Amd64;
         let sbuild_cache_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
         pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir);
         let build_env = Sbuild::new(pkg_config, build_files_dir);
@@ -960,9 +2613,9 @@ mod tests {
     fn test_clean_sbuild_env() {
         setup();
         let mut pkg_config = PkgConfig::default();
-        let build_files_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
+        let build_files_dir = tempdir().unwrap().path().to_path_buf();
         pkg_config.build_env.codename = "bookworm".to_string();
-        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.arch = Arch::Amd64;
         let sbuild_cache = tempdir().unwrap();
         // create dir manually, as it doesn't exist
         create_dir_all(sbuild_cache.path())
@@ -987,16 +2640,18 @@ mod tests {
         assert!(!cache_file_path.exists())
     }
 
+    // bootstraps a chroot from the debian mirror, run with --ignored on a host with sbuild
     #[test]
+    #[ignore]
     fn test_create_sbuild_env() {
         setup();
         let mut pkg_config = PkgConfig::default();
         pkg_config.build_env.codename = "bookworm".to_string();
-        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.arch = Arch::Amd64;
         let sbuild_cache_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
         pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir);
 
-        let build_files_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
+        let build_files_dir = tempdir().unwrap().path().to_path_buf();
         let build_env = Sbuild::new(pkg_config, build_files_dir);
         build_env.clean().expect("Could not clean previous env.");
         let cache_file = build_env.get_cache_file();
@@ -1006,4 +2661,727 @@ mod tests {
         assert!(result.is_ok());
         assert!(cache_file_path.exists())
     }
+
+    fn dotnet_config(codename: &str, use_backup_version: bool) -> PkgConfig {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = codename.to_string();
+        pkg_config.build_env.arch = Arch::Amd64;
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            tarball_url: "hello-world-dotnet-1.0.0.tar.gz".to_string(),
+            tarball_hash: None,
+            tarball_auth: None,
+            github_release: None,
+            language_env: LanguageEnv::Dotnet(DotnetConfig {
+                use_backup_version,
+                fallback_to_backup: None,
+                dotnet_packages: vec![DotnetPackage {
+                    name: "dotnet-sdk-8.0_8.0.204-1_amd64".to_string(),
+                    hash: "a94237cb852aae05b67a5c8428a6c4f9cfb4beaa".to_string(),
+                    url: "https://example.com/dotnet-sdk-8.0_8.0.204-1_amd64.deb".to_string(),
+                }],
+            }),
+        });
+        pkg_config
+    }
+
+    #[test]
+    fn test_custom_install_renders_steps() {
+        let config = CustomConfig {
+            toolchain_name: "ghc".to_string(),
+            toolchain_version: "9.4.8".to_string(),
+            steps: vec![CustomInstallStep {
+                url: "https://downloads.haskell.org/ghc/9.4.8/ghc-9.4.8-x86_64-deb10-linux.tar.xz".to_string(),
+                sha256: "a".repeat(64),
+                unpack_dir: Some("/opt/lib/ghc".to_string()),
+                strip_components: Some(1),
+                symlinks: BTreeMap::from([("/usr/bin/ghc".to_string(), "bin/ghc".to_string())]),
+            }],
+        };
+        let file_name = "custom-0-ghc-9.4.8-x86_64-deb10-linux.tar.xz";
+        let run = |name: &str, command: String| ProvisionStep::Run {
+            name: name.to_string(),
+            command,
+        };
+        assert_eq!(
+            get_custom_install(&config).steps,
+            vec![
+                ProvisionStep::Stage {
+                    url: config.steps[0].url.clone(),
+                    hash: "a".repeat(64),
+                    path: format!("/tmp/{}", file_name),
+                },
+                run(
+                    "custom-0-unpack",
+                    format!("mkdir -p /opt/lib/ghc && tar xf /tmp/{} -C /opt/lib/ghc --strip-components=1", file_name)
+                ),
+                run(
+                    "custom-0-link-0",
+                    "mkdir -p $(dirname /usr/bin/ghc) && ln -sf /opt/lib/ghc/bin/ghc /usr/bin/ghc".to_string()
+                ),
+            ]
+        );
+    }
+
+    // commands of the steps run in the chroot, without the staged files
+    fn get_run_commands(build_env: &Sbuild) -> Vec<String> {
+        build_env
+            .get_provisioner()
+            .unwrap()
+            .steps
+            .into_iter()
+            .filter_map(|step| match step {
+                ProvisionStep::Run { command, .. } => Some(command),
+                ProvisionStep::Stage { .. } | ProvisionStep::Copy { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dotnet_install_without_backup_uses_primary_only() {
+        let build_env = Sbuild::new(dotnet_config("noble numbat", false), PathBuf::from("/tmp"));
+        let install = get_run_commands(&build_env);
+        assert_eq!(install.len(), 1);
+        assert!(install[0].contains("apt-cache madison dotnet-sdk-8.0 | grep -q -F \"8.0.204-1\""));
+        assert!(!install[0].contains("example.com"));
+        assert!(!install[0].contains("wget"));
+        assert!(install[0].contains(&build_log_marker_command("dotnet-source", "primary")));
+
+        // without a declared key the microsoft repository is only added with the pinned one,
+        // its keyring is copied from the workdir, nothing is fetched for the provisioner
+        let cache_dir = tempdir().unwrap();
+        let mut pkg_config = dotnet_config("bookworm", false);
+        pkg_config.build_env.sbuild_cache_dir = Some(cache_dir.path().to_str().unwrap().to_string());
+        let build_env = Sbuild::new(pkg_config, cache_dir.path().join("workdir/hello-world-1.0.0-1/hello-world-1.0.0"));
+        let keyring_file = cache_dir.path().join("workdir/.keyrings/microsoft.asc");
+        let provisioner = build_env.get_provisioner().unwrap();
+        assert_eq!(
+            provisioner.steps[0],
+            ProvisionStep::Copy {
+                file: keyring_file.clone(),
+                path: MICROSOFT_KEYRING.to_string(),
+            }
+        );
+        assert!(get_run_commands(&build_env)[0].contains("is trusted with BC528686B50D79E339D3721CEB3E94ADBE1229CF"));
+        assert_eq!(build_env.get_missing_default_keys(), ["https://packages.microsoft.com/keys/microsoft.asc"]);
+        let keys_dir = build_env.get_download_cache_dir().join("keys");
+        fs::create_dir_all(&keys_dir).unwrap();
+        let home = tempdir().unwrap();
+        let other = generate_key(home.path(), "other@example.com");
+        fs::write(keys_dir.join("microsoft.asc"), export_key(home.path(), &other)).unwrap();
+        assert!(build_env.get_missing_default_keys().is_empty());
+        let err = build_env.prepare_keyrings().unwrap_err();
+        assert!(err.to_string().contains(&format!("contains key {}, which is not declared for microsoft", other)));
+        assert!(!keyring_file.exists());
+    }
+
+    #[test]
+    fn test_dotnet_install_with_backup_stages_debs() {
+        let build_env = Sbuild::new(dotnet_config("bookworm", true), PathBuf::from("/tmp"));
+        let provisioner = build_env.get_provisioner().unwrap();
+        assert_eq!(
+            provisioner.steps[0],
+            ProvisionStep::Stage {
+                url: "https://example.com/dotnet-sdk-8.0_8.0.204-1_amd64.deb".to_string(),
+                hash: "a94237cb852aae05b67a5c8428a6c4f9cfb4beaa".to_string(),
+                path: "/tmp/dotnet/dotnet-sdk-8.0_8.0.204-1_amd64.deb".to_string(),
+            }
+        );
+        assert_eq!(
+            get_run_commands(&build_env),
+            vec![format!(
+                "apt install -y /tmp/dotnet/dotnet-sdk-8.0_8.0.204-1_amd64.deb && {} && dotnet --version",
+                build_log_marker_command("dotnet-source", "backup")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_dotnet_install_falls_back_to_backup() {
+        let mut pkg_config = dotnet_config("noble numbat", false);
+        if let PackageType::Default(package_type) = &mut pkg_config.package_type {
+            if let LanguageEnv::Dotnet(config) = &mut package_type.language_env {
+                config.fallback_to_backup = Some(true);
+            }
+        }
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        let provisioner = build_env.get_provisioner().unwrap();
+        assert!(matches!(&provisioner.steps[0], ProvisionStep::Stage { path, .. } if path.starts_with("/tmp/dotnet/")));
+        let install = get_run_commands(&build_env);
+        assert_eq!(install.len(), 1);
+        let (primary, backup) = install[0].split_once(" || ").unwrap();
+        assert!(primary.contains("apt-cache madison dotnet-sdk-8.0"));
+        assert!(primary.contains(&build_log_marker_command("dotnet-source", "primary")));
+        assert!(backup.contains("apt install -y /tmp/dotnet/dotnet-sdk-8.0_8.0.204-1_amd64.deb"));
+        assert!(backup.contains(&build_log_marker_command("dotnet-source", "backup")));
+        assert!(install[0].ends_with(" && dotnet --version"));
+    }
+
+    #[test]
+    fn test_binnmu_rebuilds_built_source() {
+        assert_eq!(parse_binnmu_suffix("+b2").unwrap(), 2);
+        assert!(parse_binnmu_suffix("b1").is_err());
+        assert!(parse_binnmu_suffix("+b0").is_err());
+
+        let dir = tempdir().unwrap();
+        let mut pkg_config = dotnet_config("bookworm", true);
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        let build_env = Sbuild::new(pkg_config, dir.path().join("hello-world-1.0.0"));
+        let error = build_env.get_binnmu_args("+b1", "Rebuild").unwrap_err().to_string();
+        assert!(error.contains("run `pkg-builder package` first"), "{}", error);
+
+        fs::write(dir.path().join("hello-world_1.0.0-1.dsc"), "").unwrap();
+        let cmd_args = build_env
+            .get_binnmu_args("+b1", "Rebuild against openssl 3.2")
+            .unwrap();
+        assert!(cmd_args.contains(&"--binNMU=1".to_string()));
+        assert!(cmd_args.contains(&"--make-binNMU=Rebuild against openssl 3.2".to_string()));
+        assert!(!cmd_args.contains(&"-s".to_string()));
+        assert!(cmd_args.last().unwrap().ends_with("hello-world_1.0.0-1.dsc"));
+    }
+
+    #[test]
+    fn test_resolve_jobs() {
+        assert_eq!(resolve_jobs(Some(3)), 3);
+        assert!(resolve_jobs(Some(0)) >= 1);
+        assert_eq!(resolve_jobs(None), resolve_jobs(Some(0)));
+    }
+
+    #[test]
+    fn test_distro_toolchain_skips_binary_download() {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig {
+                rust_version: "1.63".to_string(),
+                use_distro_toolchain: Some(true),
+                ..RustConfig::default()
+            }),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        assert_eq!(
+            get_run_commands(&build_env),
+            vec![
+                "apt install -y rustc cargo".to_string(),
+                "rustc --version".to_string(),
+                "cargo --version".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_signature_is_verified_without_trust() {
+        let cache_dir = tempdir().unwrap();
+        let rust_tarball = b"rust".as_slice();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = Arch::Amd64;
+        pkg_config.build_env.sbuild_cache_dir = Some(cache_dir.path().to_str().unwrap().to_string());
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig {
+                rust_version: "1.77.2".to_string(),
+                rust_binary_url: "https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz"
+                    .to_string(),
+                rust_binary_checksum: calculate_sha256(rust_tarball).unwrap(),
+                rust_binary_gpg_asc: "signature".to_string(),
+                ..RustConfig::default()
+            }),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        let download_cache_dir = build_env.get_download_cache_dir();
+        fs::create_dir_all(download_cache_dir.join("keys")).unwrap();
+        fs::write(download_cache_dir.join(calculate_sha256(rust_tarball).unwrap()), rust_tarball).unwrap();
+        // the cached default key is another key than the pinned rust key
+        let home = tempdir().unwrap();
+        let other = generate_key(home.path(), "other@example.com");
+        fs::write(download_cache_dir.join("keys/rust.asc"), export_key(home.path(), &other)).unwrap();
+        let error = build_env.verify_toolchain_signature().unwrap_err().to_string();
+        assert!(error.contains(&format!("contains key {}, which is not declared for rust", other)), "{}", error);
+    }
+
+    #[test]
+    fn test_toolchain_layer_follows_toolchain_pins() {
+        let cache_dir = tempdir().unwrap();
+        let javascript = |yarn_version: &str| {
+            let mut pkg_config = PkgConfig::default();
+            pkg_config.build_env.codename = "bookworm".to_string();
+            pkg_config.build_env.arch = Arch::Amd64;
+            pkg_config.build_env.sbuild_cache_dir = Some(cache_dir.path().to_str().unwrap().to_string());
+            pkg_config.build_env.toolchain_cache = Some(true);
+            pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+                language_env: LanguageEnv::JavaScript(JavascriptConfig {
+                    node_version: "20.11.0".to_string(),
+                    node_binary_url: "https://nodejs.org/dist/v20.11.0/node-v20.11.0-linux-x64.tar.gz".to_string(),
+                    node_binary_checksum: "abc123".to_string(),
+                    yarn_version: Some(yarn_version.to_string()),
+                    ..JavascriptConfig::default()
+                }),
+                ..DefaultPackageTypeConfig::default()
+            });
+            Sbuild::new(pkg_config, PathBuf::from("/tmp"))
+        };
+        let build_env = javascript("1.22.19");
+        let layer_file = build_env.get_toolchain_layer_file().unwrap().unwrap();
+        let other_layer_file = javascript("1.22.22").get_toolchain_layer_file().unwrap().unwrap();
+        // only the layer changes, the base chroot is shared
+        assert_ne!(layer_file, other_layer_file);
+        assert_eq!(build_env.get_cache_file(), javascript("1.22.22").get_cache_file());
+        assert_eq!(layer_file, javascript("1.22.19").get_toolchain_layer_file().unwrap().unwrap());
+        let base = build_env.get_cache_file().trim_end_matches(".tar.gz").to_string();
+        assert!(layer_file.starts_with(&format!("{}-javascript-", base)));
+
+        let cmd_args = build_env.get_sbuild_args().unwrap();
+        assert!(cmd_args.contains(&layer_file));
+        assert!(!cmd_args.iter().any(|arg| arg.contains("yarn@")));
+
+        let layer_path = Path::new(&layer_file);
+        fs::write(layer_path, "layer").unwrap();
+        assert!(!is_toolchain_layer_intact(layer_path).unwrap());
+        ChrootStamp::new(layer_path, None).unwrap().write(layer_path).unwrap();
+        assert!(is_toolchain_layer_intact(layer_path).unwrap());
+        fs::write(layer_path, "tampered").unwrap();
+        assert!(!is_toolchain_layer_intact(layer_path).unwrap());
+
+        File::create(&other_layer_file).unwrap();
+        build_env.clean().unwrap();
+        assert!(!Path::new(&layer_file).exists());
+        assert!(!get_stamp_file(layer_path).exists());
+        assert!(!Path::new(&other_layer_file).exists());
+
+        // nothing to install, nothing to cache
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.toolchain_cache = Some(true);
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        assert_eq!(build_env.get_toolchain_layer_file().unwrap(), None);
+    }
+
+    #[test]
+    fn test_build_environment_exposes_jobs_to_language() {
+        let mut pkg_config = dotnet_config("bookworm", true);
+        pkg_config.build_env.jobs = Some(4);
+        let build_env = Sbuild::new(pkg_config.clone(), PathBuf::from("/tmp"));
+        assert!(build_env.get_build_environment().is_empty());
+
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig::default()),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        let build_environment = build_env.get_build_environment();
+        assert_eq!(build_environment.get("CARGO_BUILD_JOBS").unwrap(), "4");
+        assert!(!build_environment.contains_key("RUSTFLAGS"));
+    }
+
+    #[test]
+    fn test_proxies() {
+        let mut pkg_config = dotnet_config("bookworm", true);
+        let build_env = Sbuild::new(pkg_config.clone(), PathBuf::from("/tmp"));
+        assert!(build_env.get_proxy_setup_commands().is_empty());
+
+        pkg_config.build_env.proxies = Some(ProxyConfig {
+            apt: Some("http://apt-cacher:3142".to_string()),
+            go: Some("http://athens:3000".to_string()),
+            npm: None,
+            cargo: Some("sparse+http://crates-mirror/index/".to_string()),
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        let commands = build_env.get_proxy_setup_commands();
+        assert_eq!(
+            commands[0],
+            "echo 'Acquire::http::Proxy \"http://apt-cacher:3142\";' > /etc/apt/apt.conf.d/01pkg-builder-proxy"
+        );
+        assert!(commands[1].contains("registry = \"sparse+http://crates-mirror/index/\""));
+        let build_environment = build_env.get_build_environment();
+        assert_eq!(build_environment.get("GOPROXY").unwrap(), "http://athens:3000");
+        assert!(!build_environment.contains_key("NPM_CONFIG_REGISTRY"));
+    }
+
+    #[test]
+    fn test_plan_lists_the_stages_of_package() {
+        let mut pkg_config = dotnet_config("bookworm", true);
+        pkg_config.build_env.run_lintian = Some(true);
+        pkg_config.build_env.run_piuparts = Some(true);
+        pkg_config.control_overrides = Some(ControlOverrides {
+            package: Some("hello-world-tools".to_string()),
+            depends: vec!["adduser".to_string(), "libssl3 (>= 3.0)".to_string()],
+            ..ControlOverrides::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp/hello-world-1.0.0-1/hello-world-1.0.0"))
+            .with_recipe_revision(Some("0123abcd".to_string()));
+        let plan = build_env.get_plan().unwrap();
+        let stages: Vec<&str> = plan.stages.iter().map(|stage| stage.name.as_str()).collect();
+        assert_eq!(stages, ["sbuild", "piuparts"]);
+        let sbuild = &plan.stages[0].commands[0];
+        assert_eq!(sbuild[..4], ["sbuild", "-A", "-s", "--source-only-changes"]);
+        assert!(sbuild.contains(&"--run-lintian".to_string()));
+        assert_eq!(plan.stages[0].cwd.as_deref(), Some("/tmp/hello-world-1.0.0-1/hello-world-1.0.0"));
+        assert_eq!(plan.paths.get("deb_dir").unwrap(), "/tmp/hello-world-1.0.0-1");
+        assert_eq!(plan.inputs.get("recipe_revision").unwrap(), "0123abcd");
+        assert_eq!(plan.inputs.get("build_env.codename").unwrap(), "bookworm");
+        assert_eq!(
+            plan.inputs.get("control_overrides.hello-world-tools.depends").unwrap(),
+            "adduser, libssl3 (>= 3.0)"
+        );
+        assert!(!plan.inputs.contains_key("control_overrides.hello-world-tools.conflicts"));
+    }
+
+    #[test]
+    fn test_fixed_build_path_maps_prefix() {
+        let mut pkg_config = dotnet_config("bookworm", true);
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.build_env.fixed_build_path = Some(true);
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig::default()),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        assert_eq!(build_env.get_build_path().unwrap(), "/build/pkg-hello-world");
+        let build_environment = build_env.get_build_environment();
+        assert_eq!(
+            build_environment.get("RUSTFLAGS").unwrap(),
+            "--remap-path-prefix=/build/pkg-hello-world=."
+        );
+        assert_eq!(
+            build_environment.get("DEB_CFLAGS_APPEND").unwrap(),
+            "-fdebug-prefix-map=/build/pkg-hello-world=."
+        );
+    }
+
+    #[test]
+    fn test_build_options_are_passed_to_the_build() {
+        let mut pkg_config = dotnet_config("bookworm", true);
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig {
+                cargo_features: vec!["jemalloc".to_string(), "metrics".to_string()],
+                cargo_profile: Some("maxperf".to_string()),
+                ..RustConfig::default()
+            }),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config.clone(), PathBuf::from("/tmp"));
+        assert_eq!(
+            build_env.get_build_environment().get("CARGO_BUILD_ARGS").unwrap(),
+            "--features jemalloc,metrics --profile maxperf"
+        );
+        assert_eq!(
+            build_env.get_manifest().unwrap().build_options.get("CARGO_BUILD_ARGS").unwrap(),
+            "--features jemalloc,metrics --profile maxperf"
+        );
+
+        pkg_config.build_env.fixed_build_path = Some(true);
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Go(GoConfig {
+                go_build_tags: vec!["netgo".to_string(), "osusergo".to_string()],
+                go_ldflags: Some("-s -w".to_string()),
+                ..GoConfig::default()
+            }),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_environment = Sbuild::new(pkg_config, PathBuf::from("/tmp")).get_build_environment();
+        assert_eq!(build_environment.get("GOFLAGS").unwrap(), "-trimpath -tags=netgo,osusergo");
+        assert_eq!(build_environment.get("GO_LDFLAGS").unwrap(), "-s -w");
+    }
+
+    #[test]
+    fn test_build_hooks_are_passed_to_sbuild() {
+        let mut pkg_config = PkgConfig::default();
+        let build_env = Sbuild::new(pkg_config.clone(), PathBuf::from("/tmp"));
+        assert!(build_env.get_hook_args().is_empty());
+
+        pkg_config.build_hooks = Some(BuildHooks {
+            finished_build_commands: vec!["dpkg -l".to_string(), "date +%s".to_string()],
+            ..BuildHooks::default()
+        });
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp"));
+        assert_eq!(
+            build_env.get_hook_args(),
+            vec![
+                format!("--finished-build-commands={}", hook_command("finished-build", "dpkg -l")),
+                format!("--finished-build-commands={}", hook_command("finished-build", "date +%%s")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_output_of_both_streams() {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo smoke PASS; echo 'qemu: booting' >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (output, exit_code) = run_process_with_exit_code(&mut child, "autopkgtest", None).unwrap();
+        assert_eq!(output, "smoke PASS\nqemu: booting\n");
+        assert_eq!(exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_only_testbed_failures_are_retried() {
+        let passed = parse_autopkgtest("smoke                PASS\n");
+        let failed = parse_autopkgtest("smoke                FAIL non-zero exit status 1\n");
+        assert!(is_testbed_failure(Some(16), &[]));
+        assert!(is_testbed_failure(Some(16), &passed));
+        assert!(!is_testbed_failure(Some(16), &failed));
+        assert!(!is_testbed_failure(Some(4), &failed));
+        assert!(!is_testbed_failure(None, &[]));
+
+        assert_eq!(get_retry_delay(30, 1), Duration::from_secs(30));
+        assert_eq!(get_retry_delay(30, 3), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_manifest_redacts_presigned_tarball_url() {
+        let dir = tempdir().unwrap();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            tarball_url: "https://example.com/hello-world-1.0.0.tar.gz?X-Amz-Signature=abcdef".to_string(),
+            ..DefaultPackageTypeConfig::default()
+        });
+        pkg_config.redact = Some(RedactConfig::default());
+        let build_env = Sbuild::new(pkg_config.clone(), dir.path().join("artifacts").join("hello-world-1.0.0"));
+        create_dir_all(build_env.get_deb_dir()).unwrap();
+        fs::write(build_env.get_build_log_file(), "I: NPM_TOKEN=npm_abc123\n").unwrap();
+
+        build_env.redact_build_log().unwrap();
+        let manifest = build_env.write_manifest().unwrap();
+        // the build is only the last successful one once every check passed
+        assert!(!build_env.get_history_manifest_file().exists());
+        build_env.write_history_manifest(&manifest).unwrap();
+        assert_eq!(
+            fs::read_to_string(build_env.get_build_log_file()).unwrap(),
+            "I: NPM_TOKEN=***\n"
+        );
+        let manifest = fs::read_to_string(build_env.get_manifest_file()).unwrap();
+        assert!(manifest.contains("hello-world-1.0.0.tar.gz?X-Amz-Signature=***"), "{}", manifest);
+        // the history manifest is redacted the same way, so it still matches
+        build_env.check_last_build().unwrap();
+
+        // another toolchain under the same version fails, unless it is allowed
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            tarball_url: "https://example.com/hello-world-1.0.0.tar.gz?X-Amz-Signature=abcdef".to_string(),
+            language_env: LanguageEnv::Go(GoConfig {
+                go_version: "1.22.2".to_string(),
+                ..GoConfig::default()
+            }),
+            ..DefaultPackageTypeConfig::default()
+        });
+        let build_env = Sbuild::new(pkg_config.clone(), dir.path().join("artifacts").join("hello-world-1.0.0"));
+        let error = build_env.check_last_build().unwrap_err().to_string();
+        assert!(error.contains("hello-world 1.0.0-1 was built before with different inputs"), "{}", error);
+        assert!(error.contains("  package_type.language_env: "), "{}", error);
+        pkg_config.build_env.dev = Some(true);
+        let build_env = Sbuild::new(pkg_config.clone(), dir.path().join("artifacts").join("hello-world-1.0.0"));
+        build_env.check_last_build().unwrap();
+        pkg_config.build_env.dev = None;
+        pkg_config.build_env.allow_changed_inputs = Some(true);
+        let build_env = Sbuild::new(pkg_config, dir.path().join("artifacts").join("hello-world-1.0.0"));
+        build_env.check_last_build().unwrap();
+    }
+
+    #[test]
+    fn test_manifest_reports_chroot_changes() {
+        let dir = tempdir().unwrap();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        let build_env = Sbuild::new(pkg_config, dir.path().join("artifacts").join("hello-world-1.0.0"));
+        create_dir_all(build_env.get_deb_dir()).unwrap();
+        let build_log = |packages: &[&str]| {
+            let lines: Vec<String> = packages
+                .iter()
+                .map(|package| format!("pkg-builder-hook: chroot-packages: {}\n", package))
+                .collect();
+            fs::write(build_env.get_build_log_file(), lines.concat()).unwrap();
+        };
+        let read_manifest = || -> BuildManifest {
+            toml::from_str(&fs::read_to_string(build_env.get_manifest_file()).unwrap()).unwrap()
+        };
+
+        build_log(&["libc6:amd64 2.36-9+deb12u4", "libssl3:amd64 3.0.11-1~deb12u2"]);
+        let manifest = build_env.write_manifest().unwrap();
+        build_env.write_history_manifest(&manifest).unwrap();
+        assert!(read_manifest().chroot_changes.is_empty());
+
+        build_log(&["libc6:amd64 2.36-9+deb12u7", "zlib1g:amd64 1:1.2.13.dfsg-1"]);
+        let manifest = build_env.write_manifest().unwrap();
+        build_env.write_history_manifest(&manifest).unwrap();
+        assert_eq!(
+            read_manifest().chroot_changes,
+            vec![
+                "libc6:amd64: 2.36-9+deb12u4 -> 2.36-9+deb12u7",
+                "zlib1g:amd64: added 1:1.2.13.dfsg-1",
+                "libssl3:amd64: removed 3.0.11-1~deb12u2",
+            ]
+        );
+        assert_eq!(read_manifest().previous_version, None);
+
+        // the next version is compared with the last build of 1.0.0
+        let mut pkg_config = build_env.config.clone();
+        pkg_config.package_fields.version_number = "1.1.0".to_string();
+        let next_build_env = Sbuild::new(pkg_config, dir.path().join("artifacts").join("hello-world-1.1.0"));
+        fs::write(
+            next_build_env.get_build_log_file(),
+            "pkg-builder-hook: chroot-packages: libc6:amd64 2.36-9+deb12u8\n\
+             pkg-builder-hook: chroot-packages: zlib1g:amd64 1:1.2.13.dfsg-1\n",
+        )
+        .unwrap();
+        let manifest = next_build_env.write_manifest().unwrap();
+        assert!(manifest.chroot_changes.is_empty());
+        assert_eq!(manifest.previous_version, Some("1.0.0-1".to_string()));
+        assert_eq!(manifest.chroot_version_changes, vec!["libc6:amd64: 2.36-9+deb12u7 -> 2.36-9+deb12u8"]);
+    }
+
+    #[test]
+    fn test_build_installs_fail_by_policy() {
+        let dir = tempdir().unwrap();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        let build_files_dir = dir.path().join("artifacts").join("hello-world-1.0.0");
+        let manifest = BuildManifest {
+            build_installs: vec!["curl:amd64: added 7.88.1-10+deb12u5".to_string()],
+            ..BuildManifest::default()
+        };
+
+        let build_env = Sbuild::new(pkg_config.clone(), build_files_dir.clone());
+        create_dir_all(build_env.get_deb_dir()).unwrap();
+        build_env.check_build_installs(&manifest).unwrap();
+        let report = QualityReport::read(&build_env.get_quality_report_file()).unwrap();
+        let findings = report.build_installs.unwrap();
+        assert_eq!(findings[0].package, "curl:amd64");
+        assert_eq!(findings[0].level, FindingLevel::Warning);
+        assert_eq!(findings[0].message, "added 7.88.1-10+deb12u5 during the build");
+
+        pkg_config.build_env.fail_on_build_installs = Some(true);
+        let build_env = Sbuild::new(pkg_config, build_files_dir);
+        assert!(build_env.check_build_installs(&manifest).is_err());
+        assert!(build_env.check_build_installs(&BuildManifest::default()).is_ok());
+    }
+
+    #[test]
+    fn test_artifact_file_names_omit_epoch() {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.package_fields.epoch = Some("1".to_string());
+        pkg_config.build_env.arch = Arch::Amd64;
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp/hello-world/build"));
+
+        assert_eq!(
+            build_env.get_deb_name(),
+            PathBuf::from("/tmp/hello-world/hello-world_1.0.0-1_amd64.deb")
+        );
+        assert_eq!(
+            build_env.get_changes_file(),
+            PathBuf::from("/tmp/hello-world/hello-world_1.0.0-1_amd64.changes")
+        );
+    }
+
+    #[test]
+    fn test_arch_all_names_deb_all() {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.build_env.arch = Arch::All;
+        let build_env = Sbuild::new(pkg_config, PathBuf::from("/tmp/hello-world/build"));
+
+        assert_eq!(
+            build_env.get_deb_name(),
+            PathBuf::from("/tmp/hello-world/hello-world_1.0.0-1_all.deb")
+        );
+        // only arch all debs are built, --no-arch-any
+        assert_eq!(
+            build_env.get_changes_file(),
+            PathBuf::from("/tmp/hello-world/hello-world_1.0.0-1_all.changes")
+        );
+        // the build log is still named after the build architecture
+        assert_eq!(
+            build_env.get_build_log_file(),
+            PathBuf::from(format!(
+                "/tmp/hello-world/hello-world_1.0.0-1_{}.build",
+                Arch::Any.get_deb_arch()
+            ))
+        );
+    }
+
+    const CHANGES_CONTENT: &str = "Format: 1.8
+Source: hello-world
+Version: 1:1.0.0-1
+Checksums-Sha1:
+ da39a3ee5e6b4b0d3255bfef95601890afd80709 0 hello-world_1.0.0-1.dsc
+Checksums-Sha256:
+ e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 0 hello-world_1.0.0-1.dsc
+ 2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 5 hello-world_1.0.0-1_amd64.deb
+Files:
+ d41d8cd98f00b204e9800998ecf8427e 0 misc optional hello-world_1.0.0-1.dsc
+";
+
+    #[test]
+    fn test_parse_changes_checksums() {
+        let checksums = parse_changes_checksums(CHANGES_CONTENT).unwrap();
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums[0].name, "hello-world_1.0.0-1.dsc");
+        assert_eq!(checksums[0].size, 0);
+        assert_eq!(
+            checksums[1].hash,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_parse_changes_checksums_without_section() {
+        let result = parse_changes_checksums("Format: 1.8\nSource: hello-world\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_verify_changes_checksums() {
+        let output_dir = tempdir().unwrap();
+        let changes_file = output_dir.path().join("hello-world_1.0.0-1_amd64.changes");
+        fs::write(&changes_file, CHANGES_CONTENT).unwrap();
+        File::create(output_dir.path().join("hello-world_1.0.0-1.dsc")).unwrap();
+        fs::write(output_dir.path().join("hello-world_1.0.0-1_amd64.deb"), "hello").unwrap();
+
+        let errors =
+            verify_changes_checksums(&changes_file, output_dir.path(), "1:1.0.0-1").unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let errors =
+            verify_changes_checksums(&changes_file, output_dir.path(), "1.0.0-1").unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "changes file declares version 1:1.0.0-1, expected 1.0.0-1"
+        );
+    }
+
+    #[test]
+    fn test_verify_changes_checksums_detects_tampered_file() {
+        let output_dir = tempdir().unwrap();
+        let changes_file = output_dir.path().join("hello-world_1.0.0-1_amd64.changes");
+        fs::write(&changes_file, CHANGES_CONTENT).unwrap();
+        fs::write(output_dir.path().join("hello-world_1.0.0-1_amd64.deb"), "world").unwrap();
+
+        let errors =
+            verify_changes_checksums(&changes_file, output_dir.path(), "1:1.0.0-1").unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].to_string(),
+            "file hello-world_1.0.0-1.dsc declared in changes file does not exist"
+        );
+        assert!(errors[1]
+            .to_string()
+            .starts_with("file hello-world_1.0.0-1_amd64.deb actual sha256 is"));
+    }
 }
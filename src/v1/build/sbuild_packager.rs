@@ -1,138 +1,649 @@
 use crate::v1::build::sbuild::Sbuild;
 use crate::v1::packager::{BackendBuildEnv, Packager};
 
-use eyre::{Result};
+use eyre::{eyre, Result};
 
-use crate::v1::pkg_config::{PackageType, PkgConfig};
-use log::info;
-use std::path::PathBuf;
+use crate::v1::pkg_config::{Arch, LanguageEnv, PackageType, PkgConfig};
+use log::{info, warn};
+use std::fs;
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
 use crate::v1::build::dir_setup::{*};
+use crate::v1::build::control_overrides::{
+    apply_control_overrides, apply_debian_policy, apply_multi_arch, apply_recipe_revision,
+    get_binary_architectures, is_arch_all_only,
+};
+use crate::v1::build::apparmor::add_apparmor_profiles;
+use crate::v1::build::dev::{get_spec_key, DevStamp};
+use crate::v1::build::exports::export_artifacts;
+use crate::v1::build::manifest::get_recipe_revision;
+use crate::v1::build::plan::{BuildPlan, PlanStage};
+use crate::v1::build::conffiles::add_conffiles;
+use crate::v1::build::extras::add_extras;
+use crate::v1::build::patches::{add_patches, copy_patches};
+use crate::v1::build::github_release::download_release_asset;
+use crate::v1::build::java_dependencies::{get_vendored_java_paths, provision_java_dependencies};
+use crate::v1::build::python_dependencies::{provision_python_dependencies, PYTHON_WHEELS_DIR};
+use crate::v1::build::snapshot::{get_snapshot_file, restore_snapshot, save_snapshot};
+use crate::v1::build::store::{get_tarball_hash_algorithm, provision_dotnet_packages, ContentStore};
+use crate::v1::distribution::get_distribution;
+
+// store algorithm of the dependencies vendored by offline_dependencies
+const VENDORED_OBJECT: &str = "vendored";
 
 pub struct SbuildPackager {
     config: PkgConfig,
-    source_to_patch_from_path: String,
-    debian_artifacts_dir: String,
-    debian_orig_tarball_path: String,
-    build_files_dir: String,
-    config_root: String,
+    source_to_patch_from_path: PathBuf,
+    paths: BuildPaths,
+    config_root: PathBuf,
 }
 
 impl Packager for SbuildPackager {
     type BuildEnv = Sbuild;
 
     fn new(config: PkgConfig, config_root: String) -> Self {
-        let package_fields = config.package_fields.clone();
-        let config_root_path = PathBuf::from(&config_root);
-        let source_to_patch_from_path = config_root_path.join("src").to_str().unwrap().to_string();
+        let config_root = PathBuf::from(config_root);
+        let source_to_patch_from_path = config_root.join("src");
         let workdir = config
             .build_env
             .workdir
             .clone()
             .unwrap_or(format!("~/.pkg-builder/packages/{}", config.build_env.codename));
-        let workdir = expand_path(&workdir, None);
-        let debian_artifacts_dir = get_build_artifacts_dir(&package_fields.package_name, &workdir, &package_fields.version_number, &package_fields.revision_number);
-        let debian_orig_tarball_path = get_tarball_path(
-            &package_fields.package_name,
-            &package_fields.version_number,
-            &debian_artifacts_dir,
-        );
-        let build_files_dir = get_build_files_dir(
-            &package_fields.package_name,
-            &package_fields.version_number,
-            &debian_artifacts_dir,
-        );
+        let workdir = expand_config_path("workdir", &workdir, &config_root);
+        let paths = BuildPaths::new(Path::new(&workdir), &config.package_fields);
         let mut updated_config = SbuildPackager {
             config,
             source_to_patch_from_path,
-            build_files_dir,
-            debian_artifacts_dir,
-            debian_orig_tarball_path,
+            paths,
             config_root,
         };
         updated_config.config.build_env.workdir = Some(workdir);
-        let spec_file = package_fields.spec_file;
-        let spec_file_canonical = config_root_path.join(spec_file);
-        updated_config.config.package_fields.spec_file =
-            spec_file_canonical.to_str().unwrap().to_string();
+        if let Some(sbuild_cache_dir) = &mut updated_config.config.build_env.sbuild_cache_dir {
+            *sbuild_cache_dir = expand_config_path("sbuild_cache_dir", sbuild_cache_dir, &updated_config.config_root);
+        }
+        // relative to the config root, absolute and ~ paths (e.g. from --spec) are kept
+        let spec_file_canonical = expand_path(&updated_config.config.package_fields.spec_file, &updated_config.config_root);
+        updated_config.config.package_fields.spec_file = spec_file_canonical;
+        if let Some(trust) = &mut updated_config.config.trust {
+            for key in trust.keys.iter_mut() {
+                key.key_file = expand_path(&key.key_file, &updated_config.config_root);
+            }
+        }
+        if let Some(exports) = &mut updated_config.config.exports {
+            exports.destination = expand_path(&exports.destination, &updated_config.config_root);
+        }
+        if let Some(apparmor) = &mut updated_config.config.apparmor {
+            for profile in apparmor.profiles.iter_mut() {
+                *profile = expand_path(profile, &updated_config.config_root);
+            }
+        }
+        if let Some(debconf) = &mut updated_config.config.debconf {
+            debconf.preseed_file = expand_path(&debconf.preseed_file, &updated_config.config_root);
+        }
+        if let Some(patches) = &mut updated_config.config.patches {
+            for file in patches.files.iter_mut() {
+                *file = expand_path(file, &updated_config.config_root);
+            }
+        }
+        // the toolchain runs inside of the chroot, so it has to be built for its arch
+        let chroot_arch = updated_config.config.build_env.get_chroot_arch();
+        match &mut updated_config.config.package_type {
+            PackageType::Default(config) => config.language_env.select_arch(chroot_arch),
+            PackageType::Git(config) => config.language_env.select_arch(chroot_arch),
+            PackageType::Virtual => {}
+        }
+        if let PackageType::Git(git_config) = &mut updated_config.config.package_type {
+            if let Some(signers) = &mut git_config.allowed_signers {
+                signers.key_file =
+                    expand_path(&signers.key_file, &updated_config.config_root);
+            }
+        }
         updated_config
     }
 
     fn package(&self) -> Result<()> {
-        let pre_build: Result<()> = match &self.config.package_type {
+        if let Some(true) = self.config.build_env.offline {
+            self.check_offline()?;
+        }
+        if !self.config.uses_upstream_debian_dir() {
+            let spec_file = Path::new(&self.config.package_fields.spec_file);
+            validate_spec_file(spec_file, &self.config_root)?;
+        }
+        let build_env = self.get_build_env()?;
+        build_env.check_last_build()?;
+        // the container backends do not map subordinate ids
+        if self.config.build_env.uses_sbuild() {
+            validate_workdir_ownership(&self.paths.artifacts_dir)?;
+        }
+        let build_environment = build_env.get_build_environment();
+        self.provision_dependencies()?;
+        if let Some(true) = self.config.build_env.dev {
+            self.prepare_dev_source()?;
+        } else {
+            self.prepare_orig_tarball()?;
+            extract_source(&self.paths.tarball_path, &self.paths.build_files_dir)?;
+            self.prepare_debian_dir(&self.paths.build_files_dir)?;
+        }
+        self.provision_source_dependencies()?;
+        setup_sbuild(&build_env.get_build_user(), &build_environment)?;
+        let build_env = self.get_build_env()?;
+        let result = build_env.package();
+        // keep the workdir removable for the next run, even if the build failed
+        match normalize_permissions(&self.paths.artifacts_dir) {
+            Ok(foreign) if !foreign.is_empty() => warn!(
+                "{} entries under {} are owned by another user, the next run will remove them with unshare.",
+                foreign.len(),
+                self.paths.artifacts_dir.display()
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Could not normalize permissions: {}", err),
+        }
+        result?;
+        self.export_artifacts(&build_env)
+    }
+
+    fn get_build_env(&self) -> Result<Self::BuildEnv> {
+        let backend_build_env = Sbuild::new(self.get_build_config()?, self.paths.build_files_dir.clone())
+            .with_recipe_revision(self.get_recipe_revision());
+        Ok(backend_build_env)
+    }
+}
+
+
+impl SbuildPackager {
+    // writes <name>_<version>.orig.tar.gz into the artifacts dir
+    pub fn prepare_orig_tarball(&self) -> Result<()> {
+        create_package_dir(&self.paths.artifacts_dir)?;
+        if let Some((algorithm, hash)) = self.get_orig_tarball_object() {
+            let store = ContentStore::new(ContentStore::default_root());
+            if store.contains(algorithm, &hash) {
+                info!("Copying the orig tarball from the store");
+                fs::copy(store.object_path(algorithm, &hash)?, &self.paths.tarball_path)?;
+                return Ok(());
+            }
+        }
+        match &self.config.package_type {
             PackageType::Default(config) => {
-                create_package_dir(&self.debian_artifacts_dir.clone())?;
-                download_source(
-                    &self.debian_orig_tarball_path,
-                    &config.tarball_url,
-                    &self.config_root,
-                )?;
-                verify_hash(&self.debian_orig_tarball_path, config.tarball_hash.clone())?;
-                extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
-                create_debian_dir(
-                    &self.build_files_dir.clone(),
-                    &self.config.build_env.debcrafter_version,
-                    &self.config.package_fields.spec_file,
-                )?;
-                patch_source(
-                    &self.build_files_dir.clone(),
-                    &self.config.package_fields.homepage,
-                    &self.source_to_patch_from_path,
-                )?;
-                setup_sbuild()?;
-                Ok(())
+                match (&config.github_release, &config.tarball_auth) {
+                    (Some(release), _) => download_release_asset(
+                        &self.paths.tarball_path,
+                        release,
+                        &self.config.package_fields.version_number,
+                        self.config.trust.as_ref().and_then(|trust| trust.get_key("github_release")),
+                    )?,
+                    (None, Some(auth)) => download_source_with_auth(&self.paths.tarball_path, &config.tarball_url, auth)?,
+                    (None, None) => download_source(&self.paths.tarball_path, &config.tarball_url, &self.config_root)?,
+                }
+                verify_hash(&self.paths.tarball_path, config.tarball_hash.clone())?;
             }
             PackageType::Git(config) => {
-                create_package_dir(&self.debian_artifacts_dir.clone())?;
-                download_git(
-                    &self.debian_artifacts_dir,
-                    &self.debian_orig_tarball_path,
+                download_git_cached(
+                    &self.paths.artifacts_dir,
+                    &self.paths.tarball_path,
+                    &self.paths.orig_cache_dir,
                     &self.config.package_fields.package_name,
-                    &config.git_url,
-                    &config.git_tag,
-                    &config.submodules,
+                    config,
                 )?;
-                extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
-                create_debian_dir(
-                    &self.build_files_dir.clone(),
-                    &self.config.build_env.debcrafter_version,
-                    &self.config.package_fields.spec_file,
-                )?;
-                patch_source(
-                    &self.build_files_dir.clone(),
-                    &self.config.package_fields.homepage,
-                    &self.source_to_patch_from_path,
-                )?;
-                setup_sbuild()?;
-                Ok(())
             }
             PackageType::Virtual => {
                 info!("creating virtual package");
-                create_package_dir(&self.debian_artifacts_dir.clone())?;
-                create_empty_tar(&self.debian_artifacts_dir, &self.debian_orig_tarball_path)?;
-                extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
-                create_debian_dir(
-                    &self.build_files_dir.clone(),
-                    &self.config.build_env.debcrafter_version,
-                    &self.config.package_fields.spec_file,
+                create_empty_tar(&self.paths.artifacts_dir, &self.paths.tarball_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // url of a tarball downloaded without tarball_hash, it cannot be kept in the store
+    fn get_unpinned_tarball_url(&self) -> Option<&str> {
+        match &self.config.package_type {
+            PackageType::Default(config)
+                if config.tarball_hash.is_none()
+                    && (config.github_release.is_some() || config.tarball_url.starts_with("http")) =>
+            {
+                Some(&config.tarball_url)
+            }
+            _ => None,
+        }
+    }
+
+    // store object of the orig tarball, None for local tarballs
+    fn get_orig_tarball_object(&self) -> Option<(&'static str, String)> {
+        match &self.config.package_type {
+            PackageType::Default(config) if config.github_release.is_some() || config.tarball_url.starts_with("http") => {
+                let hash = config.tarball_hash.as_ref()?;
+                Some((get_tarball_hash_algorithm(hash), hash.to_lowercase()))
+            }
+            PackageType::Git(config) => Some(("git", git_tarball_cache_key(config))),
+            _ => None,
+        }
+    }
+
+    /// Downloads the source, the staged toolchain files and the dotnet packages into the store,
+    /// a build with build_env.offline takes them from there.
+    pub fn fetch(&self) -> Result<()> {
+        let store = ContentStore::new(ContentStore::default_root());
+        if let Some(url) = self.get_unpinned_tarball_url() {
+            return Err(eyre!("{} has no tarball_hash, pin it to keep the tarball in the store", url));
+        }
+        if let Some((algorithm, hash)) = self.get_orig_tarball_object() {
+            if !store.contains(algorithm, &hash) {
+                self.prepare_orig_tarball()?;
+                store.insert_copy(algorithm, &hash, &self.paths.tarball_path)?;
+            }
+        }
+        if let Some((hash, paths)) = self.get_vendored_dependencies_object()? {
+            if !store.contains(VENDORED_OBJECT, &hash) {
+                self.prepare_orig_tarball()?;
+                extract_source(&self.paths.tarball_path, &self.paths.build_files_dir)?;
+                self.provision_source_dependencies()?;
+                store.insert_archive(VENDORED_OBJECT, &hash, &self.paths.build_files_dir, &paths)?;
+            }
+        }
+        self.get_build_env()?.get_provisioner()?.fetch_into_store(&store)?;
+        self.provision_dependencies()?;
+        info!("Downloads of {} are in the store {}", self.config.package_fields.package_name, ContentStore::default_root().display());
+        Ok(())
+    }
+
+    // everything the build would download has to be in the store or the caches
+    fn check_offline(&self) -> Result<()> {
+        let store = ContentStore::new(ContentStore::default_root());
+        let mut missing = vec![];
+        if let Some(url) = self.get_unpinned_tarball_url() {
+            missing.push(format!("{}, it has no tarball_hash", url));
+        }
+        if let Some((algorithm, hash)) = self.get_orig_tarball_object() {
+            if !store.contains(algorithm, &hash) {
+                missing.push(format!("the orig tarball of {}", self.config.package_fields.package_name));
+            }
+        }
+        let build_env = self.get_build_env()?;
+        missing.extend(build_env.get_provisioner()?.get_missing_downloads(&build_env.get_download_cache_dir(), &store));
+        missing.extend(build_env.get_missing_default_keys());
+        if let Some(LanguageEnv::Dotnet(config)) = self.get_language_env() {
+            missing.extend(
+                config
+                    .dotnet_packages
+                    .iter()
+                    .filter(|package| !store.contains("sha1", &package.hash))
+                    .map(|package| package.url.clone()),
+            );
+        }
+        if let Some((hash, _)) = self.get_vendored_dependencies_object()? {
+            if !store.contains(VENDORED_OBJECT, &hash) {
+                missing.push("the dependencies of offline_dependencies".to_string());
+            }
+        }
+        // creating the chroot debootstraps from the archive
+        if self.config.build_env.uses_sbuild() && !Path::new(&build_env.get_cache_file()).exists() {
+            missing.push(format!("the chroot {}, create it with pkg-builder env create", build_env.get_cache_file()));
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(eyre!(
+            "offline build, but it would download:\n  {}\nrun pkg-builder fetch with network access first",
+            missing.join("\n  ")
+        ))
+    }
+
+    // debcrafter and patch phases, shared by the build and the review of the debian dir
+    fn prepare_debian_dir(&self, build_files_dir: &Path) -> Result<()> {
+        if self.config.uses_upstream_debian_dir() {
+            use_upstream_debian_dir(
+                build_files_dir,
+                &self.config.package_fields.package_name,
+                &self.config.package_fields.get_debian_version(),
+                &self.config.build_env.codename,
+            )?;
+        } else {
+            create_debian_dir(
+                build_files_dir,
+                &self.config.build_env.debcrafter_version,
+                Path::new(&self.config.package_fields.spec_file),
+                &self.config.package_fields.get_debian_version(),
+            )?;
+        }
+        patch_source(
+            build_files_dir,
+            &self.config.package_fields.homepage,
+            &self.source_to_patch_from_path,
+        )?;
+        self.add_patches(build_files_dir)?;
+        self.apply_debian_policy(build_files_dir)?;
+        self.add_extras(build_files_dir)?;
+        self.add_conffiles(build_files_dir)?;
+        self.add_apparmor_profiles(build_files_dir)?;
+        self.apply_control_overrides(build_files_dir)?;
+        self.apply_multi_arch(build_files_dir)?;
+        self.apply_recipe_revision(build_files_dir)
+    }
+
+    // keeps the artifacts dir of the last dev build, the source is only extracted again
+    // when the tarball changed and debcrafter only reruns when the spec changed
+    fn prepare_dev_source(&self) -> Result<()> {
+        let mut stamp = DevStamp::read(&self.paths.artifacts_dir);
+        if stamp.is_source_current(&self.config, &self.paths.tarball_path)
+            && self.paths.build_files_dir.exists()
+        {
+            info!("Reusing extracted source {}", self.paths.build_files_dir.display());
+        } else {
+            self.prepare_orig_tarball()?;
+            extract_source(&self.paths.tarball_path, &self.paths.build_files_dir)?;
+            stamp.set_source(&self.config, &self.paths.tarball_path)?;
+        }
+        // the upstream debian dir was extracted with the source, every step applies repeatedly
+        if self.config.uses_upstream_debian_dir() {
+            self.prepare_debian_dir(&self.paths.build_files_dir)?;
+            return stamp.write(&self.paths.artifacts_dir);
+        }
+        let spec = get_spec_key(&self.config)?;
+        if stamp.spec.as_ref() == Some(&spec) {
+            info!("Spec unchanged, keeping the generated debian dir");
+            // src/ and the control overrides may have changed, both apply repeatedly
+            patch_source(
+                &self.paths.build_files_dir,
+                &self.config.package_fields.homepage,
+                &self.source_to_patch_from_path,
+            )?;
+            self.add_patches(&self.paths.build_files_dir)?;
+            self.apply_debian_policy(&self.paths.build_files_dir)?;
+            self.add_extras(&self.paths.build_files_dir)?;
+            self.add_conffiles(&self.paths.build_files_dir)?;
+            self.add_apparmor_profiles(&self.paths.build_files_dir)?;
+            self.apply_control_overrides(&self.paths.build_files_dir)?;
+            self.apply_multi_arch(&self.paths.build_files_dir)?;
+            self.apply_recipe_revision(&self.paths.build_files_dir)?;
+        } else {
+            // debcrafter writes into the dir, files the spec no longer generates would stay
+            let debian_dir = self.paths.build_files_dir.join("debian");
+            if debian_dir.exists() {
+                fs::remove_dir_all(&debian_dir)?;
+            }
+            self.prepare_debian_dir(&self.paths.build_files_dir)?;
+            stamp.spec = Some(spec);
+        }
+        stamp.write(&self.paths.artifacts_dir)
+    }
+
+    /// Generates the debian dir into out_dir/debian without fetching the source.
+    pub fn generate_debian_dir(&self, out_dir: &Path) -> Result<PathBuf> {
+        if self.config.uses_upstream_debian_dir() {
+            return Err(eyre!("use_upstream_debian_dir is set, the debian dir comes with the git source"));
+        }
+        let spec_file = Path::new(&self.config.package_fields.spec_file);
+        validate_spec_file(spec_file, &self.config_root)?;
+        create_dir_all(out_dir)?;
+        self.prepare_debian_dir(out_dir)?;
+        Ok(out_dir.join("debian"))
+    }
+
+    /// Rebuilds the binaries of the last build, the source package is left untouched.
+    pub fn binnmu(&self, suffix: &str, changelog: &str) -> Result<()> {
+        let build_env = self.get_build_env()?;
+        self.provision_dependencies()?;
+        setup_sbuild(&build_env.get_build_user(), &build_env.get_build_environment())?;
+        build_env.binnmu(suffix, changelog)
+    }
+
+    /// The stages package runs with their commands, paths and inputs, nothing is fetched or built.
+    pub fn get_plan(&self) -> Result<BuildPlan> {
+        let mut plan = self.get_build_env()?.get_plan()?;
+        let path = |path: &Path| path.to_string_lossy().to_string();
+        plan.paths.insert("config_root".to_string(), path(&self.config_root));
+        plan.paths.insert("workdir".to_string(), self.config.build_env.workdir.clone().unwrap_or_default());
+        plan.paths.insert("artifacts_dir".to_string(), path(&self.paths.artifacts_dir));
+        plan.paths.insert("tarball_path".to_string(), path(&self.paths.tarball_path));
+
+        let mut debian_dir = PlanStage::new("debian_dir");
+        if !self.config.uses_upstream_debian_dir() {
+            let spec_file = Path::new(&self.config.package_fields.spec_file);
+            validate_spec_file(spec_file, &self.config_root)?;
+            plan.inputs.insert(
+                "package_fields.spec_file".to_string(),
+                calculate_sha256(fs::read(spec_file)?.as_slice())?,
+            );
+            debian_dir.commands.push(vec![
+                format!("debcrafter_{}", self.config.build_env.debcrafter_version),
+                path(spec_file),
+            ]);
+        }
+        if self.config.patches.is_some() {
+            debian_dir.commands.push(["quilt", "--quiltrc=-", "push", "-a"].map(String::from).to_vec());
+        }
+        debian_dir.cwd = Some(path(&self.paths.build_files_dir));
+        plan.stages.splice(0..0, [PlanStage::new("source"), debian_dir]);
+        Ok(plan)
+    }
+
+    pub fn save_snapshot(&self, name: &str) -> Result<()> {
+        let snapshot_file = get_snapshot_file(&self.paths.artifacts_dir, name)?;
+        save_snapshot(&self.paths.artifacts_dir, &snapshot_file)
+    }
+
+    pub fn restore_snapshot(&self, name: &str, force: bool) -> Result<()> {
+        let snapshot_file = get_snapshot_file(&self.paths.artifacts_dir, name)?;
+        restore_snapshot(&self.paths.artifacts_dir, &snapshot_file, force)
+    }
+
+    pub fn get_tarball_path(&self) -> &Path {
+        &self.paths.tarball_path
+    }
+
+    // downloads and verifies pinned dependencies and keys on the host before the build starts
+    fn provision_dependencies(&self) -> Result<()> {
+        let language_env = match &self.config.package_type {
+            PackageType::Default(config) => &config.language_env,
+            PackageType::Git(config) => &config.language_env,
+            PackageType::Virtual => return Ok(()),
+        };
+        let build_env = self.get_build_env()?;
+        match language_env {
+            LanguageEnv::Dotnet(config) => {
+                let store = ContentStore::new(ContentStore::default_root());
+                provision_dotnet_packages(&config.dotnet_packages, &store, &self.config_root)?;
+            }
+            _ => build_env.verify_toolchain_signature()?,
+        }
+        build_env.prepare_keyrings()
+    }
+
+    fn get_language_env(&self) -> Option<&LanguageEnv> {
+        match &self.config.package_type {
+            PackageType::Default(config) => Some(&config.language_env),
+            PackageType::Git(config) => Some(&config.language_env),
+            PackageType::Virtual => None,
+        }
+    }
+
+    // store object of the dependencies offline_dependencies vendors into the source, keyed by
+    // the source and the settings of the language env, with the paths they are written to
+    fn get_vendored_dependencies_object(&self) -> Result<Option<(String, Vec<&'static str>)>> {
+        let (language_env, paths) = match self.get_language_env() {
+            Some(LanguageEnv::Java(config)) if config.offline_dependencies == Some(true) => (
+                format!("java\ngradle={}", config.gradle.is_some()),
+                get_vendored_java_paths(config.gradle.is_some()),
+            ),
+            Some(LanguageEnv::Python(config)) if config.offline_dependencies == Some(true) => (
+                format!(
+                    "python\nrequirements_file={}\npython_version={}\narch={}",
+                    config.requirements_file.as_deref().unwrap_or_default(),
+                    config.python_version.as_deref().unwrap_or_default(),
+                    self.config.build_env.arch.get_build_arch()
+                ),
+                vec![PYTHON_WHEELS_DIR],
+            ),
+            _ => return Ok(None),
+        };
+        let source = match (self.get_orig_tarball_object(), &self.config.package_type) {
+            (Some((_, hash)), _) => hash,
+            (None, PackageType::Default(config)) => {
+                calculate_sha256(fs::File::open(get_tarball_url(&config.tarball_url, &self.config_root))?)?
+            }
+            (None, _) => return Ok(None),
+        };
+        let hash = calculate_sha256(format!("{}\n{}", source, language_env).as_bytes())?;
+        Ok(Some((hash, paths)))
+    }
+
+    // vendors dependencies into the extracted source, so the build runs offline
+    fn provision_source_dependencies(&self) -> Result<()> {
+        if let Some((hash, paths)) = self.get_vendored_dependencies_object()? {
+            let store = ContentStore::new(ContentStore::default_root());
+            if store.contains(VENDORED_OBJECT, &hash) {
+                info!("Copying the vendored dependencies from the store");
+                store.extract_archive(VENDORED_OBJECT, &hash, &self.paths.build_files_dir)?;
+                return add_include_binaries(&self.paths.build_files_dir, paths[0]);
+            }
+        }
+        let Some(language_env) = self.get_language_env() else {
+            return Ok(());
+        };
+        match language_env {
+            LanguageEnv::Java(config) if config.offline_dependencies == Some(true) => {
+                provision_java_dependencies(
+                    &self.paths.build_files_dir,
+                    config.gradle.is_some(),
                 )?;
-                patch_source(
-                    &self.build_files_dir.clone(),
-                    &self.config.package_fields.homepage,
-                    &self.source_to_patch_from_path,
+            }
+            LanguageEnv::Python(config) if config.offline_dependencies == Some(true) => {
+                provision_python_dependencies(
+                    &self.paths.build_files_dir,
+                    config,
+                    self.config.build_env.arch.get_build_arch(),
                 )?;
-                setup_sbuild()?;
-                Ok(())
             }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // packages that only build arch all debs are built as arch = "all", detected from the
+    // generated debian/control, declaring arch = "all" for arch-dependent packages fails
+    pub fn get_build_config(&self) -> Result<PkgConfig> {
+        let mut config = self.config.clone();
+        let control_file = self.paths.build_files_dir.join("debian").join("control");
+        if !control_file.exists() {
+            return Ok(config);
+        }
+        let control = fs::read_to_string(&control_file)?;
+        let arch_all_only = is_arch_all_only(&control);
+        match config.build_env.arch {
+            Arch::All if !arch_all_only => {
+                let arch_dependent: Vec<String> = get_binary_architectures(&control)
+                    .into_iter()
+                    .filter(|(_, architecture)| architecture != "all")
+                    .map(|(package, architecture)| format!("{} ({})", package, architecture))
+                    .collect();
+                return Err(eyre!(
+                    "arch = \"all\", but debian/control has arch-dependent packages: {}",
+                    arch_dependent.join(", ")
+                ));
+            }
+            Arch::All => {}
+            _ if arch_all_only => {
+                info!("debian/control only has arch all packages, building them as arch all");
+                config.build_env.arch = Arch::All;
+            }
+            _ => {}
+        }
+        Ok(config)
+    }
+
+    fn apply_debian_policy(&self, build_files_dir: &Path) -> Result<()> {
+        let package_fields = &self.config.package_fields;
+        let distribution = get_distribution(&self.config.build_env.codename)?;
+        for warning in package_fields.get_debian_policy_warnings(distribution.info()) {
+            warn!("{}", warning);
+        }
+        apply_debian_policy(
+            build_files_dir,
+            package_fields.standards_version.as_deref(),
+            package_fields.debhelper_compat,
+        )
+    }
+
+    // the final stage, runs only after the build and its tests succeeded
+    fn export_artifacts(&self, build_env: &Sbuild) -> Result<()> {
+        let Some(exports) = &self.config.exports else {
+            return Ok(());
         };
-        pre_build?;
-        let build_env = self.get_build_env().unwrap();
-        build_env.package()?;
+        let mut artifacts = build_env.get_artifacts()?;
+        for file in [build_env.get_manifest_file(), build_env.get_quality_report_file()] {
+            if file.exists() {
+                artifacts.push(file);
+            }
+        }
+        export_artifacts(
+            &artifacts,
+            exports,
+            &self.config.package_fields,
+            &self.config.build_env.codename,
+        )?;
         Ok(())
     }
 
-    fn get_build_env(&self) -> Result<Self::BuildEnv> {
-        let backend_build_env = Sbuild::new(self.config.clone(), self.build_files_dir.clone());
-        Ok(backend_build_env)
+    // a generated debian dir for review has no source to apply them to
+    fn add_patches(&self, build_files_dir: &Path) -> Result<()> {
+        match &self.config.patches {
+            Some(patches) if build_files_dir == self.paths.build_files_dir => add_patches(build_files_dir, patches),
+            Some(patches) => copy_patches(build_files_dir, patches),
+            None => Ok(()),
+        }
+    }
+
+    fn add_extras(&self, build_files_dir: &Path) -> Result<()> {
+        if let Some(extras) = &self.config.extras {
+            add_extras(build_files_dir, &self.config.package_fields.package_name, extras)?;
+        }
+        Ok(())
+    }
+
+    fn add_conffiles(&self, build_files_dir: &Path) -> Result<()> {
+        if let Some(conffiles) = &self.config.conffiles {
+            add_conffiles(build_files_dir, &self.config.package_fields, conffiles)?;
+        }
+        Ok(())
+    }
+
+    fn add_apparmor_profiles(&self, build_files_dir: &Path) -> Result<()> {
+        if let Some(apparmor) = &self.config.apparmor {
+            add_apparmor_profiles(build_files_dir, &self.config.package_fields.package_name, apparmor)?;
+        }
+        Ok(())
     }
-}
 
+    fn apply_control_overrides(&self, build_files_dir: &Path) -> Result<()> {
+        if let Some(control_overrides) = &self.config.control_overrides {
+            apply_control_overrides(
+                build_files_dir,
+                &self.config.package_fields.package_name,
+                control_overrides,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn apply_multi_arch(&self, build_files_dir: &Path) -> Result<()> {
+        if let Some(multi_arch) = &self.config.multi_arch {
+            apply_multi_arch(build_files_dir, multi_arch)?;
+        }
+        Ok(())
+    }
+
+    /// The git commit of the config, None when the config is not in a git repository.
+    pub fn get_recipe_revision(&self) -> Option<String> {
+        get_recipe_revision(&self.config_root)
+    }
+
+    fn apply_recipe_revision(&self, build_files_dir: &Path) -> Result<()> {
+        if self.config.build_env.embed_recipe_revision != Some(true) {
+            return Ok(());
+        }
+        match self.get_recipe_revision() {
+            Some(revision) => apply_recipe_revision(build_files_dir, &revision)?,
+            None => warn!("embed_recipe_revision is set, but {} is not in a git repository", self.config_root.display()),
+        }
+        Ok(())
+    }
+}
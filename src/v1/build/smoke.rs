@@ -0,0 +1,178 @@
+// Installs the built debs into a throwaway podman container of the target distribution
+// and runs the configured smoke tests in it.
+use crate::v1::build::debconf::PRESEED_PATH;
+use crate::v1::pkg_config::SmokeTest;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use rand::random;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn podman(args: &[String]) -> Result<Output> {
+    Command::new("podman")
+        .args(args)
+        .output()
+        .map_err(|err| eyre!("Failed to run podman: {}", err))
+}
+
+fn podman_exec(container: &str, command: &str) -> Result<Output> {
+    podman(&[
+        "exec".to_string(),
+        container.to_string(),
+        "sh".to_string(),
+        "-c".to_string(),
+        command.to_string(),
+    ])
+}
+
+/// Checks the exit code and output of one smoke test.
+pub fn check_smoke_test(test: &SmokeTest, exit_code: Option<i32>, output: &str) -> Result<()> {
+    let expected_exit_code = test.exit_code.unwrap_or(0);
+    if exit_code != Some(expected_exit_code) {
+        return Err(eyre!(
+            "`{}` exited with {}, expected {}",
+            test.command,
+            exit_code.map_or("a signal".to_string(), |code| code.to_string()),
+            expected_exit_code
+        ));
+    }
+    if let Some(output_regex) = &test.output_regex {
+        let re = Regex::new(output_regex)?;
+        if !re.is_match(output) {
+            return Err(eyre!(
+                "`{}` output does not match {}: {}",
+                test.command,
+                output_regex,
+                output.trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn run_in_container(
+    container: &str,
+    debs: &[PathBuf],
+    tests: &[SmokeTest],
+    preseeded: bool,
+    recommends_flag: Option<&str>,
+) -> Result<()> {
+    let install_paths: Vec<String> = debs
+        .iter()
+        .map(|deb| format!("/debs/{}", deb.file_name().unwrap().to_string_lossy()))
+        .collect();
+    let mut install_command = format!(
+        "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y {}{}",
+        recommends_flag.map(|flag| format!("{} ", flag)).unwrap_or_default(),
+        install_paths.join(" ")
+    );
+    if preseeded {
+        install_command = format!("debconf-set-selections {} && {}", PRESEED_PATH, install_command);
+    }
+    let install = podman_exec(container, &install_command)?;
+    if !install.status.success() {
+        return Err(eyre!(
+            "Failed to install the package into the container: {}",
+            String::from_utf8_lossy(&install.stderr)
+        ));
+    }
+
+    let mut failures = vec![];
+    for test in tests {
+        let output = podman_exec(container, &test.command)?;
+        let combined = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        match check_smoke_test(test, output.status.code(), &combined) {
+            Ok(()) => info!("Smoke test passed: {}", test.command),
+            Err(err) => failures.push(err.to_string()),
+        }
+    }
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(eyre!(
+        "{} of {} smoke tests failed:\n  {}",
+        failures.len(),
+        tests.len(),
+        failures.join("\n  ")
+    ))
+}
+
+pub fn run_smoke_tests(
+    image: &str,
+    debs: &[PathBuf],
+    tests: &[SmokeTest],
+    preseed_file: Option<&Path>,
+    recommends_flag: Option<&str>,
+) -> Result<()> {
+    let container = format!("pkg-builder-smoke-{:08x}", random::<u32>());
+    let mut args = vec![
+        "run".to_string(),
+        "-d".to_string(),
+        "--rm".to_string(),
+        "--name".to_string(),
+        container.clone(),
+    ];
+    for deb in debs {
+        args.push("-v".to_string());
+        args.push(format!(
+            "{}:/debs/{}:ro",
+            deb.display(),
+            deb.file_name().unwrap().to_string_lossy()
+        ));
+    }
+    if let Some(preseed_file) = preseed_file {
+        args.push("-v".to_string());
+        args.push(format!("{}:{}:ro", preseed_file.display(), PRESEED_PATH));
+    }
+    args.append(&mut vec![image.to_string(), "sleep".to_string(), "infinity".to_string()]);
+    info!("Starting smoke test container from {}", image);
+    let output = podman(&args)?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to start container from {}: {}",
+            image,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let result = run_in_container(&container, debs, tests, preseed_file.is_some(), recommends_flag);
+    // the container is removed even if the tests failed
+    if let Err(err) = podman(&["rm".to_string(), "-f".to_string(), container.clone()]) {
+        warn!("Failed to remove container {}: {}", container, err);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_smoke_test() {
+        let test = SmokeTest {
+            command: "geth --version".to_string(),
+            exit_code: None,
+            output_regex: Some(r"^geth version 1\.14\.\d+".to_string()),
+        };
+        check_smoke_test(&test, Some(0), "geth version 1.14.5-stable\n").unwrap();
+
+        let error = check_smoke_test(&test, Some(0), "geth version 1.13.0\n").unwrap_err();
+        assert!(error.to_string().contains("output does not match"), "{}", error);
+
+        let error = check_smoke_test(&test, Some(127), "").unwrap_err();
+        assert_eq!(error.to_string(), "`geth --version` exited with 127, expected 0");
+
+        let test = SmokeTest {
+            command: "geth --unknown-flag".to_string(),
+            exit_code: Some(1),
+            output_regex: None,
+        };
+        check_smoke_test(&test, Some(1), "").unwrap();
+        assert!(check_smoke_test(&test, None, "").is_err());
+    }
+}
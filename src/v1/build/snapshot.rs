@@ -0,0 +1,139 @@
+// Snapshots of the artifacts dir of a package: the extracted source, the generated debian dir
+// and the dev stamp, so packaging work can be moved to another machine or kept before an
+// experiment. Paths in the archive are relative to the workdir, the built debs are left out.
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const SNAPSHOTS_DIR: &str = ".snapshots";
+// rebuilt by the next package run
+const SNAPSHOT_EXCLUDES: [&str; 3] = ["*.deb", "*.ddeb", "*.udeb"];
+
+fn get_artifacts_name(artifacts_dir: &Path) -> Result<(&Path, String)> {
+    let workdir = artifacts_dir
+        .parent()
+        .ok_or_else(|| eyre!("{} has no parent dir", artifacts_dir.display()))?;
+    let name = artifacts_dir.file_name().unwrap().to_string_lossy().to_string();
+    Ok((workdir, name))
+}
+
+/// <workdir>/.snapshots/<package>-<version>-<revision>/<name>.tar.gz
+pub fn get_snapshot_file(artifacts_dir: &Path, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(eyre!("invalid snapshot name {:?}, it cannot be empty, contain / or start with .", name));
+    }
+    let (workdir, artifacts_name) = get_artifacts_name(artifacts_dir)?;
+    Ok(workdir.join(SNAPSHOTS_DIR).join(artifacts_name).join(format!("{}.tar.gz", name)))
+}
+
+pub fn save_snapshot(artifacts_dir: &Path, snapshot_file: &Path) -> Result<()> {
+    if !artifacts_dir.exists() {
+        return Err(eyre!("Nothing to snapshot, {} does not exist", artifacts_dir.display()));
+    }
+    if snapshot_file.exists() {
+        return Err(eyre!("Snapshot {} already exists", snapshot_file.display()));
+    }
+    let (workdir, artifacts_name) = get_artifacts_name(artifacts_dir)?;
+    let snapshot_dir = snapshot_file.parent().unwrap();
+    fs::create_dir_all(snapshot_dir)?;
+    // a failed run leaves no partial snapshot behind
+    let tmp_file = tempfile::Builder::new().suffix(".tar.gz").tempfile_in(snapshot_dir)?;
+    let mut command = Command::new("tar");
+    for exclude in SNAPSHOT_EXCLUDES {
+        command.arg(format!("--exclude={}", exclude));
+    }
+    let output = command
+        .arg("-czf")
+        .arg(tmp_file.path())
+        .arg("-C")
+        .arg(workdir)
+        .arg(&artifacts_name)
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to create snapshot: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    tmp_file.persist(snapshot_file)?;
+    info!("Saved snapshot of {} to {}", artifacts_dir.display(), snapshot_file.display());
+    Ok(())
+}
+
+/// Replaces the artifacts dir with the snapshot, an existing artifacts dir is only replaced with force.
+pub fn restore_snapshot(artifacts_dir: &Path, snapshot_file: &Path, force: bool) -> Result<()> {
+    if !snapshot_file.exists() {
+        return Err(eyre!("Snapshot {} does not exist", snapshot_file.display()));
+    }
+    let (workdir, artifacts_name) = get_artifacts_name(artifacts_dir)?;
+    let output = Command::new("tar").arg("-tzf").arg(snapshot_file).output()?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to read snapshot: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    let prefix = format!("{}/", artifacts_name);
+    let listing = String::from_utf8_lossy(&output.stdout);
+    if let Some(entry) = listing.lines().find(|entry| *entry != artifacts_name && !entry.starts_with(&prefix)) {
+        return Err(eyre!(
+            "Snapshot {} is not a snapshot of {}, it contains {}",
+            snapshot_file.display(),
+            artifacts_name,
+            entry
+        ));
+    }
+    if artifacts_dir.exists() {
+        if !force {
+            return Err(eyre!(
+                "{} exists, restore with --force to replace it",
+                artifacts_dir.display()
+            ));
+        }
+        fs::remove_dir_all(artifacts_dir)?;
+    }
+    fs::create_dir_all(workdir)?;
+    let output = Command::new("tar").arg("-xzf").arg(snapshot_file).arg("-C").arg(workdir).output()?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to restore snapshot: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    info!("Restored {} from {}", artifacts_dir.display(), snapshot_file.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_restore_snapshot() {
+        let workdir = tempdir().unwrap();
+        let artifacts_dir = workdir.path().join("hello-world-1.0.0-1");
+        let build_files_dir = artifacts_dir.join("hello-world-1.0.0");
+        fs::create_dir_all(build_files_dir.join("debian")).unwrap();
+        fs::write(build_files_dir.join("debian/control"), "Source: hello-world\n").unwrap();
+        fs::write(artifacts_dir.join(".dev-stamp.toml"), "spec = \"abc\"\n").unwrap();
+        fs::write(artifacts_dir.join("hello-world_1.0.0-1_amd64.deb"), "deb").unwrap();
+
+        assert!(get_snapshot_file(&artifacts_dir, "../other").is_err());
+        let snapshot_file = get_snapshot_file(&artifacts_dir, "before-patch").unwrap();
+        assert_eq!(
+            snapshot_file,
+            workdir.path().join(".snapshots/hello-world-1.0.0-1/before-patch.tar.gz")
+        );
+        save_snapshot(&artifacts_dir, &snapshot_file).unwrap();
+        assert!(save_snapshot(&artifacts_dir, &snapshot_file).is_err());
+
+        fs::write(build_files_dir.join("debian/control"), "Source: broken\n").unwrap();
+        assert!(restore_snapshot(&artifacts_dir, &snapshot_file, false).is_err());
+        restore_snapshot(&artifacts_dir, &snapshot_file, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(build_files_dir.join("debian/control")).unwrap(),
+            "Source: hello-world\n"
+        );
+        assert!(artifacts_dir.join(".dev-stamp.toml").exists());
+        assert!(!artifacts_dir.join("hello-world_1.0.0-1_amd64.deb").exists());
+
+        // a snapshot of another package version is refused
+        let other_dir = workdir.path().join("hello-world-1.0.1-1");
+        assert!(restore_snapshot(&other_dir, &snapshot_file, true).is_err());
+        assert!(!other_dir.exists());
+    }
+}
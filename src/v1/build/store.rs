@@ -0,0 +1,266 @@
+// Content-addressed store for verified downloads, objects live under
+// <root>/<algorithm>/<first two hash chars>/<hash> and are never modified.
+// `pkg-builder fetch` fills it with everything a build downloads, git sources are stored as
+// their orig tarball under the cache key pinning the tag and the submodule commits.
+use crate::v1::build::dir_setup::{download_source, open_for_hashing};
+use crate::v1::build::sbuild::calculate_sha1;
+use crate::v1::pkg_config::DotnetPackage;
+use eyre::{eyre, Report, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+/// Algorithm of a tarball_hash, verify_hash accepts sha512 and sha256.
+pub fn get_tarball_hash_algorithm(hash: &str) -> &'static str {
+    if hash.len() == 128 {
+        "sha512"
+    } else {
+        "sha256"
+    }
+}
+
+impl ContentStore {
+    pub fn new(root: PathBuf) -> Self {
+        ContentStore { root }
+    }
+
+    pub fn default_root() -> PathBuf {
+        PathBuf::from(shellexpand::tilde("~/.pkg-builder/store").to_string())
+    }
+
+    /// Path of an object, hashes come from the configs, so anything but the hex digest of the
+    /// algorithm is refused instead of being joined into the path.
+    pub fn object_path(&self, algorithm: &str, hash: &str) -> Result<PathBuf> {
+        let length = match algorithm {
+            "sha1" => 40,
+            "sha256" | "git" | "vendored" => 64,
+            "sha512" => 128,
+            _ => return Err(eyre!("Unknown store algorithm {}", algorithm)),
+        };
+        let hash = hash.to_lowercase();
+        if hash.len() != length || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(eyre!("{} is not a {} hash", hash, algorithm));
+        }
+        Ok(self.root.join(algorithm).join(&hash[..2]).join(&hash))
+    }
+
+    pub fn contains(&self, algorithm: &str, hash: &str) -> bool {
+        self.object_path(algorithm, hash).is_ok_and(|object_path| object_path.exists())
+    }
+
+    /// Moves an already verified file into the store.
+    pub fn insert(&self, algorithm: &str, hash: &str, file: &Path) -> Result<PathBuf> {
+        let object_path = self.object_path(algorithm, hash)?;
+        fs::create_dir_all(object_path.parent().unwrap())?;
+        if fs::rename(file, &object_path).is_err() {
+            fs::copy(file, &object_path)?;
+            fs::remove_file(file)?;
+        }
+        Ok(object_path)
+    }
+
+    /// Copies an already verified file into the store, the file is kept.
+    pub fn insert_copy(&self, algorithm: &str, hash: &str, file: &Path) -> Result<PathBuf> {
+        self.object_path(algorithm, hash)?;
+        let tmp_file = self.get_tmp_dir()?.join(hash);
+        fs::copy(file, &tmp_file)?;
+        self.insert(algorithm, hash, &tmp_file)
+    }
+
+    /// Archives paths relative to a directory into the store, e.g. vendored dependencies.
+    pub fn insert_archive(&self, algorithm: &str, hash: &str, dir: &Path, paths: &[&str]) -> Result<PathBuf> {
+        self.object_path(algorithm, hash)?;
+        let tmp_file = self.get_tmp_dir()?.join(hash);
+        let output = Command::new("tar")
+            .arg("czf")
+            .arg(&tmp_file)
+            .arg("-C")
+            .arg(dir)
+            .args(paths)
+            .output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "Failed to archive {} of {}: {}",
+                paths.join(", "),
+                dir.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        self.insert(algorithm, hash, &tmp_file)
+    }
+
+    /// Extracts an object of insert_archive into a directory.
+    pub fn extract_archive(&self, algorithm: &str, hash: &str, dir: &Path) -> Result<()> {
+        let object_path = self.object_path(algorithm, hash)?;
+        let output = Command::new("tar")
+            .arg("xzf")
+            .arg(&object_path)
+            .arg("-C")
+            .arg(dir)
+            .output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "Failed to extract {} into {}: {}",
+                object_path.display(),
+                dir.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn get_tmp_dir(&self) -> Result<PathBuf> {
+        let tmp_dir = self.root.join("tmp");
+        fs::create_dir_all(&tmp_dir)?;
+        Ok(tmp_dir)
+    }
+}
+
+/// Downloads every dotnet package, verifies its sha1 and caches it in the store.
+/// All mismatches are reported at once, nothing unverified is stored.
+pub fn provision_dotnet_packages(
+    dotnet_packages: &[DotnetPackage],
+    store: &ContentStore,
+    config_root: &Path,
+) -> Result<()> {
+    let mut errors: Vec<Report> = vec![];
+    for package in dotnet_packages {
+        if store.contains("sha1", &package.hash) {
+            info!("Dotnet package {} is already provisioned.", package.name);
+            continue;
+        }
+        let download_path = store.get_tmp_dir()?.join(format!("{}.deb", package.name));
+        if let Err(err) = download_source(&download_path, &package.url, config_root) {
+            errors.push(eyre!("{}: download from {} failed: {}", package.name, package.url, err));
+            continue;
+        }
+        let actual_hash = calculate_sha1(open_for_hashing(&download_path)?)?;
+        if actual_hash != package.hash.to_lowercase() {
+            fs::remove_file(&download_path)?;
+            errors.push(eyre!(
+                "{}: sha1 is {}, expected {}",
+                package.name,
+                actual_hash,
+                package.hash
+            ));
+            continue;
+        }
+        let object_path = store.insert("sha1", &actual_hash, &download_path)?;
+        info!(
+            "Dotnet package {} verified and stored at {}",
+            package.name,
+            object_path.display()
+        );
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let summary = errors
+        .iter()
+        .map(|err| format!("  {}", err))
+        .collect::<Vec<String>>()
+        .join("\n");
+    Err(eyre!(
+        "{} dotnet packages failed verification:\n{}",
+        errors.len(),
+        summary
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn dotnet_package(dir: &Path, name: &str, content: &str, hash: &str) -> DotnetPackage {
+        let path = dir.join(format!("{}.deb", name));
+        fs::write(&path, content).unwrap();
+        DotnetPackage {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            url: path.to_str().unwrap().to_string(),
+        }
+    }
+
+    #[test]
+    fn test_provision_dotnet_packages() {
+        let upstream = tempdir().unwrap();
+        let store_dir = tempdir().unwrap();
+        let store = ContentStore::new(store_dir.path().to_path_buf());
+        // sha1 of "hello"
+        let hash = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+        let packages = vec![dotnet_package(upstream.path(), "dotnet-sdk-8.0", "hello", hash)];
+
+        provision_dotnet_packages(&packages, &store, Path::new("")).unwrap();
+        assert!(store.contains("sha1", hash));
+        assert_eq!(
+            fs::read_to_string(store.object_path("sha1", hash).unwrap()).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_provision_dotnet_packages_lists_all_mismatches() {
+        let upstream = tempdir().unwrap();
+        let store_dir = tempdir().unwrap();
+        let store = ContentStore::new(store_dir.path().to_path_buf());
+        let packages = vec![
+            dotnet_package(upstream.path(), "dotnet-sdk-8.0", "hello", "0000"),
+            dotnet_package(upstream.path(), "dotnet-runtime-8.0", "world", "1111"),
+        ];
+
+        let error = provision_dotnet_packages(&packages, &store, Path::new(""))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("2 dotnet packages failed verification"));
+        assert!(error.contains("dotnet-sdk-8.0: sha1 is aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d, expected 0000"));
+        assert!(error.contains("dotnet-runtime-8.0: sha1 is"));
+        assert!(!store.contains("sha1", "0000"));
+    }
+
+    #[test]
+    fn test_vendored_dependencies_archive() {
+        let source = tempdir().unwrap();
+        let store_dir = tempdir().unwrap();
+        let store = ContentStore::new(store_dir.path().to_path_buf());
+        fs::create_dir_all(source.path().join("debian/python-wheels")).unwrap();
+        fs::write(source.path().join("debian/python-wheels/click.whl"), "wheel").unwrap();
+        fs::write(source.path().join("setup.py"), "").unwrap();
+        let hash = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+        store
+            .insert_archive("vendored", hash, source.path(), &["debian/python-wheels"])
+            .unwrap();
+        assert!(store.contains("vendored", hash));
+
+        let build_files_dir = tempdir().unwrap();
+        store.extract_archive("vendored", hash, build_files_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(build_files_dir.path().join("debian/python-wheels/click.whl")).unwrap(),
+            "wheel"
+        );
+        assert!(!build_files_dir.path().join("setup.py").exists());
+    }
+
+    #[test]
+    fn test_object_path_refuses_other_than_hex_digests() {
+        let store = ContentStore::new(PathBuf::from("/store"));
+        let hash = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+        assert_eq!(
+            store.object_path("sha1", &hash.to_uppercase()).unwrap(),
+            Path::new("/store/sha1/aa").join(hash)
+        );
+        let traversal = format!("../../{}", &hash[6..]);
+        assert_eq!(
+            store.object_path("sha1", &traversal).unwrap_err().to_string(),
+            format!("{} is not a sha1 hash", traversal)
+        );
+        assert!(store.object_path("sha256", hash).is_err());
+        assert!(store.object_path("../sha1", hash).is_err());
+        assert!(!store.contains("sha1", "../../etc/passwd"));
+    }
+}
@@ -0,0 +1,86 @@
+// The locally built debs the testbeds install, derived from the build output instead of
+// setup commands listing them by hand. autopkgtest resolves @ in debian/tests/control to
+// exactly these, debug symbols and excluded packages are left out.
+use crate::v1::pkg_config::TestDepsConfig;
+use eyre::{eyre, Result};
+use std::path::{Path, PathBuf};
+
+const RECOMMENDS_APT_CONF: &str = "/etc/apt/apt.conf.d/90pkg-builder-recommends";
+
+/// Binary package name of a deb, <name>_<version>_<arch>.deb
+pub fn get_package_name(deb: &Path) -> Option<String> {
+    let file_name = deb.file_name()?.to_str()?;
+    let name = file_name.strip_suffix(".deb")?.split('_').next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+pub fn select_test_debs(debs: &[PathBuf], config: Option<&TestDepsConfig>) -> Result<Vec<PathBuf>> {
+    let exclude = config.map(|config| config.exclude.as_slice()).unwrap_or_default();
+    let patterns = exclude
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(|err| eyre!("test_deps: invalid glob {}: {}", pattern, err)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut selected = vec![];
+    for deb in debs {
+        let name = get_package_name(deb).ok_or_else(|| eyre!("{} is not a deb", deb.display()))?;
+        if name.ends_with("-dbgsym") || patterns.iter().any(|pattern| pattern.matches(&name)) {
+            continue;
+        }
+        selected.push(deb.clone());
+    }
+    Ok(selected)
+}
+
+/// Test source and binaries of autopkgtest, with the apt config of the recommends toggle.
+pub fn get_autopkgtest_args(test_source: &Path, debs: &[PathBuf], config: Option<&TestDepsConfig>) -> Vec<String> {
+    let mut args = vec![test_source.to_string_lossy().to_string()];
+    args.extend(debs.iter().map(|deb| deb.to_string_lossy().to_string()));
+    if let Some(install_recommends) = config.and_then(|config| config.install_recommends) {
+        args.push(format!(
+            "--setup-commands=echo 'APT::Install-Recommends \"{}\";' > {}",
+            install_recommends, RECOMMENDS_APT_CONF
+        ));
+    }
+    args
+}
+
+/// apt-get install flag of the recommends toggle, apt's default when unset.
+pub fn get_apt_recommends_flag(config: Option<&TestDepsConfig>) -> Option<&'static str> {
+    match config.and_then(|config| config.install_recommends) {
+        Some(true) => Some("--install-recommends"),
+        Some(false) => Some("--no-install-recommends"),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_test_debs() {
+        let debs = vec![
+            PathBuf::from("/out/lodestar_1.20.0-1_amd64.deb"),
+            PathBuf::from("/out/lodestar-dbgsym_1.20.0-1_amd64.deb"),
+            PathBuf::from("/out/lodestar-dev_1.20.0-1_amd64.deb"),
+            PathBuf::from("/out/lodestar-doc_1.20.0-1_all.deb"),
+        ];
+        let config = TestDepsConfig {
+            install_recommends: Some(false),
+            exclude: vec!["*-doc".to_string()],
+        };
+        let selected = select_test_debs(&debs, Some(&config)).unwrap();
+        assert_eq!(
+            selected.iter().filter_map(|deb| get_package_name(deb)).collect::<Vec<_>>(),
+            ["lodestar", "lodestar-dev"]
+        );
+        assert_eq!(select_test_debs(&debs, None).unwrap().len(), 3);
+
+        let args = get_autopkgtest_args(Path::new("/out/lodestar_1.20.0-1.dsc"), &selected, Some(&config));
+        assert_eq!(args[0], "/out/lodestar_1.20.0-1.dsc");
+        assert_eq!(args[2], "/out/lodestar-dev_1.20.0-1_amd64.deb");
+        assert!(args[3].contains("APT::Install-Recommends \"false\";"));
+        assert_eq!(get_apt_recommends_flag(Some(&config)), Some("--no-install-recommends"));
+        assert_eq!(get_apt_recommends_flag(None), None);
+    }
+}
@@ -0,0 +1,117 @@
+// The testbed autopkgtest runs the built debs in. amd64 and arm64 boot a qemu image of the
+// suite, arm64 with UEFI firmware. Booting a VM of the other architectures is impractical,
+// they are tested in a podman container of the suite, emulated by qemu-user on other hosts.
+use crate::v1::pkg_config::{Arch, AutopkgtestTestbed};
+use eyre::{eyre, Result};
+use std::path::Path;
+use std::process::Command;
+
+// firmware autopkgtest-virt-qemu --efi boots arm64 images with, from qemu-efi-aarch64
+const AAVMF_CODE: &str = "/usr/share/AAVMF/AAVMF_CODE.fd";
+
+/// The configured testbed, otherwise qemu where a VM of the architecture is practical.
+pub fn get_testbed(configured: Option<AutopkgtestTestbed>, arch: &str) -> AutopkgtestTestbed {
+    match (configured, arch) {
+        (Some(testbed), _) => testbed,
+        (None, "amd64" | "arm64") => AutopkgtestTestbed::Qemu,
+        (None, _) => AutopkgtestTestbed::Podman,
+    }
+}
+
+/// qemu-system-<arch> of the debian architecture
+pub fn get_qemu_arch(arch: &str) -> Result<&'static str> {
+    match arch {
+        "amd64" => Ok("x86_64"),
+        "arm64" => Ok("aarch64"),
+        "armhf" => Ok("arm"),
+        "riscv64" => Ok("riscv64"),
+        _ => Err(eyre!("No qemu testbed for architecture {}", arch)),
+    }
+}
+
+/// Virt server arguments of autopkgtest, after the --
+pub fn get_qemu_virt_args(arch: &str, image_path: &Path) -> Result<Vec<String>> {
+    let mut args = vec!["qemu".to_string()];
+    if arch != Arch::host().as_str() {
+        args.push(format!("--qemu-architecture={}", get_qemu_arch(arch)?));
+    }
+    if arch == "arm64" {
+        args.push("--efi".to_string());
+    }
+    // streams the boot console, so boot timeouts can be diagnosed while waiting
+    args.push("--show-boot".to_string());
+    args.push(image_path.to_string_lossy().to_string());
+    Ok(args)
+}
+
+pub fn get_podman_virt_args(image: &str) -> Vec<String> {
+    vec!["podman".to_string(), image.to_string()]
+}
+
+/// The qemu binary and firmware of the architecture, with the packages providing them.
+pub fn check_qemu_installed(arch: &str) -> Result<()> {
+    let qemu = format!("qemu-system-{}", get_qemu_arch(arch)?);
+    let installed = Command::new("which")
+        .arg(&qemu)
+        .output()
+        .is_ok_and(|output| output.status.success());
+    let package = match arch {
+        "amd64" => "qemu-system-x86",
+        "riscv64" => "qemu-system-misc",
+        _ => "qemu-system-arm",
+    };
+    if !installed {
+        return Err(eyre!(
+            "{} is needed for the {} autopkgtest testbed, install {} or set autopkgtest_testbed = \"podman\"",
+            qemu,
+            arch,
+            package
+        ));
+    }
+    if arch == "arm64" && !Path::new(AAVMF_CODE).exists() {
+        return Err(eyre!(
+            "{} is needed to boot the arm64 autopkgtest testbed, install qemu-efi-aarch64",
+            AAVMF_CODE
+        ));
+    }
+    Ok(())
+}
+
+/// Pulls the image for the architecture, the virt server starts whichever was pulled last.
+pub fn pull_container_image(image: &str, arch: &str) -> Result<()> {
+    let output = Command::new("podman")
+        .args(["pull", &format!("--arch={}", arch), image])
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to pull {} for {}: {}",
+            image,
+            arch,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_testbed_follows_arch() {
+        assert_eq!(get_testbed(None, "amd64"), AutopkgtestTestbed::Qemu);
+        assert_eq!(get_testbed(None, "arm64"), AutopkgtestTestbed::Qemu);
+        assert_eq!(get_testbed(None, "armhf"), AutopkgtestTestbed::Podman);
+        assert_eq!(get_testbed(Some(AutopkgtestTestbed::Podman), "amd64"), AutopkgtestTestbed::Podman);
+
+        let image = Path::new("/cache/autopkgtest-bookworm-arm64.img");
+        let args = get_qemu_virt_args("arm64", image).unwrap();
+        assert!(args.contains(&"--efi".to_string()));
+        assert_eq!(args.last().unwrap(), "/cache/autopkgtest-bookworm-arm64.img");
+        if Arch::host() != Arch::Arm64 {
+            assert!(args.contains(&"--qemu-architecture=aarch64".to_string()));
+        }
+        assert!(get_qemu_arch("i386").is_err());
+        assert_eq!(get_podman_virt_args("docker.io/debian:bookworm"), ["podman", "docker.io/debian:bookworm"]);
+    }
+}
@@ -0,0 +1,418 @@
+// Pinned signing keys of third-party toolchains. The key material from the config
+// is checked on the host, and only the declared keys end up in the chroot. Rust and the
+// microsoft repository are verified against a pinned default key without a [trust] key.
+// Signed git tags of the sources are verified against pinned keys as well.
+use crate::v1::build::dir_setup::create_exclusively;
+use crate::v1::pkg_config::{AllowedSigners, TrustedKey};
+use eyre::{eyre, Result};
+use log::info;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Apt keyring of the microsoft repository inside the chroot.
+pub const MICROSOFT_KEYRING: &str = "/etc/apt/keyrings/pkg-builder-microsoft.asc";
+
+pub struct DefaultKey {
+    pub source: &'static str,
+    pub url: &'static str,
+    pub fingerprint: &'static str,
+}
+
+// used when [trust] declares no key for the source
+const DEFAULT_KEYS: [DefaultKey; 2] = [
+    DefaultKey {
+        source: "rust",
+        url: "https://static.rust-lang.org/rust-key.gpg.ascii",
+        fingerprint: "108F66205EAEB0AAA8DD5E1C85AB96E6FA1BE5FE",
+    },
+    DefaultKey {
+        source: "microsoft",
+        url: "https://packages.microsoft.com/keys/microsoft.asc",
+        fingerprint: "BC528686B50D79E339D3721CEB3E94ADBE1229CF",
+    },
+];
+
+/// Declared keys as armored public keys, ready to be copied into the chroot.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TrustedKeyring {
+    pub armored: String,
+    pub fingerprints: Vec<String>,
+}
+
+fn run_gpg(home: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("gpg")
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--no-tty"])
+        .args(args)
+        .output()
+        .map_err(|err| eyre!("Failed to run gpg: {}", err))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "gpg {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Imports key_file into a throwaway keyring and writes the declared keys to keyring_path,
+/// fails if the file holds undeclared primary keys or misses a declared one.
+pub fn build_keyring(key: &TrustedKey, key_file: &Path, keyring_path: &Path) -> Result<TrustedKeyring> {
+    let home = tempdir()?;
+    let key_file = key_file
+        .to_str()
+        .ok_or_else(|| eyre!("Invalid key file {}", key_file.display()))?;
+    run_gpg(home.path(), &["--import", key_file])?;
+
+    let listing = run_gpg(home.path(), &["--with-colons", "--fingerprint", "--list-keys"])?;
+    let mut found = vec![];
+    let mut primary = false;
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields[0] {
+            "pub" => primary = true,
+            "sub" => primary = false,
+            "fpr" if primary => {
+                found.push(fields[9].to_string());
+                primary = false;
+            }
+            _ => {}
+        }
+    }
+    let fingerprints = key.get_fingerprints();
+    if let Some(undeclared) = found.iter().find(|fpr| !fingerprints.contains(fpr)) {
+        return Err(eyre!(
+            "trust: {} contains key {}, which is not declared for {}",
+            key_file,
+            undeclared,
+            key.source
+        ));
+    }
+    if let Some(missing) = fingerprints.iter().find(|fpr| !found.contains(fpr)) {
+        return Err(eyre!(
+            "trust: declared key {} of {} is missing in {}",
+            missing,
+            key.source,
+            key_file
+        ));
+    }
+
+    let mut export_args = vec!["--armor", "--export"];
+    export_args.extend(fingerprints.iter().map(|fpr| fpr.as_str()));
+    let armored = run_gpg(home.path(), &export_args)?;
+    if let Some(parent) = keyring_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(keyring_path, &armored)?;
+    info!("Wrote keyring of {} to {}", key.source, keyring_path.display());
+    Ok(TrustedKeyring {
+        armored,
+        fingerprints,
+    })
+}
+
+/// The pinned default key of source, None if the source has none.
+pub fn find_default_key(source: &str) -> Option<&'static DefaultKey> {
+    DEFAULT_KEYS.iter().find(|key| key.source == source)
+}
+
+/// The default key of source is fetched into keys_dir under this name.
+pub fn get_default_key_file(source: &str, keys_dir: &Path) -> PathBuf {
+    keys_dir.join(format!("{}.asc", source))
+}
+
+/// Pinned default key of source as a trust key, None if the source has none. The key file is
+/// fetched into keys_dir once, build_keyring only accepts it with the pinned fingerprint.
+pub fn get_default_key(source: &str, keys_dir: &Path) -> Result<Option<TrustedKey>> {
+    let Some(default_key) = find_default_key(source) else {
+        return Ok(None);
+    };
+    let key_file = get_default_key_file(source, keys_dir);
+    create_exclusively(&key_file, |tmp_file| {
+        info!("Fetching the default {} key from {}", source, default_key.url);
+        let status = Command::new("wget").arg("-q").arg("-O").arg(tmp_file).arg(default_key.url).status()?;
+        if !status.success() {
+            return Err(eyre!(
+                "trust: failed to download the {} key {}: wget exited with {}",
+                source,
+                default_key.url,
+                status
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(Some(TrustedKey {
+        source: source.to_string(),
+        fingerprints: vec![default_key.fingerprint.to_string()],
+        key_file: key_file.to_string_lossy().to_string(),
+    }))
+}
+
+/// Detached signature published next to a toolchain download.
+pub fn get_signature_url(url: &str) -> String {
+    format!("{}.asc", url)
+}
+
+pub fn download_signature(url: &str, file: &Path) -> Result<()> {
+    let status = Command::new("wget").arg("-q").arg("-O").arg(file).arg(url).status()?;
+    if !status.success() {
+        return Err(eyre!("trust: failed to download the signature {}: wget exited with {}", url, status));
+    }
+    Ok(())
+}
+
+/// Verifies a detached signature on the host, returns the fingerprint of the signing key.
+pub fn verify_detached_signature(key: &TrustedKey, signature: &Path, file: &Path) -> Result<String> {
+    let home = tempdir()?;
+    let keyring_path = home.path().join("trusted-keys.asc");
+    let keyring = build_keyring(key, Path::new(&key.key_file), &keyring_path)?;
+    run_gpg(home.path(), &["--import", &keyring_path.to_string_lossy()])?;
+    let status = run_gpg(
+        home.path(),
+        &["--status-fd", "1", "--verify", &signature.to_string_lossy(), &file.to_string_lossy()],
+    )?;
+    // VALIDSIG ends with the fingerprint of the primary key
+    status
+        .lines()
+        .filter(|line| line.starts_with("[GNUPG:] VALIDSIG "))
+        .filter_map(|line| line.split_whitespace().last())
+        .find(|fingerprint| keyring.fingerprints.iter().any(|declared| declared == fingerprint))
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("{} is not signed by a declared key of {}", file.display(), key.source))
+}
+
+/// Verifies the signature of the tag, or of the tagged commit if the tag itself is not signed,
+/// returns the fingerprint of the signing key. gpg and ssh signatures are supported.
+pub fn verify_git_signature(repo: &Path, tag: &str, signers: &AllowedSigners) -> Result<String> {
+    let keys = fs::read_to_string(&signers.key_file).map_err(|err| {
+        eyre!(
+            "Could not read allowed signers {}: {}",
+            signers.key_file,
+            err
+        )
+    })?;
+    let home = tempdir()?;
+    let mut git_config = vec![];
+    if keys.contains("BEGIN PGP PUBLIC KEY BLOCK") {
+        run_gpg(home.path(), &["--import", &signers.key_file])?;
+    } else {
+        git_config.push("-c".to_string());
+        git_config.push(format!("gpg.ssh.allowedSignersFile={}", signers.key_file));
+    }
+
+    let mut verified = None;
+    for args in [
+        ["verify-tag", "--raw", tag],
+        ["verify-commit", "--raw", "HEAD"],
+    ] {
+        let output = Command::new("git")
+            .current_dir(repo)
+            .env("GNUPGHOME", home.path())
+            .args(&git_config)
+            .args(args)
+            .output()?;
+        if output.status.success() {
+            verified = Some(String::from_utf8_lossy(&output.stderr).to_string());
+            break;
+        }
+    }
+    let output = verified.ok_or_else(|| {
+        eyre!(
+            "Neither tag {} nor the tagged commit carries a valid signature of an allowed signer",
+            tag
+        )
+    })?;
+    // VALIDSIG ends with the fingerprint of the primary key
+    let re = Regex::new(r"(?m)^\[GNUPG:\] VALIDSIG .* ([0-9A-F]{40})$|(SHA256:[A-Za-z0-9+/=]+)")
+        .unwrap();
+    let signer = re
+        .captures(&output)
+        .and_then(|captures| captures.get(1).or(captures.get(2)))
+        .map(|signer| signer.as_str().to_string())
+        .ok_or_else(|| {
+            eyre!(
+                "Could not find the signing key of tag {}: {}",
+                tag,
+                output.trim()
+            )
+        })?;
+    if !signers.get_fingerprints().contains(&signer) {
+        return Err(eyre!(
+            "Tag {} is signed by {}, which is not an allowed signer",
+            tag,
+            signer
+        ));
+    }
+    info!("Tag {} is signed by allowed signer {}", tag, signer);
+    Ok(signer)
+}
+
+/// Chroot command writing the keyring to MICROSOFT_KEYRING, for testbeds the keyring file
+/// cannot be copied into.
+pub fn write_keyring_command(armored: &str) -> String {
+    format!(
+        "mkdir -p /etc/apt/keyrings && echo \"{}\" > {}",
+        armored.trim(),
+        MICROSOFT_KEYRING
+    )
+}
+
+/// Chroot commands adding an apt repository that only accepts the keys of MICROSOFT_KEYRING,
+/// the fingerprints of the keyring are logged, so they are part of the commands.
+pub fn apt_repository_commands(apt_source: &str, fingerprints: &[String]) -> Vec<String> {
+    let signed_apt_source = apt_source.replacen("deb ", &format!("deb [signed-by={}] ", MICROSOFT_KEYRING), 1);
+    vec![
+        format!("echo \"{} is trusted with {}\"", MICROSOFT_KEYRING, fingerprints.join(" ")),
+        format!("echo \"{}\" > /etc/apt/sources.list.d/microsoft-prod.list", signed_apt_source),
+        "apt update -y".to_string(),
+    ]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Generates a signing key in the gpg home, returns its fingerprint.
+    pub fn generate_key(home: &Path, user_id: &str) -> String {
+        run_gpg(
+            home,
+            &[
+                "--pinentry-mode",
+                "loopback",
+                "--passphrase",
+                "",
+                "--quick-gen-key",
+                user_id,
+                "ed25519",
+                "sign",
+                "never",
+            ],
+        )
+        .unwrap();
+        let listing = run_gpg(home, &["--with-colons", "--fingerprint", "--list-keys", user_id]).unwrap();
+        let fpr = listing.lines().find(|line| line.starts_with("fpr:")).unwrap();
+        fpr.split(':').nth(9).unwrap().to_string()
+    }
+
+    pub fn export_key(home: &Path, fingerprint: &str) -> String {
+        run_gpg(home, &["--armor", "--export", fingerprint]).unwrap()
+    }
+
+    #[test]
+    fn test_default_key_is_pinned() {
+        assert_eq!(get_default_key("go", Path::new("/nonexistent")).unwrap(), None);
+        // a cached key file is used as is, build_keyring checks it against the pinned fingerprint
+        let keys_dir = tempdir().unwrap();
+        fs::write(keys_dir.path().join("microsoft.asc"), "").unwrap();
+        let key = get_default_key("microsoft", keys_dir.path()).unwrap().unwrap();
+        assert_eq!(key.get_fingerprints(), vec!["BC528686B50D79E339D3721CEB3E94ADBE1229CF".to_string()]);
+        assert_eq!(Path::new(&key.key_file), get_default_key_file("microsoft", keys_dir.path()));
+    }
+
+    #[test]
+    fn test_build_keyring_refuses_undeclared_keys() {
+        let home = tempdir().unwrap();
+        let declared = generate_key(home.path(), "declared@example.com");
+        let other = generate_key(home.path(), "other@example.com");
+        let key_file = home.path().join("keys.asc");
+        let keyring_path = home.path().join("keyrings/rust.asc");
+
+        let armored = run_gpg(home.path(), &["--armor", "--export", &declared]).unwrap();
+        fs::write(&key_file, armored).unwrap();
+        let key = TrustedKey {
+            source: "rust".to_string(),
+            fingerprints: vec![declared.to_lowercase()],
+            key_file: "keys.asc".to_string(),
+        };
+        let keyring = build_keyring(&key, &key_file, &keyring_path).unwrap();
+        assert_eq!(keyring.fingerprints, vec![declared.clone()]);
+        assert!(fs::read_to_string(&keyring_path).unwrap().contains("BEGIN PGP PUBLIC KEY BLOCK"));
+
+        let armored = run_gpg(home.path(), &["--armor", "--export", &declared, &other]).unwrap();
+        fs::write(&key_file, armored).unwrap();
+        let error = build_keyring(&key, &key_file, &keyring_path).unwrap_err().to_string();
+        assert!(error.contains(&format!("contains key {}, which is not declared for rust", other)));
+    }
+
+    #[test]
+    fn test_verify_git_signature() {
+        let home = tempdir().unwrap();
+        let signer = generate_key(home.path(), "signer@example.com");
+        let other = generate_key(home.path(), "other@example.com");
+        let repo = tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(repo.path())
+                .env("GNUPGHOME", home.path())
+                .args([
+                    "-c",
+                    "user.name=test",
+                    "-c",
+                    "user.email=signer@example.com",
+                ])
+                .args(["-c", &format!("user.signingkey={}", signer)])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        fs::write(repo.path().join("README"), "hello").unwrap();
+        git(&["add", "README"]);
+        git(&["commit", "-q", "-m", "init"]);
+        git(&["tag", "v1.0.0"]);
+        git(&["tag", "-s", "v1.0.1", "-m", "v1.0.1"]);
+
+        let key_file = home.path().join("signers.asc");
+        let armored = run_gpg(home.path(), &["--armor", "--export", &signer]).unwrap();
+        fs::write(&key_file, armored).unwrap();
+        let signers = AllowedSigners {
+            key_file: key_file.to_str().unwrap().to_string(),
+            fingerprints: vec![signer.clone()],
+        };
+        assert_eq!(
+            verify_git_signature(repo.path(), "v1.0.1", &signers).unwrap(),
+            signer
+        );
+        let error = verify_git_signature(repo.path(), "v1.0.0", &signers).unwrap_err();
+        assert!(
+            error.to_string().contains("carries a valid signature"),
+            "{}",
+            error
+        );
+
+        let armored = run_gpg(home.path(), &["--armor", "--export", &signer, &other]).unwrap();
+        fs::write(&key_file, armored).unwrap();
+        let signers = AllowedSigners {
+            key_file: key_file.to_str().unwrap().to_string(),
+            fingerprints: vec![other.clone()],
+        };
+        let error = verify_git_signature(repo.path(), "v1.0.1", &signers).unwrap_err();
+        assert!(
+            error.to_string().contains("which is not an allowed signer"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_apt_repository_commands_pin_keyring() {
+        let commands = apt_repository_commands(
+            "deb https://packages.microsoft.com/debian/12/prod bookworm main",
+            &["BC528686B50D79E339D3721CEB3E94ADBE1229CF".to_string()],
+        );
+        assert_eq!(
+            commands[0],
+            "echo \"/etc/apt/keyrings/pkg-builder-microsoft.asc is trusted with BC528686B50D79E339D3721CEB3E94ADBE1229CF\""
+        );
+        assert_eq!(
+            commands[1],
+            "echo \"deb [signed-by=/etc/apt/keyrings/pkg-builder-microsoft.asc] https://packages.microsoft.com/debian/12/prod bookworm main\" > /etc/apt/sources.list.d/microsoft-prod.list"
+        );
+    }
+}
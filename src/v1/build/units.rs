@@ -0,0 +1,237 @@
+// Checks the systemd units shipped by the built debs, the debs are extracted into a
+// directory and every unit found under lib/systemd/system is checked against it.
+use crate::v1::build::quality::{FindingLevel, UnitFinding};
+use eyre::{eyre, Result};
+use log::warn;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const UNIT_DIRS: &[&str] = &["lib/systemd/system", "usr/lib/systemd/system"];
+const UNIT_EXTENSIONS: &[&str] = &["service", "socket", "timer", "path", "mount", "target"];
+const EXEC_KEYS: &[&str] = &[
+    "ExecStart",
+    "ExecStartPre",
+    "ExecStartPost",
+    "ExecReload",
+    "ExecStop",
+    "ExecStopPost",
+];
+
+fn finding(unit: &str, level: FindingLevel, message: String) -> UnitFinding {
+    UnitFinding {
+        unit: unit.to_string(),
+        level,
+        message,
+    }
+}
+
+/// Extracts the debs into root, as they would be installed.
+pub fn extract_debs(debs: &[PathBuf], root: &Path) -> Result<()> {
+    for deb in debs {
+        let output = Command::new("dpkg-deb").arg("-x").arg(deb).arg(root).output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "Failed to extract {}: {}",
+                deb.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn find_units(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut units = vec![];
+    for unit_dir in UNIT_DIRS {
+        let unit_dir = root.join(unit_dir);
+        if !unit_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&unit_dir)? {
+            let path = entry?.path();
+            let is_unit = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| UNIT_EXTENSIONS.contains(&extension));
+            if is_unit && path.is_file() {
+                units.push(path);
+            }
+        }
+    }
+    units.sort();
+    Ok(units)
+}
+
+// the executable of e.g. "ExecStart=-/usr/bin/geth --datadir /var/lib/geth"
+fn get_executable(command: &str) -> Option<&str> {
+    command
+        .trim()
+        .trim_start_matches(['@', '-', ':', '+', '!'])
+        .split_whitespace()
+        .next()
+}
+
+/// Mistakes systemd only reports when the unit is enabled or started, root holds the extracted debs.
+pub fn check_unit(unit: &str, content: &str, root: &Path) -> Vec<UnitFinding> {
+    let mut findings = vec![];
+    let mut section = String::new();
+    let mut has_install = false;
+    let mut has_install_target = false;
+    let mut has_exec_start = false;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name.to_string();
+            has_install |= section == "Install";
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim();
+        if section == "Install" && ["WantedBy", "RequiredBy", "Alias", "Also"].contains(&key) {
+            has_install_target = true;
+        }
+        if section != "Service" || !EXEC_KEYS.contains(&key) {
+            continue;
+        }
+        has_exec_start |= key == "ExecStart";
+        let Some(executable) = get_executable(value) else {
+            continue;
+        };
+        if !executable.starts_with('/') {
+            findings.push(finding(
+                unit,
+                FindingLevel::Warning,
+                format!("{}={} is not an absolute path", key, executable),
+            ));
+        } else if !root.join(executable.trim_start_matches('/')).exists() {
+            // the host has nothing to do with the testbed, helpers like /bin/mkdir come from
+            // dependencies, the daemon itself has to be shipped
+            let level = match key {
+                "ExecStart" => FindingLevel::Error,
+                _ => FindingLevel::Warning,
+            };
+            findings.push(finding(
+                unit,
+                level,
+                format!("{}={} is not shipped by the package", key, executable),
+            ));
+        }
+    }
+    if unit.ends_with(".service") && !has_exec_start {
+        findings.push(finding(unit, FindingLevel::Error, "no ExecStart in [Service]".to_string()));
+    }
+    if !has_install {
+        findings.push(finding(
+            unit,
+            FindingLevel::Warning,
+            "no [Install] section, the unit cannot be enabled".to_string(),
+        ));
+    } else if !has_install_target {
+        findings.push(finding(
+            unit,
+            FindingLevel::Warning,
+            "[Install] has no WantedBy or RequiredBy, enabling the unit does nothing".to_string(),
+        ));
+    }
+    findings
+}
+
+/// Parses the output of systemd-analyze verify, e.g.
+/// "/tmp/root/lib/systemd/system/geth.service:5: Unknown key 'FooBar' in section [Service], ignoring."
+pub fn parse_systemd_analyze(output: &str) -> Vec<UnitFinding> {
+    let re = Regex::new(r"^(?:\S*/)?([^/\s:]+\.[a-z]+)(?::\d+)?: (.*)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = re.captures(line)?;
+            let message = captures[2].trim();
+            // executables are checked against the extracted debs by check_unit
+            if message.contains("is not executable") {
+                return None;
+            }
+            Some(finding(&captures[1], FindingLevel::Warning, message.to_string()))
+        })
+        .collect()
+}
+
+fn run_systemd_analyze(units: &[PathBuf]) -> Vec<UnitFinding> {
+    match Command::new("systemd-analyze").arg("verify").args(units).output() {
+        Ok(output) => parse_systemd_analyze(&String::from_utf8_lossy(&output.stderr)),
+        Err(err) => {
+            warn!("Skipping systemd-analyze verify, it could not be run: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// Findings of every unit under root, the static checks and systemd-analyze verify.
+pub fn check_units(root: &Path) -> Result<Vec<UnitFinding>> {
+    let units = find_units(root)?;
+    let mut findings = vec![];
+    for unit in units.iter() {
+        let name = unit.file_name().unwrap().to_string_lossy();
+        findings.append(&mut check_unit(&name, &fs::read_to_string(unit)?, root));
+    }
+    if !units.is_empty() {
+        findings.append(&mut run_systemd_analyze(&units));
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_unit() {
+        let root = tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/bin")).unwrap();
+        fs::write(root.path().join("usr/bin/geth"), "").unwrap();
+
+        let unit = "[Unit]\nDescription=geth\n\n[Service]\nExecStart=/usr/bin/geth --datadir /var/lib/geth\n\n[Install]\nWantedBy=multi-user.target\n";
+        assert_eq!(check_unit("geth.service", unit, root.path()), vec![]);
+
+        let unit = "[Service]\nExecStartPre=-mkdir -p /var/lib/geth\nExecStartPre=/bin/chown geth /var/lib/geth\nExecStart=/usr/lib/geth/geth-nonexistent\n[Install]\n";
+        let messages: Vec<String> = check_unit("geth.service", unit, root.path())
+            .into_iter()
+            .map(|finding| format!("{}: {}", finding.level, finding.message))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "warning: ExecStartPre=mkdir is not an absolute path",
+                "warning: ExecStartPre=/bin/chown is not shipped by the package",
+                "error: ExecStart=/usr/lib/geth/geth-nonexistent is not shipped by the package",
+                "warning: [Install] has no WantedBy or RequiredBy, enabling the unit does nothing",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_units_and_parse_systemd_analyze() {
+        let root = tempdir().unwrap();
+        let unit_dir = root.path().join("lib/systemd/system");
+        fs::create_dir_all(&unit_dir).unwrap();
+        fs::write(unit_dir.join("geth.service"), "").unwrap();
+        fs::write(unit_dir.join("README"), "").unwrap();
+        assert_eq!(find_units(root.path()).unwrap(), vec![unit_dir.join("geth.service")]);
+
+        let output = "/tmp/root/lib/systemd/system/geth.service:5: Unknown key 'FooBar' in section [Service], ignoring.\n\
+                      geth.service: Command /usr/bin/geth is not executable: No such file or directory\n";
+        assert_eq!(
+            parse_systemd_analyze(output),
+            vec![finding(
+                "geth.service",
+                FindingLevel::Warning,
+                "Unknown key 'FooBar' in section [Service], ignoring.".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+// Source-only uploads of the last build for dput, e.g. to a Launchpad PPA or to Debian mentors.
+// sbuild writes the _source.changes next to the binary one, it is copied with the files it
+// declares into the upload dir and signed there by debsign, which signs the .dsc as well.
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use crate::v1::build::sbuild::parse_changes_checksums;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const UPLOAD_DIR_NAME: &str = "upload";
+
+const BINARY_EXTENSIONS: [&str; 3] = [".deb", ".udeb", ".ddeb"];
+
+fn get_field<'a>(changes_content: &'a str, name: &str) -> Option<&'a str> {
+    changes_content
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .map(str::trim)
+}
+
+/// Fails unless the changes file is a source-only upload with a .dsc, Launchpad and the NEW
+/// queue reject binaries in source uploads. Returns the declared files.
+pub fn check_source_only(changes_content: &str) -> Result<Vec<String>> {
+    let architecture = get_field(changes_content, "Architecture").unwrap_or_default();
+    if architecture != "source" {
+        return Err(eyre!("Architecture is {}, a source-only upload needs source", architecture));
+    }
+    let names: Vec<String> = parse_changes_checksums(changes_content)?
+        .into_iter()
+        .map(|checksum| checksum.name)
+        .collect();
+    let binaries: Vec<&str> = names
+        .iter()
+        .filter(|name| BINARY_EXTENSIONS.iter().any(|extension| name.ends_with(extension)))
+        .map(String::as_str)
+        .collect();
+    if !binaries.is_empty() {
+        return Err(eyre!("source-only upload declares binaries: {}", binaries.join(", ")));
+    }
+    if !names.iter().any(|name| name.ends_with(".dsc")) {
+        return Err(eyre!("source-only upload declares no .dsc"));
+    }
+    Ok(names)
+}
+
+/// Copies the changes file and the files it declares into out_dir, checked against its
+/// Checksums-Sha256, returns the copied changes file.
+pub fn copy_upload(changes_file: &Path, out_dir: &Path) -> Result<PathBuf> {
+    let content = fs::read_to_string(changes_file)
+        .map_err(|err| eyre!("Could not read {}, build the package first: {}", changes_file.display(), err))?;
+    check_source_only(&content)?;
+    let source_dir = changes_file.parent().unwrap();
+    // a previous upload may have been signed with another key
+    if out_dir.exists() {
+        fs::remove_dir_all(out_dir)?;
+    }
+    fs::create_dir_all(out_dir)?;
+    for checksum in parse_changes_checksums(&content)? {
+        let file = source_dir.join(&checksum.name);
+        let hash = calculate_sha256(open_for_hashing(&file)?)?;
+        if hash != checksum.hash || fs::metadata(&file)?.len() != checksum.size {
+            return Err(eyre!("{} does not match {}", file.display(), changes_file.display()));
+        }
+        fs::copy(&file, out_dir.join(&checksum.name))?;
+    }
+    let upload_file = out_dir.join(changes_file.file_name().unwrap());
+    fs::copy(changes_file, &upload_file)?;
+    Ok(upload_file)
+}
+
+/// Signs the upload with debsign, which updates the checksums of the re-signed .dsc.
+pub fn sign_upload(changes_file: &Path, key: &str) -> Result<()> {
+    info!("Signing {} with key {}", changes_file.display(), key);
+    let output = Command::new("debsign")
+        .arg(format!("-k{}", key))
+        .arg("--re-sign")
+        .arg(changes_file)
+        .output()
+        .map_err(|err| eyre!("Failed to run debsign, install devscripts: {}", err))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "debsign of {} failed: {}",
+            changes_file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn changes(architecture: &str, files: &[(&str, &str)]) -> String {
+        let mut content = format!("Format: 1.8\nSource: hello-world\nArchitecture: {}\nChecksums-Sha256:\n", architecture);
+        for (name, file_content) in files {
+            let hash = calculate_sha256(file_content.as_bytes()).unwrap();
+            content.push_str(&format!(" {} {} {}\n", hash, file_content.len(), name));
+        }
+        content
+    }
+
+    #[test]
+    fn test_source_only_upload() {
+        let files = [
+            ("hello-world_1.0.0-1.dsc", "dsc"),
+            ("hello-world_1.0.0.orig.tar.gz", "orig"),
+            ("hello-world_1.0.0-1.debian.tar.xz", "debian"),
+            ("hello-world_1.0.0-1_source.buildinfo", "buildinfo"),
+        ];
+        let binary = changes("source amd64", &[("hello-world_1.0.0-1_amd64.deb", "deb")]);
+        let err = check_source_only(&binary).unwrap_err().to_string();
+        assert_eq!(err, "Architecture is source amd64, a source-only upload needs source");
+        let binary = changes("source", &[files[0], ("hello-world_1.0.0-1_amd64.deb", "deb")]);
+        let err = check_source_only(&binary).unwrap_err().to_string();
+        assert_eq!(err, "source-only upload declares binaries: hello-world_1.0.0-1_amd64.deb");
+
+        let dir = tempdir().unwrap();
+        let build_dir = dir.path().join("hello-world-1.0.0-1");
+        fs::create_dir_all(&build_dir).unwrap();
+        for (name, content) in files {
+            fs::write(build_dir.join(name), content).unwrap();
+        }
+        let changes_file = build_dir.join("hello-world_1.0.0-1_source.changes");
+        fs::write(&changes_file, changes("source", &files)).unwrap();
+        let out_dir = build_dir.join(UPLOAD_DIR_NAME);
+        let upload_file = copy_upload(&changes_file, &out_dir).unwrap();
+        assert_eq!(upload_file, out_dir.join("hello-world_1.0.0-1_source.changes"));
+        assert_eq!(fs::read_to_string(out_dir.join("hello-world_1.0.0.orig.tar.gz")).unwrap(), "orig");
+
+        fs::write(build_dir.join("hello-world_1.0.0-1.dsc"), "changed").unwrap();
+        let err = copy_upload(&changes_file, &out_dir).unwrap_err().to_string();
+        let dsc_file = build_dir.join("hello-world_1.0.0-1.dsc");
+        assert_eq!(err, format!("{} does not match {}", dsc_file.display(), changes_file.display()));
+    }
+}
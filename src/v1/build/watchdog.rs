@@ -0,0 +1,214 @@
+// Watches the output of a stage for stalls, e.g. a qemu testbed hanging at boot or a link
+// step swapping for hours. A stage printing nothing for stall_minutes is reported with its
+// last lines and the output of the diagnostic command, after timeout_minutes it is killed.
+use crate::v1::pkg_config::WatchdogConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DEFAULT_STALL_MINUTES: u64 = 30;
+const DEFAULT_TAIL_LINES: usize = 20;
+// upper bound of the delay between a stall and its warning
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchdog {
+    pub stall_after: Duration,
+    pub tail_lines: usize,
+    /// run through sh, the pid of the stalled process is in $STALLED_PID
+    pub diagnostic_command: Option<String>,
+    pub timeout: Option<Duration>,
+}
+
+struct Activity {
+    last_output: Instant,
+    tail: VecDeque<String>,
+    tail_lines: usize,
+    stalled: bool,
+    done: bool,
+    timed_out: bool,
+}
+
+/// Handed to the readers of the output, every line resets the stall timer.
+#[derive(Clone)]
+pub struct Heartbeat(Arc<Mutex<Activity>>);
+
+impl Heartbeat {
+    pub fn beat(&self, line: &str) {
+        let mut activity = self.0.lock().unwrap();
+        activity.last_output = Instant::now();
+        activity.tail.push_back(line.to_string());
+        while activity.tail.len() > activity.tail_lines {
+            activity.tail.pop_front();
+        }
+    }
+}
+
+pub struct Watch {
+    heartbeat: Heartbeat,
+    stage: String,
+    timeout: Option<Duration>,
+    thread: JoinHandle<()>,
+}
+
+impl Watch {
+    pub fn heartbeat(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+
+    /// Stops watching once the process exited, errors when it was killed at the timeout.
+    pub fn stop(self) -> Result<()> {
+        self.heartbeat.0.lock().unwrap().done = true;
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+        if self.heartbeat.0.lock().unwrap().timed_out {
+            return Err(eyre!(
+                "{} was killed after {} minutes",
+                self.stage,
+                self.timeout.unwrap_or_default().as_secs() / 60
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Watchdog {
+    pub fn new(config: &WatchdogConfig) -> Self {
+        Watchdog {
+            stall_after: Duration::from_secs(config.stall_minutes.unwrap_or(DEFAULT_STALL_MINUTES) * 60),
+            tail_lines: config.tail_lines.unwrap_or(DEFAULT_TAIL_LINES),
+            diagnostic_command: config.diagnostic_command.clone(),
+            timeout: config.timeout_minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Watches the child until Watch::stop, the lines it prints are passed to its heartbeat.
+    pub fn watch(&self, child: &Child, stage: &str) -> Watch {
+        let heartbeat = Heartbeat(Arc::new(Mutex::new(Activity {
+            last_output: Instant::now(),
+            tail: VecDeque::new(),
+            tail_lines: self.tail_lines,
+            stalled: false,
+            done: false,
+            timed_out: false,
+        })));
+        let watchdog = self.clone();
+        let pid = child.id();
+        let thread_heartbeat = heartbeat.clone();
+        let thread_stage = stage.to_string();
+        let thread = std::thread::spawn(move || watchdog.run(pid, &thread_stage, &thread_heartbeat));
+        Watch {
+            heartbeat,
+            stage: stage.to_string(),
+            timeout: self.timeout,
+            thread,
+        }
+    }
+
+    fn run(&self, pid: u32, stage: &str, heartbeat: &Heartbeat) {
+        let started = Instant::now();
+        let poll_interval = (self.stall_after / 4).min(MAX_POLL_INTERVAL);
+        loop {
+            std::thread::park_timeout(poll_interval);
+            let mut activity = heartbeat.0.lock().unwrap();
+            if activity.done {
+                return;
+            }
+            if self.timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                activity.timed_out = true;
+                drop(activity);
+                warn!("{}: no result after {} minutes, killing it", stage, started.elapsed().as_secs() / 60);
+                kill(pid);
+                return;
+            }
+            let silent_for = activity.last_output.elapsed();
+            if silent_for < self.stall_after {
+                if activity.stalled {
+                    info!("{}: output resumed", stage);
+                    activity.stalled = false;
+                }
+                continue;
+            }
+            if activity.stalled {
+                continue;
+            }
+            activity.stalled = true;
+            let tail: Vec<String> = activity.tail.iter().cloned().collect();
+            drop(activity);
+            warn!(
+                "{}: no output for {} minutes, it may be stalled, last lines:\n{}",
+                stage,
+                silent_for.as_secs() / 60,
+                tail.join("\n")
+            );
+            if let Some(command) = &self.diagnostic_command {
+                warn!("{}: {}", stage, run_diagnostic(command, pid));
+            }
+        }
+    }
+}
+
+fn run_diagnostic(command: &str, pid: u32) -> String {
+    match Command::new("sh")
+        .args(["-c", command])
+        .env("STALLED_PID", pid.to_string())
+        .output()
+    {
+        Ok(output) => format!(
+            "{}:\n{}{}",
+            command,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(err) => format!("failed to run {}: {}", command, err),
+    }
+}
+
+// sbuild and autopkgtest tear down their chroot or testbed on TERM
+fn kill(pid: u32) {
+    let killed = Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .status()
+        .is_ok_and(|status| status.success());
+    if !killed {
+        warn!("Failed to kill {}, stages run through sudo can only be killed by root", pid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    #[test]
+    fn test_watchdog_kills_stalled_process() {
+        let watchdog = Watchdog {
+            stall_after: Duration::from_millis(200),
+            tail_lines: 2,
+            diagnostic_command: Some("echo $STALLED_PID".to_string()),
+            timeout: Some(Duration::from_secs(1)),
+        };
+        let mut child = Command::new("sh")
+            .args(["-c", "echo one; echo two; echo three; exec sleep 30"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let watch = watchdog.watch(&child, "autopkgtest");
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            watch.heartbeat().beat(&line.unwrap());
+        }
+        assert_eq!(watch.heartbeat.0.lock().unwrap().tail, ["two", "three"]);
+        assert!(!child.wait().unwrap().success());
+        assert_eq!(watch.stop().unwrap_err().to_string(), "autopkgtest was killed after 0 minutes");
+
+        let mut child = Command::new("true").spawn().unwrap();
+        let watch = watchdog.watch(&child, "lintian");
+        child.wait().unwrap();
+        watch.stop().unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+// Revision bumps for packaging only changes, the upstream version stays the same.
+use crate::v1::build::dir_setup::parse_changelog_version;
+use crate::v1::pkg_config::PkgConfig;
+use eyre::{eyre, Result};
+use log::info;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Increments the trailing number of a revision, e.g. 1 -> 2, 0ubuntu1 -> 0ubuntu2
+pub fn bump_revision(revision: &str) -> Result<String> {
+    let digits = revision
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    if digits == 0 {
+        return Err(eyre!(
+            "Cannot bump revision {}, it does not end with a number",
+            revision
+        ));
+    }
+    let (prefix, number) = revision.split_at(revision.len() - digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|err| eyre!("Cannot bump revision {}: {}", revision, err))?;
+    Ok(format!("{}{}", prefix, number + 1))
+}
+
+// rewrites the value in place, so comments and formatting of the config survive
+fn set_config_revision(config_file: &Path, revision: &str) -> Result<()> {
+    let content = fs::read_to_string(config_file)?;
+    let revision_line = Regex::new(r#"(?m)^(\s*revision_number\s*=\s*)"[^"]*""#).unwrap();
+    if !revision_line.is_match(&content) {
+        return Err(eyre!(
+            "Could not find revision_number in {}",
+            config_file.display()
+        ));
+    }
+    let content = revision_line.replace(&content, |captures: &regex::Captures| {
+        format!("{}\"{}\"", &captures[1], revision)
+    });
+    fs::write(config_file, content.as_ref())?;
+    Ok(())
+}
+
+/// debcrafter picks up <spec>.changelog next to the spec file
+pub fn get_changelog_path(config_root: &Path, spec_file: &str) -> PathBuf {
+    config_root.join(spec_file).with_extension("changelog")
+}
+
+/// Adds a new changelog entry on top, the maintainer is taken from the latest entry.
+/// changes are the lines of the entry body, e.g. "  * Rebuild with packaging changes."
+pub fn prepend_changelog_entry(
+    changelog_path: &Path,
+    package_name: &str,
+    debian_version: &str,
+    codename: &str,
+    changes: &[String],
+    date: &str,
+) -> Result<()> {
+    let changelog = fs::read_to_string(changelog_path).map_err(|err| {
+        eyre!(
+            "Could not read changelog {}: {}",
+            changelog_path.display(),
+            err
+        )
+    })?;
+    let maintainer = changelog
+        .lines()
+        .find_map(|line| line.strip_prefix(" -- "))
+        .and_then(|trailer| trailer.split("  ").next())
+        .ok_or_else(|| {
+            eyre!(
+                "Could not find maintainer in changelog {}",
+                changelog_path.display()
+            )
+        })?;
+    let entry = format!(
+        "{} ({}) {}; urgency=medium\n\n{}\n\n -- {}  {}\n\n",
+        package_name,
+        debian_version,
+        codename,
+        changes.join("\n"),
+        maintainer,
+        date
+    );
+    fs::write(changelog_path, entry + &changelog)?;
+    Ok(())
+}
+
+/// Bumps revision_number in the config file and adds the matching changelog entry,
+/// returns the new debian version.
+pub fn bump_package_revision(
+    config: &PkgConfig,
+    config_file: &Path,
+    date: &str,
+) -> Result<String> {
+    let package_fields = &config.package_fields;
+    let config_root = config_file.parent().unwrap();
+    let changelog_path = get_changelog_path(config_root, &package_fields.spec_file);
+    let current_version = parse_changelog_version(&fs::read_to_string(&changelog_path)?);
+    if current_version != Some(package_fields.get_debian_version()) {
+        return Err(eyre!(
+            "Changelog {} is not at the configured version {}, refusing to bump.",
+            changelog_path.display(),
+            package_fields.get_debian_version()
+        ));
+    }
+
+    let mut bumped = package_fields.clone();
+    bumped.revision_number = bump_revision(&package_fields.revision_number)?;
+    prepend_changelog_entry(
+        &changelog_path,
+        &package_fields.package_name,
+        &bumped.get_debian_version(),
+        &config.build_env.codename,
+        &["  * Rebuild with packaging changes.".to_string()],
+        date,
+    )?;
+    set_config_revision(config_file, &bumped.revision_number)?;
+    info!(
+        "Bumped {} from {} to {}",
+        package_fields.package_name,
+        package_fields.get_debian_version(),
+        bumped.get_debian_version()
+    );
+    Ok(bumped.get_debian_version())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bump_revision() {
+        assert_eq!(bump_revision("1").unwrap(), "2");
+        assert_eq!(bump_revision("9").unwrap(), "10");
+        assert_eq!(bump_revision("0ubuntu1").unwrap(), "0ubuntu2");
+        assert!(bump_revision("1ubuntu").is_err());
+    }
+
+    #[test]
+    fn test_bump_package_revision() {
+        let dir = tempdir().unwrap();
+        let config_file = dir.path().join("pkg-builder.toml");
+        fs::write(
+            &config_file,
+            "[package_fields]\n# bumped by hand before\nrevision_number = \"1\" # keep\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("hello-world.changelog"),
+            "hello-world (1:1.0.0-1) bookworm; urgency=medium\n\n  * Initial packaging\n\n -- John Doe <johndoe@example.com>  Tue, 17 Oct 2023 13:19:27 +0700\n",
+        )
+        .unwrap();
+        let mut config = PkgConfig::default();
+        config.package_fields.spec_file = "hello-world.sss".to_string();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.package_fields.epoch = Some("1".to_string());
+        config.package_fields.version_number = "1.0.0".to_string();
+        config.package_fields.revision_number = "1".to_string();
+        config.build_env.codename = "bookworm".to_string();
+
+        let date = "Wed, 05 Jun 2024 10:00:00 +0000";
+        let version = bump_package_revision(&config, &config_file, date).unwrap();
+        assert_eq!(version, "1:1.0.0-2");
+        assert_eq!(
+            fs::read_to_string(&config_file).unwrap(),
+            "[package_fields]\n# bumped by hand before\nrevision_number = \"2\" # keep\n"
+        );
+        let changelog = fs::read_to_string(dir.path().join("hello-world.changelog")).unwrap();
+        assert!(changelog.starts_with(
+            "hello-world (1:1.0.0-2) bookworm; urgency=medium\n\n  * Rebuild with packaging changes.\n\n -- John Doe <johndoe@example.com>  Wed, 05 Jun 2024 10:00:00 +0000\n\nhello-world (1:1.0.0-1)"
+        ));
+
+        // the changelog is ahead of the config now
+        assert!(bump_package_revision(&config, &config_file, date).is_err());
+    }
+}
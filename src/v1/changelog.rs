@@ -0,0 +1,250 @@
+// Changelog entries for new upstream releases, the body is drafted from the upstream git
+// history between the previously packaged tag and the configured one.
+use crate::v1::build::dir_setup::parse_changelog_version;
+use crate::v1::bump::{get_changelog_path, prepend_changelog_entry};
+use crate::v1::pkg_config::{PackageType, PkgConfig};
+use crate::v1::uversion::mangle_uversion;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// debian policy recommends changelog lines below 80 columns
+const CHANGELOG_WIDTH: usize = 79;
+
+pub struct Commit {
+    pub author: String,
+    pub subject: String,
+}
+
+fn git(repo_dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git").current_dir(repo_dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Commits after from up to to, oldest first, merges are left out.
+pub fn get_commits(repo_dir: &Path, from: &str, to: &str) -> Result<Vec<Commit>> {
+    let range = format!("{}..{}", from, to);
+    let log = git(repo_dir, &["log", "--no-merges", "--reverse", "--format=%an%x1f%s", &range])?;
+    Ok(log
+        .lines()
+        .filter_map(|line| line.split_once('\x1f'))
+        .map(|(author, subject)| Commit {
+            author: author.to_string(),
+            subject: subject.trim().to_string(),
+        })
+        .collect())
+}
+
+/// The tag that mangles to the upstream version of the previous changelog entry.
+pub fn find_previous_tag(repo_dir: &Path, upstream_version: &str, rules: &[String]) -> Result<String> {
+    let tags = git(repo_dir, &["tag", "--list"])?;
+    tags.lines()
+        .find(|tag| mangle_uversion(tag, rules).is_ok_and(|version| version == upstream_version))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            eyre!(
+                "No tag of the previously packaged version {} found, pass it with --since",
+                upstream_version
+            )
+        })
+}
+
+fn wrap(text: &str, first_prefix: &str, prefix: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = first_prefix.to_string();
+    let mut has_words = false;
+    for word in text.split_whitespace() {
+        if has_words && line.len() + 1 + word.len() > CHANGELOG_WIDTH {
+            lines.push(line);
+            line = prefix.to_string();
+        } else if has_words {
+            line.push(' ');
+        }
+        line.push_str(word);
+        has_words = true;
+    }
+    lines.push(line);
+    lines
+}
+
+/// Entry body with one bullet per commit, grouped by author in order of their first commit.
+pub fn format_changes(commits: &[Commit]) -> Vec<String> {
+    let mut authors: Vec<&str> = vec![];
+    for commit in commits {
+        if !authors.contains(&commit.author.as_str()) {
+            authors.push(&commit.author);
+        }
+    }
+    let mut changes = vec![];
+    for author in authors {
+        if !changes.is_empty() {
+            changes.push(String::new());
+        }
+        changes.push(format!("  [ {} ]", author));
+        for commit in commits.iter().filter(|commit| commit.author == author) {
+            changes.append(&mut wrap(&commit.subject, "  * ", "    "));
+        }
+    }
+    changes
+}
+
+/// Adds the changelog entry of the configured version, drafted from the upstream commits
+/// since the previously packaged tag, returns the number of commits.
+pub fn add_changelog_from_git(config: &PkgConfig, config_file: &Path, since: Option<&str>, date: &str) -> Result<usize> {
+    let PackageType::Git(git_config) = &config.package_type else {
+        return Err(eyre!("Changelog entries are drafted from git, the package type is not git."));
+    };
+    let package_fields = &config.package_fields;
+    let changelog_path = get_changelog_path(config_file.parent().unwrap(), &package_fields.spec_file);
+    let previous_version = parse_changelog_version(&fs::read_to_string(&changelog_path)?)
+        .ok_or_else(|| eyre!("Could not find the previous version in {}", changelog_path.display()))?;
+    if previous_version == package_fields.get_debian_version() {
+        return Err(eyre!(
+            "Changelog {} already has an entry for {}, update version_number first.",
+            changelog_path.display(),
+            previous_version
+        ));
+    }
+
+    let repo = tempfile::tempdir()?;
+    info!("Cloning {} for its history", git_config.git_url);
+    let output = Command::new("git")
+        .args(["clone", "--quiet", "--bare", "--filter=blob:none", &git_config.git_url])
+        .arg(repo.path())
+        .output()?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to clone {}: {}",
+            git_config.git_url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let previous_tag = match since {
+        Some(since) => since.to_string(),
+        None => {
+            // 1:1.0.0-1 -> 1.0.0
+            let upstream_version = previous_version.split_once(':').map_or(previous_version.as_str(), |(_, version)| version);
+            let upstream_version = upstream_version.rsplit_once('-').map_or(upstream_version, |(version, _)| version);
+            let rules = git_config
+                .uversion_mangle
+                .clone()
+                .unwrap_or_else(|| vec!["s/^v//".to_string()]);
+            find_previous_tag(repo.path(), upstream_version, &rules)?
+        }
+    };
+    let commits = get_commits(repo.path(), &previous_tag, &git_config.git_tag)?;
+    let changes = match commits.is_empty() {
+        true => vec![format!("  * New upstream release {}.", git_config.git_tag)],
+        false => format_changes(&commits),
+    };
+    prepend_changelog_entry(
+        &changelog_path,
+        &package_fields.package_name,
+        &package_fields.get_debian_version(),
+        &config.build_env.codename,
+        &changes,
+        date,
+    )?;
+    info!(
+        "Drafted changelog entry {} from {} commits since {} in {}, review it before packaging",
+        package_fields.get_debian_version(),
+        commits.len(),
+        previous_tag,
+        changelog_path.display()
+    );
+    Ok(commits.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::GitPackageTypeConfig;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_format_changes_groups_and_wraps() {
+        let commit = |author: &str, subject: &str| Commit {
+            author: author.to_string(),
+            subject: subject.to_string(),
+        };
+        let commits = vec![
+            commit("Alice", "Fix peer scoring"),
+            commit("Bob", "Add metrics for the transaction pool, the pending and queued transactions are reported per account"),
+            commit("Alice", "Bump go to 1.22"),
+        ];
+        assert_eq!(
+            format_changes(&commits),
+            vec![
+                "  [ Alice ]",
+                "  * Fix peer scoring",
+                "  * Bump go to 1.22",
+                "",
+                "  [ Bob ]",
+                "  * Add metrics for the transaction pool, the pending and queued transactions",
+                "    are reported per account",
+            ]
+        );
+        assert!(format_changes(&commits).iter().all(|line| line.len() <= CHANGELOG_WIDTH));
+    }
+
+    #[test]
+    fn test_add_changelog_from_git() {
+        let upstream = tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(upstream.path())
+                .env("GIT_CONFIG_GLOBAL", "/dev/null")
+                .args(["-c", "user.name=Alice", "-c", "user.email=alice@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "Initial release"]);
+        git(&["tag", "v1.0.0"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "Fix peer scoring"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "Add metrics"]);
+        git(&["tag", "v1.1.0"]);
+
+        let dir = tempdir().unwrap();
+        let config_file = dir.path().join("pkg-builder.toml");
+        let changelog_path = dir.path().join("hello-world.changelog");
+        fs::write(
+            &changelog_path,
+            "hello-world (1:1.0.0-1) bookworm; urgency=medium\n\n  * Initial packaging\n\n -- John Doe <johndoe@example.com>  Tue, 17 Oct 2023 13:19:27 +0700\n",
+        )
+        .unwrap();
+        let mut config = PkgConfig::default();
+        config.package_fields.spec_file = "hello-world.sss".to_string();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.package_fields.epoch = Some("1".to_string());
+        config.package_fields.version_number = "1.1.0".to_string();
+        config.package_fields.revision_number = "1".to_string();
+        config.build_env.codename = "bookworm".to_string();
+        config.package_type = PackageType::Git(GitPackageTypeConfig {
+            git_url: format!("file://{}", upstream.path().display()),
+            git_tag: "v1.1.0".to_string(),
+            ..GitPackageTypeConfig::default()
+        });
+
+        let date = "Wed, 05 Jun 2024 10:00:00 +0000";
+        assert_eq!(add_changelog_from_git(&config, &config_file, None, date).unwrap(), 2);
+        let changelog = fs::read_to_string(&changelog_path).unwrap();
+        assert!(changelog.starts_with(
+            "hello-world (1:1.1.0-1) bookworm; urgency=medium\n\n  [ Alice ]\n  * Fix peer scoring\n  * Add metrics\n\n -- John Doe <johndoe@example.com>  Wed, 05 Jun 2024 10:00:00 +0000\n\nhello-world (1:1.0.0-1)"
+        ));
+
+        // the entry of the configured version exists now
+        assert!(add_changelog_from_git(&config, &config_file, None, date).is_err());
+    }
+}
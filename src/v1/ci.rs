@@ -0,0 +1,268 @@
+// Generates a CI pipeline building the package on push and tags,
+// the steps follow the packaging jobs of pkg-builder's own workflow.
+use crate::v1::build::dir_setup::BuildPaths;
+use crate::v1::distribution::get_distribution;
+use crate::v1::pkg_config::PkgConfig;
+use clap::ValueEnum;
+use eyre::Result;
+use std::path::Path;
+
+const SBUILD_DEB_URL: &str =
+    "https://github.com/eth-pkg/sbuild-ubuntu/releases/download/0.85-6-1/sbuild_0.85.6_all.deb";
+const LIBSBUILD_DEB_URL: &str =
+    "https://github.com/eth-pkg/sbuild-ubuntu/releases/download/0.85-6-1/libsbuild-perl_0.85.6_all.deb";
+const PKG_BUILDER_GIT_URL: &str = "https://github.com/eth-pkg/pkg-builder";
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CiProvider {
+    Github,
+    Gitlab,
+}
+
+impl CiProvider {
+    /// Location of the pipeline, relative to the repository root.
+    pub fn get_pipeline_file(&self) -> &'static str {
+        match self {
+            CiProvider::Github => ".github/workflows/pkg-builder.yml",
+            CiProvider::Gitlab => ".gitlab-ci.yml",
+        }
+    }
+}
+
+/// Values of the config the pipeline depends on.
+struct PipelineContext {
+    // directory of pkg-builder.toml relative to the repository root
+    working_directory: String,
+    suite: String,
+    arch: String,
+    pkg_builder_version: String,
+    chroot_cache_dir: String,
+    chroot_cache_file: String,
+    artifacts_dir: String,
+    run_piuparts: bool,
+    run_autopkgtest: bool,
+}
+
+// ~ is not expanded inside quotes, the generated scripts use $HOME instead
+fn home_to_env(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("$HOME/{}", rest),
+        None => path.to_string(),
+    }
+}
+
+impl PipelineContext {
+    fn new(config: &PkgConfig, working_directory: &str) -> Result<Self> {
+        let build_env = &config.build_env;
+        // distinct for derivatives sharing the suite, e.g. raspbian bookworm
+        let suite = get_distribution(&build_env.codename)?.info().key.to_string();
+        let workdir = build_env
+            .workdir
+            .clone()
+            .unwrap_or(format!("~/.pkg-builder/packages/{}", build_env.codename));
+        let paths = BuildPaths::new(Path::new(&workdir), &config.package_fields);
+        let chroot_cache_dir = build_env
+            .sbuild_cache_dir
+            .clone()
+            .unwrap_or("~/.cache/sbuild".to_string());
+        Ok(PipelineContext {
+            working_directory: working_directory.to_string(),
+            chroot_cache_file: format!("{}-{}.tar.gz", suite, build_env.arch.get_build_arch()),
+            suite,
+            arch: build_env.arch.get_build_arch().to_string(),
+            pkg_builder_version: build_env.pkg_builder_version.clone(),
+            chroot_cache_dir,
+            artifacts_dir: home_to_env(&paths.artifacts_dir.to_string_lossy()),
+            run_piuparts: build_env.run_piuparts == Some(true),
+            run_autopkgtest: build_env.run_autopkgtest == Some(true),
+        })
+    }
+
+    fn setup_script(&self) -> Vec<String> {
+        vec![
+            "sudo apt-get update".to_string(),
+            "sudo apt-get install -y debhelper schroot ubuntu-dev-tools piuparts autopkgtest vmdb2 qemu-system-x86 genisoimage".to_string(),
+            "sudo apt-get install -y pkg-config libssl-dev uidmap libfilesys-df-perl libmime-lite-perl".to_string(),
+            format!("wget {}", SBUILD_DEB_URL),
+            format!("wget {}", LIBSBUILD_DEB_URL),
+            "sudo dpkg -i sbuild_0.85.6_all.deb libsbuild-perl_0.85.6_all.deb || true".to_string(),
+            "sudo sbuild-adduser `whoami`".to_string(),
+            // sbuild runs with --chroot-mode=unshare
+            "sudo sysctl -w kernel.unprivileged_userns_clone=1 || true".to_string(),
+            "sudo sysctl -w kernel.apparmor_restrict_unprivileged_userns=0 || true".to_string(),
+            format!(
+                "cargo install --locked --git {} --tag v{} pkg-builder",
+                PKG_BUILDER_GIT_URL, self.pkg_builder_version
+            ),
+        ]
+    }
+
+    fn package_script(&self) -> Vec<String> {
+        let mut script = vec![
+            format!("cd {}", self.working_directory),
+            "pkg-builder package --run-piuparts false --run-autopkgtest false".to_string(),
+        ];
+        if self.run_piuparts {
+            script.push("pkg-builder piuparts".to_string());
+        }
+        if self.run_autopkgtest {
+            script.push("sudo cp -R ${HOME}/.pkg-builder /root".to_string());
+            script.push("sudo $(which pkg-builder) autopkgtest".to_string());
+        }
+        script
+    }
+
+    fn collect_script(&self, out_dir: &str) -> Vec<String> {
+        let mut script = vec![format!("mkdir -p {}", out_dir)];
+        for pattern in ["*.deb", "*.changes", "*.dsc", "*.manifest.toml", "*.quality.json"] {
+            script.push(format!(
+                "cp \"{}\"/{} {}/ || true",
+                self.artifacts_dir, pattern, out_dir
+            ));
+        }
+        script
+    }
+}
+
+fn push_run_step(workflow: &mut String, name: &str, script: &[String]) {
+    workflow.push_str(&format!("      - name: {}\n        run: |\n", name));
+    for line in script {
+        workflow.push_str(&format!("          {}\n", line));
+    }
+    workflow.push('\n');
+}
+
+fn render_github(context: &PipelineContext) -> String {
+    let mut workflow = format!(
+        "name: pkg-builder\n\n\
+         on:\n  push:\n    branches:\n      - main\n    tags:\n      - '*'\n\n\
+         jobs:\n  package_{suite}_{arch}:\n    runs-on: ubuntu-24.04\n    steps:\n\
+         \x20     - uses: actions/checkout@v4\n\n",
+        suite = context.suite,
+        arch = context.arch
+    );
+    push_run_step(&mut workflow, "Sbuild setup", &context.setup_script());
+    workflow.push_str(&format!(
+        "      - name: Restore chroot\n        id: chroot-cache\n        uses: actions/cache@v4\n        with:\n          path: {}/{}\n          key: sbuild-{}-{}-${{{{ hashFiles('{}/pkg-builder.toml') }}}}\n\n",
+        context.chroot_cache_dir,
+        context.chroot_cache_file,
+        context.suite,
+        context.arch,
+        context.working_directory
+    ));
+    workflow.push_str("      - name: Create chroot env\n        if: steps.chroot-cache.outputs.cache-hit != 'true'\n        run: |\n");
+    workflow.push_str(&format!(
+        "          cd {}\n          pkg-builder env create\n\n",
+        context.working_directory
+    ));
+    push_run_step(&mut workflow, "Package", &context.package_script());
+    push_run_step(&mut workflow, "Collect artifacts", &context.collect_script("artifacts"));
+    workflow.push_str(&format!(
+        "      - uses: actions/upload-artifact@v4\n        with:\n          name: {}-{}\n          path: artifacts/\n",
+        context.suite, context.arch
+    ));
+    workflow
+}
+
+fn push_script(pipeline: &mut String, key: &str, script: &[String]) {
+    pipeline.push_str(&format!("  {}:\n", key));
+    for line in script {
+        pipeline.push_str(&format!("    - {}\n", line));
+    }
+}
+
+fn render_gitlab(context: &PipelineContext) -> String {
+    let cache_file = format!("{}/{}", home_to_env(&context.chroot_cache_dir), context.chroot_cache_file);
+    let mut pipeline = format!(
+        "# sbuild needs user namespaces, use a runner allowing them, e.g. a privileged docker executor\n\
+         package_{}_{}:\n  image: ubuntu:24.04\n\
+         \x20 rules:\n    - if: $CI_COMMIT_BRANCH == $CI_DEFAULT_BRANCH\n    - if: $CI_COMMIT_TAG\n\
+         \x20 cache:\n    key: sbuild-{}-{}\n    paths:\n      - .sbuild-cache/\n",
+        context.suite, context.arch, context.suite, context.arch
+    );
+    let mut script = vec![
+        "apt-get update && apt-get install -y sudo wget cargo git".to_string(),
+    ];
+    script.append(&mut context.setup_script());
+    script.push(format!("mkdir -p \"$(dirname \"{}\")\"", cache_file));
+    // gitlab only caches paths inside of the project directory
+    script.push(format!(
+        "cp .sbuild-cache/{} \"{}\" || (cd {} && pkg-builder env create)",
+        context.chroot_cache_file, cache_file, context.working_directory
+    ));
+    script.append(&mut context.package_script());
+    script.push("cd $CI_PROJECT_DIR".to_string());
+    script.push(format!("mkdir -p .sbuild-cache && cp \"{}\" .sbuild-cache/", cache_file));
+    script.append(&mut context.collect_script("artifacts"));
+    push_script(&mut pipeline, "script", &script);
+    pipeline.push_str("  artifacts:\n    paths:\n      - artifacts/\n");
+    pipeline
+}
+
+/// Renders the pipeline for the package whose pkg-builder.toml is in working_directory.
+pub fn render_pipeline(provider: CiProvider, config: &PkgConfig, working_directory: &str) -> Result<String> {
+    let context = PipelineContext::new(config, working_directory)?;
+    Ok(match provider {
+        CiProvider::Github => render_github(&context),
+        CiProvider::Gitlab => render_gitlab(&context),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::parse;
+
+    fn config() -> PkgConfig {
+        let config_str = r#"
+[package_fields]
+spec_file = "hello-world.sss"
+package_name = "hello-world"
+version_number = "1.0.0"
+revision_number = "1"
+homepage = "https://github.com/eth-pkg/pkg-builder"
+
+[package_type]
+package_type = "virtual"
+
+[build_env]
+codename = "noble numbat"
+arch = "amd64"
+pkg_builder_version = "0.2.8"
+debcrafter_version = "8189263"
+run_lintian = true
+run_piuparts = false
+run_autopkgtest = true
+lintian_version = "2.116.3"
+piuparts_version = "1.1.7"
+autopkgtest_version = "5.28"
+sbuild_version = "0.85.6"
+workdir = "~/.pkg-builder/packages/noble"
+"#;
+        parse::<PkgConfig>(config_str).unwrap()
+    }
+
+    #[test]
+    fn test_render_github_pipeline() {
+        let workflow = render_pipeline(CiProvider::Github, &config(), "examples/noble/virtual").unwrap();
+        assert!(workflow.contains("  package_noble_amd64:\n    runs-on: ubuntu-24.04\n"));
+        assert!(workflow.contains("          path: ~/.cache/sbuild/noble-amd64.tar.gz\n"));
+        assert!(workflow.contains("key: sbuild-noble-amd64-${{ hashFiles('examples/noble/virtual/pkg-builder.toml') }}"));
+        assert!(workflow.contains("cargo install --locked --git https://github.com/eth-pkg/pkg-builder --tag v0.2.8 pkg-builder"));
+        assert!(workflow.contains("sudo $(which pkg-builder) autopkgtest"));
+        assert!(!workflow.contains("pkg-builder piuparts"));
+        assert!(workflow.contains("cp \"$HOME/.pkg-builder/packages/noble/hello-world-1.0.0-1\"/*.deb artifacts/ || true"));
+    }
+
+    #[test]
+    fn test_render_gitlab_pipeline() {
+        let pipeline = render_pipeline(CiProvider::Gitlab, &config(), ".").unwrap();
+        assert!(pipeline.starts_with("# sbuild needs user namespaces"));
+        assert!(pipeline.contains("package_noble_amd64:\n  image: ubuntu:24.04\n"));
+        assert!(pipeline.contains("    - if: $CI_COMMIT_TAG\n"));
+        assert!(pipeline.contains(
+            "    - cp .sbuild-cache/noble-amd64.tar.gz \"$HOME/.cache/sbuild/noble-amd64.tar.gz\" || (cd . && pkg-builder env create)\n"
+        ));
+        assert!(pipeline.ends_with("  artifacts:\n    paths:\n      - artifacts/\n"));
+    }
+}
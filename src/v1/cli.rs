@@ -1,38 +1,92 @@
-use super::args::{ActionType, BuildEnvSubCommand, PkgBuilderArgs};
+use super::args::{ActionType, BuildEnvSubCommand, PkgBuilderArgs, ShowSubCommand, SnapshotSubCommand, UploadSubCommand};
 use super::packager::DistributionPackager;
+use crate::v1::build::dir_setup::{expand_path, get_review_files};
+use crate::v1::build::archive::{format_changelog_date, now};
+use crate::v1::build::host_env::HostEnv;
+use crate::v1::build::matrix::{build_matrix, get_log_prefix, get_matrix_configs};
 use crate::v1::pkg_config::{get_config, PkgConfig};
 use clap::Parser;
 use env_logger::Env;
 use eyre::{eyre, Result};
-use std::{env, fs, path::Path};
+use std::io::{self, Write};
+use std::{env, fs, path::{Path, PathBuf}};
 use std::process::Command;
 use cargo_metadata::semver;
 use log::{error, info, warn};
-use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use crate::v1::pkg_config_verify::{apply_hash_updates, HashUpdate, PkgVerifyConfig};
+use crate::v1::workspace::freeze::{freeze_package, FreezeFile, FrozenPackage, FREEZE_FILE_NAME};
+use crate::v1::workspace::promote::{check_promotion, get_channel_dir, get_channel_package, promote_package, PromotedPackage};
+use crate::v1::workspace::release::{check_out_dir, create_release, ReleasePackage};
+use crate::v1::workspace::run::{
+    build_workspace, get_changed_packages, get_workspace_dependencies, BuildSelection,
+    RUN_STATE_FILE_NAME,
+};
+use crate::v1::workspace::WorkspaceConfig;
+use crate::v1::bump::bump_package_revision;
+use crate::v1::changelog::add_changelog_from_git;
+use crate::v1::notify::{format_drift_report, notify};
+use crate::v1::ci::render_pipeline;
+use crate::v1::build::sbuild::resolve_jobs;
+use crate::v1::lint::{check_recipe, find_config_files, format_lint_report, lint_configs};
+use crate::v1::schema::{explain, get_json_schema, get_schema};
+use crate::v1::signing::check_signing_key;
+use crate::v1::tools::{self, check_tool_version};
+use crate::v1::transition::{
+    check_transitions, get_build_depends, ArchiveTarget, SeenVersions, TRANSITIONS_FILE_NAME,
+};
 use semver::Version;
-use regex::Regex;
+use tempfile::tempdir;
 
 const CONFIG_FILE_NAME: &str = "pkg-builder.toml";
 const VERIFY_CONFIG_FILE_NAME: &str = "pkg-builder-verify.toml";
+const WORKSPACE_FILE_NAME: &str = "workspace.toml";
 
 
 pub fn run_cli() -> Result<()> {
     let args = PkgBuilderArgs::parse();
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let log_level = match args.verbose {
+        0 => "info",
+        1 => "debug",
+        _ => "trace",
+    };
+    env_logger::Builder::from_env(Env::default().default_filter_or(log_level))
+        .format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] {}{}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                get_log_prefix(),
+                record.args()
+            )
+        })
+        .init();
     let program_name: &str = env!("CARGO_PKG_NAME");
     let program_version: &str = env!("CARGO_PKG_VERSION");
+    let jobs = args.jobs;
     match args.action {
         ActionType::Verify(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
-            let config = get_config::<PkgConfig>(config_file.clone())?;
+            let mut config = get_config::<PkgConfig>(config_file.clone())?;
 
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+            if jobs.is_some() {
+                config.build_env.jobs = jobs;
+            }
 
             let distribution = get_distribution(config, config_file)?;
             let verify_config_file = get_config_file(command.verify_config, VERIFY_CONFIG_FILE_NAME)?;
-            let verify_config_file = get_config::<PkgVerifyConfig>(verify_config_file.clone())?;
+            let verify_config = get_config::<PkgVerifyConfig>(verify_config_file.clone())?;
             let no_package = command.no_package.unwrap_or_default();
-            distribution.verify(verify_config_file, !no_package)?;
+            if command.update {
+                let updates = distribution.get_hash_updates(&verify_config, !no_package)?;
+                update_verify_file(Path::new(&verify_config_file), &updates, command.yes)?;
+                return Ok(());
+            }
+            let check_changes = command.check_changes.unwrap_or_default();
+            distribution.verify(verify_config, !no_package, check_changes)?;
         }
         ActionType::Lintian(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
@@ -59,12 +113,66 @@ pub fn run_cli() -> Result<()> {
             let distribution = get_distribution(config, config_file)?;
             distribution.run_autopkgtests()?;
         }
+        ActionType::SmokeTest(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+
+            let distribution = get_distribution(config, config_file)?;
+            distribution.run_smoke_tests()?;
+        }
+        ActionType::UnitCheck(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+
+            let distribution = get_distribution(config, config_file)?;
+            distribution.run_unit_checks()?;
+        }
+        ActionType::DebconfCheck(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+
+            let distribution = get_distribution(config, config_file)?;
+            distribution.run_debconf_checks()?;
+        }
+        ActionType::ContentCheck(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+
+            let distribution = get_distribution(config, config_file)?;
+            distribution.run_content_checks()?;
+        }
         ActionType::Package(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
             let mut config = get_config::<PkgConfig>(config_file.clone())?;
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            check_sbuild_version(config.build_env.sbuild_version.clone())?;
+            if config.build_env.uses_sbuild() {
+                check_tool_version(&tools::Sbuild(config.build_env.sbuild_version.clone()))?;
+            }
+            if command.dev {
+                config.build_env.dev = Some(true);
+                config.build_env.run_lintian = Some(false);
+                config.build_env.run_piuparts = Some(false);
+                config.build_env.run_autopkgtest = Some(false);
+                config.build_env.run_unit_checks = Some(false);
+                config.build_env.run_debconf_checks = Some(false);
+                config.content_policy = None;
+                // the unpacked chroot is reused while its tarball is unchanged
+                config.build_env.snapshot_chroot.get_or_insert(true);
+            }
+            if command.quiet_chroot_setup {
+                config.build_env.quiet_chroot_setup = Some(true);
+            }
+            if command.offline {
+                config.build_env.offline = Some(true);
+            }
+            if command.allow_changed_inputs {
+                config.build_env.allow_changed_inputs = Some(true);
+            }
             if let Some(run_piuparts) = command.run_piuparts {
                 config.build_env.run_piuparts = Some(run_piuparts);
             }
@@ -74,8 +182,322 @@ pub fn run_cli() -> Result<()> {
             if let Some(run_lintian) = command.run_lintian {
                 config.build_env.run_lintian = Some(run_lintian);
             }
+            if let Some(run_unit_checks) = command.run_unit_checks {
+                config.build_env.run_unit_checks = Some(run_unit_checks);
+            }
+            if let Some(run_debconf_checks) = command.run_debconf_checks {
+                config.build_env.run_debconf_checks = Some(run_debconf_checks);
+            }
+            if jobs.is_some() {
+                config.build_env.jobs = jobs;
+            }
+            if let Some(spec) = command.spec {
+                config.package_fields.spec_file = expand_path(&spec, &env::current_dir()?);
+            }
+            check_recipe(&config, &config_file)?;
+            if command.all_distros {
+                build_matrix(get_matrix_configs(&config, command.parallel_builds)?, command.parallel_builds, |config| {
+                    get_distribution(config, config_file.clone())?.package()
+                })?;
+            } else {
+                let distribution = get_distribution(config, config_file)?;
+                distribution.package()?;
+            }
+        }
+        ActionType::Binnmu(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let mut config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+
+            check_tool_version(&tools::Sbuild(config.build_env.sbuild_version.clone()))?;
+            if jobs.is_some() {
+                config.build_env.jobs = jobs;
+            }
             let distribution = get_distribution(config, config_file)?;
-            distribution.package()?;
+            distribution.binnmu(&command.suffix, &command.changelog)?;
+        }
+        ActionType::BuildAll(command) => {
+            let (_, workspace_root, config_files) = get_workspace(command.manifest)?;
+            let workspace_packages =
+                get_workspace_packages(config_files, true, command.frozen, command.fail_on_eol, jobs)?;
+            if command.frozen {
+                FreezeFile::read(&workspace_root.join(FREEZE_FILE_NAME))?.check(&workspace_packages.frozen_packages)?;
+            }
+            let selection = match command.changed_since {
+                Some(git_ref) => BuildSelection::ChangedSince {
+                    packages: get_changed_packages(&workspace_root, &workspace_packages.config_roots, &git_ref)?,
+                    git_ref,
+                },
+                None if command.retry_failed => BuildSelection::RetryFailed,
+                None => BuildSelection::All,
+            };
+            let dependencies = get_workspace_dependencies(&workspace_packages.specs)?;
+            let state_file = workspace_root.join(RUN_STATE_FILE_NAME);
+            build_workspace(&workspace_packages.packages, &dependencies, &state_file, &selection)?;
+        }
+        ActionType::Release(command) => {
+            let (workspace, workspace_root, config_files) = get_workspace(command.manifest)?;
+            let build = !command.no_package.unwrap_or_default();
+            let WorkspacePackages { packages, specs, frozen_packages, .. } =
+                get_workspace_packages(config_files, build, command.frozen, command.fail_on_eol, jobs)?;
+            if command.frozen {
+                FreezeFile::read(&workspace_root.join(FREEZE_FILE_NAME))?.check(&frozen_packages)?;
+            }
+            let out_dir = expand_path(&command.out, &env::current_dir()?);
+            check_out_dir(Path::new(&out_dir))?;
+            if let Some(signing) = &workspace.signing {
+                check_signing_key(signing)?;
+            }
+            if build {
+                let dependencies = get_workspace_dependencies(&specs)?;
+                let state_file = workspace_root.join(RUN_STATE_FILE_NAME);
+                build_workspace(&packages, &dependencies, &state_file, &BuildSelection::All)?;
+            }
+            let current_dir = env::current_dir()?;
+            let previous = command.previous.map(|previous| expand_path(&previous, &current_dir));
+            create_release(&workspace, &packages, Path::new(&out_dir), previous.as_deref().map(Path::new))?;
+        }
+        ActionType::Freeze(command) => {
+            let (workspace, workspace_root, config_files) = get_workspace(command.manifest)?;
+            let mut packages = vec![];
+            for config_file in config_files {
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                packages.push(freeze_package(&config, Path::new(&config_file))?);
+            }
+            let freeze_file = workspace_root.join(FREEZE_FILE_NAME);
+            FreezeFile::new(&workspace, packages).write(&freeze_file)?;
+            info!("Froze {} packages into {}", workspace.packages.len(), freeze_file.display());
+        }
+        ActionType::Promote(command) => {
+            let (workspace, workspace_root, config_files) = get_workspace(command.manifest)?;
+            check_promotion(&workspace.channels, command.from.as_deref(), &command.to)?;
+            let mut found = None;
+            for config_file in config_files {
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                if config.package_fields.package_name == command.package {
+                    found = Some((config, config_file));
+                    break;
+                }
+            }
+            let (config, config_file) =
+                found.ok_or_else(|| eyre!("No package {} in the workspace", command.package))?;
+            let config_root = Path::new(&config_file).parent().unwrap().to_str().unwrap().to_string();
+            let verify_config_file = get_config_file(Some(config_root), VERIFY_CONFIG_FILE_NAME)?;
+            let verify_config = get_config::<PkgVerifyConfig>(verify_config_file)?;
+            let package = PromotedPackage::new(&config)?;
+            let (artifacts, quality_report_file) = match &command.from {
+                Some(from) => get_channel_package(&get_channel_dir(&workspace, &workspace_root, from)?, &package)?,
+                None => {
+                    let distribution = get_distribution(config, config_file)?;
+                    (distribution.get_artifacts()?, distribution.get_quality_report_file()?)
+                }
+            };
+            let channel_dir = get_channel_dir(&workspace, &workspace_root, &command.to)?;
+            if let Some(signing) = &workspace.signing {
+                check_signing_key(signing)?;
+            }
+            promote_package(&workspace, &channel_dir, &package, &artifacts, &quality_report_file, &verify_config)?;
+        }
+        ActionType::TransitionCheck(command) => {
+            let (_, workspace_root, config_files) = get_workspace(command.manifest)?;
+            let mut targets: Vec<ArchiveTarget> = vec![];
+            for config_file in config_files {
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                let config_root = Path::new(&config_file)
+                    .parent()
+                    .ok_or_else(|| eyre!("{} has no parent dir", config_file))?;
+                let spec_file = expand_path(&config.package_fields.spec_file, config_root);
+                let target = ArchiveTarget::new(&config)?;
+                let index = match targets.iter().position(|other| other.is_same_archive(&target)) {
+                    Some(index) => index,
+                    None => {
+                        targets.push(target);
+                        targets.len() - 1
+                    }
+                };
+                for dependency in get_build_depends(Path::new(&spec_file))? {
+                    targets[index].add_dependency(&dependency, &config.package_fields.package_name);
+                }
+            }
+
+            let transitions_file = workspace_root.join(TRANSITIONS_FILE_NAME);
+            let mut seen = SeenVersions::read(&transitions_file)?;
+            let transitions = check_transitions(&targets, &mut seen)?;
+            seen.write(&transitions_file)?;
+            if transitions.is_empty() {
+                info!("No changes of the build dependencies since the last check.");
+            }
+            for transition in transitions.iter() {
+                warn!("{}", transition.describe());
+            }
+        }
+        ActionType::RebuildCheck(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let mut config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+            if config.build_env.uses_sbuild() {
+                check_tool_version(&tools::Sbuild(config.build_env.sbuild_version.clone()))?;
+            }
+            if jobs.is_some() {
+                config.build_env.jobs = jobs;
+            }
+            let package = config.package_fields.package_name.clone();
+            let version = config.package_fields.get_debian_version();
+            let distribution = get_distribution(config, config_file)?;
+            let verify_config_file = get_config_file(command.verify_config, VERIFY_CONFIG_FILE_NAME)?;
+            let verify_config = get_config::<PkgVerifyConfig>(verify_config_file)?;
+
+            let updates = distribution.get_hash_updates(&verify_config, true)?;
+            if updates.is_empty() {
+                info!("Rebuild of {} {} matches the released hashes.", package, version);
+                return Ok(());
+            }
+            let report = format_drift_report(&package, &version, &updates);
+            error!("{}", report);
+            if let Some(notify_command) = command.notify_command {
+                let subject = format!("pkg-builder: {} {} output drifted", package, version);
+                notify(&notify_command, &subject, &report)?;
+            }
+            return Err(eyre!("Rebuild of {} {} drifted from the released hashes.", package, version));
+        }
+        ActionType::Bump(command) => {
+            if !command.revision {
+                return Err(eyre!("Nothing to bump, pass --revision."));
+            }
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            let version = bump_package_revision(&config, Path::new(&config_file), &format_changelog_date(now()))?;
+            println!("Version: {}", version);
+        }
+        ActionType::Changelog(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            add_changelog_from_git(&config, Path::new(&config_file), command.since.as_deref(), &format_changelog_date(now()))?;
+        }
+        ActionType::CiInit(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            let config_dir = fs::canonicalize(Path::new(&config_file))?
+                .parent()
+                .unwrap()
+                .to_path_buf();
+            let (repo_root, working_directory) = get_repo_location(&config_dir);
+            let pipeline = render_pipeline(command.provider, &config, &working_directory)?;
+            let pipeline_file = repo_root.join(command.provider.get_pipeline_file());
+            if pipeline_file.exists() {
+                return Err(eyre!("{} already exists, remove it to regenerate", pipeline_file.display()));
+            }
+            fs::create_dir_all(pipeline_file.parent().unwrap())?;
+            fs::write(&pipeline_file, pipeline)?;
+            info!("Pipeline written to {}", pipeline_file.display());
+        }
+        ActionType::Show(show_action) => match show_action.show_sub_command {
+            ShowSubCommand::Debian(sub_command) => {
+                let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                let distribution = get_distribution(config, config_file)?;
+                match sub_command.out {
+                    Some(out) => {
+                        let out = expand_path(&out, &env::current_dir()?);
+                        if Path::new(&out).join("debian").exists() {
+                            return Err(eyre!("{}/debian already exists", out));
+                        }
+                        let debian_dir = distribution.generate_debian_dir(Path::new(&out))?;
+                        info!("Debian dir written to {}", debian_dir.display());
+                    }
+                    None => {
+                        let out = tempdir()?;
+                        let debian_dir = distribution.generate_debian_dir(out.path())?;
+                        for file in get_review_files(&debian_dir)? {
+                            println!("==> debian/{} <==", file.file_name().unwrap().to_string_lossy());
+                            println!("{}", fs::read_to_string(&file)?);
+                        }
+                    }
+                }
+            }
+        },
+        ActionType::LintAll(command) => {
+            let dir = expand_path(&command.dir, &env::current_dir()?);
+            let config_files = find_config_files(Path::new(&dir), CONFIG_FILE_NAME)?;
+            let results = lint_configs(&config_files, resolve_jobs(jobs));
+            print!("{}", format_lint_report(&results));
+            let failed = results.iter().filter(|result| !result.errors.is_empty()).count();
+            if failed > 0 {
+                return Err(eyre!("{} of {} configs failed to lint", failed, results.len()));
+            }
+        }
+        ActionType::Snapshot(snapshot_action) => match snapshot_action.snapshot_sub_command {
+            SnapshotSubCommand::Save(sub_command) => {
+                let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                let distribution = get_distribution(config, config_file)?;
+                distribution.save_snapshot(&sub_command.name)?;
+            }
+            SnapshotSubCommand::Restore(sub_command) => {
+                let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                let distribution = get_distribution(config, config_file)?;
+                distribution.restore_snapshot(&sub_command.name, sub_command.force)?;
+            }
+        },
+        ActionType::Plan(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            check_recipe(&config, &config_file)?;
+            let distribution = get_distribution(config, config_file)?;
+            let plan = distribution.get_plan()?;
+            match command.json {
+                true => println!("{}", plan.to_json()?),
+                false => print!("{}", plan.format_summary()),
+            }
+        }
+        ActionType::Doctor(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file)?;
+            let host = HostEnv::get();
+            let mut build_env = config.build_env.clone();
+            host.adapt_build_env(&mut build_env);
+            print!("{}", host.format_report(&build_env));
+            host.check_build_env(&build_env)?;
+        }
+        ActionType::Upload(upload_action) => match upload_action.upload_sub_command {
+            UploadSubCommand::Prepare(command) => {
+                let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+                let config = get_config::<PkgConfig>(config_file.clone())?;
+                let dput_target = config.upload.as_ref().and_then(|upload| upload.dput_target.clone());
+                let distribution = get_distribution(config, config_file)?;
+                let upload_file = distribution.prepare_upload(command.key)?;
+                println!("dput {} {}", dput_target.as_deref().unwrap_or("<target>"), upload_file.display());
+            }
+        },
+        ActionType::Fetch(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+            let distribution = get_distribution(config, config_file)?;
+            distribution.fetch()?;
+        }
+        ActionType::Explain(command) => {
+            if command.json_schema {
+                println!("{}", serde_json::to_string_pretty(&get_json_schema()?)?);
+                return Ok(());
+            }
+            match command.field {
+                Some(field) => println!("{}", explain(&field)?),
+                None => {
+                    for field in get_schema()?.iter().filter(|field| !field.path.contains('.')) {
+                        println!("{}: {}", field.path, field.description.replace('\n', " "));
+                    }
+                }
+            }
+        }
+        ActionType::OrigHash(command) => {
+            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
+            fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+            let distribution = get_distribution(config, config_file)?;
+            let (name, hash) = distribution.get_orig_tarball_hash()?;
+            println!("{{ hash=\"{}\", name= \"{}\"}},", hash, name);
         }
         ActionType::Env(build_env_action) => {
             match build_env_action.build_env_sub_command {
@@ -87,6 +509,13 @@ pub fn run_cli() -> Result<()> {
                     let distribution = get_distribution(config, config_file)?;
                     distribution.create_build_env()?;
                 }
+                BuildEnvSubCommand::Update(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    let distribution = get_distribution(config, config_file)?;
+                    distribution.update_build_env()?;
+                }
                 BuildEnvSubCommand::Clean(sub_command) => {
                     let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
                     let config = get_config::<PkgConfig>(config_file.clone())?;
@@ -103,37 +532,115 @@ pub fn run_cli() -> Result<()> {
     Ok(())
 }
 
-pub fn check_sbuild_version(expected_version: String) -> Result<()> {
-    let output = Command::new("sbuild")
-        .arg("--version")
-        .output()?;
-
-    if output.status.success() {
-        let actual_version = String::from_utf8_lossy(&output.stdout).to_string();
-        let actual_version = get_first_line(&actual_version);
-        let actual_version = extract_version(actual_version).unwrap();
-        info!("sbuild version {}", actual_version);
-        fail_compare_versions(expected_version, &actual_version, "sbuild")?;
-        Ok(())
-    } else {
-        Err(eyre!("Failed to execute sbuild --version"))
+fn update_verify_file(verify_config_file: &Path, updates: &[HashUpdate], yes: bool) -> Result<()> {
+    if updates.is_empty() {
+        println!("All hashes in {} are up to date.", verify_config_file.display());
+        return Ok(());
+    }
+    for update in updates {
+        println!("{}", update.name);
+        println!("  old: {}", update.old_hash);
+        println!("  new: {}", update.new_hash);
+    }
+    if !yes {
+        print!("Write {} new hashes to {}? [y/N] ", updates.len(), verify_config_file.display());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err(eyre!("Hashes were not updated."));
+        }
     }
+    let content = fs::read_to_string(verify_config_file)?;
+    fs::write(verify_config_file, apply_hash_updates(&content, updates)?)?;
+    println!("Updated {}", verify_config_file.display());
+    Ok(())
 }
 
-fn extract_version(input: &str) -> Option<&str> {
-    // Define a regular expression pattern to match the version number
-    let re = Regex::new(r"sbuild \(Debian sbuild\) ([\d.]+)").unwrap();
+// workspace, its root directory and the config files of its packages
+fn get_workspace(manifest: Option<String>) -> Result<(WorkspaceConfig, PathBuf, Vec<String>)> {
+    let manifest_file = get_config_file(manifest, WORKSPACE_FILE_NAME)?;
+    let workspace = get_config::<WorkspaceConfig>(manifest_file.clone())?;
+    let manifest_file = fs::canonicalize(Path::new(&manifest_file))?;
+    let workspace_root = manifest_file.parent().unwrap().to_path_buf();
+    let mut config_files = vec![];
+    for package in workspace.packages.iter() {
+        let package = expand_path(package, &workspace_root);
+        config_files.push(get_config_file(Some(package), CONFIG_FILE_NAME)?);
+    }
+    Ok((workspace, workspace_root, config_files))
+}
+
+struct WorkspacePackages {
+    packages: Vec<ReleasePackage>,
+    /// (package name, spec file)
+    specs: Vec<(String, PathBuf)>,
+    /// (package name, config root)
+    config_roots: Vec<(String, PathBuf)>,
+    /// filled when frozen is given
+    frozen_packages: Vec<FrozenPackage>,
+}
 
-    // Use the regular expression to capture the version number
-    if let Some(captures) = re.captures(input) {
-        if let Some(version) = captures.get(1) {
-            return Some(version.as_str());
+fn get_workspace_packages(
+    config_files: Vec<String>,
+    build: bool,
+    frozen: bool,
+    fail_on_eol: bool,
+    jobs: Option<usize>,
+) -> Result<WorkspacePackages> {
+    let program_name: &str = env!("CARGO_PKG_NAME");
+    let program_version: &str = env!("CARGO_PKG_VERSION");
+    let mut workspace_packages = WorkspacePackages {
+        packages: vec![],
+        specs: vec![],
+        config_roots: vec![],
+        frozen_packages: vec![],
+    };
+    for config_file in config_files {
+        let mut config = get_config::<PkgConfig>(config_file.clone())?;
+        let config_root = Path::new(&config_file).parent().unwrap().to_path_buf();
+        let package_name = config.package_fields.package_name.clone();
+        let spec_file = expand_path(&config.package_fields.spec_file, &config_root);
+        workspace_packages.specs.push((package_name.clone(), PathBuf::from(spec_file)));
+        workspace_packages.config_roots.push((package_name, config_root));
+        if frozen {
+            workspace_packages.frozen_packages.push(freeze_package(&config, Path::new(&config_file))?);
+        }
+        fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+        if build && config.build_env.uses_sbuild() {
+            check_tool_version(&tools::Sbuild(config.build_env.sbuild_version.clone()))?;
         }
+        if jobs.is_some() {
+            config.build_env.jobs = jobs;
+        }
+        if fail_on_eol {
+            config.build_env.fail_on_eol = Some(true);
+        }
+        let packager = get_distribution(config.clone(), config_file)?;
+        workspace_packages.packages.push(ReleasePackage { config, packager });
     }
-    None
+    Ok(workspace_packages)
 }
-fn get_first_line(text: &str) -> &str {
-    text.split_once('\n').map_or(text, |(first_line, _rest)| first_line)
+
+// git repository root and the config directory relative to it,
+// outside of a git repository the config directory is the root
+fn get_repo_location(config_dir: &Path) -> (PathBuf, String) {
+    let git = |arg: &str| {
+        Command::new("git")
+            .args(["rev-parse", arg])
+            .current_dir(config_dir)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    match (git("--show-toplevel"), git("--show-prefix")) {
+        (Some(root), Some(prefix)) if !prefix.is_empty() => {
+            (PathBuf::from(root), prefix.trim_end_matches('/').to_string())
+        }
+        (Some(root), Some(_)) => (PathBuf::from(root), ".".to_string()),
+        _ => (config_dir.to_path_buf(), ".".to_string()),
+    }
 }
 
 pub fn fail_compare_versions(expected_version: String, actual_version: &str, program_name: &str) -> Result<()> {
@@ -169,7 +676,7 @@ pub fn get_distribution(config: PkgConfig, config_file_path: String) -> Result<D
 
 
 pub fn get_config_file(config: Option<String>, config_file_name: &str) -> Result<String> {
-    return if let Some(location) = config {
+    if let Some(location) = config {
         let path = Path::new(&location);
         if !path.exists() {
             return Err(eyre!("Directory or file does not exist {}", location));
@@ -189,5 +696,5 @@ pub fn get_config_file(config: Option<String>, config_file_name: &str) -> Result
             return Ok(config_file.to_str().unwrap().to_string());
         }
         Err(eyre!("Could not find {} in current directory.", config_file_name))
-    };
+    }
 }
\ No newline at end of file
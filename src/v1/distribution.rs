@@ -0,0 +1,494 @@
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistributionFamily {
+    Debian,
+    Ubuntu,
+}
+
+/// Typed identifier of a registered distribution, compare on this instead of codename strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistributionId {
+    Bookworm,
+    /// any release of the ubuntu releases table, tell them apart by suite
+    Ubuntu,
+    Daedalus,
+    RaspbianBookworm,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DotnetRepository {
+    /// apt source line, signed by the microsoft trust key
+    pub apt_source: &'static str,
+}
+
+const MICROSOFT_DEBIAN_12: DotnetRepository = DotnetRepository {
+    apt_source: "deb https://packages.microsoft.com/debian/12/prod bookworm main",
+};
+
+/// Versions of the toolchains in the archive, None when use_distro_toolchain is not available.
+#[derive(Debug, PartialEq, Default)]
+pub struct ToolchainVersions {
+    pub rust: Option<&'static str>,
+    pub go: Option<&'static str>,
+    pub nodejs: Option<&'static str>,
+}
+
+/// Everything pkg-builder needs to know about a distribution.
+#[derive(Debug, PartialEq)]
+pub struct DistributionInfo {
+    pub id: DistributionId,
+    /// as written in build_env.codename, e.g. "noble numbat"
+    pub codename: &'static str,
+    /// suite passed to sbuild, piuparts and autopkgtest, e.g. "noble"
+    pub suite: &'static str,
+    /// names the chroot caches and the suite of the repositories packages are published to,
+    /// unlike the suite unique between derivatives, e.g. "raspbian-bookworm"
+    pub key: &'static str,
+    pub family: DistributionFamily,
+    pub repo_url: &'static str,
+    pub keyring: &'static str,
+    /// None when dotnet is installed from the distribution archive
+    pub dotnet_repository: Option<DotnetRepository>,
+    /// run in the build chroot before the language dependencies are installed
+    pub chroot_setup_commands: &'static [&'static str],
+    /// lintian tags which are known false positives on this distribution
+    pub lintian_suppress_tags: &'static [&'static str],
+    /// image the smoke tests install the built package into
+    pub container_image: Option<&'static str>,
+    /// false when no autopkgtest image can be built, e.g. ubuntu releases without cloud images
+    pub autopkgtest_image: bool,
+    pub toolchain_versions: ToolchainVersions,
+    /// debian-policy version of the suite, the newest Standards-Version lintian knows
+    pub standards_version: &'static str,
+    /// newest stable debhelper compat level of the suite
+    pub debhelper_compat: u32,
+    /// end of life as YYYY-MM-DD, the end of LTS for debian and of standard support for ubuntu
+    pub eol: Option<&'static str>,
+    /// newest GLIBC and GLIBCXX symbol versions of the suite, the abi check is skipped when not set
+    pub glibc_version: Option<&'static str>,
+    pub glibcxx_version: Option<&'static str>,
+}
+
+/// A supported distribution, the hooks have defaults based on the distribution family,
+/// so a derivative only needs to override what it does differently.
+pub trait Distribution: Sync {
+    fn info(&self) -> &DistributionInfo;
+
+    /// Command creating the qemu image autopkgtests run in, it is run as root.
+    fn autopkgtest_image_command(&self, image_path: &Path, arch: &str, repo_url: &str) -> Command {
+        let info = self.info();
+        match info.family {
+            DistributionFamily::Debian => {
+                let mut cmd = Command::new("autopkgtest-build-qemu");
+                cmd.arg(info.suite)
+                    .arg(image_path)
+                    .arg(format!("--mirror={}", repo_url))
+                    .arg(format!("--arch={}", arch));
+                // arm64 VMs have no BIOS, autopkgtest-virt-qemu --efi boots them with AAVMF
+                if arch == "arm64" {
+                    cmd.arg("--boot=efi");
+                }
+                cmd
+            }
+            DistributionFamily::Ubuntu => {
+                let mirror = match arch {
+                    "amd64" => repo_url,
+                    _ if repo_url == info.repo_url => UBUNTU_PORTS_URL,
+                    _ => repo_url,
+                };
+                // the cloud image of the arch is downloaded from cloud-images.ubuntu.com
+                let mut cmd = Command::new("autopkgtest-buildvm-ubuntu-cloud");
+                cmd.arg(format!("--release={}", info.suite))
+                    .arg(format!("--mirror={}", mirror))
+                    .arg(format!("--arch={}", arch))
+                    .arg("-v")
+                    .current_dir(image_path.parent().unwrap());
+                cmd
+            }
+        }
+    }
+}
+
+pub struct StandardDistribution(DistributionInfo);
+
+impl Distribution for StandardDistribution {
+    fn info(&self) -> &DistributionInfo {
+        &self.0
+    }
+}
+
+static BOOKWORM: StandardDistribution = StandardDistribution(DistributionInfo {
+    id: DistributionId::Bookworm,
+    codename: "bookworm",
+    suite: "bookworm",
+    key: "bookworm",
+    family: DistributionFamily::Debian,
+    repo_url: "http://deb.debian.org/debian",
+    keyring: "/usr/share/keyrings/debian-archive-keyring.gpg",
+    dotnet_repository: Some(MICROSOFT_DEBIAN_12),
+    chroot_setup_commands: &[],
+    lintian_suppress_tags: &[],
+    container_image: Some("docker.io/library/debian:bookworm"),
+    autopkgtest_image: true,
+    toolchain_versions: ToolchainVersions {
+        rust: Some("1.63.0"),
+        go: Some("1.19.8"),
+        nodejs: Some("18.19.0"),
+    },
+    standards_version: "4.6.2",
+    debhelper_compat: 13,
+    eol: Some("2028-06-30"),
+    glibc_version: Some("2.36"),
+    glibcxx_version: Some("3.4.30"),
+});
+
+// Devuan 5 is based on bookworm
+static DAEDALUS: StandardDistribution = StandardDistribution(DistributionInfo {
+    id: DistributionId::Daedalus,
+    codename: "daedalus",
+    suite: "daedalus",
+    key: "daedalus",
+    family: DistributionFamily::Debian,
+    repo_url: "http://deb.devuan.org/merged",
+    keyring: "/usr/share/keyrings/devuan-archive-keyring.gpg",
+    dotnet_repository: Some(MICROSOFT_DEBIAN_12),
+    chroot_setup_commands: &[],
+    lintian_suppress_tags: &[],
+    container_image: Some("docker.io/dyne/devuan:daedalus"),
+    autopkgtest_image: true,
+    toolchain_versions: ToolchainVersions {
+        rust: None,
+        go: None,
+        nodejs: None,
+    },
+    standards_version: "4.6.2",
+    debhelper_compat: 13,
+    eol: Some("2028-06-30"),
+    glibc_version: Some("2.36"),
+    glibcxx_version: Some("3.4.30"),
+});
+
+static RASPBIAN_BOOKWORM: StandardDistribution = StandardDistribution(DistributionInfo {
+    id: DistributionId::RaspbianBookworm,
+    codename: "raspbian bookworm",
+    suite: "bookworm",
+    key: "raspbian-bookworm",
+    family: DistributionFamily::Debian,
+    repo_url: "http://raspbian.raspberrypi.com/raspbian",
+    keyring: "/usr/share/keyrings/raspbian-archive-keyring.gpg",
+    // microsoft does not publish armhf packages
+    dotnet_repository: None,
+    chroot_setup_commands: &[],
+    lintian_suppress_tags: &[],
+    // no official image, smoke_test.image has to be set
+    container_image: None,
+    autopkgtest_image: true,
+    toolchain_versions: ToolchainVersions {
+        rust: None,
+        go: None,
+        nodejs: None,
+    },
+    standards_version: "4.6.2",
+    debhelper_compat: 13,
+    eol: Some("2028-06-30"),
+    glibc_version: Some("2.36"),
+    glibcxx_version: Some("3.4.30"),
+});
+
+// archive.ubuntu.com only carries amd64 and i386, the other architectures are on ports
+const UBUNTU_PORTS_URL: &str = "http://ports.ubuntu.com/ubuntu-ports";
+
+const UBUNTU_RELEASES: &str = include_str!("ubuntu_releases.toml");
+/// Adds releases to the embedded table or overrides them by codename.
+pub const USER_UBUNTU_RELEASES: &str = "~/.pkg-builder/ubuntu-releases.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UbuntuReleases {
+    release: Vec<UbuntuRelease>,
+}
+
+/// An entry of the ubuntu releases table, see ubuntu_releases.toml for the defaults.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UbuntuRelease {
+    /// as written in build_env.codename, e.g. "noble numbat"
+    pub codename: String,
+    pub suite: String,
+    #[serde(default = "default_ubuntu_repo_url")]
+    pub repo_url: String,
+    #[serde(default = "default_ubuntu_keyring")]
+    pub keyring: String,
+    /// false once the release is end of life and its cloud images are removed
+    #[serde(default = "default_true")]
+    pub cloud_image: bool,
+    #[serde(default = "default_true")]
+    pub dotnet_in_archive: bool,
+    #[serde(default = "default_ubuntu_chroot_setup_commands")]
+    pub chroot_setup_commands: Vec<String>,
+    #[serde(default = "default_ubuntu_lintian_suppress_tags")]
+    pub lintian_suppress_tags: Vec<String>,
+    pub container_image: Option<String>,
+    pub rust_version: Option<String>,
+    pub go_version: Option<String>,
+    pub nodejs_version: Option<String>,
+    #[serde(default = "default_ubuntu_standards_version")]
+    pub standards_version: String,
+    #[serde(default = "default_ubuntu_debhelper_compat")]
+    pub debhelper_compat: u32,
+    /// end of standard support as YYYY-MM-DD
+    pub eol: Option<String>,
+    pub glibc_version: Option<String>,
+    pub glibcxx_version: Option<String>,
+}
+
+fn default_ubuntu_repo_url() -> String {
+    "http://archive.ubuntu.com/ubuntu".to_string()
+}
+
+fn default_ubuntu_keyring() -> String {
+    "/usr/share/keyrings/ubuntu-archive-keyring.gpg".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_ubuntu_chroot_setup_commands() -> Vec<String> {
+    [
+        "apt install -y software-properties-common",
+        "add-apt-repository universe",
+        "add-apt-repository restricted",
+        "add-apt-repository multiverse",
+        "apt update",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+fn default_ubuntu_standards_version() -> String {
+    "4.7.0".to_string()
+}
+
+fn default_ubuntu_debhelper_compat() -> u32 {
+    13
+}
+
+// changed a format of .deb packages on ubuntu, it's not a bug
+// but some lintian will report as such
+fn default_ubuntu_lintian_suppress_tags() -> Vec<String> {
+    vec!["malformed-deb-archive".to_string()]
+}
+
+// the table is loaded once per run, so the strings live as long as the static distributions
+fn leak(value: String) -> &'static str {
+    Box::leak(value.into_boxed_str())
+}
+
+fn leak_all(values: Vec<String>) -> &'static [&'static str] {
+    Box::leak(values.into_iter().map(leak).collect::<Vec<&str>>().into_boxed_slice())
+}
+
+impl UbuntuRelease {
+    pub fn into_distribution(self) -> StandardDistribution {
+        let container_image = self
+            .container_image
+            .unwrap_or_else(|| format!("docker.io/library/ubuntu:{}", self.suite));
+        StandardDistribution(DistributionInfo {
+            id: DistributionId::Ubuntu,
+            codename: leak(self.codename),
+            key: leak(self.suite.clone()),
+            suite: leak(self.suite),
+            family: DistributionFamily::Ubuntu,
+            repo_url: leak(self.repo_url),
+            keyring: leak(self.keyring),
+            dotnet_repository: match self.dotnet_in_archive {
+                true => None,
+                false => Some(MICROSOFT_DEBIAN_12),
+            },
+            chroot_setup_commands: leak_all(self.chroot_setup_commands),
+            lintian_suppress_tags: leak_all(self.lintian_suppress_tags),
+            container_image: Some(leak(container_image)),
+            autopkgtest_image: self.cloud_image,
+            toolchain_versions: ToolchainVersions {
+                rust: self.rust_version.map(leak),
+                go: self.go_version.map(leak),
+                nodejs: self.nodejs_version.map(leak),
+            },
+            standards_version: leak(self.standards_version),
+            debhelper_compat: self.debhelper_compat,
+            eol: self.eol.map(leak),
+            glibc_version: self.glibc_version.map(leak),
+            glibcxx_version: self.glibcxx_version.map(leak),
+        })
+    }
+}
+
+/// Releases of a table, later tables override earlier entries with the same codename.
+pub fn parse_ubuntu_releases(tables: &[&str]) -> Result<Vec<UbuntuRelease>> {
+    let mut releases: Vec<UbuntuRelease> = vec![];
+    for table in tables {
+        let parsed: UbuntuReleases = toml::from_str(table)?;
+        for release in parsed.release {
+            releases.retain(|existing| existing.codename != release.codename);
+            releases.push(release);
+        }
+    }
+    Ok(releases)
+}
+
+fn load_ubuntu_distributions() -> Result<Vec<StandardDistribution>> {
+    let user_releases_file = shellexpand::tilde(USER_UBUNTU_RELEASES).to_string();
+    let user_releases = match Path::new(&user_releases_file).exists() {
+        true => fs::read_to_string(&user_releases_file)?,
+        false => String::new(),
+    };
+    let mut tables = vec![UBUNTU_RELEASES];
+    if !user_releases.is_empty() {
+        tables.push(&user_releases);
+    }
+    let releases = parse_ubuntu_releases(&tables)
+        .map_err(|err| eyre!("Invalid ubuntu releases in {}: {}", user_releases_file, err))?;
+    Ok(releases.into_iter().map(UbuntuRelease::into_distribution).collect())
+}
+
+static UBUNTU_DISTRIBUTIONS: OnceLock<Result<Vec<StandardDistribution>, String>> = OnceLock::new();
+
+fn get_distributions() -> Result<Vec<&'static dyn Distribution>> {
+    let ubuntu = UBUNTU_DISTRIBUTIONS
+        .get_or_init(|| load_ubuntu_distributions().map_err(|err| err.to_string()))
+        .as_ref()
+        .map_err(|err| eyre!("{}", err))?;
+    let mut distributions: Vec<&'static dyn Distribution> = vec![&BOOKWORM];
+    distributions.extend(ubuntu.iter().map(|distribution| distribution as &dyn Distribution));
+    distributions.push(&DAEDALUS);
+    distributions.push(&RASPBIAN_BOOKWORM);
+    Ok(distributions)
+}
+
+pub fn get_distribution(codename: &str) -> Result<&'static dyn Distribution> {
+    let distributions = get_distributions()?;
+    distributions
+        .iter()
+        .find(|distribution| distribution.info().codename == codename)
+        .copied()
+        .ok_or_else(|| {
+            let supported: Vec<&str> = distributions
+                .iter()
+                .map(|distribution| distribution.info().codename)
+                .collect();
+            eyre!(
+                "Invalid codename '{}' specified, supported codenames: {}, ubuntu releases can be added in {}",
+                codename,
+                supported.join(", "),
+                USER_UBUNTU_RELEASES
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_distribution() {
+        let noble = get_distribution("noble numbat").unwrap().info();
+        assert_eq!(noble.id, DistributionId::Ubuntu);
+        assert_eq!(noble.suite, "noble");
+        assert_eq!(noble.family, DistributionFamily::Ubuntu);
+        assert_eq!(noble.dotnet_repository, None);
+        assert_eq!(noble.chroot_setup_commands.len(), 5);
+        assert_eq!(noble.container_image, Some("docker.io/library/ubuntu:noble"));
+        assert_eq!(noble.toolchain_versions.go, Some("1.22.2"));
+
+        let jammy = get_distribution("jammy jellyfish").unwrap().info();
+        assert_eq!(jammy.dotnet_repository, Some(MICROSOFT_DEBIAN_12));
+        assert!(jammy.chroot_setup_commands.is_empty());
+        assert_eq!(jammy.lintian_suppress_tags, &["malformed-deb-archive"]);
+
+        let oracular = get_distribution("oracular oriole").unwrap().info();
+        assert_eq!(oracular.repo_url, "http://old-releases.ubuntu.com/ubuntu");
+        assert!(!oracular.autopkgtest_image);
+        assert_eq!(oracular.toolchain_versions, ToolchainVersions::default());
+        assert_eq!(oracular.eol, Some("2025-07-10"));
+
+        // derivatives of bookworm keep the suite, but not the chroot caches and pool of bookworm
+        let raspbian = get_distribution("raspbian bookworm").unwrap().info();
+        assert_eq!(raspbian.suite, "bookworm");
+        assert_eq!(raspbian.key, "raspbian-bookworm");
+        assert_eq!(noble.key, "noble");
+
+        let error = get_distribution("noble").err().unwrap().to_string();
+        assert!(error.starts_with("Invalid codename 'noble' specified, supported codenames: bookworm, jammy jellyfish"));
+    }
+
+    #[test]
+    fn test_parse_ubuntu_releases_with_user_table() {
+        let user_table = r#"
+[[release]]
+codename = "questing quokka"
+suite = "questing"
+
+[[release]]
+codename = "noble numbat"
+suite = "noble"
+repo_url = "http://mirror.example.com/ubuntu"
+"#;
+        let releases = parse_ubuntu_releases(&[UBUNTU_RELEASES, user_table]).unwrap();
+        let codenames: Vec<&str> = releases.iter().map(|release| release.codename.as_str()).collect();
+        assert_eq!(
+            codenames,
+            vec!["jammy jellyfish", "oracular oriole", "plucky puffin", "questing quokka", "noble numbat"]
+        );
+
+        let noble = releases.last().unwrap().clone().into_distribution();
+        assert_eq!(noble.info().repo_url, "http://mirror.example.com/ubuntu");
+        // overrides replace the whole entry
+        assert_eq!(noble.info().toolchain_versions.go, None);
+
+        let questing = releases[3].clone().into_distribution();
+        assert_eq!(questing.info().keyring, "/usr/share/keyrings/ubuntu-archive-keyring.gpg");
+        assert!(questing.info().autopkgtest_image);
+        assert_eq!(questing.info().dotnet_repository, None);
+
+        assert!(parse_ubuntu_releases(&["[[release]]\nsuite = \"questing\"\n"]).is_err());
+    }
+
+    #[test]
+    fn test_autopkgtest_image_command_depends_on_family() {
+        let image_path = Path::new("/tmp/autopkgtest-jammy-amd64.img");
+        let jammy = get_distribution("jammy jellyfish").unwrap();
+        let cmd = jammy.autopkgtest_image_command(image_path, "amd64", "http://archive.ubuntu.com/ubuntu");
+        assert_eq!(cmd.get_program(), "autopkgtest-buildvm-ubuntu-cloud");
+        let args: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            vec![
+                "--release=jammy",
+                "--mirror=http://archive.ubuntu.com/ubuntu",
+                "--arch=amd64",
+                "-v"
+            ]
+        );
+
+        let daedalus = get_distribution("daedalus").unwrap();
+        let cmd = daedalus.autopkgtest_image_command(image_path, "amd64", "http://deb.devuan.org/merged");
+        let args: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(cmd.get_program(), "autopkgtest-build-qemu");
+        assert_eq!(args[0], "daedalus");
+
+        let cmd = jammy.autopkgtest_image_command(image_path, "arm64", jammy.info().repo_url);
+        let args: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args[1], "--mirror=http://ports.ubuntu.com/ubuntu-ports");
+        let bookworm = get_distribution("bookworm").unwrap();
+        let cmd = bookworm.autopkgtest_image_command(image_path, "arm64", "http://deb.debian.org/debian");
+        let args: Vec<&str> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+        assert_eq!(args.last().unwrap(), &"--boot=efi");
+    }
+}
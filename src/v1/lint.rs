@@ -0,0 +1,321 @@
+// Lints every pkg-builder.toml under a directory, e.g. the examples after a change of the
+// config schema. A config is parsed and validated, then the files and urls it references are
+// checked up front, the same checks run before plan and package.
+use crate::v1::build::dir_setup::{expand_path, validate_spec_file};
+use crate::v1::distribution::get_distribution;
+use crate::v1::pkg_config::{get_url_arch, read_config, LanguageEnv, PackageType, PkgConfig};
+use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use eyre::{eyre, Report, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LintResult {
+    pub config_file: PathBuf,
+    pub errors: Vec<String>,
+}
+
+/// Config files named config_file_name under dir, hidden directories are skipped.
+pub fn find_config_files(dir: &Path, config_file_name: &str) -> Result<Vec<PathBuf>> {
+    let mut config_files = vec![];
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap().to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') {
+                config_files.append(&mut find_config_files(&path, config_file_name)?);
+            }
+        } else if name == config_file_name {
+            config_files.push(path);
+        }
+    }
+    Ok(config_files)
+}
+
+const VERIFY_FILE_NAME: &str = "pkg-builder-verify.toml";
+
+fn check_file(errors: &mut Vec<Report>, field: &str, path: &str, config_root: &Path) {
+    let expanded = expand_path(path, config_root);
+    if !Path::new(&expanded).is_file() {
+        errors.push(eyre!("{} {} does not exist", field, expanded));
+    }
+}
+
+fn check_url(errors: &mut Vec<Report>, field: &str, url: &str) {
+    let http_url = Regex::new(r"^https?://[^/\s:]+(:\d+)?(/\S*)?$").unwrap();
+    if !url.is_empty() && !http_url.is_match(url) {
+        errors.push(eyre!("{} {} is not an http or https url", field, url));
+    }
+}
+
+/// Names listed in debian/patches/series, without comments and patch options.
+fn get_series_patches(series: &str) -> Vec<String> {
+    series
+        .lines()
+        .filter_map(|line| line.split('#').next()?.split_whitespace().next())
+        .map(|patch| patch.to_string())
+        .collect()
+}
+
+fn check_src_overrides(errors: &mut Vec<Report>, config: &PkgConfig, config_root: &Path) {
+    let src_dir = config_root.join("src");
+    if src_dir.exists() && !src_dir.is_dir() {
+        errors.push(eyre!("{} overrides the generated debian dir, it has to be a directory", src_dir.display()));
+        return;
+    }
+    let debian_dir = src_dir.join("debian");
+    // the upstream debian dir brings its own tests, debcrafter generates none
+    if config.build_env.run_autopkgtest == Some(true)
+        && !config.uses_upstream_debian_dir()
+        && !debian_dir.join("tests/control").is_file()
+    {
+        errors.push(eyre!(
+            "build_env.run_autopkgtest is set, but {} does not exist",
+            debian_dir.join("tests/control").display()
+        ));
+    }
+    let series_path = debian_dir.join("patches/series");
+    if let Ok(series) = fs::read_to_string(&series_path) {
+        for patch in get_series_patches(&series) {
+            if !debian_dir.join("patches").join(&patch).is_file() {
+                errors.push(eyre!("{} lists {}, which does not exist", series_path.display(), patch));
+            }
+        }
+    }
+}
+
+fn check_language_env(errors: &mut Vec<Report>, language_env: &LanguageEnv, chroot_arch: &str) {
+    if language_env.is_missing_arch_binary(chroot_arch) {
+        let configured = language_env.get_binary_url().and_then(get_url_arch).unwrap_or_default();
+        errors.push(eyre!(
+            "package_type.language_env.arch_binaries has no {} toolchain, the configured one is for {}",
+            chroot_arch,
+            configured
+        ));
+    }
+    match language_env {
+        LanguageEnv::Rust(config) => check_url(errors, "rust_binary_url", &config.rust_binary_url),
+        LanguageEnv::Go(config) => check_url(errors, "go_binary_url", &config.go_binary_url),
+        LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
+            check_url(errors, "node_binary_url", &config.node_binary_url)
+        }
+        LanguageEnv::Java(config) => {
+            check_url(errors, "jdk_binary_url", &config.jdk_binary_url);
+            if let Some(gradle) = &config.gradle {
+                check_url(errors, "gradle.gradle_binary_url", &gradle.gradle_binary_url);
+            }
+        }
+        LanguageEnv::Dotnet(config) => {
+            for package in config.dotnet_packages.iter() {
+                check_url(errors, "dotnet_packages.url", &package.url);
+            }
+        }
+        LanguageEnv::Nim(config) => check_url(errors, "nim_binary_url", &config.nim_binary_url),
+        LanguageEnv::Zig(config) => check_url(errors, "zig_binary_url", &config.zig_binary_url),
+        LanguageEnv::Custom(config) => {
+            for step in config.steps.iter() {
+                check_url(errors, "steps.url", &step.url);
+            }
+        }
+        LanguageEnv::C | LanguageEnv::Python(_) => {}
+    }
+}
+
+/// Checks the files and urls the recipe references, so they fail before the build instead of
+/// in the middle of it. The verify file is checked when it exists, unless it is required.
+pub fn validate_recipe(config: &PkgConfig, config_root: &Path, require_verify_file: bool) -> Result<(), Vec<Report>> {
+    let mut errors = Vec::new();
+    let chroot_arch = config.build_env.get_chroot_arch();
+
+    if !config.uses_upstream_debian_dir() {
+        let spec_file = expand_path(&config.package_fields.spec_file, config_root);
+        if let Err(err) = validate_spec_file(Path::new(&spec_file), config_root) {
+            errors.push(err);
+        }
+    }
+    match &config.package_type {
+        PackageType::Default(package_type) => {
+            if package_type.github_release.is_none() {
+                match package_type.tarball_url.starts_with("http") {
+                    true => check_url(&mut errors, "package_type.tarball_url", &package_type.tarball_url),
+                    false => check_file(&mut errors, "package_type.tarball_url", &package_type.tarball_url, config_root),
+                }
+            }
+            check_language_env(&mut errors, &package_type.language_env, chroot_arch);
+        }
+        PackageType::Git(package_type) => {
+            let git_url = Regex::new(r"^((https?|ssh|git)://[^/\s]+/|[\w.-]+@[\w.-]+:)\S+$").unwrap();
+            if !git_url.is_match(&package_type.git_url) {
+                errors.push(eyre!("package_type.git_url {} is not a git url", package_type.git_url));
+            }
+            if let Some(signers) = &package_type.allowed_signers {
+                check_file(&mut errors, "package_type.allowed_signers.key_file", &signers.key_file, config_root);
+            }
+            check_language_env(&mut errors, &package_type.language_env, chroot_arch);
+        }
+        PackageType::Virtual => {}
+    }
+    check_src_overrides(&mut errors, config, config_root);
+    if let Some(repo_url) = &config.build_env.repo_url {
+        check_url(&mut errors, "build_env.repo_url", repo_url);
+    }
+    if let Some(trust) = &config.trust {
+        for key in trust.keys.iter() {
+            check_file(&mut errors, "trust.keys.key_file", &key.key_file, config_root);
+        }
+    }
+    if let Some(apparmor) = &config.apparmor {
+        for profile in apparmor.profiles.iter() {
+            check_file(&mut errors, "apparmor.profiles", profile, config_root);
+        }
+    }
+    if let Some(debconf) = &config.debconf {
+        check_file(&mut errors, "debconf.preseed_file", &debconf.preseed_file, config_root);
+    }
+    if let Some(patches) = &config.patches {
+        for file in patches.files.iter() {
+            check_file(&mut errors, "patches.files", file, config_root);
+        }
+    }
+    let verify_file = config_root.join(VERIFY_FILE_NAME);
+    if verify_file.exists() {
+        if let Err(err) = read_config::<PkgVerifyConfig>(&verify_file) {
+            errors.push(eyre!("{}: {}", verify_file.display(), err));
+        }
+    } else if require_verify_file {
+        errors.push(eyre!("{} does not exist, the build cannot be verified", verify_file.display()));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// validate_recipe for the commands, all problems are reported in one error.
+pub fn check_recipe(config: &PkgConfig, config_file: &str) -> Result<()> {
+    let config_root = fs::canonicalize(config_file)?.parent().unwrap().to_path_buf();
+    validate_recipe(config, &config_root, false).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        eyre!("{} has {} problems:\n  {}", config_file, errors.len(), errors.join("\n  "))
+    })
+}
+
+pub fn lint_config(config_file: &Path) -> LintResult {
+    let mut errors = vec![];
+    match read_config::<PkgConfig>(config_file) {
+        Err(err) => errors.push(err.to_string()),
+        Ok(config) => {
+            if let Err(err) = get_distribution(&config.build_env.codename) {
+                errors.push(err.to_string());
+            }
+            let config_root = config_file.parent().unwrap();
+            if let Err(recipe_errors) = validate_recipe(&config, config_root, true) {
+                errors.extend(recipe_errors.iter().map(|err| err.to_string()));
+            }
+        }
+    }
+    LintResult {
+        config_file: config_file.to_path_buf(),
+        errors,
+    }
+}
+
+/// Lints the configs on jobs threads, the results keep the order of config_files.
+pub fn lint_configs(config_files: &[PathBuf], jobs: usize) -> Vec<LintResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; config_files.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(config_files.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(config_file) = config_files.get(index) else {
+                    break;
+                };
+                let result = lint_config(config_file);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+pub fn format_lint_report(results: &[LintResult]) -> String {
+    let mut report = String::new();
+    for result in results {
+        let status = if result.errors.is_empty() { "ok" } else { "FAILED" };
+        report.push_str(&format!("{}: {}\n", result.config_file.display(), status));
+        for error in result.errors.iter() {
+            report.push_str(&format!("    {}\n", error));
+        }
+    }
+    let failed = results.iter().filter(|result| !result.errors.is_empty()).count();
+    report.push_str(&format!("{} configs, {} failed\n", results.len(), failed));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lint_configs() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("broken")).unwrap();
+        fs::create_dir_all(dir.path().join(".hidden")).unwrap();
+        fs::write(dir.path().join("broken/pkg-builder.toml"), "[package_fields]\n").unwrap();
+        fs::write(dir.path().join(".hidden/pkg-builder.toml"), "").unwrap();
+        let broken = find_config_files(dir.path(), "pkg-builder.toml").unwrap();
+        assert_eq!(broken, vec![dir.path().join("broken/pkg-builder.toml")]);
+
+        // the examples are kept valid
+        let mut config_files = find_config_files(Path::new("examples/bookworm"), "pkg-builder.toml").unwrap();
+        assert!(!config_files.is_empty());
+        let examples = config_files.len();
+        config_files.extend(broken);
+        let results = lint_configs(&config_files, 4);
+        assert_eq!(results.len(), examples + 1);
+        for result in results[..examples].iter() {
+            assert_eq!(result.errors, Vec::<String>::new(), "{}", result.config_file.display());
+        }
+        assert_eq!(results[examples].errors.len(), 1);
+        let report = format_lint_report(&results);
+        assert!(report.ends_with(&format!("{} configs, 1 failed\n", examples + 1)));
+    }
+
+    #[test]
+    fn test_validate_recipe_reports_every_problem() {
+        let mut config = read_config::<PkgConfig>(Path::new("examples/bookworm/rust/hello-world/pkg-builder.toml")).unwrap();
+        if let PackageType::Default(package_type) = &mut config.package_type {
+            package_type.language_env = LanguageEnv::Rust(crate::v1::pkg_config::RustConfig {
+                rust_binary_url: "static.rust-lang.org/rust.tar.xz".to_string(),
+                ..Default::default()
+            });
+        }
+        let dir = tempdir().unwrap();
+        let patches_dir = dir.path().join("src/debian/patches");
+        fs::create_dir_all(&patches_dir).unwrap();
+        fs::write(patches_dir.join("series"), "# applied in order\nfix-build.patch -p1\nmissing.patch\n").unwrap();
+        fs::write(patches_dir.join("fix-build.patch"), "").unwrap();
+
+        let errors: Vec<String> = validate_recipe(&config, dir.path(), true)
+            .unwrap_err()
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(errors.len(), 6, "{:#?}", errors);
+        assert!(errors[0].starts_with("Spec file"));
+        assert!(errors[1].starts_with("package_type.tarball_url"));
+        assert_eq!(errors[2], "rust_binary_url static.rust-lang.org/rust.tar.xz is not an http or https url");
+        assert!(errors[3].starts_with("build_env.run_autopkgtest is set"));
+        assert!(errors[4].ends_with("lists missing.patch, which does not exist"));
+        assert!(errors[5].ends_with("pkg-builder-verify.toml does not exist, the build cannot be verified"));
+        assert_eq!(get_series_patches("a.patch\n\n# b.patch\nc.patch -p0 # vendored\n"), ["a.patch", "c.patch"]);
+    }
+}
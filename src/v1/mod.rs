@@ -2,6 +2,21 @@
 pub mod cli;
 pub mod build;
 pub mod packager;
+pub mod distribution;
 mod args;
 pub mod pkg_config;
 pub mod pkg_config_verify;
+pub mod apt_repo;
+pub mod attestation;
+pub mod signing;
+pub mod workspace;
+pub mod uversion;
+pub mod bump;
+pub mod changelog;
+pub mod notify;
+pub mod ci;
+pub mod transition;
+pub mod secrets;
+pub mod lint;
+pub mod schema;
+pub mod tools;
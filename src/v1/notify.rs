@@ -0,0 +1,69 @@
+// Alerts for unattended runs, e.g. from cron. The message is passed to a user
+// supplied shell command, which can mail it, post it to a chat, etc.
+use crate::v1::pkg_config_verify::HashUpdate;
+use eyre::{eyre, Result};
+use log::info;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `sh -c <command>` with the body on stdin and the subject in PKG_BUILDER_SUBJECT.
+pub fn notify(command: &str, subject: &str, body: &str) -> Result<()> {
+    info!("Sending notification: {}", subject);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PKG_BUILDER_SUBJECT", subject)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| eyre!("Failed to run notify command: {}", err))?;
+    child.stdin.take().unwrap().write_all(body.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(eyre!("Notify command failed with {}", status));
+    }
+    Ok(())
+}
+
+pub fn format_drift_report(package: &str, version: &str, updates: &[HashUpdate]) -> String {
+    let mut report = format!(
+        "Rebuild of {} {} does not match the released hashes.\n\n",
+        package, version
+    );
+    for update in updates {
+        report.push_str(&format!(
+            "{}\n  released: {}\n  rebuilt:  {}\n",
+            update.name, update.old_hash, update.new_hash
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_notify_passes_subject_and_body() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("notification");
+        let updates = vec![HashUpdate {
+            name: "hello-world_1.0.0-1_amd64.deb".to_string(),
+            old_hash: "a171d91d".to_string(),
+            new_hash: "00000000".to_string(),
+        }];
+        let report = format_drift_report("hello-world", "1.0.0-1", &updates);
+        let command = format!(
+            "{{ echo \"$PKG_BUILDER_SUBJECT\"; cat; }} > {}",
+            output.display()
+        );
+
+        notify(&command, "drift detected", &report).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "drift detected\nRebuild of hello-world 1.0.0-1 does not match the released hashes.\n\nhello-world_1.0.0-1_amd64.deb\n  released: a171d91d\n  rebuilt:  00000000\n"
+        );
+        assert!(notify("exit 1", "drift detected", &report).is_err());
+    }
+}
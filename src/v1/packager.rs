@@ -1,9 +1,17 @@
 use eyre::{eyre, Result};
+use crate::v1::build::dir_setup::open_for_hashing;
+use crate::v1::build::host_env::HostEnv;
+use crate::v1::build::plan::BuildPlan;
+use crate::v1::build::quality::QualityReport;
+use crate::v1::build::sbuild::calculate_sha1;
 use crate::v1::build::sbuild_packager::SbuildPackager;
+use crate::v1::build::upload::{copy_upload, sign_upload, UPLOAD_DIR_NAME};
+use crate::v1::distribution::get_distribution;
+use std::path::{Path, PathBuf};
 
 
 use crate::v1::pkg_config::PkgConfig;
-use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use crate::v1::pkg_config_verify::{HashUpdate, PkgVerifyConfig};
 
 
 pub trait Packager {
@@ -21,13 +29,28 @@ pub struct DistributionPackager {
 pub trait BackendBuildEnv {
     fn clean(&self) -> Result<()>;
     fn create(&self) -> Result<()>;
+    /// Upgrades the packages of the build env, recreates it once it is too old.
+    fn update(&self) -> Result<()>;
     fn package(&self) -> Result<()>;
 
-    fn verify(&self, verify_config: PkgVerifyConfig) -> Result<()>;
+    fn verify(&self, verify_config: PkgVerifyConfig, check_changes: bool) -> Result<()>;
 
     fn run_lintian(&self) -> Result<()>;
     fn run_piuparts(&self) -> Result<()>;
     fn run_autopkgtests(&self) -> Result<()>;
+    fn run_smoke_tests(&self) -> Result<()>;
+    fn run_unit_checks(&self) -> Result<()>;
+    fn run_debconf_checks(&self) -> Result<()>;
+    fn run_content_checks(&self) -> Result<()>;
+}
+
+/// Builds the extracted source, the artifacts and the build log end up next to it named like
+/// sbuild names them, so the checks after the build are the same for every backend.
+pub trait BuildBackend {
+    /// Prepares the reusable build environment, e.g. the build image.
+    fn create(&self) -> Result<()>;
+    fn clean(&self) -> Result<()>;
+    fn build(&self) -> Result<()>;
 }
 
 impl DistributionPackager {
@@ -37,139 +60,185 @@ impl DistributionPackager {
             config_root,
         }
     }
+    // every registered distribution is built with sbuild, or the backend the host can run
+    fn get_packager(&self, mut config: PkgConfig) -> Result<SbuildPackager> {
+        get_distribution(&config.build_env.codename)?;
+        config.build_env.backend = config.build_env.get_backend();
+        HostEnv::get().adapt_build_env(&mut config.build_env);
+        Ok(SbuildPackager::new(config, self.config_root.clone()))
+    }
     pub fn package(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                packager.package()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        packager.package()?;
         Ok(())
     }
+    pub fn save_snapshot(&self, name: &str) -> Result<()> {
+        self.get_packager(self.config.clone())?.save_snapshot(name)
+    }
+    pub fn restore_snapshot(&self, name: &str, force: bool) -> Result<()> {
+        self.get_packager(self.config.clone())?.restore_snapshot(name, force)
+    }
+    pub fn binnmu(&self, suffix: &str, changelog: &str) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.binnmu(suffix, changelog)
+    }
     pub fn run_lintian(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_lintian()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_lintian()?;
         Ok(())
     }
     pub fn run_piuparts(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_piuparts()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_piuparts()?;
         Ok(())
     }
     pub fn run_autopkgtests(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_autopkgtests()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_autopkgtests()?;
+        Ok(())
+    }
+    pub fn run_smoke_tests(&self) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_smoke_tests()?;
+        Ok(())
+    }
+    pub fn run_unit_checks(&self) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_unit_checks()?;
+        Ok(())
+    }
+    pub fn run_debconf_checks(&self) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_debconf_checks()?;
+        Ok(())
+    }
+    pub fn run_content_checks(&self) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.run_content_checks()?;
         Ok(())
     }
     pub fn clean_build_env(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-
-                let build_env = packager.get_build_env()?;
-                build_env.clean()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.clean()?;
         Ok(())
     }
     pub fn create_build_env(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.create()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.create()?;
         Ok(())
     }
+    pub fn update_build_env(&self) -> Result<()> {
+        let packager = self.get_packager(self.config.clone())?;
+        let build_env = packager.get_build_env()?;
+        build_env.update()?;
+        Ok(())
+    }
+
+    /// The config the package is built with, arch all packages are detected from debian/control.
+    pub fn get_build_config(&self) -> Result<PkgConfig> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.get_build_config()
+    }
+
+    pub fn get_plan(&self) -> Result<BuildPlan> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.get_plan()
+    }
+
+    pub fn get_recipe_revision(&self) -> Result<Option<String>> {
+        let packager = self.get_packager(self.config.clone())?;
+        Ok(packager.get_recipe_revision())
+    }
+
+    /// Licenses found by the license scan of the last build, empty when it did not run.
+    pub fn get_source_licenses(&self) -> Result<Vec<String>> {
+        let packager = self.get_packager(self.config.clone())?;
+        let report = QualityReport::read(&packager.get_build_env()?.get_quality_report_file())?;
+        Ok(report.licenses.map(|scan| scan.get_licenses()).unwrap_or_default())
+    }
+
+    pub fn get_artifacts(&self) -> Result<Vec<PathBuf>> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.get_build_env()?.get_artifacts()
+    }
+
+    pub fn get_quality_report_file(&self) -> Result<PathBuf> {
+        let packager = self.get_packager(self.config.clone())?;
+        Ok(packager.get_build_env()?.get_quality_report_file())
+    }
+
+    pub fn generate_debian_dir(&self, out_dir: &Path) -> Result<PathBuf> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.generate_debian_dir(out_dir)
+    }
+
+    /// Copies the source-only upload of the last build into the upload dir and signs it,
+    /// key overrides upload.key.
+    pub fn prepare_upload(&self, key: Option<String>) -> Result<PathBuf> {
+        let key = key
+            .or_else(|| self.config.upload.as_ref().map(|upload| upload.key.clone()))
+            .ok_or_else(|| eyre!("No key to sign the upload with, set upload.key or pass --key"))?;
+        let packager = self.get_packager(self.config.clone())?;
+        let changes_file = packager.get_build_env()?.get_source_changes_file();
+        let out_dir = changes_file.parent().unwrap().join(UPLOAD_DIR_NAME);
+        let upload_file = copy_upload(&changes_file, &out_dir)?;
+        sign_upload(&upload_file, &key)?;
+        Ok(upload_file)
+    }
+
+    pub fn fetch(&self) -> Result<()> {
+        self.get_packager(self.config.clone())?.fetch()
+    }
+
+    // returns file name and sha1, as pinned in pkg-builder-verify.toml
+    pub fn get_orig_tarball_hash(&self) -> Result<(String, String)> {
+        let packager = self.get_packager(self.config.clone())?;
+        packager.prepare_orig_tarball()?;
+        let tarball_path = packager.get_tarball_path();
+        let name = tarball_path.file_name().unwrap().to_string_lossy().to_string();
+        let hash = calculate_sha1(open_for_hashing(tarball_path)?)?;
+        Ok((name, hash))
+    }
+
+    // verification only needs the artifacts, the package tests are skipped
+    fn get_verify_packager(&self) -> Result<SbuildPackager> {
+        let mut config = self.config.clone();
+        config.build_env.run_autopkgtest = Some(false);
+        config.build_env.run_lintian = Some(false);
+        config.build_env.run_piuparts = Some(false);
+        config.build_env.run_unit_checks = Some(false);
+        config.build_env.run_debconf_checks = Some(false);
+        config.content_policy = None;
+        config.exports = None;
+        config.build_env.dev = Some(false);
+        self.get_packager(config)
+    }
+
+    pub fn get_hash_updates(&self, verify_config: &PkgVerifyConfig, package: bool) -> Result<Vec<HashUpdate>> {
+        let packager = self.get_verify_packager()?;
+        if package {
+            packager.package()?;
+        }
+        packager.get_build_env()?.get_hash_updates(verify_config)
+    }
 
-    pub fn verify(&self, verify_config: PkgVerifyConfig, package: bool) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let mut config = config.clone();
-                config.build_env.run_autopkgtest = Some(false);
-                config.build_env.run_lintian = Some(false);
-                config.build_env.run_piuparts = Some(false);
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                if package {
-                    packager.package()?;
-                }
-                let build_env = packager.get_build_env()?;
-                // files to verify
-                build_env.verify(verify_config)?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
+    pub fn verify(&self, verify_config: PkgVerifyConfig, package: bool, check_changes: bool) -> Result<()> {
+        let packager = self.get_verify_packager()?;
+        if package {
+            packager.package()?;
         }
+        let build_env = packager.get_build_env()?;
+        // files to verify
+        build_env.verify(verify_config, check_changes)?;
         Ok(())
     }
 }
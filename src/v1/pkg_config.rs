@@ -1,7 +1,15 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 use eyre::{eyre, Report, Result};
-use serde::{Deserialize, Deserializer};
+use regex::Regex;
+use crate::v1::distribution::{get_distribution, DistributionInfo};
+use crate::v1::build::conffiles::{get_placeholders, CONFFILE_BUILTINS};
+use crate::v1::build::exports::EXPORT_PLACEHOLDERS;
+use crate::v1::uversion::mangle_uversion;
+use schemars::JsonSchema;
+use serde_json::json;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::str::FromStr;
 use serde::de::DeserializeOwned;
 
@@ -21,6 +29,11 @@ pub fn deserialize_option_empty_string<'de, T, D>(deserializer: D) -> Result<Opt
 
 pub trait Validation {
     fn validate(&self) -> Result<(), Vec<Report>>;
+
+    /// fills in fields derived from other fields, runs before validate
+    fn resolve(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub fn validate_not_empty(name: &str, value: &str) -> Result<()> {
@@ -30,11 +43,99 @@ pub fn validate_not_empty(name: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+/// Version in the file name of an upstream binary url, pattern has a single capture group.
+/// Urls not following the upstream naming, e.g. of a mirror, are not checked.
+pub fn get_url_version(pattern: &str, url: &str) -> Option<String> {
+    let file_name = url.rsplit('/').next()?;
+    let re = Regex::new(pattern).unwrap();
+    re.captures(file_name).map(|captures| captures[1].to_string())
+}
+
+/// Catches a version bump without the matching url change, the pinned version
+/// may be less specific than the url, e.g. jdk_version 17.0 for jdk-17.0.10.
+pub fn validate_url_version(
+    version_field: &str,
+    version: &str,
+    url_field: &str,
+    url: &str,
+    pattern: &str,
+) -> Result<()> {
+    let Some(url_version) = get_url_version(pattern, url) else {
+        return Ok(());
+    };
+    let version = version.trim();
+    if url_version == version || url_version.starts_with(&format!("{}.", version)) {
+        return Ok(());
+    }
+    Err(eyre!(
+        "field: {} points to version {}, which does not match {} {}",
+        url_field,
+        url_version,
+        version_field,
+        version
+    ))
+}
+
+/// Toolchain download for one architecture, the urls of the language env are the amd64 ones.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ArchBinary {
+    /// download of the toolchain built for the architecture
+    #[schemars(example = json!("https://static.rust-lang.org/dist/rust-1.77.2-aarch64-unknown-linux-gnu.tar.xz"))]
+    pub url: String,
+    /// replaces the checksum of the language env: the sha256sum line for nim, the sha256 otherwise
+    pub checksum: String,
+    /// replaces rust_binary_gpg_asc, rust only
+    #[schemars(example = json!("-----BEGIN PGP SIGNATURE-----\n...\n-----END PGP SIGNATURE-----"))]
+    pub gpg_asc: Option<String>,
+}
+
+fn validate_arch_binaries(arch_binaries: &BTreeMap<String, ArchBinary>) -> Vec<Report> {
+    let mut errors = Vec::new();
+    for (arch, binary) in arch_binaries.iter() {
+        if !["amd64", "arm64", "armhf", "riscv64"].contains(&arch.as_str()) {
+            errors.push(eyre!("field: arch_binaries has unknown architecture {}", arch));
+        }
+        if let Err(err) = validate_not_empty(&format!("arch_binaries.{}.url", arch), &binary.url) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty(&format!("arch_binaries.{}.checksum", arch), &binary.checksum) {
+            errors.push(err);
+        }
+    }
+    errors
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct RustConfig {
+    /// version of the rust toolchain, has to match rust_binary_url
+    #[schemars(example = json!("1.77.2"))]
     pub rust_version: String,
+    /// standalone installer of the toolchain from static.rust-lang.org
+    #[schemars(example = json!("https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz"))]
+    #[serde(default)]
     pub rust_binary_url: String,
+    /// sha256 of rust_binary_url, checked on the host before the toolchain is staged
+    #[serde(default)]
+    pub rust_binary_checksum: String,
+    /// armored signature of rust_binary_url, verified on the host against the rust trust key
+    #[schemars(example = json!("-----BEGIN PGP SIGNATURE-----\n...\n-----END PGP SIGNATURE-----"))]
+    #[serde(default)]
     pub rust_binary_gpg_asc: String,
+    /// use rustc and cargo from the archive instead of downloading rust_binary_url,
+    /// rust_version is the minimum version required
+    pub use_distro_toolchain: Option<bool>,
+    /// passed to debian/rules as CARGO_BUILD_ARGS="--features <features> --profile <profile>"
+    #[schemars(example = json!(["jemalloc", "portable"]))]
+    #[serde(default)]
+    pub cargo_features: Vec<String>,
+    /// cargo profile of the build, see cargo_features
+    #[schemars(example = json!("maxperf"))]
+    pub cargo_profile: Option<String>,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
 impl Validation for RustConfig {
@@ -45,14 +146,41 @@ impl Validation for RustConfig {
             errors.push(err);
         }
 
-        if let Err(err) = validate_not_empty("rust_binary_url", &self.rust_binary_url) {
-            errors.push(err);
-        }
+        if self.use_distro_toolchain != Some(true) {
+            if let Err(err) = validate_not_empty("rust_binary_url", &self.rust_binary_url) {
+                errors.push(err);
+            }
 
-        if let Err(err) = validate_not_empty("rust_binary_gpg_asc", &self.rust_binary_gpg_asc) {
-            errors.push(err);
+            if let Err(err) = validate_not_empty("rust_binary_checksum", &self.rust_binary_checksum) {
+                errors.push(err);
+            }
+
+            if let Err(err) = validate_not_empty("rust_binary_gpg_asc", &self.rust_binary_gpg_asc) {
+                errors.push(err);
+            }
+
+            if let Err(err) = validate_url_version(
+                "rust_version",
+                &self.rust_version,
+                "rust_binary_url",
+                &self.rust_binary_url,
+                r"^rust-(\d[\w.]*)-[a-z0-9_]+-",
+            ) {
+                errors.push(err);
+            }
+        }
+        for feature in &self.cargo_features {
+            if let Err(err) = validate_build_option("cargo_features", feature, r"^[A-Za-z0-9_][A-Za-z0-9_+/.-]*$") {
+                errors.push(err);
+            }
+        }
+        if let Some(profile) = &self.cargo_profile {
+            if let Err(err) = validate_build_option("cargo_profile", profile, r"^[A-Za-z0-9_-]+$") {
+                errors.push(err);
+            }
         }
 
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
         if errors.is_empty() {
             Ok(())
         } else {
@@ -61,11 +189,32 @@ impl Validation for RustConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct GoConfig {
+    /// version of the go toolchain, has to match go_binary_url
+    #[schemars(example = json!("1.22.2"))]
     pub go_version: String,
+    /// release tarball of the toolchain from go.dev
+    #[schemars(example = json!("https://go.dev/dl/go1.22.2.linux-amd64.tar.gz"))]
+    #[serde(default)]
     pub go_binary_url: String,
+    /// sha256 of go_binary_url
+    #[serde(default)]
     pub go_binary_checksum: String,
+    /// use golang-go from the archive instead of downloading go_binary_url,
+    /// go_version is the minimum version required
+    pub use_distro_toolchain: Option<bool>,
+    /// added to GOFLAGS as -tags=<tags>, so every go command picks them up
+    #[schemars(example = json!(["netgo", "osusergo"]))]
+    #[serde(default)]
+    pub go_build_tags: Vec<String>,
+    /// passed to debian/rules as GO_LDFLAGS, GOFLAGS values cannot contain spaces
+    #[schemars(example = json!("-s -w -X main.version=1.0.0"))]
+    pub go_ldflags: Option<String>,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
 impl Validation for GoConfig {
@@ -76,14 +225,37 @@ impl Validation for GoConfig {
             errors.push(err);
         }
 
-        if let Err(err) = validate_not_empty("go_binary_url", &self.go_binary_url) {
-            errors.push(err);
-        }
+        if self.use_distro_toolchain != Some(true) {
+            if let Err(err) = validate_not_empty("go_binary_url", &self.go_binary_url) {
+                errors.push(err);
+            }
 
-        if let Err(err) = validate_not_empty("go_binary_checksum", &self.go_binary_checksum) {
-            errors.push(err);
+            if let Err(err) = validate_not_empty("go_binary_checksum", &self.go_binary_checksum) {
+                errors.push(err);
+            }
+
+            if let Err(err) = validate_url_version(
+                "go_version",
+                &self.go_version,
+                "go_binary_url",
+                &self.go_binary_url,
+                r"^go(\d[\w.]*)\.linux-",
+            ) {
+                errors.push(err);
+            }
+        }
+        for tag in &self.go_build_tags {
+            if let Err(err) = validate_build_option("go_build_tags", tag, r"^[A-Za-z0-9_.]+$") {
+                errors.push(err);
+            }
+        }
+        if let Some(ldflags) = &self.go_ldflags {
+            if let Err(err) = validate_not_empty("go_ldflags", ldflags) {
+                errors.push(err);
+            }
         }
 
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
         if errors.is_empty() {
             Ok(())
         } else {
@@ -92,12 +264,31 @@ impl Validation for GoConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct JavascriptConfig {
+    /// version of node, has to match node_binary_url
+    #[schemars(example = json!("20.12.2"))]
     pub node_version: String,
+    /// release tarball of node from nodejs.org
+    #[schemars(example = json!("https://nodejs.org/download/release/v20.12.2/node-v20.12.2-linux-x64.tar.gz"))]
+    #[serde(default)]
     pub node_binary_url: String,
+    /// sha256 of node_binary_url
+    #[serde(default)]
     pub node_binary_checksum: String,
+    /// installs yarn of this version with npm, yarn is not installed when not set
+    #[schemars(example = json!("1.22.19"))]
     pub yarn_version: Option<String>,
+    /// use nodejs and npm from the archive instead of downloading node_binary_url,
+    /// node_version is the minimum version required
+    pub use_distro_toolchain: Option<bool>,
+    /// passed to debian/rules as NPM_BUILD_SCRIPT, e.g. npm run $(NPM_BUILD_SCRIPT)
+    #[schemars(example = json!("build:release"))]
+    pub npm_build_script: Option<String>,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
 impl Validation for JavascriptConfig {
@@ -107,18 +298,35 @@ impl Validation for JavascriptConfig {
         if let Err(err) = validate_not_empty("node_version", &self.node_version) {
             errors.push(err);
         }
-        if let Err(err) = validate_not_empty("node_binary_url", &self.node_binary_url) {
-            errors.push(err);
-        }
-        if let Err(err) = validate_not_empty("node_binary_checksum", &self.node_binary_checksum) {
-            errors.push(err);
+        if self.use_distro_toolchain != Some(true) {
+            if let Err(err) = validate_not_empty("node_binary_url", &self.node_binary_url) {
+                errors.push(err);
+            }
+            if let Err(err) = validate_not_empty("node_binary_checksum", &self.node_binary_checksum) {
+                errors.push(err);
+            }
+            if let Err(err) = validate_url_version(
+                "node_version",
+                &self.node_version,
+                "node_binary_url",
+                &self.node_binary_url,
+                r"^node-v(\d[\w.]*)-linux-",
+            ) {
+                errors.push(err);
+            }
         }
         if let Some(yarn_version) = &self.yarn_version {
             if let Err(err) = validate_not_empty("yarn_version", yarn_version) {
                 errors.push(err);
             }
         }
+        if let Some(script) = &self.npm_build_script {
+            if let Err(err) = validate_build_option("npm_build_script", script, r"^[A-Za-z0-9_:.-]+$") {
+                errors.push(err);
+            }
+        }
 
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
         if errors.is_empty() {
             Ok(())
         } else {
@@ -127,11 +335,21 @@ impl Validation for JavascriptConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct GradleConfig {
+    /// version of gradle, has to match gradle_binary_url
+    #[schemars(example = json!("8.7"))]
     pub gradle_version: String,
+    /// distribution zip of gradle from services.gradle.org
+    #[schemars(example = json!("https://services.gradle.org/distributions/gradle-8.7-bin.zip"))]
     pub gradle_binary_url: String,
+    /// sha256 of gradle_binary_url
     pub gradle_binary_checksum: String,
+    /// passed to debian/rules as GRADLE_TASKS, separated by spaces
+    #[schemars(example = json!(["installDist", "-x", "test"]))]
+    #[serde(default)]
+    pub gradle_tasks: Vec<String>,
 }
 
 impl Validation for GradleConfig {
@@ -147,6 +365,20 @@ impl Validation for GradleConfig {
         if let Err(err) = validate_not_empty("gradle_binary_checksum", &self.gradle_binary_checksum) {
             errors.push(err);
         }
+        if let Err(err) = validate_url_version(
+            "gradle_version",
+            &self.gradle_version,
+            "gradle_binary_url",
+            &self.gradle_binary_url,
+            r"^gradle-(\d[\w.]*)-(?:bin|all)\.zip$",
+        ) {
+            errors.push(err);
+        }
+        for task in &self.gradle_tasks {
+            if let Err(err) = validate_build_option("gradle_tasks", task, r"^[A-Za-z0-9_:.-]+$") {
+                errors.push(err);
+            }
+        }
         if errors.is_empty() {
             Ok(())
         } else {
@@ -155,13 +387,27 @@ impl Validation for GradleConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct JavaConfig {
+    /// jdk_binary_url is an oracle jdk instead of an openjdk build
+    #[schemars(example = json!(true))]
     pub is_oracle: bool,
+    /// version of the jdk, has to match jdk_binary_url
+    #[schemars(example = json!("17.0"))]
     pub jdk_version: String,
+    /// release tarball of the jdk
+    #[schemars(example = json!("https://download.oracle.com/java/17/archive/jdk-17.0.10_linux-x64_bin.tar.gz"))]
     pub jdk_binary_url: String,
+    /// sha256 of jdk_binary_url
     pub jdk_binary_checksum: String,
+    /// gradle installed next to the jdk, for gradle builds
     pub gradle: Option<GradleConfig>,
+    /// resolve gradle or maven dependencies on the host and build offline
+    pub offline_dependencies: Option<bool>,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
 impl Validation for JavaConfig {
@@ -177,6 +423,17 @@ impl Validation for JavaConfig {
         if let Err(err) = validate_not_empty("jdk_binary_checksum", &self.jdk_binary_checksum) {
             errors.push(err);
         }
+        // oracle jdk-17.0.10_linux-x64_bin.tar.gz, openjdk-17.0.2_linux-x64_bin.tar.gz
+        if let Err(err) = validate_url_version(
+            "jdk_version",
+            &self.jdk_version,
+            "jdk_binary_url",
+            &self.jdk_binary_url,
+            r"^(?:open)?jdk-(\d[\w.]*)_linux-",
+        ) {
+            errors.push(err);
+        }
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
         if errors.is_empty() {
             Ok(())
         } else {
@@ -184,10 +441,16 @@ impl Validation for JavaConfig {
         }
     }
 }
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DotnetPackage {
+    /// file name of the deb without .deb
+    #[schemars(example = json!("dotnet-host_8.0.5-1_amd64"))]
     pub name: String,
+    /// sha1 of the deb
     pub hash: String,
+    /// backup location of the deb, see use_backup_version
+    #[schemars(example = json!("https://example.com/backup/dotnet-host_8.0.5-1_amd64.deb"))]
     pub url: String,
 }
 
@@ -216,9 +479,16 @@ impl Validation for DotnetPackage {
 }
 
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct DotnetConfig {
+    /// installs dotnet_packages from their url instead of the pinned versions of the package
+    /// repository
     pub use_backup_version: bool,
+    /// installs from the package repository and falls back to the urls of dotnet_packages when
+    /// the pinned version is missing or its hash changed, the manifest records the source used
+    pub fallback_to_backup: Option<bool>,
+    /// pinned debs of the dotnet sdk and runtime installed into the chroot
     pub dotnet_packages: Vec<DotnetPackage>,
 }
 
@@ -234,11 +504,20 @@ impl Validation for DotnetConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct NimConfig {
+    /// version of nim, has to match nim_binary_url
+    #[schemars(example = json!("2.0.2"))]
     pub nim_version: String,
+    /// release tarball of nim from nim-lang.org
+    #[schemars(example = json!("https://nim-lang.org/download/nim-2.0.2-linux_x64.tar.xz"))]
     pub nim_binary_url: String,
+    /// sha256sum line of nim_binary_url
     pub nim_version_checksum: String,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
 impl Validation for NimConfig {
@@ -254,7 +533,17 @@ impl Validation for NimConfig {
         if let Err(err) = validate_not_empty("nim_version_checksum", &self.nim_version_checksum) {
             errors.push(err);
         }
+        if let Err(err) = validate_url_version(
+            "nim_version",
+            &self.nim_version,
+            "nim_binary_url",
+            &self.nim_binary_url,
+            r"^nim-(\d[\w.]*)-linux_",
+        ) {
+            errors.push(err);
+        }
 
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
         if errors.is_empty() {
             Ok(())
         } else {
@@ -263,62 +552,89 @@ impl Validation for NimConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-#[serde(tag = "language_env", rename_all = "lowercase")]
-pub enum LanguageEnv {
-    Rust(RustConfig),
-    Go(GoConfig),
-    JavaScript(JavascriptConfig),
-    Java(JavaConfig),
-    Dotnet(DotnetConfig),
-    TypeScript(JavascriptConfig),
-    Nim(NimConfig),
-    #[default]
-    C,
-    Python,
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ZigConfig {
+    /// version of zig, has to match zig_binary_url
+    #[schemars(example = json!("0.13.0"))]
+    pub zig_version: String,
+    /// release tarball of zig from ziglang.org
+    #[schemars(example = json!("https://ziglang.org/download/0.13.0/zig-linux-x86_64-0.13.0.tar.xz"))]
+    pub zig_binary_url: String,
+    /// sha256 of zig_binary_url
+    pub zig_binary_checksum: String,
+    /// downloads of the toolchain for the other architectures, by debian architecture
+    #[serde(default)]
+    pub arch_binaries: BTreeMap<String, ArchBinary>,
 }
 
-impl Validation for LanguageEnv {
+impl Validation for ZigConfig {
     fn validate(&self) -> Result<(), Vec<Report>> {
-        match self {
-            LanguageEnv::Rust(config) => config.validate(),
-            LanguageEnv::Go(config) => config.validate(),
-            LanguageEnv::JavaScript(config) => config.validate(),
-            LanguageEnv::Java(config) => config.validate(),
-            LanguageEnv::Dotnet(config) => config.validate(),
-            LanguageEnv::TypeScript(config) => config.validate(),
-            LanguageEnv::Nim(config) => config.validate(),
-            LanguageEnv::C => Ok(()),
-            LanguageEnv::Python => Ok(()),
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("zig_version", &self.zig_version) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("zig_binary_url", &self.zig_binary_url) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("zig_binary_checksum", &self.zig_binary_checksum) {
+            errors.push(err);
+        }
+        // zig-linux-x86_64-0.13.0.tar.xz, zig-x86_64-linux-0.14.1.tar.xz since 0.14.1
+        if let Err(err) = validate_url_version(
+            "zig_version",
+            &self.zig_version,
+            "zig_binary_url",
+            &self.zig_binary_url,
+            r"^zig-[a-z0-9_]+-[a-z0-9_]+-(\d[\w.+-]*?)\.tar",
+        ) {
+            errors.push(err);
+        }
+
+        errors.append(&mut validate_arch_binaries(&self.arch_binaries));
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-pub struct DefaultPackageTypeConfig {
-    pub tarball_url: String,
-    pub tarball_hash: Option<String>,
-    pub language_env: LanguageEnv,
+/// python3 of the suite, with the pip dependencies optionally vendored as hashed wheels.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PythonConfig {
+    /// major.minor of python3 in the suite, the chroot setup fails on another one
+    #[schemars(example = json!("3.11"))]
+    pub python_version: Option<String>,
+    /// pip requirements pinned with --hash, relative to the source root, e.g. from
+    /// pip-compile --generate-hashes pyproject.toml
+    #[schemars(example = json!("requirements.txt"))]
+    pub requirements_file: Option<String>,
+    /// downloads the wheels of requirements_file on the host into debian/python-wheels and
+    /// verifies their hashes, pip in the build installs only from there
+    pub offline_dependencies: Option<bool>,
 }
 
-impl Validation for DefaultPackageTypeConfig {
+impl Validation for PythonConfig {
     fn validate(&self) -> Result<(), Vec<Report>> {
         let mut errors = Vec::new();
 
-        if let Err(err) = validate_not_empty("tarball_url", &self.tarball_url) {
-            errors.push(err);
-        }
-        if let Some(value) = &self.tarball_hash {
-            if let Err(err) = validate_not_empty("tarball_hash", value) {
+        if let Some(python_version) = &self.python_version {
+            if let Err(err) = validate_build_option("python_version", python_version, r"^3\.\d+$") {
                 errors.push(err);
             }
         }
-        let language_errors = self.language_env.validate();
-
-        if let Err(mut language_errors) = language_errors {
-            errors.append(&mut language_errors);
+        match &self.requirements_file {
+            Some(requirements_file) if requirements_file.is_empty() || requirements_file.starts_with('/') => {
+                errors.push(eyre!("field: requirements_file '{}' has to be relative to the source root", requirements_file));
+            }
+            None if self.offline_dependencies == Some(true) => {
+                errors.push(eyre!("field: requirements_file is required by offline_dependencies"));
+            }
+            _ => {}
         }
-
         if errors.is_empty() {
             Ok(())
         } else {
@@ -327,24 +643,55 @@ impl Validation for DefaultPackageTypeConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-pub struct SubModule {
-    pub commit: String,
-    pub path: String,
+/// Fetches an archive, checks its sha256 and unpacks it, e.g. a GHC bindist or a vendor SDK.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CustomInstallStep {
+    /// archive downloaded into the chroot
+    #[schemars(example = json!("https://downloads.haskell.org/ghc/9.4.8/ghc-9.4.8-x86_64-deb10-linux.tar.xz"))]
+    pub url: String,
+    /// sha256 of url
+    pub sha256: String,
+    /// absolute directory the archive is unpacked into, the archive is kept in /tmp if not set
+    #[schemars(example = json!("/opt/ghc"))]
+    pub unpack_dir: Option<String>,
+    /// leading path components dropped when unpacking, like tar --strip-components
+    #[schemars(example = json!(1))]
+    pub strip_components: Option<usize>,
+    /// link path -> target relative to unpack_dir, e.g. "/usr/bin/ghc" = "bin/ghc"
+    #[schemars(example = json!({ "/usr/bin/ghc": "bin/ghc" }))]
+    #[serde(default)]
+    pub symlinks: BTreeMap<String, String>,
 }
 
-impl Validation for SubModule {
+impl Validation for CustomInstallStep {
     fn validate(&self) -> Result<(), Vec<Report>> {
         let mut errors = Vec::new();
 
-        if let Err(err) = validate_not_empty("commit", &self.commit) {
-            errors.push(err);
+        if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+            errors.push(eyre!("field: steps.url '{}' has to be a http(s) url", self.url));
         }
-
-        if let Err(err) = validate_not_empty("path", &self.path) {
-            errors.push(err);
+        if self.sha256.len() != 64 || !self.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            errors.push(eyre!("field: steps.sha256 of {} is not a sha256 hash", self.url));
+        }
+        match &self.unpack_dir {
+            Some(unpack_dir) if !unpack_dir.starts_with('/') => {
+                errors.push(eyre!("field: steps.unpack_dir '{}' has to be absolute", unpack_dir));
+            }
+            None if !self.symlinks.is_empty() => {
+                errors.push(eyre!("field: steps.symlinks of {} need an unpack_dir", self.url));
+            }
+            _ => {}
+        }
+        for (link, target) in &self.symlinks {
+            if !link.starts_with('/') || target.is_empty() || target.starts_with('/') {
+                errors.push(eyre!(
+                    "field: steps.symlinks '{}' = '{}' needs an absolute link and a relative target",
+                    link,
+                    target
+                ));
+            }
         }
-
         if errors.is_empty() {
             Ok(())
         } else {
@@ -353,26 +700,38 @@ impl Validation for SubModule {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-pub struct GitPackageTypeConfig {
-    pub git_tag: String,
-    pub git_url: String,
-    pub submodules: Vec<SubModule>,
-    pub language_env: LanguageEnv,
+/// Toolchain pkg-builder has no language env for, installed by declarative steps.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CustomConfig {
+    /// name of the toolchain in logs and the build manifest
+    #[schemars(example = json!("ghc"))]
+    pub toolchain_name: String,
+    /// version of the toolchain in logs and the build manifest
+    #[schemars(example = json!("9.4.8"))]
+    pub toolchain_version: String,
+    /// archives installed into the chroot before the build, in order
+    pub steps: Vec<CustomInstallStep>,
 }
 
-impl Validation for GitPackageTypeConfig {
+impl Validation for CustomConfig {
     fn validate(&self) -> Result<(), Vec<Report>> {
         let mut errors = Vec::new();
 
-        if let Err(err) = validate_not_empty("git_tag", &self.git_tag) {
+        if let Err(err) = validate_not_empty("toolchain_name", &self.toolchain_name) {
             errors.push(err);
         }
-
-        if let Err(err) = validate_not_empty("git_url", &self.git_url) {
+        if let Err(err) = validate_not_empty("toolchain_version", &self.toolchain_version) {
             errors.push(err);
         }
-
+        if self.steps.is_empty() {
+            errors.push(eyre!("field: steps cannot be empty"));
+        }
+        for step in &self.steps {
+            if let Err(mut step_errors) = step.validate() {
+                errors.append(&mut step_errors);
+            }
+        }
         if errors.is_empty() {
             Ok(())
         } else {
@@ -381,141 +740,2386 @@ impl Validation for GitPackageTypeConfig {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-#[serde(tag = "package_type", rename_all = "lowercase")]
-pub enum PackageType {
-    Default(DefaultPackageTypeConfig),
-    Git(GitPackageTypeConfig),
+/// Toolchain the package is built with, selected by language_env.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(tag = "language_env", rename_all = "lowercase")]
+pub enum LanguageEnv {
+    Rust(RustConfig),
+    Go(GoConfig),
+    JavaScript(JavascriptConfig),
+    Java(JavaConfig),
+    Dotnet(DotnetConfig),
+    TypeScript(JavascriptConfig),
+    Nim(NimConfig),
+    Zig(ZigConfig),
+    Custom(CustomConfig),
     #[default]
-    Virtual,
+    C,
+    Python(PythonConfig),
 }
 
-impl Validation for PackageType {
-    fn validate(&self) -> Result<(), Vec<Report>> {
-        match self {
-            PackageType::Default(config) => config.validate(),
-            PackageType::Git(config) => config.validate(),
-            PackageType::Virtual => Ok(()),
+pub struct DistroToolchain {
+    pub packages: Vec<&'static str>,
+    /// version shipped in the suite, including updates
+    pub version: &'static str,
+}
+
+// 1.63.0 >= 1.63, missing components count as 0
+pub fn version_satisfies(available: &str, minimum: &str) -> bool {
+    let components = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .take_while(|component| !component.is_empty())
+            .map(|component| component.parse().unwrap_or(0))
+            .collect()
+    };
+    let available = components(available);
+    let minimum = components(minimum);
+    for i in 0..available.len().max(minimum.len()) {
+        let available_component = available.get(i).copied().unwrap_or(0);
+        let minimum_component = minimum.get(i).copied().unwrap_or(0);
+        if available_component != minimum_component {
+            return available_component > minimum_component;
         }
     }
+    true
 }
 
-#[derive(Debug, Deserialize, PartialEq, Default, Clone)]
-pub struct PackageFields {
-    pub spec_file: String,
-    pub package_name: String,
-    pub version_number: String,
-    pub revision_number: String,
-    pub homepage: String,
+/// Debian architecture of a download by the name of its file, e.g. arm64 for
+/// go1.22.2.linux-arm64.tar.gz or amd64 for rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz.
+pub fn get_url_arch(url: &str) -> Option<&'static str> {
+    let file_name = url.rsplit('/').next().unwrap_or(url).to_lowercase();
+    let names: [(&[&str], &str); 4] = [
+        (&["aarch64", "arm64"], "arm64"),
+        (&["riscv64"], "riscv64"),
+        (&["armv7", "armv6", "armhf"], "armhf"),
+        (&["x86_64", "amd64", "x64"], "amd64"),
+    ];
+    names
+        .iter()
+        .find(|(patterns, _)| patterns.iter().any(|pattern| file_name.contains(pattern)))
+        .map(|(_, arch)| *arch)
 }
 
-impl Validation for PackageFields {
-    fn validate(&self) -> Result<(), Vec<Report>> {
-        let mut errors = Vec::new();
+// build options end up in make variables and the sbuildrc, so they are restricted to names
+fn validate_build_option(name: &str, value: &str, pattern: &str) -> Result<()> {
+    if !Regex::new(pattern).unwrap().is_match(value) {
+        return Err(eyre!("field: {} has invalid value '{}'", name, value));
+    }
+    Ok(())
+}
 
-        if let Err(err) = validate_not_empty("spec_file", &self.spec_file) {
-            errors.push(err);
-        }
-        if let Err(err) = validate_not_empty("package_name", &self.package_name) {
-            errors.push(err);
-        }
-        if let Err(err) = validate_not_empty("version_number", &self.version_number) {
-            errors.push(err);
+impl LanguageEnv {
+    /// Downloads of the toolchain, None when the language env downloads none.
+    fn get_arch_binaries(&self) -> Option<&BTreeMap<String, ArchBinary>> {
+        match self {
+            LanguageEnv::Rust(config) if config.use_distro_toolchain != Some(true) => Some(&config.arch_binaries),
+            LanguageEnv::Go(config) if config.use_distro_toolchain != Some(true) => Some(&config.arch_binaries),
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config)
+                if config.use_distro_toolchain != Some(true) =>
+            {
+                Some(&config.arch_binaries)
+            }
+            LanguageEnv::Java(config) if config.is_oracle => Some(&config.arch_binaries),
+            LanguageEnv::Nim(config) => Some(&config.arch_binaries),
+            LanguageEnv::Zig(config) => Some(&config.arch_binaries),
+            _ => None,
         }
-        if let Err(err) = validate_not_empty("revision_number", &self.revision_number) {
-            errors.push(err);
+    }
+
+    /// The download of the toolchain outside of arch_binaries.
+    pub fn get_binary_url(&self) -> Option<&str> {
+        self.get_arch_binaries()?;
+        match self {
+            LanguageEnv::Rust(config) => Some(&config.rust_binary_url),
+            LanguageEnv::Go(config) => Some(&config.go_binary_url),
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => Some(&config.node_binary_url),
+            LanguageEnv::Java(config) => Some(&config.jdk_binary_url),
+            LanguageEnv::Nim(config) => Some(&config.nim_binary_url),
+            LanguageEnv::Zig(config) => Some(&config.zig_binary_url),
+            _ => None,
         }
-        if let Err(err) = validate_not_empty("homepage", &self.homepage) {
-            errors.push(err);
+    }
+
+    /// Hash of get_binary_url, the staged download is named by it.
+    pub fn get_binary_checksum(&self) -> Option<&str> {
+        self.get_arch_binaries()?;
+        match self {
+            LanguageEnv::Rust(config) => Some(&config.rust_binary_checksum),
+            LanguageEnv::Go(config) => Some(&config.go_binary_checksum),
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => Some(&config.node_binary_checksum),
+            LanguageEnv::Java(config) => Some(&config.jdk_binary_checksum),
+            // in the sha256sum format, followed by the file name
+            LanguageEnv::Nim(config) => config.nim_version_checksum.split_whitespace().next(),
+            LanguageEnv::Zig(config) => Some(&config.zig_binary_checksum),
+            _ => None,
         }
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(errors)
+    }
+
+    /// Source of the [trust] key the toolchain download is signed by.
+    pub fn get_trust_source(&self) -> Option<&'static str> {
+        self.get_arch_binaries()?;
+        match self {
+            LanguageEnv::JavaScript(_) | LanguageEnv::TypeScript(_) => Some("node"),
+            language_env => Some(language_env.get_name()),
         }
     }
-}
 
-#[derive(Debug, Deserialize, PartialEq, Default, Clone)]
-pub struct BuildEnv {
-    pub codename: String,
-    pub arch: String,
-    pub pkg_builder_version: String,
-    pub debcrafter_version: String,
-    pub sbuild_cache_dir: Option<String>,
-    pub docker: Option<bool>,
-    pub run_lintian: Option<bool>,
-    pub run_piuparts: Option<bool>,
-    pub run_autopkgtest: Option<bool>,
-    pub lintian_version: String,
-    pub piuparts_version: String,
-    pub autopkgtest_version: String,
-    pub sbuild_version: String,
-    #[serde(deserialize_with = "deserialize_option_empty_string")]
-    pub workdir: Option<String>,
-}
+    /// The toolchain runs in a chroot of arch, but arch_binaries has none for it and the file
+    /// name of the configured download names another arch. Unknown names are not checked.
+    pub fn is_missing_arch_binary(&self, arch: &str) -> bool {
+        let Some(binaries) = self.get_arch_binaries() else {
+            return false;
+        };
+        let url_arch = self.get_binary_url().and_then(get_url_arch);
+        !binaries.contains_key(arch) && url_arch.is_some_and(|url_arch| url_arch != arch)
+    }
 
-impl Validation for BuildEnv {
-    fn validate(&self) -> Result<(), Vec<Report>> {
-        let mut errors = Vec::new();
+    /// Replaces the toolchain download by the one of arch, if there is one.
+    pub fn select_arch(&mut self, arch: &str) {
+        let Some(binary) = self.get_arch_binaries().and_then(|binaries| binaries.get(arch)).cloned() else {
+            return;
+        };
+        match self {
+            LanguageEnv::Rust(config) => {
+                config.rust_binary_url = binary.url;
+                config.rust_binary_checksum = binary.checksum;
+                config.rust_binary_gpg_asc = binary.gpg_asc.unwrap_or_default();
+            }
+            LanguageEnv::Go(config) => {
+                config.go_binary_url = binary.url;
+                config.go_binary_checksum = binary.checksum;
+            }
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
+                config.node_binary_url = binary.url;
+                config.node_binary_checksum = binary.checksum;
+            }
+            LanguageEnv::Java(config) => {
+                config.jdk_binary_url = binary.url;
+                config.jdk_binary_checksum = binary.checksum;
+            }
+            LanguageEnv::Nim(config) => {
+                config.nim_binary_url = binary.url;
+                config.nim_version_checksum = binary.checksum;
+            }
+            LanguageEnv::Zig(config) => {
+                config.zig_binary_url = binary.url;
+                config.zig_binary_checksum = binary.checksum;
+            }
+            _ => {}
+        }
+    }
 
-        if let Err(err) = validate_not_empty("codename", &self.codename) {
-            errors.push(err);
+    /// As in the config, e.g. javascript.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            LanguageEnv::Rust(_) => "rust",
+            LanguageEnv::Go(_) => "go",
+            LanguageEnv::JavaScript(_) => "javascript",
+            LanguageEnv::Java(_) => "java",
+            LanguageEnv::Dotnet(_) => "dotnet",
+            LanguageEnv::TypeScript(_) => "typescript",
+            LanguageEnv::Nim(_) => "nim",
+            LanguageEnv::Zig(_) => "zig",
+            LanguageEnv::Custom(_) => "custom",
+            LanguageEnv::C => "c",
+            LanguageEnv::Python(_) => "python",
+        }
+    }
+
+    /// Build environment variables of the configured build options, debian/rules passes them
+    /// to the build, e.g. cargo build $(CARGO_BUILD_ARGS).
+    pub fn get_build_options(&self) -> BTreeMap<String, String> {
+        let mut options = BTreeMap::new();
+        match self {
+            LanguageEnv::Rust(config) => {
+                let mut args = vec![];
+                if !config.cargo_features.is_empty() {
+                    args.push(format!("--features {}", config.cargo_features.join(",")));
+                }
+                if let Some(profile) = &config.cargo_profile {
+                    args.push(format!("--profile {}", profile));
+                }
+                if !args.is_empty() {
+                    options.insert("CARGO_BUILD_ARGS".to_string(), args.join(" "));
+                }
+            }
+            LanguageEnv::Go(config) => {
+                if !config.go_build_tags.is_empty() {
+                    options.insert("GOFLAGS".to_string(), format!("-tags={}", config.go_build_tags.join(",")));
+                }
+                if let Some(ldflags) = &config.go_ldflags {
+                    options.insert("GO_LDFLAGS".to_string(), ldflags.clone());
+                }
+            }
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
+                if let Some(script) = &config.npm_build_script {
+                    options.insert("NPM_BUILD_SCRIPT".to_string(), script.clone());
+                }
+            }
+            LanguageEnv::Java(JavaConfig { gradle: Some(gradle), .. }) if !gradle.gradle_tasks.is_empty() => {
+                options.insert("GRADLE_TASKS".to_string(), gradle.gradle_tasks.join(" "));
+            }
+            _ => {}
+        }
+        options
+    }
+
+    /// The pinned version, when the toolchain is taken from the archive instead of downloaded.
+    pub fn get_distro_toolchain_version(&self) -> Option<&str> {
+        match self {
+            LanguageEnv::Rust(config) if config.use_distro_toolchain == Some(true) => {
+                Some(&config.rust_version)
+            }
+            LanguageEnv::Go(config) if config.use_distro_toolchain == Some(true) => {
+                Some(&config.go_version)
+            }
+            LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config)
+                if config.use_distro_toolchain == Some(true) =>
+            {
+                Some(&config.node_version)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_distro_toolchain(&self, distribution: &DistributionInfo) -> Option<DistroToolchain> {
+        let versions = &distribution.toolchain_versions;
+        let (packages, version) = match self {
+            LanguageEnv::Rust(_) => (vec!["rustc", "cargo"], versions.rust?),
+            LanguageEnv::Go(_) => (vec!["golang-go"], versions.go?),
+            LanguageEnv::JavaScript(_) | LanguageEnv::TypeScript(_) => (vec!["nodejs", "npm"], versions.nodejs?),
+            _ => return None,
+        };
+        Some(DistroToolchain { packages, version })
+    }
+
+    pub fn validate_distro_toolchain(&self, codename: &str) -> Result<()> {
+        let minimum_version = match self.get_distro_toolchain_version() {
+            Some(minimum_version) => minimum_version,
+            None => return Ok(()),
+        };
+        // unknown codenames are reported by the packager, there is no toolchain for them either
+        let toolchain = get_distribution(codename)
+            .ok()
+            .and_then(|distribution| self.get_distro_toolchain(distribution.info()));
+        match toolchain {
+            Some(toolchain) if version_satisfies(toolchain.version, minimum_version) => Ok(()),
+            Some(toolchain) => Err(eyre!(
+                "use_distro_toolchain: {} ships {} {}, which does not satisfy the pinned version {}",
+                codename,
+                toolchain.packages[0],
+                toolchain.version,
+                minimum_version
+            )),
+            None => Err(eyre!(
+                "use_distro_toolchain: no distro toolchain available for this language in {}",
+                codename
+            )),
+        }
+    }
+}
+
+impl Validation for LanguageEnv {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        match self {
+            LanguageEnv::Rust(config) => config.validate(),
+            LanguageEnv::Go(config) => config.validate(),
+            LanguageEnv::JavaScript(config) => config.validate(),
+            LanguageEnv::Java(config) => config.validate(),
+            LanguageEnv::Dotnet(config) => config.validate(),
+            LanguageEnv::TypeScript(config) => config.validate(),
+            LanguageEnv::Nim(config) => config.validate(),
+            LanguageEnv::Zig(config) => config.validate(),
+            LanguageEnv::Custom(config) => config.validate(),
+            LanguageEnv::C => Ok(()),
+            LanguageEnv::Python(config) => config.validate(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultPackageTypeConfig {
+    /// left out when the tarball is a github_release asset
+    #[schemars(example = json!("https://github.com/ethereum/go-ethereum/archive/refs/tags/v1.14.0.tar.gz"))]
+    #[serde(default)]
+    pub tarball_url: String,
+    /// sha512 of the tarball, the build fails when it does not match
+    pub tarball_hash: Option<String>,
+    /// toolchain the package is built with
+    pub language_env: LanguageEnv,
+    /// headers and credential of tarball_url, for artifact stores requiring authentication
+    pub tarball_auth: Option<TarballAuth>,
+    /// resolves the tarball from the assets of a github release instead of tarball_url
+    pub github_release: Option<GithubReleaseConfig>,
+}
+
+impl DefaultPackageTypeConfig {
+    /// tarball_url, or the page of the github release the tarball is an asset of.
+    pub fn get_source_url(&self, version_number: &str) -> String {
+        match &self.github_release {
+            Some(release) => release.get_release_url(version_number),
+            None => self.tarball_url.clone(),
+        }
+    }
+}
+
+impl Validation for DefaultPackageTypeConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        match &self.github_release {
+            Some(github_release) => {
+                if !self.tarball_url.is_empty() {
+                    errors.push(eyre!("field: tarball_url cannot be set together with github_release"));
+                }
+                if self.tarball_auth.is_some() {
+                    errors.push(eyre!("field: tarball_auth cannot be set together with github_release"));
+                }
+                if let Err(mut github_release_errors) = github_release.validate() {
+                    errors.append(&mut github_release_errors);
+                }
+            }
+            None => {
+                if let Err(err) = validate_not_empty("tarball_url", &self.tarball_url) {
+                    errors.push(err);
+                }
+            }
+        }
+        if let Some(value) = &self.tarball_hash {
+            if let Err(err) = validate_not_empty("tarball_hash", value) {
+                errors.push(err);
+            }
+        }
+        if let Some(tarball_auth) = &self.tarball_auth {
+            if !self.tarball_url.starts_with("http") {
+                errors.push(eyre!("field: tarball_auth needs an http or https tarball_url"));
+            }
+            if let Err(mut tarball_auth_errors) = tarball_auth.validate() {
+                errors.append(&mut tarball_auth_errors);
+            }
+        }
+        let language_errors = self.language_env.validate();
+
+        if let Err(mut language_errors) = language_errors {
+            errors.append(&mut language_errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A tarball published as github release asset, the tag is derived from version_number.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GithubReleaseConfig {
+    /// owner/repo, e.g. ChainSafe/lodestar
+    #[schemars(example = json!("ChainSafe/lodestar"))]
+    pub repo: String,
+    /// tag of the release, {version} is replaced by version_number, defaults to v{version}
+    #[schemars(example = json!("v{version}"))]
+    pub tag_format: Option<String>,
+    /// glob matching the name of exactly one asset, {version} and {tag} are replaced,
+    /// e.g. "lodestar-{tag}-linux-amd64*.tar.gz"
+    pub asset: String,
+    /// glob of the asset holding the sha256 checksum, <asset>.sha256, SHA256SUMS and
+    /// checksums.txt are tried when not set
+    #[schemars(example = json!("SHA256SUMS"))]
+    pub checksum_asset: Option<String>,
+    /// glob of the detached signature, <asset>.asc and <asset>.sig are tried when not set,
+    /// verified against the trust key of source github_release
+    #[schemars(example = json!("{tag}.tar.gz.asc"))]
+    pub signature_asset: Option<String>,
+}
+
+impl GithubReleaseConfig {
+    pub fn get_tag(&self, version_number: &str) -> String {
+        self.tag_format
+            .as_deref()
+            .unwrap_or("v{version}")
+            .replace("{version}", version_number)
+    }
+
+    pub fn get_asset_pattern(&self, version_number: &str) -> String {
+        self.asset
+            .replace("{tag}", &self.get_tag(version_number))
+            .replace("{version}", version_number)
+    }
+
+    pub fn get_release_url(&self, version_number: &str) -> String {
+        format!("https://github.com/{}/releases/tag/{}", self.repo, self.get_tag(version_number))
+    }
+}
+
+impl Validation for GithubReleaseConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        let parts: Vec<&str> = self.repo.split('/').collect();
+        if parts.len() != 2 || parts.iter().any(|part| part.trim().is_empty()) {
+            errors.push(eyre!("field: github_release.repo must be owner/repo, got {}", self.repo));
+        }
+        if let Err(err) = validate_not_empty("github_release.asset", &self.asset) {
+            errors.push(err);
+        }
+        for pattern in [Some(&self.asset), self.checksum_asset.as_ref(), self.signature_asset.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if let Err(err) = glob::Pattern::new(pattern) {
+                errors.push(eyre!("github_release: invalid glob {}: {}", pattern, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Only the name of the credential is configured, the credential is never written to logs,
+/// the build manifest or the attestation.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TarballAuth {
+    /// read from PKG_BUILDER_SECRET_<NAME> or ~/.pkg-builder/secrets/<name>
+    #[schemars(example = json!("artifactory-token"))]
+    pub credential: Option<String>,
+    /// header the credential is sent in as is, e.g. "Bearer <token>", defaults to Authorization
+    #[schemars(example = json!("X-JFrog-Art-Api"))]
+    pub header: Option<String>,
+    /// query parameter the credential is sent in instead of a header, e.g. "private_token"
+    pub query_param: Option<String>,
+    /// further headers of the request, e.g. { Accept = "application/octet-stream" }
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Validation for TarballAuth {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+        let name_re = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+
+        if let Some(credential) = &self.credential {
+            if !name_re.is_match(credential) {
+                errors.push(eyre!("tarball_auth: invalid credential name {}", credential));
+            }
+        } else if self.header.is_some() || self.query_param.is_some() {
+            errors.push(eyre!("tarball_auth: header and query_param need a credential"));
+        }
+        if self.header.is_some() && self.query_param.is_some() {
+            errors.push(eyre!("tarball_auth: set either header or query_param"));
+        }
+        let header_names = self.header.iter().chain(self.headers.keys());
+        for name in header_names.chain(self.query_param.iter()) {
+            if !name_re.is_match(name) {
+                errors.push(eyre!("tarball_auth: invalid header or parameter name {}", name));
+            }
+        }
+        for (name, value) in self.headers.iter() {
+            if value.contains(['\n', '\r']) {
+                errors.push(eyre!("tarball_auth: header {} contains a line break", name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SubModule {
+    /// commit the submodule is checked out at
+    pub commit: String,
+    /// path of the submodule in the repository
+    #[schemars(example = json!("vendor/nim-libbacktrace"))]
+    pub path: String,
+}
+
+impl Validation for SubModule {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("commit", &self.commit) {
+            errors.push(err);
+        }
+
+        if let Err(err) = validate_not_empty("path", &self.path) {
+            errors.push(err);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Keys allowed to sign the git tag, or the tagged commit.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AllowedSigners {
+    /// armored gpg public keys or an ssh allowed signers file, relative to the config file
+    #[schemars(example = json!("keys/maintainers.asc"))]
+    pub key_file: String,
+    /// gpg primary key fingerprints or ssh key fingerprints, e.g. "SHA256:..."
+    pub fingerprints: Vec<String>,
+}
+
+impl AllowedSigners {
+    pub fn get_fingerprints(&self) -> Vec<String> {
+        self.fingerprints
+            .iter()
+            .map(|fingerprint| match fingerprint.starts_with("SHA256:") {
+                true => fingerprint.clone(),
+                false => fingerprint.replace(' ', "").to_uppercase(),
+            })
+            .collect()
+    }
+}
+
+impl Validation for AllowedSigners {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("allowed_signers.key_file", &self.key_file) {
+            errors.push(err);
+        }
+        if self.fingerprints.is_empty() {
+            errors.push(eyre!("field: allowed_signers.fingerprints cannot be empty"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Client the git source is cloned with.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GitClient {
+    /// gitoxide, the git command line when gitoxide fails, e.g. for git-lfs
+    Auto,
+    /// the git command line, needs git and git-lfs
+    #[default]
+    Cli,
+    /// gitoxide only, needs no git installed
+    Gitoxide,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GitPackageTypeConfig {
+    /// tag the source is checked out at
+    #[schemars(example = json!("v24.5.0"))]
+    pub git_tag: String,
+    /// repository the source is cloned from
+    #[schemars(example = json!("https://github.com/status-im/nimbus-eth2.git"))]
+    pub git_url: String,
+    /// submodules checked out at pinned commits, instead of the commits recorded by the tag
+    pub submodules: Vec<SubModule>,
+    /// toolchain the package is built with
+    pub language_env: LanguageEnv,
+    /// uscan style substitutions turning git_tag into version_number, e.g. ["s/^v//"]
+    /// version_number can be left out when given
+    pub uversion_mangle: Option<Vec<String>>,
+    /// fails the build unless the tag, or the tagged commit, is signed by allowed_signers
+    pub require_signed_tag: Option<bool>,
+    /// keys allowed to sign git_tag, see require_signed_tag
+    pub allowed_signers: Option<AllowedSigners>,
+    /// packages the debian dir of the git tag as is instead of generating it from spec_file,
+    /// its changelog gets an entry for the configured version when it has none
+    pub use_upstream_debian_dir: Option<bool>,
+    /// how the source is cloned, defaults to cli
+    pub git_client: Option<GitClient>,
+}
+
+impl GitPackageTypeConfig {
+    /// Signers the tag is verified against, None when no signature is required.
+    pub fn get_required_signers(&self) -> Option<&AllowedSigners> {
+        match self.require_signed_tag {
+            Some(true) => self.allowed_signers.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Validation for GitPackageTypeConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("git_tag", &self.git_tag) {
+            errors.push(err);
+        }
+
+        if let Err(err) = validate_not_empty("git_url", &self.git_url) {
+            errors.push(err);
+        }
+
+        match (&self.allowed_signers, self.require_signed_tag) {
+            (Some(allowed_signers), _) => {
+                if let Err(mut signers_errors) = allowed_signers.validate() {
+                    errors.append(&mut signers_errors);
+                }
+            }
+            (None, Some(true)) => {
+                errors.push(eyre!(
+                    "field: allowed_signers is required by require_signed_tag"
+                ));
+            }
+            (None, _) => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(tag = "package_type", rename_all = "lowercase")]
+pub enum PackageType {
+    Default(DefaultPackageTypeConfig),
+    Git(GitPackageTypeConfig),
+    #[default]
+    Virtual,
+}
+
+impl Validation for PackageType {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        match self {
+            PackageType::Default(config) => config.validate(),
+            PackageType::Git(config) => config.validate(),
+            PackageType::Virtual => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Default, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PackageFields {
+    /// can be left out with use_upstream_debian_dir
+    #[schemars(example = json!("hello-world-rust.sss"))]
+    #[serde(default)]
+    pub spec_file: String,
+    /// name of the source and the main binary package
+    #[schemars(example = json!("hello-world-rust"))]
+    pub package_name: String,
+    /// upstream version of the package
+    #[schemars(example = json!("1.0.0"))]
+    #[serde(default)]
+    pub version_number: String,
+    /// debian revision, raised for packaging only changes
+    #[schemars(example = json!("1"))]
+    pub revision_number: String,
+    /// Homepage of debian/control
+    #[schemars(example = json!("https://github.com/eth-pkg/pkg-builder#examples"))]
+    pub homepage: String,
+    /// debian epoch, e.g. "1" for version 1:1.0.0-1, never part of artifact file names
+    pub epoch: Option<String>,
+    /// Standards-Version of debian/control, defaults to 4.5.1
+    #[schemars(example = json!("4.6.2"))]
+    pub standards_version: Option<String>,
+    /// debhelper compat level, defaults to the one debcrafter generates
+    #[schemars(example = json!(13))]
+    pub debhelper_compat: Option<u32>,
+}
+
+pub fn validate_epoch(epoch: &str) -> Result<()> {
+    if epoch.is_empty() || !epoch.chars().all(|c| c.is_ascii_digit()) {
+        return Err(eyre!(
+            "field: epoch must be an unsigned integer without the trailing ':', got '{}'",
+            epoch
+        ));
+    }
+    Ok(())
+}
+
+pub const DEFAULT_STANDARDS_VERSION: &str = "4.5.1";
+// older levels are deprecated by debhelper
+pub const MIN_DEBHELPER_COMPAT: u32 = 10;
+
+impl PackageFields {
+    pub fn get_standards_version(&self) -> &str {
+        self.standards_version.as_deref().unwrap_or(DEFAULT_STANDARDS_VERSION)
+    }
+
+    /// The chosen standards version and compat level have to be known by the tools of the suite.
+    pub fn validate_debian_policy(&self, distribution: &DistributionInfo) -> Result<()> {
+        if let Some(standards_version) = &self.standards_version {
+            if !version_satisfies(distribution.standards_version, standards_version) {
+                return Err(eyre!(
+                    "field: standards_version {} is newer than debian-policy {} of {}",
+                    standards_version,
+                    distribution.standards_version,
+                    distribution.codename
+                ));
+            }
+        }
+        if let Some(compat) = self.debhelper_compat {
+            if !(MIN_DEBHELPER_COMPAT..=distribution.debhelper_compat).contains(&compat) {
+                return Err(eyre!(
+                    "field: debhelper_compat {} is not supported on {}, use {} to {}",
+                    compat,
+                    distribution.codename,
+                    MIN_DEBHELPER_COMPAT,
+                    distribution.debhelper_compat
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Chosen values older than what the suite supports.
+    pub fn get_debian_policy_warnings(&self, distribution: &DistributionInfo) -> Vec<String> {
+        let mut warnings = vec![];
+        // only major.minor changes require package changes
+        let major_minor = |version: &str| version.split('.').take(2).collect::<Vec<&str>>().join(".");
+        let standards_version = major_minor(self.get_standards_version());
+        if self.standards_version.is_some() && !version_satisfies(&standards_version, &major_minor(distribution.standards_version)) {
+            warnings.push(format!(
+                "standards_version {} is outdated, {} ships debian-policy {}",
+                self.get_standards_version(),
+                distribution.codename,
+                distribution.standards_version
+            ));
+        }
+        if let Some(compat) = self.debhelper_compat {
+            if compat < distribution.debhelper_compat {
+                warnings.push(format!(
+                    "debhelper_compat {} is outdated, {} supports compat level {}",
+                    compat, distribution.codename, distribution.debhelper_compat
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Full debian version as in debian/changelog and .changes, e.g. 1:1.0.0-1
+    pub fn get_debian_version(&self) -> String {
+        match &self.epoch {
+            Some(epoch) => format!("{}:{}", epoch, self.get_file_version()),
+            None => self.get_file_version(),
+        }
+    }
+
+    /// Version used in artifact file names, the epoch is omitted, e.g. 1.0.0-1
+    pub fn get_file_version(&self) -> String {
+        format!("{}-{}", self.version_number, self.revision_number)
+    }
+}
+
+impl Validation for PackageFields {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        self.validate_fields(true)
+    }
+}
+
+impl PackageFields {
+    /// spec_file is not required when the debian dir comes from the upstream source.
+    pub fn validate_fields(&self, requires_spec_file: bool) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if requires_spec_file {
+            if let Err(err) = validate_not_empty("spec_file", &self.spec_file) {
+                errors.push(err);
+            }
+        }
+        if let Err(err) = validate_not_empty("package_name", &self.package_name) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("version_number", &self.version_number) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("revision_number", &self.revision_number) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("homepage", &self.homepage) {
+            errors.push(err);
+        }
+        if let Some(epoch) = &self.epoch {
+            if let Err(err) = validate_epoch(epoch) {
+                errors.push(err);
+            }
+        }
+        if let Some(standards_version) = &self.standards_version {
+            if !Regex::new(r"^\d+\.\d+\.\d+(\.\d+)?$").unwrap().is_match(standards_version) {
+                errors.push(eyre!(
+                    "field: standards_version must look like 4.6.2, got '{}'",
+                    standards_version
+                ));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Tool the package is built with.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// sbuild in an unshare chroot
+    Sbuild,
+    /// dpkg-buildpackage in a docker container
+    Docker,
+    /// dpkg-buildpackage in a rootless podman container
+    Podman,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Sbuild => "sbuild",
+            Backend::Docker => "docker",
+            Backend::Podman => "podman",
+        }
+    }
+}
+
+/// Tool the commands needing root are run through.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegeTool {
+    /// sudo -n, or sudo -S with privilege_prompt
+    Sudo,
+    /// doas -n, or doas with privilege_prompt
+    Doas,
+    /// pkexec, asks through the polkit agent of the session only with privilege_prompt
+    Pkexec,
+    /// fails the build when a command needs root
+    Never,
+}
+
+impl PrivilegeTool {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrivilegeTool::Sudo => "sudo",
+            PrivilegeTool::Doas => "doas",
+            PrivilegeTool::Pkexec => "pkexec",
+            PrivilegeTool::Never => "never",
+        }
+    }
+}
+
+/// Where autopkgtest runs the tests of the built debs.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AutopkgtestTestbed {
+    /// a qemu VM booted from a cloud image of the suite
+    Qemu,
+    /// a podman container of the suite, for architectures impractical to boot a VM of
+    Podman,
+}
+
+/// Debian architecture of the package, all and any are resolved against the build host.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Arch {
+    #[default]
+    Amd64,
+    Arm64,
+    Armhf,
+    Riscv64,
+    /// architecture independent, built on the host architecture
+    All,
+    /// built for the host architecture
+    Any,
+}
+
+impl Arch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::Arm64 => "arm64",
+            Arch::Armhf => "armhf",
+            Arch::Riscv64 => "riscv64",
+            Arch::All => "all",
+            Arch::Any => "any",
+        }
+    }
+
+    /// Debian name of the architecture pkg-builder runs on.
+    pub fn host() -> Arch {
+        match std::env::consts::ARCH {
+            "aarch64" => Arch::Arm64,
+            "arm" => Arch::Armhf,
+            "riscv64" => Arch::Riscv64,
+            _ => Arch::Amd64,
+        }
+    }
+
+    /// Architecture of the build chroot, also used in the names of the .changes and .build files.
+    pub fn get_build_arch(&self) -> &'static str {
+        match self {
+            Arch::All | Arch::Any => Arch::host().as_str(),
+            arch => arch.as_str(),
+        }
+    }
+
+    /// Architecture in the file names of the built debs, e.g. hello-world_1.0.0-1_all.deb.
+    pub fn get_deb_arch(&self) -> &'static str {
+        match self {
+            Arch::All => "all",
+            arch => arch.get_build_arch(),
+        }
+    }
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Default, Clone, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BuildEnv {
+    /// distribution the package is built for, e.g. bookworm or noble
+    #[schemars(example = json!("bookworm"))]
+    pub codename: String,
+    /// distributions `package --all-distros` builds the package for in parallel, each with
+    /// this config and its own codename, into <workdir>/<codename> when workdir is set
+    #[schemars(example = json!(["bookworm", "jammy jellyfish", "noble numbat"]))]
+    pub codenames: Option<Vec<String>>,
+    /// amd64, arm64, armhf, riscv64, all or any
+    pub arch: Arch,
+    /// version of pkg-builder the config was written for, checked on every run
+    #[schemars(example = json!("0.2.8"))]
+    pub pkg_builder_version: String,
+    /// commit of debcrafter generating the debian dir
+    #[schemars(example = json!("8189263"))]
+    pub debcrafter_version: String,
+    /// directory of the chroot tarballs and testbed images, defaults to ~/.cache/sbuild
+    #[schemars(example = json!("~/.cache/sbuild"))]
+    pub sbuild_cache_dir: Option<String>,
+    /// replaced by backend = "docker", still honored when backend is not set
+    pub docker: Option<bool>,
+    /// defaults to sbuild, docker and podman build in a container image of the suite
+    /// for CI systems that cannot run unshare chroots
+    pub backend: Option<Backend>,
+    /// image the docker and podman backends build in, pinned by digest, defaults to the
+    /// container image of the suite at the digest it was pulled with
+    #[schemars(example = json!("docker.io/library/debian@sha256:<RepoDigest of docker image inspect debian:bookworm>"))]
+    pub container_image: Option<String>,
+    /// runs lintian on the built package
+    pub run_lintian: Option<bool>,
+    /// runs piuparts install, upgrade and removal tests on the built package
+    pub run_piuparts: Option<bool>,
+    /// runs the autopkgtests of debian/tests against the built package
+    pub run_autopkgtest: Option<bool>,
+    /// defaults to qemu for amd64 and arm64, to podman for the other architectures
+    pub autopkgtest_testbed: Option<AutopkgtestTestbed>,
+    /// builds a foreign arch with the cross toolchain in a chroot of the host architecture,
+    /// otherwise the build runs in a chroot of arch emulated by qemu-user-static
+    pub cross_build: Option<bool>,
+    /// checks the systemd units shipped by the built debs
+    pub run_unit_checks: Option<bool>,
+    /// checks that the maintainer scripts install without prompting
+    pub run_debconf_checks: Option<bool>,
+    /// version of lintian, checked before it runs
+    #[schemars(example = json!("2.116.3"))]
+    pub lintian_version: String,
+    /// version of piuparts, checked before it runs
+    #[schemars(example = json!("1.1.7"))]
+    pub piuparts_version: String,
+    /// version of autopkgtest, checked before it runs
+    #[schemars(example = json!("5.28"))]
+    pub autopkgtest_version: String,
+    /// version of sbuild, checked before it runs
+    #[schemars(example = json!("0.85.6"))]
+    pub sbuild_version: String,
+    /// directory the package is built in, defaults to ~/.pkg-builder/packages/<codename>
+    #[schemars(example = json!("~/.pkg-builder/packages/bookworm"))]
+    #[serde(deserialize_with = "deserialize_option_empty_string")]
+    pub workdir: Option<String>,
+    /// overrides the distribution mirror, e.g. a local mirror for hermetic builds
+    #[schemars(example = json!("http://localhost:8080/debian"))]
+    pub repo_url: Option<String>,
+    /// number of parallel build jobs, 0 or not set means all available cores
+    #[schemars(example = json!(8))]
+    pub jobs: Option<usize>,
+    /// compressed build logs kept per package under <workdir>/.logs, defaults to 10
+    #[schemars(example = json!(10))]
+    pub log_keep_count: Option<usize>,
+    /// upper bound of the kept build logs per package in MB, defaults to 200
+    #[schemars(example = json!(200))]
+    pub log_keep_size_mb: Option<u64>,
+    /// builds in /build/pkg-<package_name> inside of the chroot and maps it away in debug info,
+    /// so binaries do not depend on the host workdir, backend sbuild only
+    pub fixed_build_path: Option<bool>,
+    /// user the package is built as inside of the chroot, defaults to the invoking user,
+    /// or sbuild when invoked as root, some build systems misbehave when run as root,
+    /// backend sbuild only
+    #[schemars(example = json!("builder"))]
+    pub build_user: Option<String>,
+    /// fails the build when the suite is past its end of life instead of warning,
+    /// set for every package by `release --fail-on-eol`
+    pub fail_on_eol: Option<bool>,
+    /// installs the toolchain of the language env once into a cached chroot layer instead of
+    /// on every build, the layer is rebuilt when any toolchain pin changes
+    pub toolchain_cache: Option<bool>,
+    /// builds in a snapshot of the unpacked chroot instead of unpacking the cached tarball, a btrfs
+    /// snapshot with the sbuild cache dir on btrfs, a fuse-overlayfs overlay otherwise, without
+    /// either the tarball is unpacked as before
+    pub snapshot_chroot: Option<bool>,
+    /// days after its creation `env update` recreates the cached chroot instead of upgrading
+    /// its packages in place, defaults to 30
+    #[schemars(example = json!(14))]
+    pub chroot_max_age_days: Option<u64>,
+    /// runs piuparts and the creation of the autopkgtest qemu image as root, defaults to sudo
+    pub privilege_tool: Option<PrivilegeTool>,
+    /// lets the privilege tool ask for a password, otherwise it must not need one and the build
+    /// fails before it starts when it does
+    pub privilege_prompt: Option<bool>,
+    /// fails the build unless sbuild, lintian, piuparts, autopkgtest and debcrafter are installed
+    /// in exactly their pinned versions, instead of checking for the minimum version
+    pub strict_tool_versions: Option<bool>,
+    /// shows a line per chroot setup command instead of its output, the output of a failed
+    /// command is shown, the full output is kept in provision/<step>.log next to the build log
+    pub quiet_chroot_setup: Option<bool>,
+    /// caching proxies the dependencies of the build are downloaded through
+    pub proxies: Option<ProxyConfig>,
+    /// adds the git commit of the config to the control of every deb as Pkg-Builder-Recipe,
+    /// the debs then change with every commit of the config, pinned hashes included
+    pub embed_recipe_revision: Option<bool>,
+    /// fails the build when the build itself installed, removed or upgraded chroot packages
+    /// after the build dependencies were installed, instead of warning
+    pub fail_on_build_installs: Option<bool>,
+    /// incremental builds for packaging development, set by `package --dev`, inputs changed
+    /// since the last build of the version are only warned about
+    pub dev: Option<bool>,
+    /// fails before the build when it would download anything, the downloads have to be in the
+    /// store of `pkg-builder fetch`, set by `package --offline`
+    pub offline: Option<bool>,
+    /// builds a version built before with other tool pins or toolchains instead of failing,
+    /// set by `package --allow-changed-inputs`
+    pub allow_changed_inputs: Option<bool>,
+}
+
+impl BuildEnv {
+    pub fn uses_sbuild(&self) -> bool {
+        matches!(self.get_backend(), Some(Backend::Sbuild) | None)
+    }
+
+    /// Architecture of the build chroot, the host one for cross builds.
+    pub fn get_chroot_arch(&self) -> &'static str {
+        match self.cross_build {
+            Some(true) => Arch::host().as_str(),
+            _ => self.arch.get_build_arch(),
+        }
+    }
+
+    /// The configured backend, the legacy docker = true when none is.
+    pub fn get_backend(&self) -> Option<Backend> {
+        match (self.backend, self.docker) {
+            (None, Some(true)) => Some(Backend::Docker),
+            (backend, _) => backend,
+        }
+    }
+
+    /// Neither a cross build nor a build for a foreign arch, which container backends cannot do.
+    pub fn builds_for_host(&self) -> bool {
+        self.cross_build != Some(true) && self.arch.get_build_arch() == Arch::host().as_str()
+    }
+}
+
+impl Validation for BuildEnv {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.cross_build == Some(true) && matches!(self.arch, Arch::All | Arch::Any) {
+            errors.push(eyre!("field: cross_build needs arch amd64, arm64, armhf or riscv64, not {}", self.arch));
+        }
+        if let (Some(true), Some(backend @ (Backend::Sbuild | Backend::Podman))) = (self.docker, self.backend) {
+            errors.push(eyre!("field: docker = true is replaced by backend, it conflicts with backend {}", backend.as_str()));
+        }
+        if let Some(image) = &self.container_image {
+            let is_pinned = image
+                .split_once("@sha256:")
+                .is_some_and(|(_, digest)| digest.len() == 64 && digest.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+            if !is_pinned {
+                errors.push(eyre!("field: container_image has to be pinned by digest, e.g. debian@sha256:<digest>, not {}", image));
+            }
+        }
+        if let Some(backend @ (Backend::Docker | Backend::Podman)) = self.get_backend() {
+            if !self.builds_for_host() {
+                errors.push(eyre!(
+                    "field: backend {} builds for the host architecture only, use sbuild for {}",
+                    backend.as_str(),
+                    self.arch
+                ));
+            }
+            // the container builds as root in /build/<source dir>
+            if self.fixed_build_path == Some(true) {
+                errors.push(eyre!("field: fixed_build_path needs backend sbuild, not {}", backend.as_str()));
+            }
+            if self.build_user.is_some() {
+                errors.push(eyre!("field: build_user needs backend sbuild, not {}", backend.as_str()));
+            }
+        }
+
+        if let Err(err) = validate_not_empty("codename", &self.codename) {
+            errors.push(err);
+        }
+        if let Some(codenames) = &self.codenames {
+            if codenames.is_empty() {
+                errors.push(eyre!("field: codenames cannot be empty"));
+            }
+            for (index, codename) in codenames.iter().enumerate() {
+                if let Err(err) = validate_not_empty("codenames", codename) {
+                    errors.push(err);
+                } else if codenames[..index].contains(codename) {
+                    errors.push(eyre!("field: codenames has {} twice", codename));
+                }
+            }
+        }
+        if let Err(err) = validate_not_empty("pkg_builder_version", &self.pkg_builder_version) {
+            errors.push(err);
+        }
+
+        if let Err(err) = validate_not_empty("debcrafter_version", &self.debcrafter_version) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("lintian_version", &self.lintian_version) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("piuparts_version", &self.piuparts_version) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("autopkgtest_version", &self.autopkgtest_version) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("sbuild_version", &self.sbuild_version) {
+            errors.push(err);
+        }
+        if let Some(repo_url) = &self.repo_url {
+            if let Err(err) = validate_not_empty("repo_url", repo_url) {
+                errors.push(err);
+            }
+        }
+        if let Some(build_user) = &self.build_user {
+            if build_user == "root" {
+                errors.push(eyre!("field: build_user must not be root, leave it empty to build as the default user"));
+            } else if !Regex::new(r"^[a-z_][a-z0-9_-]*$").unwrap().is_match(build_user) {
+                errors.push(eyre!("field: build_user '{}' is not a valid user name", build_user));
+            }
+        }
+        if self.chroot_max_age_days == Some(0) {
+            errors.push(eyre!("field: chroot_max_age_days must be at least 1"));
+        }
+        if let Some(proxies) = &self.proxies {
+            if let Err(mut proxy_errors) = proxies.validate() {
+                errors.append(&mut proxy_errors);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Caching proxies of a build farm, e.g. apt-cacher-ng, an Athens go module proxy,
+/// a Verdaccio npm registry or a crates.io mirror.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// http proxy of apt inside of the build chroot, e.g. "http://apt-cacher:3142"
+    pub apt: Option<String>,
+    /// passed to the build as GOPROXY, e.g. "http://athens:3000"
+    pub go: Option<String>,
+    /// passed to the build as NPM_CONFIG_REGISTRY, e.g. "http://verdaccio:4873"
+    pub npm: Option<String>,
+    /// registry replacing crates.io, e.g. "sparse+http://crates-mirror/index/"
+    pub cargo: Option<String>,
+}
+
+impl Validation for ProxyConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        let proxies = [("apt", &self.apt), ("go", &self.go), ("npm", &self.npm), ("cargo", &self.cargo)];
+        for (name, url) in proxies {
+            let Some(url) = url else {
+                continue;
+            };
+            let url = url.strip_prefix("sparse+").filter(|_| name == "cargo").unwrap_or(url);
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(eyre!("field: proxies.{} must be an http or https url, got {}", name, url));
+            } else if url.contains(['\'', '"', ' ']) {
+                errors.push(eyre!("field: proxies.{} must not contain quotes or spaces", name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// libfoo (>= 1.2) [amd64] | libbar, ${shlibs:Depends}
+pub fn validate_relation(name: &str, relation: &str) -> Result<()> {
+    let alternative = Regex::new(
+        r"^(\$\{[A-Za-z0-9:.-]+\}|[a-z0-9][a-z0-9+.-]+(:[a-z0-9-]+)?(\s*\(\s*(<<|<=|=|>=|>>)\s*[A-Za-z0-9.+~:-]+\s*\))?(\s*\[[^\]]+\])?)$",
+    )
+    .unwrap();
+    let is_valid = !relation.contains(',')
+        && relation
+            .split('|')
+            .all(|alternative_relation| alternative.is_match(alternative_relation.trim()));
+    if !is_valid {
+        return Err(eyre!("field: {} has invalid relation '{}'", name, relation));
+    }
+    Ok(())
+}
+
+/// Relationships merged into the debcrafter generated debian/control. A relation on
+/// a package that is already listed replaces the generated one, others are appended.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ControlOverrides {
+    /// binary package stanza to patch, defaults to package_fields.package_name
+    #[schemars(example = json!("geth"))]
+    pub package: Option<String>,
+    /// relations added to Depends
+    #[schemars(example = json!(["adduser", "libssl3 (>= 3.0)"]))]
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// relations added to Conflicts
+    #[schemars(example = json!(["geth-unstable"]))]
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    /// relations added to Provides
+    #[schemars(example = json!(["ethereum-execution-client"]))]
+    #[serde(default)]
+    pub provides: Vec<String>,
+}
+
+impl ControlOverrides {
+    pub fn get_relations(&self) -> Vec<(&'static str, &Vec<String>)> {
+        vec![
+            ("Depends", &self.depends),
+            ("Conflicts", &self.conflicts),
+            ("Provides", &self.provides),
+        ]
+    }
+}
+
+impl Validation for ControlOverrides {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Some(package) = &self.package {
+            if let Err(err) = validate_not_empty("control_overrides.package", package) {
+                errors.push(err);
+            }
+        }
+        for (field, relations) in self.get_relations() {
+            let name = format!("control_overrides.{}", field.to_lowercase());
+            for relation in relations {
+                if let Err(err) = validate_relation(&name, relation) {
+                    errors.push(err);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Multi-Arch field of the binary packages, set in the debcrafter generated debian/control.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MultiArchConfig {
+    /// co-installable for several architectures, e.g. libraries, their files are checked for conflicts
+    #[schemars(example = json!(["libsecp256k1-1"]))]
+    #[serde(default)]
+    pub same: Vec<String>,
+    /// satisfies the dependencies of other architectures, e.g. tools
+    #[schemars(example = json!(["geth-tools"]))]
+    #[serde(default)]
+    pub foreign: Vec<String>,
+    /// satisfies dependencies on package:any
+    #[schemars(example = json!(["python3-eth"]))]
+    #[serde(default)]
+    pub allowed: Vec<String>,
+}
+
+impl MultiArchConfig {
+    pub fn get_fields(&self) -> Vec<(&'static str, &Vec<String>)> {
+        vec![
+            ("same", &self.same),
+            ("foreign", &self.foreign),
+            ("allowed", &self.allowed),
+        ]
+    }
+}
+
+impl Validation for MultiArchConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+        let mut seen: BTreeMap<&str, &str> = BTreeMap::new();
+
+        for (value, packages) in self.get_fields() {
+            for package in packages {
+                if let Err(err) = validate_not_empty(&format!("multi_arch.{}", value), package) {
+                    errors.push(err);
+                }
+                if let Some(other) = seen.insert(package, value) {
+                    errors.push(eyre!("field: multi_arch lists {} as both {} and {}", package, other, value));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Checks the GLIBC and GLIBCXX symbol versions the ELF binaries of the built debs require.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AbiConfig {
+    /// newest GLIBC version the binaries may require, e.g. "2.31" to also run on older releases,
+    /// defaults to the glibc of the codename
+    pub max_glibc_version: Option<String>,
+    /// newest GLIBCXX version of libstdc++, defaults to the one of the codename
+    #[schemars(example = json!("3.4.28"))]
+    pub max_glibcxx_version: Option<String>,
+    /// binaries requiring newer versions fail the build, otherwise they are warnings, defaults to true
+    pub fail_on_newer: Option<bool>,
+}
+
+impl Validation for AbiConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        let fields = [("max_glibc_version", &self.max_glibc_version), ("max_glibcxx_version", &self.max_glibcxx_version)];
+        for (field, version) in fields {
+            let Some(version) = version else {
+                continue;
+            };
+            if !version.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())) {
+                errors.push(eyre!("field: abi.{} must be a version like 2.36, got {}", field, version));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Commands passed to sbuild at the matching stage, so backend sbuild only, their output
+/// is gathered into hooks/<stage>.log next to the built artifacts. A % is passed as is, sbuild
+/// does not expand its % escapes in them.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BuildHooks {
+    /// run on the host before the chroot is set up
+    #[schemars(example = json!(["./scripts/fetch-assets.sh"]))]
+    #[serde(default)]
+    pub pre_build_commands: Vec<String>,
+    /// run inside the chroot after the package was built
+    #[schemars(example = json!(["ls -la /build"]))]
+    #[serde(default)]
+    pub finished_build_commands: Vec<String>,
+    /// run inside the chroot before it is removed, also after failed builds
+    #[schemars(example = json!(["rm -rf /root/.cache/go-build"]))]
+    #[serde(default)]
+    pub chroot_cleanup_commands: Vec<String>,
+}
+
+impl BuildHooks {
+    /// (sbuild option, stage name, commands)
+    pub fn get_stages(&self) -> Vec<(&'static str, &'static str, &Vec<String>)> {
+        vec![
+            ("--pre-build-commands", "pre-build", &self.pre_build_commands),
+            ("--finished-build-commands", "finished-build", &self.finished_build_commands),
+            ("--chroot-cleanup-commands", "chroot-cleanup", &self.chroot_cleanup_commands),
+        ]
+    }
+}
+
+impl Validation for BuildHooks {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for (_, stage, commands) in self.get_stages() {
+            let name = format!("build_hooks.{}_commands", stage.replace('-', "_"));
+            for command in commands {
+                if let Err(err) = validate_not_empty(&name, command) {
+                    errors.push(err);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// third-party sources whose signing keys can be pinned
+pub const TRUST_SOURCES: [&str; 8] = ["rust", "go", "node", "java", "nim", "zig", "microsoft", "github_release"];
+
+/// Pinned signing key material of a third-party source.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TrustedKey {
+    /// what the key signs: rust, go, node, java, nim, zig, microsoft or github_release. The
+    /// toolchains other than rust are verified against the detached signature <url>.asc
+    /// published next to their download
+    #[schemars(example = json!("rust"))]
+    pub source: String,
+    /// primary key fingerprints allowed to sign the source
+    #[schemars(example = json!(["108F66205EAEB0AAA8DD5E1C85AB96E6FA1BE5FE"]))]
+    pub fingerprints: Vec<String>,
+    /// armored public keys, relative to the config file
+    #[schemars(example = json!("keys/rust.asc"))]
+    pub key_file: String,
+}
+
+impl TrustedKey {
+    pub fn get_fingerprints(&self) -> Vec<String> {
+        self.fingerprints
+            .iter()
+            .map(|fingerprint| fingerprint.replace(' ', "").to_uppercase())
+            .collect()
+    }
+}
+
+impl Validation for TrustedKey {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if !TRUST_SOURCES.contains(&self.source.as_str()) {
+            errors.push(eyre!(
+                "trust: unknown source '{}', supported sources: {}",
+                self.source,
+                TRUST_SOURCES.join(", ")
+            ));
+        }
+        if self.fingerprints.is_empty() {
+            errors.push(eyre!("trust: fingerprints of {} cannot be empty", self.source));
+        }
+        for fingerprint in self.get_fingerprints() {
+            if fingerprint.len() != 40 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+                errors.push(eyre!("trust: {} is not a full key fingerprint", fingerprint));
+            }
+        }
+        if let Err(err) = validate_not_empty("key_file", &self.key_file) {
+            errors.push(err);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// When present, third-party toolchains are only installed if signed by a declared key.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TrustConfig {
+    /// keys the downloaded toolchains and sources are verified against, rust and microsoft
+    /// are verified against their pinned default key without one
+    #[serde(default)]
+    pub keys: Vec<TrustedKey>,
+}
+
+impl TrustConfig {
+    pub fn get_key(&self, source: &str) -> Option<&TrustedKey> {
+        self.keys.iter().find(|key| key.source == source)
+    }
+}
+
+impl Validation for TrustConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for (index, key) in self.keys.iter().enumerate() {
+            if let Err(mut key_errors) = key.validate() {
+                errors.append(&mut key_errors);
+            }
+            if self.keys[..index].iter().any(|other| other.source == key.source) {
+                errors.push(eyre!("trust: source {} is declared twice", key.source));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Command run in a container with the built package installed.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SmokeTest {
+    /// run with sh -c, e.g. "geth --version"
+    pub command: String,
+    /// expected exit code of the command, defaults to 0
+    #[schemars(example = json!(0))]
+    pub exit_code: Option<i32>,
+    /// has to match stdout or stderr of the command
+    #[schemars(example = json!("^Geth\\s+Version: 1\\.14"))]
+    pub output_regex: Option<String>,
+}
+
+impl Validation for SmokeTest {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("smoke_test.tests.command", &self.command) {
+            errors.push(err);
+        }
+        if let Some(output_regex) = &self.output_regex {
+            if let Err(err) = Regex::new(output_regex) {
+                errors.push(eyre!("smoke_test: invalid output_regex {}: {}", output_regex, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Quick runtime checks of the built package, much faster than autopkgtest.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SmokeTestConfig {
+    /// container image, defaults to the image of the distribution
+    #[schemars(example = json!("docker.io/library/debian:bookworm"))]
+    pub image: Option<String>,
+    /// commands run after the built debs were installed
+    #[serde(default)]
+    pub tests: Vec<SmokeTest>,
+}
+
+impl Validation for SmokeTestConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.tests.is_empty() {
+            errors.push(eyre!("field: smoke_test.tests cannot be empty"));
+        }
+        for test in self.tests.iter() {
+            if let Err(mut test_errors) = test.validate() {
+                errors.append(&mut test_errors);
+            }
+        }
+        if let Some(image) = &self.image {
+            if let Err(err) = validate_not_empty("smoke_test.image", image) {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// The built debs autopkgtest and the smoke tests install, every deb but debug symbols by default.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TestDepsConfig {
+    /// apt's default when unset
+    pub install_recommends: Option<bool>,
+    /// globs of binary package names left out of the testbeds
+    #[schemars(example = json!(["*-doc"]))]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl Validation for TestDepsConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for pattern in self.exclude.iter() {
+            if let Err(err) = glob::Pattern::new(pattern) {
+                errors.push(eyre!("field: test_deps.exclude has invalid glob {}: {}", pattern, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Masks secrets in the build log, hook logs and build manifest before they are written.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+    /// regexes on top of the built in credential formats, only the first capture group
+    /// is masked if the regex has one, e.g. "MY_SERVICE_KEY=(\\S+)"
+    #[schemars(example = json!(["MY_SERVICE_KEY=(\\S+)"]))]
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl Validation for RedactConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for pattern in self.patterns.iter() {
+            if let Err(err) = validate_not_empty("redact.patterns", pattern) {
+                errors.push(err);
+            } else if let Err(err) = Regex::new(pattern) {
+                errors.push(eyre!("redact: invalid pattern {}: {}", pattern, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Answers to the debconf questions of the package for the automated installs.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DebconfConfig {
+    /// debconf-set-selections file, relative to the config file, loaded into the piuparts,
+    /// autopkgtest and smoke test environments before the package is installed
+    #[schemars(example = json!("debian/preseed.cfg"))]
+    pub preseed_file: String,
+}
+
+impl Validation for DebconfConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("debconf.preseed_file", &self.preseed_file) {
+            errors.push(err);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Patches of the upstream source, copied into debian/patches and applied with quilt in order.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PatchesConfig {
+    /// patch files relative to the config file, the file name is the name in debian/patches/series
+    #[schemars(example = json!(["patches/fix-build-on-arm64.patch"]))]
+    pub files: Vec<String>,
+}
+
+impl Validation for PatchesConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.files.is_empty() {
+            errors.push(eyre!("field: patches.files cannot be empty"));
+        }
+        let mut names = vec![];
+        for file in self.files.iter() {
+            if let Err(err) = validate_not_empty("patches.files", file) {
+                errors.push(err);
+                continue;
+            }
+            let name = file.rsplit('/').next().unwrap_or(file);
+            if name == "series" {
+                errors.push(eyre!("patches: {} cannot be named series", file));
+            } else if names.contains(&name) {
+                errors.push(eyre!("patches: two patches are named {}", name));
+            }
+            names.push(name);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Shell completions and man pages shipped in the upstream source that the spec does not install.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExtrasConfig {
+    /// binary package they are installed into, defaults to package_name
+    #[schemars(example = json!("geth"))]
+    pub package: Option<String>,
+    /// globs relative to the source root, the built in globs are used when empty
+    #[schemars(example = json!(["completions/*.bash"]))]
+    #[serde(default)]
+    pub completions: Vec<String>,
+    /// man pages, globs relative to the source root, the built in globs are used when empty
+    #[schemars(example = json!(["docs/*.1"]))]
+    #[serde(default)]
+    pub man_pages: Vec<String>,
+}
+
+impl Validation for ExtrasConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for pattern in self.completions.iter().chain(self.man_pages.iter()) {
+            if let Err(err) = glob::Pattern::new(pattern) {
+                errors.push(eyre!("extras: invalid glob {}: {}", pattern, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// AppArmor profiles shipped by the package, installed into /etc/apparmor.d and checked
+/// with apparmor_parser in the autopkgtest testbed.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AppArmorConfig {
+    /// binary package they are installed into, defaults to package_name
+    #[schemars(example = json!("geth"))]
+    pub package: Option<String>,
+    /// profile files relative to the config file, the file name is the profile name, e.g. apparmor/usr.bin.geth
+    #[schemars(example = json!(["apparmor/usr.bin.geth"]))]
+    pub profiles: Vec<String>,
+}
+
+impl Validation for AppArmorConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.profiles.is_empty() {
+            errors.push(eyre!("field: apparmor.profiles cannot be empty"));
+        }
+        let mut names = vec![];
+        for profile in self.profiles.iter() {
+            if let Err(err) = validate_not_empty("apparmor.profiles", profile) {
+                errors.push(err);
+                continue;
+            }
+            let name = profile.rsplit('/').next().unwrap_or(profile);
+            if names.contains(&name) {
+                errors.push(eyre!("apparmor: two profiles are named {}", name));
+            }
+            names.push(name);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Configuration files under /etc rendered from a template, e.g. /etc/default/<package>.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConffilesConfig {
+    /// binary package they are installed into, defaults to package_name
+    #[schemars(example = json!("lodestar"))]
+    pub package: Option<String>,
+    /// templates rendered into conffiles of the package
+    pub files: Vec<ConffileTemplate>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConffileTemplate {
+    /// absolute path under /etc, e.g. /etc/default/lodestar
+    #[schemars(example = json!("/etc/default/lodestar"))]
+    pub path: String,
+    /// content with {{ name }} placeholders, package_name and version are always set
+    #[schemars(example = json!("PORT={{ port }}"))]
+    pub template: String,
+    /// values of the placeholders of template
+    #[schemars(example = json!({ "port": "9000" }))]
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl Validation for ConffilesConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.files.is_empty() {
+            errors.push(eyre!("field: conffiles.files cannot be empty"));
+        }
+        let mut paths = vec![];
+        for conffile in self.files.iter() {
+            let path = &conffile.path;
+            if !path.starts_with("/etc/") || path.ends_with('/') || path.split('/').any(|part| part == "..") {
+                errors.push(eyre!("conffiles: {} must be a file under /etc", path));
+            }
+            if paths.contains(&path) {
+                errors.push(eyre!("conffiles: {} is declared twice", path));
+            }
+            paths.push(path);
+            for name in get_placeholders(&conffile.template) {
+                if !conffile.variables.contains_key(&name) && !CONFFILE_BUILTINS.contains(&name.as_str()) {
+                    errors.push(eyre!("conffiles: {} has no value for {{{{ {} }}}}", path, name));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Scans the upstream source for licenses and compares them with debian/copyright.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LicenseScanConfig {
+    /// globs of source paths left out of the scan, e.g. "testdata" or "*/fixtures"
+    #[schemars(example = json!(["testdata", "*/fixtures"]))]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl Validation for LicenseScanConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        for pattern in self.exclude.iter() {
+            if let Err(err) = glob::Pattern::new(pattern) {
+                errors.push(eyre!("license_scan: invalid glob {}: {}", pattern, err));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Checks of the built debs, evaluated from their contents after the build.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ContentPolicyConfig {
+    /// upper bound of the Installed-Size of each deb in KiB
+    #[schemars(example = json!(102400))]
+    pub max_installed_size_kb: Option<u64>,
+    /// globs of the files under /usr/local and /opt the debs may ship, e.g. "/opt/geth/*"
+    #[schemars(example = json!(["/opt/geth/*"]))]
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+    /// globs of the setuid and setgid files the debs may ship
+    #[schemars(example = json!(["/usr/lib/geth/helper"]))]
+    #[serde(default)]
+    pub allowed_setuid: Vec<String>,
+    /// known libraries the debs may embed a copy of, e.g. "libsecp256k1"
+    #[schemars(example = json!(["libsecp256k1"]))]
+    #[serde(default)]
+    pub allowed_libraries: Vec<String>,
+}
+
+impl Validation for ContentPolicyConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.max_installed_size_kb == Some(0) {
+            errors.push(eyre!("field: content_policy.max_installed_size_kb must be greater than 0"));
+        }
+        for pattern in self.allowed_paths.iter().chain(self.allowed_setuid.iter()) {
+            if let Err(err) = glob::Pattern::new(pattern) {
+                errors.push(eyre!("content_policy: invalid glob {}: {}", pattern, err));
+            }
+        }
+        for library in self.allowed_libraries.iter() {
+            if let Err(err) = validate_not_empty("content_policy.allowed_libraries", library) {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Artifact of the build copied to the export destination.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExportRule {
+    /// glob over the file names of the artifacts, e.g. "*.deb"
+    pub glob: String,
+    /// path relative to the destination, with the placeholders {name}, {package}, {arch}, {ext},
+    /// {source}, {version}, {revision}, {file_version} and {codename}, defaults to "{name}"
+    #[schemars(example = json!("{codename}/{name}"))]
+    pub name: Option<String>,
+}
+
+/// Copies artifacts to a destination after the build and its tests succeeded.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExportsConfig {
+    /// directory relative to the config file
+    #[schemars(example = json!("out"))]
+    pub destination: String,
+    #[serde(default)]
+    pub files: Vec<ExportRule>,
+}
+
+impl Validation for ExportsConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+        let placeholder = Regex::new(r"\{([^}]*)\}").unwrap();
+
+        if let Err(err) = validate_not_empty("exports.destination", &self.destination) {
+            errors.push(err);
+        }
+        if self.files.is_empty() {
+            errors.push(eyre!("field: exports.files cannot be empty"));
+        }
+        for rule in self.files.iter() {
+            if let Err(err) = glob::Pattern::new(&rule.glob) {
+                errors.push(eyre!("exports: invalid glob {}: {}", rule.glob, err));
+            }
+            let Some(name) = &rule.name else {
+                continue;
+            };
+            if name.starts_with('/') || name.split('/').any(|part| part == "..") {
+                errors.push(eyre!("exports: {} must stay inside of the destination", name));
+            }
+            for captures in placeholder.captures_iter(name) {
+                if !EXPORT_PLACEHOLDERS.contains(&&captures[1]) {
+                    errors.push(eyre!("exports: unknown placeholder {} in {}", &captures[0], name));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Stall detection of sbuild, lintian, piuparts and autopkgtest, a stage is stalled when it
+/// prints nothing for stall_minutes.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// minutes without output after which a stage is reported as stalled, defaults to 30
+    #[schemars(example = json!(30))]
+    pub stall_minutes: Option<u64>,
+    /// lines of output shown with the stall warning, defaults to 20
+    #[schemars(example = json!(20))]
+    pub tail_lines: Option<usize>,
+    /// run through sh on a stall, the pid of the stage is in $STALLED_PID, e.g. "ps -ef --forest"
+    pub diagnostic_command: Option<String>,
+    /// kills a stage still running after this many minutes, stages are not killed when not set
+    #[schemars(example = json!(240))]
+    pub timeout_minutes: Option<u64>,
+}
+
+impl Validation for WatchdogConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.stall_minutes == Some(0) {
+            errors.push(eyre!("field: watchdog.stall_minutes cannot be 0"));
+        }
+        if self.timeout_minutes == Some(0) {
+            errors.push(eyre!("field: watchdog.timeout_minutes cannot be 0"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Built-in processor of a post_process step.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PostProcessor {
+    /// tar bundle of the debs
+    Tar,
+    /// squashfs image of the debs
+    Squashfs,
+    /// container image of the suite with the debs installed, saved as an archive
+    Oci,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PostProcessStep {
+    /// name of the step, its outputs are written to post-process/<name>/ next to the debs
+    #[schemars(example = json!("image"))]
+    pub name: String,
+    /// built-in processor, either processor or command is set
+    pub processor: Option<PostProcessor>,
+    /// run on the host in the output dir of the step, with the paths of the build manifest,
+    /// the artifacts dir and the output dir in PKG_BUILDER_MANIFEST, PKG_BUILDER_ARTIFACTS_DIR
+    /// and PKG_BUILDER_OUTPUT_DIR
+    #[schemars(example = json!("../../../scripts/build-appliance.sh"))]
+    pub command: Option<String>,
+    /// base image of the oci processor, defaults to the container image of the suite
+    #[schemars(example = json!("docker.io/library/debian:bookworm-slim"))]
+    pub image: Option<String>,
+}
+
+/// Derived outputs made from the checked debs, e.g. a container image, listed as secondary
+/// artifacts in the build manifest.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PostProcessConfig {
+    /// run in order after the package passed its checks
+    #[serde(default)]
+    pub steps: Vec<PostProcessStep>,
+}
+
+impl Validation for PostProcessConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+        let name_re = Regex::new(r"^[a-z0-9][a-z0-9-]*$").unwrap();
+
+        if self.steps.is_empty() {
+            errors.push(eyre!("field: post_process.steps cannot be empty"));
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            if !name_re.is_match(&step.name) {
+                errors.push(eyre!(
+                    "field: post_process.steps.name must be lowercase letters, digits and dashes, got '{}'",
+                    step.name
+                ));
+            }
+            if self.steps[..index].iter().any(|other| other.name == step.name) {
+                errors.push(eyre!("field: post_process.steps has {} more than once", step.name));
+            }
+            if step.processor.is_some() == step.command.is_some() {
+                errors.push(eyre!("field: post_process.steps {} needs either processor or command", step.name));
+            }
+            if step.image.is_some() && step.processor != Some(PostProcessor::Oci) {
+                errors.push(eyre!("field: post_process.steps {} sets image, which only the oci processor uses", step.name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Retries of the test stages failing on the testbed instead of the tests, e.g. on qemu boot
+/// timeouts, the build itself is never retried.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TestRetryConfig {
+    /// retries after the first attempt, defaults to 2
+    #[schemars(example = json!(2))]
+    pub retries: Option<u32>,
+    /// seconds before the first retry, doubled for every further retry, defaults to 30
+    #[schemars(example = json!(30))]
+    pub backoff_secs: Option<u64>,
+}
+
+impl Validation for TestRetryConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.retries.is_some_and(|retries| retries > 10) {
+            errors.push(eyre!("field: test_retry.retries cannot be more than 10"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Source-only uploads prepared by `pkg-builder upload prepare`, e.g. for a PPA or mentors.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UploadConfig {
+    /// gpg key fingerprint or user id debsign signs the upload with, overridden by --key
+    pub key: String,
+    /// dput target of the printed upload command
+    #[schemars(example = json!("ppa:eth-pkg/stable"))]
+    pub dput_target: Option<String>,
+}
+
+impl Validation for UploadConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("upload.key", &self.key) {
+            errors.push(err);
+        }
+        if let Some(dput_target) = &self.dput_target {
+            if let Err(err) = validate_not_empty("upload.dput_target", dput_target) {
+                errors.push(err);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PkgConfig {
+    /// name, version and metadata of the package
+    pub package_fields: PackageFields,
+    /// where the source comes from and how it is built
+    pub package_type: PackageType,
+    /// distribution, architecture and tool versions of the build
+    pub build_env: BuildEnv,
+    pub control_overrides: Option<ControlOverrides>,
+    pub multi_arch: Option<MultiArchConfig>,
+    pub abi: Option<AbiConfig>,
+    pub build_hooks: Option<BuildHooks>,
+    pub trust: Option<TrustConfig>,
+    pub smoke_test: Option<SmokeTestConfig>,
+    pub test_deps: Option<TestDepsConfig>,
+    pub redact: Option<RedactConfig>,
+    pub debconf: Option<DebconfConfig>,
+    pub extras: Option<ExtrasConfig>,
+    pub apparmor: Option<AppArmorConfig>,
+    pub conffiles: Option<ConffilesConfig>,
+    pub content_policy: Option<ContentPolicyConfig>,
+    pub license_scan: Option<LicenseScanConfig>,
+    pub test_retry: Option<TestRetryConfig>,
+    pub watchdog: Option<WatchdogConfig>,
+    pub exports: Option<ExportsConfig>,
+    pub post_process: Option<PostProcessConfig>,
+    pub patches: Option<PatchesConfig>,
+    pub upload: Option<UploadConfig>,
+}
+
+impl PkgConfig {
+    /// The debian dir is taken from the git source, debcrafter does not run.
+    pub fn uses_upstream_debian_dir(&self) -> bool {
+        matches!(&self.package_type, PackageType::Git(config) if config.use_upstream_debian_dir == Some(true))
+    }
+}
+
+impl Validation for PkgConfig {
+    fn resolve(&mut self) -> Result<()> {
+        let PackageType::Git(config) = &self.package_type else {
+            return Ok(());
+        };
+        let Some(rules) = &config.uversion_mangle else {
+            return Ok(());
+        };
+        let version_number = mangle_uversion(&config.git_tag, rules)?;
+        if self.package_fields.version_number.is_empty() {
+            self.package_fields.version_number = version_number;
+        } else if self.package_fields.version_number != version_number {
+            return Err(eyre!(
+                "git_tag {} mangles to version {}, but version_number is {}",
+                config.git_tag,
+                version_number,
+                self.package_fields.version_number
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+        let package_field_errors = self.package_fields.validate_fields(!self.uses_upstream_debian_dir());
+        let package_type_errors = self.package_type.validate();
+        let build_env_errors = self.build_env.validate();
+        if let Err(mut package_field_errors) = package_field_errors {
+            errors.append(&mut package_field_errors);
+        }
+
+        if let Err(mut package_type_errors) = package_type_errors {
+            errors.append(&mut package_type_errors);
+        }
+
+        if let Err(mut build_env_errors) = build_env_errors {
+            errors.append(&mut build_env_errors);
+        }
+
+        let language_env = match &self.package_type {
+            PackageType::Default(config) => Some(&config.language_env),
+            PackageType::Git(config) => Some(&config.language_env),
+            PackageType::Virtual => None,
+        };
+        if let Some(language_env) = language_env {
+            if let Err(err) = language_env.validate_distro_toolchain(&self.build_env.codename) {
+                errors.push(err);
+            }
+        }
+        // unknown codenames are reported by the packager
+        if let Ok(distribution) = get_distribution(&self.build_env.codename) {
+            if let Err(err) = self.package_fields.validate_debian_policy(distribution.info()) {
+                errors.push(err);
+            }
+        }
+
+        if let Some(control_overrides) = &self.control_overrides {
+            if let Err(mut control_overrides_errors) = control_overrides.validate() {
+                errors.append(&mut control_overrides_errors);
+            }
+        }
+
+        if let Some(multi_arch) = &self.multi_arch {
+            if let Err(mut multi_arch_errors) = multi_arch.validate() {
+                errors.append(&mut multi_arch_errors);
+            }
+        }
+
+        if let Some(abi) = &self.abi {
+            if let Err(mut abi_errors) = abi.validate() {
+                errors.append(&mut abi_errors);
+            }
+        }
+
+        if let Some(build_hooks) = &self.build_hooks {
+            if let Err(mut build_hooks_errors) = build_hooks.validate() {
+                errors.append(&mut build_hooks_errors);
+            }
+            // the hooks are sbuild hooks, the container backends have no such stages
+            if let Some(backend @ (Backend::Docker | Backend::Podman)) = self.build_env.get_backend() {
+                errors.push(eyre!("field: build_hooks need backend sbuild, not {}", backend.as_str()));
+            }
+        }
+
+        if let Some(trust) = &self.trust {
+            if let Err(mut trust_errors) = trust.validate() {
+                errors.append(&mut trust_errors);
+            }
+        }
+
+        if let Some(smoke_test) = &self.smoke_test {
+            if let Err(mut smoke_test_errors) = smoke_test.validate() {
+                errors.append(&mut smoke_test_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("arch", &self.arch) {
-            errors.push(err);
+        if let Some(test_deps) = &self.test_deps {
+            if let Err(mut test_deps_errors) = test_deps.validate() {
+                errors.append(&mut test_deps_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("pkg_builder_version", &self.pkg_builder_version) {
-            errors.push(err);
+
+        if let Some(debconf) = &self.debconf {
+            if let Err(mut debconf_errors) = debconf.validate() {
+                errors.append(&mut debconf_errors);
+            }
         }
 
-        if let Err(err) = validate_not_empty("debcrafter_version", &self.debcrafter_version) {
-            errors.push(err);
+        if let Some(extras) = &self.extras {
+            if let Err(mut extras_errors) = extras.validate() {
+                errors.append(&mut extras_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("lintian_version", &self.lintian_version) {
-            errors.push(err);
+
+        if let Some(apparmor) = &self.apparmor {
+            if let Err(mut apparmor_errors) = apparmor.validate() {
+                errors.append(&mut apparmor_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("piuparts_version", &self.piuparts_version) {
-            errors.push(err);
+
+        if let Some(conffiles) = &self.conffiles {
+            if let Err(mut conffiles_errors) = conffiles.validate() {
+                errors.append(&mut conffiles_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("autopkgtest_version", &self.autopkgtest_version) {
-            errors.push(err);
+
+        if let Some(license_scan) = &self.license_scan {
+            if let Err(mut license_scan_errors) = license_scan.validate() {
+                errors.append(&mut license_scan_errors);
+            }
         }
-        if let Err(err) = validate_not_empty("sbuild_version", &self.sbuild_version) {
-            errors.push(err);
+
+        if let Some(content_policy) = &self.content_policy {
+            if let Err(mut content_policy_errors) = content_policy.validate() {
+                errors.append(&mut content_policy_errors);
+            }
         }
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(errors)
+
+        if let Some(test_retry) = &self.test_retry {
+            if let Err(mut test_retry_errors) = test_retry.validate() {
+                errors.append(&mut test_retry_errors);
+            }
         }
-    }
-}
 
+        if let Some(watchdog) = &self.watchdog {
+            if let Err(mut watchdog_errors) = watchdog.validate() {
+                errors.append(&mut watchdog_errors);
+            }
+        }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
-pub struct PkgConfig {
-    pub package_fields: PackageFields,
-    pub package_type: PackageType,
-    pub build_env: BuildEnv,
-}
+        if let Some(exports) = &self.exports {
+            if let Err(mut exports_errors) = exports.validate() {
+                errors.append(&mut exports_errors);
+            }
+        }
 
-impl Validation for PkgConfig {
-    fn validate(&self) -> Result<(), Vec<Report>> {
-        let mut errors = Vec::new();
-        let package_field_errors = self.package_fields.validate();
-        let package_type_errors = self.package_type.validate();
-        let build_env_errors = self.build_env.validate();
-        if let Err(mut package_field_errors) = package_field_errors {
-            errors.append(&mut package_field_errors);
+        if let Some(post_process) = &self.post_process {
+            if let Err(mut post_process_errors) = post_process.validate() {
+                errors.append(&mut post_process_errors);
+            }
         }
 
-        if let Err(mut package_type_errors) = package_type_errors {
-            errors.append(&mut package_type_errors);
+        if let Some(patches) = &self.patches {
+            if let Err(mut patches_errors) = patches.validate() {
+                errors.append(&mut patches_errors);
+            }
         }
 
-        if let Err(mut build_env_errors) = build_env_errors {
-            errors.append(&mut build_env_errors);
+        if let Some(upload) = &self.upload {
+            if let Err(mut upload_errors) = upload.validate() {
+                errors.append(&mut upload_errors);
+            }
+        }
+
+        if let Some(redact) = &self.redact {
+            if let Err(mut redact_errors) = redact.validate() {
+                errors.append(&mut redact_errors);
+            }
         }
 
         if errors.is_empty() {
@@ -526,11 +3130,49 @@ impl Validation for PkgConfig {
     }
 }
 
+// number of single character edits turning a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The expected field closest to the unknown one of a serde error, e.g. for
+/// "unknown field `tarbal_url`, expected one of `tarball_url`, `tarball_hash`" -> tarball_url
+pub fn suggest_field(message: &str) -> Option<String> {
+    let unknown_field = Regex::new(r"unknown field `([^`]+)`, expected (.*)").unwrap();
+    let captures = unknown_field.captures(message)?;
+    let unknown = &captures[1];
+    let expected: Vec<&str> = captures[2].split('`').skip(1).step_by(2).collect();
+    expected
+        .into_iter()
+        .map(|field| (edit_distance(unknown, field), field))
+        // more than a third of the name changed is not a typo anymore
+        .filter(|(distance, _)| *distance <= 2.max(unknown.len() / 3))
+        .min()
+        .map(|(_, field)| field.to_string())
+}
+
 pub fn parse<T>(config_str: &str) -> Result<T>
     where
         T: Validation + DeserializeOwned,
 {
-    let configuration = toml::from_str::<T>(config_str)?;
+    let mut configuration = toml::from_str::<T>(config_str).map_err(|err| {
+        let message = err.to_string();
+        match suggest_field(&message) {
+            Some(field) => eyre!("{}did you mean `{}`?", message, field),
+            None => eyre!(message),
+        }
+    })?;
+    configuration.resolve()?;
     configuration
         .validate()
         .map_err(|errors| eyre!("Validation failed: {:?}", errors))?;
@@ -561,6 +3203,7 @@ pub fn get_config<T>(config_file: String) -> Result<T>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::v1::pkg_config_verify::PkgVerifyConfig;
 
     #[test]
     fn test_full_config() {
@@ -575,15 +3218,13 @@ homepage="https://github.com/eth-pkg/pkg-builder#examples"
 [package_type]
 package_type="default"
 tarball_url = "hello-world-1.0.0.tar.gz"
-git_source = ""
-git_commit=""
 
 [package_type.language_env]
 language_env = "rust"
 rust_version = "1.22"
 rust_binary_url = "http:://example.com"
+rust_binary_checksum = "binary_checksum"
 rust_binary_gpg_asc = "binary_key"
-go_version = "1.22"
 
 
 [build_env]
@@ -607,36 +3248,426 @@ workdir="~/.pkg-builder/packages/jammy"
                 version_number: "1.0.0".to_string(),
                 revision_number: "1".to_string(),
                 homepage: "https://github.com/eth-pkg/pkg-builder#examples".to_string(),
+                epoch: None,
+                standards_version: None,
+                debhelper_compat: None,
             },
             package_type: PackageType::Default(DefaultPackageTypeConfig {
                 tarball_url: "hello-world-1.0.0.tar.gz".to_string(),
                 tarball_hash: None,
+                tarball_auth: None,
+                github_release: None,
                 language_env: LanguageEnv::Rust(RustConfig {
                     rust_version: "1.22".to_string(),
                     rust_binary_url: "http:://example.com".to_string(),
+                    rust_binary_checksum: "binary_checksum".to_string(),
                     rust_binary_gpg_asc: "binary_key".to_string(),
+                    use_distro_toolchain: None,
+                    cargo_features: vec![],
+                    cargo_profile: None,
+                    arch_binaries: BTreeMap::new(),
                 }),
             }),
             build_env: BuildEnv {
                 codename: "bookworm".to_string(),
-                arch: "amd64".to_string(),
+                codenames: None,
+                arch: Arch::Amd64,
                 pkg_builder_version: "0.2.8".to_string(),
                 debcrafter_version: "8189263".to_string(),
                 sbuild_cache_dir: None,
                 docker: None,
+                backend: None,
+                container_image: None,
                 run_lintian: Some(false),
                 run_piuparts: Some(false),
                 run_autopkgtest: Some(false),
+                autopkgtest_testbed: None,
+            cross_build: None,
                 lintian_version: "2.116.3".to_string(),
                 piuparts_version: "1.1.7".to_string(),
                 autopkgtest_version: "5.28".to_string(),
                 sbuild_version: "0.85.6".to_string(),
                 workdir: Some("~/.pkg-builder/packages/jammy".to_string()),
+                repo_url: None,
+                jobs: None,
+                run_unit_checks: None,
+                run_debconf_checks: None,
+                log_keep_count: None,
+                log_keep_size_mb: None,
+                fixed_build_path: None,
+                build_user: None,
+                fail_on_eol: None,
+                toolchain_cache: None,
+                snapshot_chroot: None,
+                chroot_max_age_days: None,
+                privilege_tool: None,
+                privilege_prompt: None,
+                strict_tool_versions: None,
+                quiet_chroot_setup: None,
+                proxies: None,
+                embed_recipe_revision: None,
+                fail_on_build_installs: None,
+                dev: None,
+                offline: None,
+                allow_changed_inputs: None,
             },
+            control_overrides: None,
+            multi_arch: None,
+            abi: None,
+            build_hooks: None,
+            trust: None,
+            smoke_test: None,
+            test_deps: None,
+            redact: None,
+            debconf: None,
+            extras: None,
+            apparmor: None,
+            conffiles: None,
+            content_policy: None,
+            license_scan: None,
+            test_retry: None,
+            watchdog: None,
+            exports: None,
+            post_process: None,
+            patches: None,
+            upload: None,
         };
         assert_eq!(parse::<PkgConfig>(config_str).unwrap(), config);
     }
 
+    #[test]
+    fn test_toolchain_download_trust_source() {
+        let node = LanguageEnv::TypeScript(JavascriptConfig {
+            node_binary_url: "https://nodejs.org/dist/v20.11.1/node-v20.11.1-linux-x64.tar.gz".to_string(),
+            node_binary_checksum: "a".repeat(64),
+            ..JavascriptConfig::default()
+        });
+        assert_eq!(node.get_trust_source(), Some("node"));
+        assert_eq!(node.get_binary_checksum(), Some("a".repeat(64).as_str()));
+
+        let nim = LanguageEnv::Nim(NimConfig {
+            nim_version_checksum: format!("{}  nim-2.0.2-linux_x64.tar.xz", "b".repeat(64)),
+            ..NimConfig::default()
+        });
+        assert_eq!(nim.get_trust_source(), Some("nim"));
+        assert_eq!(nim.get_binary_checksum(), Some("b".repeat(64).as_str()));
+
+        // the openjdk of the archive is not downloaded
+        let java = LanguageEnv::Java(JavaConfig::default());
+        assert_eq!(java.get_trust_source(), None);
+        assert_eq!(java.get_binary_url(), None);
+    }
+
+    #[test]
+    fn test_arch_is_validated_on_parse() {
+        let build_env = |arch: &str| {
+            toml::from_str::<BuildEnv>(&format!(
+                "codename = \"bookworm\"\narch = \"{}\"\npkg_builder_version = \"0.2.8\"\ndebcrafter_version = \"8189263\"\nlintian_version = \"2.116.3\"\npiuparts_version = \"1.1.7\"\nautopkgtest_version = \"5.28\"\nsbuild_version = \"0.85.6\"\nworkdir = \"\"\n",
+                arch
+            ))
+        };
+        assert_eq!(build_env("arm64").unwrap().arch, Arch::Arm64);
+        assert_eq!(build_env("all").unwrap().arch.get_deb_arch(), "all");
+        let error = build_env("amd_64").unwrap_err().to_string();
+        assert!(error.contains("unknown variant `amd_64`"), "{}", error);
+    }
+
+    #[test]
+    fn test_version_satisfies() {
+        assert!(version_satisfies("1.63.0", "1.63"));
+        assert!(version_satisfies("1.75.0", "1.63.0"));
+        assert!(version_satisfies("18.19.0", "18"));
+        assert!(!version_satisfies("1.19.8", "1.22"));
+        assert!(!version_satisfies("1.63.0", "1.63.1"));
+    }
+
+    #[test]
+    fn test_url_version_validation() {
+        let rust = RustConfig {
+            rust_version: "1.78.0".to_string(),
+            rust_binary_url: "https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz".to_string(),
+            rust_binary_checksum: "a".repeat(64),
+            rust_binary_gpg_asc: "binary_key".to_string(),
+            use_distro_toolchain: None,
+            ..RustConfig::default()
+        };
+        let errors = rust.validate().unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "field: rust_binary_url points to version 1.77.2, which does not match rust_version 1.78.0"
+        );
+
+        let urls = [
+            (r"^go(\d[\w.]*)\.linux-", "https://go.dev/dl/go1.22.2.linux-amd64.tar.gz", "1.22.2"),
+            (r"^node-v(\d[\w.]*)-linux-", "https://nodejs.org/download/release/v20.12.2/node-v20.12.2-linux-x64.tar.gz", "20.12.2"),
+            (r"^(?:open)?jdk-(\d[\w.]*)_linux-", "https://download.oracle.com/java/17/archive/jdk-17.0.10_linux-x64_bin.tar.gz", "17.0.10"),
+            (r"^gradle-(\d[\w.]*)-(?:bin|all)\.zip$", "https://github.com/gradle/gradle-distributions/releases/download/v8.7.0/gradle-8.7-bin.zip", "8.7"),
+            (r"^nim-(\d[\w.]*)-linux_", "https://nim-lang.org/download/nim-2.0.2-linux_x64.tar.xz", "2.0.2"),
+        ];
+        for (pattern, url, version) in urls {
+            assert_eq!(get_url_version(pattern, url).as_deref(), Some(version));
+        }
+
+        assert!(validate_url_version("jdk_version", "17.0", "jdk_binary_url", urls[2].1, urls[2].0).is_ok());
+        assert!(validate_url_version("jdk_version", "17.1", "jdk_binary_url", urls[2].1, urls[2].0).is_err());
+        assert!(validate_url_version("go_version", "1.22", "go_binary_url", "https://mirror.example.com/go.tar.gz", urls[0].0).is_ok());
+    }
+
+    #[test]
+    fn test_distro_toolchain_validation() {
+        let config_str = r#"
+[package_fields]
+spec_file = "hello-world.sss"
+package_name = "hello-world"
+version_number = "1.0.0"
+revision_number = "1"
+homepage="https://github.com/eth-pkg/pkg-builder#examples"
+
+[package_type]
+package_type="default"
+tarball_url = "hello-world-1.0.0.tar.gz"
+
+[package_type.language_env]
+language_env = "go"
+go_version = "GO_VERSION"
+use_distro_toolchain = true
+
+[build_env]
+codename="bookworm"
+arch = "amd64"
+pkg_builder_version="0.2.8"
+debcrafter_version = "8189263"
+lintian_version="2.116.3"
+piuparts_version="1.1.7"
+autopkgtest_version="5.28"
+sbuild_version="0.85.6"
+workdir=""
+"#;
+        parse::<PkgConfig>(&config_str.replace("GO_VERSION", "1.19")).unwrap();
+        let result = parse::<PkgConfig>(&config_str.replace("GO_VERSION", "1.22"));
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("use_distro_toolchain: bookworm ships golang-go 1.19.8, which does not satisfy the pinned version 1.22"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_version_number_from_mangled_git_tag() {
+        let config_str = r#"
+[package_fields]
+spec_file = "hello-world.sss"
+package_name = "hello-world"
+VERSION_NUMBER
+revision_number = "1"
+homepage="https://github.com/eth-pkg/pkg-builder#examples"
+
+[package_type]
+package_type="git"
+git_tag = "v1.2.3-rc.1"
+git_url = "https://github.com/eth-pkg/hello-world.git"
+submodules = []
+uversion_mangle = ['s/^v//', 's/-rc\.(\d+)$/~rc$1/']
+
+[package_type.language_env]
+language_env = "c"
+
+[build_env]
+codename="bookworm"
+arch = "amd64"
+pkg_builder_version="0.2.8"
+debcrafter_version = "8189263"
+lintian_version="2.116.3"
+piuparts_version="1.1.7"
+autopkgtest_version="5.28"
+sbuild_version="0.85.6"
+workdir=""
+"#;
+        let config = parse::<PkgConfig>(&config_str.replace("VERSION_NUMBER", "")).unwrap();
+        assert_eq!(config.package_fields.version_number, "1.2.3~rc1");
+        parse::<PkgConfig>(&config_str.replace("VERSION_NUMBER", "version_number = \"1.2.3~rc1\"")).unwrap();
+        let result = parse::<PkgConfig>(&config_str.replace("VERSION_NUMBER", "version_number = \"1.2.3\""));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "git_tag v1.2.3-rc.1 mangles to version 1.2.3~rc1, but version_number is 1.2.3"
+        );
+    }
+
+    #[test]
+    fn test_upstream_debian_dir_needs_no_spec_file() {
+        let config_str = r#"
+[package_fields]
+package_name = "hello-world"
+version_number = "1.0.0"
+revision_number = "1"
+homepage="https://github.com/eth-pkg/pkg-builder#examples"
+
+[package_type]
+package_type="git"
+git_tag = "v1.0.0"
+git_url = "https://github.com/eth-pkg/hello-world.git"
+submodules = []
+UPSTREAM_DEBIAN_DIR
+
+[package_type.language_env]
+language_env = "c"
+
+[build_env]
+codename="bookworm"
+arch = "amd64"
+pkg_builder_version="0.2.8"
+debcrafter_version = "8189263"
+lintian_version="2.116.3"
+piuparts_version="1.1.7"
+autopkgtest_version="5.28"
+sbuild_version="0.85.6"
+workdir=""
+"#;
+        let config = parse::<PkgConfig>(&config_str.replace("UPSTREAM_DEBIAN_DIR", "use_upstream_debian_dir = true")).unwrap();
+        assert!(config.uses_upstream_debian_dir());
+        let result = parse::<PkgConfig>(&config_str.replace("UPSTREAM_DEBIAN_DIR", ""));
+        assert!(result.unwrap_err().to_string().contains("field: spec_file cannot be empty"));
+    }
+
+    #[test]
+    fn test_control_overrides_validation() {
+        let control_overrides = ControlOverrides {
+            package: None,
+            depends: vec![
+                "libssl3 (>= 3.0.11)".to_string(),
+                "default-mta | mail-transport-agent".to_string(),
+                "${shlibs:Depends}".to_string(),
+            ],
+            conflicts: vec!["hello-world-legacy (<< 1.0.0)".to_string()],
+            provides: vec!["hello-world-api (= 1.0)".to_string()],
+        };
+        assert!(control_overrides.validate().is_ok());
+
+        let control_overrides = ControlOverrides {
+            package: None,
+            depends: vec!["libssl3 (>= 3.0.11), libc6".to_string()],
+            conflicts: vec!["Hello World".to_string()],
+            provides: vec!["hello-world-api (=> 1.0)".to_string()],
+        };
+        let errors = control_overrides.validate().unwrap_err();
+        let expected_errors = [
+            "field: control_overrides.depends has invalid relation 'libssl3 (>= 3.0.11), libc6'",
+            "field: control_overrides.conflicts has invalid relation 'Hello World'",
+            "field: control_overrides.provides has invalid relation 'hello-world-api (=> 1.0)'",
+        ];
+        assert_eq!(errors.len(), expected_errors.len());
+        for (actual, expected) in errors.iter().zip(expected_errors.iter()) {
+            assert_eq!(actual.to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_multi_arch_validation() {
+        let multi_arch = MultiArchConfig {
+            same: vec!["libhello1".to_string(), "libhello-dev".to_string()],
+            foreign: vec!["hello-tools".to_string()],
+            allowed: vec![],
+        };
+        assert!(multi_arch.validate().is_ok());
+
+        let multi_arch = MultiArchConfig {
+            same: vec!["libhello1".to_string(), " ".to_string()],
+            foreign: vec!["libhello1".to_string()],
+            allowed: vec![],
+        };
+        let errors: Vec<String> = multi_arch.validate().unwrap_err().iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "field: multi_arch.same cannot be empty",
+                "field: multi_arch lists libhello1 as both same and foreign",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trust_validation() {
+        let key = TrustedKey {
+            source: "rust".to_string(),
+            fingerprints: vec!["108f 6620 5eae b0aa a8dd 5e1c 85ab 96e6 fa1b e5fe".to_string()],
+            key_file: "keys/rust.asc".to_string(),
+        };
+        assert_eq!(key.get_fingerprints(), vec!["108F66205EAEB0AAA8DD5E1C85AB96E6FA1BE5FE"]);
+        let trust = TrustConfig {
+            keys: vec![key.clone()],
+        };
+        assert!(trust.validate().is_ok());
+
+        let trust = TrustConfig {
+            keys: vec![
+                key,
+                TrustedKey {
+                    source: "rust".to_string(),
+                    fingerprints: vec!["85AB96E6".to_string()],
+                    key_file: "".to_string(),
+                },
+                TrustedKey {
+                    source: "keybase".to_string(),
+                    fingerprints: vec![],
+                    key_file: "keys/keybase.asc".to_string(),
+                },
+            ],
+        };
+        let errors = trust.validate().unwrap_err();
+        let expected_errors = [
+            "trust: 85AB96E6 is not a full key fingerprint",
+            "field: key_file cannot be empty",
+            "trust: source rust is declared twice",
+            "trust: unknown source 'keybase', supported sources: rust, go, node, java, nim, zig, microsoft, github_release",
+            "trust: fingerprints of keybase cannot be empty",
+        ];
+        assert_eq!(errors.len(), expected_errors.len());
+        for (actual, expected) in errors.iter().zip(expected_errors.iter()) {
+            assert_eq!(actual.to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn test_custom_language_env_validation() {
+        let config_str = r#"
+language_env = "custom"
+toolchain_name = "ghc"
+toolchain_version = "9.4.8"
+
+[[steps]]
+url = "https://downloads.haskell.org/ghc/9.4.8/ghc-9.4.8-x86_64-deb10-linux.tar.xz"
+sha256 = "2e6c5f2f0aab33e64a4ea70f3a0a58e8fa1d4a6f0e2b1a4b1a5c6e8d9f0a1b2c"
+unpack_dir = "/opt/lib/ghc"
+strip_components = 1
+symlinks = { "/usr/bin/ghc" = "bin/ghc" }
+"#;
+        let language_env = toml::from_str::<LanguageEnv>(config_str).unwrap();
+        assert!(language_env.validate().is_ok());
+
+        let config = CustomConfig {
+            toolchain_name: "ghc".to_string(),
+            toolchain_version: "9.4.8".to_string(),
+            steps: vec![CustomInstallStep {
+                url: "ftp://example.com/sdk.tar.gz".to_string(),
+                sha256: "abc".to_string(),
+                unpack_dir: None,
+                strip_components: None,
+                symlinks: BTreeMap::from([("/usr/bin/sdk".to_string(), "bin/sdk".to_string())]),
+            }],
+        };
+        let errors = config.validate().unwrap_err();
+        let expected_errors = [
+            "field: steps.url 'ftp://example.com/sdk.tar.gz' has to be a http(s) url",
+            "field: steps.sha256 of ftp://example.com/sdk.tar.gz is not a sha256 hash",
+            "field: steps.symlinks of ftp://example.com/sdk.tar.gz need an unpack_dir",
+        ];
+        assert_eq!(errors.len(), expected_errors.len());
+        for (actual, expected) in errors.iter().zip(expected_errors.iter()) {
+            assert_eq!(actual.to_string(), *expected);
+        }
+    }
+
     #[test]
     fn test_empty_strings_are_error_rust_config() {
         let config = RustConfig::default();
@@ -645,6 +3676,7 @@ workdir="~/.pkg-builder/packages/jammy"
                 let expected_errors = [
                     "field: rust_version cannot be empty",
                     "field: rust_binary_url cannot be empty",
+                    "field: rust_binary_checksum cannot be empty",
                     "field: rust_binary_gpg_asc cannot be empty",
                 ];
                 assert_eq!(
@@ -796,6 +3828,44 @@ workdir="~/.pkg-builder/packages/jammy"
         }
     }
 
+    #[test]
+    fn test_empty_strings_are_error_zig_config() {
+        let config = ZigConfig::default();
+        match config.validate() {
+            Err(validation_errors) => {
+                let expected_errors = [
+                    "field: zig_version cannot be empty",
+                    "field: zig_binary_url cannot be empty",
+                    "field: zig_binary_checksum cannot be empty",
+                ];
+                assert_eq!(
+                    validation_errors.len(),
+                    expected_errors.len(),
+                    "Number of errors is different"
+                );
+                for (actual, expected) in validation_errors.iter().zip(expected_errors.iter()) {
+                    assert_eq!(actual.to_string(), *expected);
+                }
+            }
+            Ok(_) => panic!("Validation should have failed."),
+        }
+        let config = ZigConfig {
+            zig_version: "0.14.1".to_string(),
+            zig_binary_url: "https://ziglang.org/download/0.14.1/zig-x86_64-linux-0.14.1.tar.xz".to_string(),
+            zig_binary_checksum: "24aeeec8af16c381934a6cd7d95c807a8cb2cf7df9fa40d359aa884195c4716c".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        let config = ZigConfig {
+            zig_version: "0.14.0".to_string(),
+            ..config
+        };
+        assert_eq!(
+            config.validate().unwrap_err()[0].to_string(),
+            "field: zig_binary_url points to version 0.14.1, which does not match zig_version 0.14.0"
+        );
+    }
+
     #[test]
     fn test_empty_strings_are_error_default_package_type_config() {
         let config = DefaultPackageTypeConfig::default();
@@ -839,6 +3909,69 @@ workdir="~/.pkg-builder/packages/jammy"
         }
     }
 
+    #[test]
+    fn test_build_user() {
+        let build_env = BuildEnv {
+            build_user: Some("root".to_string()),
+            ..BuildEnv::default()
+        };
+        let errors: Vec<String> = build_env
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(|err| err.to_string())
+            .filter(|err| err.contains("build_user"))
+            .collect();
+        assert_eq!(
+            errors,
+            vec!["field: build_user must not be root, leave it empty to build as the default user"]
+        );
+    }
+
+    #[test]
+    fn test_require_signed_tag_needs_allowed_signers() {
+        let mut config = GitPackageTypeConfig {
+            git_tag: "v1.0.0".to_string(),
+            git_url: "https://github.com/example/hello-world.git".to_string(),
+            require_signed_tag: Some(true),
+            ..GitPackageTypeConfig::default()
+        };
+        let errors: Vec<String> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec!["field: allowed_signers is required by require_signed_tag"]
+        );
+
+        config.allowed_signers = Some(AllowedSigners::default());
+        let errors: Vec<String> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "field: allowed_signers.key_file cannot be empty",
+                "field: allowed_signers.fingerprints cannot be empty",
+            ]
+        );
+
+        config.allowed_signers = Some(AllowedSigners {
+            key_file: "signers.asc".to_string(),
+            fingerprints: vec!["SHA256:abc".to_string()],
+        });
+        assert!(config.validate().is_ok());
+        assert!(config.get_required_signers().is_some());
+        config.require_signed_tag = None;
+        assert!(config.get_required_signers().is_none());
+    }
+
     #[test]
     fn test_empty_strings_are_error_gradle_config() {
         let config = GradleConfig::default();
@@ -887,6 +4020,107 @@ workdir="~/.pkg-builder/packages/jammy"
         }
     }
 
+    #[test]
+    fn test_unknown_fields_suggest_the_closest_field() {
+        let config_str = r#"
+[package_fields]
+spec_file = "hello-world.sss"
+package_name = "hello-world"
+version_number = "1.0.0"
+revision_number = "1"
+homepage = "https://example.com"
+
+[package_type]
+package_type = "default"
+tarbal_url = "hello-world-1.0.0.tar.gz"
+
+[package_type.language_env]
+language_env = "c"
+"#;
+        let error = parse::<PkgConfig>(config_str).unwrap_err().to_string();
+        assert!(error.contains("unknown field `tarbal_url`"), "{}", error);
+        assert!(error.ends_with("did you mean `tarball_url`?"), "{}", error);
+
+        let error = parse::<PkgConfig>(&config_str.replace("tarbal_url", "mirror")).unwrap_err().to_string();
+        assert!(error.contains("unknown field `mirror`"), "{}", error);
+        assert!(!error.contains("did you mean"), "{}", error);
+
+        assert_eq!(
+            suggest_field("unknown field `run_lintain`, expected one of `run_lintian`, `run_piuparts`"),
+            Some("run_lintian".to_string())
+        );
+        assert_eq!(suggest_field("unknown field `kye`, expected `key`"), Some("key".to_string()));
+    }
+
+    #[test]
+    fn test_examples_have_no_unknown_fields() {
+        for entry in glob::glob("examples/*/*/*/pkg-builder*.toml").unwrap() {
+            let path = entry.unwrap();
+            let content = fs::read_to_string(&path).unwrap();
+            let result = match path.ends_with("pkg-builder-verify.toml") {
+                true => toml::from_str::<PkgVerifyConfig>(&content).map(|_| ()),
+                false => toml::from_str::<PkgConfig>(&content).map(|_| ()),
+            };
+            assert!(result.is_ok(), "{}: {}", path.display(), result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn test_debian_policy_per_distribution() {
+        let jammy = get_distribution("jammy jellyfish").unwrap().info();
+        let mut fields = PackageFields {
+            standards_version: Some("4.6.2".to_string()),
+            debhelper_compat: Some(14),
+            ..PackageFields::default()
+        };
+        assert_eq!(
+            fields.validate_debian_policy(jammy).unwrap_err().to_string(),
+            "field: standards_version 4.6.2 is newer than debian-policy 4.6.0.1 of jammy jellyfish"
+        );
+        fields.standards_version = Some("4.6.0".to_string());
+        assert_eq!(
+            fields.validate_debian_policy(jammy).unwrap_err().to_string(),
+            "field: debhelper_compat 14 is not supported on jammy jellyfish, use 10 to 13"
+        );
+        fields.debhelper_compat = Some(12);
+        assert!(fields.validate_debian_policy(jammy).is_ok());
+        // 4.6.0 and 4.6.0.1 only differ in editorial changes
+        assert_eq!(
+            fields.get_debian_policy_warnings(jammy),
+            vec!["debhelper_compat 12 is outdated, jammy jellyfish supports compat level 13"]
+        );
+
+        let bookworm = get_distribution("bookworm").unwrap().info();
+        fields.standards_version = Some("4.5.1".to_string());
+        assert_eq!(fields.get_debian_policy_warnings(bookworm).len(), 2);
+    }
+
+    #[test]
+    fn test_epoch_validation_and_versions() {
+        let mut fields = PackageFields {
+            spec_file: "hello-world.sss".to_string(),
+            package_name: "hello-world".to_string(),
+            version_number: "1.0.0".to_string(),
+            revision_number: "1".to_string(),
+            homepage: "https://example.com".to_string(),
+            epoch: None,
+            ..PackageFields::default()
+        };
+        assert_eq!(fields.get_debian_version(), "1.0.0-1");
+
+        fields.epoch = Some("1".to_string());
+        assert!(fields.validate().is_ok());
+        assert_eq!(fields.get_debian_version(), "1:1.0.0-1");
+        assert_eq!(fields.get_file_version(), "1.0.0-1");
+
+        for invalid in ["", "1:", "-1", "a"] {
+            fields.epoch = Some(invalid.to_string());
+            let errors = fields.validate().unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].to_string().contains("field: epoch must be an unsigned integer"));
+        }
+    }
+
     #[test]
     fn test_empty_strings_are_error_build_env() {
         let config = BuildEnv::default();
@@ -894,7 +4128,6 @@ workdir="~/.pkg-builder/packages/jammy"
             Err(validation_errors) => {
                 let expected_errors = [
                     "field: codename cannot be empty",
-                    "field: arch cannot be empty",
                     "field: pkg_builder_version cannot be empty",
                     "field: debcrafter_version cannot be empty",
                     "field: lintian_version cannot be empty",
@@ -927,7 +4160,6 @@ workdir="~/.pkg-builder/packages/jammy"
                     "field: revision_number cannot be empty",
                     "field: homepage cannot be empty",
                     "field: codename cannot be empty",
-                    "field: arch cannot be empty",
                     "field: pkg_builder_version cannot be empty",
                     "field: debcrafter_version cannot be empty",
                     "field: lintian_version cannot be empty",
@@ -947,4 +4179,19 @@ workdir="~/.pkg-builder/packages/jammy"
             Ok(_) => panic!("Validation should have failed."),
         }
     }
+
+    #[test]
+    fn test_proxies_validation() {
+        let mut proxies = ProxyConfig {
+            apt: Some("http://apt-cacher:3142".to_string()),
+            cargo: Some("sparse+https://crates-mirror/index/".to_string()),
+            ..ProxyConfig::default()
+        };
+        assert!(proxies.validate().is_ok());
+        proxies.go = Some("sparse+http://athens:3000".to_string());
+        proxies.npm = Some("http://verdaccio:4873/' && rm -rf /".to_string());
+        let errors = proxies.validate().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "field: proxies.go must be an http or https url, got sparse+http://athens:3000");
+    }
 }
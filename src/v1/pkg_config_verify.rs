@@ -3,6 +3,7 @@ use serde::Deserialize;
 use crate::v1::pkg_config::{validate_not_empty, Validation};
 
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct PackageHash {
     pub name: String,
     pub hash: String,
@@ -29,6 +30,7 @@ impl Validation for PackageHash {
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct VerifyConfig {
     pub package_hash: Vec<PackageHash>,
 }
@@ -57,12 +59,86 @@ impl Validation for VerifyConfig {
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct PkgVerifyConfig {
     pub verify: VerifyConfig,
 }
 
 impl Validation for PkgVerifyConfig {
     fn validate(&self) -> eyre::Result<(), Vec<Report>> {
-        return self.verify.validate();
+        self.verify.validate()
     }
-}
\ No newline at end of file
+}
+#[derive(Debug, PartialEq, Clone)]
+pub struct HashUpdate {
+    pub name: String,
+    pub old_hash: String,
+    pub new_hash: String,
+}
+
+/// Replaces the hashes in the verify file content in place, so comments,
+/// ordering and formatting are kept.
+pub fn apply_hash_updates(content: &str, updates: &[HashUpdate]) -> eyre::Result<String> {
+    let mut lines: Vec<String> = content.lines().map(|line| line.to_string()).collect();
+    for update in updates {
+        let quoted_name = format!("\"{}\"", update.name);
+        let line = lines
+            .iter_mut()
+            .find(|line| line.contains(&quoted_name) && line.contains(&update.old_hash))
+            .ok_or_else(|| {
+                eyre!(
+                    "Could not find hash {} of {} in verify file, update it by hand.",
+                    update.old_hash,
+                    update.name
+                )
+            })?;
+        *line = line.replace(&update.old_hash, &update.new_hash);
+    }
+    let mut updated = lines.join("\n");
+    if content.ends_with('\n') {
+        updated.push('\n');
+    }
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::parse;
+
+    #[test]
+    fn test_apply_hash_updates_keeps_comments_and_order() {
+        let content = r#"[verify]
+# rebuilt on bookworm
+package_hash=[
+    { hash="d3aa1f40ca330f76b167d434f6bae999f50397e5", name= "hello-world_1.0.0-1.dsc"},
+    # deb changes with the toolchain
+    { hash="a171d91d6c02b9f3b64d99b8bbbc60d72f5432e2", name= "hello-world_1.0.0-1_amd64.deb"},
+]
+"#;
+        let updates = vec![HashUpdate {
+            name: "hello-world_1.0.0-1_amd64.deb".to_string(),
+            old_hash: "a171d91d6c02b9f3b64d99b8bbbc60d72f5432e2".to_string(),
+            new_hash: "0000000000000000000000000000000000000000".to_string(),
+        }];
+        let updated = apply_hash_updates(content, &updates).unwrap();
+        assert_eq!(
+            updated,
+            content.replace(
+                "a171d91d6c02b9f3b64d99b8bbbc60d72f5432e2",
+                "0000000000000000000000000000000000000000"
+            )
+        );
+        let config = parse::<PkgVerifyConfig>(&updated).unwrap();
+        assert_eq!(
+            config.verify.package_hash[1].hash,
+            "0000000000000000000000000000000000000000"
+        );
+
+        let missing = vec![HashUpdate {
+            name: "hello-world_1.0.0-1.changes".to_string(),
+            ..updates[0].clone()
+        }];
+        assert!(apply_hash_updates(content, &missing).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+// Schema of pkg-builder.toml, derived by schemars from the serde types of pkg_config.rs, so the
+// recipe format documents itself: the /// comments of the fields are their descriptions, the
+// examples come from their #[schemars(example)] attributes, the allowed values of the enums
+// and whether a field is required follow from the types.
+use crate::v1::pkg_config::PkgConfig;
+use eyre::{eyre, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// A field of the config, the path joins the tables with dots, e.g. build_env.codename.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SchemaField {
+    pub path: String,
+    /// toml type, e.g. "string", "array of strings", "table"
+    pub kind: String,
+    pub description: String,
+    pub example: Option<String>,
+    pub allowed: Vec<(String, String)>,
+    pub required: bool,
+    /// the field only exists in these variants, e.g. language_env = "rust"
+    pub conditions: Vec<String>,
+    /// names of the fields of a table
+    pub children: Vec<String>,
+}
+
+fn get_description(node: &Value) -> Option<String> {
+    node.get("description").and_then(Value::as_str).map(|description| description.to_string())
+}
+
+/// The type of a node, without the null of an Option, e.g. ["string", "null"].
+fn get_type(node: &Value) -> Option<&str> {
+    match node.get("type")? {
+        Value::String(kind) => Some(kind),
+        Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).find(|kind| *kind != "null"),
+        _ => None,
+    }
+}
+
+/// The example of a field as toml, e.g. ["jemalloc", "portable"].
+fn get_example(node: &Value) -> Option<String> {
+    let example = node.get("examples")?.as_array()?.first()?;
+    toml::Value::try_from(example).ok().map(|value| value.to_string())
+}
+
+/// The fields of the variants of a tagged enum, the tag is the property every variant fixes.
+fn get_tag(variants: &[Value]) -> Option<String> {
+    let first = variants.first()?.get("properties")?.as_object()?;
+    first
+        .iter()
+        .find(|(name, _)| {
+            variants
+                .iter()
+                .all(|variant| variant["properties"][name.as_str()].get("const").is_some())
+        })
+        .map(|(name, _)| name.clone())
+}
+
+struct SchemaBuilder<'a> {
+    defs: &'a Map<String, Value>,
+    fields: BTreeMap<String, SchemaField>,
+}
+
+impl<'a> SchemaBuilder<'a> {
+    /// The definition of a $ref, and the non-null branch of an Option.
+    fn resolve(&self, node: &'a Value) -> &'a Value {
+        if let Some(reference) = node.get("$ref").and_then(Value::as_str) {
+            if let Some(def) = reference.strip_prefix("#/$defs/").and_then(|name| self.defs.get(name)) {
+                return self.resolve(def);
+            }
+        }
+        if let Some(branches) = node.get("anyOf").and_then(Value::as_array) {
+            if let Some(branch) = branches.iter().find(|branch| branch.get("type") != Some(&Value::from("null"))) {
+                return self.resolve(branch);
+            }
+        }
+        node
+    }
+
+    fn add(&mut self, field: SchemaField) {
+        match self.fields.get_mut(&field.path) {
+            // the same field in several variants, e.g. language_env of default and git packages
+            Some(existing) => {
+                for condition in field.conditions {
+                    if !existing.conditions.contains(&condition) {
+                        existing.conditions.push(condition);
+                    }
+                }
+                for child in field.children {
+                    if !existing.children.contains(&child) {
+                        existing.children.push(child);
+                    }
+                }
+                if existing.description.is_empty() {
+                    existing.description = field.description;
+                }
+                existing.example = existing.example.take().or(field.example);
+            }
+            None => {
+                self.fields.insert(field.path.clone(), field);
+            }
+        }
+    }
+
+    fn add_properties(&mut self, prefix: &str, table: &'a Value, conditions: &[String]) -> Vec<String> {
+        let required: Vec<&str> = table
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut children = vec![];
+        if let Some(properties) = table.get("properties").and_then(Value::as_object) {
+            for (name, property) in properties {
+                let path = format!("{}{}", prefix, name);
+                self.add_field(&path, property, required.contains(&name.as_str()), conditions);
+                children.push(name.clone());
+            }
+        }
+        children
+    }
+
+    fn add_field(&mut self, path: &str, property: &'a Value, required: bool, conditions: &[String]) {
+        let node = self.resolve(property);
+        let name = path.rsplit('.').next().unwrap_or(path);
+        let mut field = SchemaField {
+            path: path.to_string(),
+            description: get_description(property)
+                .or_else(|| get_description(node))
+                .unwrap_or_default(),
+            example: get_example(property),
+            required,
+            conditions: conditions.to_vec(),
+            ..Default::default()
+        };
+        let variants = node.get("oneOf").and_then(Value::as_array);
+        match (get_type(node), variants) {
+            (None, Some(variants)) if variants.iter().all(|variant| get_type(variant) == Some("object")) => {
+                field.kind = "table".to_string();
+                let Some(tag) = get_tag(variants) else {
+                    self.add(field);
+                    return;
+                };
+                let mut allowed = vec![];
+                for variant in variants {
+                    let value = variant["properties"][tag.as_str()]["const"].as_str().unwrap_or_default();
+                    allowed.push((value.to_string(), get_description(variant).unwrap_or_default()));
+                    let condition = format!("{} = \"{}\"", tag, value);
+                    for child in self.add_properties(&format!("{}.", path), variant, &[condition]) {
+                        if child != tag && !field.children.contains(&child) {
+                            field.children.push(child);
+                        }
+                    }
+                }
+                // the tag is a field of every variant, not only of one
+                self.fields.remove(&format!("{}.{}", path, tag));
+                self.add(SchemaField {
+                    path: format!("{}.{}", path, tag),
+                    kind: "string".to_string(),
+                    description: format!("kind of {}, selects the other fields of the table", name),
+                    example: allowed.first().map(|(value, _)| format!("\"{}\"", value)),
+                    allowed,
+                    required: true,
+                    ..Default::default()
+                });
+                field.children.insert(0, tag);
+            }
+            (_, Some(variants)) => {
+                field.kind = "string".to_string();
+                for variant in variants {
+                    let description = get_description(variant).unwrap_or_default();
+                    if let Some(value) = variant.get("const").and_then(Value::as_str) {
+                        field.allowed.push((value.to_string(), description.clone()));
+                    }
+                    for value in variant.get("enum").and_then(Value::as_array).into_iter().flatten() {
+                        field.allowed.push((value.as_str().unwrap_or_default().to_string(), description.clone()));
+                    }
+                }
+            }
+            (Some("object"), _) => match node.get("additionalProperties").map(|value| self.resolve(value)) {
+                // tables keyed by name, e.g. arch_binaries.arm64, the key is * in the paths
+                Some(value) if value.get("properties").is_some() => {
+                    field.kind = "table of tables".to_string();
+                    let value_path = format!("{}.*", path);
+                    let children = self.add_properties(&format!("{}.", value_path), value, &[]);
+                    self.add(SchemaField {
+                        path: value_path,
+                        kind: "table".to_string(),
+                        description: get_description(value).unwrap_or_default(),
+                        required: true,
+                        children,
+                        ..Default::default()
+                    });
+                    field.children.push("*".to_string());
+                }
+                Some(value) if value.get("type").is_some() => {
+                    field.kind = format!("table of {}s", get_type(value).unwrap_or("string"));
+                }
+                _ => {
+                    field.kind = "table".to_string();
+                    field.children = self.add_properties(&format!("{}.", path), node, &[]);
+                }
+            },
+            (Some("array"), _) => {
+                let items = self.resolve(&node["items"]);
+                match items.get("properties") {
+                    Some(_) => {
+                        field.kind = "array of tables".to_string();
+                        if field.description.is_empty() {
+                            field.description = get_description(items).unwrap_or_default();
+                        }
+                        field.children = self.add_properties(&format!("{}.", path), items, &[]);
+                    }
+                    None => field.kind = format!("array of {}s", get_type(items).unwrap_or("string")),
+                }
+            }
+            (kind, None) => {
+                field.kind = kind.unwrap_or("string").to_string();
+                for value in node.get("enum").and_then(Value::as_array).into_iter().flatten() {
+                    field.allowed.push((value.as_str().unwrap_or_default().to_string(), String::new()));
+                }
+            }
+        }
+        self.add(field);
+    }
+}
+
+/// Every field of the config, sorted by path.
+pub fn get_schema() -> Result<Vec<SchemaField>> {
+    let json_schema = get_json_schema()?;
+    let no_defs = Map::new();
+    let defs = json_schema.get("$defs").and_then(Value::as_object).unwrap_or(&no_defs);
+    let mut builder = SchemaBuilder {
+        defs,
+        fields: BTreeMap::new(),
+    };
+    builder.add_properties("", &json_schema, &[]);
+    Ok(builder.fields.into_values().collect())
+}
+
+/// Description, type and metadata of a field, with the fields of a table.
+pub fn explain(path: &str) -> Result<String> {
+    let schema = get_schema()?;
+    let Some(field) = schema.iter().find(|field| field.path == path) else {
+        let hint = match path.rsplit_once('.') {
+            Some((parent, _)) if schema.iter().any(|field| field.path == parent) => format!("`pkg-builder explain {}`", parent),
+            _ => "`pkg-builder explain`".to_string(),
+        };
+        return Err(eyre!("Unknown field {}, {} lists the fields", path, hint));
+    };
+    let mut lines = vec![format!("{} ({})", field.path, field.kind)];
+    for line in field.description.lines() {
+        lines.push(format!("  {}", line));
+    }
+    lines.push(String::new());
+    lines.push(format!("  required: {}", if field.required { "yes" } else { "no" }));
+    if !field.conditions.is_empty() {
+        lines.push(format!("  only with: {}", field.conditions.join(" or ")));
+    }
+    if !field.allowed.is_empty() {
+        lines.push("  allowed values:".to_string());
+        for (value, description) in field.allowed.iter() {
+            match description.is_empty() {
+                true => lines.push(format!("    {}", value)),
+                false => lines.push(format!("    {}: {}", value, description)),
+            }
+        }
+    }
+    if let Some(example) = &field.example {
+        let name = field.path.rsplit('.').next().unwrap_or(&field.path);
+        lines.push(format!("  example: {} = {}", name, example));
+    }
+    if !field.children.is_empty() {
+        lines.push("  fields:".to_string());
+        for child in field.children.iter() {
+            let child_path = format!("{}.{}", field.path, child);
+            let summary = schema
+                .iter()
+                .find(|field| field.path == child_path)
+                .map(|field| field.description.replace('\n', " "))
+                .unwrap_or_default();
+            lines.push(format!("    {}: {}", child, summary));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// JSON Schema of pkg-builder.toml, e.g. for taplo or the even better toml extension.
+pub fn get_json_schema() -> Result<Value> {
+    let mut json_schema = serde_json::to_value(schemars::schema_for!(PkgConfig))?;
+    json_schema["title"] = Value::from("pkg-builder.toml");
+    Ok(json_schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_field_is_documented() {
+        let schema = get_schema().unwrap();
+        let undocumented: Vec<&str> = schema
+            .iter()
+            .filter(|field| field.description.is_empty())
+            .map(|field| field.path.as_str())
+            .collect();
+        assert!(undocumented.is_empty(), "fields without description: {:?}", undocumented);
+
+        let explained = explain("package_type.language_env.rust_binary_url").unwrap();
+        assert!(explained.starts_with("package_type.language_env.rust_binary_url (string)"), "{}", explained);
+        assert!(explained.contains("only with: language_env = \"rust\""), "{}", explained);
+        let explained = explain("build_env.arch").unwrap();
+        assert!(explained.contains("    arm64"), "{}", explained);
+        assert_eq!(
+            explain("build_env.codname").unwrap_err().to_string(),
+            "Unknown field build_env.codname, `pkg-builder explain build_env` lists the fields"
+        );
+
+        let explained = explain("package_type.language_env.cargo_features").unwrap();
+        assert!(explained.contains("example: cargo_features = [\"jemalloc\", \"portable\"]"), "{}", explained);
+        assert!(explain("package_type.language_env.arch_binaries.*.url").is_ok());
+
+        let json_schema = get_json_schema().unwrap();
+        assert_eq!(json_schema["title"], "pkg-builder.toml");
+        assert!(json_schema["required"].as_array().unwrap().contains(&Value::from("build_env")));
+        let rust = json_schema["$defs"]["LanguageEnv"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variant| variant["properties"]["language_env"]["const"] == "rust")
+            .unwrap();
+        assert_eq!(rust["properties"]["cargo_features"]["type"], "array");
+        assert_eq!(json_schema["$defs"]["ArchBinary"]["required"], serde_json::json!(["url", "checksum"]));
+    }
+}
@@ -0,0 +1,56 @@
+// Named credentials, kept out of the config files. The credential <name> is read from the
+// PKG_BUILDER_SECRET_<NAME> environment variable, or from ~/.pkg-builder/secrets/<name>.
+use eyre::{eyre, Result};
+use log::warn;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+pub const SECRETS_DIR: &str = "~/.pkg-builder/secrets";
+
+/// e.g. artifact-store -> PKG_BUILDER_SECRET_ARTIFACT_STORE
+pub fn get_secret_env_var(name: &str) -> String {
+    format!("PKG_BUILDER_SECRET_{}", name.to_uppercase().replace('-', "_"))
+}
+
+/// Reads the credential from the environment, or from secrets_dir, surrounding whitespace is trimmed.
+pub fn read_secret(name: &str, secrets_dir: &Path) -> Result<String> {
+    if let Ok(secret) = std::env::var(get_secret_env_var(name)) {
+        return Ok(secret.trim().to_string());
+    }
+    let path = secrets_dir.join(name);
+    let secret = fs::read_to_string(&path).map_err(|_| {
+        eyre!(
+            "Credential {} not found, set {} or write it to {}",
+            name,
+            get_secret_env_var(name),
+            path.display()
+        )
+    })?;
+    if fs::metadata(&path)?.permissions().mode() & 0o077 != 0 {
+        warn!("{} is readable by other users, restrict it with chmod 600", path.display());
+    }
+    Ok(secret.trim().to_string())
+}
+
+pub fn get_secret(name: &str) -> Result<String> {
+    read_secret(name, Path::new(&shellexpand::tilde(SECRETS_DIR).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_secret() {
+        let dir = tempdir().unwrap();
+        assert_eq!(get_secret_env_var("artifact-store"), "PKG_BUILDER_SECRET_ARTIFACT_STORE");
+        assert!(read_secret("pkg-builder-test-store", dir.path()).is_err());
+
+        let path = dir.path().join("pkg-builder-test-store");
+        fs::write(&path, "Bearer abc123\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_secret("pkg-builder-test-store", dir.path()).unwrap(), "Bearer abc123");
+    }
+}
@@ -0,0 +1,162 @@
+// Thin wrapper around gpg, keys are referenced by fingerprint or user id
+// and have to be available in the user's keyring. Keys on an OpenPGP card or a
+// PKCS#11 token are used through gpg-agent as well, the keyring only holds a stub.
+use crate::v1::workspace::{SigningBackend, SigningConfig};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn run_gpg(key: &str, args: &[&str], input: &Path, output: &Path) -> Result<()> {
+    info!("Signing {} with key {}", input.display(), key);
+    let result = Command::new("gpg")
+        .args(["--batch", "--yes", "--local-user", key])
+        .args(args)
+        .arg("--output")
+        .arg(output)
+        .arg(input)
+        .output()
+        .map_err(|err| eyre!("Failed to run gpg: {}", err))?;
+    if !result.status.success() {
+        return Err(eyre!(
+            "Failed to sign {} with key {}: {}",
+            input.display(),
+            key,
+            String::from_utf8_lossy(&result.stderr)
+        ));
+    }
+    Ok(())
+}
+
+fn get_gpg_output(args: &[&str]) -> Result<String> {
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .args(args)
+        .output()
+        .map_err(|err| eyre!("Failed to run gpg: {}", err))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "gpg {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Inline signature, e.g. InRelease
+pub fn clearsign(input: &Path, output: &Path, key: &str) -> Result<()> {
+    run_gpg(key, &["--clearsign"], input, output)
+}
+
+/// Armored detached signature, e.g. Release.gpg
+pub fn detach_sign(input: &Path, output: &Path, key: &str) -> Result<()> {
+    run_gpg(key, &["--armor", "--detach-sign"], input, output)
+}
+
+/// Signs the .changes files in place like debsign, files already signed are kept.
+pub fn sign_changes(files: &[PathBuf], key: &str) -> Result<()> {
+    for file in files.iter().filter(|file| file.extension() == Some("changes".as_ref())) {
+        if fs::read_to_string(file)?.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+            continue;
+        }
+        let signed = file.with_extension("changes.asc");
+        clearsign(file, &signed, key)?;
+        fs::rename(&signed, file)?;
+    }
+    Ok(())
+}
+
+/// Serial number of the token holding the newest signing key, from the output of
+/// gpg --list-secret-keys --with-colons. Empty when the secret key is in the keyring,
+/// None without a usable signing key.
+pub fn get_signing_key_serial(colons: &str) -> Option<String> {
+    colons
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .filter(|fields| fields.len() > 14 && (fields[0] == "sec" || fields[0] == "ssb"))
+        // revoked and expired keys are not used
+        .rfind(|fields| !["r", "e"].contains(&fields[1]) && fields[11].contains('s'))
+        // + is a secret key in the keyring, # a stub without the secret key
+        .map(|fields| match fields[14] {
+            "+" | "#" => String::new(),
+            serial => serial.to_string(),
+        })
+}
+
+// gnupg-pkcs11-scd replaces scdaemon, gpg-agent has to be told to run it
+fn check_pkcs11_scdaemon() -> Result<()> {
+    let output = Command::new("gpgconf")
+        .args(["--list-options", "gpg-agent"])
+        .output()
+        .map_err(|err| eyre!("Failed to run gpgconf: {}", err))?;
+    let options = String::from_utf8_lossy(&output.stdout);
+    let scdaemon_program = options
+        .lines()
+        .find(|line| line.starts_with("scdaemon-program:"))
+        .unwrap_or_default();
+    if !scdaemon_program.contains("pkcs11") {
+        return Err(eyre!(
+            "signing.backend is pkcs11, but gpg-agent does not run gnupg-pkcs11-scd, add scdaemon-program /usr/bin/gnupg-pkcs11-scd to ~/.gnupg/gpg-agent.conf"
+        ));
+    }
+    Ok(())
+}
+
+/// Fails when the signing key cannot be used, run before the builds so a missing or locked
+/// token does not fail the release after hours of building. The test signature makes the
+/// token ask for its PIN now, gpg-agent caches it for the signatures of the release.
+pub fn check_signing_key(signing: &SigningConfig) -> Result<()> {
+    let key = &signing.key;
+    let backend = signing.backend.unwrap_or(SigningBackend::Gpg);
+    if backend != SigningBackend::Gpg {
+        if backend == SigningBackend::Pkcs11 {
+            check_pkcs11_scdaemon()?;
+        }
+        get_gpg_output(&["--card-status"])
+            .map_err(|err| eyre!("No token for signing key {} found, plug it in: {}", key, err))?;
+    }
+    let colons = get_gpg_output(&["--list-secret-keys", "--with-colons", key])
+        .map_err(|err| eyre!("Signing key {} is not in the keyring: {}", key, err))?;
+    let serial = get_signing_key_serial(&colons)
+        .ok_or_else(|| eyre!("Signing key {} has no valid signing capable secret key", key))?;
+    if backend != SigningBackend::Gpg && serial.is_empty() {
+        return Err(eyre!(
+            "Signing key {} is not on a token, run gpg --card-status to link the token to the keyring",
+            key
+        ));
+    }
+    info!("Unlocking signing key {}, please enter the PIN, if prompted.", key);
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("preflight");
+    fs::write(&input, "pkg-builder signing preflight\n")?;
+    detach_sign(&input, &dir.path().join("preflight.asc"), key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_key_serial() {
+        let keyring = "\
+sec:u:255:22:1111111111111111:1700000000:::u:::scESC:::+:::23::0:
+fpr:::::::::AAAA1111111111111111:
+uid:u::::1700000000::HASH::John Doe <johndoe@example.com>::::::::::0:
+ssb:u:255:18:2222222222222222:1700000000::::::e:::+:::23:
+";
+        assert_eq!(get_signing_key_serial(keyring), Some(String::new()));
+
+        // the signing subkey moved to a YubiKey, the primary key stays offline
+        let card = "\
+sec:u:255:22:1111111111111111:1700000000:::u:::cSC:::#:::23::0:
+ssb:u:255:22:3333333333333333:1700000001::::::s:::D2760001240100000006123456780000:::23:
+ssb:u:255:18:2222222222222222:1700000000::::::e:::D2760001240100000006123456780000:::23:
+";
+        assert_eq!(get_signing_key_serial(card), Some("D2760001240100000006123456780000".to_string()));
+
+        let expired = "sec:e:255:22:1111111111111111:1600000000:1650000000::u:::sc:::+:::23::0:\n";
+        assert_eq!(get_signing_key_serial(expired), None);
+    }
+}
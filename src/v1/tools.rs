@@ -0,0 +1,395 @@
+// The external tools the build depends on and the check of their installed version against
+// the version pinned in build_env. Versions are compared like dpkg does, so vendor builds,
+// e.g. 5.32ubuntu3~22.04.1 of autopkgtest, compare as the Debian packages they are.
+// Every tool a build runs is recorded with its path and version in the build manifest.
+use crate::v1::build::manifest::ToolRecord;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+pub trait Tool {
+    fn name(&self) -> &str;
+    /// The program the build runs, looked up in PATH.
+    fn program(&self) -> String {
+        self.version_command()[0].to_string()
+    }
+    /// Program and arguments printing the installed version.
+    fn version_command(&self) -> Vec<&str>;
+    /// The version in the output of version_command.
+    fn parse_version(&self, output: &str) -> Option<String>;
+    /// The version pinned in the config, None for tools without a pin.
+    fn minimum_version(&self) -> Option<&str>;
+    /// An older version fails the check instead of warning.
+    fn requires_minimum_version(&self) -> bool {
+        false
+    }
+}
+
+pub struct Lintian(pub String);
+pub struct Piuparts(pub String);
+pub struct Autopkgtest(pub String);
+pub struct Sbuild(pub String);
+
+/// debcrafter is installed as debcrafter_<version>, the pin is the program name.
+pub struct Debcrafter {
+    version: String,
+    program: String,
+}
+
+impl Debcrafter {
+    pub fn new(version: &str) -> Self {
+        Debcrafter {
+            version: version.to_string(),
+            program: format!("debcrafter_{}", version),
+        }
+    }
+}
+
+/// A program without a pin in build_env, only recorded.
+pub struct HostTool {
+    program: &'static str,
+    version_args: &'static [&'static str],
+}
+
+impl HostTool {
+    pub fn new(program: &'static str, version_args: &'static [&'static str]) -> Self {
+        HostTool { program, version_args }
+    }
+}
+
+fn capture_version(pattern: &str, output: &str) -> Option<String> {
+    Regex::new(pattern)
+        .unwrap()
+        .captures(output)
+        .map(|captures| captures[1].to_string())
+}
+
+impl Tool for Lintian {
+    fn name(&self) -> &str {
+        "lintian"
+    }
+    fn version_command(&self) -> Vec<&str> {
+        vec!["lintian", "--version"]
+    }
+    // Lintian v2.116.3ubuntu1
+    fn parse_version(&self, output: &str) -> Option<String> {
+        capture_version(r"Lintian v(\S+)", output)
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        Some(&self.0)
+    }
+}
+
+impl Tool for Piuparts {
+    fn name(&self) -> &str {
+        "piuparts"
+    }
+    fn version_command(&self) -> Vec<&str> {
+        vec!["piuparts", "--version"]
+    }
+    // piuparts 1.1.7
+    fn parse_version(&self, output: &str) -> Option<String> {
+        capture_version(r"piuparts (\S+)", output)
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        Some(&self.0)
+    }
+}
+
+impl Tool for Autopkgtest {
+    fn name(&self) -> &str {
+        "autopkgtest"
+    }
+    // autopkgtest has no --version
+    fn program(&self) -> String {
+        "autopkgtest".to_string()
+    }
+    fn version_command(&self) -> Vec<&str> {
+        vec!["dpkg-query", "--show", "--showformat=${Version}", "autopkgtest"]
+    }
+    fn parse_version(&self, output: &str) -> Option<String> {
+        Some(output.trim().to_string()).filter(|version| !version.is_empty())
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        Some(&self.0)
+    }
+}
+
+impl Tool for Sbuild {
+    fn name(&self) -> &str {
+        "sbuild"
+    }
+    fn version_command(&self) -> Vec<&str> {
+        vec!["sbuild", "--version"]
+    }
+    // sbuild (Debian sbuild) 0.85.0 (04 January 2023)
+    fn parse_version(&self, output: &str) -> Option<String> {
+        capture_version(r"sbuild \(Debian sbuild\) (\S+)", output)
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        Some(&self.0)
+    }
+    fn requires_minimum_version(&self) -> bool {
+        true
+    }
+}
+
+impl Tool for Debcrafter {
+    fn name(&self) -> &str {
+        "debcrafter"
+    }
+    fn program(&self) -> String {
+        self.program.clone()
+    }
+    fn version_command(&self) -> Vec<&str> {
+        vec!["which", &self.program]
+    }
+    fn parse_version(&self, output: &str) -> Option<String> {
+        Some(self.version.clone()).filter(|_| !output.trim().is_empty())
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        Some(&self.version)
+    }
+    fn requires_minimum_version(&self) -> bool {
+        true
+    }
+}
+
+impl Tool for HostTool {
+    fn name(&self) -> &str {
+        self.program
+    }
+    fn version_command(&self) -> Vec<&str> {
+        let mut command = vec![self.program];
+        command.extend(self.version_args);
+        command
+    }
+    // the first version number, e.g. tar (GNU tar) 1.34, git version 2.39.2, btrfs-progs v6.2
+    fn parse_version(&self, output: &str) -> Option<String> {
+        capture_version(r"(\d+(?:\.\d+)+[^\s,)]*)", output)
+    }
+    fn minimum_version(&self) -> Option<&str> {
+        None
+    }
+}
+
+// dpkg's order of the non digit parts, ~ sorts before everything, even the end of the part
+fn lexical_order(c: Option<char>) -> i32 {
+    match c {
+        None => 0,
+        Some('~') => -1,
+        Some(c) if c.is_ascii_alphabetic() => c as i32,
+        Some(c) => c as i32 + 256,
+    }
+}
+
+fn compare_part(mut a: &str, mut b: &str) -> Ordering {
+    while !a.is_empty() || !b.is_empty() {
+        let a_end = a.find(|c: char| c.is_ascii_digit()).unwrap_or(a.len());
+        let b_end = b.find(|c: char| c.is_ascii_digit()).unwrap_or(b.len());
+        let (mut a_chars, mut b_chars) = (a[..a_end].chars(), b[..b_end].chars());
+        loop {
+            let (a_char, b_char) = (a_chars.next(), b_chars.next());
+            if a_char.is_none() && b_char.is_none() {
+                break;
+            }
+            match lexical_order(a_char).cmp(&lexical_order(b_char)) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        (a, b) = (&a[a_end..], &b[b_end..]);
+
+        let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+        let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+        let a_number: u64 = a[..a_end].parse().unwrap_or(0);
+        let b_number: u64 = b[..b_end].parse().unwrap_or(0);
+        match a_number.cmp(&b_number) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        (a, b) = (&a[a_end..], &b[b_end..]);
+    }
+    Ordering::Equal
+}
+
+// epoch, upstream version and revision, e.g. 1:2.0-3
+fn split_version(version: &str) -> (u64, &str, &str) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+        None => (0, version),
+    };
+    match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (epoch, upstream, revision),
+        None => (epoch, rest, ""),
+    }
+}
+
+/// Compares Debian versions like dpkg --compare-versions.
+pub fn compare_debian_versions(a: &str, b: &str) -> Ordering {
+    let (a_epoch, a_upstream, a_revision) = split_version(a.trim());
+    let (b_epoch, b_upstream, b_revision) = split_version(b.trim());
+    a_epoch
+        .cmp(&b_epoch)
+        .then_with(|| compare_part(a_upstream, b_upstream))
+        .then_with(|| compare_part(a_revision, b_revision))
+}
+
+pub fn get_installed_version(tool: &dyn Tool) -> Result<String> {
+    let command = tool.version_command();
+    let output = Command::new(command[0])
+        .args(&command[1..])
+        .output()
+        .map_err(|err| eyre!("Failed to execute {}: {}, is {} installed?", command.join(" "), err, tool.name()))?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to execute {}", command.join(" ")));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    tool.parse_version(&stdout)
+        .ok_or_else(|| eyre!("Could not find the {} version in the output of {}: {}", tool.name(), command.join(" "), stdout.trim()))
+}
+
+/// Compares the installed version with the pinned one, a newer version only warns.
+pub fn check_tool_version(tool: &dyn Tool) -> Result<()> {
+    let installed = get_installed_version(tool)?;
+    info!("{} version {}", tool.name(), installed);
+    let Some(expected) = tool.minimum_version() else {
+        return Ok(());
+    };
+    match compare_debian_versions(&installed, expected) {
+        Ordering::Greater => warn!("{} {} is newer than the expected {}", tool.name(), installed, expected),
+        Ordering::Less if tool.requires_minimum_version() => {
+            return Err(eyre!(
+                "{} {} is older than the expected {}, please install a newer version",
+                tool.name(),
+                installed,
+                expected
+            ))
+        }
+        Ordering::Less => warn!("{} {} is older than the expected {}", tool.name(), installed, expected),
+        Ordering::Equal => info!("{} versions match. Proceeding.", tool.name()),
+    }
+    Ok(())
+}
+
+/// The executable program in PATH.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|file| file.metadata().is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0))
+}
+
+/// Path and version of every tool, missing tools are recorded without them.
+pub fn get_tool_records(tools: &[Box<dyn Tool>]) -> Vec<ToolRecord> {
+    tools
+        .iter()
+        .map(|tool| {
+            let path = find_program(&tool.program());
+            ToolRecord {
+                name: tool.name().to_string(),
+                version: path.as_ref().and_then(|_| get_installed_version(tool.as_ref()).ok()),
+                path: path.map(|path| path.to_string_lossy().to_string()),
+                pinned_version: tool.minimum_version().map(str::to_string),
+            }
+        })
+        .collect()
+}
+
+/// Fails unless every pinned tool is installed in exactly the pinned version.
+pub fn check_pinned_versions(records: &[ToolRecord]) -> Result<()> {
+    let mut mismatches = vec![];
+    for record in records.iter() {
+        let Some(pinned) = &record.pinned_version else {
+            continue;
+        };
+        match &record.version {
+            Some(version) if compare_debian_versions(version, pinned) == Ordering::Equal => {}
+            Some(version) => mismatches.push(format!("{} {} is not the pinned {}", record.name, version, pinned)),
+            None => mismatches.push(format!("{} {} is not installed", record.name, pinned)),
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(eyre!(
+            "strict_tool_versions is set, but {}",
+            mismatches.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_debian_versions() {
+        let cases = [
+            ("5.32ubuntu3~22.04.1", "5.32", Ordering::Greater),
+            ("5.32~rc1", "5.32", Ordering::Less),
+            ("2.116.3", "2.116.3", Ordering::Equal),
+            ("2.9", "2.10", Ordering::Less),
+            ("1:1.0", "2.0", Ordering::Greater),
+            ("1.0-1", "1.0-1+b1", Ordering::Less),
+            ("1.0a", "1.0+", Ordering::Less),
+            ("1.0", "1.0.0", Ordering::Less),
+        ];
+        for (a, b, ordering) in cases {
+            assert_eq!(compare_debian_versions(a, b), ordering, "{} {}", a, b);
+            assert_eq!(compare_debian_versions(b, a), ordering.reverse(), "{} {}", b, a);
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let lintian = Lintian("2.116.3".to_string());
+        assert_eq!(lintian.parse_version("Lintian v2.114.0ubuntu1.3\n").unwrap(), "2.114.0ubuntu1.3");
+        let piuparts = Piuparts("1.1.7".to_string());
+        assert_eq!(piuparts.parse_version("piuparts 1.1.7\n").unwrap(), "1.1.7");
+        let autopkgtest = Autopkgtest("5.28".to_string());
+        assert_eq!(autopkgtest.parse_version("5.32ubuntu3~22.04.1").unwrap(), "5.32ubuntu3~22.04.1");
+        assert_eq!(autopkgtest.parse_version(""), None);
+        let sbuild = Sbuild("0.85.0".to_string());
+        assert_eq!(
+            sbuild.parse_version("sbuild (Debian sbuild) 0.85.0 (04 January 2023)\n\nWritten by Roman Roelofsen.\n").unwrap(),
+            "0.85.0"
+        );
+        assert!(sbuild.requires_minimum_version() && !lintian.requires_minimum_version());
+    }
+
+    #[test]
+    fn test_tool_records() {
+        let tar = HostTool::new("tar", &["--version"]);
+        assert_eq!(tar.parse_version("tar (GNU tar) 1.34\n").unwrap(), "1.34");
+        assert_eq!(tar.parse_version("btrfs-progs v6.2\n").unwrap(), "6.2");
+        let debcrafter = Debcrafter::new("1.0.0");
+        assert_eq!(debcrafter.program(), "debcrafter_1.0.0");
+        assert_eq!(Autopkgtest("5.28".to_string()).program(), "autopkgtest");
+
+        let tools: Vec<Box<dyn Tool>> = vec![Box::new(HostTool::new("sh", &["-c", "echo 1.2.3"])), Box::new(debcrafter)];
+        let records = get_tool_records(&tools);
+        assert!(records[0].path.as_ref().unwrap().ends_with("/sh"));
+        assert_eq!(records[0].version.as_deref(), Some("1.2.3"));
+        assert_eq!(records[0].pinned_version, None);
+        assert_eq!(records[1].pinned_version.as_deref(), Some("1.0.0"));
+
+        let mut record = ToolRecord {
+            name: "lintian".to_string(),
+            path: Some("/usr/bin/lintian".to_string()),
+            version: Some("2.116.3".to_string()),
+            pinned_version: Some("2.116.3".to_string()),
+        };
+        assert!(check_pinned_versions(&[record.clone(), records[0].clone()]).is_ok());
+        record.version = Some("2.117.0".to_string());
+        let err = check_pinned_versions(&[record, records[1].clone()]).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "strict_tool_versions is set, but lintian 2.117.0 is not the pinned 2.116.3, debcrafter 1.0.0 is not installed"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+// Watches the archive versions of the build dependencies of a workspace, a changed
+// dependency (e.g. an openssl soname bump) means the depending packages need a rebuild.
+// The versions seen by the last check are kept in workspace.transitions.toml.
+use crate::v1::apt_repo::parse_index;
+use crate::v1::distribution::{get_distribution, DistributionFamily};
+use crate::v1::pkg_config::PkgConfig;
+use eyre::{eyre, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+pub const TRANSITIONS_FILE_NAME: &str = "workspace.transitions.toml";
+
+// versions of the build dependencies per suite and arch, as seen by the last check
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct SeenVersions {
+    /// suite -> arch -> build dependency -> version
+    #[serde(default)]
+    pub versions: BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl SeenVersions {
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(SeenVersions::default());
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|err| eyre!("Failed to parse {}: {}", path.display(), err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|err| eyre!("Failed to serialize {}: {}", path.display(), err))?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn get_archive_mut(&mut self, suite: &str, arch: &str) -> &mut BTreeMap<String, String> {
+        self.versions
+            .entry(suite.to_string())
+            .or_default()
+            .entry(arch.to_string())
+            .or_default()
+    }
+}
+
+/// Archive suite some of the workspace packages are built against.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArchiveTarget {
+    pub repo_url: String,
+    pub suite: String,
+    pub arch: String,
+    pub family: DistributionFamily,
+    /// build dependency -> workspace packages using it
+    pub dependencies: BTreeMap<String, Vec<String>>,
+}
+
+impl ArchiveTarget {
+    pub fn new(config: &PkgConfig) -> Result<Self> {
+        let distribution = get_distribution(&config.build_env.codename)?.info();
+        Ok(ArchiveTarget {
+            repo_url: config
+                .build_env
+                .repo_url
+                .clone()
+                .unwrap_or(distribution.repo_url.to_string()),
+            suite: distribution.suite.to_string(),
+            arch: config.build_env.arch.get_build_arch().to_string(),
+            family: distribution.family,
+            dependencies: BTreeMap::new(),
+        })
+    }
+
+    pub fn is_same_archive(&self, other: &ArchiveTarget) -> bool {
+        self.repo_url == other.repo_url && self.suite == other.suite && self.arch == other.arch
+    }
+
+    pub fn add_dependency(&mut self, dependency: &str, package: &str) {
+        let packages = self.dependencies.entry(dependency.to_string()).or_default();
+        if !packages.iter().any(|other| other == package) {
+            packages.push(package.to_string());
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Transition {
+    pub dependency: String,
+    pub suite: String,
+    pub arch: String,
+    pub previous: Option<String>,
+    pub current: Option<String>,
+    /// version waiting in the proposed suite, when it differs from current
+    pub proposed: Option<String>,
+    /// workspace packages build depending on it
+    pub packages: Vec<String>,
+}
+
+impl Transition {
+    pub fn describe(&self) -> String {
+        let archive = format!("{}/{}", self.suite, self.arch);
+        let mut description = match (&self.previous, &self.current) {
+            (Some(previous), Some(current)) if previous != current => {
+                format!("{} {} -> {} in {}", self.dependency, previous, current, archive)
+            }
+            (_, Some(current)) => format!("{} {} in {}", self.dependency, current, archive),
+            (_, None) => format!("{} is no longer in {}", self.dependency, archive),
+        };
+        if let Some(proposed) = &self.proposed {
+            description.push_str(&format!(", {} upcoming", proposed));
+        }
+        format!("{}, rebuild {}", description, self.packages.join(", "))
+    }
+}
+
+// suite holding the updates of the next point release
+fn get_proposed_suite(family: DistributionFamily, suite: &str) -> String {
+    match family {
+        DistributionFamily::Debian => format!("{}-proposed-updates", suite),
+        DistributionFamily::Ubuntu => format!("{}-proposed", suite),
+    }
+}
+
+fn get_components(family: DistributionFamily) -> &'static [&'static str] {
+    match family {
+        DistributionFamily::Debian => &["main"],
+        DistributionFamily::Ubuntu => &["main", "universe"],
+    }
+}
+
+#[derive(Deserialize)]
+struct Spec {
+    #[serde(default)]
+    build_depends: Vec<String>,
+    #[serde(default)]
+    packages: Vec<String>,
+}
+
+fn read_spec(spec_file: &Path) -> Result<Spec> {
+    let content = fs::read_to_string(spec_file)
+        .map_err(|err| eyre!("Could not read {}: {}", spec_file.display(), err))?;
+    toml::from_str(&content).map_err(|err| eyre!("Failed to parse {}: {}", spec_file.display(), err))
+}
+
+/// Binary packages built from a debcrafter spec.
+pub fn get_binary_packages(spec_file: &Path) -> Result<Vec<String>> {
+    Ok(read_spec(spec_file)?.packages)
+}
+
+/// Package names of the build_depends of a debcrafter spec, relations and alternatives are flattened.
+pub fn get_build_depends(spec_file: &Path) -> Result<Vec<String>> {
+    let spec = read_spec(spec_file)?;
+    let mut names = vec![];
+    for relation in spec.build_depends.iter() {
+        for alternative in relation.split('|') {
+            let name = alternative
+                .trim()
+                .split(|c: char| c.is_whitespace() || c == '(' || c == '[' || c == ':')
+                .next()
+                .unwrap_or_default();
+            if !name.is_empty() && !names.contains(&name.to_string()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Versions of every binary package of the suite, fetched from the archive.
+fn fetch_archive_versions(
+    repo_url: &str,
+    suite: &str,
+    components: &[&str],
+    arch: &str,
+) -> Result<BTreeMap<String, String>> {
+    let dir = tempdir()?;
+    let mut versions = BTreeMap::new();
+    for component in components {
+        let url = format!(
+            "{}/dists/{}/{}/binary-{}/Packages.gz",
+            repo_url.trim_end_matches('/'),
+            suite,
+            component,
+            arch
+        );
+        let index = dir.path().join(format!("{}-Packages.gz", component));
+        let status = Command::new("wget")
+            .arg("-q")
+            .arg("-O")
+            .arg(&index)
+            .arg(&url)
+            .status()?;
+        if !status.success() {
+            return Err(eyre!("Failed to download {}", url));
+        }
+        let output = Command::new("gzip").arg("-dc").arg(&index).output()?;
+        if !output.status.success() {
+            return Err(eyre!("Failed to decompress {}", url));
+        }
+        versions.extend(parse_index(&String::from_utf8_lossy(&output.stdout)));
+    }
+    Ok(versions)
+}
+
+/// Compares the archive against the versions seen last time, dependencies maps
+/// each build dependency to the workspace packages using it.
+pub fn find_transitions(
+    suite: &str,
+    arch: &str,
+    dependencies: &BTreeMap<String, Vec<String>>,
+    seen: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+    proposed: &BTreeMap<String, String>,
+) -> Vec<Transition> {
+    let mut transitions = vec![];
+    for (dependency, packages) in dependencies.iter() {
+        let previous = seen.get(dependency).cloned();
+        let current_version = current.get(dependency).cloned();
+        let proposed_version = proposed
+            .get(dependency)
+            .filter(|version| Some(*version) != current_version.as_ref())
+            .cloned();
+        let changed = previous.is_some() && previous != current_version;
+        if changed || proposed_version.is_some() {
+            transitions.push(Transition {
+                dependency: dependency.clone(),
+                suite: suite.to_string(),
+                arch: arch.to_string(),
+                previous,
+                current: current_version,
+                proposed: proposed_version,
+                packages: packages.clone(),
+            });
+        }
+    }
+    transitions
+}
+
+/// Fetches the current and proposed versions of every target and records the current ones in seen.
+pub fn check_transitions(targets: &[ArchiveTarget], seen: &mut SeenVersions) -> Result<Vec<Transition>> {
+    let mut transitions = vec![];
+    for target in targets {
+        let components = get_components(target.family);
+        let current = fetch_archive_versions(&target.repo_url, &target.suite, components, &target.arch)?;
+        let proposed_suite = get_proposed_suite(target.family, &target.suite);
+        let proposed = fetch_archive_versions(&target.repo_url, &proposed_suite, components, &target.arch)
+            .unwrap_or_else(|err| {
+                warn!("Could not check {}: {}", proposed_suite, err);
+                BTreeMap::new()
+            });
+        let seen_archive = seen.get_archive_mut(&target.suite, &target.arch);
+        transitions.append(&mut find_transitions(
+            &target.suite,
+            &target.arch,
+            &target.dependencies,
+            seen_archive,
+            &current,
+            &proposed,
+        ));
+        for dependency in target.dependencies.keys() {
+            if let Some(version) = current.get(dependency) {
+                seen_archive.insert(dependency.clone(), version.clone());
+            }
+        }
+    }
+    Ok(transitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_get_build_depends() {
+        let dir = tempdir().unwrap();
+        let spec_file = dir.path().join("hello-world.sss");
+        fs::write(
+            &spec_file,
+            "name = \"hello-world\"\nbuild_depends = [\"libssl-dev (>= 3.0)\", \"pkg-config | pkgconf\", \"cmake:native\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            get_build_depends(&spec_file).unwrap(),
+            vec!["libssl-dev", "pkg-config", "pkgconf", "cmake"]
+        );
+    }
+
+    #[test]
+    fn test_find_transitions() {
+        let mut dependencies = BTreeMap::new();
+        dependencies.insert("libssl-dev".to_string(), vec!["geth".to_string(), "reth".to_string()]);
+        dependencies.insert("pkg-config".to_string(), vec!["reth".to_string()]);
+        dependencies.insert("cmake".to_string(), vec!["reth".to_string()]);
+        let seen = versions(&[("libssl-dev", "3.0.11-1~deb12u2"), ("pkg-config", "1.8.1-1")]);
+        let current = versions(&[
+            ("libssl-dev", "3.0.13-1~deb12u1"),
+            ("pkg-config", "1.8.1-1"),
+            ("cmake", "3.25.1-1"),
+        ]);
+        let proposed = versions(&[("pkg-config", "1.8.1-2"), ("cmake", "3.25.1-1")]);
+
+        let transitions =
+            find_transitions("bookworm", "amd64", &dependencies, &seen, &current, &proposed);
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(
+            transitions[0].describe(),
+            "libssl-dev 3.0.11-1~deb12u2 -> 3.0.13-1~deb12u1 in bookworm/amd64, rebuild geth, reth"
+        );
+        assert_eq!(
+            transitions[1].describe(),
+            "pkg-config 1.8.1-1 in bookworm/amd64, 1.8.1-2 upcoming, rebuild reth"
+        );
+    }
+
+    #[test]
+    fn test_seen_versions_are_kept_per_arch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(TRANSITIONS_FILE_NAME);
+        let mut seen = SeenVersions::default();
+        seen.get_archive_mut("bookworm", "amd64")
+            .insert("libssl-dev".to_string(), "3.0.13-1~deb12u1".to_string());
+        seen.get_archive_mut("bookworm", "arm64")
+            .insert("libssl-dev".to_string(), "3.0.11-1~deb12u2".to_string());
+        seen.write(&path).unwrap();
+
+        let mut seen = SeenVersions::read(&path).unwrap();
+        assert_eq!(seen.get_archive_mut("bookworm", "amd64")["libssl-dev"], "3.0.13-1~deb12u1");
+        assert_eq!(seen.get_archive_mut("bookworm", "arm64")["libssl-dev"], "3.0.11-1~deb12u2");
+    }
+}
@@ -0,0 +1,141 @@
+// Upstream version mangling, like uscan's uversionmangle.
+// Rules are perl style substitutions applied in order, e.g.
+//   s/^v//
+//   s/-?(rc|beta|alpha)\.?(\d+)$/~$1$2/
+// Any character may be used as delimiter: s|^release/||
+use eyre::{eyre, Result};
+use regex::Regex;
+
+#[derive(Debug)]
+pub struct MangleRule {
+    pattern: Regex,
+    replacement: String,
+    global: bool,
+}
+
+// splits on unescaped delimiters, escaped delimiters are unescaped
+fn split_rule(body: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            parts.last_mut().unwrap().push(chars.next().unwrap());
+        } else if c == '\\' {
+            let part = parts.last_mut().unwrap();
+            part.push(c);
+            if let Some(next) = chars.next() {
+                part.push(next);
+            }
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    parts
+}
+
+// perl $1 and \1 back references to the regex crate's ${1}
+fn convert_replacement(replacement: &str) -> String {
+    let back_reference = Regex::new(r"[$\\](\d+)").unwrap();
+    back_reference.replace_all(replacement, "$${${1}}").to_string()
+}
+
+impl MangleRule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let invalid = || eyre!("Invalid uversion_mangle rule '{}', expected s/regex/replacement/[g]", rule);
+        let body = rule.strip_prefix('s').ok_or_else(invalid)?;
+        let delimiter = body.chars().next().ok_or_else(invalid)?;
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+            return Err(invalid());
+        }
+        let parts = split_rule(&body[delimiter.len_utf8()..], delimiter);
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let global = match parts[2].as_str() {
+            "" => false,
+            "g" => true,
+            flags => return Err(eyre!("Unsupported flags '{}' in uversion_mangle rule '{}'", flags, rule)),
+        };
+        let pattern = Regex::new(&parts[0])
+            .map_err(|err| eyre!("Invalid regex in uversion_mangle rule '{}': {}", rule, err))?;
+        Ok(MangleRule {
+            pattern,
+            replacement: convert_replacement(&parts[1]),
+            global,
+        })
+    }
+
+    pub fn apply(&self, version: &str) -> String {
+        if self.global {
+            self.pattern.replace_all(version, self.replacement.as_str()).to_string()
+        } else {
+            self.pattern.replace(version, self.replacement.as_str()).to_string()
+        }
+    }
+}
+
+/// Upstream versions must start with a digit and only contain [A-Za-z0-9.+~-]
+pub fn validate_upstream_version(version: &str) -> Result<()> {
+    let upstream_version = Regex::new(r"^[0-9][A-Za-z0-9.+~-]*$").unwrap();
+    if !upstream_version.is_match(version) {
+        return Err(eyre!(
+            "'{}' is not a valid debian upstream version, add uversion_mangle rules to convert it",
+            version
+        ));
+    }
+    Ok(())
+}
+
+pub fn mangle_uversion(version: &str, rules: &[String]) -> Result<String> {
+    let mut mangled = version.to_string();
+    for rule in rules {
+        mangled = MangleRule::parse(rule)?.apply(&mangled);
+    }
+    validate_upstream_version(&mangled)?;
+    Ok(mangled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mangle(version: &str, rules: &[&str]) -> Result<String> {
+        let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+        mangle_uversion(version, &rules)
+    }
+
+    #[test]
+    fn test_mangle_release_candidates() {
+        let rules = [r"s/^v//", r"s/-?(rc|beta|alpha)\.?(\d+)$/~$1$2/"];
+        assert_eq!(mangle("v1.2.3-rc.1", &rules).unwrap(), "1.2.3~rc1");
+        assert_eq!(mangle("v2.0.0-beta.3", &rules).unwrap(), "2.0.0~beta3");
+        assert_eq!(mangle("v0.9.0alpha2", &rules).unwrap(), "0.9.0~alpha2");
+        assert_eq!(mangle("v1.2.3", &rules).unwrap(), "1.2.3");
+    }
+
+    #[test]
+    fn test_mangle_date_based_tags() {
+        assert_eq!(mangle("release/2024.05", &[r"s|^release/||"]).unwrap(), "2024.05");
+        assert_eq!(
+            mangle("nightly-2024_05_01", &[r"s/^nightly-//", r"s/_/./g"]).unwrap(),
+            "2024.05.01"
+        );
+        assert_eq!(mangle("r2024-05", &[r"s/^r(\d{4})-(\d{2})$/\1.\2/"]).unwrap(), "2024.05");
+    }
+
+    #[test]
+    fn test_escaped_delimiter() {
+        assert_eq!(mangle("release/1.0", &[r"s/^release\///"]).unwrap(), "1.0");
+    }
+
+    #[test]
+    fn test_invalid_rules_and_versions() {
+        assert!(mangle("v1.0", &["y/v//"]).unwrap_err().to_string().contains("Invalid uversion_mangle rule"));
+        assert!(mangle("v1.0", &["s/v/"]).is_err());
+        assert!(mangle("v1.0", &["s/(/x/"]).unwrap_err().to_string().contains("Invalid regex"));
+        assert!(mangle("v1.0", &["s/v//i"]).unwrap_err().to_string().contains("Unsupported flags"));
+        assert!(mangle("v1.0", &[]).unwrap_err().to_string().contains("not a valid debian upstream version"));
+    }
+}
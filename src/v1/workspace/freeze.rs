@@ -0,0 +1,189 @@
+// Pins the exact package set of a workspace, so a coordinated release only ships
+// the versions that were tested together. Kept as workspace.freeze.toml next to workspace.toml.
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::pkg_config::{PackageType, PkgConfig};
+use crate::v1::workspace::WorkspaceConfig;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+pub const FREEZE_FILE_NAME: &str = "workspace.freeze.toml";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct FrozenPackage {
+    pub name: String,
+    pub version: String,
+    pub codename: String,
+    pub arch: String,
+    /// tarball_url or git_url@git_tag, submodules as path@commit
+    pub source: String,
+    pub source_hash: Option<String>,
+    /// sha256 of pkg-builder.toml, catches toolchain and packaging changes
+    pub config_sha256: String,
+}
+
+impl FrozenPackage {
+    fn is_same_package(&self, other: &FrozenPackage) -> bool {
+        self.name == other.name && self.codename == other.codename && self.arch == other.arch
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct FreezeFile {
+    pub workspace: String,
+    pub version: String,
+    pub packages: Vec<FrozenPackage>,
+}
+
+impl FreezeFile {
+    pub fn new(workspace: &WorkspaceConfig, packages: Vec<FrozenPackage>) -> Self {
+        FreezeFile {
+            workspace: workspace.workspace.name.clone(),
+            version: workspace.workspace.version.clone(),
+            packages,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|err| {
+            eyre!(
+                "Could not read {}: {}, run `pkg-builder freeze` first.",
+                path.display(),
+                err
+            )
+        })?;
+        toml::from_str(&content).map_err(|err| eyre!("Failed to parse {}: {}", path.display(), err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content =
+            toml::to_string(self).map_err(|err| eyre!("Failed to serialize freeze file: {}", err))?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Fails listing every package whose version, source or config differs from the freeze.
+    pub fn check(&self, packages: &[FrozenPackage]) -> Result<()> {
+        let mut drifted = vec![];
+        for package in packages {
+            let Some(frozen) = self.packages.iter().find(|frozen| frozen.is_same_package(package)) else {
+                drifted.push(format!("{}: not in the freeze", package.name));
+                continue;
+            };
+            if frozen.version != package.version {
+                drifted.push(format!(
+                    "{}: version {} -> {}",
+                    package.name, frozen.version, package.version
+                ));
+            }
+            if frozen.source != package.source || frozen.source_hash != package.source_hash {
+                drifted.push(format!(
+                    "{}: source {} -> {}",
+                    package.name, frozen.source, package.source
+                ));
+            }
+            if frozen.config_sha256 != package.config_sha256 {
+                drifted.push(format!("{}: pkg-builder.toml changed", package.name));
+            }
+        }
+        for frozen in self.packages.iter() {
+            if !packages.iter().any(|package| package.is_same_package(frozen)) {
+                drifted.push(format!("{}: frozen but no longer in the workspace", frozen.name));
+            }
+        }
+        if drifted.is_empty() {
+            return Ok(());
+        }
+        Err(eyre!(
+            "Workspace deviates from the freeze, run `pkg-builder freeze` to accept the changes:\n  {}",
+            drifted.join("\n  ")
+        ))
+    }
+}
+
+pub fn freeze_package(config: &PkgConfig, config_file: &Path) -> Result<FrozenPackage> {
+    let (source, source_hash) = match &config.package_type {
+        PackageType::Default(package_type) => {
+            let source = match &package_type.github_release {
+                Some(release) => format!(
+                    "{} {}",
+                    package_type.get_source_url(&config.package_fields.version_number),
+                    release.get_asset_pattern(&config.package_fields.version_number)
+                ),
+                None => package_type.tarball_url.clone(),
+            };
+            (source, package_type.tarball_hash.clone())
+        }
+        PackageType::Git(package_type) => {
+            let mut source = format!("{}@{}", package_type.git_url, package_type.git_tag);
+            for submodule in package_type.submodules.iter() {
+                source.push_str(&format!(" {}@{}", submodule.path, submodule.commit));
+            }
+            (source, None)
+        }
+        PackageType::Virtual => ("virtual".to_string(), None),
+    };
+    let config_file = fs::File::open(config_file)
+        .map_err(|err| eyre!("Could not open {}: {}", config_file.display(), err))?;
+    Ok(FrozenPackage {
+        name: config.package_fields.package_name.clone(),
+        version: config.package_fields.get_debian_version(),
+        codename: config.build_env.codename.clone(),
+        arch: config.build_env.arch.to_string(),
+        source,
+        source_hash,
+        config_sha256: calculate_sha256(config_file)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::Arch;
+    use crate::v1::workspace::WorkspaceFields;
+    use tempfile::tempdir;
+
+    fn frozen_package(config_file: &Path) -> FrozenPackage {
+        let mut config = PkgConfig::default();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.package_fields.version_number = "1.0.0".to_string();
+        config.package_fields.revision_number = "1".to_string();
+        config.build_env.codename = "bookworm".to_string();
+        config.build_env.arch = Arch::Amd64;
+        freeze_package(&config, config_file).unwrap()
+    }
+
+    #[test]
+    fn test_freeze_detects_drift() {
+        let dir = tempdir().unwrap();
+        let config_file = dir.path().join("pkg-builder.toml");
+        fs::write(&config_file, "[package_fields]\n").unwrap();
+        let workspace = WorkspaceConfig {
+            workspace: WorkspaceFields {
+                name: "eth-node-stack".to_string(),
+                version: "2024.06.1".to_string(),
+            },
+            packages: vec!["hello-world".to_string()],
+            signing: None,
+            channels: vec![],
+        };
+        let freeze_file = dir.path().join(FREEZE_FILE_NAME);
+        FreezeFile::new(&workspace, vec![frozen_package(&config_file)])
+            .write(&freeze_file)
+            .unwrap();
+
+        let freeze = FreezeFile::read(&freeze_file).unwrap();
+        freeze.check(&[frozen_package(&config_file)]).unwrap();
+
+        fs::write(&config_file, "[package_fields]\nrevision_number = \"2\"\n").unwrap();
+        let mut package = frozen_package(&config_file);
+        package.version = "1.0.0-2".to_string();
+        let error = freeze.check(&[package]).unwrap_err().to_string();
+        assert!(error.contains("hello-world: version 1.0.0-1 -> 1.0.0-2"), "{}", error);
+        assert!(error.contains("hello-world: pkg-builder.toml changed"), "{}", error);
+
+        let error = freeze.check(&[]).unwrap_err().to_string();
+        assert!(error.contains("hello-world: frozen but no longer in the workspace"), "{}", error);
+    }
+}
@@ -0,0 +1,194 @@
+pub mod freeze;
+pub mod promote;
+pub mod release;
+pub mod run;
+
+use crate::v1::pkg_config::{validate_not_empty, Validation};
+use eyre::{eyre, Report, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceFields {
+    pub name: String,
+    /// version of the coordinated release, independent of the package versions
+    pub version: String,
+}
+
+impl Validation for WorkspaceFields {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("workspace.name", &self.name) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("workspace.version", &self.version) {
+            errors.push(err);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SigningConfig {
+    /// gpg key fingerprint or user id, the key has to be in the user's keyring
+    pub key: String,
+    /// where the secret key lives, gpg by default
+    pub backend: Option<SigningBackend>,
+}
+
+/// Holder of the secret signing key, gpg-agent is asked for the signatures in every case.
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningBackend {
+    /// secret key in the gpg keyring
+    Gpg,
+    /// OpenPGP card, e.g. a YubiKey, through scdaemon
+    Card,
+    /// PKCS#11 token through gnupg-pkcs11-scd configured as scdaemon-program of gpg-agent
+    Pkcs11,
+}
+
+impl Validation for SigningConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        match validate_not_empty("signing.key", &self.key) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(vec![err]),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// e.g. "dev", "staging" or "stable"
+    pub name: String,
+    /// directory of the APT repository of the channel, relative to the workspace manifest
+    pub path: String,
+}
+
+impl Validation for ChannelConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("channels.name", &self.name) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("channels.path", &self.path) {
+            errors.push(err);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    pub workspace: WorkspaceFields,
+    /// package config files, or directories containing pkg-builder.toml,
+    /// relative to the workspace manifest
+    pub packages: Vec<String>,
+    pub signing: Option<SigningConfig>,
+    /// in promotion order, packages are promoted one channel at a time
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl Validation for WorkspaceConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(mut workspace_errors) = self.workspace.validate() {
+            errors.append(&mut workspace_errors);
+        }
+        if self.packages.is_empty() {
+            errors.push(eyre!("field: packages cannot be empty"));
+        }
+        for package in self.packages.iter() {
+            if let Err(err) = validate_not_empty("packages", package) {
+                errors.push(err);
+            }
+        }
+        if let Some(signing) = &self.signing {
+            if let Err(mut signing_errors) = signing.validate() {
+                errors.append(&mut signing_errors);
+            }
+        }
+        for (index, channel) in self.channels.iter().enumerate() {
+            if let Err(mut channel_errors) = channel.validate() {
+                errors.append(&mut channel_errors);
+            }
+            let previous = &self.channels[..index];
+            if previous.iter().any(|other| other.name == channel.name) {
+                errors.push(eyre!("field: channels has {} more than once", channel.name));
+            }
+            if previous.iter().any(|other| other.path == channel.path) {
+                errors.push(eyre!("field: channels {} shares its path with another channel", channel.name));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::parse;
+
+    #[test]
+    fn test_parse_workspace_config() {
+        let config_str = r#"
+packages = ["rust/hello-world", "go/hello-world/pkg-builder.toml"]
+
+[workspace]
+name = "eth-node-stack"
+version = "2024.06.1"
+
+[signing]
+key = "0123456789ABCDEF"
+backend = "card"
+
+[[channels]]
+name = "dev"
+path = "repo/dev"
+
+[[channels]]
+name = "stable"
+path = "repo/stable"
+"#;
+        let workspace = parse::<WorkspaceConfig>(config_str).unwrap();
+        assert_eq!(workspace.workspace.name, "eth-node-stack");
+        assert_eq!(workspace.packages.len(), 2);
+        let signing = workspace.signing.unwrap();
+        assert_eq!(signing.key, "0123456789ABCDEF");
+        assert_eq!(signing.backend, Some(SigningBackend::Card));
+        assert_eq!(workspace.channels[1].name, "stable");
+    }
+
+    #[test]
+    fn test_empty_workspace_is_error() {
+        let errors = WorkspaceConfig::default().validate().unwrap_err();
+        let expected_errors = [
+            "field: workspace.name cannot be empty",
+            "field: workspace.version cannot be empty",
+            "field: packages cannot be empty",
+        ];
+        assert_eq!(errors.len(), expected_errors.len());
+        for (actual, expected) in errors.iter().zip(expected_errors.iter()) {
+            assert_eq!(actual.to_string(), *expected);
+        }
+    }
+}
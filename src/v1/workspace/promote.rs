@@ -0,0 +1,333 @@
+// Promotes the packages of a workspace through its channels, e.g. dev -> staging -> stable.
+// Each channel is an APT repository laid out like the one of a release, a package is copied
+// into the next channel only when its quality report is green and its artifacts match
+// pkg-builder-verify.toml. The quality report travels along in the pool, so every
+// promotion checks the same report.
+use crate::v1::apt_repo::AptRepo;
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use crate::v1::build::quality::QualityReport;
+use crate::v1::build::sbuild::{calculate_sha1, parse_changes_checksums};
+use crate::v1::distribution::get_distribution;
+use crate::v1::pkg_config::{Arch, PkgConfig};
+use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use crate::v1::signing::sign_changes;
+use crate::v1::workspace::release::check_not_published;
+use crate::v1::workspace::{ChannelConfig, WorkspaceConfig};
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct PromotedPackage {
+    pub name: String,
+    /// version in the artifact file names, e.g. 1.0.0-1
+    pub file_version: String,
+    pub suite: String,
+}
+
+impl PromotedPackage {
+    pub fn new(config: &PkgConfig) -> Result<Self> {
+        Ok(PromotedPackage {
+            name: config.package_fields.package_name.clone(),
+            file_version: config.package_fields.get_file_version(),
+            suite: get_distribution(&config.build_env.codename)?.info().key.to_string(),
+        })
+    }
+
+    fn get_pool_dir(&self, channel_dir: &Path) -> PathBuf {
+        channel_dir.join("pool").join(&self.suite).join(&self.name)
+    }
+}
+
+/// Without from the last build is published into the first channel,
+/// otherwise to has to be the channel following from.
+pub fn check_promotion(channels: &[ChannelConfig], from: Option<&str>, to: &str) -> Result<()> {
+    let position = |name: &str| {
+        channels
+            .iter()
+            .position(|channel| channel.name == name)
+            .ok_or_else(|| eyre!("No channel {} in the [[channels]] of the workspace", name))
+    };
+    let to_position = position(to)?;
+    let expected = match from {
+        Some(from) => position(from)? + 1,
+        None => 0,
+    };
+    if to_position != expected {
+        let source = from.unwrap_or("the build");
+        return match channels.get(expected) {
+            Some(next) => Err(eyre!("{} can only be promoted to {}, not {}", source, next.name, to)),
+            None => Err(eyre!("{} is the last channel, there is nothing to promote to", source)),
+        };
+    }
+    Ok(())
+}
+
+pub fn get_channel_dir(workspace: &WorkspaceConfig, workspace_root: &Path, name: &str) -> Result<PathBuf> {
+    let channel = workspace
+        .channels
+        .iter()
+        .find(|channel| channel.name == name)
+        .ok_or_else(|| eyre!("No channel {} in the [[channels]] of the workspace", name))?;
+    Ok(workspace_root.join(&channel.path))
+}
+
+// the one file of the package version in the pool with the suffix
+fn find_pool_file(pool_dir: &Path, package: &PromotedPackage, suffix: &str) -> Result<PathBuf> {
+    let prefix = format!("{}_{}_", package.name, package.file_version);
+    let mut found = vec![];
+    if pool_dir.exists() {
+        for entry in fs::read_dir(pool_dir)? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            if file_name.starts_with(&prefix) && file_name.ends_with(suffix) {
+                found.push(path);
+            }
+        }
+    }
+    match found.len() {
+        1 => Ok(found.remove(0)),
+        0 => Err(eyre!(
+            "{} {} has no {} in {}, was it promoted there?",
+            package.name,
+            package.file_version,
+            suffix,
+            pool_dir.display()
+        )),
+        _ => Err(eyre!("{} has more than one {}{}", pool_dir.display(), prefix, suffix)),
+    }
+}
+
+/// Artifacts of the package in a channel, as listed by its changes file, and its quality report.
+pub fn get_channel_package(channel_dir: &Path, package: &PromotedPackage) -> Result<(Vec<PathBuf>, PathBuf)> {
+    let pool_dir = package.get_pool_dir(channel_dir);
+    let changes_file = find_pool_file(&pool_dir, package, ".changes")?;
+    let mut artifacts = vec![];
+    for checksum in parse_changes_checksums(&fs::read_to_string(&changes_file)?)? {
+        let artifact = pool_dir.join(&checksum.name);
+        if !artifact.exists() {
+            return Err(eyre!("{} of {} is missing", checksum.name, changes_file.display()));
+        }
+        artifacts.push(artifact);
+    }
+    artifacts.push(changes_file);
+    Ok((artifacts, find_pool_file(&pool_dir, package, ".quality.json")?))
+}
+
+pub fn check_quality(quality_report_file: &Path) -> Result<()> {
+    if !quality_report_file.exists() {
+        return Err(eyre!(
+            "No quality report {}, only packages built by pkg-builder can be promoted",
+            quality_report_file.display()
+        ));
+    }
+    let failures = QualityReport::read(quality_report_file)?.get_failures();
+    if !failures.is_empty() {
+        return Err(eyre!(
+            "Quality report {} is not green:\n{}",
+            quality_report_file.display(),
+            failures.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+/// Every file pinned in pkg-builder-verify.toml has to be promoted with the pinned hash.
+pub fn check_verify_hashes(verify_config: &PkgVerifyConfig, artifacts: &[PathBuf]) -> Result<()> {
+    let mut errors = vec![];
+    for package_hash in verify_config.verify.package_hash.iter() {
+        let artifact = artifacts
+            .iter()
+            .find(|artifact| artifact.file_name() == Some(package_hash.name.as_ref()));
+        match artifact {
+            Some(artifact) => {
+                let actual_sha1 = calculate_sha1(open_for_hashing(artifact)?)?;
+                if actual_sha1 != package_hash.hash {
+                    errors.push(format!("file {} actual sha1 is {}", package_hash.name, actual_sha1));
+                }
+            }
+            None => errors.push(format!("file {} is not among the artifacts", package_hash.name)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(eyre!("Artifacts do not match pkg-builder-verify.toml:\n{}", errors.join("\n")));
+    }
+    Ok(())
+}
+
+// file name -> sha256 of the files already in the pool
+fn get_published(pool_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut published = BTreeMap::new();
+    if pool_dir.exists() {
+        for entry in fs::read_dir(pool_dir)? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            published.insert(file_name, calculate_sha256(open_for_hashing(&path)?)?);
+        }
+    }
+    Ok(published)
+}
+
+// archs already indexed in the suite and the ones of the promoted debs
+fn get_suite_archs(channel_dir: &Path, suite: &str, artifacts: &[PathBuf]) -> Result<Vec<String>> {
+    let mut archs = BTreeSet::new();
+    let main_dir = channel_dir.join("dists").join(suite).join("main");
+    if main_dir.exists() {
+        for entry in fs::read_dir(&main_dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Some(arch) = name.strip_prefix("binary-") {
+                archs.insert(arch.to_string());
+            }
+        }
+    }
+    for artifact in artifacts.iter().filter(|artifact| artifact.extension() == Some("deb".as_ref())) {
+        let file_stem = artifact.file_stem().unwrap().to_string_lossy().to_string();
+        match file_stem.rsplit('_').next() {
+            Some("all") => archs.insert(Arch::All.get_build_arch().to_string()),
+            Some(arch) => archs.insert(arch.to_string()),
+            None => false,
+        };
+    }
+    Ok(archs.into_iter().collect())
+}
+
+/// Copies the artifacts and the quality report into the channel and re-signs its indices.
+pub fn promote_package(
+    workspace: &WorkspaceConfig,
+    channel_dir: &Path,
+    package: &PromotedPackage,
+    artifacts: &[PathBuf],
+    quality_report_file: &Path,
+    verify_config: &PkgVerifyConfig,
+) -> Result<()> {
+    check_quality(quality_report_file)?;
+    check_verify_hashes(verify_config, artifacts)?;
+    check_not_published(&get_published(&package.get_pool_dir(channel_dir))?, artifacts)?;
+
+    let repo = AptRepo::new(channel_dir.to_path_buf(), workspace.workspace.name.clone());
+    let mut files = artifacts.to_vec();
+    files.push(quality_report_file.to_path_buf());
+    let added = repo.add_package(&package.suite, &package.name, &files)?;
+    if let Some(signing) = &workspace.signing {
+        sign_changes(&added, &signing.key)?;
+    }
+    let archs = get_suite_archs(channel_dir, &package.suite, artifacts)?;
+    repo.update_suite(&package.suite, &archs)?;
+    repo.check_sources(&package.suite, &archs)?;
+    match &workspace.signing {
+        Some(signing) => repo.sign_suite(&package.suite, &signing.key)?,
+        None => warn!("No [signing] key configured in the workspace, {} is not signed.", channel_dir.display()),
+    }
+    info!(
+        "Promoted {} {} into {}",
+        package.name,
+        package.file_version,
+        channel_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::apt_repo::tests::{create_deb, create_dsc};
+    use crate::v1::build::quality::AutopkgtestResult;
+    use crate::v1::pkg_config_verify::{PackageHash, VerifyConfig};
+    use crate::v1::workspace::WorkspaceFields;
+    use tempfile::tempdir;
+
+    fn channel(name: &str) -> ChannelConfig {
+        ChannelConfig {
+            name: name.to_string(),
+            path: format!("repo/{}", name),
+        }
+    }
+
+    // build output of hello-world 1.0.0-1 and the verify config pinning its deb
+    fn built_package(dir: &Path) -> (Vec<PathBuf>, PathBuf, PkgVerifyConfig) {
+        let mut artifacts = create_dsc(dir, "hello-world", "1.0.0-1");
+        let deb = create_deb(dir, "hello-world", "1.0.0-1", "amd64");
+        artifacts.push(deb.clone());
+        let mut changes = "Source: hello-world\nVersion: 1.0.0-1\nChecksums-Sha256:\n".to_string();
+        for artifact in artifacts.iter() {
+            let size = fs::metadata(artifact).unwrap().len();
+            let hash = calculate_sha256(fs::File::open(artifact).unwrap()).unwrap();
+            changes.push_str(&format!(" {} {} {}\n", hash, size, artifact.file_name().unwrap().to_str().unwrap()));
+        }
+        let changes_file = dir.join("hello-world_1.0.0-1_amd64.changes");
+        fs::write(&changes_file, changes).unwrap();
+        artifacts.push(changes_file);
+
+        let quality_report_file = dir.join("hello-world_1.0.0-1_amd64.quality.json");
+        QualityReport::default().write(&quality_report_file).unwrap();
+        let verify_config = PkgVerifyConfig {
+            verify: VerifyConfig {
+                package_hash: vec![PackageHash {
+                    name: "hello-world_1.0.0-1_amd64.deb".to_string(),
+                    hash: calculate_sha1(fs::File::open(&deb).unwrap()).unwrap(),
+                }],
+            },
+        };
+        (artifacts, quality_report_file, verify_config)
+    }
+
+    #[test]
+    fn test_promote_through_channels() {
+        let channels = [channel("dev"), channel("staging"), channel("stable")];
+        check_promotion(&channels, None, "dev").unwrap();
+        check_promotion(&channels, Some("dev"), "staging").unwrap();
+        let error = check_promotion(&channels, Some("dev"), "stable").unwrap_err().to_string();
+        assert_eq!(error, "dev can only be promoted to staging, not stable");
+        assert!(check_promotion(&channels, Some("stable"), "dev").is_err());
+        assert!(check_promotion(&channels, None, "qa").is_err());
+
+        let build_dir = tempdir().unwrap();
+        let root = tempdir().unwrap();
+        let workspace = WorkspaceConfig {
+            workspace: WorkspaceFields {
+                name: "eth-node-stack".to_string(),
+                version: "2024.06.1".to_string(),
+            },
+            packages: vec!["hello-world".to_string()],
+            signing: None,
+            channels: channels.to_vec(),
+        };
+        let package = PromotedPackage {
+            name: "hello-world".to_string(),
+            file_version: "1.0.0-1".to_string(),
+            suite: "bookworm".to_string(),
+        };
+        let (artifacts, quality_report_file, verify_config) = built_package(build_dir.path());
+        let dev_dir = get_channel_dir(&workspace, root.path(), "dev").unwrap();
+        promote_package(&workspace, &dev_dir, &package, &artifacts, &quality_report_file, &verify_config).unwrap();
+        assert!(dev_dir.join("dists/bookworm/Release").exists());
+
+        let (dev_artifacts, dev_quality_report_file) = get_channel_package(&dev_dir, &package).unwrap();
+        assert_eq!(dev_artifacts.len(), artifacts.len());
+        assert_eq!(dev_quality_report_file.parent().unwrap(), package.get_pool_dir(&dev_dir));
+        let staging_dir = get_channel_dir(&workspace, root.path(), "staging").unwrap();
+        let mut wrong_hash = verify_config.clone();
+        wrong_hash.verify.package_hash[0].hash = "0000".to_string();
+        let error = promote_package(&workspace, &staging_dir, &package, &dev_artifacts, &dev_quality_report_file, &wrong_hash)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("file hello-world_1.0.0-1_amd64.deb actual sha1 is"), "{}", error);
+        promote_package(&workspace, &staging_dir, &package, &dev_artifacts, &dev_quality_report_file, &verify_config).unwrap();
+        let packages = fs::read_to_string(staging_dir.join("dists/bookworm/main/binary-amd64/Packages")).unwrap();
+        assert!(packages.contains("Package: hello-world"));
+
+        // a failed test keeps the package where it is
+        let report = QualityReport {
+            autopkgtest: Some(vec![AutopkgtestResult {
+                test: "smoke".to_string(),
+                result: "FAIL".to_string(),
+                details: "non-zero exit status 1".to_string(),
+            }]),
+            ..Default::default()
+        };
+        report.write(&dev_quality_report_file).unwrap();
+        let error = check_quality(&dev_quality_report_file).unwrap_err().to_string();
+        assert!(error.ends_with("autopkgtest: smoke failed"), "{}", error);
+    }
+}
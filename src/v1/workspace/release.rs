@@ -0,0 +1,368 @@
+// Collects the built packages of a workspace into one publishable directory:
+//   pool/, dists/         APT repository
+//   sbom/, provenance/    per package attestations, provenance signed next to it
+//   SHA256SUMS            checksums of every file above
+//   RELEASE, RELEASE.asc  release manifest and its signature
+use crate::v1::apt_repo::AptRepo;
+use crate::v1::attestation::{create_provenance, create_sbom};
+use crate::v1::build::dir_setup::{calculate_sha256, open_for_hashing};
+use crate::v1::distribution::get_distribution;
+use crate::v1::packager::DistributionPackager;
+use crate::v1::pkg_config::PkgConfig;
+use crate::v1::signing::{detach_sign, sign_changes};
+use crate::v1::workspace::WorkspaceConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CHECKSUMS_FILE_NAME: &str = "SHA256SUMS";
+pub const RELEASE_FILE_NAME: &str = "RELEASE";
+pub const RELEASE_SIGNATURE_FILE_NAME: &str = "RELEASE.asc";
+
+pub struct ReleasePackage {
+    pub config: PkgConfig,
+    pub packager: DistributionPackager,
+}
+
+#[derive(Debug, Serialize)]
+struct ReleaseManifestPackage {
+    name: String,
+    version: String,
+    codename: String,
+    arch: String,
+    artifacts: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReleaseManifest {
+    name: String,
+    version: String,
+    pkg_builder_version: String,
+    checksums_sha256: String,
+    packages: Vec<ReleaseManifestPackage>,
+}
+
+fn write_json(path: &Path, value: &serde_json::Value) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    let content = serde_json::to_string_pretty(value)
+        .map_err(|err| eyre!("Failed to serialize {}: {}", path.display(), err))?;
+    fs::write(path, content + "\n")?;
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Writes a sha256sum compatible checksums file over every file in the release.
+pub fn write_checksums(out_dir: &Path) -> Result<PathBuf> {
+    let mut files = vec![];
+    collect_files(out_dir, &mut files)?;
+    let mut names: Vec<String> = files
+        .iter()
+        .map(|file| file.strip_prefix(out_dir).unwrap().to_str().unwrap().to_string())
+        .filter(|name| {
+            name != CHECKSUMS_FILE_NAME
+                && name != RELEASE_FILE_NAME
+                && name != RELEASE_SIGNATURE_FILE_NAME
+        })
+        .collect();
+    names.sort();
+    let mut checksums = String::new();
+    for name in names {
+        let hash = calculate_sha256(open_for_hashing(&out_dir.join(&name))?)?;
+        checksums.push_str(&format!("{}  {}\n", hash, name));
+    }
+    let checksums_file = out_dir.join(CHECKSUMS_FILE_NAME);
+    fs::write(&checksums_file, checksums)?;
+    Ok(checksums_file)
+}
+
+// file name -> sha256 of every package published in a release
+fn read_published_packages(release_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let checksums_file = release_dir.join(CHECKSUMS_FILE_NAME);
+    let checksums = fs::read_to_string(&checksums_file).map_err(|err| {
+        eyre!("Could not read {}: {}", checksums_file.display(), err)
+    })?;
+    let mut published = BTreeMap::new();
+    for line in checksums.lines() {
+        if let Some((hash, name)) = line.split_once("  ") {
+            if name.starts_with("pool/") {
+                let file_name = Path::new(name).file_name().unwrap().to_str().unwrap();
+                published.insert(file_name.to_string(), hash.to_string());
+            }
+        }
+    }
+    Ok(published)
+}
+
+/// Repositories reject a version-revision that was already published with
+/// different contents, the revision has to be bumped for packaging only changes.
+pub fn check_not_published(
+    published: &BTreeMap<String, String>,
+    artifacts: &[PathBuf],
+) -> Result<()> {
+    for artifact in artifacts.iter().filter(|artifact| artifact.extension() == Some("deb".as_ref())) {
+        let file_name = artifact.file_name().unwrap().to_str().unwrap();
+        if let Some(published_hash) = published.get(file_name) {
+            let hash = calculate_sha256(open_for_hashing(artifact)?)?;
+            if *published_hash != hash {
+                return Err(eyre!(
+                    "{} was already published with different contents, run `pkg-builder bump --revision` and rebuild.",
+                    file_name
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The release is written into a new directory, checked before the packages are built.
+pub fn check_out_dir(out_dir: &Path) -> Result<()> {
+    if out_dir.exists() && fs::read_dir(out_dir)?.next().is_some() {
+        return Err(eyre!(
+            "Release directory {} is not empty, refusing to overwrite it.",
+            out_dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// previous_release is the output directory of an earlier release of the workspace,
+/// packages already published there must not change without a revision bump.
+pub fn create_release(
+    workspace: &WorkspaceConfig,
+    packages: &[ReleasePackage],
+    out_dir: &Path,
+    previous_release: Option<&Path>,
+) -> Result<()> {
+    check_out_dir(out_dir)?;
+    let published = match previous_release {
+        Some(previous_release) => read_published_packages(previous_release)?,
+        None => BTreeMap::new(),
+    };
+    fs::create_dir_all(out_dir)?;
+    let repo = AptRepo::new(out_dir.to_path_buf(), workspace.workspace.name.clone());
+    let mut suites: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut manifest_packages = vec![];
+
+    for package in packages {
+        // arch is "all" for packages detected to only build arch all debs
+        let config = package.packager.get_build_config()?;
+        let package_fields = &config.package_fields;
+        let build_env = &config.build_env;
+        let artifacts = package.packager.get_artifacts()?;
+        check_not_published(&published, &artifacts)?;
+        let suite = get_distribution(&build_env.codename)?.info().key;
+        let added = repo.add_package(suite, &package_fields.package_name, &artifacts)?;
+        if let Some(signing) = &workspace.signing {
+            sign_changes(&added, &signing.key)?;
+        }
+        suites
+            .entry(suite.to_string())
+            .or_default()
+            .insert(build_env.arch.get_build_arch().to_string());
+
+        let base_name = format!(
+            "{}_{}_{}",
+            package_fields.package_name,
+            package_fields.get_file_version(),
+            build_env.arch.get_deb_arch()
+        );
+        write_json(
+            &out_dir.join("sbom").join(format!("{}.cdx.json", base_name)),
+            &create_sbom(&config, &artifacts, &package.packager.get_source_licenses()?)?,
+        )?;
+        let provenance_file = out_dir.join("provenance").join(format!("{}.intoto.json", base_name));
+        write_json(
+            &provenance_file,
+            &create_provenance(&config, &artifacts, package.packager.get_recipe_revision()?.as_deref())?,
+        )?;
+        if let Some(signing) = &workspace.signing {
+            detach_sign(&provenance_file, &provenance_file.with_extension("json.asc"), &signing.key)?;
+        }
+        manifest_packages.push(ReleaseManifestPackage {
+            name: package_fields.package_name.clone(),
+            version: package_fields.get_debian_version(),
+            codename: build_env.codename.clone(),
+            arch: build_env.arch.to_string(),
+            artifacts: artifacts
+                .iter()
+                .map(|artifact| artifact.file_name().unwrap().to_str().unwrap().to_string())
+                .collect(),
+        });
+    }
+
+    for (suite, archs) in suites.iter() {
+        let archs: Vec<String> = archs.iter().cloned().collect();
+        repo.update_suite(suite, &archs)?;
+        repo.check_sources(suite, &archs)?;
+        if let Some(signing) = &workspace.signing {
+            repo.sign_suite(suite, &signing.key)?;
+        }
+    }
+
+    let checksums_file = write_checksums(out_dir)?;
+    let manifest = ReleaseManifest {
+        name: workspace.workspace.name.clone(),
+        version: workspace.workspace.version.clone(),
+        pkg_builder_version: env!("CARGO_PKG_VERSION").to_string(),
+        checksums_sha256: calculate_sha256(fs::File::open(&checksums_file)?)?,
+        packages: manifest_packages,
+    };
+    let release_file = out_dir.join(RELEASE_FILE_NAME);
+    let content = toml::to_string(&manifest)
+        .map_err(|err| eyre!("Failed to serialize release manifest: {}", err))?;
+    fs::write(&release_file, content)?;
+    match &workspace.signing {
+        Some(signing) => detach_sign(
+            &release_file,
+            &out_dir.join(RELEASE_SIGNATURE_FILE_NAME),
+            &signing.key,
+        )?,
+        None => warn!("No [signing] key configured in the workspace, the release is not signed."),
+    }
+    info!(
+        "Release {} {} written to {}",
+        workspace.workspace.name,
+        workspace.workspace.version,
+        out_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::apt_repo::tests::{create_deb, create_dsc};
+    use crate::v1::pkg_config::Arch;
+    use crate::v1::workspace::WorkspaceFields;
+    use tempfile::tempdir;
+
+    // lays out what sbuild would have produced for the config, deb_arch "all" for a
+    // package whose debian/control only has arch all packages
+    fn built_package(workdir: &Path, deb_arch: &str) -> ReleasePackage {
+        let mut config = PkgConfig::default();
+        config.package_fields.package_name = "hello-world".to_string();
+        config.package_fields.version_number = "1.0.0".to_string();
+        config.package_fields.revision_number = "1".to_string();
+        config.build_env.codename = "bookworm".to_string();
+        config.build_env.arch = Arch::Amd64;
+        config.build_env.workdir = Some(workdir.to_str().unwrap().to_string());
+
+        let deb_dir = workdir.join("hello-world-1.0.0-1");
+        fs::create_dir_all(&deb_dir).unwrap();
+        let mut files = create_dsc(&deb_dir, "hello-world", "1.0.0-1");
+        files.push(create_deb(&deb_dir, "hello-world", "1.0.0-1", deb_arch));
+        let debian_dir = deb_dir.join("hello-world-1.0.0").join("debian");
+        fs::create_dir_all(&debian_dir).unwrap();
+        fs::write(
+            debian_dir.join("control"),
+            format!("Source: hello-world\n\nPackage: hello-world\nArchitecture: {}\n", deb_arch),
+        )
+        .unwrap();
+        let mut changes = "Source: hello-world\nVersion: 1.0.0-1\nChecksums-Sha256:\n".to_string();
+        for file in files {
+            let size = fs::metadata(&file).unwrap().len();
+            let hash = calculate_sha256(fs::File::open(&file).unwrap()).unwrap();
+            changes.push_str(&format!(" {} {} {}\n", hash, size, file.file_name().unwrap().to_str().unwrap()));
+        }
+        fs::write(deb_dir.join(format!("hello-world_1.0.0-1_{}.changes", deb_arch)), changes).unwrap();
+        let packager = DistributionPackager::new(config.clone(), workdir.to_str().unwrap().to_string());
+        ReleasePackage { config, packager }
+    }
+
+    #[test]
+    fn test_create_release_from_built_packages() {
+        let workdir = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let out_dir = out.path().join("dist");
+        let workspace = WorkspaceConfig {
+            workspace: WorkspaceFields {
+                name: "eth-node-stack".to_string(),
+                version: "2024.06.1".to_string(),
+            },
+            packages: vec!["hello-world".to_string()],
+            signing: None,
+            channels: vec![],
+        };
+
+        create_release(&workspace, &[built_package(workdir.path(), "amd64")], &out_dir, None).unwrap();
+
+        assert!(out_dir
+            .join("pool/bookworm/hello-world/hello-world_1.0.0-1_amd64.deb")
+            .exists());
+        assert!(out_dir.join("dists/bookworm/Release").exists());
+        assert!(out_dir.join("sbom/hello-world_1.0.0-1_amd64.cdx.json").exists());
+        assert!(out_dir
+            .join("provenance/hello-world_1.0.0-1_amd64.intoto.json")
+            .exists());
+        let checksums = fs::read_to_string(out_dir.join(CHECKSUMS_FILE_NAME)).unwrap();
+        assert!(checksums.contains("  pool/bookworm/hello-world/hello-world_1.0.0-1_amd64.changes\n"));
+        assert!(checksums.contains("  dists/bookworm/main/binary-amd64/Packages\n"));
+        let release = fs::read_to_string(out_dir.join(RELEASE_FILE_NAME)).unwrap();
+        assert!(release.contains("name = \"eth-node-stack\""));
+        assert!(release.contains("version = \"1.0.0-1\""));
+
+        let result = create_release(&workspace, &[built_package(workdir.path(), "amd64")], &out_dir, None);
+        assert!(result.unwrap_err().to_string().contains("is not empty"));
+
+        // unchanged rebuild of an already published version is fine
+        let next_out_dir = out.path().join("dist-next");
+        create_release(&workspace, &[built_package(workdir.path(), "amd64")], &next_out_dir, Some(&out_dir)).unwrap();
+    }
+
+    #[test]
+    fn test_create_release_of_arch_all_package() {
+        let workdir = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let out_dir = out.path().join("dist");
+        let workspace = WorkspaceConfig {
+            workspace: WorkspaceFields {
+                name: "eth-node-stack".to_string(),
+                version: "2024.06.1".to_string(),
+            },
+            packages: vec!["hello-world".to_string()],
+            signing: None,
+            channels: vec![],
+        };
+
+        // arch = "amd64" in the config, debian/control only has arch all packages
+        create_release(&workspace, &[built_package(workdir.path(), "all")], &out_dir, None).unwrap();
+
+        assert!(out_dir
+            .join("pool/bookworm/hello-world/hello-world_1.0.0-1_all.deb")
+            .exists());
+        assert!(out_dir.join("sbom/hello-world_1.0.0-1_all.cdx.json").exists());
+        let binary_dir = format!("binary-{}", Arch::All.get_build_arch());
+        let packages = fs::read_to_string(out_dir.join("dists/bookworm/main").join(binary_dir).join("Packages")).unwrap();
+        assert!(packages.contains("Architecture: all"));
+        let release = fs::read_to_string(out_dir.join(RELEASE_FILE_NAME)).unwrap();
+        assert!(release.contains("arch = \"all\""));
+    }
+
+    #[test]
+    fn test_check_not_published() {
+        let dir = tempdir().unwrap();
+        let deb = create_deb(dir.path(), "hello-world", "1.0.0-1", "amd64");
+        let hash = calculate_sha256(fs::File::open(&deb).unwrap()).unwrap();
+        let mut published = BTreeMap::new();
+        published.insert("hello-world_1.0.0-1_amd64.deb".to_string(), hash);
+        check_not_published(&published, std::slice::from_ref(&deb)).unwrap();
+
+        published.insert("hello-world_1.0.0-1_amd64.deb".to_string(), "0000".to_string());
+        let error = check_not_published(&published, &[deb]).unwrap_err().to_string();
+        assert!(error.contains("run `pkg-builder bump --revision`"), "{}", error);
+    }
+}
@@ -0,0 +1,424 @@
+// Outcome of every package of the workspace builds, kept in workspace.run.toml next to
+// workspace.toml. A retry only rebuilds the failed packages and the workspace packages
+// build depending on them, the earlier runs stay in the file. The same goes for the
+// packages changed since a git ref.
+use crate::v1::transition::{get_binary_packages, get_build_depends};
+use crate::v1::workspace::release::ReleasePackage;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const RUN_STATE_FILE_NAME: &str = "workspace.run.toml";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildStatus {
+    Succeeded,
+    Failed,
+    /// built successfully by an earlier run, its artifacts were reused
+    Reused,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PackageResult {
+    pub name: String,
+    pub version: String,
+    pub status: BuildStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct RunReport {
+    pub retry: bool,
+    /// git ref the changed packages were selected by
+    pub changed_since: Option<String>,
+    pub packages: Vec<PackageResult>,
+}
+
+/// Packages a workspace build rebuilds, the others reuse the artifacts of earlier runs.
+#[derive(Debug, PartialEq, Clone)]
+pub enum BuildSelection {
+    All,
+    /// packages failed or changed version since the recorded runs
+    RetryFailed,
+    /// packages changed since the git ref
+    ChangedSince {
+        git_ref: String,
+        packages: BTreeSet<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct RunState {
+    pub runs: Vec<RunReport>,
+}
+
+impl RunState {
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(RunState::default());
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|err| eyre!("Failed to parse {}: {}", path.display(), err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self)
+            .map_err(|err| eyre!("Failed to serialize {}: {}", path.display(), err))?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // built by an earlier run and not changed since
+    fn is_built(&self, name: &str, version: &str) -> bool {
+        let last_result = self
+            .runs
+            .iter()
+            .rev()
+            .find_map(|run| run.packages.iter().find(|result| result.name == name));
+        matches!(
+            last_result,
+            Some(result) if result.version == version && result.status != BuildStatus::Failed
+        )
+    }
+}
+
+/// workspace package -> workspace packages it build depends on, specs holds (package name, spec file).
+pub fn get_workspace_dependencies(specs: &[(String, PathBuf)]) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut binary_packages = vec![];
+    for (name, spec_file) in specs {
+        binary_packages.push((name, get_binary_packages(spec_file)?));
+    }
+    let mut dependencies = BTreeMap::new();
+    for (name, spec_file) in specs {
+        let build_depends = get_build_depends(spec_file)?;
+        let depends_on: Vec<String> = binary_packages
+            .iter()
+            .filter(|(other, binaries)| {
+                *other != name && binaries.iter().any(|binary| build_depends.contains(binary))
+            })
+            .map(|(other, _)| other.to_string())
+            .collect();
+        dependencies.insert(name.clone(), depends_on);
+    }
+    Ok(dependencies)
+}
+
+/// Packages not built by an earlier run or without artifacts, including everything build
+/// depending on them. packages holds (name, version).
+pub fn get_rebuilds(
+    state: &RunState,
+    packages: &[(String, String)],
+    missing_artifacts: &BTreeSet<String>,
+    dependencies: &BTreeMap<String, Vec<String>>,
+) -> BTreeSet<String> {
+    let rebuilds: BTreeSet<String> = packages
+        .iter()
+        .filter(|(name, version)| !state.is_built(name, version) || missing_artifacts.contains(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    add_dependents(rebuilds, dependencies)
+}
+
+/// Adds the workspace packages build depending, also transitively, on the rebuilt ones.
+pub fn add_dependents(
+    mut rebuilds: BTreeSet<String>,
+    dependencies: &BTreeMap<String, Vec<String>>,
+) -> BTreeSet<String> {
+    loop {
+        let dependents: Vec<String> = dependencies
+            .iter()
+            .filter(|(name, depends_on)| {
+                !rebuilds.contains(*name) && depends_on.iter().any(|other| rebuilds.contains(other))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        if dependents.is_empty() {
+            return rebuilds;
+        }
+        rebuilds.extend(dependents);
+    }
+}
+
+// git diff of the tracked files against git_ref, plus the untracked files, as absolute paths
+fn get_changed_files(repo_dir: &Path, git_ref: &str) -> Result<Vec<PathBuf>> {
+    let git = |dir: &Path, args: &[&str]| -> Result<String> {
+        let output = Command::new("git").args(args).current_dir(dir).output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    let toplevel = PathBuf::from(git(repo_dir, &["rev-parse", "--show-toplevel"])?.trim());
+    let mut changed = git(&toplevel, &["diff", "--name-only", git_ref, "--"])?;
+    changed.push_str(&git(&toplevel, &["ls-files", "--others", "--exclude-standard"])?);
+    Ok(changed
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| toplevel.join(line))
+        .collect())
+}
+
+/// Workspace packages with files changed under their config root since git_ref,
+/// config_roots holds (package name, config root).
+pub fn get_changed_packages(
+    repo_dir: &Path,
+    config_roots: &[(String, PathBuf)],
+    git_ref: &str,
+) -> Result<BTreeSet<String>> {
+    let changed_files = get_changed_files(repo_dir, git_ref)?;
+    let mut changed = BTreeSet::new();
+    for (name, config_root) in config_roots {
+        let config_root = fs::canonicalize(config_root)?;
+        if changed_files.iter().any(|file| file.starts_with(&config_root)) {
+            changed.insert(name.clone());
+        }
+    }
+    Ok(changed)
+}
+
+/// Builds the selected packages, failures do not stop the remaining builds.
+pub fn build_workspace(
+    packages: &[ReleasePackage],
+    dependencies: &BTreeMap<String, Vec<String>>,
+    state_file: &Path,
+    selection: &BuildSelection,
+) -> Result<()> {
+    let mut state = match selection {
+        BuildSelection::All => RunState::default(),
+        _ => RunState::read(state_file)?,
+    };
+    if *selection == BuildSelection::RetryFailed && state.runs.is_empty() {
+        return Err(eyre!(
+            "No earlier run recorded in {}, nothing to retry.",
+            state_file.display()
+        ));
+    }
+    let versions: Vec<(String, String)> = packages
+        .iter()
+        .map(|package| {
+            let package_fields = &package.config.package_fields;
+            (package_fields.package_name.clone(), package_fields.get_debian_version())
+        })
+        .collect();
+    // nothing to reuse without the artifacts of the earlier build, e.g. on a fresh checkout
+    let missing_artifacts: BTreeSet<String> = match selection {
+        BuildSelection::All => BTreeSet::new(),
+        _ => packages
+            .iter()
+            .zip(&versions)
+            .filter(|(package, _)| package.packager.get_artifacts().is_err())
+            .map(|(_, (name, _))| name.clone())
+            .collect(),
+    };
+    for name in missing_artifacts.iter() {
+        info!("No artifacts of {} found, building it", name);
+    }
+    let (rebuilds, changed_since) = match selection {
+        BuildSelection::All => (versions.iter().map(|(name, _)| name.clone()).collect(), None),
+        BuildSelection::RetryFailed => (get_rebuilds(&state, &versions, &missing_artifacts, dependencies), None),
+        BuildSelection::ChangedSince { git_ref, packages } => (
+            add_dependents(packages.union(&missing_artifacts).cloned().collect(), dependencies),
+            Some(git_ref.clone()),
+        ),
+    };
+    info!("Building {} of {} packages", rebuilds.len(), versions.len());
+
+    let mut report = RunReport {
+        retry: *selection == BuildSelection::RetryFailed,
+        changed_since,
+        packages: vec![],
+    };
+    for (package, (name, version)) in packages.iter().zip(versions) {
+        if !rebuilds.contains(&name) {
+            info!("Reusing {} {} built by an earlier run", name, version);
+            report.packages.push(PackageResult {
+                name,
+                version,
+                status: BuildStatus::Reused,
+                error: None,
+            });
+            continue;
+        }
+        info!("Building {}", name);
+        let (status, error) = match package.packager.package() {
+            Ok(()) => (BuildStatus::Succeeded, None),
+            Err(err) => {
+                warn!("Failed to build {}: {:?}", name, err);
+                (BuildStatus::Failed, Some(err.to_string()))
+            }
+        };
+        report.packages.push(PackageResult {
+            name,
+            version,
+            status,
+            error,
+        });
+    }
+    let failed: Vec<String> = report
+        .packages
+        .iter()
+        .filter(|result| result.status == BuildStatus::Failed)
+        .map(|result| format!("{}: {}", result.name, result.error.as_deref().unwrap_or_default()))
+        .collect();
+    let total = report.packages.len();
+    state.runs.push(report);
+    state.write(state_file)?;
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(eyre!(
+        "{} of {} packages failed, run again with --retry-failed to only rebuild them:\n  {}",
+        failed.len(),
+        total,
+        failed.join("\n  ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn result(name: &str, status: BuildStatus) -> PackageResult {
+        PackageResult {
+            name: name.to_string(),
+            version: "1.0.0-1".to_string(),
+            status,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_get_workspace_dependencies() {
+        let dir = tempdir().unwrap();
+        let libfoo = dir.path().join("libfoo.sss");
+        fs::write(&libfoo, "name = \"libfoo\"\npackages = [\"libfoo1\", \"libfoo-dev\"]\n").unwrap();
+        let bar = dir.path().join("bar.sss");
+        fs::write(&bar, "name = \"bar\"\nbuild_depends = [\"libfoo-dev (>= 1.0)\"]\npackages = [\"bar\"]\n").unwrap();
+
+        let dependencies = get_workspace_dependencies(&[
+            ("libfoo".to_string(), libfoo),
+            ("bar".to_string(), bar),
+        ])
+        .unwrap();
+        assert_eq!(dependencies["bar"], vec!["libfoo"]);
+        assert!(dependencies["libfoo"].is_empty());
+    }
+
+    #[test]
+    fn test_retry_rebuilds_failures_and_dependents() {
+        let dir = tempdir().unwrap();
+        let state_file = dir.path().join(RUN_STATE_FILE_NAME);
+        let state = RunState {
+            runs: vec![
+                RunReport {
+                    retry: false,
+                    changed_since: None,
+                    packages: vec![
+                        result("libfoo", BuildStatus::Failed),
+                        result("bar", BuildStatus::Succeeded),
+                        result("baz", BuildStatus::Failed),
+                        result("geth", BuildStatus::Succeeded),
+                        result("reth", BuildStatus::Succeeded),
+                    ],
+                },
+                RunReport {
+                    retry: true,
+                    changed_since: None,
+                    packages: vec![
+                        result("libfoo", BuildStatus::Succeeded),
+                        result("bar", BuildStatus::Succeeded),
+                        result("baz", BuildStatus::Succeeded),
+                        result("geth", BuildStatus::Reused),
+                        result("reth", BuildStatus::Reused),
+                    ],
+                },
+            ],
+        };
+        state.write(&state_file).unwrap();
+        let state = RunState::read(&state_file).unwrap();
+
+        let versions: Vec<(String, String)> = ["libfoo", "bar", "baz", "geth", "reth"]
+            .iter()
+            .map(|name| (name.to_string(), "1.0.0-1".to_string()))
+            .collect();
+        let mut dependencies = BTreeMap::new();
+        dependencies.insert("bar".to_string(), vec!["baz".to_string()]);
+        dependencies.insert("baz".to_string(), vec![]);
+        dependencies.insert("geth".to_string(), vec![]);
+        assert!(get_rebuilds(&state, &versions, &BTreeSet::new(), &dependencies).is_empty());
+        // reused packages whose artifacts are gone are built again
+        assert_eq!(
+            get_rebuilds(&state, &versions, &BTreeSet::from(["baz".to_string()]), &dependencies),
+            BTreeSet::from(["bar".to_string(), "baz".to_string()])
+        );
+
+        let mut versions = versions;
+        versions[2].1 = "1.0.0-2".to_string();
+        versions[3].1 = "1.0.1-1".to_string();
+        assert_eq!(
+            get_rebuilds(&state, &versions, &BTreeSet::new(), &dependencies),
+            BTreeSet::from(["bar".to_string(), "baz".to_string(), "geth".to_string()])
+        );
+
+        let failed = RunState {
+            runs: vec![RunReport {
+                retry: false,
+                changed_since: None,
+                packages: vec![result("baz", BuildStatus::Failed), result("bar", BuildStatus::Succeeded)],
+            }],
+        };
+        assert_eq!(
+            get_rebuilds(&failed, &versions[1..3], &BTreeSet::new(), &dependencies),
+            BTreeSet::from(["bar".to_string(), "baz".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_changed_since_selects_packages_and_dependents() {
+        let dir = tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(dir.path())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        for name in ["libfoo", "bar", "geth"] {
+            fs::create_dir_all(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("pkg-builder.toml"), "").unwrap();
+        }
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+        fs::write(dir.path().join("libfoo/pkg-builder.toml"), "changed").unwrap();
+        fs::create_dir_all(dir.path().join("geth/src")).unwrap();
+        fs::write(dir.path().join("geth/src/new.patch"), "").unwrap();
+
+        let config_roots: Vec<(String, PathBuf)> = ["libfoo", "bar", "geth"]
+            .iter()
+            .map(|name| (name.to_string(), dir.path().join(name)))
+            .collect();
+        let changed = get_changed_packages(&dir.path().join("bar"), &config_roots, "HEAD").unwrap();
+        assert_eq!(changed, BTreeSet::from(["geth".to_string(), "libfoo".to_string()]));
+
+        let mut dependencies = BTreeMap::new();
+        dependencies.insert("bar".to_string(), vec!["libfoo".to_string()]);
+        dependencies.insert("baz".to_string(), vec!["bar".to_string()]);
+        assert_eq!(
+            add_dependents(BTreeSet::from(["libfoo".to_string()]), &dependencies),
+            BTreeSet::from(["bar".to_string(), "baz".to_string(), "libfoo".to_string()])
+        );
+    }
+}